@@ -0,0 +1,351 @@
+//! This module anonymizes the hosts, so that their graph, CSV files and reports can be shared
+//! without the real host names, IP addresses and process names. The mapping back to the real
+//! names can be written to a separate key file.
+
+use crate::{
+    host::{Connection, Host, ListeningSocket, Process, Route},
+    parsers::{diagnostics::ParseDiagnostic, directory_scanner::HostFailure},
+};
+use anyhow::Context;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+/// First address of the anonymized IPv4 addresses, which are numbered from `10.0.0.1`
+const IPV4_BASE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 0);
+/// First address of the anonymized IPv6 addresses, which are numbered from `fd00::1`
+const IPV6_BASE: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0);
+
+/// Replaces the host names with `host-01`, `host-02`…, the IP addresses with `10.0.0.1`,
+/// `10.0.0.2`… (`fd00::1`… for IPv6) and the process names with `proc-01`, `proc-02`…
+///
+/// The same real name or address is always replaced the same way, so that the connections between
+/// the hosts still match. The loopback and unspecified addresses are kept, since they tell how the
+/// sockets listen.
+#[derive(Debug, Default)]
+pub struct Anonymizer {
+    hosts: BTreeMap<String, String>,
+    ips: BTreeMap<IpAddr, IpAddr>,
+    processes: BTreeMap<String, String>,
+    ipv4_count: u32,
+    ipv6_count: u128,
+}
+
+/// Anonymized names and addresses, with the real ones they stand for
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub struct AnonymizationMapping {
+    pub hosts: BTreeMap<String, String>,
+    pub ips: BTreeMap<IpAddr, IpAddr>,
+    pub processes: BTreeMap<String, String>,
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Anonymize the hosts. The new names and addresses are given in the order of the real ones, so
+    /// that the same hosts are always anonymized the same way, whatever their order.
+    pub fn anonymize_hosts(&mut self, hosts: &[Host]) -> Vec<Host> {
+        let names: BTreeSet<&str> = hosts.iter().map(Host::name).collect();
+        let mut ips = BTreeSet::new();
+        let mut processes = BTreeSet::new();
+        for host in hosts {
+            ips.extend(host.ips().iter().copied());
+            for listening_socket in host.listening_sockets() {
+                ips.insert(listening_socket.ip_addr());
+                processes.insert(listening_socket.process().name());
+            }
+            for connection in host.connections() {
+                ips.insert(connection.local_socket().ip());
+                ips.insert(connection.peer_socket().ip());
+                processes.insert(connection.process().name());
+            }
+            for route in host.routes() {
+                ips.insert(route.destination());
+                ips.extend(route.gateway());
+            }
+        }
+        for name in names {
+            self.host_name(name);
+        }
+        for ip in ips {
+            self.ip(ip);
+        }
+        for process in processes {
+            self.process_name(process);
+        }
+
+        hosts.iter().map(|host| self.anonymize_host(host)).collect()
+    }
+
+    /// Anonymize the hosts which could not be parsed
+    pub fn anonymize_failures(&mut self, failures: &[HostFailure]) -> Vec<HostFailure> {
+        failures
+            .iter()
+            .map(|failure| failure.anonymized(self.host_name(failure.name())))
+            .collect()
+    }
+
+    /// Anonymize the diagnostics of the hosts, by host name. Their messages never quote the
+    /// capture files, so only the host names are replaced.
+    pub fn anonymize_diagnostics(
+        &mut self,
+        diagnostics: &BTreeMap<String, Vec<ParseDiagnostic>>,
+    ) -> BTreeMap<String, Vec<ParseDiagnostic>> {
+        diagnostics
+            .iter()
+            .map(|(name, host_diagnostics)| {
+                let name = self.host_name(name);
+                let host_diagnostics = host_diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.anonymized(name.clone()))
+                    .collect();
+                (name, host_diagnostics)
+            })
+            .collect()
+    }
+
+    /// Anonymized name of the host `name`
+    pub fn host_name(&mut self, name: &str) -> String {
+        let count = self.hosts.len();
+        self.hosts
+            .entry(name.to_string())
+            .or_insert_with(|| format!("host-{:02}", count + 1))
+            .clone()
+    }
+
+    /// Anonymized name of the process `name`
+    pub fn process_name(&mut self, name: &str) -> String {
+        let count = self.processes.len();
+        self.processes
+            .entry(name.to_string())
+            .or_insert_with(|| format!("proc-{:02}", count + 1))
+            .clone()
+    }
+
+    /// Anonymized address of `ip`. An IPv4-mapped IPv6 address, such as `::ffff:192.168.1.10`,
+    /// maps the anonymized address of its IPv4 address.
+    pub fn ip(&mut self, ip: IpAddr) -> IpAddr {
+        if ip.is_loopback() || ip.is_unspecified() {
+            return ip;
+        }
+        if let IpAddr::V6(ipv6) = ip {
+            if let Some(ipv4) = ipv6.to_ipv4_mapped() {
+                return match self.ip(IpAddr::V4(ipv4)) {
+                    IpAddr::V4(anonymized) => IpAddr::V6(anonymized.to_ipv6_mapped()),
+                    anonymized => anonymized,
+                };
+            }
+        }
+        if let Some(anonymized) = self.ips.get(&ip) {
+            return *anonymized;
+        }
+
+        let anonymized = match ip {
+            IpAddr::V4(_) => {
+                self.ipv4_count += 1;
+                IpAddr::V4(Ipv4Addr::from(u32::from(IPV4_BASE) + self.ipv4_count))
+            }
+            IpAddr::V6(_) => {
+                self.ipv6_count += 1;
+                IpAddr::V6(Ipv6Addr::from(u128::from(IPV6_BASE) + self.ipv6_count))
+            }
+        };
+        self.ips.insert(ip, anonymized);
+        anonymized
+    }
+
+    fn socket(&mut self, socket: &SocketAddr) -> SocketAddr {
+        SocketAddr::new(self.ip(socket.ip()), socket.port())
+    }
+
+    fn process(&mut self, process: &Process, host_name: &str) -> Process {
+        Process::new(
+            &self.process_name(process.name()),
+            *process.pid(),
+            host_name.to_string(),
+        )
+    }
+
+    fn anonymize_host(&mut self, host: &Host) -> Host {
+        let name = self.host_name(host.name());
+        let mut anonymized = Host::new(&name);
+        *anonymized.ips_mut() = host.ips().iter().map(|ip| self.ip(*ip)).collect();
+        for listening_socket in host.listening_sockets() {
+            let process = self.process(listening_socket.process(), &name);
+            anonymized.add_listening_socket(ListeningSocket::new(
+                self.socket(listening_socket.socket()),
+                listening_socket.socket_type().clone(),
+                process,
+                name.clone(),
+                listening_socket.ipv6_only().copied(),
+            ));
+        }
+        for connection in host.connections() {
+            let process = self.process(connection.process(), &name);
+            anonymized.add_established_connection(Connection::new(
+                self.socket(connection.local_socket()),
+                self.socket(connection.peer_socket()),
+                connection.socket_type().clone(),
+                process,
+            ));
+        }
+        for route in host.routes() {
+            anonymized.add_route(Route::new(
+                self.ip(route.destination()),
+                route.prefix_len(),
+                route.gateway().map(|gateway| self.ip(gateway)),
+                route.interface().map(str::to_string),
+            ));
+        }
+        anonymized
+    }
+
+    /// Anonymized names and addresses, with the real ones they stand for
+    pub fn mapping(&self) -> AnonymizationMapping {
+        AnonymizationMapping {
+            hosts: reverse(&self.hosts),
+            ips: reverse(&self.ips),
+            processes: reverse(&self.processes),
+        }
+    }
+
+    /// Write the mapping to the real names and addresses to the JSON file `path`
+    pub fn write_mapping(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(&self.mapping())?;
+        std::fs::write(path, json)
+            .with_context(|| format!("unable to write the anonymization mapping to {path:?}"))
+    }
+}
+
+fn reverse<K: Clone, V: Clone + Ord>(map: &BTreeMap<K, V>) -> BTreeMap<V, K> {
+    map.iter()
+        .map(|(real, anonymized)| (anonymized.clone(), real.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connections_model, host::SocketType, test_utils::HostBuilder};
+
+    /// A web server and a database, whose connection goes through their IPv4 addresses
+    fn make_hosts() -> Vec<Host> {
+        let mut web = HostBuilder::new("web-prod")
+            .ip("192.168.1.10")
+            .ip("2001:db8::10")
+            .listen_tcp(443, "nginx", 100)
+            .connect_tcp("192.168.1.10:50000", "192.168.1.20:5432", "nginx", 100)
+            .build();
+        web.add_route(Route::new(
+            "0.0.0.0".parse().unwrap(),
+            0,
+            Some("192.168.1.254".parse().unwrap()),
+            Some("eth0".to_string()),
+        ));
+        let db = HostBuilder::new("db-prod")
+            .ip("192.168.1.20")
+            .listen(
+                "[::ffff:192.168.1.20]:5432",
+                SocketType::TCP,
+                "postgres",
+                200,
+                Some(false),
+            )
+            .connect_tcp("192.168.1.20:5432", "192.168.1.10:50000", "postgres", 200)
+            .build();
+
+        vec![web, db]
+    }
+
+    /// The same hosts are anonymized the same way, whatever their order
+    #[test]
+    fn test_anonymize_deterministic() {
+        let hosts = make_hosts();
+        let anonymized = Anonymizer::new().anonymize_hosts(&hosts);
+        let reversed: Vec<Host> = hosts.into_iter().rev().collect();
+        let mut anonymized_reversed = Anonymizer::new().anonymize_hosts(&reversed);
+        anonymized_reversed.reverse();
+        assert_eq!(anonymized, anonymized_reversed);
+
+        // Sorted by their real names
+        let names: Vec<&str> = anonymized.iter().map(Host::name).collect();
+        assert_eq!(names, ["host-02", "host-01"]);
+        assert_eq!(
+            anonymized[0].listening_sockets()[0].process().name(),
+            "proc-01"
+        );
+        assert_eq!(
+            anonymized[1].listening_sockets()[0].process().name(),
+            "proc-02"
+        );
+    }
+
+    /// The real names and addresses do not show, and each one gets its own anonymized value
+    #[test]
+    fn test_anonymize_collision_free() {
+        let hosts = make_hosts();
+        let mut anonymizer = Anonymizer::new();
+        let anonymized = anonymizer.anonymize_hosts(&hosts);
+
+        let json = serde_json::to_string(&anonymized).unwrap();
+        for real in [
+            "web-prod",
+            "db-prod",
+            "nginx",
+            "postgres",
+            "192.168.1.",
+            "2001:db8",
+        ] {
+            assert!(!json.contains(real), "{real} is not anonymized");
+        }
+
+        let mapping = anonymizer.mapping();
+        assert_eq!(mapping.hosts.len(), anonymizer.hosts.len());
+        assert_eq!(mapping.ips.len(), anonymizer.ips.len());
+        assert_eq!(mapping.processes.len(), anonymizer.processes.len());
+        assert_eq!(mapping.hosts["host-01"], "db-prod");
+        assert_eq!(
+            mapping.ips[&"10.0.0.1".parse::<IpAddr>().unwrap()],
+            "192.168.1.10".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            mapping.ips[&"fd00::1".parse::<IpAddr>().unwrap()],
+            "2001:db8::10".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    /// The same IP is renumbered the same way everywhere, so that the connections still match
+    #[test]
+    fn test_anonymize_connections() {
+        let hosts = make_hosts();
+        let mut anonymizer = Anonymizer::new();
+        let anonymized = anonymizer.anonymize_hosts(&hosts);
+
+        let connections = connections_model::build_connections_list(&hosts, true);
+        let anonymized_connections = connections_model::build_connections_list(&anonymized, true);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(anonymized_connections.len(), connections.len());
+
+        let web = &anonymized[0];
+        assert!(web.ips().contains(&"10.0.0.1".parse().unwrap()));
+        assert!(web.ips().contains(&"::ffff:10.0.0.1".parse().unwrap()));
+        assert_eq!(
+            web.listening_sockets()[0].socket(),
+            &"0.0.0.0:443".parse().unwrap()
+        );
+        assert_eq!(
+            web.connections()[0].peer_socket(),
+            &"10.0.0.2:5432".parse().unwrap()
+        );
+        assert_eq!(
+            anonymized[1].listening_sockets()[0].socket(),
+            &"[::ffff:10.0.0.2]:5432".parse().unwrap()
+        );
+        assert_eq!(web.routes()[0].to_string(), "default via 10.0.0.3 dev eth0");
+    }
+}
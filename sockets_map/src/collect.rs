@@ -0,0 +1,427 @@
+//! This module collects the sockets of the local host, by running the same commands as the
+//! cheatsheets (or through system calls when the `native` feature is enabled). It is shared by the
+//! agent and the CLI `capture` subcommand.
+
+use crate::host::Host;
+use anyhow::{bail, Context};
+use std::{net::IpAddr, path::Path, time::Duration};
+
+#[cfg(target_os = "linux")]
+pub use linux::*;
+#[cfg(target_os = "windows")]
+pub use windows::*;
+
+/// Name of the local host: its pretty name if given, its hostname otherwise
+pub fn host_name(pretty_name: Option<&str>) -> anyhow::Result<String> {
+    match pretty_name {
+        Some(pretty_name) => Ok(pretty_name.to_string()),
+        None => Ok(hostname::get()?.to_string_lossy().to_string()),
+    }
+}
+
+/// Files written by [`write_captures`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureFormat {
+    /// A single bundle per host, `<host>.smap.json`, see [`crate::parsers::bundle`]
+    #[default]
+    Bundle,
+    /// One file per command output, such as `<host>.ss` and `<host>.linux_ip`
+    LegacyFiles,
+}
+
+/// Selection of the local IP addresses sent to the server, out of the ones of the interfaces
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    /// Patterns of the names of the interfaces to leave out, with `*` and `?` wildcards
+    pub exclude_interfaces: Vec<String>,
+    /// Addresses to add, such as the public address of a NAT gateway
+    pub extra_ips: Vec<IpAddr>,
+    /// Keep the link-local addresses and the ones of the default Docker bridge
+    pub keep_virtual_ips: bool,
+}
+
+impl IpFilter {
+    /// Keep the addresses of the `(interface name, address)` pairs which are not filtered out, then
+    /// add the extra addresses
+    pub fn apply(&self, interfaces: &[(String, IpAddr)]) -> Vec<IpAddr> {
+        let mut ips = Vec::new();
+        for (interface, ip) in interfaces {
+            if self
+                .exclude_interfaces
+                .iter()
+                .any(|pattern| glob_match(pattern, interface))
+            {
+                log::debug!("leaving out {ip} of excluded interface {interface}");
+                continue;
+            }
+            if !self.keep_virtual_ips && is_virtual_ip(ip) {
+                log::debug!("leaving out virtual address {ip} of interface {interface}");
+                continue;
+            }
+            ips.push(*ip);
+        }
+        ips.extend(self.extra_ips.iter().copied());
+        let mut unique_ips = Vec::new();
+        for ip in ips {
+            if !unique_ips.contains(&ip) {
+                unique_ips.push(ip);
+            }
+        }
+        unique_ips
+    }
+}
+
+/// Whether the address is link-local, or in the default Docker bridge network (172.17.0.0/16),
+/// which many hosts share
+pub(crate) fn is_virtual_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local() || (ip.octets()[0], ip.octets()[1]) == (172, 17),
+        IpAddr::V6(ip) => {
+            (ip.segments()[0] & 0xffc0) == 0xfe80
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| is_virtual_ip(&IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Whether the whole `name` matches `pattern`, in which `*` stands for any characters and `?` for
+/// one character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Positions to resume from after the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// IP addresses of the local network interfaces, selected by `filter`
+pub fn local_ip_addresses(filter: &IpFilter) -> anyhow::Result<Vec<IpAddr>> {
+    let interfaces = local_ip_address::list_afinet_netifas()
+        .with_context(|| "unable to retrieve list of local IP addresses")?;
+    Ok(filter.apply(&interfaces))
+}
+
+/// Check that the collection can see the processes of all the sockets. Without the privileges, it
+/// is a warning if `allow_unprivileged` is set, and an error otherwise.
+pub fn check_privileges(allow_unprivileged: bool) -> anyhow::Result<()> {
+    if ensure_privileged() {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    let message = "Must run as root";
+    #[cfg(target_os = "windows")]
+    let message = "Must run as administrator";
+    match allow_unprivileged {
+        true => {
+            log::warn!("{message}: the processes of some sockets will be missing");
+            Ok(())
+        }
+        false => bail!("{message}"),
+    }
+}
+
+/// Collect the sockets of the local host, named `name`
+pub fn collect_host(name: &str, ip_addresses: &[IpAddr]) -> anyhow::Result<Host> {
+    #[cfg(feature = "native")]
+    if let Some(host) = native::try_collect_host(name, ip_addresses) {
+        return Ok(host);
+    }
+
+    get_host_data(name.to_string(), ip_addresses)?.into()
+}
+
+/// Make `count` captures, `interval` seconds apart, and append them to the bundle or the capture
+/// files of the host `name` in `output_dir`. The files are named so that they can be used directly
+/// by the CLI or the GUI.
+pub async fn write_captures(
+    output_dir: &Path,
+    interval: f64,
+    count: u32,
+    name: &str,
+    ip_addresses: &[IpAddr],
+    format: CaptureFormat,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+
+    for capture in 1..=count {
+        let host_data = get_host_data(name.to_string(), ip_addresses)?;
+        match format {
+            CaptureFormat::Bundle => host_data.write_bundle(output_dir)?,
+            CaptureFormat::LegacyFiles => host_data.write_capture_files(output_dir)?,
+        }
+        log::info!("wrote capture {capture}/{count} to {output_dir:?}");
+
+        if capture < count {
+            tokio::time::sleep(Duration::from_secs_f64(interval)).await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+mod native {
+    use crate::{host::Host, parsers::native};
+    use std::{
+        net::IpAddr,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    /// Set after the first failure, so that the commands are used from then on
+    static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+    /// Collect the host sockets through system calls.
+    /// Returns `None` if this is not possible on this host, in which case the commands must be used.
+    pub fn try_collect_host(hostname: &str, ip_addresses: &[IpAddr]) -> Option<Host> {
+        if UNAVAILABLE.load(Ordering::Relaxed) {
+            return None;
+        }
+        match native::collect_host(hostname, ip_addresses) {
+            Ok(host) => Some(host),
+            Err(e) => {
+                log::warn!("native sockets collection failed, falling back to commands: {e:#}");
+                UNAVAILABLE.store(true, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::parsers::{
+        linux::{LinuxHostRawData, NetworkOutput},
+        HostData,
+    };
+    use std::{net::IpAddr, process::Command};
+
+    /// Locale of the commands, whose output is parsed in English
+    const COMMANDS_LOCALE: &str = "C";
+
+    /// Retrieve sockets information from the host
+    pub fn get_host_data(name: String, ip_addresses: &[IpAddr]) -> anyhow::Result<HostData> {
+        Ok(HostData::LinuxHostData(LinuxHostRawData::new(
+            name,
+            get_host_sockets_info()?,
+            ip_addresses.to_vec(),
+            get_route_output(),
+        )))
+    }
+
+    /// Retrieve the routing table of the host, if the `ip` command is available
+    pub fn get_route_output() -> Option<String> {
+        match exec_ip_route() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                log::debug!("unable to retrieve the routing table: {e}");
+                None
+            }
+        }
+    }
+
+    /// Retrieve network sockets information from the host
+    /// First try ss, then netstat
+    pub fn get_host_sockets_info() -> anyhow::Result<NetworkOutput> {
+        if let Ok(output) = exec_ss() {
+            Ok(NetworkOutput::Ss(output))
+        } else {
+            Ok(NetworkOutput::Netstat(exec_netstat()?))
+        }
+    }
+
+    /// Run `program` with `args` in the commands locale, and return its output
+    fn exec(program: &str, args: &[&str]) -> anyhow::Result<String> {
+        let output = Command::new(program)
+            .args(args)
+            .env("LC_ALL", COMMANDS_LOCALE)
+            .output()?;
+
+        Ok(std::str::from_utf8(&output.stdout)?.to_string())
+    }
+
+    fn exec_ss() -> anyhow::Result<String> {
+        exec("ss", &["-apn"])
+    }
+
+    fn exec_netstat() -> anyhow::Result<String> {
+        // netstat -Wltpn; netstat -Wtpn
+        let output_str1 = exec("netstat", &["-Wltpn"])?;
+        let output_str2 = exec("netstat", &["-Wtpn"])?;
+
+        Ok(format!("{output_str1}\n{output_str2}"))
+    }
+
+    fn exec_ip_route() -> anyhow::Result<String> {
+        // ip route; ip -6 route
+        let output_str1 = exec("ip", &["route"])?;
+        let output_str2 = exec("ip", &["-6", "route"])?;
+
+        Ok(format!("{output_str1}\n{output_str2}"))
+    }
+
+    /// Whether the process runs as root
+    pub fn ensure_privileged() -> bool {
+        is_sudo::RunningAs::Root == is_sudo::check()
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows {
+    use crate::parsers::{
+        windows::{decode_command_output, WindowsHostRawData},
+        HostData,
+    };
+    use std::{net::IpAddr, process::Command};
+    use windows_sys::Win32::{Globalization::GetOEMCP, System::Console::GetConsoleOutputCP};
+
+    /// Retrieve sockets information from the host
+    pub fn get_host_data(name: String, ip_addresses: &[IpAddr]) -> anyhow::Result<HostData> {
+        Ok(HostData::WindowsHostData(WindowsHostRawData::new(
+            name,
+            get_host_sockets_info()?,
+            exec_tasklist()?,
+            ip_addresses.to_vec(),
+            get_route_output(),
+        )))
+    }
+
+    /// Retrieve the routing table of the host
+    pub fn get_route_output() -> Option<String> {
+        match exec_route_print() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                log::debug!("unable to retrieve the routing table: {e}");
+                None
+            }
+        }
+    }
+
+    /// Code page of the commands output: the one of the console, or the OEM code page when the
+    /// process has no console (e.g. when running as a service)
+    fn console_code_page() -> u16 {
+        // SAFETY: these functions have no preconditions
+        let code_page = match unsafe { GetConsoleOutputCP() } {
+            0 => unsafe { GetOEMCP() },
+            code_page => code_page,
+        };
+        u16::try_from(code_page).unwrap_or_default()
+    }
+
+    /// Run `program` with `args`, and return its output decoded from the console code page
+    fn exec(program: &str, args: &[&str]) -> anyhow::Result<String> {
+        let output = Command::new(program).args(args).output()?;
+
+        Ok(decode_command_output(&output.stdout, console_code_page()))
+    }
+
+    /// Retrieve network sockets information from the host
+    pub fn get_host_sockets_info() -> anyhow::Result<String> {
+        exec("netstat", &["-ano"])
+    }
+
+    fn exec_route_print() -> anyhow::Result<String> {
+        exec("route", &["print"])
+    }
+
+    fn exec_tasklist() -> anyhow::Result<String> {
+        exec("tasklist", &["/FO", "CSV"])
+    }
+
+    // TODO: need to fix an build issue with the [is_sudo](https://github.com/spa5k/is_sudo) crate
+    pub fn ensure_privileged() -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interfaces() -> Vec<(String, IpAddr)> {
+        [
+            ("lo", "127.0.0.1"),
+            ("eth0", "10.0.0.1"),
+            ("eth0", "fe80::1"),
+            ("docker0", "172.17.0.1"),
+            ("br-1a2b", "172.18.0.1"),
+            ("virbr0", "192.168.122.1"),
+            ("tun0", "10.8.0.2"),
+        ]
+        .into_iter()
+        .map(|(name, ip)| (name.to_string(), ip.parse().unwrap()))
+        .collect()
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    /// The link-local and Docker bridge addresses must be left out unless they are kept, and the
+    /// extra addresses added once
+    #[test]
+    fn test_ip_filter_virtual_ips() {
+        assert_eq!(
+            IpFilter::default().apply(&interfaces()),
+            ips(&[
+                "127.0.0.1",
+                "10.0.0.1",
+                "172.18.0.1",
+                "192.168.122.1",
+                "10.8.0.2"
+            ])
+        );
+
+        let filter = IpFilter {
+            extra_ips: ips(&["203.0.113.1", "10.0.0.1"]),
+            keep_virtual_ips: true,
+            ..Default::default()
+        };
+        let kept = filter.apply(&interfaces());
+        assert_eq!(kept.len(), 8);
+        assert!(kept.contains(&"fe80::1".parse().unwrap()));
+        assert!(kept.contains(&"172.17.0.1".parse().unwrap()));
+        assert_eq!(kept.last(), Some(&"203.0.113.1".parse().unwrap()));
+    }
+
+    /// The interfaces must be excluded by their whole name, with wildcards
+    #[test]
+    fn test_ip_filter_exclude_interfaces() {
+        let filter = IpFilter {
+            exclude_interfaces: vec!["br-*".into(), "virbr?".into(), "tun".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.apply(&interfaces()),
+            ips(&["127.0.0.1", "10.0.0.1", "10.8.0.2"])
+        );
+
+        assert!(glob_match("*", ""));
+        assert!(glob_match("veth*", "veth1234"));
+        assert!(glob_match("*eth*0", "veth10"));
+        assert!(!glob_match("eth?", "eth10"));
+        assert!(is_virtual_ip(&"::ffff:169.254.1.1".parse().unwrap()));
+        assert!(!is_virtual_ip(&"172.16.0.1".parse().unwrap()));
+    }
+}
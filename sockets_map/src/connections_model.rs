@@ -1,7 +1,14 @@
 //! This module models the connections between processesm with listening and connected sockets.
 
-use crate::host;
+use crate::host::{self, canonical_ip};
 use log;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    time::SystemTime,
+};
 
 #[derive(Debug)]
 /// A connection between the connected_host on the connected_connection's local_socket to the
@@ -11,6 +18,91 @@ pub struct Connection<'a> {
     connected_host: &'a host::Host,
     listening_connection: &'a host::ListeningSocket,
     connected_connection: &'a host::Connection,
+    /// Proxies the connection goes through, as `host (process)`, when it is derived from a chain
+    /// of connections by [`ProxyAwareMatcher`] or [`collapse_docker_proxy`]
+    via: Vec<String>,
+    direction: Direction,
+    server_side: ServerSide,
+}
+
+/// Direction of a connection, relative to its source, the connected process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the source to the destination
+    #[default]
+    Out,
+    /// From the destination to the source, for the UDP sockets of a well-known port exchanging
+    /// with a socket bound to an ephemeral port
+    In,
+    /// Both ways, for the connections seen from both ends, see [`collapse_bidirectional`]
+    Both,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Direction::Out => "out",
+                Direction::In => "in",
+                Direction::Both => "both",
+            }
+        )
+    }
+}
+
+/// How the server side of a connection was told apart from the client side
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerSide {
+    /// The destination is listening, and the source is not
+    #[default]
+    Listen,
+    /// Both ends could be the server, and one of them has a well-known port or the other an
+    /// ephemeral one
+    Port,
+    /// Both ends could be the server, and their ports do not tell which one is
+    Arbitrary,
+}
+
+impl std::fmt::Display for ServerSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ServerSide::Listen => "listen",
+                ServerSide::Port => "port",
+                ServerSide::Arbitrary => "arbitrary",
+            }
+        )
+    }
+}
+
+/// End of the well-known ports, which are the ones of the services
+const WELL_KNOWN_PORTS_END: u16 = 1024;
+
+/// Start of the ephemeral ports, the lowest of the default ranges of Linux and Windows, which are
+/// the ones of the clients
+const EPHEMERAL_PORTS_START: u16 = 32768;
+
+/// Whether the ports tell that the server of a connection is the end of `server_port`, with
+/// `Some(true)`, or the end of `client_port`, with `Some(false)`
+fn server_by_port(client_port: u16, server_port: u16) -> Option<bool> {
+    let looks_like_server = |port: u16, other: u16| {
+        (port < WELL_KNOWN_PORTS_END && other >= WELL_KNOWN_PORTS_END)
+            || (other >= EPHEMERAL_PORTS_START && port < EPHEMERAL_PORTS_START)
+    };
+    match (
+        looks_like_server(server_port, client_port),
+        looks_like_server(client_port, server_port),
+    ) {
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        _ => None,
+    }
 }
 
 impl<'a> std::fmt::Display for Connection<'a> {
@@ -42,7 +134,26 @@ impl<'a> Connection<'a> {
             connected_host,
             listening_connection,
             connected_connection,
+            via: Vec::new(),
+            direction: Direction::default(),
+            server_side: ServerSide::default(),
+        }
+        .with_udp_direction()
+    }
+
+    /// A UDP socket is listening as soon as it is bound, so a client bound to an ephemeral port
+    /// looks like the server of its well-known peer: such connections go the other way
+    fn with_udp_direction(mut self) -> Self {
+        if *self.connected_connection.socket_type() == host::SocketType::UDP
+            && server_by_port(
+                self.connected_connection.local_socket().port(),
+                self.listening_connection.port(),
+            ) == Some(false)
+        {
+            self.direction = Direction::In;
+            self.server_side = ServerSide::Port;
         }
+        self
     }
 
     /// Get a reference to the connection's listening host.
@@ -63,11 +174,712 @@ impl<'a> Connection<'a> {
     pub fn listening_connection(&self) -> &&'a host::ListeningSocket {
         &self.listening_connection
     }
+
+    /// Get a reference to the proxies the connection goes through, empty for the direct ones.
+    pub fn via(&self) -> &[String] {
+        &self.via
+    }
+
+    /// Returns true if the connection is between two processes of the same host
+    pub fn is_loopback(&self) -> bool {
+        self.listening_host.name() == self.connected_host.name()
+    }
+
+    /// Get the direction of the connection.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get how the server side of the connection was told.
+    pub fn server_side(&self) -> ServerSide {
+        self.server_side
+    }
+}
+
+/// What a [`ConnectionRecord`] stands for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    /// A connection matched with its listening socket
+    #[default]
+    Connection,
+    /// A connection to a captured host which has no listening socket for it, see
+    /// [`UnmatchedKnownPeer`]. The destination process is unknown.
+    UnmatchedKnownPeer,
+}
+
+impl RecordKind {
+    fn is_connection(&self) -> bool {
+        *self == RecordKind::Connection
+    }
+}
+
+impl std::fmt::Display for RecordKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                RecordKind::Connection => "connection",
+                RecordKind::UnmatchedKnownPeer => "unmatched_known_peer",
+            }
+        )
+    }
+}
+
+/// Owned copy of a [`Connection`], which can be kept once the hosts are dropped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectionRecord {
+    #[serde(skip_serializing_if = "RecordKind::is_connection")]
+    kind: RecordKind,
+    source_host: String,
+    source_process: String,
+    source_pid: u32,
+    source_socket: SocketAddr,
+    dest_host: String,
+    dest_process: String,
+    dest_pid: u32,
+    dest_socket: SocketAddr,
+    /// Socket the connected process connected to, which differs from the listening socket when
+    /// it is bound to every address
+    peer_socket: SocketAddr,
+    protocol: host::SocketType,
+    /// Whether the listening socket only accepts IPv6 connections, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_only: Option<bool>,
+    /// Number of captures in which the connection was seen, when several captures are aggregated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<usize>,
+    /// Time of the first capture in which the connection was seen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_seen: Option<SystemTime>,
+    /// Time of the last capture in which the connection was seen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<SystemTime>,
+    /// Proxies the connection goes through, see [`Connection::via`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    via: Vec<String>,
+    direction: Direction,
+    server_side: ServerSide,
+}
+
+impl<'a> From<&Connection<'a>> for ConnectionRecord {
+    fn from(connection: &Connection<'a>) -> Self {
+        let source = connection.connected_connection();
+        let dest = connection.listening_connection();
+        Self {
+            kind: RecordKind::Connection,
+            source_host: connection.connected_host().name().to_string(),
+            source_process: source.process().name().to_string(),
+            source_pid: *source.process().pid(),
+            source_socket: *source.local_socket(),
+            dest_host: connection.listening_host().name().to_string(),
+            dest_process: dest.process().name().to_string(),
+            dest_pid: *dest.process().pid(),
+            dest_socket: *dest.socket(),
+            peer_socket: *source.peer_socket(),
+            protocol: source.socket_type().clone(),
+            ipv6_only: dest.ipv6_only().copied(),
+            count: None,
+            first_seen: None,
+            last_seen: None,
+            via: connection.via().to_vec(),
+            direction: connection.direction(),
+            server_side: connection.server_side(),
+        }
+    }
+}
+
+impl<'a> From<&UnmatchedKnownPeer<'a>> for ConnectionRecord {
+    fn from(unmatched: &UnmatchedKnownPeer<'a>) -> Self {
+        let source = unmatched.connection();
+        Self {
+            kind: RecordKind::UnmatchedKnownPeer,
+            source_host: unmatched.connected_host().name().to_string(),
+            source_process: source.process().name().to_string(),
+            source_pid: *source.process().pid(),
+            source_socket: *source.local_socket(),
+            dest_host: unmatched.peer_host().name().to_string(),
+            dest_process: String::new(),
+            dest_pid: 0,
+            dest_socket: *source.peer_socket(),
+            peer_socket: *source.peer_socket(),
+            protocol: source.socket_type().clone(),
+            ipv6_only: None,
+            count: None,
+            first_seen: None,
+            last_seen: None,
+            via: Vec::new(),
+            direction: Direction::Out,
+            server_side: match server_by_port(
+                source.local_socket().port(),
+                source.peer_socket().port(),
+            ) {
+                Some(true) => ServerSide::Port,
+                _ => ServerSide::Arbitrary,
+            },
+        }
+    }
+}
+
+impl ConnectionRecord {
+    /// Set the number of captures in which the connection was seen
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Set the time of the first capture in which the connection was seen
+    pub fn with_first_seen(mut self, first_seen: SystemTime) -> Self {
+        self.first_seen = Some(first_seen);
+        self
+    }
+
+    /// Set the time of the last capture in which the connection was seen
+    pub fn with_last_seen(mut self, last_seen: SystemTime) -> Self {
+        self.last_seen = Some(last_seen);
+        self
+    }
+
+    /// Whether both records are the same connection, whatever the captures they were seen in
+    pub fn is_same_connection(&self, other: &ConnectionRecord) -> bool {
+        self.kind == other.kind
+            && self.source_host == other.source_host
+            && self.source_process == other.source_process
+            && self.source_pid == other.source_pid
+            && self.source_socket == other.source_socket
+            && self.dest_host == other.dest_host
+            && self.dest_process == other.dest_process
+            && self.dest_pid == other.dest_pid
+            && self.dest_socket == other.dest_socket
+            && self.protocol == other.protocol
+            && self.via == other.via
+    }
+
+    /// Get what the record stands for.
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
+
+    /// Get a reference to the name of the connected host.
+    pub fn source_host(&self) -> &str {
+        &self.source_host
+    }
+
+    /// Get a reference to the name of the connected process.
+    pub fn source_process(&self) -> &str {
+        &self.source_process
+    }
+
+    /// Get the PID of the connected process.
+    pub fn source_pid(&self) -> u32 {
+        self.source_pid
+    }
+
+    /// Get a reference to the local socket of the connected process.
+    pub fn source_socket(&self) -> &SocketAddr {
+        &self.source_socket
+    }
+
+    /// Get a reference to the name of the listening host.
+    pub fn dest_host(&self) -> &str {
+        &self.dest_host
+    }
+
+    /// Get a reference to the name of the listening process.
+    pub fn dest_process(&self) -> &str {
+        &self.dest_process
+    }
+
+    /// Get the PID of the listening process.
+    pub fn dest_pid(&self) -> u32 {
+        self.dest_pid
+    }
+
+    /// Get a reference to the listening socket.
+    pub fn dest_socket(&self) -> &SocketAddr {
+        &self.dest_socket
+    }
+
+    /// Get a reference to the socket the connected process connected to.
+    pub fn peer_socket(&self) -> &SocketAddr {
+        &self.peer_socket
+    }
+
+    /// Get whether the listening socket only accepts IPv6 connections, if known.
+    pub fn ipv6_only(&self) -> Option<bool> {
+        self.ipv6_only
+    }
+
+    /// Get a reference to the protocol of the connection.
+    pub fn protocol(&self) -> &host::SocketType {
+        &self.protocol
+    }
+
+    /// Get the number of captures in which the connection was seen, if known.
+    pub fn count(&self) -> Option<usize> {
+        self.count
+    }
+
+    /// Get the time of the first capture in which the connection was seen, if known.
+    pub fn first_seen(&self) -> Option<SystemTime> {
+        self.first_seen
+    }
+
+    /// Get the time of the last capture in which the connection was seen, if known.
+    pub fn last_seen(&self) -> Option<SystemTime> {
+        self.last_seen
+    }
+
+    /// Get a reference to the proxies the connection goes through.
+    pub fn via(&self) -> &[String] {
+        &self.via
+    }
+
+    /// Get the direction of the connection.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get how the server side of the connection was told.
+    pub fn server_side(&self) -> ServerSide {
+        self.server_side
+    }
+}
+
+/// A connection which can be exported, whether it borrows the hosts or is owned
+pub trait ToConnectionRecord {
+    fn to_connection_record(&self) -> ConnectionRecord;
+}
+
+impl<'a> ToConnectionRecord for Connection<'a> {
+    fn to_connection_record(&self) -> ConnectionRecord {
+        ConnectionRecord::from(self)
+    }
+}
+
+impl<'a> ToConnectionRecord for UnmatchedKnownPeer<'a> {
+    fn to_connection_record(&self) -> ConnectionRecord {
+        ConnectionRecord::from(self)
+    }
+}
+
+impl ToConnectionRecord for ConnectionRecord {
+    fn to_connection_record(&self) -> ConnectionRecord {
+        self.clone()
+    }
+}
+
+/// Options of the connections built from the hosts
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionsOptions {
+    /// Leave out the connections between the processes of a same host
+    pub no_loopback: bool,
+    /// Replace the connections to docker-proxy and from docker-proxy to the containers with
+    /// connections from the clients to the containers, see [`collapse_docker_proxy`]
+    pub collapse_docker_proxy: bool,
+    /// Add the clients of the connections captured only on the listening side, see
+    /// [`infer_clients_from_server_side`]. [`build_connection_records`] adds them itself, while the
+    /// hosts given to the other functions must have been through [`infer_clients_from_server_side`].
+    pub infer_clients_from_server_side: bool,
+    /// Keep both connections of the flows seen from both ends, instead of collapsing them into a
+    /// bidirectional one, see [`collapse_bidirectional`]
+    pub keep_both_directions: bool,
+}
+
+/// Build the list of connections between hosts, as owned records which can be kept once the hosts
+/// are dropped. [`crate::graphs::create_graph`] still needs the borrowed connections of
+/// [`build_connections_list`].
+pub fn build_connection_records(
+    hosts: &[host::Host],
+    options: &ConnectionsOptions,
+) -> Vec<ConnectionRecord> {
+    let hosts = match options.infer_clients_from_server_side {
+        true => {
+            let mut hosts = hosts.to_vec();
+            infer_clients_from_server_side(&mut hosts);
+            Cow::Owned(hosts)
+        }
+        false => Cow::Borrowed(hosts),
+    };
+    build_connections_list_with_options(&hosts, options, &DefaultMatcher)
+        .iter()
+        .map(ConnectionRecord::from)
+        .collect()
+}
+
+/// Build the list of connections between hosts with the rules of `matcher` and the `options`
+pub fn build_connections_list_with_options<'a>(
+    hosts: &'a [host::Host],
+    options: &ConnectionsOptions,
+    matcher: &dyn ConnectionMatcher,
+) -> Vec<Connection<'a>> {
+    let connections = match options.collapse_docker_proxy {
+        false => build_connections_list_with(hosts, options.no_loopback, matcher),
+        true => {
+            // The connections from docker-proxy to the containers of its host are loopback
+            // connections
+            let mut connections =
+                collapse_docker_proxy(build_connections_list_with(hosts, false, matcher));
+            if options.no_loopback {
+                connections.retain(|connection| !connection.is_loopback());
+            }
+            connections
+        }
+    };
+    match options.keep_both_directions {
+        true => connections,
+        false => collapse_bidirectional(connections),
+    }
+}
+
+/// Connections between the hosts, along with the connections to captured hosts which have no
+/// listening socket for them
+#[derive(Debug, Default)]
+pub struct ConnectionsReport<'a> {
+    pub connections: Vec<Connection<'a>>,
+    pub unmatched_known_peers: Vec<UnmatchedKnownPeer<'a>>,
 }
 
-/// Build the list of connections between hosts
+impl<'a> ConnectionsReport<'a> {
+    /// Records of the connections, followed by those of the unmatched connections
+    pub fn records(&self) -> Vec<ConnectionRecord> {
+        self.connections
+            .iter()
+            .map(ConnectionRecord::from)
+            .chain(
+                self.unmatched_known_peers
+                    .iter()
+                    .map(ConnectionRecord::from),
+            )
+            .collect()
+    }
+}
+
+/// Build the connections between hosts with the rules of `matcher` and the `options`, and find
+/// the connections to captured hosts which were left out, see [`find_unmatched_known_peers`]
+pub fn build_connections_report<'a>(
+    hosts: &'a [host::Host],
+    options: &ConnectionsOptions,
+    matcher: &dyn ConnectionMatcher,
+) -> ConnectionsReport<'a> {
+    let connections = build_connections_list_with_options(hosts, options, matcher);
+    ConnectionsReport {
+        unmatched_known_peers: find_unmatched_known_peers(hosts, &connections),
+        connections,
+    }
+}
+
+/// Build the list of connections between hosts, with the [`DefaultMatcher`]
 pub fn build_connections_list(hosts: &[host::Host], no_loopback: bool) -> Vec<Connection<'_>> {
+    build_connections_list_with(hosts, no_loopback, &DefaultMatcher)
+}
+
+/// Build the list of connections between hosts with the rules of `matcher`
+pub fn build_connections_list_with<'a>(
+    hosts: &'a [host::Host],
+    no_loopback: bool,
+    matcher: &dyn ConnectionMatcher,
+) -> Vec<Connection<'a>> {
     log::debug!("Building connections list");
+    matcher.build_connections(hosts, no_loopback)
+}
+
+/// Rules matching the sockets of the hosts into connections, to plug in the connections of a
+/// particular network
+pub trait ConnectionMatcher {
+    /// Build the connections between `hosts`, leaving out the connections between the processes of
+    /// a same host if `no_loopback`
+    fn build_connections<'a>(
+        &self,
+        hosts: &'a [host::Host],
+        no_loopback: bool,
+    ) -> Vec<Connection<'a>>;
+}
+
+/// Matches the connected sockets with the listening sockets of the same host, with the listening
+/// sockets of the other hosts, and with the sockets handed out by the listening processes of the
+/// other hosts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultMatcher;
+
+impl ConnectionMatcher for DefaultMatcher {
+    fn build_connections<'a>(
+        &self,
+        hosts: &'a [host::Host],
+        no_loopback: bool,
+    ) -> Vec<Connection<'a>> {
+        match_sockets(hosts, no_loopback)
+    }
+}
+
+/// Matches the connections like the [`DefaultMatcher`], then adds a connection from each process
+/// connecting to a proxy, such as stunnel, to the processes the proxy connects to. The proxies
+/// cannot tell which incoming connection goes to which outgoing one, so every pair is kept.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyAwareMatcher {
+    /// Names of the proxy processes
+    proxies: Vec<String>,
+}
+
+impl ProxyAwareMatcher {
+    /// Matcher of the proxies with these process names, the `.exe` suffix being optional
+    pub fn new<S: AsRef<str>>(proxies: &[S]) -> Self {
+        Self {
+            proxies: proxies
+                .iter()
+                .map(|proxy| proxy.as_ref().to_string())
+                .collect(),
+        }
+    }
+
+    /// Get a reference to the names of the proxy processes.
+    pub fn proxies(&self) -> &[String] {
+        &self.proxies
+    }
+
+    fn is_proxy(&self, process: &host::Process) -> bool {
+        let name = process.name();
+        let name = name.strip_suffix(".exe").unwrap_or(name);
+        self.proxies
+            .iter()
+            .any(|proxy| proxy.eq_ignore_ascii_case(name))
+    }
+
+    /// Follow the connections going out of the proxy `hop` is connected to, adding a connection
+    /// from `origin` to each process reached through `via`
+    fn follow<'a>(
+        &self,
+        connections: &[Connection<'a>],
+        origin: &Connection<'a>,
+        hop: &Connection<'a>,
+        via: Vec<String>,
+        derived: &mut Vec<Connection<'a>>,
+    ) {
+        let proxy_host = hop.listening_host();
+        let proxy = hop.listening_connection().process();
+        for outgoing in connections.iter().filter(|connection| {
+            connection.connected_host().name() == proxy_host.name()
+                && connection.connected_connection().process() == proxy
+        }) {
+            let next_proxy = outgoing.listening_connection().process();
+            if self.is_proxy(next_proxy) {
+                let next_hop = proxy_label(outgoing.listening_host(), next_proxy);
+                // The proxies connecting to each other in a loop are followed once
+                if !via.contains(&next_hop) {
+                    let mut next_via = via.clone();
+                    next_via.push(next_hop);
+                    self.follow(connections, origin, outgoing, next_via, derived);
+                }
+                continue;
+            }
+            let connection = Connection {
+                via: via.clone(),
+                ..Connection::new(
+                    outgoing.listening_host(),
+                    origin.connected_host(),
+                    outgoing.listening_connection(),
+                    origin.connected_connection(),
+                )
+            };
+            if !derived
+                .iter()
+                .any(|other| is_same_derived(other, &connection))
+            {
+                log::debug!("found connection through proxies: {connection}");
+                derived.push(connection);
+            }
+        }
+    }
+}
+
+impl ConnectionMatcher for ProxyAwareMatcher {
+    fn build_connections<'a>(
+        &self,
+        hosts: &'a [host::Host],
+        no_loopback: bool,
+    ) -> Vec<Connection<'a>> {
+        // The connections to a local proxy are loopback connections
+        let mut connections = DefaultMatcher.build_connections(hosts, false);
+        let mut derived = Vec::new();
+        for origin in connections.iter().filter(|connection| {
+            self.is_proxy(connection.listening_connection().process())
+                && !self.is_proxy(connection.connected_connection().process())
+        }) {
+            let via = vec![proxy_label(
+                origin.listening_host(),
+                origin.listening_connection().process(),
+            )];
+            self.follow(&connections, origin, origin, via, &mut derived);
+        }
+        connections.extend(derived);
+        if no_loopback {
+            connections.retain(|connection| !connection.is_loopback());
+        }
+        connections
+    }
+}
+
+/// Name of the process forwarding the published ports of the Docker containers
+const DOCKER_PROXY: &str = "docker-proxy";
+
+/// Replace the connections of the clients to docker-proxy, and the connections of docker-proxy to
+/// the containers, with connections from the clients to the processes of the containers, going
+/// through `host (docker-proxy)`. Docker runs a docker-proxy process per published port and IP
+/// version, so the clients of a port are paired with the connections of the docker-proxy processes
+/// listening on this port. The connections which cannot be paired are kept as they are.
+pub fn collapse_docker_proxy(connections: Vec<Connection<'_>>) -> Vec<Connection<'_>> {
+    let mut collapsed = HashSet::new();
+    let mut derived = Vec::new();
+    for (inbound_index, inbound) in connections.iter().enumerate().filter(|(_, connection)| {
+        connection.listening_connection().process().name() == DOCKER_PROXY
+            && connection.connected_connection().process().name() != DOCKER_PROXY
+    }) {
+        let proxy_host = inbound.listening_host();
+        let port = inbound.listening_connection().port();
+        let forwards_port = |process: &host::Process| {
+            process.name() == DOCKER_PROXY
+                && proxy_host
+                    .listening_sockets()
+                    .iter()
+                    .any(|listening_socket| {
+                        listening_socket.port() == port && listening_socket.process() == process
+                    })
+        };
+        for (outbound_index, outbound) in
+            connections.iter().enumerate().filter(|(_, connection)| {
+                connection.connected_host().name() == proxy_host.name()
+                    && forwards_port(connection.connected_connection().process())
+            })
+        {
+            collapsed.extend([inbound_index, outbound_index]);
+            let connection = Connection {
+                via: vec![proxy_label(
+                    proxy_host,
+                    outbound.connected_connection().process(),
+                )],
+                ..Connection::new(
+                    outbound.listening_host(),
+                    inbound.connected_host(),
+                    outbound.listening_connection(),
+                    inbound.connected_connection(),
+                )
+            };
+            if !derived
+                .iter()
+                .any(|other| is_same_derived(other, &connection))
+            {
+                log::debug!("found connection through docker-proxy: {connection}");
+                derived.push(connection);
+            }
+        }
+    }
+    connections
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !collapsed.contains(index))
+        .map(|(_, connection)| connection)
+        .chain(derived)
+        .collect()
+}
+
+/// Collapse the connections seen from both ends into a single one going [`Direction::Both`]. Both
+/// ends of a flow may be listening on their port, such as the UDP sockets of symmetric protocols
+/// like NTP, in which case the flow is matched once from each end. The connection kept is the one
+/// going to the end the ports tell is the server, see [`ServerSide::Port`], or else the one from
+/// the host whose name comes first.
+pub fn collapse_bidirectional(connections: Vec<Connection<'_>>) -> Vec<Connection<'_>> {
+    let mut collapsed = HashSet::new();
+    let mut kept = HashMap::new();
+    for (index, connection) in connections.iter().enumerate() {
+        if collapsed.contains(&index) || kept.contains_key(&index) {
+            continue;
+        }
+        // The flow may also be matched several times from the same end
+        let (reverse, same): (Vec<usize>, Vec<usize>) = connections
+            .iter()
+            .enumerate()
+            .skip(index + 1)
+            .filter(|(other_index, other)| {
+                !collapsed.contains(other_index)
+                    && (is_reverse(connection, other) || is_same_derived(connection, other))
+            })
+            .map(|(other_index, _)| other_index)
+            .partition(|other_index| is_reverse(connection, &connections[*other_index]));
+        let Some(&reverse_index) = reverse.first() else {
+            continue;
+        };
+        let (kept_index, server_side) = match server_by_port(
+            connection.connected_connection().local_socket().port(),
+            connections[reverse_index]
+                .connected_connection()
+                .local_socket()
+                .port(),
+        ) {
+            Some(true) => (index, ServerSide::Port),
+            Some(false) => (reverse_index, ServerSide::Port),
+            // The same one whatever the order of the hosts
+            None => match source_key(connection) <= source_key(&connections[reverse_index]) {
+                true => (index, ServerSide::Arbitrary),
+                false => (reverse_index, ServerSide::Arbitrary),
+            },
+        };
+        log::debug!(
+            "found connection seen from both ends: {}",
+            connections[kept_index]
+        );
+        collapsed.extend(
+            [index]
+                .into_iter()
+                .chain(reverse)
+                .chain(same)
+                .filter(|other_index| *other_index != kept_index),
+        );
+        kept.insert(kept_index, server_side);
+    }
+    connections
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !collapsed.contains(index))
+        .map(|(index, connection)| match kept.get(&index) {
+            Some(server_side) => Connection {
+                direction: Direction::Both,
+                server_side: *server_side,
+                ..connection
+            },
+            None => connection,
+        })
+        .collect()
+}
+
+/// Name of the connected host and local socket of the connected process, to order connections
+fn source_key<'a>(connection: &Connection<'a>) -> (&'a str, SocketAddr) {
+    let host: &'a host::Host = connection.connected_host();
+    (
+        host.name(),
+        canonical_socket(connection.connected_connection().local_socket()),
+    )
+}
+
+/// Whether both connections are the same flow seen from each end, between the same processes
+fn is_reverse(connection: &Connection, other: &Connection) -> bool {
+    let (local, peer) = (
+        connection.connected_connection(),
+        other.connected_connection(),
+    );
+    local.socket_type() == peer.socket_type()
+        && connection.connected_host().name() == other.listening_host().name()
+        && connection.listening_host().name() == other.connected_host().name()
+        && local.process() == other.listening_connection().process()
+        && peer.process() == connection.listening_connection().process()
+        && canonical_socket(local.local_socket()) == canonical_socket(peer.peer_socket())
+        && canonical_socket(local.peer_socket()) == canonical_socket(peer.local_socket())
+        && connection.via().is_empty()
+        && other.via().is_empty()
+}
+
+/// Connections of the [`DefaultMatcher`]
+fn match_sockets(hosts: &[host::Host], no_loopback: bool) -> Vec<Connection<'_>> {
     let mut hosts_connections: Vec<Connection> = Vec::new();
 
     // First, get loopback connection
@@ -77,16 +889,8 @@ pub fn build_connections_list(hosts: &[host::Host], no_loopback: bool) -> Vec<Co
                 for listening_socket in host.listening_sockets() {
                     if host_connection.socket_type() == listening_socket.socket_type()
                         && host_connection.peer_socket().port() == listening_socket.port()
-                        && host.ips().contains(&host_connection.peer_socket().ip())
-                        && ((host_connection.peer_socket().is_ipv4()
-                            && match listening_socket.ipv6_only() {
-                                Some(b) => !b,
-                                None => false,
-                            })
-                            || (host_connection.peer_socket().is_ipv6()
-                                && listening_socket.ip_addr().is_ipv6())
-                            || (host_connection.peer_socket().is_ipv4()
-                                && listening_socket.ip_addr().is_ipv4()))
+                        && host.has_ip(host_connection.peer_socket().ip())
+                        && accepts_ip_version(listening_socket, host_connection.peer_socket())
                     {
                         // Here we found a connection between a local process and a local listening
                         // socket
@@ -113,17 +917,9 @@ pub fn build_connections_list(hosts: &[host::Host], no_loopback: bool) -> Vec<Co
                 for host_connection in host.connections() {
                     // Check if the connection matches a listening socket
                     if host_connection.socket_type() == peer_listening_socket.socket_type()
-                        && peer.ips().contains(&host_connection.peer_socket().ip())
+                        && peer.has_ip(host_connection.peer_socket().ip())
                         && peer_listening_socket.port() == host_connection.peer_socket().port()
-                        && ((host_connection.peer_socket().is_ipv4()
-                            && match peer_listening_socket.ipv6_only() {
-                                Some(b) => !b,
-                                None => false,
-                            })
-                            || (peer_listening_socket.ip_addr().is_ipv4()
-                                && host_connection.peer_socket().is_ipv4())
-                            || (peer_listening_socket.ip_addr().is_ipv6()
-                                && host_connection.peer_socket().is_ipv6()))
+                        && accepts_ip_version(peer_listening_socket, host_connection.peer_socket())
                         && !peer_listening_socket.is_loopback()
                     {
                         // Here we found a connection between host and peer, with peer being the
@@ -146,15 +942,12 @@ pub fn build_connections_list(hosts: &[host::Host], no_loopback: bool) -> Vec<Co
             for peer_connection in peer.connections() {
                 for host_connection in host.connections() {
                     if host_connection.socket_type() == peer_connection.socket_type()
-                        && peer.ips().contains(&host_connection.peer_socket().ip())
-                        && !host_connection.local_socket().ip().is_loopback()
+                        && peer.has_ip(host_connection.peer_socket().ip())
+                        && !canonical_ip(host_connection.local_socket().ip()).is_loopback()
                         && host_connection.peer_socket().port()
                             == peer_connection.local_socket().port()
-                        && host_connection.peer_socket().ip() == peer_connection.local_socket().ip()
-                        && ((peer_connection.peer_socket().is_ipv4()
-                            && host_connection.peer_socket().is_ipv4())
-                            || (peer_connection.local_socket().is_ipv6()
-                                && host_connection.peer_socket().is_ipv6()))
+                        && canonical_ip(host_connection.peer_socket().ip())
+                            == canonical_ip(peer_connection.local_socket().ip())
                     {
                         // Find the listening socket that peer_connection belongs to
                         let mut connected_peer_listening_socket: Option<&host::ListeningSocket> =
@@ -181,85 +974,288 @@ pub fn build_connections_list(hosts: &[host::Host], no_loopback: bool) -> Vec<Co
     hosts_connections
 }
 
-#[cfg(test)]
-mod tests {
-    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+/// Connection of a host to the IP address of another captured host, which has no listening
+/// socket for it: the listener is firewalled, it was closed before the capture of the peer, or its
+/// line could not be parsed. Such connections are not part of the matched connections.
+#[derive(Debug)]
+pub struct UnmatchedKnownPeer<'a> {
+    connected_host: &'a host::Host,
+    connection: &'a host::Connection,
+    peer_host: &'a host::Host,
+}
 
-    use crate::{
-        connections_model::build_connections_list,
-        host::{Connection, Host, ListeningSocket, Process, SocketType},
-    };
+impl<'a> UnmatchedKnownPeer<'a> {
+    /// Get a reference to the host of the connection.
+    pub fn connected_host(&self) -> &'a host::Host {
+        self.connected_host
+    }
 
-    fn make_fake_connections() -> Vec<Host> {
-        // Create machines
-        let mut hosts = Vec::<Host>::new();
+    /// Get a reference to the connection.
+    pub fn connection(&self) -> &'a host::Connection {
+        self.connection
+    }
 
-        // Machine 1
-        let mut machine1 = Host::new("machine1");
-        // SSHD server
-        let sshd_listening_socket = ListeningSocket::new(
-            SocketAddr::V6("[::ffff:10.0.0.1]:22".parse().unwrap()),
-            SocketType::TCP,
-            Process::new("sshd", 101, "machine1".to_string()),
-            "machine1".to_string(),
-            Some(false),
-        );
-        // Nginx server
-        let nginx_listening_socket = ListeningSocket::new(
-            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 443)),
-            SocketType::TCP,
-            Process::new("nginx", 102, "machine1".to_string()),
-            "machine1".to_string(),
-            None,
-        );
-        machine1.add_listening_socket(sshd_listening_socket);
-        machine1.add_listening_socket(nginx_listening_socket);
-        machine1.add_ip(std::net::IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
-
-        let mut machine2 = Host::new("machine2");
-        machine2.add_ip(std::net::IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
-        // SSH connection
-        machine2.add_established_connection(Connection::new(
-            "10.0.0.2:5688".parse().unwrap(),
-            "[::ffff:10.0.0.1]:22".parse().unwrap(),
-            SocketType::TCP,
-            Process::new("ssh", 201, "machine2".to_string()),
-        ));
-        // HTTPS connection
-        machine2.add_established_connection(Connection::new(
-            "10.0.0.2:5681".parse().unwrap(),
-            "10.0.0.1:443".parse().unwrap(),
-            SocketType::TCP,
-            Process::new("firefox", 202, "machine2".to_string()),
+    /// Get a reference to the captured host having the peer IP address of the connection.
+    pub fn peer_host(&self) -> &'a host::Host {
+        self.peer_host
+    }
+}
+
+impl<'a> std::fmt::Display for UnmatchedKnownPeer<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} {}) -> {} (no listener on {})",
+            self.connected_host.name(),
+            self.connection.process().name(),
+            self.connection.local_socket(),
+            self.peer_host.name(),
+            self.connection.peer_socket(),
+        )
+    }
+}
+
+/// Find the connections to the IP address of another captured host which are not the connected
+/// side of one of `connections`, built from the `hosts`. The server ends of the connections,
+/// accepted by a listening socket of their host or bound to the port of a server, are left out, as
+/// are the connections to hosts which were not captured.
+pub fn find_unmatched_known_peers<'a>(
+    hosts: &'a [host::Host],
+    connections: &[Connection<'a>],
+) -> Vec<UnmatchedKnownPeer<'a>> {
+    let matched: HashSet<*const host::Connection> = connections
+        .iter()
+        .map(|connection| connection.connected_connection as *const _)
+        .collect();
+    let mut unmatched = Vec::new();
+    for host in hosts {
+        for connection in host.connections() {
+            let peer_ip = connection.peer_socket().ip();
+            if canonical_ip(peer_ip).is_loopback() || host.has_ip(peer_ip) {
+                continue;
+            }
+            let Some(peer_host) = hosts
+                .iter()
+                .find(|peer| peer.name() != host.name() && peer.has_ip(peer_ip))
+            else {
+                continue;
+            };
+            if is_accepted(host, connection)
+                || server_by_port(
+                    connection.local_socket().port(),
+                    connection.peer_socket().port(),
+                ) == Some(false)
+                || matched.contains(&(connection as *const _))
+            {
+                continue;
+            }
+            let unmatched_known_peer = UnmatchedKnownPeer {
+                connected_host: host,
+                connection,
+                peer_host,
+            };
+            log::debug!("unmatched connection: {}", unmatched_known_peer);
+            unmatched.push(unmatched_known_peer);
+        }
+    }
+    unmatched
+}
+
+/// Name of the processes of the connections added by [`infer_clients_from_server_side`]
+pub const INFERRED_CLIENT_PROCESS: &str = "unknown";
+
+/// Add the clients of the connections accepted by the listening sockets of the hosts, when the
+/// client side of the connection was not captured. The accepted socket is taken as evidence of a
+/// connection from its peer to the listening socket, made by an `unknown` process of:
+///
+/// - the host having the IP address of the peer, if it was captured without this connection;
+/// - otherwise, a host named after the IP address of the peer, added to `hosts`.
+///
+/// The connections captured on both sides are left as they are.
+pub fn infer_clients_from_server_side(hosts: &mut Vec<host::Host>) {
+    // Client host, if known, and accepted connection of each connection to infer
+    let mut accepted = Vec::<(Option<String>, host::Connection)>::new();
+    for host in hosts.iter() {
+        for connection in host.connections() {
+            let peer_ip = canonical_ip(connection.peer_socket().ip());
+            if peer_ip.is_loopback() || host.has_ip(peer_ip) || !is_accepted(host, connection) {
+                continue;
+            }
+            let client = hosts.iter().find(|h| h.has_ip(peer_ip));
+            if client.is_some_and(|client| {
+                client.connections().iter().any(|c| {
+                    c.socket_type() == connection.socket_type()
+                        && canonical_socket(c.local_socket())
+                            == canonical_socket(connection.peer_socket())
+                })
+            }) {
+                continue;
+            }
+            accepted.push((client.map(|c| c.name().to_string()), connection.clone()));
+        }
+    }
+
+    for (client, connection) in accepted {
+        let peer_ip = canonical_ip(connection.peer_socket().ip());
+        let name = client.unwrap_or_else(|| peer_ip.to_string());
+        let client = match hosts.iter().position(|h| h.name() == name) {
+            Some(index) => &mut hosts[index],
+            None => {
+                log::debug!("adding host {name} for the connections accepted from it");
+                let mut client = host::Host::new(&name);
+                client.add_ip(peer_ip);
+                hosts.push(client);
+                hosts.last_mut().expect("the host was just added")
+            }
+        };
+        client.add_established_connection(host::Connection::new(
+            *connection.peer_socket(),
+            *connection.local_socket(),
+            connection.socket_type().clone(),
+            host::Process::new(INFERRED_CLIENT_PROCESS, 0, name),
         ));
-        // Some UDP service
-        let some_udp_service = ListeningSocket::new(
-            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 3), 50001)),
-            SocketType::UDP,
-            Process::new("some_udp_service", 203, "machine2".to_string()),
-            "machine2".to_string(),
-            None,
-        );
-        machine2.add_listening_socket(some_udp_service);
-
-        // Machine 3
-        let mut machine3 = Host::new("machine3");
-        machine3.add_ip(std::net::IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)));
-        // Some UDP client
-        let some_udp_client = Connection::new(
-            "10.0.0.3:50002".parse().unwrap(),
-            "10.0.0.2:50001".parse().unwrap(),
-            SocketType::UDP,
-            Process::new("some_udp_client", 301, "machine3".to_string()),
-        );
-        machine3.add_established_connection(some_udp_client);
+    }
+}
 
-        // Machines vector
-        hosts.push(machine1);
-        hosts.push(machine2);
-        hosts.push(machine3);
+/// Whether the connection was accepted by one of the listening sockets of its host
+fn is_accepted(host: &host::Host, connection: &host::Connection) -> bool {
+    let local_socket = canonical_socket(connection.local_socket());
+    host.listening_sockets().iter().any(|listening_socket| {
+        listening_socket.socket_type() == connection.socket_type()
+            && listening_socket.port() == local_socket.port()
+            && (listening_socket.ip_addr().is_unspecified()
+                || canonical_ip(listening_socket.ip_addr()) == local_socket.ip())
+    })
+}
 
-        hosts
+/// Socket address with its IPv4-mapped IPv6 address written as an IPv4 address
+fn canonical_socket(socket: &SocketAddr) -> SocketAddr {
+    SocketAddr::new(canonical_ip(socket.ip()), socket.port())
+}
+
+/// Name of a proxy in [`Connection::via`]
+fn proxy_label(host: &host::Host, process: &host::Process) -> String {
+    format!("{} ({})", host.name(), process.name())
+}
+
+/// Whether both connections derived by the [`ProxyAwareMatcher`] or [`collapse_docker_proxy`], or
+/// matched from the same end by [`collapse_bidirectional`], join the same processes through the
+/// same proxies
+fn is_same_derived(connection: &Connection, other: &Connection) -> bool {
+    std::ptr::eq(connection.connected_connection, other.connected_connection)
+        && std::ptr::eq(connection.listening_connection, other.listening_connection)
+        && connection.via == other.via
+}
+
+/// Keep the connections of the neighborhood of the focus hosts: the connections of these hosts
+/// for a depth of 1, plus the connections of their peers for a depth of 2, and so on. A depth of 0
+/// is taken as 1.
+pub fn filter_neighborhood<'a, S: AsRef<str>>(
+    connections: Vec<Connection<'a>>,
+    focus_hosts: &[S],
+    depth: usize,
+) -> Vec<Connection<'a>> {
+    let mut neighborhood: HashSet<&str> = focus_hosts.iter().map(AsRef::as_ref).collect();
+    for _ in 1..depth {
+        let peers: Vec<&str> = connections
+            .iter()
+            .filter_map(|connection| {
+                let listening_host = connection.listening_host.name();
+                let connected_host = connection.connected_host.name();
+                match (
+                    neighborhood.contains(listening_host),
+                    neighborhood.contains(connected_host),
+                ) {
+                    (true, false) => Some(connected_host),
+                    (false, true) => Some(listening_host),
+                    _ => None,
+                }
+            })
+            .collect();
+        if peers.is_empty() {
+            break;
+        }
+        neighborhood.extend(peers);
+    }
+    connections
+        .into_iter()
+        .filter(|connection| {
+            neighborhood.contains(connection.listening_host().name())
+                || neighborhood.contains(connection.connected_host().name())
+        })
+        .collect()
+}
+
+/// Whether the listening socket accepts connections to `peer_socket`, as far as the IP versions
+/// go. The IPv4-mapped IPv6 addresses are IPv4 addresses, which the dual-stack IPv6 sockets accept.
+fn accepts_ip_version(listening_socket: &host::ListeningSocket, peer_socket: &SocketAddr) -> bool {
+    match (
+        canonical_ip(listening_socket.ip_addr()),
+        canonical_ip(peer_socket.ip()),
+    ) {
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => true,
+        (IpAddr::V6(_), IpAddr::V4(_)) => match listening_socket.ipv6_only() {
+            Some(ipv6_only) => !ipv6_only,
+            // The peers written in the IPv6 form were reached from an IPv6 socket, so the listening
+            // socket must accept both versions
+            None => peer_socket.is_ipv6(),
+        },
+        (IpAddr::V4(_), IpAddr::V6(_)) => false,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::{
+        connections_model::{
+            build_connection_records, build_connections_list, build_connections_list_with,
+            build_connections_report, filter_neighborhood, find_unmatched_known_peers,
+            infer_clients_from_server_side, ConnectionRecord, ConnectionsOptions, DefaultMatcher,
+            Direction, ProxyAwareMatcher, RecordKind, ServerSide, ToConnectionRecord,
+        },
+        host::{Connection, Host, Process, SocketType},
+        parsers::linux::{LinuxHostRawData, NetworkOutput},
+        test_utils::{self, HostBuilder},
+    };
+
+    pub(crate) fn make_fake_connections() -> Vec<Host> {
+        vec![
+            HostBuilder::new("machine1")
+                .ip("10.0.0.1")
+                // SSHD server
+                .listen(
+                    "[::ffff:10.0.0.1]:22",
+                    SocketType::TCP,
+                    "sshd",
+                    101,
+                    Some(false),
+                )
+                // Nginx server
+                .listen_tcp(443, "nginx", 102)
+                .build(),
+            HostBuilder::new("machine2")
+                .ip("10.0.0.2")
+                // SSH connection
+                .connect_tcp("10.0.0.2:5688", "[::ffff:10.0.0.1]:22", "ssh", 201)
+                // HTTPS connection
+                .connect_tcp("10.0.0.2:5681", "10.0.0.1:443", "firefox", 202)
+                // Some UDP service
+                .listen(
+                    "10.0.0.3:50001",
+                    SocketType::UDP,
+                    "some_udp_service",
+                    203,
+                    None,
+                )
+                .build(),
+            HostBuilder::new("machine3")
+                .ip("10.0.0.3")
+                // Some UDP client
+                .connect_udp("10.0.0.3:50002", "10.0.0.2:50001", "some_udp_client", 301)
+                .build(),
+        ]
     }
 
     #[test]
@@ -351,4 +1347,522 @@ mod tests {
             "missing TCP connection from machine2 ssh client on machine1 sshd server:\n{connections:#?}"
         );
     }
+
+    #[test]
+    /// Test TCP connections detection between an IPv4 server and clients connecting to its
+    /// IPv4-mapped IPv6 address, from another host or from the same host, while an IPv6 only server
+    /// refuses them
+    fn test_tcp4_mapped_peer_connections() {
+        let hosts = [
+            HostBuilder::new("server")
+                .ip("10.0.0.5")
+                .listen("0.0.0.0:80", SocketType::TCP, "nginx", 101, None)
+                .listen("[::]:8080", SocketType::TCP, "tomcat", 102, Some(true))
+                .connect_tcp(
+                    "[::ffff:127.0.0.1]:50000",
+                    "[::ffff:127.0.0.1]:80",
+                    "curl",
+                    103,
+                )
+                .connect_tcp(
+                    "[::ffff:127.0.0.1]:50001",
+                    "[::ffff:127.0.0.1]:8080",
+                    "curl",
+                    103,
+                )
+                .build(),
+            HostBuilder::new("client")
+                .ip("10.0.0.6")
+                .connect_tcp(
+                    "[::ffff:10.0.0.6]:50000",
+                    "[::ffff:10.0.0.5]:80",
+                    "wget",
+                    201,
+                )
+                .connect_tcp(
+                    "[::ffff:10.0.0.6]:50000",
+                    "[::ffff:10.0.0.5]:8080",
+                    "wget",
+                    202,
+                )
+                .build(),
+        ];
+        let connections = build_connections_list(&hosts, false);
+        let found: Vec<(&str, &str, u16)> = connections
+            .iter()
+            .map(|c| {
+                (
+                    c.connected_host().name(),
+                    c.listening_connection().process().name(),
+                    c.listening_connection().port(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [("server", "nginx", 80), ("client", "nginx", 80)],
+            "{connections:#?}"
+        );
+    }
+
+    #[test]
+    /// Test that the records keep the connections once the hosts are dropped
+    fn test_connection_records() {
+        let records: Vec<ConnectionRecord> = {
+            let hosts = make_fake_connections();
+            build_connection_records(&hosts, &ConnectionsOptions::default())
+        };
+
+        let record = records
+            .iter()
+            .find(|r| r.source_process() == "firefox")
+            .unwrap();
+        assert_eq!(record.source_host(), "machine2");
+        assert_eq!(record.source_pid(), 202);
+        assert_eq!(record.source_socket(), &"10.0.0.2:5681".parse().unwrap());
+        assert_eq!(record.dest_host(), "machine1");
+        assert_eq!(record.dest_process(), "nginx");
+        assert_eq!(record.dest_pid(), 102);
+        assert_eq!(record.dest_socket(), &"0.0.0.0:443".parse().unwrap());
+        assert_eq!(record.protocol(), &SocketType::TCP);
+        assert_eq!(record.count(), None);
+        assert_eq!(record.first_seen(), None);
+        assert_eq!(record.last_seen(), None);
+    }
+
+    #[test]
+    /// Test that the records are built with the same options as the borrowed connections
+    fn test_build_connection_records_options() {
+        let mut hosts = make_fake_connections();
+        let mut machine1 = hosts.remove(0);
+        machine1.add_established_connection(Connection::new(
+            "10.0.0.1:40000".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("curl", 103, "machine1".to_string()),
+        ));
+        hosts.push(machine1);
+
+        let all = build_connection_records(&hosts, &ConnectionsOptions::default());
+        let no_loopback = build_connection_records(
+            &hosts,
+            &ConnectionsOptions {
+                no_loopback: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(all.len(), build_connections_list(&hosts, false).len());
+        assert_eq!(no_loopback.len(), all.len() - 1);
+        assert!(!no_loopback.iter().any(|r| r.source_process() == "curl"));
+
+        let connections = build_connections_list(&hosts, false);
+        let records: Vec<ConnectionRecord> = connections
+            .iter()
+            .map(ToConnectionRecord::to_connection_record)
+            .collect();
+        assert_eq!(records, all);
+        assert_eq!(all[0].to_connection_record(), all[0]);
+    }
+
+    /// Chain of 4 hosts, each one connecting to the SSH server of the previous one
+    fn make_chain() -> Vec<Host> {
+        (1..=4)
+            .map(|number| {
+                let builder = HostBuilder::new(&format!("machine{number}"))
+                    .ip(&format!("10.0.0.{number}"))
+                    .listen_tcp(22, "sshd", 100);
+                match number {
+                    1 => builder,
+                    _ => builder.connect_tcp(
+                        &format!("10.0.0.{number}:50000"),
+                        &format!("10.0.0.{}:22", number - 1),
+                        "ssh",
+                        200,
+                    ),
+                }
+                .build()
+            })
+            .collect()
+    }
+
+    #[test]
+    /// Test that the neighborhood of the focus hosts only reaches the hosts within the depth
+    fn test_filter_neighborhood() {
+        let hosts = make_chain();
+        let hosts_of = |focus_hosts: &[&str], depth: usize| {
+            let connections = build_connections_list(&hosts, false);
+            let mut names: Vec<&str> = filter_neighborhood(connections, focus_hosts, depth)
+                .iter()
+                .flat_map(|c| [c.listening_host().name(), c.connected_host().name()])
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            names
+        };
+
+        assert_eq!(hosts_of(&["machine1"], 1), ["machine1", "machine2"]);
+        assert_eq!(hosts_of(&["machine1"], 0), ["machine1", "machine2"]);
+        assert_eq!(
+            hosts_of(&["machine1"], 2),
+            ["machine1", "machine2", "machine3"]
+        );
+        assert_eq!(
+            hosts_of(&["machine2"], 1),
+            ["machine1", "machine2", "machine3"]
+        );
+        assert_eq!(
+            hosts_of(&["machine2"], 2),
+            ["machine1", "machine2", "machine3", "machine4"]
+        );
+        assert_eq!(
+            hosts_of(&["machine1", "machine4"], 1),
+            ["machine1", "machine2", "machine3", "machine4"]
+        );
+        assert!(hosts_of(&["machine5"], 2).is_empty());
+    }
+
+    /// The app of `web` reaches the database of `db` through its local stunnel client, which
+    /// connects to the stunnel server of `relay`
+    pub(crate) fn make_stunnel_chain() -> Vec<Host> {
+        vec![
+            HostBuilder::new("web")
+                .ip("10.0.0.10")
+                .ip("127.0.0.1")
+                .listen("127.0.0.1:6380", SocketType::TCP, "stunnel", 101, None)
+                .connect_tcp("127.0.0.1:50000", "127.0.0.1:6380", "app", 100)
+                .connect_tcp("10.0.0.10:50001", "10.0.0.20:8443", "stunnel", 101)
+                .build(),
+            HostBuilder::new("relay")
+                .ip("10.0.0.20")
+                .listen_tcp(8443, "stunnel", 200)
+                .connect_tcp("10.0.0.20:50002", "10.0.0.30:5432", "stunnel", 200)
+                .build(),
+            HostBuilder::new("db")
+                .ip("10.0.0.30")
+                .listen_tcp(5432, "postgres", 300)
+                .build(),
+        ]
+    }
+
+    #[test]
+    /// Test that the chain of stunnel proxies gives a connection from the app to the database
+    fn test_proxy_aware_matcher() {
+        let hosts = make_stunnel_chain();
+        let direct = build_connections_list(&hosts, false);
+        assert_eq!(direct.len(), 3);
+        assert!(direct.iter().all(|c| c.via().is_empty()));
+
+        let matcher = ProxyAwareMatcher::new(&["stunnel"]);
+        let connections = build_connections_list_with(&hosts, false, &matcher);
+        assert_eq!(connections.len(), 4);
+        let derived: Vec<_> = connections.iter().filter(|c| !c.via().is_empty()).collect();
+        assert_eq!(derived.len(), 1);
+        let record = ConnectionRecord::from(derived[0]);
+        assert_eq!(
+            (record.source_host(), record.source_process()),
+            ("web", "app")
+        );
+        assert_eq!(
+            (record.dest_host(), record.dest_process()),
+            ("db", "postgres")
+        );
+        assert_eq!(record.via(), ["web (stunnel)", "relay (stunnel)"]);
+
+        // The hop to the local proxy is a loopback connection, unlike the derived one
+        let connections = build_connections_list_with(&hosts, true, &matcher);
+        assert_eq!(connections.len(), 3);
+        assert_eq!(
+            connections.iter().filter(|c| !c.via().is_empty()).count(),
+            1
+        );
+
+        // Without proxies, the matcher finds the direct connections only
+        let matcher = ProxyAwareMatcher::new(&["haproxy"]);
+        assert_eq!(
+            build_connections_list_with(&hosts, false, &matcher).len(),
+            3
+        );
+    }
+
+    /// Capture of a Docker host publishing the port 80 of its `webapp` container as 8080, and
+    /// serving SSH
+    const DOCKER_HOST_SS: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      4096   0.0.0.0:8080       0.0.0.0:*         users:((\"docker-proxy\",pid=2301,fd=4))
+tcp   LISTEN 0      4096   [::]:8080          [::]:*            users:((\"docker-proxy\",pid=2308,fd=4))
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=812,fd=3))
+tcp   ESTAB  0      0      10.0.0.5:8080      10.0.0.2:51000    users:((\"docker-proxy\",pid=2301,fd=7))
+tcp   ESTAB  0      0      172.17.0.1:46790   172.17.0.2:80     users:((\"docker-proxy\",pid=2301,fd=8))
+tcp   ESTAB  0      0      10.0.0.5:22        10.0.0.2:50022    users:((\"sshd\",pid=3120,fd=4))";
+
+    /// The `laptop` browses the container `webapp` through the docker-proxy of `docker01`, and
+    /// connects to `docker01` over SSH
+    fn make_docker_proxy_hosts() -> Vec<Host> {
+        let (docker_host, _) = LinuxHostRawData::new(
+            "docker01".to_string(),
+            NetworkOutput::Ss(DOCKER_HOST_SS.to_string()),
+            vec!["10.0.0.5".parse().unwrap(), "172.17.0.1".parse().unwrap()],
+            None,
+        )
+        .parse_host()
+        .unwrap();
+        vec![
+            docker_host,
+            HostBuilder::new("webapp")
+                .ip("172.17.0.2")
+                .listen_tcp(80, "nginx", 1)
+                .build(),
+            HostBuilder::new("laptop")
+                .ip("10.0.0.2")
+                .connect_tcp("10.0.0.2:51000", "10.0.0.5:8080", "firefox", 4000)
+                .connect_tcp("10.0.0.2:50022", "10.0.0.5:22", "ssh", 4001)
+                .build(),
+        ]
+    }
+
+    #[test]
+    /// Test that the client of docker-proxy is connected to the process of the container instead
+    fn test_collapse_docker_proxy() {
+        let hosts = make_docker_proxy_hosts();
+        let summary = |records: &[ConnectionRecord]| {
+            let mut summary: Vec<String> = records
+                .iter()
+                .map(|r| {
+                    format!(
+                        "{} ({}) -> {} ({}) via {:?}",
+                        r.source_host(),
+                        r.source_process(),
+                        r.dest_host(),
+                        r.dest_process(),
+                        r.via()
+                    )
+                })
+                .collect();
+            summary.sort();
+            summary.dedup();
+            summary
+        };
+
+        let records = build_connection_records(&hosts, &ConnectionsOptions::default());
+        assert_eq!(
+            summary(&records),
+            [
+                "docker01 (docker-proxy) -> webapp (nginx) via []",
+                "laptop (firefox) -> docker01 (docker-proxy) via []",
+                "laptop (ssh) -> docker01 (sshd) via []",
+            ]
+        );
+
+        let options = ConnectionsOptions {
+            collapse_docker_proxy: true,
+            ..Default::default()
+        };
+        let records = build_connection_records(&hosts, &options);
+        assert_eq!(
+            summary(&records),
+            [
+                "laptop (firefox) -> webapp (nginx) via [\"docker01 (docker-proxy)\"]",
+                "laptop (ssh) -> docker01 (sshd) via []",
+            ]
+        );
+        let derived: Vec<&ConnectionRecord> =
+            records.iter().filter(|r| !r.via().is_empty()).collect();
+        assert_eq!(derived.len(), 1);
+        assert_eq!(derived[0].source_socket().port(), 51000);
+        assert_eq!(derived[0].dest_socket().port(), 80);
+    }
+
+    /// Sorted `source (process) -> dest (process)` summary of the records, without duplicates
+    fn summarize(records: &[ConnectionRecord]) -> Vec<String> {
+        let mut summary: Vec<String> = records
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} ({}) -> {} ({})",
+                    r.source_host(),
+                    r.source_process(),
+                    r.dest_host(),
+                    r.dest_process()
+                )
+            })
+            .collect();
+        summary.sort();
+        summary.dedup();
+        summary
+    }
+
+    #[test]
+    /// Test that the clients of a server captured alone are inferred from its accepted sockets,
+    /// while its own outgoing connections are left as they are
+    fn test_infer_clients_solo_server() {
+        let hosts = vec![
+            HostBuilder::new("db01")
+                .ip("10.0.0.1")
+                .listen_tcp(5432, "postgres", 100)
+                .connect_tcp("10.0.0.1:5432", "10.0.0.9:40000", "postgres", 101)
+                .connect_tcp(
+                    "[::ffff:10.0.0.1]:5432",
+                    "[::ffff:10.0.0.7]:40001",
+                    "postgres",
+                    102,
+                )
+                .connect_tcp("10.0.0.1:50000", "10.0.0.8:443", "curl", 103)
+                .build(),
+            HostBuilder::new("backup").ip("10.0.0.7").build(),
+        ];
+        assert!(build_connection_records(&hosts, &ConnectionsOptions::default()).is_empty());
+
+        let options = ConnectionsOptions {
+            infer_clients_from_server_side: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            summarize(&build_connection_records(&hosts, &options)),
+            [
+                "10.0.0.9 (unknown) -> db01 (postgres)",
+                "backup (unknown) -> db01 (postgres)",
+            ]
+        );
+
+        let mut inferred_hosts = hosts.clone();
+        infer_clients_from_server_side(&mut inferred_hosts);
+        let names: Vec<&str> = inferred_hosts.iter().map(|h| h.name()).collect();
+        assert_eq!(names, ["db01", "backup", "10.0.0.9"]);
+        assert_eq!(
+            inferred_hosts[2].connections()[0].local_socket(),
+            &"10.0.0.9:40000".parse().unwrap()
+        );
+    }
+
+    #[test]
+    /// Test that the connections captured on both sides are not inferred again
+    fn test_infer_clients_both_sides() {
+        let hosts = vec![
+            HostBuilder::new("db01")
+                .ip("10.0.0.1")
+                .listen_tcp(5432, "postgres", 100)
+                .connect_tcp("10.0.0.1:5432", "[::ffff:10.0.0.2]:40000", "postgres", 101)
+                .build(),
+            HostBuilder::new("app01")
+                .ip("10.0.0.2")
+                .connect_tcp("10.0.0.2:40000", "10.0.0.1:5432", "java", 200)
+                .build(),
+        ];
+        let mut inferred_hosts = hosts.clone();
+        infer_clients_from_server_side(&mut inferred_hosts);
+        assert_eq!(inferred_hosts, hosts);
+
+        let options = ConnectionsOptions {
+            infer_clients_from_server_side: true,
+            ..Default::default()
+        };
+        let records = build_connection_records(&hosts, &options);
+        assert_eq!(
+            records,
+            build_connection_records(&hosts, &ConnectionsOptions::default())
+        );
+        assert_eq!(summarize(&records), ["app01 (java) -> db01 (postgres)"]);
+    }
+
+    #[test]
+    /// Test that a UDP flow seen from both ends is drawn once, both ways
+    fn test_collapse_bidirectional() {
+        let hosts = test_utils::udp_symmetric();
+        let records = build_connection_records(&hosts, &ConnectionsOptions::default());
+        assert_eq!(summarize(&records), ["ntp1 (ntpd) -> ntp2 (ntpd)"]);
+        assert_eq!(records[0].direction(), Direction::Both);
+        assert_eq!(records[0].server_side(), ServerSide::Arbitrary);
+
+        let options = ConnectionsOptions {
+            keep_both_directions: true,
+            ..Default::default()
+        };
+        let records = build_connection_records(&hosts, &options);
+        assert_eq!(
+            summarize(&records),
+            ["ntp1 (ntpd) -> ntp2 (ntpd)", "ntp2 (ntpd) -> ntp1 (ntpd)"]
+        );
+        assert!(records.iter().all(|r| r.direction() == Direction::Out));
+        assert!(records
+            .iter()
+            .all(|r| r.server_side() == ServerSide::Listen));
+    }
+
+    #[test]
+    /// Test that the server of a UDP flow is told by its ports, when both ends are listening or
+    /// when the client is bound to an ephemeral port
+    fn test_udp_direction() {
+        let dns = HostBuilder::new("dns")
+            .ip("10.0.0.5")
+            .listen_udp(53, "named", 500)
+            .connect_udp("10.0.0.5:53", "10.0.0.4:40000", "named", 500)
+            .build();
+        let resolver = HostBuilder::new("resolver")
+            .ip("10.0.0.4")
+            .listen_udp(40000, "unbound", 400)
+            .connect_udp("10.0.0.4:40000", "10.0.0.5:53", "unbound", 400)
+            .build();
+        let records = build_connection_records(
+            &[dns.clone(), resolver.clone()],
+            &ConnectionsOptions::default(),
+        );
+        assert_eq!(summarize(&records), ["resolver (unbound) -> dns (named)"]);
+        assert_eq!(records[0].direction(), Direction::Both);
+        assert_eq!(records[0].server_side(), ServerSide::Port);
+
+        let mut resolver = resolver;
+        resolver.connections_mut().clear();
+        let records = build_connection_records(&[dns, resolver], &ConnectionsOptions::default());
+        assert_eq!(summarize(&records), ["dns (named) -> resolver (unbound)"]);
+        assert_eq!(records[0].direction(), Direction::In);
+        assert_eq!(records[0].server_side(), ServerSide::Port);
+    }
+
+    #[test]
+    /// Test that a connection to a captured host lacking its listening socket is reported, unlike
+    /// the server end of the same connection and the connections to hosts which were not captured
+    fn test_unmatched_known_peers() {
+        let hosts = test_utils::missing_listener();
+        let report =
+            build_connections_report(&hosts, &ConnectionsOptions::default(), &DefaultMatcher);
+        let records: Vec<ConnectionRecord> = report
+            .connections
+            .iter()
+            .map(ConnectionRecord::from)
+            .collect();
+        assert_eq!(summarize(&records), ["app (ssh) -> db (sshd)"]);
+        let unmatched: Vec<String> = report
+            .unmatched_known_peers
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            unmatched,
+            ["app (java 10.0.0.2:40000) -> db (no listener on 10.0.0.3:5432)"]
+        );
+
+        let record = report.unmatched_known_peers[0].to_connection_record();
+        assert_eq!(record.kind(), RecordKind::UnmatchedKnownPeer);
+        assert_eq!(record.dest_host(), "db");
+        assert_eq!(record.dest_process(), "");
+        assert_eq!(record.dest_socket(), &"10.0.0.3:5432".parse().unwrap());
+        assert!(!record.is_same_connection(&records[0]));
+
+        // Once the listener is captured, the connection is matched
+        let mut hosts = hosts;
+        hosts[0] = test_utils::HostBuilder::new("db")
+            .ip("10.0.0.3")
+            .listen_tcp(22, "sshd", 300)
+            .listen_tcp(5432, "postgres", 301)
+            .build();
+        let report =
+            build_connections_report(&hosts, &ConnectionsOptions::default(), &DefaultMatcher);
+        assert!(report.unmatched_known_peers.is_empty());
+        let hosts = test_utils::client_server();
+        let connections = build_connections_list(&hosts, false);
+        assert!(find_unmatched_known_peers(&hosts, &connections).is_empty());
+
+        // The connections left out by the matcher are reported
+        assert_eq!(find_unmatched_known_peers(&hosts, &[]).len(), 1);
+    }
 }
@@ -1,75 +1,564 @@
 //! This module handles the CSV output of the connections graph.
 
-use crate::connections_model::Connection;
-use crate::host;
+use crate::{
+    connections_model::{ConnectionRecord, ToConnectionRecord},
+    host::{canonical_ip, Host},
+    parsers::dns_map::DnsMap,
+    provenance::Provenance,
+};
 use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
 use csv;
+use std::{io::Write, time::SystemTime};
 
-/// Output a CSV formatted string of all the hosts connections with the following columns :
+/// A column of the CSV output, named as in the `--columns` option of the CLI. The captures only
+/// keep the established connections, so there is no connection state column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    SourceHost,
+    DestHost,
+    SourceProcess,
+    DestProcess,
+    SourcePid,
+    DestPid,
+    /// Local socket of the connected process
+    SourceSocket,
+    /// Listening socket of the listening process
+    DestSocket,
+    Protocol,
+    SourceIp,
+    SourcePort,
+    /// IP the connected process connected to
+    DestIp,
+    DestPort,
+    /// IP the listening socket is bound to, such as `0.0.0.0`
+    DestBindAddress,
+    /// Whether the listening socket only accepts IPv6 connections, empty if unknown
+    Ipv6Only,
+    /// Number of captures in which the connection was seen, empty for a single capture
+    Count,
+    FirstSeen,
+    LastSeen,
+    /// Name of the IP the connected process connected to, from the DNS map of the capture
+    /// directories, empty if unknown
+    DestName,
+    /// `out`, `in` or `both`, see [`crate::connections_model::Direction`]
+    Direction,
+    /// How the server side was told: `listen`, `port` or `arbitrary`, see
+    /// [`crate::connections_model::ServerSide`]
+    ServerSide,
+    /// `connection`, or `unmatched_known_peer` for the connections to a captured host without a
+    /// listening socket for them, see [`crate::connections_model::RecordKind`]
+    RowType,
+}
+
+/// Columns of the CSV output by default, which must stay in this order for the existing scripts
+pub const DEFAULT_COLUMNS: [CsvColumn; 9] = [
+    CsvColumn::SourceHost,
+    CsvColumn::DestHost,
+    CsvColumn::SourceProcess,
+    CsvColumn::DestProcess,
+    CsvColumn::SourcePid,
+    CsvColumn::DestPid,
+    CsvColumn::SourceSocket,
+    CsvColumn::DestSocket,
+    CsvColumn::Protocol,
+];
+
+/// Every column, in the order of the help messages
+pub const ALL_COLUMNS: [CsvColumn; 22] = [
+    CsvColumn::SourceHost,
+    CsvColumn::DestHost,
+    CsvColumn::SourceProcess,
+    CsvColumn::DestProcess,
+    CsvColumn::SourcePid,
+    CsvColumn::DestPid,
+    CsvColumn::SourceSocket,
+    CsvColumn::DestSocket,
+    CsvColumn::Protocol,
+    CsvColumn::SourceIp,
+    CsvColumn::SourcePort,
+    CsvColumn::DestIp,
+    CsvColumn::DestPort,
+    CsvColumn::DestBindAddress,
+    CsvColumn::Ipv6Only,
+    CsvColumn::Count,
+    CsvColumn::FirstSeen,
+    CsvColumn::LastSeen,
+    CsvColumn::DestName,
+    CsvColumn::Direction,
+    CsvColumn::ServerSide,
+    CsvColumn::RowType,
+];
+
+impl CsvColumn {
+    /// Name of the column in the `--columns` option
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::SourceHost => "src_host",
+            CsvColumn::DestHost => "dst_host",
+            CsvColumn::SourceProcess => "src_process",
+            CsvColumn::DestProcess => "dst_process",
+            CsvColumn::SourcePid => "src_pid",
+            CsvColumn::DestPid => "dst_pid",
+            CsvColumn::SourceSocket => "src_socket",
+            CsvColumn::DestSocket => "dst_socket",
+            CsvColumn::Protocol => "protocol",
+            CsvColumn::SourceIp => "src_ip",
+            CsvColumn::SourcePort => "src_port",
+            CsvColumn::DestIp => "dst_ip",
+            CsvColumn::DestPort => "dst_port",
+            CsvColumn::DestBindAddress => "dst_bind_address",
+            CsvColumn::Ipv6Only => "ipv6_only",
+            CsvColumn::Count => "count",
+            CsvColumn::FirstSeen => "first_seen",
+            CsvColumn::LastSeen => "last_seen",
+            CsvColumn::DestName => "dst_name",
+            CsvColumn::Direction => "direction",
+            CsvColumn::ServerSide => "server_side",
+            CsvColumn::RowType => "row_type",
+        }
+    }
+
+    /// Header of the column in the CSV output
+    pub fn header(&self) -> &'static str {
+        match self {
+            CsvColumn::SourceHost => "Source host",
+            CsvColumn::DestHost => "Dest host",
+            CsvColumn::SourceProcess => "Source process",
+            CsvColumn::DestProcess => "Dest process",
+            CsvColumn::SourcePid => "Source PID",
+            CsvColumn::DestPid => "Dest PID",
+            CsvColumn::SourceSocket => "Source process socket",
+            CsvColumn::DestSocket => "Dest process socket",
+            CsvColumn::Protocol => "Protocol",
+            CsvColumn::SourceIp => "Source IP",
+            CsvColumn::SourcePort => "Source port",
+            CsvColumn::DestIp => "Dest IP",
+            CsvColumn::DestPort => "Dest port",
+            CsvColumn::DestBindAddress => "Dest bind address",
+            CsvColumn::Ipv6Only => "IPv6 only",
+            CsvColumn::Count => "Captures",
+            CsvColumn::FirstSeen => "First seen",
+            CsvColumn::LastSeen => "Last seen",
+            CsvColumn::DestName => "Dest name",
+            CsvColumn::Direction => "Direction",
+            CsvColumn::ServerSide => "Server side",
+            CsvColumn::RowType => "Row type",
+        }
+    }
+
+    /// Value of the column for `record`, empty if unknown
+    fn value(&self, record: &ConnectionRecord, options: &CsvOptions) -> String {
+        let format_time = |time: Option<SystemTime>| {
+            time.map(|time| DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true))
+                .unwrap_or_default()
+        };
+        match self {
+            CsvColumn::SourceHost => record.source_host().to_string(),
+            CsvColumn::DestHost => record.dest_host().to_string(),
+            CsvColumn::SourceProcess => record.source_process().to_string(),
+            CsvColumn::DestProcess => record.dest_process().to_string(),
+            CsvColumn::SourcePid => record.source_pid().to_string(),
+            CsvColumn::DestPid => record.dest_pid().to_string(),
+            CsvColumn::SourceSocket => record.source_socket().to_string(),
+            CsvColumn::DestSocket => record.dest_socket().to_string(),
+            CsvColumn::Protocol => record.protocol().to_string(),
+            CsvColumn::SourceIp => record.source_socket().ip().to_string(),
+            CsvColumn::SourcePort => record.source_socket().port().to_string(),
+            CsvColumn::DestIp => record.peer_socket().ip().to_string(),
+            CsvColumn::DestPort => record.peer_socket().port().to_string(),
+            CsvColumn::DestBindAddress => record.dest_socket().ip().to_string(),
+            CsvColumn::Ipv6Only => record
+                .ipv6_only()
+                .map(|ipv6_only| ipv6_only.to_string())
+                .unwrap_or_default(),
+            CsvColumn::Count => record
+                .count()
+                .map(|count| count.to_string())
+                .unwrap_or_default(),
+            CsvColumn::FirstSeen => format_time(record.first_seen()),
+            CsvColumn::LastSeen => format_time(record.last_seen()),
+            CsvColumn::DestName => options
+                .peer_names
+                .get(&canonical_ip(record.peer_socket().ip()))
+                .cloned()
+                .unwrap_or_default(),
+            CsvColumn::Direction => record.direction().to_string(),
+            CsvColumn::ServerSide => record.server_side().to_string(),
+            CsvColumn::RowType => record.kind().to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for CsvColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL_COLUMNS
+            .into_iter()
+            .find(|column| column.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = ALL_COLUMNS.iter().map(CsvColumn::name).collect();
+                format!("unknown column {s:?}, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// Layout of the CSV output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub columns: Vec<CsvColumn>,
+    /// Field delimiter, usually `b','`
+    pub delimiter: u8,
+    /// Write the headers of the columns as the first row
+    pub headers: bool,
+    /// Names of the IP addresses for the `dst_name` column, see
+    /// [`crate::parsers::dns_map::find_dns_map`]
+    pub peer_names: DnsMap,
+    /// Provenance written as `#` comments after the rows, if any
+    pub provenance: Option<Provenance>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            columns: DEFAULT_COLUMNS.to_vec(),
+            delimiter: b',',
+            headers: true,
+            peer_names: DnsMap::new(),
+            provenance: None,
+        }
+    }
+}
+
+/// Output a CSV formatted string of all the hosts connections, with the columns of `options`.
+/// The default columns are:
 ///
 /// - Source host
 /// - Dest host
 /// - Source process name
-/// - Source process PID
 /// - Dest process name
+/// - Source process PID
 /// - Dest process PID
 /// - Source socket
 /// - Dest socket
+/// - Protocol
 ///
-pub fn write_connections_to_csv(
-    connections: &Vec<Connection>,
+/// The connections are either borrowed from the hosts, or owned [`ConnectionRecord`]s.
+pub fn write_connections_to_csv<C: ToConnectionRecord>(
+    connections: &[C],
     out_file_path: &std::path::Path,
+    options: &CsvOptions,
 ) -> anyhow::Result<()> {
-    let out_file = match std::fs::File::create(out_file_path) {
-        Ok(f) => f,
-        Err(e) => {
-            log::error!("unable to create file {:?}: {}", out_file_path, e);
-            std::process::exit(1);
-        }
-    };
-
-    let mut wtr = csv::Writer::from_writer(&out_file);
-
-    wtr.write_record([
-        "Source host",
-        "Dest host",
-        "Source process",
-        "Dest process",
-        "Source PID",
-        "Dest PID",
-        "Source process socket",
-        "Dest process socket",
-        "Protocol",
-    ])
+    let out_file = std::fs::File::create(out_file_path)
+        .with_context(|| format!("unable to create file {out_file_path:?}"))?;
+
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .from_writer(&out_file);
+    write_records(
+        &mut wtr,
+        connections
+            .iter()
+            .map(ToConnectionRecord::to_connection_record),
+        options,
+    )
     .with_context(|| "unable to write CSV records to file")?;
+    (&out_file)
+        .write_all(provenance_comment(options).as_bytes())
+        .with_context(|| "unable to write the provenance to file")
+}
 
-    for conn in connections {
-        let connected_host_name = conn.connected_host().name();
-        let listening_host_name = conn.listening_host().name();
-        let source_process_name = conn.connected_connection().process().name();
-        let source_process_pid = conn.connected_connection().process().pid();
-        let listening_process_name = conn.listening_connection().process().name();
-        let listening_process_pid = conn.listening_connection().process().pid();
-        let source_socket = conn.connected_connection().local_socket().to_string();
-        let dest_socket = conn.listening_connection().socket().to_string();
-        let protocol = match conn.connected_connection().socket_type() {
-            host::SocketType::TCP => "TCP",
-            host::SocketType::UDP => "UDP",
-            host::SocketType::UNIX => "UNIX",
-        };
+/// Format the connection records as CSV, in the same way as [`write_connections_to_csv`]
+pub fn connection_records_to_string(
+    records: &[ConnectionRecord],
+    options: &CsvOptions,
+) -> anyhow::Result<String> {
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .from_writer(Vec::new());
+    write_records(&mut wtr, records.iter().cloned(), options)?;
+    let bytes = wtr.into_inner().with_context(|| "unable to flush CSV")?;
+    Ok(String::from_utf8(bytes)? + &provenance_comment(options))
+}
 
-        wtr.write_record([
-            connected_host_name,
-            listening_host_name,
-            source_process_name,
-            listening_process_name,
-            source_process_pid.to_string().as_str(),
-            listening_process_pid.to_string().as_str(),
-            &source_socket,
-            &dest_socket,
-            protocol,
-        ])
+/// Write the routes of the hosts to a CSV file, a row per route with the host name, the destination
+/// network, the gateway and the interface. Only the delimiter and the headers of `options` apply.
+pub fn write_routes_to_csv(
+    hosts: &[Host],
+    out_file_path: &std::path::Path,
+    options: &CsvOptions,
+) -> anyhow::Result<()> {
+    let out_file = std::fs::File::create(out_file_path)
+        .with_context(|| format!("unable to create file {out_file_path:?}"))?;
+
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .from_writer(&out_file);
+    write_routes(&mut wtr, hosts, options)
         .with_context(|| "unable to write CSV records to file")?;
+    (&out_file)
+        .write_all(provenance_comment(options).as_bytes())
+        .with_context(|| "unable to write the provenance to file")
+}
+
+/// Comment lines of the provenance of `options`, empty if there is none
+fn provenance_comment(options: &CsvOptions) -> String {
+    options
+        .provenance
+        .as_ref()
+        .map(|provenance| provenance.comment_block("#"))
+        .unwrap_or_default()
+}
+
+/// Write the header if asked, then a row per route
+fn write_routes<W: std::io::Write>(
+    wtr: &mut csv::Writer<W>,
+    hosts: &[Host],
+    options: &CsvOptions,
+) -> anyhow::Result<()> {
+    if options.headers {
+        wtr.write_record(["Host", "Destination", "Gateway", "Interface"])?;
     }
+
+    for host in hosts {
+        for route in host.routes() {
+            wtr.write_record([
+                host.name().to_string(),
+                route.network(),
+                route.gateway().map(|g| g.to_string()).unwrap_or_default(),
+                route.interface().unwrap_or_default().to_string(),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Write the header if asked, then a row per record
+fn write_records<W: std::io::Write>(
+    wtr: &mut csv::Writer<W>,
+    records: impl Iterator<Item = ConnectionRecord>,
+    options: &CsvOptions,
+) -> anyhow::Result<()> {
+    if options.headers {
+        wtr.write_record(options.columns.iter().map(CsvColumn::header))?;
+    }
+
+    for record in records {
+        wtr.write_record(
+            options
+                .columns
+                .iter()
+                .map(|column| column.value(&record, options)),
+        )?;
+    }
+    wtr.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections_model::{build_connections_list, tests::make_fake_connections};
+
+    /// The borrowed connections and the records must give the same file
+    #[test]
+    fn test_write_connections_to_csv() {
+        let directory = tempfile::tempdir().unwrap();
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let records: Vec<ConnectionRecord> =
+            connections.iter().map(ConnectionRecord::from).collect();
+
+        let connections_path = directory.path().join("connections.csv");
+        let records_path = directory.path().join("records.csv");
+        let options = CsvOptions::default();
+        write_connections_to_csv(&connections, &connections_path, &options).unwrap();
+        write_connections_to_csv(&records, &records_path, &options).unwrap();
+        let content = std::fs::read_to_string(&connections_path).unwrap();
+        assert_eq!(content, std::fs::read_to_string(&records_path).unwrap());
+        assert_eq!(
+            content,
+            connection_records_to_string(&records, &options).unwrap()
+        );
+    }
+
+    /// A file which cannot be created must give an error, instead of ending the process
+    #[test]
+    fn test_write_connections_to_csv_unwritable() {
+        let directory = tempfile::tempdir().unwrap();
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let path = directory.path().join("missing").join("connections.csv");
+
+        let res = write_connections_to_csv(&connections, &path, &CsvOptions::default());
+        assert!(format!("{:#}", res.unwrap_err()).contains("unable to create file"));
+        assert!(!path.exists());
+    }
+
+    /// The records must be formatted with the same columns as the CSV file
+    #[test]
+    fn test_connection_records_to_string() {
+        let hosts = make_fake_connections();
+        let records: Vec<_> = build_connections_list(&hosts, false)
+            .iter()
+            .map(ConnectionRecord::from)
+            .filter(|r| r.source_process() == "some_udp_client")
+            .collect();
+
+        let options = CsvOptions {
+            delimiter: b';',
+            ..Default::default()
+        };
+        let csv = connection_records_to_string(&records, &options).unwrap();
+        assert_eq!(
+            csv,
+            "Source host;Dest host;Source process;Dest process;Source PID;Dest PID;\
+            Source process socket;Dest process socket;Protocol\n\
+            machine3;machine2;some_udp_client;some_udp_service;301;203;\
+            10.0.0.3:50002;10.0.0.3:50001;UDP\n"
+        );
+    }
+
+    /// The selected columns must be written in their order, with the new fields and without
+    /// headers if asked, and the peers named after the DNS map
+    #[test]
+    fn test_custom_columns() {
+        let hosts = make_fake_connections();
+        let records: Vec<_> = build_connections_list(&hosts, false)
+            .iter()
+            .map(ConnectionRecord::from)
+            .filter(|r| r.source_process() == "ssh")
+            .collect();
+        let columns: Vec<CsvColumn> = "dst_port,src_host,dst_ip,dst_bind_address,ipv6_only,count"
+            .split(',')
+            .map(|name| name.parse().unwrap())
+            .collect();
+        let options = CsvOptions {
+            columns,
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_records_to_string(&records, &options).unwrap(),
+            "Dest port,Source host,Dest IP,Dest bind address,IPv6 only,Captures\n\
+            22,machine2,::ffff:10.0.0.1,::ffff:10.0.0.1,false,\n"
+        );
+
+        let first_seen = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let records = [records[0].clone().with_count(2).with_first_seen(first_seen)];
+        let options = CsvOptions {
+            columns: vec![CsvColumn::Count, CsvColumn::FirstSeen, CsvColumn::LastSeen],
+            headers: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_records_to_string(&records, &options).unwrap(),
+            "2,2023-11-14T22:13:20Z,\n"
+        );
+
+        let options = CsvOptions {
+            columns: vec![CsvColumn::DestIp, CsvColumn::DestName],
+            headers: false,
+            peer_names: [("10.0.0.1".parse().unwrap(), "gateway.internal".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_records_to_string(&records, &options).unwrap(),
+            "::ffff:10.0.0.1,gateway.internal\n"
+        );
+
+        assert!("dst_state".parse::<CsvColumn>().is_err());
+        for column in ALL_COLUMNS {
+            assert_eq!(column.name().parse(), Ok(column));
+        }
+    }
+
+    /// The flows seen from both ends must be a single row going both ways
+    #[test]
+    fn test_direction_columns() {
+        let hosts = crate::test_utils::udp_symmetric();
+        let options = CsvOptions {
+            columns: "src_host,dst_host,direction,server_side"
+                .split(',')
+                .map(|name| name.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        let records =
+            crate::connections_model::build_connection_records(&hosts, &Default::default());
+        assert_eq!(
+            connection_records_to_string(&records, &options).unwrap(),
+            "Source host,Dest host,Direction,Server side\n\
+            ntp1,ntp2,both,arbitrary\n"
+        );
+
+        let records: Vec<_> = build_connections_list(&make_fake_connections(), false)
+            .iter()
+            .map(ConnectionRecord::from)
+            .filter(|r| r.source_process() == "ssh")
+            .collect();
+        assert_eq!(
+            connection_records_to_string(&records, &options).unwrap(),
+            "Source host,Dest host,Direction,Server side\n\
+            machine2,machine1,out,listen\n"
+        );
+    }
+
+    /// The connections to a captured host without listener must follow the connections, with
+    /// their own row type and no destination process
+    #[test]
+    fn test_unmatched_rows() {
+        let hosts = crate::test_utils::missing_listener();
+        let report = crate::connections_model::build_connections_report(
+            &hosts,
+            &Default::default(),
+            &crate::connections_model::DefaultMatcher,
+        );
+        let options = CsvOptions {
+            columns: "row_type,src_host,src_process,dst_host,dst_process,dst_socket"
+                .split(',')
+                .map(|name| name.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_records_to_string(&report.records(), &options).unwrap(),
+            "Row type,Source host,Source process,Dest host,Dest process,Dest process socket\n\
+            connection,app,ssh,db,sshd,0.0.0.0:22\n\
+            unmatched_known_peer,app,java,db,,10.0.0.3:5432\n"
+        );
+    }
+
+    /// A row per route, the hosts without routes having none
+    #[test]
+    fn test_write_routes_to_csv() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("routes.csv");
+        let mut hosts = make_fake_connections();
+        hosts[0].add_route(crate::host::Route::new(
+            "0.0.0.0".parse().unwrap(),
+            0,
+            Some("10.0.0.254".parse().unwrap()),
+            Some("eth0".to_string()),
+        ));
+        hosts[0].add_route(crate::host::Route::new(
+            "10.0.0.0".parse().unwrap(),
+            24,
+            None,
+            Some("eth0".to_string()),
+        ));
+
+        write_routes_to_csv(&hosts, &path, &CsvOptions::default()).unwrap();
+        let name = hosts[0].name();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                "Host,Destination,Gateway,Interface\n\
+                 {name},0.0.0.0/0,10.0.0.254,eth0\n\
+                 {name},10.0.0.0/24,,eth0\n"
+            )
+        );
+    }
+}
@@ -1,183 +1,364 @@
 //! This module models the DOT objects in order to draw the graph using Graphviz.
 
+use self::model::{EdgeKind, MapEdge, MapNode, NodeKind};
+use crate::connections_model;
 use crate::graphviz::LayoutEngine;
-use crate::{connections_model, host};
 use anyhow::anyhow;
 use rand::prelude::ThreadRng;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use tabbycat::attributes::*;
 use tabbycat::{AttrList, Edge, GraphType, Identity, StmtList};
 
+pub mod model;
+pub use self::model::{build_map_graph, build_report_map_graph, MapGraph, MapGraphOptions};
+
 const DEFAULT_FONTNAME: &str = "Verdata";
+/// Fill color of the highlighted nodes
+const HIGHLIGHT_FILLCOLOR: &str = "gold";
 
-/// The structure to graphically represent a host
-pub struct GraphHost<'a> {
-    /// The host name
-    name: &'a str,
-    /// A unique node id
-    node_id: &'a str,
-    listening_processes_nodes_ids: Vec<&'a str>,
-    /// The listening processes nodes associated with this host node
-    listening_processes_nodes_stmts: Vec<tabbycat::Stmt<'a>>,
-    /// The listening edges nodes associated with this host node
-    listening_processes_edges_stmts: Vec<tabbycat::Stmt<'a>>,
-    connected_and_listening_processes_nodes_ids: Vec<(&'a str, &'a str)>,
-    connected_processes_nodes_ids: Vec<&'a str>,
-    connected_processes_edges_stmts: Vec<tabbycat::Stmt<'a>>,
-    connected_processes_nodes_stmts: Vec<tabbycat::Stmt<'a>>,
-}
-
-impl<'a> GraphHost<'a> {
-    pub fn new(name: &'a str, node_id: &'a str) -> Self {
-        Self {
-            name,
-            node_id,
-            listening_processes_nodes_ids: Vec::new(),
-            listening_processes_nodes_stmts: Vec::new(),
-            listening_processes_edges_stmts: Vec::new(),
-            connected_and_listening_processes_nodes_ids: Vec::new(),
-            connected_processes_nodes_stmts: Vec::new(),
-            connected_processes_edges_stmts: Vec::new(),
-            connected_processes_nodes_ids: Vec::new(),
+/// Level of detail of the graph
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphGranularity {
+    /// A node per process, in the cluster of its host
+    #[default]
+    Process,
+    /// A node per host, and a single edge per pair of hosts telling the number of connections and
+    /// their destination ports
+    Host,
+}
+
+impl std::str::FromStr for GraphGranularity {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "process" => Ok(GraphGranularity::Process),
+            "host" => Ok(GraphGranularity::Host),
+            _ => Err("unknown graph granularity"),
         }
     }
+}
 
-    /// Get a reference to the graph host's name.
-    pub fn name(&self) -> &'a str {
-        self.name
+impl From<&GraphGranularity> for &'static str {
+    fn from(value: &GraphGranularity) -> Self {
+        match value {
+            GraphGranularity::Process => "process",
+            GraphGranularity::Host => "host",
+        }
     }
+}
+
+impl std::fmt::Display for GraphGranularity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.into())
+    }
+}
 
-    // Add a listening process and build its statement list
-    pub fn add_listening_process(
-        &mut self,
-        listening_connection: &'a host::ListeningSocket,
-        host: &'a host::Host,
-    ) {
-        let node_id = listening_connection.node_id();
+/// How the connections between the processes of a same host are drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopbackRendering {
+    #[default]
+    Show,
+    Hide,
+    /// Replace the loopback connections of each host with a single node telling their number
+    Collapse,
+}
 
-        // See if we already have seen that listening process
-        // Only add it to the statements list we we haven't already
-        if !self.listening_processes_nodes_ids.contains(&node_id) {
-            self.listening_processes_nodes_ids.push(node_id);
+impl std::str::FromStr for LoopbackRendering {
+    type Err = &'static str;
 
-            // Build the listening process statements list
-            let id = Identity::String(node_id);
-            let listening_process_node = tabbycat::Stmt::Node {
-                id,
-                port: None,
-                attr: Some(listening_process_node_attrs(
-                    listening_connection.node_name(),
-                )),
-            };
-            let listening_process_edge = tabbycat::Stmt::Edge(
-                Edge::head_node(Identity::String(host.cluster_id()), None)
-                    .arrow_to_node(Identity::String(listening_connection.node_id()), None)
-                    .add_attrpair(color(Color::Black))
-                    .add_attrpair(style(Style::Dashed)),
-            );
-            self.listening_processes_nodes_stmts
-                .push(listening_process_node);
-            self.listening_processes_edges_stmts
-                .push(listening_process_edge);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "show" => Ok(LoopbackRendering::Show),
+            "hide" => Ok(LoopbackRendering::Hide),
+            "collapse" => Ok(LoopbackRendering::Collapse),
+            _ => Err("unknown loopback rendering"),
         }
     }
+}
 
-    // Add a connected process
-    pub fn add_connected_process(
-        &mut self,
-        connected_connection: &'a host::Connection,
-        host: &'a host::Host,
-        listening_connection: &'a host::ListeningSocket,
-        rng: &mut ThreadRng,
-    ) {
-        let connected_node_id = connected_connection.process().node_id();
-        let listening_node_id = listening_connection.node_id();
-
-        // See if we already have seen that connected process
-        // Only add it to the statements list we we haven't already
-        if !self
-            .connected_and_listening_processes_nodes_ids
-            .contains(&(connected_node_id, listening_node_id))
-        {
-            self.connected_and_listening_processes_nodes_ids
-                .push((connected_node_id, listening_node_id));
-
-            // Build the connected process statements list
-            let connected_process_node = tabbycat::Stmt::Node {
-                id: Identity::String(connected_node_id),
-                port: None,
-                attr: Some(connected_process_node_attrs(
-                    connected_connection.process().name(),
-                )),
-            };
+impl From<&LoopbackRendering> for &'static str {
+    fn from(value: &LoopbackRendering) -> Self {
+        match value {
+            LoopbackRendering::Show => "show",
+            LoopbackRendering::Hide => "hide",
+            LoopbackRendering::Collapse => "collapse",
+        }
+    }
+}
 
-            // Each edge will have a random dark color
-            let hue: f32 = rng.gen_range(0.0..1.0);
-            let saturation: f32 = rng.gen_range(0.7..0.99);
-            let value: f32 = 0.65;
+impl std::fmt::Display for LoopbackRendering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.into())
+    }
+}
 
-            let interprocess_edge = tabbycat::Stmt::Edge(
-                Edge::head_node(
-                    Identity::String(connected_connection.process().node_id()),
-                    None,
-                )
-                .arrow_to_node(Identity::String(listening_connection.node_id()), None)
-                .add_attrpair(color(Color::HSV(hue, saturation, value))),
-            );
+/// How the listening sockets of the hosts are drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerRendering {
+    /// A node per listening process and socket
+    #[default]
+    PerProcess,
+    /// A single node per host listing its listening ports, the edges being labeled with the port
+    /// they target
+    Summary,
+}
 
-            // Check if we already have a link between this host and this connected process
-            if !self
-                .connected_processes_nodes_ids
-                .contains(&connected_connection.process().node_id())
-            {
-                let connected_process_edge = tabbycat::Stmt::Edge(
-                    Edge::head_node(Identity::String(host.cluster_id()), None)
-                        .arrow_to_node(
-                            Identity::String(connected_connection.process().node_id()),
-                            None,
-                        )
-                        .add_attrpair(color(Color::Black))
-                        .add_attrpair(style(Style::Dashed)),
-                );
-                self.connected_processes_nodes_ids
-                    .push(connected_connection.process().node_id());
-                self.connected_processes_edges_stmts
-                    .push(connected_process_edge);
+/// Create the nodes of the hosts and the edges between them, for [`GraphGranularity::Host`]
+fn create_hosts_nodes(map_graph: &MapGraph) -> StmtList<'_> {
+    let mut stmts = StmtList::new();
+    for host in &map_graph.hosts {
+        let fill = host.fill.map(hsv_color).unwrap_or(Color::White);
+        stmts = stmts.add_node(
+            Identity::String(&host.node_id),
+            None,
+            Some(graph_host_node_attrs(&host.label, fill, host.highlighted)),
+        );
+    }
+    for edge in &map_graph.edges {
+        let connection = match &edge.kind {
+            EdgeKind::Membership => continue,
+            EdgeKind::Unmatched(connection) => {
+                stmts = stmts.add_edge(unmatched_edge(edge, connection));
+                continue;
             }
+            EdgeKind::Connection(connection) => connection,
+        };
+        let mut dot_edge = Edge::head_node(Identity::String(&edge.tail), None)
+            .arrow_to_node(Identity::String(&edge.head), None)
+            .add_attrpair(color(Color::Black));
+        if let Some(edge_label) = &connection.label {
+            dot_edge = dot_edge
+                .add_attrpair(label(edge_label))
+                .add_attrpair(fontname(DEFAULT_FONTNAME));
+        }
+        if connection.direction != connections_model::Direction::Out {
+            dot_edge = dot_edge.add_attrpair(edge_dir(connection.direction));
+        }
+        stmts = stmts.add_edge(match connection.proxied {
+            true => dot_edge.add_attrpair(style(Style::Dotted)),
+            false => dot_edge,
+        });
+    }
+    stmts
+}
+
+/// Patterns of the names of the hosts and processes to highlight in the graph
+#[derive(Debug, Clone, Default)]
+pub struct HighlightRules {
+    patterns: Vec<Regex>,
+}
+
+impl HighlightRules {
+    pub fn new(patterns: Vec<Regex>) -> Self {
+        Self { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns true if a host or process named `name` is highlighted
+    pub fn matches(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.is_match(name))
+    }
 
-            self.connected_processes_nodes_stmts
-                .push(connected_process_node);
-            self.connected_processes_edges_stmts.push(interprocess_edge);
+    /// Number of hosts and processes of the graph which are highlighted
+    pub fn count_matches(&self, connections: &[connections_model::Connection]) -> usize {
+        let mut matching_nodes = HashSet::new();
+        for connection in connections {
+            for host in [connection.listening_host(), connection.connected_host()] {
+                if self.matches(host.name()) {
+                    matching_nodes.insert(host.cluster_id());
+                }
+            }
+            let listening_connection = connection.listening_connection();
+            if self.matches(listening_connection.process().name()) {
+                matching_nodes.insert(listening_connection.node_id());
+            }
+            let connected_process = connection.connected_connection().process();
+            if self.matches(connected_process.name()) {
+                matching_nodes.insert(connected_process.node_id());
+            }
         }
+        matching_nodes.len()
     }
+}
+
+/// Placement of a host given by the user, so that recurring graphs keep the same arrangement
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostLayoutHint {
+    /// The hosts of the same group are drawn on the same rank by dot
+    pub rank_group: Option<String>,
+    /// Position of the host as `x,y`, in points, pinned by neato and fdp
+    pub pos: Option<String>,
+}
+
+/// Layout hints of the hosts, by host name. The hints of the hosts which are not drawn are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutHints {
+    pub hosts: BTreeMap<String, HostLayoutHint>,
+}
 
-    /// Get a reference to the graph host's listening processes stmts.
-    pub fn listening_processes_nodes_stmts(&self) -> Vec<tabbycat::Stmt<'a>> {
-        self.listening_processes_nodes_stmts.clone()
+impl LayoutHints {
+    pub fn is_empty(&self) -> bool {
+        self.hosts
+            .values()
+            .all(|hint| hint == &HostLayoutHint::default())
     }
 
-    /// Get a reference to the graph host's connected processes stmts.
-    pub fn connected_processes_nodes_stmts(&self) -> Vec<tabbycat::Stmt<'a>> {
-        self.connected_processes_nodes_stmts.clone()
+    /// Put `hosts` in the rank group `rank_group`, instead of the group they may already be in
+    pub fn set_rank_group(&mut self, rank_group: &str, hosts: &[String]) {
+        for host in hosts {
+            self.hosts.entry(host.clone()).or_default().rank_group = Some(rank_group.to_string());
+        }
     }
 
-    /// Get a reference to the graph host's node id.
-    pub fn node_id(&self) -> &'a str {
-        self.node_id
+    /// Check that the positions are made of two coordinates
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (host, hint) in &self.hosts {
+            if let Some(pos) = &hint.pos {
+                if parse_pos(pos).is_none() {
+                    return Err(anyhow!(
+                        "invalid position {pos:?} of host {host}, expected x,y"
+                    ));
+                }
+            }
+        }
+        Ok(())
     }
+}
+
+/// Coordinates of a `x,y` position
+fn parse_pos(pos: &str) -> Option<(f64, f64)> {
+    let (x, y) = pos.split_once(',')?;
+    let coordinate = |c: &str| c.trim().parse::<f64>().ok().filter(|c| c.is_finite());
+    Some((coordinate(x)?, coordinate(y)?))
+}
 
-    /// Get a reference to the graph host's connected processes edges stmts.
-    pub fn connected_processes_edges_stmts(&self) -> Vec<tabbycat::Stmt<'a>> {
-        self.connected_processes_edges_stmts.clone()
+/// Node ids of the hosts drawn in the graph, by host name
+fn drawn_hosts(map_graph: &MapGraph) -> BTreeMap<&str, &str> {
+    map_graph
+        .hosts
+        .iter()
+        .map(|host| (host.name.as_str(), host.node_id.as_str()))
+        .collect()
+}
+
+/// Pinned position of each drawn host which has one, as its node id and the value of its `pos`
+/// attribute
+fn pinned_positions<'a>(
+    layout_hints: &LayoutHints,
+    drawn_hosts: &BTreeMap<&'a str, &'a str>,
+) -> Vec<(&'a str, String)> {
+    drawn_hosts
+        .iter()
+        .filter_map(|(name, node_id)| {
+            let (x, y) = parse_pos(layout_hints.hosts.get(*name)?.pos.as_deref()?)?;
+            Some((*node_id, format!("\"{x},{y}!\"")))
+        })
+        .collect()
+}
+
+/// Statements of the layout hints: the drawn hosts of each rank group on the same rank for dot,
+/// and the `pinned_positions` for neato and fdp. The other engines have no use for them.
+fn layout_hints_stmts<'a>(
+    layout_hints: &LayoutHints,
+    drawn_hosts: &BTreeMap<&'a str, &'a str>,
+    pinned_positions: &'a [(&'a str, String)],
+    layout_engine: Option<&LayoutEngine>,
+) -> StmtList<'a> {
+    let mut stmts = StmtList::new();
+    match layout_engine {
+        None | Some(LayoutEngine::Dot) => {
+            let mut rank_groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+            for (name, node_id) in drawn_hosts {
+                if let Some(rank_group) = layout_hints
+                    .hosts
+                    .get(*name)
+                    .and_then(|hint| hint.rank_group.as_deref())
+                {
+                    rank_groups.entry(rank_group).or_default().push(node_id);
+                }
+            }
+            for node_ids in rank_groups.into_values() {
+                let mut rank_stmts = StmtList::new().add_attr(
+                    tabbycat::AttrType::Graph,
+                    AttrList::new().add(Identity::String("rank"), Identity::String("same")),
+                );
+                for node_id in node_ids {
+                    rank_stmts = rank_stmts.add_node(Identity::String(node_id), None, None);
+                }
+                stmts = stmts.add_subgraph(tabbycat::SubGraph::subgraph(None, rank_stmts));
+            }
+        }
+        Some(LayoutEngine::Neato | LayoutEngine::Fdp) => {
+            for (node_id, pos) in pinned_positions {
+                stmts = stmts.add_node(
+                    Identity::String(node_id),
+                    None,
+                    Some(AttrList::new().add(Identity::String("pos"), Identity::String(pos))),
+                );
+            }
+        }
+        Some(LayoutEngine::Circo) => (),
     }
+    stmts
+}
+
+/// `dir` attribute of an edge going in `direction`
+fn edge_dir<'a>(direction: connections_model::Direction) -> (Identity<'a>, Identity<'a>) {
+    (
+        Identity::String("dir"),
+        Identity::String(match direction {
+            connections_model::Direction::Out => "forward",
+            connections_model::Direction::In => "back",
+            connections_model::Direction::Both => "both",
+        }),
+    )
+}
 
-    /// Get a reference to the graph host's listening processes edges stmts.
-    pub fn listening_processes_edges_stmts(&self) -> Vec<tabbycat::Stmt<'a>> {
-        self.listening_processes_edges_stmts.clone()
+/// Random dark color of an edge between processes
+fn edge_color(rng: &mut impl Rng) -> Color {
+    let hue: f32 = rng.gen_range(0.0..1.0);
+    let saturation: f32 = rng.gen_range(0.7..0.99);
+    let value: f32 = 0.65;
+    Color::HSV(hue, saturation, value)
+}
+
+/// Generator seeded with the edge from `tail` to `head`, which gives the same color to the edge in
+/// every graph
+fn edge_rng(tail: &str, head: &str) -> StdRng {
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(format!("{tail}->{head}"));
+    let digest = hasher.finalize();
+    let mut seed = [0; 8];
+    seed.copy_from_slice(&digest[..8]);
+    StdRng::seed_from_u64(u64::from_le_bytes(seed))
+}
+
+/// Fill the node with the highlight color if `highlighted`, or with `fill` otherwise
+fn node_fillcolor<'a>(fill: Color, highlighted: bool) -> (Identity<'a>, Identity<'a>) {
+    match highlighted {
+        true => (
+            Identity::String("fillcolor"),
+            Identity::String(HIGHLIGHT_FILLCOLOR),
+        ),
+        false => fillcolor(fill),
     }
 }
 
-fn graph_host_node_attrs(name: &str) -> AttrList {
+fn graph_host_node_attrs(name: &str, fill: Color, highlighted: bool) -> AttrList {
     AttrList::new()
         .add_pair(fontname(DEFAULT_FONTNAME))
         .add_pair(label(name))
@@ -186,10 +367,10 @@ fn graph_host_node_attrs(name: &str) -> AttrList {
             Identity::String("style"),
             Identity::String("\"filled,bold\""),
         )
-        .add_pair(fillcolor(Color::White))
+        .add_pair(node_fillcolor(fill, highlighted))
 }
 
-fn connected_process_node_attrs(name: &str) -> AttrList {
+fn connected_process_node_attrs(name: &str, highlighted: bool) -> AttrList {
     AttrList::new()
         .add_pair(fontname(DEFAULT_FONTNAME))
         .add_pair(shape(Shape::Box))
@@ -197,11 +378,60 @@ fn connected_process_node_attrs(name: &str) -> AttrList {
             Identity::String("style"),
             Identity::String("\"rounded,filled\""),
         )
-        .add_pair(fillcolor(Color::White))
+        .add_pair(node_fillcolor(Color::White, highlighted))
         .add_pair(label(name))
 }
 
-fn listening_process_node_attrs(name: &str) -> AttrList {
+fn collapsed_loopback_node_attrs(label_text: &str) -> AttrList {
+    AttrList::new()
+        .add_pair(fontname(DEFAULT_FONTNAME))
+        .add_pair(shape(Shape::Note))
+        .add(
+            Identity::String("style"),
+            Identity::String("\"dashed,filled\""),
+        )
+        .add_pair(fillcolor(Color::White))
+        .add_pair(label(label_text))
+}
+
+fn listeners_summary_node_attrs(label_text: &str) -> AttrList {
+    AttrList::new()
+        .add_pair(fontname(DEFAULT_FONTNAME))
+        .add_pair(shape(Shape::Box))
+        .add(Identity::String("style"), Identity::String("filled"))
+        .add_pair(fillcolor(Color::Black))
+        .add_pair((Identity::String("fontcolor"), Identity::from(Color::White)))
+        .add_pair(label(label_text))
+}
+
+fn unmatched_peer_node_attrs(label_text: &str) -> AttrList {
+    AttrList::new()
+        .add_pair(fontname(DEFAULT_FONTNAME))
+        .add_pair(shape(Shape::Circle))
+        .add(
+            Identity::String("style"),
+            Identity::String("\"dashed,filled\""),
+        )
+        .add_pair(fillcolor(Color::White))
+        .add_pair(fontsize(10.0))
+        .add_pair(label(label_text))
+}
+
+/// Dashed grey edge of the connections without a matching listener
+fn unmatched_edge<'a>(edge: &'a MapEdge, connection: &'a model::ConnectionEdge) -> Edge<'a> {
+    let dot_edge = Edge::head_node(Identity::String(&edge.tail), None)
+        .arrow_to_node(Identity::String(&edge.head), None)
+        .add_attrpair(color(Color::Grey))
+        .add_attrpair(style(Style::Dashed));
+    match &connection.label {
+        Some(edge_label) => dot_edge
+            .add_attrpair(label(edge_label))
+            .add_attrpair(fontname(DEFAULT_FONTNAME)),
+        None => dot_edge,
+    }
+}
+
+fn listening_process_node_attrs(name: &str, highlighted: bool) -> AttrList {
     AttrList::new()
         .add_pair(fontname(DEFAULT_FONTNAME))
         .add_pair(shape(Shape::Box))
@@ -209,15 +439,84 @@ fn listening_process_node_attrs(name: &str) -> AttrList {
             Identity::String("style"),
             Identity::String("\"rounded,filled\""),
         ))
-        .add_pair(fillcolor(Color::Black))
-        .add_pair((Identity::String("fontcolor"), Identity::from(Color::White)))
+        .add_pair(node_fillcolor(Color::Black, highlighted))
+        .add_pair((
+            Identity::String("fontcolor"),
+            Identity::from(match highlighted {
+                true => Color::Black,
+                false => Color::White,
+            }),
+        ))
         .add_pair(label(name))
 }
 
-/// Create hosts subgraphs with their connected listening and connected processes around it
-fn create_hosts_subgraph<'a>(
-    connections: &Vec<connections_model::Connection<'a>>,
-) -> (Vec<tabbycat::SubGraph<'a>>, StmtList<'a>) {
+/// Graphviz color of red, green and blue components
+fn hsv_color([red, green, blue]: [u8; 3]) -> Color {
+    let [red, green, blue] = [red, green, blue].map(|c| f32::from(c) / 255.0);
+    let max = red.max(green).max(blue);
+    let delta = max - red.min(green).min(blue);
+    // The greys have no hue, and black has no saturation either
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == red {
+        ((green - blue) / delta).rem_euclid(6.0)
+    } else if max == green {
+        (blue - red) / delta + 2.0
+    } else {
+        (red - green) / delta + 4.0
+    } / 6.0;
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    Color::HSV(hue, saturation, max)
+}
+
+/// Edge between two nodes drawn in hosts, for [`GraphGranularity::Process`]. The color of the
+/// connections is drawn from `rng`, or derived from the processes if it is `None`, and their
+/// arrows follow their direction.
+fn process_edge<'a>(edge: &'a MapEdge, rng: Option<&mut ThreadRng>) -> Edge<'a> {
+    let dot_edge = Edge::head_node(Identity::String(&edge.tail), None)
+        .arrow_to_node(Identity::String(&edge.head), None);
+    let connection = match &edge.kind {
+        EdgeKind::Membership => {
+            return dot_edge
+                .add_attrpair(color(Color::Black))
+                .add_attrpair(style(Style::Dashed))
+        }
+        EdgeKind::Unmatched(connection) => return unmatched_edge(edge, connection),
+        EdgeKind::Connection(connection) => connection,
+    };
+
+    // Each edge will have a random dark color
+    let edge_color = match rng {
+        Some(rng) => edge_color(rng),
+        None => {
+            let listening_node_id = connection.listening_node_id.as_deref();
+            edge_color(&mut edge_rng(
+                &edge.tail,
+                listening_node_id.unwrap_or(&edge.head),
+            ))
+        }
+    };
+    let mut dot_edge = dot_edge.add_attrpair(color(edge_color));
+    if connection.direction != connections_model::Direction::Out {
+        dot_edge = dot_edge.add_attrpair(edge_dir(connection.direction));
+    }
+    if let Some(edge_label) = &connection.label {
+        dot_edge = dot_edge
+            .add_attrpair(label(edge_label))
+            .add_attrpair(fontname(DEFAULT_FONTNAME));
+    }
+    match connection.proxied {
+        true => dot_edge.add_attrpair(style(Style::Dotted)),
+        false => dot_edge,
+    }
+}
+
+/// Create hosts subgraphs with their listening and connected processes around it, and the edges.
+/// Unless `deterministic`, the edges get new random colors each time.
+fn create_hosts_subgraph(
+    map_graph: &MapGraph,
+    deterministic: bool,
+) -> (Vec<tabbycat::SubGraph<'_>>, StmtList<'_>) {
     let mut subgraphs: Vec<tabbycat::SubGraph> = Vec::new();
     let mut edges_stmts = tabbycat::StmtList::new();
 
@@ -233,103 +532,287 @@ fn create_hosts_subgraph<'a>(
             .add_pair(color(Color::Lightgrey)),
     );
 
-    // Keep track of the hosts we saw during the loop
-    let mut graph_hosts: Vec<GraphHost> = Vec::new();
-
     // Initialize the rng for random edge color generation
     let mut rng = rand::thread_rng();
 
-    for connection in connections {
-        let listening_host = connection.listening_host();
-        let connected_host = connection.connected_host();
-        let listening_connection = connection.listening_connection();
-        let connected_connection = connection.connected_connection();
-
-        // See if we already have seen this listening host before
-        if !&graph_hosts
-            .iter()
-            .map(|h| h.name())
-            .any(|graph_host_name| graph_host_name == listening_host.name())
-        {
-            // First time seeing that host, create the GraphHost object
-            let graph_host: GraphHost<'a> =
-                GraphHost::new(listening_host.name(), listening_host.cluster_id());
-            graph_hosts.push(graph_host);
-        }
-
-        // Same for the connected host
-        if !graph_hosts
-            .iter()
-            .map(|h| h.name())
-            .any(|graph_host_name| graph_host_name == connected_host.name())
-        {
-            // First time seeing that host, create the GraphHost object
-            let graph_host = GraphHost::new(connected_host.name(), connected_host.cluster_id());
-            graph_hosts.push(graph_host);
-        }
-
-        // Add the listening process to the listening host
-        for graph_host in &mut graph_hosts {
-            if graph_host.name() == listening_host.name() {
-                graph_host.add_listening_process(listening_connection, listening_host);
-                break;
-            }
-        }
-
-        // Add the connected process to the connected host
-        for graph_host in &mut graph_hosts {
-            if graph_host.name() == connected_host.name() {
-                graph_host.add_connected_process(
-                    connected_connection,
-                    connected_host,
-                    listening_connection,
-                    &mut rng,
-                );
-                break;
-            }
-        }
-    }
-
-    // Create the subgraphs from the GraphHost structures
-    for graph_host in graph_hosts {
+    for host in &map_graph.hosts {
         // Create the StmtList, starting with the host node
         let layout = AttrList::new().add_pair(layout("dot"));
         let mut stmts = tabbycat::StmtList::new()
             .add_node(
-                Identity::String(graph_host.node_id()),
+                Identity::String(&host.node_id),
                 None,
-                Some(graph_host_node_attrs(graph_host.name())),
+                Some(graph_host_node_attrs(
+                    &host.label,
+                    Color::White,
+                    host.highlighted,
+                )),
             )
             .extend(host_subgraph_attrs.clone())
             .add_attr(tabbycat::AttrType::Graph, layout.clone());
-        for stmt in graph_host.listening_processes_nodes_stmts() {
-            stmts = stmts.add(stmt);
+        if let Some(fill) = host.fill {
+            stmts = stmts.add_attr(
+                tabbycat::AttrType::Graph,
+                AttrList::new().add_pair(fillcolor(hsv_color(fill))),
+            );
         }
-        for stmt in graph_host.connected_processes_nodes_stmts() {
-            stmts = stmts.add(stmt);
+
+        // The connected processes are declared along with each of their connections
+        let connected_nodes: HashMap<&str, &MapNode> = host
+            .nodes
+            .iter()
+            .filter(|node| node.kind == NodeKind::ConnectedProcess)
+            .map(|node| (node.node_id.as_str(), node))
+            .collect();
+        let connected_nodes_stmts = map_graph
+            .edges
+            .iter()
+            .filter(|edge| matches!(edge.kind, EdgeKind::Connection(_) | EdgeKind::Unmatched(_)))
+            .filter_map(|edge| connected_nodes.get(edge.tail.as_str()));
+        for node in host
+            .nodes
+            .iter()
+            .filter(|node| node.kind == NodeKind::ListeningProcess)
+        {
+            stmts = stmts.add_node(
+                Identity::String(&node.node_id),
+                None,
+                Some(listening_process_node_attrs(&node.label, node.highlighted)),
+            );
         }
-        for stmt in graph_host.listening_processes_edges_stmts() {
-            edges_stmts = edges_stmts.add(stmt)
+        for node in connected_nodes_stmts {
+            stmts = stmts.add_node(
+                Identity::String(&node.node_id),
+                None,
+                Some(connected_process_node_attrs(&node.label, node.highlighted)),
+            );
         }
-        for stmt in graph_host.connected_processes_edges_stmts() {
-            edges_stmts = edges_stmts.add(stmt);
+        for node in &host.nodes {
+            let attrs = match node.kind {
+                NodeKind::CollapsedLoopback => collapsed_loopback_node_attrs(&node.label),
+                NodeKind::ListenersSummary => listeners_summary_node_attrs(&node.label),
+                NodeKind::UnmatchedPeer => unmatched_peer_node_attrs(&node.label),
+                NodeKind::ListeningProcess | NodeKind::ConnectedProcess => continue,
+            };
+            stmts = stmts.add_node(Identity::String(&node.node_id), None, Some(attrs));
         }
-        let subgraph =
-            tabbycat::SubGraph::subgraph(Some(Identity::String(graph_host.node_id())), stmts);
+        let subgraph = tabbycat::SubGraph::subgraph(Some(Identity::String(&host.node_id)), stmts);
         subgraphs.push(subgraph);
     }
 
+    for edge in &map_graph.edges {
+        edges_stmts =
+            edges_stmts.add_edge(process_edge(edge, (!deterministic).then_some(&mut rng)));
+    }
+
     (subgraphs, edges_stmts)
 }
 
-// Create the graph
-pub fn create_graph<'a>(
-    connections: &Vec<connections_model::Connection<'a>>,
+/// DPI with which Graphviz draws the SVG graphs without cropping them
+const SVG_DPI: f64 = 96.0;
+/// Number of nodes above which circo takes a long time to lay out the graph
+const CIRCO_MAX_NODES: usize = 50;
+
+/// Rendering options of a graph, to be checked with [`validate_options`] before a long render
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// Output format, as the extension passed to Graphviz
+    pub format: String,
+    pub dpi: f64,
+    pub layout_engine: Option<LayoutEngine>,
+    pub transparent_background: bool,
+    /// Number of nodes of the graph, as given by [`nodes_count`]
+    pub nodes: usize,
+}
+
+impl RenderOptions {
+    /// Apply the corrections of the warnings which have one, such as the white background of the
+    /// formats without transparency
+    pub fn correct(&mut self, warnings: &[OptionWarning]) {
+        for warning in warnings {
+            if let OptionWarning::OpaqueFormat { .. } = warning {
+                self.transparent_background = false;
+            }
+        }
+    }
+}
+
+/// Combination of rendering options which does not give the expected graph
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionWarning {
+    /// Graphviz crops the SVG graphs drawn with another DPI
+    SvgDpi { dpi: f64 },
+    /// The layout engine is slow with this number of nodes
+    SlowLayout {
+        layout_engine: LayoutEngine,
+        nodes: usize,
+    },
+    /// The format has no transparency, so the background is made white
+    OpaqueFormat { format: String },
+}
+
+impl OptionWarning {
+    /// Whether the options are corrected by [`RenderOptions::correct`], the warning being a mere
+    /// notice
+    pub fn is_corrected(&self) -> bool {
+        matches!(self, OptionWarning::OpaqueFormat { .. })
+    }
+}
+
+impl std::fmt::Display for OptionWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionWarning::SvgDpi { dpi } => write!(
+                f,
+                "SVG graphs drawn with a DPI of {dpi} are cropped, use a DPI of {SVG_DPI}"
+            ),
+            OptionWarning::SlowLayout {
+                layout_engine,
+                nodes,
+            } => write!(
+                f,
+                "the {} layout engine is slow with {nodes} nodes, dot is faster",
+                <&str>::from(layout_engine)
+            ),
+            OptionWarning::OpaqueFormat { format } => write!(
+                f,
+                "the {format} format has no transparency, the background is white instead"
+            ),
+        }
+    }
+}
+
+/// Check the combinations of rendering options which are known to give a cropped or slow graph,
+/// or to be ignored
+pub fn validate_options(options: &RenderOptions) -> Vec<OptionWarning> {
+    let mut warnings = Vec::new();
+    let format = options.format.to_lowercase();
+    if format == "svg" && options.dpi != SVG_DPI {
+        warnings.push(OptionWarning::SvgDpi { dpi: options.dpi });
+    }
+    if options.layout_engine == Some(LayoutEngine::Circo) && options.nodes > CIRCO_MAX_NODES {
+        warnings.push(OptionWarning::SlowLayout {
+            layout_engine: LayoutEngine::Circo,
+            nodes: options.nodes,
+        });
+    }
+    if options.transparent_background && matches!(format.as_str(), "jpeg" | "jpg" | "jpe") {
+        warnings.push(OptionWarning::OpaqueFormat {
+            format: options.format.clone(),
+        });
+    }
+    warnings
+}
+
+/// Number of nodes drawn for the connections: their hosts, and their processes unless only the
+/// hosts are drawn
+pub fn nodes_count(
+    connections: &[connections_model::Connection],
+    granularity: GraphGranularity,
+) -> usize {
+    let mut nodes = HashSet::new();
+    for connection in connections {
+        nodes.insert(connection.listening_host().name());
+        nodes.insert(connection.connected_host().name());
+        if granularity == GraphGranularity::Process {
+            nodes.insert(connection.listening_connection().node_id());
+            nodes.insert(connection.connected_connection().process().node_id());
+        }
+    }
+    nodes.len()
+}
+
+/// Options of the DOT code drawn by [`create_graph`]
+#[derive(Debug, Clone)]
+pub struct DrawOptions {
+    /// Hosts, nodes and edges of the graph. The nodes matching its highlight rules are filled with
+    /// the highlight color.
+    pub graph: MapGraphOptions,
+    pub transparent_background: bool,
+    pub hide_legend: bool,
+    /// Line written under the legend, which only explains what the options draw
+    pub legend_caption: Option<String>,
+    pub dpi: f64,
+    pub layout_engine: Option<LayoutEngine>,
+    /// Placement of the hosts: dot uses the rank groups, neato and fdp the positions
+    pub layout_hints: Option<LayoutHints>,
+}
+
+impl Default for DrawOptions {
+    fn default() -> Self {
+        Self {
+            graph: MapGraphOptions::default(),
+            transparent_background: false,
+            hide_legend: false,
+            legend_caption: None,
+            dpi: 96.0,
+            layout_engine: None,
+            layout_hints: None,
+        }
+    }
+}
+
+// Create the DOT code of the graph. With `GraphGranularity::Host`, the processes are left out and
+// the listener rendering ignored. If the options are deterministic, the same connections give the
+// same DOT code, whatever their order. Otherwise, the statements follow the order of the
+// connections and the edges get new random colors each time.
+pub fn create_graph(
+    connections: &[connections_model::Connection],
+    options: &DrawOptions,
+) -> anyhow::Result<String> {
+    draw_map_graph(&build_map_graph(connections, &options.graph), options)
+}
+
+/// Same as [`create_graph`], with the options given one by one as before [`DrawOptions`]
+#[deprecated(since = "1.0.0", note = "use `create_graph` with a `DrawOptions`")]
+#[allow(clippy::too_many_arguments)]
+pub fn create_graph_with_ordering(
+    connections: &[connections_model::Connection],
     transparent_background: bool,
     hide_legend: bool,
+    legend_caption: Option<&str>,
     dpi_value: f64,
     layout_engine: Option<&LayoutEngine>,
-) -> anyhow::Result<tabbycat::Graph<'a>> {
+    highlight: Option<&HighlightRules>,
+    loopback_rendering: LoopbackRendering,
+    listener_rendering: ListenerRendering,
+    granularity: GraphGranularity,
+    layout_hints: Option<&LayoutHints>,
+    deterministic: bool,
+) -> anyhow::Result<String> {
+    let options = DrawOptions {
+        graph: MapGraphOptions {
+            granularity,
+            loopback_rendering,
+            listener_rendering,
+            highlight: highlight.cloned(),
+            deterministic,
+            show_unmatched: false,
+        },
+        transparent_background,
+        hide_legend,
+        legend_caption: legend_caption.map(str::to_string),
+        dpi: dpi_value,
+        layout_engine: layout_engine.cloned(),
+        layout_hints: layout_hints.cloned(),
+    };
+    create_graph(connections, &options)
+}
+
+/// Same as [`create_graph`] for the connections of the report, drawing its connections to captured
+/// hosts without a matching listener too if [`MapGraphOptions::show_unmatched`]
+pub fn create_report_graph(
+    report: &connections_model::ConnectionsReport,
+    options: &DrawOptions,
+) -> anyhow::Result<String> {
+    draw_map_graph(&build_report_map_graph(report, &options.graph), options)
+}
+
+/// DOT code of the graph, see [`create_graph`]
+fn draw_map_graph(map_graph: &MapGraph, options: &DrawOptions) -> anyhow::Result<String> {
+    let granularity = map_graph.granularity;
+    let layout_engine = options.layout_engine.as_ref();
+    let layout_hints = options.layout_hints.as_ref();
     let graph_builder = tabbycat::GraphBuilder::default()
         .graph_type(GraphType::DiGraph)
         .strict(false)
@@ -348,38 +831,104 @@ pub fn create_graph<'a>(
         });
 
     // Background
-    if transparent_background {
+    if options.transparent_background {
         layout = layout.add_pair(bgcolor(Color::Transparent));
     } else {
         layout = layout.add_pair(bgcolor(Color::White));
     }
 
-    // Hosts subgraphs
-    let hosts_subgraphs = create_hosts_subgraph(connections);
     let mut graph_stmts = tabbycat::StmtList::new()
         .add_attr(tabbycat::AttrType::Graph, layout.clone())
         .add_attr(
             tabbycat::AttrType::Graph,
-            AttrList::new().add_pair(dpi(dpi_value)),
+            AttrList::new().add_pair(dpi(options.dpi)),
         );
-    for host_subgraph in hosts_subgraphs.0 {
-        graph_stmts = graph_stmts.add_subgraph(host_subgraph);
+
+    // Hosts subgraphs, or hosts nodes
+    match granularity {
+        GraphGranularity::Process => {
+            let hosts_subgraphs = create_hosts_subgraph(map_graph, options.graph.deterministic);
+            for host_subgraph in hosts_subgraphs.0 {
+                graph_stmts = graph_stmts.add_subgraph(host_subgraph);
+            }
+            graph_stmts = graph_stmts.extend(hosts_subgraphs.1);
+        }
+        GraphGranularity::Host => {
+            graph_stmts = graph_stmts.extend(create_hosts_nodes(map_graph));
+        }
+    }
+
+    // Layout hints, which refer to the host nodes declared above
+    let drawn_hosts = match layout_hints {
+        Some(layout_hints) if !layout_hints.is_empty() => drawn_hosts(map_graph),
+        _ => BTreeMap::new(),
+    };
+    let pinned_positions = layout_hints
+        .map(|layout_hints| pinned_positions(layout_hints, &drawn_hosts))
+        .unwrap_or_default();
+    if let Some(layout_hints) = layout_hints {
+        graph_stmts = graph_stmts.extend(layout_hints_stmts(
+            layout_hints,
+            &drawn_hosts,
+            &pinned_positions,
+            layout_engine,
+        ));
     }
-    graph_stmts = graph_stmts.extend(hosts_subgraphs.1);
 
     // Legend
-    if !hide_legend {
-        let legend_subgraph = generate_legend();
+    let legend_spec = LegendSpec {
+        granularity,
+        listener_rendering: options.graph.listener_rendering,
+        collapsed_loopback: map_graph
+            .hosts
+            .iter()
+            .flat_map(|host| &host.nodes)
+            .any(|node| node.kind == NodeKind::CollapsedLoopback),
+        highlight: options
+            .graph
+            .highlight
+            .as_ref()
+            .is_some_and(|rules| !rules.is_empty()),
+        proxies: map_graph.edges.iter().any(
+            |edge| matches!(&edge.kind, EdgeKind::Connection(connection) if connection.proxied),
+        ),
+        unmatched: map_graph
+            .edges
+            .iter()
+            .any(|edge| matches!(edge.kind, EdgeKind::Unmatched(_))),
+        caption: options.legend_caption.clone(),
+    };
+    if !options.hide_legend {
+        let legend_subgraph = generate_legend(&legend_spec);
         graph_stmts = graph_stmts.add_subgraph(legend_subgraph);
     }
 
     graph_builder
         .stmts(graph_stmts)
         .build()
+        .map(|graph| graph.to_string())
         .map_err(|e| anyhow!(e))
 }
 
-fn generate_legend<'a>() -> tabbycat::SubGraph<'a> {
+/// Entries of the legend, derived from the options of the graph so that it only explains what is
+/// drawn
+#[derive(Debug, Clone, Default)]
+struct LegendSpec {
+    granularity: GraphGranularity,
+    listener_rendering: ListenerRendering,
+    /// The loopback connections of some hosts are drawn as a single node
+    collapsed_loopback: bool,
+    /// Some nodes may be filled with the highlight color
+    highlight: bool,
+    /// Some connections go through proxies
+    proxies: bool,
+    /// Some connections target a captured host without a matching listener
+    unmatched: bool,
+    /// Line given by the user, such as the date of the capture, escaped along with the labels
+    caption: Option<String>,
+}
+
+fn generate_legend(spec: &LegendSpec) -> tabbycat::SubGraph<'_> {
     // Styling parameters
     let legend_cluster_attrs = tabbycat::StmtList::new()
         .add_attr(
@@ -418,56 +967,773 @@ fn generate_legend<'a>() -> tabbycat::SubGraph<'a> {
             AttrList::new().add_pair(arrowsize(0.4)),
         );
 
-    let legend_stmts = tabbycat::StmtList::new()
+    // The listening node stands for the listeners summary of the host in summary mode, the edges
+    // then telling the port they target
+    let (listening_node_attrs, edge_label) = match spec.listener_rendering {
+        ListenerRendering::PerProcess => (
+            listening_process_node_attrs("Listening process\nprotocol:port", false),
+            None,
+        ),
+        ListenerRendering::Summary => (
+            listeners_summary_node_attrs("Listening ports\nprotocol: port, port"),
+            Some("protocol:port"),
+        ),
+    };
+    let connection_edge = |edge_color: Color| {
+        let edge = Edge::head_node(Identity::String("connected_process"), None)
+            .arrow_to_node(Identity::String("listening_process"), None)
+            .add_attrpair(color(edge_color))
+            .add_attrpair(constraint(false));
+        match edge_label {
+            Some(edge_label) => edge
+                .add_attrpair(label(edge_label))
+                .add_attrpair(fontsize(8.0)),
+            None => edge,
+        }
+    };
+
+    // The edges of the options go between the connected and listening nodes, which are hosts when
+    // only the hosts are drawn
+    let (connected_node, listening_node) = match spec.granularity {
+        GraphGranularity::Process => ("connected_process", "listening_process"),
+        GraphGranularity::Host => ("host2", "host1"),
+    };
+    let mut legend_stmts = tabbycat::StmtList::new()
         .extend(legend_cluster_attrs)
         .add_node(
             Identity::String("host1"),
             None,
-            Some(graph_host_node_attrs("Host")),
-        )
-        .add_node(
-            Identity::String("listening_process"),
+            Some(graph_host_node_attrs("Host", Color::White, false)),
+        );
+    legend_stmts = match spec.granularity {
+        GraphGranularity::Process => legend_stmts
+            .add_node(
+                Identity::String("listening_process"),
+                None,
+                Some(listening_node_attrs),
+            )
+            .add_node(
+                Identity::String("connected_process"),
+                None,
+                Some(connected_process_node_attrs("Connected process", false)),
+            )
+            .add_edge(
+                Edge::head_node(Identity::String("host1"), None)
+                    .arrow_to_node(Identity::String("listening_process"), None)
+                    .add_attrpair(style(Style::Dashed)),
+            )
+            .add_edge(
+                Edge::head_node(Identity::String("host1"), None)
+                    .arrow_to_node(Identity::String("connected_process"), None)
+                    .add_attrpair(style(Style::Dashed)),
+            )
+            .add_edge(connection_edge(Color::Darkblue))
+            .add_edge(connection_edge(Color::Darkred))
+            .add_edge(connection_edge(Color::Darkgreen)),
+        GraphGranularity::Host => legend_stmts
+            .add_node(
+                Identity::String("host2"),
+                None,
+                Some(graph_host_node_attrs("Connected host", Color::White, false)),
+            )
+            .add_edge(
+                Edge::head_node(Identity::String("host2"), None)
+                    .arrow_to_node(Identity::String("host1"), None)
+                    .add_attrpair(color(Color::Black))
+                    .add_attrpair(constraint(false))
+                    .add_attrpair(label("connections: protocol:port"))
+                    .add_attrpair(fontsize(8.0)),
+            ),
+    };
+
+    // Entries of the options in use
+    if spec.collapsed_loopback {
+        legend_stmts = legend_stmts
+            .add_node(
+                Identity::String("collapsed_loopback"),
+                None,
+                Some(collapsed_loopback_node_attrs(
+                    "Local connections\nof the host",
+                )),
+            )
+            .add_edge(
+                Edge::head_node(Identity::String("host1"), None)
+                    .arrow_to_node(Identity::String("collapsed_loopback"), None)
+                    .add_attrpair(style(Style::Dashed)),
+            );
+    }
+    if spec.proxies {
+        legend_stmts = legend_stmts.add_edge(
+            Edge::head_node(Identity::String(connected_node), None)
+                .arrow_to_node(Identity::String(listening_node), None)
+                .add_attrpair(style(Style::Dotted))
+                .add_attrpair(constraint(false))
+                .add_attrpair(label("via proxy"))
+                .add_attrpair(fontsize(8.0)),
+        );
+    }
+    if spec.unmatched {
+        let unmatched_node = match spec.granularity {
+            GraphGranularity::Process => {
+                legend_stmts = legend_stmts.add_node(
+                    Identity::String("unmatched_peer"),
+                    None,
+                    Some(unmatched_peer_node_attrs("?")),
+                );
+                "unmatched_peer"
+            }
+            GraphGranularity::Host => listening_node,
+        };
+        legend_stmts = legend_stmts.add_edge(
+            Edge::head_node(Identity::String(connected_node), None)
+                .arrow_to_node(Identity::String(unmatched_node), None)
+                .add_attrpair(color(Color::Grey))
+                .add_attrpair(style(Style::Dashed))
+                .add_attrpair(constraint(false))
+                .add_attrpair(label("no listener captured"))
+                .add_attrpair(fontsize(8.0)),
+        );
+    }
+    if spec.highlight {
+        let highlighted_attrs = match spec.granularity {
+            GraphGranularity::Process => connected_process_node_attrs("Highlighted", true),
+            GraphGranularity::Host => graph_host_node_attrs("Highlighted", Color::White, true),
+        };
+        legend_stmts = legend_stmts.add_node(
+            Identity::String("highlighted_process"),
             None,
-            Some(listening_process_node_attrs(
-                "Listening process\nprotocol:port",
-            )),
-        )
-        .add_node(
-            Identity::String("connected_process"),
+            Some(highlighted_attrs),
+        );
+    }
+    if let Some(caption) = &spec.caption {
+        legend_stmts = legend_stmts.add_node(
+            Identity::String("legend_caption"),
             None,
-            Some(connected_process_node_attrs("Connected process")),
-        )
-        .add_edge(
-            Edge::head_node(Identity::String("host1"), None)
-                .arrow_to_node(Identity::String("listening_process"), None)
-                .add_attrpair(style(Style::Dashed)),
-        )
-        .add_edge(
-            Edge::head_node(Identity::String("host1"), None)
-                .arrow_to_node(Identity::String("connected_process"), None)
-                .add_attrpair(style(Style::Dashed)),
-        )
-        .add_edge(
-            Edge::head_node(Identity::String("connected_process"), None)
-                .arrow_to_node(Identity::String("listening_process"), None)
-                .add_attrpair(color(Color::Darkblue))
-                .add_attrpair(constraint(false)),
-        )
-        .add_edge(
-            Edge::head_node(Identity::String("connected_process"), None)
-                .arrow_to_node(Identity::String("listening_process"), None)
-                .add_attrpair(color(Color::Darkred))
-                .add_attrpair(constraint(false)),
-        )
-        .add_edge(
-            Edge::head_node(Identity::String("connected_process"), None)
-                .arrow_to_node(Identity::String("listening_process"), None)
-                .add_attrpair(color(Color::Darkgreen))
-                .add_attrpair(constraint(false)),
+            Some(
+                AttrList::new()
+                    .add_pair(shape(Shape::Plaintext))
+                    .add_pair(label(caption)),
+            ),
         );
+    }
+
     let legend_subgraph = tabbycat::SubGraph::subgraph(
         Some(tabbycat::Identity::String("cluster_legend")),
         legend_stmts,
     );
     legend_subgraph
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections_model::{
+        build_connections_list, build_connections_list_with, build_connections_list_with_options,
+        tests::{make_fake_connections, make_stunnel_chain},
+        ConnectionsOptions, DefaultMatcher, ProxyAwareMatcher,
+    };
+    use crate::host;
+
+    /// The DOT code must not depend on the order of the hosts, colors included
+    #[test]
+    fn test_deterministic_dot() {
+        use rand::seq::SliceRandom;
+
+        let dot_code = |hosts: &[host::Host]| {
+            create_graph(
+                &build_connections_list(hosts, false),
+                &DrawOptions::default(),
+            )
+            .unwrap()
+        };
+        let mut hosts = crate::test_utils::random_mesh(8, 7);
+        let expected = dot_code(&hosts);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..3 {
+            hosts.shuffle(&mut rng);
+            assert_eq!(dot_code(&hosts), expected);
+        }
+    }
+
+    /// Only the hosts and processes matching the rules must be filled with the highlight color
+    #[test]
+    fn test_highlight() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let rules = HighlightRules::new(vec![Regex::new("^ssh").unwrap()]);
+        assert_eq!(rules.count_matches(&connections), 2);
+
+        let dot_code = create_graph(
+            &connections,
+            &DrawOptions {
+                graph: MapGraphOptions {
+                    highlight: Some(rules.clone()),
+                    ..Default::default()
+                },
+                hide_legend: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(dot_code.matches(HIGHLIGHT_FILLCOLOR).count(), 2);
+        let dot_code = create_graph(
+            &connections,
+            &DrawOptions {
+                hide_legend: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!dot_code.contains(HIGHLIGHT_FILLCOLOR));
+    }
+
+    /// Collapsed loopback connections must be replaced with a single node per host, leaving the
+    /// connections between hosts as they are
+    #[test]
+    fn test_loopback_rendering() {
+        let mut hosts = make_fake_connections();
+        for (port, process) in [(40000, "curl"), (40001, "wget")] {
+            hosts[0].add_established_connection(host::Connection::new(
+                format!("10.0.0.1:{port}").parse().unwrap(),
+                "10.0.0.1:443".parse().unwrap(),
+                host::SocketType::TCP,
+                host::Process::new(process, 103, "machine1".to_string()),
+            ));
+        }
+        let connections = build_connections_list(&hosts, false);
+        let graph = |loopback_rendering| {
+            create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        loopback_rendering,
+                        ..Default::default()
+                    },
+                    hide_legend: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let shown = graph(LoopbackRendering::Show);
+        assert!(shown.contains("curl") && shown.contains("wget"));
+        assert!(!shown.contains("local connections"));
+
+        let hidden = graph(LoopbackRendering::Hide);
+        assert!(!hidden.contains("curl") && !hidden.contains("wget"));
+        assert!(hidden.contains("firefox"));
+
+        let collapsed = graph(LoopbackRendering::Collapse);
+        assert!(!collapsed.contains("curl") && !collapsed.contains("wget"));
+        assert!(collapsed.contains("firefox"));
+        assert_eq!(
+            collapsed
+                .matches("2 local connections among 3 processes")
+                .count(),
+            1
+        );
+        assert!(collapsed.contains("cluster_machine1_loopback"));
+        assert!(!collapsed.contains("cluster_machine2_loopback"));
+    }
+
+    /// Summarized listeners must be drawn as a single node per host listing its ports, the edges
+    /// of the connections going to it labeled with the port they target
+    #[test]
+    fn test_listener_rendering() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let graph = |listener_rendering| {
+            create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        listener_rendering,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let per_process = graph(ListenerRendering::PerProcess);
+        assert!(per_process.contains("Listening process"));
+        assert!(!per_process.contains("_listeners"));
+        assert!(!per_process.contains("tcp:443"));
+        for listening_socket in hosts.iter().flat_map(host::Host::listening_sockets) {
+            assert!(per_process.contains(listening_socket.node_id()));
+        }
+
+        let summary = graph(ListenerRendering::Summary);
+        assert!(summary.contains("Listening ports"));
+        assert!(!summary.contains("Listening process"));
+        for listening_socket in hosts.iter().flat_map(host::Host::listening_sockets) {
+            assert!(!summary.contains(listening_socket.node_id()));
+        }
+        assert_eq!(summary.matches("\"tcp: 22, 443\"").count(), 1);
+        assert_eq!(summary.matches("\"udp: 50001\"").count(), 1);
+        for (process, port) in [
+            ("ssh", "tcp:22"),
+            ("firefox", "tcp:443"),
+            ("some_udp_client", "udp:50001"),
+        ] {
+            let edge_start = summary.find(&format!("_{process}->")).unwrap();
+            let edge = &summary[edge_start..];
+            let edge = &edge[..edge.find(']').unwrap()];
+            assert!(edge.contains("_listeners"), "{edge}");
+            assert!(edge.contains(&format!("label=\"{port}\"")), "{edge}");
+        }
+        // machine3 does not listen on any port
+        assert!(summary.contains("cluster_machine1_listeners"));
+        assert!(!summary.contains("cluster_machine3_listeners"));
+    }
+
+    /// The legend must only have the entries of the options in use, and the caption if one is given
+    #[test]
+    fn test_legend() {
+        let mut hosts = make_fake_connections();
+        hosts[0].add_established_connection(host::Connection::new(
+            "10.0.0.1:40000".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+            host::SocketType::TCP,
+            host::Process::new("curl", 103, "machine1".to_string()),
+        ));
+        let connections = build_connections_list(&hosts, false);
+        let rules = HighlightRules::new(vec![Regex::new("^ssh").unwrap()]);
+        let legend = |caption: Option<&str>, highlight, loopback_rendering| {
+            let dot_code = create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        loopback_rendering,
+                        highlight,
+                        ..Default::default()
+                    },
+                    legend_caption: caption.map(str::to_string),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            dot_code[dot_code.find("cluster_legend").unwrap()..].to_string()
+        };
+
+        let default = legend(None, None, LoopbackRendering::Show);
+        assert!(default.contains("Listening process"));
+        assert!(!default.contains("collapsed_loopback"));
+        assert!(!default.contains("highlighted_process"));
+        assert!(!default.contains("legend_caption"));
+
+        let collapsed = legend(None, None, LoopbackRendering::Collapse);
+        assert!(collapsed.contains("host1->collapsed_loopback"));
+        // Nothing is collapsed when the loopback connections are hidden
+        assert!(!legend(None, None, LoopbackRendering::Hide).contains("collapsed_loopback"));
+
+        let highlighted = legend(None, Some(rules), LoopbackRendering::Show);
+        assert!(highlighted.contains("highlighted_process"));
+        assert!(highlighted.contains(HIGHLIGHT_FILLCOLOR));
+
+        let captioned = legend(Some("Capture of \"prod\""), None, LoopbackRendering::Show);
+        assert!(captioned.contains("label=\"Capture of \\\"prod\\\"\""));
+    }
+
+    /// The overridden hosts must be drawn with their display name, and keep their node ids
+    #[test]
+    fn test_display_overrides() {
+        let mut hosts = make_fake_connections();
+        let overrides = std::collections::BTreeMap::from([(
+            "machine1".to_string(),
+            host::DisplayOverride {
+                name: Some("Gateway".to_string()),
+                color: Some("#ff0000".to_string()),
+            },
+        )]);
+        host::apply_display_overrides(&mut hosts, &overrides);
+        let connections = build_connections_list(&hosts, false);
+        let dot_code = create_graph(
+            &connections,
+            &DrawOptions {
+                hide_legend: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(dot_code.contains("label=\"Gateway\""));
+        assert!(!dot_code.contains("label=\"machine1\""));
+        assert!(dot_code.contains("cluster_machine1"));
+
+        // tabbycat colors can't be compared, only their components
+        let hsv = |rgb| match hsv_color(rgb) {
+            Color::HSV(h, s, v) => Some((h, s, v)),
+            _ => None,
+        };
+        assert_eq!(hsv([255, 0, 0]), Some((0.0, 1.0, 1.0)));
+        assert_eq!(hsv([0, 0, 255]), Some((4.0 / 6.0, 1.0, 1.0)));
+        assert_eq!(hsv([0, 0, 0]), Some((0.0, 0.0, 0.0)));
+    }
+
+    /// Each rule must only flag its combination of options, and the transparent background of
+    /// JPEG graphs be corrected
+    #[test]
+    fn test_validate_options() {
+        let options = RenderOptions {
+            format: "png".to_string(),
+            dpi: 300.0,
+            layout_engine: Some(LayoutEngine::Circo),
+            transparent_background: true,
+            nodes: CIRCO_MAX_NODES,
+        };
+        assert!(validate_options(&options).is_empty());
+
+        let svg = RenderOptions {
+            format: "svg".to_string(),
+            ..options.clone()
+        };
+        assert_eq!(
+            validate_options(&svg),
+            [OptionWarning::SvgDpi { dpi: 300.0 }]
+        );
+        assert!(validate_options(&RenderOptions { dpi: 96.0, ..svg }).is_empty());
+
+        let large = RenderOptions {
+            nodes: CIRCO_MAX_NODES + 1,
+            ..options.clone()
+        };
+        assert_eq!(
+            validate_options(&large),
+            [OptionWarning::SlowLayout {
+                layout_engine: LayoutEngine::Circo,
+                nodes: CIRCO_MAX_NODES + 1
+            }]
+        );
+        let dot = RenderOptions {
+            layout_engine: Some(LayoutEngine::Dot),
+            ..large
+        };
+        assert!(validate_options(&dot).is_empty());
+
+        let mut jpeg = RenderOptions {
+            format: "JPG".to_string(),
+            ..options
+        };
+        let warnings = validate_options(&jpeg);
+        assert_eq!(
+            warnings,
+            [OptionWarning::OpaqueFormat {
+                format: "JPG".to_string()
+            }]
+        );
+        assert!(warnings[0].is_corrected());
+        jpeg.correct(&warnings);
+        assert!(!jpeg.transparent_background);
+        assert!(validate_options(&jpeg).is_empty());
+    }
+
+    /// The hosts and processes must be counted once, however many connections they have
+    #[test]
+    fn test_nodes_count() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let dot_code = create_graph(
+            &connections,
+            &DrawOptions {
+                hide_legend: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // Each node has a label, the edges between processes do not
+        assert_eq!(
+            nodes_count(&connections, GraphGranularity::Process),
+            dot_code.matches("label=").count()
+        );
+        let dot_code = create_graph(
+            &connections,
+            &DrawOptions {
+                graph: MapGraphOptions {
+                    granularity: GraphGranularity::Host,
+                    ..Default::default()
+                },
+                hide_legend: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            nodes_count(&connections, GraphGranularity::Host),
+            dot_code.matches("shape=egg").count()
+        );
+    }
+
+    /// DOT code of the standard fixture with both granularities, the random edge colors aside: the
+    /// host level draws a single edge per pair of hosts instead of the processes
+    #[test]
+    fn test_graph_granularity_snapshots() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let dot_code = |granularity| {
+            let dot_code = create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        granularity,
+                        ..Default::default()
+                    },
+                    hide_legend: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            Regex::new(r#"color="[0-9.,+]+""#)
+                .unwrap()
+                .replace_all(&dot_code, "color=\"\"")
+                .to_string()
+        };
+
+        assert_eq!(
+            dot_code(GraphGranularity::Process),
+            "digraph G{graph [layout=\"dot\";fontname=\"Verdata\";scale=1;bgcolor=white;];\
+graph [dpi=96;];\
+subgraph cluster_machine1 {cluster_machine1[fontname=\"Verdata\";label=\"machine1\";shape=egg;style=\"filled,bold\";fillcolor=white;];\
+graph [fontname=\"Verdata\";style=\"rounded,filled\";color=lightgrey;];\
+graph [layout=\"dot\";];\
+a8e19baf9b19c9349da504dd4cda987393b0fdf2b[fontname=\"Verdata\";shape=box;style=\"rounded,filled\";fillcolor=black;fontcolor=white;label=\"nginx\\ntcp4:443\";];\
+ae54c7e0495bc2ac4f05429f1f94bde4aaf9bd5dc[fontname=\"Verdata\";shape=box;style=\"rounded,filled\";fillcolor=black;fontcolor=white;label=\"sshd\\ntcp4/6:22\";];\
+};subgraph cluster_machine2 {cluster_machine2[fontname=\"Verdata\";label=\"machine2\";shape=egg;style=\"filled,bold\";fillcolor=white;];\
+graph [fontname=\"Verdata\";style=\"rounded,filled\";color=lightgrey;];\
+graph [layout=\"dot\";];\
+aca41f950efde33be4556a441282b335111eafd02[fontname=\"Verdata\";shape=box;style=\"rounded,filled\";fillcolor=black;fontcolor=white;label=\"some_udp_service\\nudp4:50001\";];\
+machine2_firefox[fontname=\"Verdata\";shape=box;style=\"rounded,filled\";fillcolor=white;label=\"firefox\";];\
+machine2_ssh[fontname=\"Verdata\";shape=box;style=\"rounded,filled\";fillcolor=white;label=\"ssh\";];\
+};subgraph cluster_machine3 {cluster_machine3[fontname=\"Verdata\";label=\"machine3\";shape=egg;style=\"filled,bold\";fillcolor=white;];\
+graph [fontname=\"Verdata\";style=\"rounded,filled\";color=lightgrey;];\
+graph [layout=\"dot\";];\
+machine3_some_udp_client[fontname=\"Verdata\";shape=box;style=\"rounded,filled\";fillcolor=white;label=\"some_udp_client\";];\
+};cluster_machine1->a8e19baf9b19c9349da504dd4cda987393b0fdf2b[color=black;style=dashed;];\
+cluster_machine1->ae54c7e0495bc2ac4f05429f1f94bde4aaf9bd5dc[color=black;style=dashed;];\
+cluster_machine2->aca41f950efde33be4556a441282b335111eafd02[color=black;style=dashed;];\
+cluster_machine2->machine2_firefox[color=black;style=dashed;];\
+cluster_machine2->machine2_ssh[color=black;style=dashed;];\
+machine2_firefox->a8e19baf9b19c9349da504dd4cda987393b0fdf2b[color=\"\";];\
+machine2_ssh->ae54c7e0495bc2ac4f05429f1f94bde4aaf9bd5dc[color=\"\";];\
+cluster_machine3->machine3_some_udp_client[color=black;style=dashed;];\
+machine3_some_udp_client->aca41f950efde33be4556a441282b335111eafd02[color=\"\";];\
+}"
+        );
+        assert_eq!(
+            dot_code(GraphGranularity::Host),
+            "digraph G{graph [layout=\"dot\";fontname=\"Verdata\";scale=1;bgcolor=white;];\
+graph [dpi=96;];\
+cluster_machine1[fontname=\"Verdata\";label=\"machine1\";shape=egg;style=\"filled,bold\";fillcolor=white;];\
+cluster_machine2[fontname=\"Verdata\";label=\"machine2\";shape=egg;style=\"filled,bold\";fillcolor=white;];\
+cluster_machine3[fontname=\"Verdata\";label=\"machine3\";shape=egg;style=\"filled,bold\";fillcolor=white;];\
+cluster_machine2->cluster_machine1[color=black;label=\"2 connections: tcp:22, tcp:443\";fontname=\"Verdata\";];\
+cluster_machine3->cluster_machine2[color=black;label=\"1 connection: udp:50001\";fontname=\"Verdata\";];\
+}"
+        );
+    }
+
+    /// The deprecated positional options must draw the same graph as their `DrawOptions`
+    #[test]
+    #[allow(deprecated)]
+    fn test_create_graph_with_ordering() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let highlight = HighlightRules::new(vec![Regex::new("^ssh").unwrap()]);
+        let positional = create_graph_with_ordering(
+            &connections,
+            true,
+            false,
+            Some("Capture of machine1"),
+            150.0,
+            Some(&LayoutEngine::Neato),
+            Some(&highlight),
+            LoopbackRendering::Collapse,
+            ListenerRendering::PerProcess,
+            GraphGranularity::Process,
+            None,
+            true,
+        )
+        .unwrap();
+        let dot_code = create_graph(
+            &connections,
+            &DrawOptions {
+                graph: MapGraphOptions {
+                    loopback_rendering: LoopbackRendering::Collapse,
+                    listener_rendering: ListenerRendering::PerProcess,
+                    highlight: Some(highlight),
+                    ..Default::default()
+                },
+                transparent_background: true,
+                legend_caption: Some("Capture of machine1".to_string()),
+                dpi: 150.0,
+                layout_engine: Some(LayoutEngine::Neato),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(positional, dot_code);
+    }
+
+    /// The hosts of each rank group must share a rank with dot, and the positions must be pinned
+    /// with neato and fdp, the hosts which are not drawn being left out
+    #[test]
+    fn test_layout_hints_snapshots() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+        let mut layout_hints = LayoutHints::default();
+        layout_hints.set_rank_group("dmz", &["machine1".into(), "machine3".into()]);
+        layout_hints.set_rank_group("lan", &["machine2".into(), "machine9".into()]);
+        layout_hints.hosts.entry("machine1".into()).or_default().pos = Some("0, 300.5".into());
+        assert!(layout_hints.validate().is_ok());
+        let dot_code = |layout_engine: Option<LayoutEngine>| {
+            create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        granularity: GraphGranularity::Host,
+                        ..Default::default()
+                    },
+                    hide_legend: true,
+                    layout_engine,
+                    layout_hints: Some(layout_hints.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // Only the hints are checked, the rest of the graph being the one drawn without them
+        let ranked = dot_code(None);
+        assert!(
+            ranked.contains(
+                "subgraph {graph [rank=same;];cluster_machine1;cluster_machine3;};\
+subgraph {graph [rank=same;];cluster_machine2;};"
+            ),
+            "{ranked}"
+        );
+        assert!(!ranked.contains("pos="));
+        for layout_engine in [LayoutEngine::Neato, LayoutEngine::Fdp] {
+            let dot_code = dot_code(Some(layout_engine));
+            assert!(
+                dot_code.contains("cluster_machine1[pos=\"0,300.5!\";];"),
+                "{dot_code}"
+            );
+            assert!(!dot_code.contains("rank=same"));
+        }
+        let dot_code = dot_code(Some(LayoutEngine::Circo));
+        assert!(!dot_code.contains("rank=same") && !dot_code.contains("pos="));
+
+        // The positions need two coordinates
+        for pos in ["300", "0,x", "1,2,3", "inf,0"] {
+            let mut layout_hints = LayoutHints::default();
+            layout_hints.hosts.entry("machine1".into()).or_default().pos = Some(pos.into());
+            assert!(layout_hints.validate().is_err(), "{pos}");
+        }
+    }
+
+    /// The connections through proxies must be dotted and labeled with the proxies, and explained
+    /// in the legend
+    #[test]
+    fn test_proxied_connections() {
+        let hosts = make_stunnel_chain();
+        let matcher = ProxyAwareMatcher::new(&["stunnel"]);
+        let connections = build_connections_list_with(&hosts, false, &matcher);
+        let graph = |listener_rendering| {
+            create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        listener_rendering,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let dot_code = graph(ListenerRendering::PerProcess);
+        assert!(dot_code.contains("label=\"via web (stunnel), relay (stunnel)\""));
+        // The derived connection and the legend entry
+        assert_eq!(dot_code.matches("style=dotted").count(), 2);
+        assert!(dot_code.contains("label=\"via proxy\""));
+
+        let dot_code = graph(ListenerRendering::Summary);
+        assert!(dot_code.contains("label=\"tcp:5432 via web (stunnel), relay (stunnel)\""));
+
+        let connections = build_connections_list(&hosts, false);
+        let dot_code = create_graph(&connections, &DrawOptions::default()).unwrap();
+        assert!(!dot_code.contains("style=dotted"));
+    }
+
+    /// The flows seen from both ends must be drawn as a single edge with arrows at both ends
+    #[test]
+    fn test_bidirectional_edge() {
+        let hosts = crate::test_utils::udp_symmetric();
+        let graph = |options: &ConnectionsOptions, granularity| {
+            let connections = build_connections_list_with_options(&hosts, options, &DefaultMatcher);
+            create_graph(
+                &connections,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        granularity,
+                        ..Default::default()
+                    },
+                    hide_legend: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let dot_code = graph(&ConnectionsOptions::default(), GraphGranularity::Process);
+        assert_eq!(dot_code.matches("dir=both").count(), 1);
+        let dot_code = graph(&ConnectionsOptions::default(), GraphGranularity::Host);
+        assert_eq!(dot_code.matches("dir=both").count(), 1);
+        assert!(dot_code.contains("label=\"1 connection: udp:123\""));
+
+        let options = ConnectionsOptions {
+            keep_both_directions: true,
+            ..Default::default()
+        };
+        assert!(!graph(&options, GraphGranularity::Process).contains("dir="));
+    }
+
+    /// The connections without a matching listener must only be drawn if asked, as dashed edges
+    /// to a "?" node in the cluster of their peer host, explained by the legend
+    #[test]
+    fn test_unmatched_graph() {
+        let hosts = crate::test_utils::missing_listener();
+        let report = connections_model::build_connections_report(
+            &hosts,
+            &ConnectionsOptions::default(),
+            &DefaultMatcher,
+        );
+        let graph = |show_unmatched: bool| {
+            create_report_graph(
+                &report,
+                &DrawOptions {
+                    graph: MapGraphOptions {
+                        show_unmatched,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let expected = create_graph(&report.connections, &DrawOptions::default()).unwrap();
+        assert_eq!(graph(false), expected);
+
+        let dot_code = graph(true);
+        let db_cluster = dot_code
+            .split("subgraph ")
+            .find(|subgraph| subgraph.starts_with("cluster_db"))
+            .unwrap();
+        assert!(db_cluster.contains("cluster_db_unmatched["));
+        assert!(dot_code.contains("label=\"tcp:5432\""));
+        assert!(dot_code.contains("no listener captured"));
+    }
+}
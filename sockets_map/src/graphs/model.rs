@@ -0,0 +1,982 @@
+//! This module models the graph of the connections as hosts, processes and listeners linked by
+//! edges, independently of the format it is drawn in. The DOT code of [`super::create_graph`] is
+//! drawn from it, and other renderers can use it the same way.
+
+use super::{GraphGranularity, HighlightRules, ListenerRendering, LoopbackRendering};
+use crate::connections_model::{Connection, ConnectionsReport, Direction, UnmatchedKnownPeer};
+use crate::host;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Number of destination ports listed on the edges of [`GraphGranularity::Host`], the others being
+/// counted
+const HOST_EDGE_MAX_PORTS: usize = 5;
+
+/// Options of the graph, whatever it is drawn with
+#[derive(Debug, Clone)]
+pub struct MapGraphOptions {
+    pub granularity: GraphGranularity,
+    pub loopback_rendering: LoopbackRendering,
+    /// Ignored with [`GraphGranularity::Host`]
+    pub listener_rendering: ListenerRendering,
+    /// Hosts and processes flagged as highlighted
+    pub highlight: Option<HighlightRules>,
+    /// Sort the hosts, nodes and edges, so that the graph does not depend on the order of the
+    /// connections
+    pub deterministic: bool,
+    /// Draw the connections to captured hosts which have no matching listener, with
+    /// [`build_report_map_graph`]
+    pub show_unmatched: bool,
+}
+
+impl Default for MapGraphOptions {
+    fn default() -> Self {
+        Self {
+            granularity: GraphGranularity::default(),
+            loopback_rendering: LoopbackRendering::default(),
+            listener_rendering: ListenerRendering::default(),
+            highlight: None,
+            deterministic: true,
+            show_unmatched: false,
+        }
+    }
+}
+
+/// Graph of the connections: the hosts, with the nodes drawn in them, and the edges between all
+/// the nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapGraph {
+    pub granularity: GraphGranularity,
+    pub hosts: Vec<MapHost>,
+    /// Edges in drawing order. With [`GraphGranularity::Process`], those of each host follow the
+    /// order of the hosts: the memberships of its listening processes, then those of its connected
+    /// processes along with their connections, then the memberships of its other nodes.
+    pub edges: Vec<MapEdge>,
+}
+
+/// Host of the graph, drawn as a cluster with [`GraphGranularity::Process`] and as a single node
+/// with [`GraphGranularity::Host`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapHost {
+    pub name: String,
+    pub node_id: String,
+    /// The name drawn, see [`host::apply_display_overrides`]
+    pub label: String,
+    /// Fill color as red, green and blue components, left to the renderer if `None`
+    pub fill: Option<[u8; 3]>,
+    pub highlighted: bool,
+    /// Nodes drawn in the host, none with [`GraphGranularity::Host`]
+    pub nodes: Vec<MapNode>,
+}
+
+/// Node drawn in a host
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapNode {
+    pub node_id: String,
+    pub kind: NodeKind,
+    pub label: String,
+    pub highlighted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Listening socket of a process
+    ListeningProcess,
+    ConnectedProcess,
+    /// Listening ports of the host, with [`ListenerRendering::Summary`]
+    ListenersSummary,
+    /// Loopback connections of the host, with [`LoopbackRendering::Collapse`]
+    CollapsedLoopback,
+    /// Missing listeners of the host, which the connections of other hosts target, with
+    /// [`MapGraphOptions::show_unmatched`]
+    UnmatchedPeer,
+}
+
+/// Edge from the `tail` node to the `head` node, the hosts being nodes too
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEdge {
+    pub tail: String,
+    pub head: String,
+    pub kind: EdgeKind,
+}
+
+impl MapEdge {
+    /// Label of the connections, `None` for the memberships
+    pub fn label(&self) -> Option<&str> {
+        match &self.kind {
+            EdgeKind::Membership => None,
+            EdgeKind::Connection(connection) | EdgeKind::Unmatched(connection) => {
+                connection.label.as_deref()
+            }
+        }
+    }
+
+    /// Key ordering the edges: their tail, head and label
+    fn sort_key(&self) -> (&str, &str, Option<&str>) {
+        (&self.tail, &self.head, self.label())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdgeKind {
+    /// From a host to a node drawn in it
+    Membership,
+    Connection(ConnectionEdge),
+    /// Connections to a captured host without a matching listener, to its
+    /// [`NodeKind::UnmatchedPeer`] node, or to the host itself with [`GraphGranularity::Host`]
+    Unmatched(ConnectionEdge),
+}
+
+/// Connections between two processes, or between two hosts with [`GraphGranularity::Host`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionEdge {
+    /// Port the connections target in summary mode, proxies they go through, or number of
+    /// connections and destination ports between hosts
+    pub label: Option<String>,
+    /// Direction of the first connection between processes. Between hosts, [`Direction::Both`] if
+    /// all the connections go both ways, [`Direction::Out`] otherwise.
+    pub direction: Direction,
+    /// Whether the connections go through proxies
+    pub proxied: bool,
+    /// Number of connections drawn by the edge
+    pub weight: usize,
+    /// Destination ports, as protocol and port
+    pub ports: BTreeSet<(&'static str, u16)>,
+    /// Node id of the listening socket, which is not the head when the listeners are summarized.
+    /// `None` between hosts.
+    pub listening_node_id: Option<String>,
+}
+
+/// Build the graph of the connections. With [`GraphGranularity::Host`], the processes are left out
+/// and the listener rendering ignored.
+pub fn build_map_graph(connections: &[Connection], options: &MapGraphOptions) -> MapGraph {
+    map_graph(connections, &[], options)
+}
+
+/// Build the graph of the connections of the report, along with its connections without a
+/// matching listener if [`MapGraphOptions::show_unmatched`] is set
+pub fn build_report_map_graph(report: &ConnectionsReport, options: &MapGraphOptions) -> MapGraph {
+    let unmatched: &[UnmatchedKnownPeer] = match options.show_unmatched {
+        true => &report.unmatched_known_peers,
+        false => &[],
+    };
+    map_graph(&report.connections, unmatched, options)
+}
+
+fn map_graph(
+    connections: &[Connection],
+    unmatched: &[UnmatchedKnownPeer],
+    options: &MapGraphOptions,
+) -> MapGraph {
+    let is_highlighted = |name: &str| {
+        options
+            .highlight
+            .as_ref()
+            .is_some_and(|rules| rules.matches(name))
+    };
+    let (hosts, edges) = match options.granularity {
+        GraphGranularity::Process => {
+            process_graph(connections, unmatched, options, &is_highlighted)
+        }
+        GraphGranularity::Host => host_graph(connections, unmatched, options, &is_highlighted),
+    };
+    MapGraph {
+        granularity: options.granularity,
+        hosts,
+        edges,
+    }
+}
+
+/// Listening ports of a host, drawn as a single node with [`ListenerRendering::Summary`]
+struct ListenersSummary {
+    node_id: String,
+    label: String,
+    /// Label of the edges to each listening socket, such as `tcp:443`, by node id of the socket
+    ports_labels: HashMap<String, String>,
+}
+
+/// Protocol of a listening socket, as shown in the listeners summaries
+fn protocol_name(socket_type: &host::SocketType) -> &'static str {
+    match socket_type {
+        host::SocketType::TCP => "tcp",
+        host::SocketType::UDP => "udp",
+        host::SocketType::UNIX => "unix",
+    }
+}
+
+/// List the TCP and UDP ports of the hosts of the connections which listen on any
+fn summarize_listeners(connections: &[Connection]) -> HashMap<String, ListenersSummary> {
+    let mut summaries = HashMap::new();
+    let hosts = connections
+        .iter()
+        .flat_map(|c| [c.listening_host(), c.connected_host()]);
+    for host in hosts {
+        if summaries.contains_key(host.name()) {
+            continue;
+        }
+        let mut ports: Vec<(&str, u16)> = host
+            .listening_sockets()
+            .iter()
+            .filter(|s| *s.socket_type() != host::SocketType::UNIX)
+            .map(|s| (protocol_name(s.socket_type()), s.socket().port()))
+            .collect();
+        if ports.is_empty() {
+            continue;
+        }
+        ports.sort_unstable();
+        ports.dedup();
+        let label = ["tcp", "udp"]
+            .iter()
+            .filter_map(|protocol| {
+                let protocol_ports: Vec<String> = ports
+                    .iter()
+                    .filter(|(p, _)| p == protocol)
+                    .map(|(_, port)| port.to_string())
+                    .collect();
+                match protocol_ports.is_empty() {
+                    true => None,
+                    false => Some(format!("{protocol}: {}", protocol_ports.join(", "))),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" / ");
+        let ports_labels = host
+            .listening_sockets()
+            .iter()
+            .map(|s| {
+                let port_label =
+                    format!("{}:{}", protocol_name(s.socket_type()), s.socket().port());
+                (s.node_id().to_string(), port_label)
+            })
+            .collect();
+        let node_id = format!("{}_listeners", host.cluster_id());
+        summaries.insert(
+            host.name().to_string(),
+            ListenersSummary {
+                node_id,
+                label,
+                ports_labels,
+            },
+        );
+    }
+    summaries
+}
+
+/// Loopback connections of a host, drawn as a single node with [`LoopbackRendering::Collapse`]
+struct CollapsedLoopback {
+    node_id: String,
+    label: String,
+}
+
+/// Count the loopback connections of each host, and the processes they are between
+fn collapse_loopback(connections: &[Connection]) -> HashMap<String, CollapsedLoopback> {
+    let mut hosts_loopback: HashMap<&str, (&str, usize, HashSet<&str>)> = HashMap::new();
+    for connection in connections.iter().filter(|c| c.is_loopback()) {
+        let host = connection.listening_host();
+        let (_, connections_count, processes) = hosts_loopback
+            .entry(host.name())
+            .or_insert_with(|| (host.cluster_id(), 0, HashSet::new()));
+        *connections_count += 1;
+        processes.insert(connection.listening_connection().process().node_id());
+        processes.insert(connection.connected_connection().process().node_id());
+    }
+    hosts_loopback
+        .into_iter()
+        .map(|(name, (cluster_id, connections_count, processes))| {
+            let label = format!(
+                "{connections_count} local {} among {} {}",
+                match connections_count {
+                    1 => "connection",
+                    _ => "connections",
+                },
+                processes.len(),
+                match processes.len() {
+                    1 => "process",
+                    _ => "processes",
+                }
+            );
+            let node_id = format!("{cluster_id}_loopback");
+            (name.to_string(), CollapsedLoopback { node_id, label })
+        })
+        .collect()
+}
+
+/// Label of the edge of each connection going through proxies, following its port in summary mode,
+/// in the order of the connections
+fn via_labels(
+    connections: &[Connection],
+    listeners_summaries: &HashMap<String, ListenersSummary>,
+) -> Vec<Option<String>> {
+    connections
+        .iter()
+        .map(|connection| {
+            if connection.via().is_empty() {
+                return None;
+            }
+            let via = format!("via {}", connection.via().join(", "));
+            let port_label = listeners_summaries
+                .get(connection.listening_host().name())
+                .and_then(|summary| {
+                    summary
+                        .ports_labels
+                        .get(connection.listening_connection().node_id())
+                });
+            Some(match port_label {
+                Some(port_label) => format!("{port_label} {via}"),
+                None => via,
+            })
+        })
+        .collect()
+}
+
+/// Host of the graph while its nodes and edges are gathered, for [`GraphGranularity::Process`]
+struct HostBuilder {
+    host: MapHost,
+    listening_nodes: Vec<MapNode>,
+    /// Memberships of the listening processes
+    listening_edges: Vec<MapEdge>,
+    connected_nodes: Vec<MapNode>,
+    /// Memberships of the connected processes and their connections, in the order they are found
+    connected_edges: Vec<MapEdge>,
+    /// Node of the missing listeners, if connections of other hosts target some
+    unmatched_node: Option<MapNode>,
+}
+
+impl HostBuilder {
+    fn new(host: &host::Host, highlighted: bool) -> Self {
+        Self {
+            host: MapHost {
+                name: host.name().to_string(),
+                node_id: host.cluster_id().to_string(),
+                label: host.display_name().to_string(),
+                fill: host.display_color(),
+                highlighted,
+                nodes: Vec::new(),
+            },
+            listening_nodes: Vec::new(),
+            listening_edges: Vec::new(),
+            connected_nodes: Vec::new(),
+            connected_edges: Vec::new(),
+            unmatched_node: None,
+        }
+    }
+
+    /// Add a listening process, unless it has already been seen
+    fn add_listening_process(
+        &mut self,
+        listening_socket: &host::ListeningSocket,
+        highlighted: bool,
+    ) {
+        let node_id = listening_socket.node_id();
+        if self.listening_nodes.iter().any(|n| n.node_id == node_id) {
+            return;
+        }
+        self.listening_nodes.push(MapNode {
+            node_id: node_id.to_string(),
+            kind: NodeKind::ListeningProcess,
+            label: listening_socket.node_name().to_string(),
+            highlighted,
+        });
+        self.listening_edges
+            .push(membership(&self.host.node_id, node_id));
+    }
+
+    /// Add a connected process and its connection. The connection goes to the listening socket, or
+    /// to `summary`, the node id of the listeners summary of the listening host and the label of
+    /// the port, if it is set. The connections between the same processes and socket make a single
+    /// edge, weighted by their number.
+    fn add_connection(
+        &mut self,
+        connection: &Connection,
+        summary: Option<(&str, &str)>,
+        via_label: Option<&str>,
+        highlighted: bool,
+    ) {
+        let process = connection.connected_connection().process();
+        let connected_node_id = process.node_id();
+        let listening_socket = connection.listening_connection();
+        let listening_node_id = listening_socket.node_id();
+
+        let existing_edge = self
+            .connected_edges
+            .iter_mut()
+            .find_map(|edge| match &mut edge.kind {
+                EdgeKind::Connection(c)
+                    if edge.tail == connected_node_id
+                        && c.listening_node_id.as_deref() == Some(listening_node_id) =>
+                {
+                    Some(c)
+                }
+                _ => None,
+            });
+        if let Some(connection_edge) = existing_edge {
+            connection_edge.weight += 1;
+            return;
+        }
+
+        self.add_connected_process(process, highlighted);
+        let head = summary.map_or(listening_node_id, |(summary_node_id, _)| summary_node_id);
+        let label = via_label.or(summary.map(|(_, port_label)| port_label));
+        self.connected_edges.push(MapEdge {
+            tail: connected_node_id.to_string(),
+            head: head.to_string(),
+            kind: EdgeKind::Connection(ConnectionEdge {
+                label: label.map(str::to_string),
+                direction: connection.direction(),
+                proxied: via_label.is_some(),
+                weight: 1,
+                ports: BTreeSet::from([(
+                    protocol_name(listening_socket.socket_type()),
+                    listening_socket.port(),
+                )]),
+                listening_node_id: Some(listening_node_id.to_string()),
+            }),
+        });
+    }
+
+    /// Add a connected process, unless it has already been seen
+    fn add_connected_process(&mut self, process: &host::Process, highlighted: bool) {
+        let node_id = process.node_id();
+        if self.connected_nodes.iter().any(|n| n.node_id == node_id) {
+            return;
+        }
+        self.connected_nodes.push(MapNode {
+            node_id: node_id.to_string(),
+            kind: NodeKind::ConnectedProcess,
+            label: process.name().to_string(),
+            highlighted,
+        });
+        self.connected_edges
+            .push(membership(&self.host.node_id, node_id));
+    }
+
+    /// Add a connected process and its connection without a matching listener, to the node
+    /// `unmatched_node_id` of the missing listeners of the peer host. The connections of the same
+    /// process to the same port make a single edge, weighted by their number.
+    fn add_unmatched(
+        &mut self,
+        unmatched: &UnmatchedKnownPeer,
+        unmatched_node_id: &str,
+        highlighted: bool,
+    ) {
+        let connection = unmatched.connection();
+        let process = connection.process();
+        let port = (
+            protocol_name(connection.socket_type()),
+            connection.peer_socket().port(),
+        );
+
+        let existing_edge = self
+            .connected_edges
+            .iter_mut()
+            .find_map(|edge| match &mut edge.kind {
+                EdgeKind::Unmatched(c)
+                    if edge.tail == process.node_id()
+                        && edge.head == unmatched_node_id
+                        && c.ports.contains(&port) =>
+                {
+                    Some(c)
+                }
+                _ => None,
+            });
+        if let Some(unmatched_edge) = existing_edge {
+            unmatched_edge.weight += 1;
+            return;
+        }
+
+        self.add_connected_process(process, highlighted);
+        self.connected_edges.push(MapEdge {
+            tail: process.node_id().to_string(),
+            head: unmatched_node_id.to_string(),
+            kind: EdgeKind::Unmatched(ConnectionEdge {
+                label: Some(format!("{}:{}", port.0, port.1)),
+                direction: Direction::Out,
+                proxied: false,
+                weight: 1,
+                ports: BTreeSet::from([port]),
+                listening_node_id: None,
+            }),
+        });
+    }
+
+    /// Node id of the missing listeners of the host, adding their node the first time
+    fn unmatched_node_id(&mut self) -> String {
+        let node_id = format!("{}_unmatched", self.host.node_id);
+        self.unmatched_node.get_or_insert_with(|| MapNode {
+            node_id: node_id.clone(),
+            kind: NodeKind::UnmatchedPeer,
+            label: "?".to_string(),
+            highlighted: false,
+        });
+        node_id
+    }
+
+    /// The host with its nodes, and its edges, adding the nodes of its collapsed loopback
+    /// connections, of its listeners summary and of its missing listeners. If `deterministic`, the nodes are sorted by node
+    /// id and the edges by [`MapEdge::sort_key`].
+    fn build(
+        mut self,
+        collapsed_loopback: &HashMap<String, CollapsedLoopback>,
+        listeners_summaries: &HashMap<String, ListenersSummary>,
+        deterministic: bool,
+    ) -> (MapHost, Vec<MapEdge>) {
+        if deterministic {
+            for nodes in [&mut self.listening_nodes, &mut self.connected_nodes] {
+                nodes.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+            }
+            for edges in [&mut self.listening_edges, &mut self.connected_edges] {
+                edges.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+            }
+        }
+
+        let mut host = self.host;
+        host.nodes = self.listening_nodes;
+        host.nodes.extend(self.connected_nodes);
+        let mut edges = self.listening_edges;
+        edges.extend(self.connected_edges);
+        if let Some(collapsed) = collapsed_loopback.get(&host.name) {
+            host.nodes.push(MapNode {
+                node_id: collapsed.node_id.clone(),
+                kind: NodeKind::CollapsedLoopback,
+                label: collapsed.label.clone(),
+                highlighted: false,
+            });
+            edges.push(membership(&host.node_id, &collapsed.node_id));
+        }
+        if let Some(summary) = listeners_summaries.get(&host.name) {
+            host.nodes.push(MapNode {
+                node_id: summary.node_id.clone(),
+                kind: NodeKind::ListenersSummary,
+                label: summary.label.clone(),
+                highlighted: false,
+            });
+            edges.push(membership(&host.node_id, &summary.node_id));
+        }
+        if let Some(unmatched_node) = self.unmatched_node {
+            edges.push(membership(&host.node_id, &unmatched_node.node_id));
+            host.nodes.push(unmatched_node);
+        }
+        (host, edges)
+    }
+}
+
+/// Edge from a host to a node drawn in it
+fn membership(host_node_id: &str, node_id: &str) -> MapEdge {
+    MapEdge {
+        tail: host_node_id.to_string(),
+        head: node_id.to_string(),
+        kind: EdgeKind::Membership,
+    }
+}
+
+/// Hosts with their processes, and the edges between them, for [`GraphGranularity::Process`]. The
+/// `unmatched` connections go to the missing listeners of their peer host. If `deterministic`, the hosts are sorted by name, otherwise they come in the order they are found.
+fn process_graph(
+    connections: &[Connection],
+    unmatched: &[UnmatchedKnownPeer],
+    options: &MapGraphOptions,
+    is_highlighted: &dyn Fn(&str) -> bool,
+) -> (Vec<MapHost>, Vec<MapEdge>) {
+    let collapsed_loopback = match options.loopback_rendering {
+        LoopbackRendering::Collapse => collapse_loopback(connections),
+        _ => HashMap::new(),
+    };
+    let listeners_summaries = match options.listener_rendering {
+        ListenerRendering::Summary => summarize_listeners(connections),
+        ListenerRendering::PerProcess => HashMap::new(),
+    };
+    let via_labels = via_labels(connections, &listeners_summaries);
+
+    let mut builders: Vec<HostBuilder> = Vec::new();
+    let add_host = |builders: &mut Vec<HostBuilder>, host: &host::Host| {
+        if !builders.iter().any(|b| b.host.name == host.name()) {
+            builders.push(HostBuilder::new(host, is_highlighted(host.name())));
+        }
+    };
+    for (connection, via_label) in connections.iter().zip(&via_labels) {
+        let listening_host = connection.listening_host();
+        let connected_host = connection.connected_host();
+        let listening_socket = connection.listening_connection();
+
+        // Collapsed loopback connections are counted once per host
+        if connection.is_loopback() {
+            match options.loopback_rendering {
+                LoopbackRendering::Show => (),
+                LoopbackRendering::Hide => continue,
+                LoopbackRendering::Collapse => {
+                    add_host(&mut builders, listening_host);
+                    continue;
+                }
+            }
+        }
+        add_host(&mut builders, listening_host);
+        add_host(&mut builders, connected_host);
+
+        // The listening process is left out if the listening ports of its host are summarized
+        let summary = listeners_summaries
+            .get(listening_host.name())
+            .and_then(|summary| {
+                let port_label = summary.ports_labels.get(listening_socket.node_id())?;
+                Some((summary.node_id.as_str(), port_label.as_str()))
+            });
+        if summary.is_none() {
+            if let Some(builder) = builders
+                .iter_mut()
+                .find(|b| b.host.name == listening_host.name())
+            {
+                builder.add_listening_process(
+                    listening_socket,
+                    is_highlighted(listening_socket.process().name()),
+                );
+            }
+        }
+        if let Some(builder) = builders
+            .iter_mut()
+            .find(|b| b.host.name == connected_host.name())
+        {
+            builder.add_connection(
+                connection,
+                summary,
+                via_label.as_deref(),
+                is_highlighted(connection.connected_connection().process().name()),
+            );
+        }
+    }
+
+    for unmatched in unmatched {
+        let (connected_host, peer_host) = (unmatched.connected_host(), unmatched.peer_host());
+        add_host(&mut builders, connected_host);
+        add_host(&mut builders, peer_host);
+        let Some(unmatched_node_id) = builders
+            .iter_mut()
+            .find(|b| b.host.name == peer_host.name())
+            .map(HostBuilder::unmatched_node_id)
+        else {
+            continue;
+        };
+        if let Some(builder) = builders
+            .iter_mut()
+            .find(|b| b.host.name == connected_host.name())
+        {
+            builder.add_unmatched(
+                unmatched,
+                &unmatched_node_id,
+                is_highlighted(unmatched.connection().process().name()),
+            );
+        }
+    }
+
+    if options.deterministic {
+        builders.sort_by(|a, b| a.host.name.cmp(&b.host.name));
+    }
+    let mut hosts = Vec::new();
+    let mut edges = Vec::new();
+    for builder in builders {
+        let (host, host_edges) = builder.build(
+            &collapsed_loopback,
+            &listeners_summaries,
+            options.deterministic,
+        );
+        hosts.push(host);
+        edges.extend(host_edges);
+    }
+    (hosts, edges)
+}
+
+/// Connections from a host to another one, drawn as a single edge with [`GraphGranularity::Host`]
+struct HostEdge<'a> {
+    connected_host: &'a host::Host,
+    listening_host: &'a host::Host,
+    connections: usize,
+    /// Destination ports, as protocol and port
+    ports: BTreeSet<(&'static str, u16)>,
+    /// Proxies of the connections through one, which are drawn apart
+    via: BTreeSet<&'a str>,
+    /// Whether all the connections go both ways
+    bidirectional: bool,
+    /// Whether the connections have no matching listener
+    unmatched: bool,
+}
+
+impl HostEdge<'_> {
+    /// Number of connections and destination ports, such as `2 connections: tcp:22, tcp:443`, and
+    /// the proxies on the next line
+    fn label(&self) -> String {
+        let mut ports: Vec<String> = self
+            .ports
+            .iter()
+            .take(HOST_EDGE_MAX_PORTS)
+            .map(|(protocol, port)| format!("{protocol}:{port}"))
+            .collect();
+        if self.ports.len() > HOST_EDGE_MAX_PORTS {
+            ports.push(format!("+{} more", self.ports.len() - HOST_EDGE_MAX_PORTS));
+        }
+        let mut label = format!(
+            "{} {}{}: {}",
+            self.connections,
+            match self.connections {
+                1 => "connection",
+                _ => "connections",
+            },
+            match self.unmatched {
+                true => " without listener",
+                false => "",
+            },
+            ports.join(", ")
+        );
+        if !self.via.is_empty() {
+            let via: Vec<&str> = self.via.iter().copied().collect();
+            label.push_str(&format!("\nvia {}", via.join(", ")));
+        }
+        label
+    }
+}
+
+/// Hosts, sorted by name, and a single edge per pair of hosts, those through proxies apart, for
+/// [`GraphGranularity::Host`]. The loopback connections make an edge from their host to itself,
+/// unless they are hidden, and the `unmatched` connections an edge of their own to their peer host.
+fn host_graph(
+    connections: &[Connection],
+    unmatched: &[UnmatchedKnownPeer],
+    options: &MapGraphOptions,
+    is_highlighted: &dyn Fn(&str) -> bool,
+) -> (Vec<MapHost>, Vec<MapEdge>) {
+    let mut host_edges: BTreeMap<(&str, &str, bool), HostEdge> = BTreeMap::new();
+    for connection in connections {
+        if connection.is_loopback() && options.loopback_rendering == LoopbackRendering::Hide {
+            continue;
+        }
+        let connected_host = connection.connected_host();
+        let listening_host = connection.listening_host();
+        let proxied = !connection.via().is_empty();
+        let host_edge = host_edges
+            .entry((connected_host.name(), listening_host.name(), proxied))
+            .or_insert_with(|| HostEdge {
+                connected_host,
+                listening_host,
+                connections: 0,
+                ports: BTreeSet::new(),
+                via: BTreeSet::new(),
+                bidirectional: true,
+                unmatched: false,
+            });
+        host_edge.connections += 1;
+        host_edge.bidirectional &= connection.direction() == Direction::Both;
+        let listening_socket = connection.listening_connection();
+        host_edge.ports.insert((
+            protocol_name(listening_socket.socket_type()),
+            listening_socket.port(),
+        ));
+        host_edge
+            .via
+            .extend(connection.via().iter().map(String::as_str));
+    }
+    let mut unmatched_edges: BTreeMap<(&str, &str), HostEdge> = BTreeMap::new();
+    for unmatched in unmatched {
+        let connected_host = unmatched.connected_host();
+        let peer_host = unmatched.peer_host();
+        let host_edge = unmatched_edges
+            .entry((connected_host.name(), peer_host.name()))
+            .or_insert_with(|| HostEdge {
+                connected_host,
+                listening_host: peer_host,
+                connections: 0,
+                ports: BTreeSet::new(),
+                via: BTreeSet::new(),
+                bidirectional: false,
+                unmatched: true,
+            });
+        host_edge.connections += 1;
+        let connection = unmatched.connection();
+        host_edge.ports.insert((
+            protocol_name(connection.socket_type()),
+            connection.peer_socket().port(),
+        ));
+    }
+
+    let mut hosts: BTreeMap<&str, &host::Host> = BTreeMap::new();
+    for host_edge in host_edges.values().chain(unmatched_edges.values()) {
+        hosts.insert(host_edge.connected_host.name(), host_edge.connected_host);
+        hosts.insert(host_edge.listening_host.name(), host_edge.listening_host);
+    }
+    let hosts = hosts
+        .into_values()
+        .map(|host| MapHost {
+            name: host.name().to_string(),
+            node_id: host.cluster_id().to_string(),
+            label: host.display_name().to_string(),
+            fill: host.display_color(),
+            highlighted: is_highlighted(host.name()),
+            nodes: Vec::new(),
+        })
+        .collect();
+    let edges = host_edges
+        .into_values()
+        .chain(unmatched_edges.into_values())
+        .map(|host_edge| {
+            let connection_edge = ConnectionEdge {
+                label: Some(host_edge.label()),
+                direction: match host_edge.bidirectional {
+                    true => Direction::Both,
+                    false => Direction::Out,
+                },
+                proxied: !host_edge.via.is_empty(),
+                weight: host_edge.connections,
+                ports: host_edge.ports,
+                listening_node_id: None,
+            };
+            MapEdge {
+                tail: host_edge.connected_host.cluster_id().to_string(),
+                head: host_edge.listening_host.cluster_id().to_string(),
+                kind: match host_edge.unmatched {
+                    true => EdgeKind::Unmatched(connection_edge),
+                    false => EdgeKind::Connection(connection_edge),
+                },
+            }
+        })
+        .collect();
+    (hosts, edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections_model::{
+        build_connections_list, build_connections_report, tests::make_fake_connections,
+        ConnectionsOptions, DefaultMatcher,
+    };
+    use crate::test_utils;
+
+    /// The processes must be drawn in their host with an edge from it, and the connections between
+    /// the same processes be a single edge weighted by their number, down to the hosts
+    #[test]
+    fn test_build_map_graph() {
+        let mut hosts = make_fake_connections();
+        hosts[1].add_established_connection(host::Connection::new(
+            "10.0.0.2:5682".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+            host::SocketType::TCP,
+            host::Process::new("firefox", 202, "machine2".to_string()),
+        ));
+        let connections = build_connections_list(&hosts, false);
+
+        let map_graph = build_map_graph(&connections, &MapGraphOptions::default());
+        let names: Vec<&str> = map_graph.hosts.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["machine1", "machine2", "machine3"]);
+        let kinds: Vec<NodeKind> = map_graph.hosts[1].nodes.iter().map(|n| n.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                NodeKind::ListeningProcess,
+                NodeKind::ConnectedProcess,
+                NodeKind::ConnectedProcess
+            ]
+        );
+        let memberships = map_graph
+            .edges
+            .iter()
+            .filter(|e| e.kind == EdgeKind::Membership)
+            .count();
+        assert_eq!(memberships, 6);
+        let firefox_edge = map_graph
+            .edges
+            .iter()
+            .find_map(|e| match &e.kind {
+                EdgeKind::Connection(c) if e.tail == "machine2_firefox" => Some(c),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(firefox_edge.weight, 2);
+        assert_eq!(firefox_edge.ports, BTreeSet::from([("tcp", 443)]));
+
+        let options = MapGraphOptions {
+            granularity: GraphGranularity::Host,
+            ..Default::default()
+        };
+        let map_graph = build_map_graph(&connections, &options);
+        assert!(map_graph.hosts.iter().all(|h| h.nodes.is_empty()));
+        let EdgeKind::Connection(host_edge) = &map_graph.edges[0].kind else {
+            panic!("{:?}", map_graph.edges[0])
+        };
+        assert_eq!(map_graph.edges[0].head, "cluster_machine1");
+        assert_eq!(host_edge.weight, 3);
+        assert_eq!(host_edge.ports, BTreeSet::from([("tcp", 22), ("tcp", 443)]));
+    }
+
+    /// The connections without a matching listener must only be drawn if asked, to the missing
+    /// listeners of the peer host, or to the peer host itself when only the hosts are drawn
+    #[test]
+    fn test_unmatched_map_graph() {
+        let hosts = test_utils::missing_listener();
+        let report =
+            build_connections_report(&hosts, &ConnectionsOptions::default(), &DefaultMatcher);
+        assert_eq!(
+            build_report_map_graph(&report, &MapGraphOptions::default()),
+            build_map_graph(&report.connections, &MapGraphOptions::default())
+        );
+
+        let options = MapGraphOptions {
+            show_unmatched: true,
+            ..Default::default()
+        };
+        let map_graph = build_report_map_graph(&report, &options);
+        let db = map_graph.hosts.iter().find(|h| h.name == "db").unwrap();
+        let unmatched_node = db.nodes.last().unwrap();
+        assert_eq!(unmatched_node.kind, NodeKind::UnmatchedPeer);
+        assert_eq!(unmatched_node.label, "?");
+        let unmatched_edges: Vec<&MapEdge> = map_graph
+            .edges
+            .iter()
+            .filter(|e| matches!(e.kind, EdgeKind::Unmatched(_)))
+            .collect();
+        assert_eq!(unmatched_edges.len(), 1);
+        assert_eq!(unmatched_edges[0].head, unmatched_node.node_id);
+        assert_eq!(unmatched_edges[0].label(), Some("tcp:5432"));
+        let app = map_graph.hosts.iter().find(|h| h.name == "app").unwrap();
+        assert!(app
+            .nodes
+            .iter()
+            .any(|n| n.node_id == unmatched_edges[0].tail && n.kind == NodeKind::ConnectedProcess));
+
+        let options = MapGraphOptions {
+            granularity: GraphGranularity::Host,
+            show_unmatched: true,
+            ..Default::default()
+        };
+        let map_graph = build_report_map_graph(&report, &options);
+        let unmatched_edge = map_graph
+            .edges
+            .iter()
+            .find(|e| matches!(e.kind, EdgeKind::Unmatched(_)))
+            .unwrap();
+        assert_eq!(
+            unmatched_edge.label(),
+            Some("1 connection without listener: tcp:5432")
+        );
+    }
+
+    /// The edges of the hosts must list a few destination ports only, then count the others
+    #[test]
+    fn test_host_edge_label() {
+        let (client, server) = (host::Host::new("client"), host::Host::new("server"));
+        let mut host_edge = HostEdge {
+            connected_host: &client,
+            listening_host: &server,
+            connections: 8,
+            ports: (1..=8).map(|port| ("tcp", 8000 + port)).collect(),
+            via: BTreeSet::new(),
+            bidirectional: false,
+            unmatched: false,
+        };
+        assert_eq!(
+            host_edge.label(),
+            "8 connections: tcp:8001, tcp:8002, tcp:8003, tcp:8004, tcp:8005, +3 more"
+        );
+        host_edge.ports.retain(|(_, port)| *port == 8001);
+        host_edge.via.insert("stunnel");
+        assert_eq!(host_edge.label(), "8 connections: tcp:8001\nvia stunnel");
+        host_edge.via.clear();
+        host_edge.connections = 1;
+        host_edge.unmatched = true;
+        assert_eq!(host_edge.label(), "1 connection without listener: tcp:8001");
+    }
+}
@@ -1,10 +1,18 @@
 //! This module leverages the Graphviz utility to generate graphs.
 
-use anyhow::bail;
-use std::{io::Write, process::Command};
+use crate::provenance::Provenance;
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
 use tempfile;
+use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LayoutEngine {
     Dot,
     Neato,
@@ -43,6 +51,260 @@ impl std::fmt::Display for LayoutEngine {
     }
 }
 
+/// Size of the overview relatively to the graph, when not given
+pub const DEFAULT_OVERVIEW_SCALE: f64 = 0.15;
+/// Space between the overview inset and the edges of the graph, in points
+const OVERVIEW_MARGIN: f64 = 10.0;
+
+/// Corner of the graph where the overview inset is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverviewCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl std::str::FromStr for OverviewCorner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(OverviewCorner::TopLeft),
+            "top-right" => Ok(OverviewCorner::TopRight),
+            "bottom-left" => Ok(OverviewCorner::BottomLeft),
+            "bottom-right" => Ok(OverviewCorner::BottomRight),
+            _ => Err(format!(
+                "unknown corner {s} (top-left, top-right, bottom-left or bottom-right)"
+            )),
+        }
+    }
+}
+
+/// Downscaled copy of a large graph, to find one's way in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverviewOptions {
+    /// Size of the overview relatively to the graph, between 0 and 1
+    pub scale: f64,
+    /// Corner of the inset, for the SVG graphs
+    pub corner: OverviewCorner,
+}
+
+impl std::str::FromStr for OverviewOptions {
+    type Err = String;
+
+    /// Parse `<corner>[:<scale>]`, such as `bottom-right:0.15`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (corner, scale) = match s.split_once(':') {
+            Some((corner, scale)) => (
+                corner,
+                scale
+                    .parse::<f64>()
+                    .map_err(|e| format!("invalid overview scale {scale}: {e}"))?,
+            ),
+            None => (s, DEFAULT_OVERVIEW_SCALE),
+        };
+        if !(scale > 0.0 && scale < 1.0) {
+            return Err(format!(
+                "the overview scale must be between 0 and 1, not {scale}"
+            ));
+        }
+        Ok(OverviewOptions {
+            scale,
+            corner: corner.parse()?,
+        })
+    }
+}
+
+/// Path of the overview of a graph: `<name>_overview.<ext>`, next to it
+pub fn overview_path(output_file_path: &Path) -> PathBuf {
+    let mut file_name = output_file_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push("_overview");
+    if let Some(extension) = output_file_path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    output_file_path.with_file_name(file_name)
+}
+
+/// DOT code rendering the same graph with another DPI, to make the overview of a raster graph
+pub fn overview_dot_code(dot_code: &str, dpi: f64) -> String {
+    // The last value of a graph attribute wins, so it is set again at the end of the graph
+    match dot_code.rfind('}') {
+        Some(end) => format!(
+            "{}graph [dpi={dpi:.2}]\n{}",
+            &dot_code[..end],
+            &dot_code[end..]
+        ),
+        None => dot_code.to_string(),
+    }
+}
+
+/// Write the overview of the SVG graph at `output_file_path` to `overview_file_path`, and draw it
+/// as an inset in a corner of the graph
+pub fn write_svg_overview(
+    output_file_path: &Path,
+    overview_file_path: &Path,
+    overview: &OverviewOptions,
+) -> anyhow::Result<()> {
+    let svg = std::fs::read_to_string(output_file_path)?;
+    std::fs::write(overview_file_path, scaled_svg(&svg, overview.scale)?)?;
+    std::fs::write(output_file_path, svg_with_overview_inset(&svg, overview)?)?;
+    Ok(())
+}
+
+/// Get the root `svg` tag of an SVG document
+fn svg_tag(svg: &str) -> anyhow::Result<&str> {
+    let start = svg
+        .find("<svg")
+        .ok_or_else(|| anyhow!("no svg element in the graph"))?;
+    let end = svg[start..]
+        .find('>')
+        .ok_or_else(|| anyhow!("unterminated svg element in the graph"))?;
+    Ok(&svg[start..start + end + 1])
+}
+
+/// Same SVG document, with its width and height multiplied by `scale`
+fn scaled_svg(svg: &str, scale: f64) -> anyhow::Result<String> {
+    let tag = svg_tag(svg)?;
+    let size_regex = regex::Regex::new(r#"\b(width|height)="([0-9.]+)([a-z]*)""#)?;
+    let scaled_tag = size_regex.replace_all(tag, |captures: &regex::Captures| {
+        let value: f64 = captures[2].parse().unwrap_or_default();
+        format!("{}=\"{:.2}{}\"", &captures[1], value * scale, &captures[3])
+    });
+    Ok(svg.replacen(tag, &scaled_tag, 1))
+}
+
+/// Same SVG document, with a bordered copy of the graph drawn at `overview.scale` in a corner.
+/// The copy reuses the `graph0` group written by Graphviz.
+fn svg_with_overview_inset(svg: &str, overview: &OverviewOptions) -> anyhow::Result<String> {
+    let view_box_regex = regex::Regex::new(r#"viewBox="([^"]*)""#)?;
+    let view_box: Vec<f64> = view_box_regex
+        .captures(svg_tag(svg)?)
+        .ok_or_else(|| anyhow!("no viewBox in the graph"))?[1]
+        .split_whitespace()
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    let [x, y, width, height] = view_box[..] else {
+        bail!("invalid viewBox in the graph");
+    };
+    if !svg.contains(r#"id="graph0""#) {
+        bail!("no graph0 group in the graph");
+    }
+    let end = svg
+        .rfind("</svg>")
+        .ok_or_else(|| anyhow!("unterminated svg element in the graph"))?;
+
+    let scale = overview.scale;
+    let inset_x = match overview.corner {
+        OverviewCorner::TopLeft | OverviewCorner::BottomLeft => x + OVERVIEW_MARGIN,
+        OverviewCorner::TopRight | OverviewCorner::BottomRight => {
+            x + width - width * scale - OVERVIEW_MARGIN
+        }
+    };
+    let inset_y = match overview.corner {
+        OverviewCorner::TopLeft | OverviewCorner::TopRight => y + OVERVIEW_MARGIN,
+        OverviewCorner::BottomLeft | OverviewCorner::BottomRight => {
+            y + height - height * scale - OVERVIEW_MARGIN
+        }
+    };
+    let inset = format!(
+        "<g id=\"overview\" transform=\"translate({:.2} {:.2}) scale({scale:.4})\">\n\
+         <rect x=\"{x:.2}\" y=\"{y:.2}\" width=\"{width:.2}\" height=\"{height:.2}\" \
+         fill=\"white\" stroke=\"black\" stroke-width=\"{:.2}\"/>\n\
+         <use href=\"#graph0\" xlink:href=\"#graph0\"/>\n\
+         </g>\n",
+        inset_x - x * scale,
+        inset_y - y * scale,
+        1.0 / scale,
+    );
+    Ok(format!("{}{inset}{}", &svg[..end], &svg[end..]))
+}
+
+/// Embed `provenance` into the image at `path`, as text chunks of PNG images and as the metadata of
+/// SVG images. The images of the other formats are left as they are.
+pub fn embed_provenance(
+    path: &Path,
+    extension: &str,
+    provenance: &Provenance,
+) -> anyhow::Result<()> {
+    match extension {
+        "png" => {
+            let png = std::fs::read(path)?;
+            std::fs::write(path, png_with_text_chunks(&png, &provenance.entries())?)?;
+        }
+        "svg" => {
+            let svg = std::fs::read_to_string(path)?;
+            std::fs::write(path, svg_with_metadata(&svg, &provenance.entries())?)?;
+        }
+        _ => log::debug!("no provenance embedded in the {extension} image {path:?}"),
+    }
+    Ok(())
+}
+
+/// Signature starting the PNG images
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// End of the IHDR chunk, which must come first, in a PNG image
+const PNG_IHDR_END: usize = PNG_SIGNATURE.len() + 4 + 4 + 13 + 4;
+
+/// Same PNG image, with a tEXt chunk per entry after its header. The text of these chunks is
+/// Latin-1, so the other characters are replaced with `?`.
+fn png_with_text_chunks(png: &[u8], entries: &[(&str, String)]) -> anyhow::Result<Vec<u8>> {
+    if !png.starts_with(PNG_SIGNATURE) || png.get(12..16) != Some(b"IHDR".as_slice()) {
+        bail!("not a PNG image");
+    }
+    let mut output = png[..PNG_IHDR_END].to_vec();
+    for (keyword, text) in entries {
+        let mut chunk = b"tEXt".to_vec();
+        chunk.extend(keyword.bytes());
+        chunk.push(0);
+        chunk.extend(text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')));
+        output.extend((chunk.len() as u32 - 4).to_be_bytes());
+        output.extend(&chunk);
+        output.extend(crc32(&chunk).to_be_bytes());
+    }
+    output.extend(&png[PNG_IHDR_END..]);
+    Ok(output)
+}
+
+/// CRC-32 of a PNG chunk
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// Same SVG document, with the entries in a `metadata` element at the start of its root element
+fn svg_with_metadata(svg: &str, entries: &[(&str, String)]) -> anyhow::Result<String> {
+    let tag = svg_tag(svg)?;
+    let tag_end = svg.find(tag).unwrap_or_default() + tag.len();
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let lines: String = entries
+        .iter()
+        .map(|(key, value)| format!("{key}: {}\n", escape(value)))
+        .collect();
+    Ok(format!(
+        "{}\n<metadata id=\"provenance\">\n{lines}</metadata>{}",
+        &svg[..tag_end],
+        &svg[tag_end..]
+    ))
+}
+
 pub fn run_graphviz(
     dot_code: String,
     output_file_path: &std::path::Path,
@@ -51,6 +313,55 @@ pub fn run_graphviz(
     vertical: bool,
     layout_engine: Option<&LayoutEngine>,
 ) -> anyhow::Result<()> {
+    let (mut command, _dot_file) = graphviz_command(
+        &dot_code,
+        output_file_path,
+        &extension,
+        dump_dot_code,
+        vertical,
+        layout_engine,
+    )?;
+    check_graphviz_output(command.output())
+}
+
+/// Same as [`run_graphviz`], but Graphviz is killed if `cancel_token` is cancelled before the graph
+/// is rendered
+pub async fn run_graphviz_cancellable(
+    dot_code: String,
+    output_file_path: &std::path::Path,
+    extension: String,
+    dump_dot_code: Option<&std::path::PathBuf>,
+    vertical: bool,
+    layout_engine: Option<&LayoutEngine>,
+    cancel_token: &CancellationToken,
+) -> anyhow::Result<()> {
+    let (command, _dot_file) = graphviz_command(
+        &dot_code,
+        output_file_path,
+        &extension,
+        dump_dot_code,
+        vertical,
+        layout_engine,
+    )?;
+    let mut command = tokio::process::Command::from(command);
+    command.kill_on_drop(true);
+    tokio::select! {
+        biased;
+        _ = cancel_token.cancelled() => bail!("graph generation cancelled"),
+        output = command.output() => check_graphviz_output(output),
+    }
+}
+
+/// Write the DOT code to a temporary file, which must be kept until Graphviz has run, dump it if
+/// asked, and build the Graphviz command
+fn graphviz_command(
+    dot_code: &str,
+    output_file_path: &std::path::Path,
+    extension: &str,
+    dump_dot_code: Option<&std::path::PathBuf>,
+    vertical: bool,
+    layout_engine: Option<&LayoutEngine>,
+) -> anyhow::Result<(Command, tempfile::NamedTempFile)> {
     // Write dot code to file
     let mut dot_file = match tempfile::NamedTempFile::new() {
         Ok(d) => d,
@@ -97,7 +408,12 @@ pub fn run_graphviz(
     }
 
     log::debug!("Generating graph with Graphviz");
-    let output = Command::new("dot").args(args).output();
+    let mut command = Command::new("dot");
+    command.args(args);
+    Ok((command, dot_file))
+}
+
+fn check_graphviz_output(output: std::io::Result<std::process::Output>) -> anyhow::Result<()> {
     match output {
         Ok(o) => {
             if let Some(code) = o.status.code() {
@@ -110,3 +426,178 @@ pub fn run_graphviz(
         Err(e) => bail!(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cancelled generation must fail without waiting for Graphviz, or writing the output file
+    #[tokio::test]
+    async fn test_run_graphviz_cancelled() {
+        let directory = tempfile::tempdir().unwrap();
+        let output_file_path = directory.path().join("graph.png");
+        let cancel_token = CancellationToken::new();
+        cancel_token.cancel();
+        let res = run_graphviz_cancellable(
+            "digraph {}".into(),
+            &output_file_path,
+            "png".into(),
+            None,
+            false,
+            None,
+            &cancel_token,
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(!output_file_path.exists());
+    }
+
+    /// The provenance must be read back from the text chunks of a PNG image, whose chunks must
+    /// keep valid CRCs, and from the metadata of an SVG image
+    #[test]
+    fn test_embed_provenance() {
+        fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+            let kind_and_data = [kind, data].concat();
+            [
+                (data.len() as u32).to_be_bytes().as_slice(),
+                &kind_and_data,
+                &crc32(&kind_and_data).to_be_bytes(),
+            ]
+            .concat()
+        }
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+
+        let directory = tempfile::tempdir().unwrap();
+        let provenance = Provenance {
+            tool: "sockets_map 1.0.0".into(),
+            generated_at: "2024-03-01T10:00:00Z".into(),
+            inputs: vec!["captures (sha1 0123)".into()],
+            options: vec!["--loopback".into(), "hide".into()],
+        };
+        let png_path = directory.path().join("graph.png");
+        std::fs::write(
+            &png_path,
+            [
+                PNG_SIGNATURE,
+                &chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+                &chunk(b"IEND", &[]),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        embed_provenance(&png_path, "png", &provenance).unwrap();
+
+        let png = std::fs::read(&png_path).unwrap();
+        let mut text_chunks = Vec::new();
+        let mut kinds = Vec::new();
+        let mut offset = PNG_SIGNATURE.len();
+        while offset < png.len() {
+            let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+            let kind_and_data = &png[offset + 4..offset + 8 + length];
+            let crc = &png[offset + 8 + length..offset + 12 + length];
+            assert_eq!(crc, crc32(kind_and_data).to_be_bytes());
+            kinds.push(String::from_utf8_lossy(&kind_and_data[..4]).to_string());
+            if &kind_and_data[..4] == b"tEXt" {
+                let text = String::from_utf8_lossy(&kind_and_data[4..]).to_string();
+                let (keyword, text) = text.split_once('\0').unwrap();
+                text_chunks.push((keyword.to_string(), text.to_string()));
+            }
+            offset += 12 + length;
+        }
+        assert_eq!(kinds, ["IHDR", "tEXt", "tEXt", "tEXt", "tEXt", "IEND"]);
+        assert_eq!(
+            text_chunks,
+            provenance
+                .entries()
+                .into_iter()
+                .map(|(keyword, text)| (keyword.to_string(), text))
+                .collect::<Vec<_>>()
+        );
+
+        let svg_path = directory.path().join("graph.svg");
+        std::fs::write(
+            &svg_path,
+            "<?xml?>\n<svg width=\"10pt\">\n<g id=\"graph0\"/>\n</svg>\n",
+        )
+        .unwrap();
+        embed_provenance(&svg_path, "svg", &provenance).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&svg_path).unwrap(),
+            "<?xml?>\n<svg width=\"10pt\">\n<metadata id=\"provenance\">\n\
+             Software: sockets_map 1.0.0\nCreation Time: 2024-03-01T10:00:00Z\n\
+             Source: captures (sha1 0123)\nOptions: --loopback hide\n</metadata>\n\
+             <g id=\"graph0\"/>\n</svg>\n"
+        );
+    }
+
+    /// The overview options must default to the default scale, and refuse scales beyond the graph
+    #[test]
+    fn test_parse_overview_options() {
+        assert_eq!(
+            "bottom-right:0.2".parse::<OverviewOptions>(),
+            Ok(OverviewOptions {
+                scale: 0.2,
+                corner: OverviewCorner::BottomRight
+            })
+        );
+        assert_eq!(
+            "top-left".parse::<OverviewOptions>(),
+            Ok(OverviewOptions {
+                scale: DEFAULT_OVERVIEW_SCALE,
+                corner: OverviewCorner::TopLeft
+            })
+        );
+        assert!("top-left:1.5".parse::<OverviewOptions>().is_err());
+        assert!("middle:0.2".parse::<OverviewOptions>().is_err());
+        assert_eq!(
+            overview_path(Path::new("out/graph.png")),
+            Path::new("out/graph_overview.png")
+        );
+    }
+
+    /// The overview of an SVG graph must be scaled down, and be drawn in the corner of the graph
+    /// with its border
+    #[test]
+    fn test_svg_overview() {
+        let svg = "<?xml version=\"1.0\"?>\n\
+                   <svg width=\"200pt\" height=\"100pt\" viewBox=\"0.00 0.00 200.00 100.00\" \
+                   xmlns=\"http://www.w3.org/2000/svg\">\n\
+                   <g id=\"graph0\" class=\"graph\"><text>node</text></g>\n\
+                   </svg>\n";
+        let overview = OverviewOptions {
+            scale: 0.1,
+            corner: OverviewCorner::BottomRight,
+        };
+
+        let scaled = scaled_svg(svg, overview.scale).unwrap();
+        assert!(scaled.contains(
+            r#"<svg width="20.00pt" height="10.00pt" viewBox="0.00 0.00 200.00 100.00""#
+        ));
+
+        let with_inset = svg_with_overview_inset(svg, &overview).unwrap();
+        assert!(with_inset.starts_with(&svg[..svg.rfind("</svg>").unwrap()]));
+        assert!(with_inset.ends_with("</g>\n</svg>\n"));
+        // 200x100 graph, 20x10 inset, 10pt margin
+        assert!(with_inset.contains(r#"transform="translate(170.00 80.00) scale(0.1000)""#));
+        assert!(with_inset.contains(r#"<rect x="0.00" y="0.00" width="200.00" height="100.00""#));
+        assert!(with_inset.contains(r##"<use href="#graph0""##));
+
+        let top_left = OverviewOptions {
+            scale: 0.1,
+            corner: OverviewCorner::TopLeft,
+        };
+        assert!(svg_with_overview_inset(svg, &top_left)
+            .unwrap()
+            .contains("translate(10.00 10.00)"));
+        assert!(svg_with_overview_inset("<svg viewBox=\"0 0 1 1\"></svg>", &top_left).is_err());
+    }
+
+    /// The DPI of the overview must override the one of the graph
+    #[test]
+    fn test_overview_dot_code() {
+        assert_eq!(
+            overview_dot_code("digraph { graph [dpi=96] a -> b }", 14.4),
+            "digraph { graph [dpi=96] a -> b graph [dpi=14.40]\n}"
+        );
+    }
+}
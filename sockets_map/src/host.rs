@@ -3,7 +3,15 @@
 use hex;
 use serde::{Deserialize, Serialize};
 use sha1::Digest;
-use std::{net::IpAddr, vec};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    ops::RangeInclusive,
+    vec,
+};
+
+pub(crate) mod wire;
+pub use self::wire::HostDetails;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 /// A process that can be linked to some sockets
@@ -12,6 +20,10 @@ pub struct Process {
     name: String,
     /// The process ID
     pid: u32,
+    /// The process start time (in clock ticks since the boot on Linux), when it could be collected.
+    /// It tells apart the processes which got the PID of a dead one during a recording.
+    #[serde(default)]
+    start_time: Option<u64>,
     /// Its unique node id to be graphically represented
     node_id: String,
 }
@@ -21,10 +33,17 @@ impl Process {
         Self {
             name: name.to_string(),
             pid,
+            start_time: None,
             node_id: format!("{host_name}_{name}").replace(['.', '?', '-'], "_"),
         }
     }
 
+    /// Set the start time of the process
+    pub fn with_start_time(mut self, start_time: Option<u64>) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
     #[allow(dead_code)]
     /// Get a reference to the process's pid.
     pub fn pid(&self) -> &u32 {
@@ -40,6 +59,27 @@ impl Process {
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
+
+    /// Get the process's start time.
+    pub fn start_time(&self) -> Option<u64> {
+        self.start_time
+    }
+
+    /// Whether `other` is the same process, the start times being compared only when both are
+    /// known. If so, the start time of `other` is kept when this one has none.
+    fn absorb(&mut self, other: &Process) -> bool {
+        if self.name != other.name || self.pid != other.pid || self.node_id != other.node_id {
+            return false;
+        }
+        match (self.start_time, other.start_time) {
+            (Some(start_time), Some(other_start_time)) => start_time == other_start_time,
+            (None, other_start_time) => {
+                self.start_time = other_start_time;
+                true
+            }
+            (Some(_), None) => true,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq, PartialOrd, Ord)]
@@ -138,9 +178,9 @@ impl ListeningSocket {
         self.node_id.as_str()
     }
 
-    /// Returns true if this is a loopback address.
+    /// Returns true if this is a loopback address, including its IPv4-mapped IPv6 form.
     pub fn is_loopback(&self) -> bool {
-        self.socket.ip().is_loopback()
+        canonical_ip(self.socket.ip()).is_loopback()
     }
 
     /// Get a reference to the listening socket's process.
@@ -168,6 +208,16 @@ pub enum SocketType {
     UNIX,
 }
 
+impl std::fmt::Display for SocketType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketType::TCP => write!(f, "TCP"),
+            SocketType::UDP => write!(f, "UDP"),
+            SocketType::UNIX => write!(f, "UNIX"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 /// A connection between two sockets, and hence between two processes
 pub struct Connection {
@@ -215,6 +265,81 @@ impl Connection {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+/// A route of the host's routing table: a default gateway or a directly connected network
+pub struct Route {
+    /// Network address of the destination
+    destination: IpAddr,
+    /// Prefix length of the destination, 0 for a default route
+    prefix_len: u8,
+    /// Next hop, none for the directly connected networks
+    gateway: Option<IpAddr>,
+    /// Name, address or index of the interface, as given by the routing command
+    interface: Option<String>,
+}
+
+impl Route {
+    pub fn new(
+        destination: IpAddr,
+        prefix_len: u8,
+        gateway: Option<IpAddr>,
+        interface: Option<String>,
+    ) -> Self {
+        Self {
+            destination,
+            prefix_len,
+            gateway,
+            interface,
+        }
+    }
+
+    /// Get a reference to the route's destination.
+    pub fn destination(&self) -> IpAddr {
+        self.destination
+    }
+
+    /// Get a reference to the route's prefix length.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Get a reference to the route's gateway.
+    pub fn gateway(&self) -> Option<IpAddr> {
+        self.gateway
+    }
+
+    /// Get a reference to the route's interface.
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.as_deref()
+    }
+
+    /// Returns true if this is a default route.
+    pub fn is_default(&self) -> bool {
+        self.prefix_len == 0
+    }
+
+    /// Destination network in CIDR notation, such as `10.0.0.0/24`
+    pub fn network(&self) -> String {
+        format!("{}/{}", self.destination, self.prefix_len)
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.is_default() {
+            true => write!(f, "default")?,
+            false => write!(f, "{}", self.network())?,
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {gateway}")?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " dev {interface}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 /// A host that has processes and connections
 pub struct Host {
@@ -228,6 +353,16 @@ pub struct Host {
     connections: Vec<Connection>,
     /// IP addresses associated with the host
     ips: Vec<IpAddr>,
+    /// Default gateways and directly connected networks, when the routing table was captured
+    #[serde(default)]
+    routes: Vec<Route>,
+    /// Name drawn instead of the host name, see [`apply_display_overrides`]. It is only set for
+    /// the graph being drawn, and never sent by the agents.
+    #[serde(skip)]
+    display_name: Option<String>,
+    /// Fill color of the host cluster, as red, green and blue components
+    #[serde(skip)]
+    display_color: Option<[u8; 3]>,
 }
 
 impl Host {
@@ -238,6 +373,9 @@ impl Host {
             listening_sockets: Vec::new(),
             connections: Vec::new(),
             ips: vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+            routes: Vec::new(),
+            display_name: None,
+            display_color: None,
         }
     }
 
@@ -266,12 +404,29 @@ impl Host {
 
     pub fn add_ip(&mut self, ip: IpAddr) {
         log::debug!("add IP {} to {}", ip, self.name);
-        self.ips.push(ip);
-        // Also put the IP6 equivalent such as [::ffff:127.0.0.1]
-        if ip.is_ipv4() {
-            let ip_str = ip.to_string();
-            let ip6_addr: std::net::Ipv6Addr = format!("::ffff:{ip_str}").parse().unwrap();
-            self.ips.push(std::net::IpAddr::from(ip6_addr));
+        // Also put the IP6 equivalent such as [::ffff:127.0.0.1], for the listings of the host
+        let ip6_addr = match ip {
+            IpAddr::V4(ipv4) => Some(IpAddr::V6(ipv4.to_ipv6_mapped())),
+            IpAddr::V6(_) => None,
+        };
+        for ip in [Some(ip), ip6_addr].into_iter().flatten() {
+            if !self.ips.contains(&ip) {
+                self.ips.push(ip);
+            }
+        }
+    }
+
+    /// Whether the address is one of the host, an IPv4-mapped IPv6 address being the same as its
+    /// IPv4 address
+    pub fn has_ip(&self, ip: IpAddr) -> bool {
+        let ip = canonical_ip(ip);
+        self.ips.iter().any(|host_ip| canonical_ip(*host_ip) == ip)
+    }
+
+    pub fn add_route(&mut self, route: Route) {
+        log::debug!("add route {} to {}", route, self.name);
+        if !self.routes.contains(&route) {
+            self.routes.push(route);
         }
     }
 
@@ -303,14 +458,463 @@ impl Host {
         self.ips.as_slice()
     }
 
+    pub fn ips_mut(&mut self) -> &mut Vec<IpAddr> {
+        &mut self.ips
+    }
+
+    /// Get a reference to the host's routes.
+    pub fn routes(&self) -> &[Route] {
+        self.routes.as_slice()
+    }
+
+    /// Get the name drawn in the graphs, the host name unless it is overridden.
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Get the fill color of the host cluster, if it is overridden.
+    pub fn display_color(&self) -> Option<[u8; 3]> {
+        self.display_color
+    }
+
     /// Get a reference to the host's cluster id.
     pub fn cluster_id(&self) -> &str {
         self.cluster_id.as_str()
     }
 
-    /// Filter out the connections on process name matching
+    /// Merge the connections, listening sockets, IPs and routes of another host into this one.
+    /// Duplicates are removed, and connections and listening sockets are kept sorted.
+    /// The processes are identified by their PID and their start time, or by their PID alone when
+    /// the start time is missing.
+    pub fn merge(&mut self, other: &Host) {
+        self.connections.extend_from_slice(other.connections());
+        self.connections.sort();
+        self.connections.dedup_by(|next, kept| {
+            next.socket_type == kept.socket_type
+                && next.local_socket == kept.local_socket
+                && next.peer_socket == kept.peer_socket
+                && kept.process.absorb(&next.process)
+        });
+
+        self.listening_sockets
+            .extend_from_slice(other.listening_sockets());
+        self.listening_sockets.sort();
+        self.listening_sockets.dedup_by(|next, kept| {
+            next.socket == kept.socket
+                && next.socket_type == kept.socket_type
+                && next.ipv6_only == kept.ipv6_only
+                && kept.process.absorb(&next.process)
+        });
+
+        for ip in other.ips() {
+            if !self.ips.contains(ip) {
+                self.ips.push(*ip);
+            }
+        }
+
+        for route in other.routes() {
+            self.add_route(route.clone());
+        }
+    }
+
+    /// Get a copy of this host, keeping only the connections and listening sockets that are not in `previous`
+    pub fn delta(&self, previous: &Host) -> Host {
+        let previous_connections: BTreeSet<&Connection> = previous.connections.iter().collect();
+        let previous_listening_sockets: BTreeSet<&ListeningSocket> =
+            previous.listening_sockets.iter().collect();
+
+        let mut delta = self.clone();
+        delta
+            .connections
+            .retain(|c| !previous_connections.contains(c));
+        delta
+            .listening_sockets
+            .retain(|s| !previous_listening_sockets.contains(s));
+        delta
+    }
+
+    /// Filter out the connections and listening sockets of the processes whose name starts with
+    /// one of the patterns
     pub fn exclude_processes(&mut self, pattern: &[&str]) {
+        self.retain_processes(|process| !pattern.iter().any(|p| process.name.starts_with(p)));
+    }
+
+    /// Keep only the connections and listening sockets of the processes for which `keep` is true
+    pub fn retain_processes(&mut self, keep: impl Fn(&Process) -> bool) {
+        self.connections.retain(|c| keep(&c.process));
+        self.listening_sockets.retain(|s| keep(&s.process));
+    }
+
+    /// Filter out the listening sockets on one of the port ranges, and the connections with a
+    /// local or peer port in one of them
+    pub fn exclude_ports(&mut self, ports: &[RangeInclusive<u16>]) {
+        let excluded = |port: u16| ports.iter().any(|r| r.contains(&port));
         self.connections
-            .retain(|c| pattern.iter().any(|p| !c.process.name.starts_with(p)));
+            .retain(|c| !excluded(c.local_socket.port()) && !excluded(c.peer_socket.port()));
+        self.listening_sockets.retain(|s| !excluded(s.port()));
+    }
+
+    /// Keep only the listening sockets on one of the port ranges, and the connections with a local
+    /// or peer port in one of them
+    pub fn only_ports(&mut self, ports: &[RangeInclusive<u16>]) {
+        let included = |port: u16| ports.iter().any(|r| r.contains(&port));
+        self.connections
+            .retain(|c| included(c.local_socket.port()) || included(c.peer_socket.port()));
+        self.listening_sockets.retain(|s| included(s.port()));
+    }
+
+    /// Get a copy of this host under another name, with the node ids of its processes and listening
+    /// sockets made again for that name
+    pub fn renamed(&self, name: &str) -> Host {
+        let process = |p: &Process| Process::new(&p.name, p.pid, name.to_string());
+        let mut renamed = Host::new(name);
+        renamed.ips = self.ips.clone();
+        renamed.routes = self.routes.clone();
+        renamed.listening_sockets = self
+            .listening_sockets
+            .iter()
+            .map(|s| {
+                ListeningSocket::new(
+                    s.socket,
+                    s.socket_type.clone(),
+                    process(&s.process),
+                    name.to_string(),
+                    s.ipv6_only,
+                )
+            })
+            .collect();
+        renamed.connections = self
+            .connections
+            .iter()
+            .map(|c| {
+                Connection::new(
+                    c.local_socket,
+                    c.peer_socket,
+                    c.socket_type.clone(),
+                    process(&c.process),
+                )
+            })
+            .collect();
+        renamed
+    }
+
+    /// Whether `other` is the same machine: both have the same name, whatever its case, or share an
+    /// IP address other than the loopback ones
+    pub fn is_same_machine(&self, other: &Host) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+            || self
+                .ips
+                .iter()
+                .any(|ip| is_machine_ip(ip) && other.has_ip(*ip))
+    }
+}
+
+/// The IPv4 address of an IPv4-mapped IPv6 address such as `::ffff:10.0.0.1`, the address itself
+/// otherwise, so that both forms of an address compare equal
+pub(crate) fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Whether the IP address tells a machine apart, unlike the loopback and unspecified addresses
+/// (including their IPv4-mapped IPv6 forms) that every host has
+fn is_machine_ip(ip: &IpAddr) -> bool {
+    let ip = canonical_ip(*ip);
+    !ip.is_loopback() && !ip.is_unspecified()
+}
+
+/// Which data to keep for a machine found both in the capture files and in the agents updates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourcePolicy {
+    /// Keep the live data of the agent, leaving out the capture files
+    PreferAgent,
+    /// Keep the capture files, leaving out the data of the agent
+    PreferFiles,
+    /// Merge the sockets, IPs and routes of both, under the name of the agent host
+    #[default]
+    Merge,
+}
+
+/// Combine the hosts built from capture files with the hosts of the agents updates, so that a
+/// machine found in both is drawn once. The hosts of both sources are matched with
+/// [`Host::is_same_machine`], and combined according to `policy`.
+pub fn merge_host_sources(
+    static_hosts: Vec<Host>,
+    agent_hosts: Vec<Host>,
+    policy: SourcePolicy,
+) -> Vec<Host> {
+    let mut static_hosts: Vec<Option<Host>> = static_hosts.into_iter().map(Some).collect();
+    let mut agent_results = Vec::<Host>::new();
+    for agent_host in agent_hosts {
+        let matching: Vec<Host> = static_hosts
+            .iter_mut()
+            .filter(|h| matches!(h, Some(h) if h.is_same_machine(&agent_host)))
+            .filter_map(Option::take)
+            .collect();
+        if !matching.is_empty() {
+            let names: Vec<&str> = matching.iter().map(Host::name).collect();
+            log::info!(
+                "agent host {} is also found in the capture files as {}",
+                agent_host.name(),
+                names.join(", ")
+            );
+        }
+        match policy {
+            _ if matching.is_empty() => agent_results.push(agent_host),
+            SourcePolicy::PreferAgent => agent_results.push(agent_host),
+            SourcePolicy::PreferFiles => agent_results.extend(matching),
+            SourcePolicy::Merge => {
+                let mut merged = agent_host;
+                for static_host in matching {
+                    merged.merge(&static_host.renamed(merged.name()));
+                }
+                agent_results.push(merged);
+            }
+        }
+    }
+
+    static_hosts
+        .into_iter()
+        .flatten()
+        .chain(agent_results)
+        .collect()
+}
+
+/// Name and color of a host in the graphs, chosen by the user over the name of its captures
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayOverride {
+    /// Name drawn instead of the host name
+    pub name: Option<String>,
+    /// Fill color of the host cluster, as `#rrggbb`
+    pub color: Option<String>,
+}
+
+impl DisplayOverride {
+    /// Whether neither the name nor the color is overridden
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.color.is_none()
+    }
+}
+
+/// Parse a `#rrggbb` color into its red, green and blue components
+pub fn parse_hex_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let mut components = [0; 3];
+    for (i, component) in components.iter_mut().enumerate() {
+        *component = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(components)
+}
+
+/// Apply the names and colors of `overrides`, keyed by host name. The host names are kept, so that
+/// the connections, the filters and the highlight rules still match them. The empty names and the
+/// colors which are not `#rrggbb` are ignored.
+pub fn apply_display_overrides(hosts: &mut [Host], overrides: &BTreeMap<String, DisplayOverride>) {
+    for host in hosts {
+        let Some(display_override) = overrides.get(host.name()) else {
+            continue;
+        };
+        host.display_name = display_override
+            .name
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        host.display_color = display_override.color.as_deref().and_then(|color| {
+            let rgb = parse_hex_color(color);
+            if rgb.is_none() {
+                log::warn!("ignoring the invalid color {color:?} of host {}", host.name);
+            }
+            rgb
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::HostBuilder;
+
+    fn make_host() -> Host {
+        HostBuilder::new("machine1")
+            .listen_tcp(443, "nginx", 101)
+            .listen_tcp(22, "sshd", 102)
+            .listen_tcp(10050, "zabbix_agentd", 103)
+            .connect_tcp("10.0.0.1:443", "10.0.0.2:50000", "nginx", 101)
+            .connect_tcp("10.0.0.1:10050", "10.0.0.3:50001", "zabbix_agentd", 103)
+            .connect_tcp("10.0.0.1:50002", "10.0.0.3:10051", "zabbix_agentd", 103)
+            .connect_tcp("10.0.0.1:50003", "10.0.0.4:8080", "curl", 104)
+            .build()
+    }
+
+    /// Every process matching one of the prefixes must be removed
+    #[test]
+    fn test_exclude_processes() {
+        let mut host = make_host();
+        host.exclude_processes(&["zabbix", "cu"]);
+        let names: Vec<&str> = host
+            .connections()
+            .iter()
+            .map(|c| c.process().name())
+            .chain(host.listening_sockets().iter().map(|s| s.process().name()))
+            .collect();
+        assert_eq!(names, vec!["nginx", "nginx", "sshd"]);
+    }
+
+    /// Ports and port ranges apply to both ends of the connections
+    #[test]
+    fn test_exclude_ports() {
+        let mut host = make_host();
+        host.exclude_ports(&[22..=22, 10050..=10051]);
+        let listening_ports: Vec<u16> = host.listening_sockets().iter().map(|s| s.port()).collect();
+        assert_eq!(listening_ports, vec![443]);
+        let connections_processes: Vec<&str> = host
+            .connections()
+            .iter()
+            .map(|c| c.process().name())
+            .collect();
+        assert_eq!(connections_processes, vec!["nginx", "curl"]);
+    }
+
+    /// Only the sockets using one of the ports, on either end of the connections, must be kept
+    #[test]
+    fn test_only_ports() {
+        let mut host = make_host();
+        host.only_ports(&[443..=443, 8000..=8100]);
+        let listening_ports: Vec<u16> = host.listening_sockets().iter().map(|s| s.port()).collect();
+        assert_eq!(listening_ports, vec![443]);
+        let connections_processes: Vec<&str> = host
+            .connections()
+            .iter()
+            .map(|c| c.process().name())
+            .collect();
+        assert_eq!(connections_processes, vec!["nginx", "curl"]);
+    }
+
+    /// Static and agent hosts of the same machine, matched by their name or by an IP
+    fn make_sources() -> (Vec<Host>, Vec<Host>) {
+        let host = |name: &str, ip: &str| HostBuilder::new(name).ip(ip);
+        (
+            vec![
+                host("WEB1", "10.0.0.1")
+                    .listen_tcp(443, "nginx", 101)
+                    .build(),
+                host("database", "10.0.0.2").build(),
+                host("backup", "10.0.0.3").build(),
+            ],
+            vec![
+                host("web1", "10.0.0.1").listen_tcp(22, "sshd", 102).build(),
+                host("db-server", "10.0.0.2").build(),
+                host("monitoring", "10.0.0.4").build(),
+            ],
+        )
+    }
+
+    fn names(hosts: &[Host]) -> Vec<&str> {
+        hosts.iter().map(Host::name).collect()
+    }
+
+    /// The machines are matched by their case-insensitive name or by an IP, but not by a loopback
+    /// IP
+    #[test]
+    fn test_is_same_machine() {
+        let (static_hosts, agent_hosts) = make_sources();
+        assert!(static_hosts[0].is_same_machine(&agent_hosts[0]));
+        assert!(static_hosts[1].is_same_machine(&agent_hosts[1]));
+        assert!(!static_hosts[2].is_same_machine(&agent_hosts[2]));
+
+        let mut a = Host::new("a");
+        a.add_ip("127.0.0.1".parse().unwrap());
+        let mut b = Host::new("b");
+        b.add_ip("127.0.0.1".parse().unwrap());
+        assert!(a.ips().contains(&"::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!a.is_same_machine(&b));
+    }
+
+    /// The agent hosts replace the matching static hosts
+    #[test]
+    fn test_merge_host_sources_prefer_agent() {
+        let (static_hosts, agent_hosts) = make_sources();
+        let hosts = merge_host_sources(static_hosts, agent_hosts, SourcePolicy::PreferAgent);
+        assert_eq!(names(&hosts), ["backup", "web1", "db-server", "monitoring"]);
+        assert_eq!(hosts[1].listening_sockets().len(), 1);
+        assert_eq!(hosts[1].listening_sockets()[0].process().name(), "sshd");
+    }
+
+    /// The static hosts replace the matching agent hosts
+    #[test]
+    fn test_merge_host_sources_prefer_files() {
+        let (static_hosts, agent_hosts) = make_sources();
+        let hosts = merge_host_sources(static_hosts, agent_hosts, SourcePolicy::PreferFiles);
+        assert_eq!(names(&hosts), ["backup", "WEB1", "database", "monitoring"]);
+        assert_eq!(hosts[1].listening_sockets()[0].process().name(), "nginx");
+    }
+
+    /// The matching hosts are merged under the name of the agent host
+    #[test]
+    fn test_merge_host_sources_merge() {
+        let (static_hosts, agent_hosts) = make_sources();
+        let hosts = merge_host_sources(static_hosts, agent_hosts, SourcePolicy::Merge);
+        assert_eq!(names(&hosts), ["backup", "web1", "db-server", "monitoring"]);
+
+        let web = &hosts[1];
+        let mut processes: Vec<&str> = web
+            .listening_sockets()
+            .iter()
+            .map(|s| s.process().name())
+            .collect();
+        processes.sort_unstable();
+        assert_eq!(processes, ["nginx", "sshd"]);
+        // The sockets of the static host are drawn inside the merged host
+        assert!(web
+            .listening_sockets()
+            .iter()
+            .all(|s| s.process().node_id().starts_with("web1_")));
+        assert_eq!(
+            web.ips()
+                .iter()
+                .filter(|ip| **ip == "10.0.0.1".parse::<IpAddr>().unwrap())
+                .count(),
+            1
+        );
+    }
+
+    /// The overrides only change the drawn name and color, and the invalid ones are ignored
+    #[test]
+    fn test_apply_display_overrides() {
+        let mut hosts = vec![Host::new("ip-10-0-3-12"), Host::new("db"), Host::new("web")];
+        let overrides = BTreeMap::from([
+            (
+                "ip-10-0-3-12".to_string(),
+                DisplayOverride {
+                    name: Some("Payment API".to_string()),
+                    color: Some("#1a2B3c".to_string()),
+                },
+            ),
+            (
+                "db".to_string(),
+                DisplayOverride {
+                    name: Some("  ".to_string()),
+                    color: Some("blue".to_string()),
+                },
+            ),
+        ]);
+        apply_display_overrides(&mut hosts, &overrides);
+
+        assert_eq!(hosts[0].name(), "ip-10-0-3-12");
+        assert_eq!(hosts[0].display_name(), "Payment API");
+        assert_eq!(hosts[0].display_color(), Some([0x1a, 0x2b, 0x3c]));
+        assert_eq!(hosts[1].display_name(), "db");
+        assert_eq!(hosts[1].display_color(), None);
+        assert_eq!(hosts[2].display_name(), "web");
+
+        assert_eq!(parse_hex_color("#ffffff"), Some([255, 255, 255]));
+        for invalid in ["ffffff", "#fff", "#gggggg", "#ffffffff", "#ééé"] {
+            assert_eq!(parse_hex_color(invalid), None, "{invalid}");
+        }
     }
 }
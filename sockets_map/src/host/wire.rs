@@ -0,0 +1,257 @@
+//! Layout of the hosts in the updates sent by the agents, which must stay the one of the first
+//! versions so that the agents and servers of different versions understand each other. What the
+//! hosts gained since travels apart, as [`HostDetails`].
+
+use std::{borrow::Cow, net::IpAddr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Connection, Host, ListeningSocket, Process, Route, SocketType};
+
+#[derive(Serialize, Deserialize)]
+struct WireProcess<'a> {
+    name: Cow<'a, str>,
+    pid: u32,
+    node_id: Cow<'a, str>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireListeningSocket<'a> {
+    socket: std::net::SocketAddr,
+    socket_type: SocketType,
+    process: WireProcess<'a>,
+    node_name: Cow<'a, str>,
+    node_id: Cow<'a, str>,
+    ipv6_only: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireConnection<'a> {
+    socket_type: SocketType,
+    local_socket: std::net::SocketAddr,
+    peer_socket: std::net::SocketAddr,
+    process: WireProcess<'a>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireHost<'a> {
+    name: Cow<'a, str>,
+    cluster_id: Cow<'a, str>,
+    listening_sockets: Vec<WireListeningSocket<'a>>,
+    connections: Vec<WireConnection<'a>>,
+    ips: Cow<'a, [IpAddr]>,
+}
+
+impl<'a> From<&'a Process> for WireProcess<'a> {
+    fn from(process: &'a Process) -> Self {
+        Self {
+            name: Cow::Borrowed(&process.name),
+            pid: process.pid,
+            node_id: Cow::Borrowed(&process.node_id),
+        }
+    }
+}
+
+impl From<WireProcess<'_>> for Process {
+    fn from(process: WireProcess) -> Self {
+        Self {
+            name: process.name.into_owned(),
+            pid: process.pid,
+            start_time: None,
+            node_id: process.node_id.into_owned(),
+        }
+    }
+}
+
+/// Write the host as in the updates of the first versions, without its details
+pub(crate) fn serialize<S: Serializer>(host: &Host, serializer: S) -> Result<S::Ok, S::Error> {
+    WireHost {
+        name: Cow::Borrowed(&host.name),
+        cluster_id: Cow::Borrowed(&host.cluster_id),
+        listening_sockets: host
+            .listening_sockets
+            .iter()
+            .map(|s| WireListeningSocket {
+                socket: s.socket,
+                socket_type: s.socket_type.clone(),
+                process: (&s.process).into(),
+                node_name: Cow::Borrowed(&s.node_name),
+                node_id: Cow::Borrowed(&s.node_id),
+                ipv6_only: s.ipv6_only,
+            })
+            .collect(),
+        connections: host
+            .connections
+            .iter()
+            .map(|c| WireConnection {
+                socket_type: c.socket_type.clone(),
+                local_socket: c.local_socket,
+                peer_socket: c.peer_socket,
+                process: (&c.process).into(),
+            })
+            .collect(),
+        ips: Cow::Borrowed(&host.ips),
+    }
+    .serialize(serializer)
+}
+
+/// Read a host written by [`serialize`], whose details are to be applied with
+/// [`Host::apply_details`]
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Host, D::Error> {
+    let wire_host = WireHost::deserialize(deserializer)?;
+    let mut host = Host::new(&wire_host.name);
+    host.cluster_id = wire_host.cluster_id.into_owned();
+    host.ips = wire_host.ips.into_owned();
+    host.listening_sockets = wire_host
+        .listening_sockets
+        .into_iter()
+        .map(|s| ListeningSocket {
+            socket: s.socket,
+            socket_type: s.socket_type,
+            process: s.process.into(),
+            node_name: s.node_name.into_owned(),
+            node_id: s.node_id.into_owned(),
+            ipv6_only: s.ipv6_only,
+        })
+        .collect();
+    host.connections = wire_host
+        .connections
+        .into_iter()
+        .map(|c| Connection {
+            socket_type: c.socket_type,
+            local_socket: c.local_socket,
+            peer_socket: c.peer_socket,
+            process: c.process.into(),
+        })
+        .collect();
+    Ok(host)
+}
+
+/// Data of a host left out of the updates, sent before them to the servers supporting
+/// [`crate::server::message::Capability::UpdateDetails`]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HostDetails {
+    /// Start time of the process of each listening socket, then of each connection, in the order
+    /// of the host. Empty if none of them is known.
+    pub start_times: Vec<Option<u64>>,
+    /// Routing table of the host
+    pub routes: Vec<Route>,
+}
+
+impl Host {
+    /// Get the details of the host which are not sent in its updates, or `None` if it has none.
+    pub fn details(&self) -> Option<HostDetails> {
+        let mut start_times: Vec<Option<u64>> = self.processes().map(Process::start_time).collect();
+        if start_times.iter().all(Option::is_none) {
+            start_times.clear();
+        }
+        if start_times.is_empty() && self.routes.is_empty() {
+            return None;
+        }
+        Some(HostDetails {
+            start_times,
+            routes: self.routes.clone(),
+        })
+    }
+
+    /// Set the details of the host, which must have been sent along with it. The start times are
+    /// left out if they do not match its processes.
+    pub fn apply_details(&mut self, details: HostDetails) {
+        self.routes = details.routes;
+        if details.start_times.is_empty() {
+            return;
+        }
+        if details.start_times.len() != self.processes().count() {
+            log::warn!(
+                "{} start times sent for the {} processes of {}, they are left out",
+                details.start_times.len(),
+                self.processes().count(),
+                self.name
+            );
+            return;
+        }
+        let processes = self
+            .listening_sockets
+            .iter_mut()
+            .map(|s| &mut s.process)
+            .chain(self.connections.iter_mut().map(|c| &mut c.process));
+        for (process, start_time) in processes.zip(details.start_times) {
+            process.start_time = start_time;
+        }
+    }
+
+    /// Processes of the listening sockets, then of the connections
+    fn processes(&self) -> impl Iterator<Item = &Process> {
+        self.listening_sockets
+            .iter()
+            .map(|s| &s.process)
+            .chain(self.connections.iter().map(|c| &c.process))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A host written without its details must be read back the same once they are applied
+    #[test]
+    fn test_host_details_round_trip() {
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper(#[serde(with = "super")] Host);
+
+        let mut host = Host::new("web-01");
+        host.add_ip("10.0.0.1".parse().unwrap());
+        let sshd = Process::new("sshd", 901, "web-01".to_string()).with_start_time(Some(1200));
+        host.add_listening_socket(ListeningSocket::new(
+            "0.0.0.0:22".parse().unwrap(),
+            SocketType::TCP,
+            sshd.clone(),
+            "web-01".to_string(),
+            None,
+        ));
+        host.add_established_connection(Connection::new(
+            "10.0.0.1:22".parse().unwrap(),
+            "10.0.0.2:51234".parse().unwrap(),
+            SocketType::TCP,
+            sshd,
+        ));
+        host.add_established_connection(Connection::new(
+            "10.0.0.1:40000".parse().unwrap(),
+            "10.0.0.3:443".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("curl", 2000, "web-01".to_string()),
+        ));
+        host.add_route(Route::new(
+            "0.0.0.0".parse().unwrap(),
+            0,
+            Some("10.0.0.254".parse().unwrap()),
+            Some("eth0".to_string()),
+        ));
+
+        let bytes = bincode::serialize(&Wrapper(host.clone())).unwrap();
+        let Wrapper(mut received) = bincode::deserialize(&bytes).unwrap();
+        assert_ne!(received, host);
+        let details = host.details().unwrap();
+        assert_eq!(details.start_times, [Some(1200), Some(1200), None]);
+        assert_eq!(details.routes, host.routes());
+        received.apply_details(details);
+        assert_eq!(received, host);
+
+        // Start times which do not match the processes are left out
+        let mut other = Host::new("other");
+        other.apply_details(host.details().unwrap());
+        assert_eq!(other.routes(), host.routes());
+        assert!(other.listening_sockets().is_empty());
+
+        // Routes are sent without any start time
+        let mut routes_only = Host::new("routes-only");
+        routes_only.add_route(host.routes()[0].clone());
+        let details = routes_only.details().unwrap();
+        assert!(details.start_times.is_empty());
+        let mut received = Host::new("routes-only");
+        received.apply_details(details);
+        assert_eq!(received, routes_only);
+        assert_eq!(Host::new("empty").details(), None);
+    }
+}
@@ -1,3 +1,24 @@
+//! Map the network interactions between the processes of a group of machines, from the sockets
+//! listed by native tools on each of them.
+//!
+//! The [`prelude`] goes from a folder of capture files to a PNG image of the graph, drawn by
+//! Graphviz:
+//!
+//! ```no_run
+//! use sockets_map::prelude::*;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let hosts = build_hosts(&scan_dir("captures".as_ref())?)?;
+//! let connections = build_connections_list(&hosts, false);
+//! let dot_code = create_graph(&connections, &DrawOptions::default())?;
+//! run_graphviz(dot_code, "graph.png".as_ref(), "png".into(), None, false, None)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod anonymize;
+#[cfg(all(feature = "collect", any(target_os = "linux", target_os = "windows")))]
+pub mod collect;
 pub mod connections_model;
 pub mod csv;
 pub mod graphs;
@@ -5,4 +26,12 @@ pub mod graphviz;
 pub mod help;
 pub mod host;
 pub mod parsers;
+pub mod prelude;
+pub mod provenance;
+#[cfg(feature = "ssh")]
+pub mod remote;
 pub mod server;
+pub mod summary;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod test_utils;
+pub mod timeline;
@@ -1,8 +1,238 @@
 //! This modules aggregates the parsers to retrieve hostm sockets and processes information from
 //! commands output retrieved from target machines
+//!
+//! The entry points are:
+//!
+//! - [`directory_scanner::scan_dir`] and [`directory_scanner::build_hosts`], for a folder of
+//!   capture files of any kind;
+//! - [`bundle::read_bundle`] and [`bundle::HostCapture`], for the bundle of a single host;
+//! - [`linux::LinuxHostRawData`], [`windows::WindowsHostRawData`] and [`HostData`], for the outputs
+//!   of the commands of a single host;
+//! - [`detect_and_parse`], for the output of a single network command;
+//! - [`Host::from_csv_files`] and [`Host::from_nmap_output_file`], whose parsers are private.
 
+pub mod bundle;
 mod csv;
+pub mod diagnostics;
 pub mod directory_scanner;
+pub mod dns_map;
+pub mod hosts_cache;
 pub mod linux;
+#[cfg(feature = "native")]
+pub mod native;
 mod nmap;
 pub mod windows;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::{io::Write, net::IpAddr, path::Path};
+
+use crate::host::Host;
+
+/// Commands outputs collected on a host, by an agent or through SSH, from which the host is built
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum HostData {
+    LinuxHostData(linux::LinuxHostRawData),
+    WindowsHostData(windows::WindowsHostRawData),
+}
+
+impl From<HostData> for anyhow::Result<Host> {
+    fn from(host_data: HostData) -> Self {
+        match host_data {
+            HostData::LinuxHostData(h) => h.into(),
+            HostData::WindowsHostData(h) => h.into(),
+        }
+    }
+}
+
+impl HostData {
+    /// Append the commands outputs to capture files in `output_dir`, as the agent offline mode does
+    pub fn write_capture_files(&self, output_dir: &Path) -> anyhow::Result<()> {
+        match self {
+            HostData::LinuxHostData(h) => h.write_capture_files(output_dir),
+            HostData::WindowsHostData(h) => h.write_capture_files(output_dir),
+        }
+    }
+
+    /// Add the commands outputs to the bundle of the host in `output_dir`, as the agent offline
+    /// mode does
+    pub fn write_bundle(&self, output_dir: &Path) -> anyhow::Result<()> {
+        bundle::write_bundle(output_dir, &self.clone().into())?;
+        Ok(())
+    }
+}
+
+/// Command whose output is recognized by [`detect_network_output`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkCommand {
+    /// `ss -apn` on Linux
+    LinuxSs,
+    /// `netstat -apn` on Linux
+    LinuxNetstat,
+    /// `netstat -ano` on Windows
+    WindowsNetstat,
+}
+
+/// Append `contents` to the file at `path`, creating it if needed
+pub(crate) fn append_to_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("unable to open file {path:?}"))?;
+    writeln!(file, "{contents}").with_context(|| format!("unable to write to file {path:?}"))
+}
+
+/// Parse a network in CIDR notation, such as `10.0.0.0/24`. An address without prefix length is a
+/// single host network.
+pub(crate) fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix_len) = match network.split_once('/') {
+        Some((address, prefix_len)) => (address.parse().ok()?, prefix_len.parse().ok()?),
+        None => {
+            let address: IpAddr = network.parse().ok()?;
+            let prefix_len = match address {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            };
+            (address, prefix_len)
+        }
+    };
+    let max_prefix_len = match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    (prefix_len <= max_prefix_len).then_some((address, prefix_len))
+}
+
+/// Recognize the command which gave `contents` from its header or its first socket line. The
+/// socket lines start with `TCP` or `UDP` on Windows, and with `tcp` or `udp` on Linux, followed
+/// by the state with ss and by the receive queue with netstat.
+pub fn detect_network_output(contents: &str) -> Option<NetworkCommand> {
+    for line in contents.lines() {
+        let mut columns = line.split_whitespace();
+        let first = columns.next().unwrap_or_default();
+        let second = columns.next().unwrap_or_default();
+        match first {
+            "Netid" => return Some(NetworkCommand::LinuxSs),
+            "Proto" if second == "Recv-Q" => return Some(NetworkCommand::LinuxNetstat),
+            "Proto" if second == "Local" => return Some(NetworkCommand::WindowsNetstat),
+            "TCP" | "UDP" => return Some(NetworkCommand::WindowsNetstat),
+            _ if first.starts_with("tcp") || first.starts_with("udp") => {
+                return match second.parse::<u64>() {
+                    Ok(_) => Some(NetworkCommand::LinuxNetstat),
+                    Err(_) => Some(NetworkCommand::LinuxSs),
+                };
+            }
+            _ => continue,
+        }
+    }
+    None
+}
+
+/// Build a host from the output of a single network command, recognized with
+/// [`detect_network_output`]. The Windows processes are named after their PID, unless the output
+/// of `tasklist /FO CSV` is appended to the netstat one.
+pub fn detect_and_parse(contents: &str, hostname: &str, ips: Vec<IpAddr>) -> anyhow::Result<Host> {
+    let network_output = match detect_network_output(contents) {
+        Some(NetworkCommand::LinuxSs) => linux::NetworkOutput::Ss(contents.to_string()),
+        Some(NetworkCommand::LinuxNetstat) => linux::NetworkOutput::Netstat(contents.to_string()),
+        Some(NetworkCommand::WindowsNetstat) => {
+            return Ok(windows::parse_standalone_netstat_output(
+                hostname, contents, ips,
+            ))
+        }
+        None => bail!("unable to recognize the output of ss, netstat or the Windows netstat"),
+    };
+    linux::LinuxHostRawData::new(hostname.to_string(), network_output, ips, None).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SS_OUTPUT: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:50000    users:((\"sshd\",pid=200,fd=4))";
+
+    const LINUX_NETSTAT_OUTPUT: &str = "\
+Active Internet connections (servers and established)
+Proto Recv-Q Send-Q Local Address           Foreign Address         State       PID/Program name
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN      100/sshd
+tcp        0      0 10.0.0.1:22             10.0.0.2:50000          ESTABLISHED 200/sshd";
+
+    const WINDOWS_NETSTAT_OUTPUT: &str = "\
+\r
+Active Connections\r
+\r
+  Proto  Local Address          Foreign Address        State           PID\r
+  TCP    0.0.0.0:3389           0.0.0.0:0              LISTENING       1044\r
+  TCP    10.0.0.1:3389          10.0.0.2:50000         ESTABLISHED     1044\r
+";
+
+    /// Each output must be recognized, with or without its header
+    #[test]
+    fn test_detect_network_output() {
+        assert_eq!(
+            detect_network_output(SS_OUTPUT),
+            Some(NetworkCommand::LinuxSs)
+        );
+        assert_eq!(
+            detect_network_output(LINUX_NETSTAT_OUTPUT),
+            Some(NetworkCommand::LinuxNetstat)
+        );
+        assert_eq!(
+            detect_network_output(WINDOWS_NETSTAT_OUTPUT),
+            Some(NetworkCommand::WindowsNetstat)
+        );
+        for (output, command) in [
+            (SS_OUTPUT, NetworkCommand::LinuxSs),
+            (LINUX_NETSTAT_OUTPUT, NetworkCommand::LinuxNetstat),
+        ] {
+            let without_header: String = output
+                .lines()
+                .filter(|line| line.starts_with("tcp"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert_eq!(detect_network_output(&without_header), Some(command));
+        }
+        assert_eq!(detect_network_output(""), None);
+        assert_eq!(detect_network_output("inet 10.0.0.1/24"), None);
+    }
+
+    /// The host must be built with the sockets of each output
+    #[test]
+    fn test_detect_and_parse() {
+        let ips = vec!["10.0.0.1".parse().unwrap()];
+        for output in [SS_OUTPUT, LINUX_NETSTAT_OUTPUT] {
+            let host = detect_and_parse(output, "machine1", ips.clone()).unwrap();
+            assert_eq!(host.name(), "machine1");
+            assert!(host.ips().contains(&ips[0]));
+            assert!(host
+                .listening_sockets()
+                .iter()
+                .any(|s| s.port() == 22 && s.process().name() == "sshd"));
+            assert!(host
+                .connections()
+                .iter()
+                .any(|c| c.peer_socket() == &"10.0.0.2:50000".parse().unwrap()));
+        }
+
+        // The Windows processes are named after the tasklist lines, or after their PID
+        let host = detect_and_parse(WINDOWS_NETSTAT_OUTPUT, "machine2", ips.clone()).unwrap();
+        assert!(host
+            .listening_sockets()
+            .iter()
+            .any(|s| s.port() == 3389 && s.process().name() == "PID 1044"));
+        let with_tasklist = format!(
+            "{WINDOWS_NETSTAT_OUTPUT}\"Image Name\",\"PID\"\r\n\"svchost.exe\",\"1044\"\r\n"
+        );
+        let host = detect_and_parse(&with_tasklist, "machine2", ips).unwrap();
+        assert!(host
+            .connections()
+            .iter()
+            .any(|c| c.process().name() == "svchost.exe"));
+
+        assert!(detect_and_parse("", "machine3", Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! This module reads and writes the capture bundles: a single JSON file per host, named
+//! `<hostname>.smap.json`, holding the raw outputs of the capture commands of one or more samples,
+//! or the sockets of the host when they were collected without commands.
+//!
+//! The bundles are versioned. The fields unknown to this version are ignored, so that the bundles
+//! written by newer agents can still be read.
+
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    diagnostics::ParseDiagnostics,
+    linux::{LinuxHostRawData, NetworkOutput},
+    windows::WindowsHostRawData,
+    HostData,
+};
+use crate::host::Host;
+
+/// Extension of the bundle files, after the name of their host
+pub const BUNDLE_EXTENSION: &str = "smap.json";
+/// Version of the bundles written by this version
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Output of `ss -apn` on Linux
+pub const SS: &str = "ss";
+/// Output of `netstat -apn` on Linux
+pub const LINUX_NETSTAT: &str = "linux_netstat";
+/// Output of `ip route` and `ip -6 route` on Linux
+pub const LINUX_ROUTE: &str = "linux_route";
+/// Output of `netstat -ano` on Windows
+pub const WINDOWS_NETSTAT: &str = "windows_netstat";
+/// Output of `tasklist /FO CSV` on Windows
+pub const WINDOWS_TASKLIST: &str = "windows_tasklist";
+/// Output of `route print` on Windows
+pub const WINDOWS_ROUTE: &str = "windows_route";
+
+/// Outputs of the successive samples appended to each other, the other ones being replaced by the
+/// latest sample
+const APPENDED_OUTPUTS: [&str; 4] = [SS, LINUX_NETSTAT, WINDOWS_NETSTAT, WINDOWS_TASKLIST];
+
+/// OS of a captured host, which tells how its outputs are parsed
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureOs {
+    Linux,
+    Windows,
+}
+
+/// Contents of a bundle file
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct HostCapture {
+    /// Version of the schema, [`BUNDLE_VERSION`] for the bundles written by this version
+    pub version: u32,
+    pub hostname: String,
+    pub os: CaptureOs,
+    /// Time of each sample of the bundle
+    #[serde(default)]
+    pub timestamps: Vec<DateTime<Utc>>,
+    /// IP addresses of the host, in its latest sample
+    #[serde(default)]
+    pub ips: Vec<IpAddr>,
+    /// Raw outputs of the commands, keyed by kind, such as [`SS`] or [`WINDOWS_NETSTAT`]. The kinds
+    /// are the extensions of the matching legacy capture files.
+    #[serde(default)]
+    pub outputs: BTreeMap<String, String>,
+    /// Sockets of the host, for the hosts collected without commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<Host>,
+}
+
+impl HostCapture {
+    /// Empty bundle of the host, sampled now
+    pub fn new(hostname: &str, os: CaptureOs) -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            hostname: hostname.to_string(),
+            os,
+            timestamps: vec![Utc::now()],
+            ips: Vec::new(),
+            outputs: BTreeMap::new(),
+            host: None,
+        }
+    }
+
+    /// Bundle of a host whose sockets were collected without commands, sampled now
+    pub fn from_host(host: Host, os: CaptureOs) -> Self {
+        Self {
+            ips: host.ips().to_vec(),
+            host: Some(host.clone()),
+            ..Self::new(host.name(), os)
+        }
+    }
+
+    /// Add the samples of `other` to the bundle: the network outputs and the sockets are
+    /// aggregated, while the IP addresses and the routing table are the latest ones
+    pub fn append(&mut self, other: HostCapture) {
+        self.version = self.version.max(other.version);
+        self.timestamps.extend(other.timestamps);
+        if !other.ips.is_empty() {
+            self.ips = other.ips;
+        }
+        for (kind, output) in other.outputs {
+            match self.outputs.get_mut(&kind) {
+                Some(existing) if APPENDED_OUTPUTS.contains(&kind.as_str()) => {
+                    if !existing.is_empty() && !existing.ends_with('\n') {
+                        existing.push('\n');
+                    }
+                    existing.push_str(&output);
+                }
+                _ => {
+                    self.outputs.insert(kind, output);
+                }
+            }
+        }
+        match (&mut self.host, other.host) {
+            (Some(host), Some(other_host)) => host.merge(&other_host),
+            (host @ None, other_host) => *host = other_host,
+            (Some(_), None) => (),
+        }
+    }
+
+    /// Parse the outputs into the host, along with the problems met on their lines. The sockets
+    /// of the bundle, if any, are merged into the host.
+    pub fn parse_host(self) -> anyhow::Result<(Host, ParseDiagnostics)> {
+        let mut outputs = self.outputs;
+        let host_data = match self.os {
+            CaptureOs::Linux => {
+                let network_output = match (outputs.remove(SS), outputs.remove(LINUX_NETSTAT)) {
+                    (Some(output), _) => Some(NetworkOutput::Ss(output)),
+                    (None, Some(output)) => Some(NetworkOutput::Netstat(output)),
+                    (None, None) => None,
+                };
+                network_output.map(|network_output| {
+                    HostData::LinuxHostData(LinuxHostRawData::new(
+                        self.hostname.clone(),
+                        network_output,
+                        self.ips.clone(),
+                        outputs.remove(LINUX_ROUTE),
+                    ))
+                })
+            }
+            // The output of `netstat -anob` names the processes without the tasklist
+            CaptureOs::Windows => outputs.remove(WINDOWS_NETSTAT).map(|network_output| {
+                HostData::WindowsHostData(WindowsHostRawData::new(
+                    self.hostname.clone(),
+                    network_output,
+                    outputs.remove(WINDOWS_TASKLIST).unwrap_or_default(),
+                    self.ips.clone(),
+                    outputs.remove(WINDOWS_ROUTE),
+                ))
+            }),
+        };
+
+        let sockets = self.host.map(|h| h.renamed(&self.hostname));
+        match (host_data, sockets) {
+            (Some(host_data), sockets) => {
+                let (mut host, diagnostics) = match host_data {
+                    HostData::LinuxHostData(h) => h.parse_host()?,
+                    HostData::WindowsHostData(h) => h.parse_host()?,
+                };
+                if let Some(sockets) = sockets {
+                    host.merge(&sockets);
+                }
+                Ok((host, diagnostics))
+            }
+            (None, Some(host)) => Ok((host, ParseDiagnostics::new(&self.hostname))),
+            (None, None) => bail!(
+                "the bundle of host {} has neither network output nor sockets",
+                self.hostname
+            ),
+        }
+    }
+}
+
+impl From<LinuxHostRawData> for HostCapture {
+    /// Bundle of a single sample, stamped with the current time
+    fn from(host_data: LinuxHostRawData) -> Self {
+        let mut capture = Self::new(&host_data.hostname, CaptureOs::Linux);
+        capture.ips = host_data.ips;
+        let (kind, output) = match host_data.network_output {
+            NetworkOutput::Ss(output) => (SS, output),
+            NetworkOutput::Netstat(output) => (LINUX_NETSTAT, output),
+        };
+        capture.outputs.insert(kind.to_string(), output);
+        if let Some(route_output) = host_data.route_output {
+            capture
+                .outputs
+                .insert(LINUX_ROUTE.to_string(), route_output);
+        }
+        capture
+    }
+}
+
+impl From<WindowsHostRawData> for HostCapture {
+    /// Bundle of a single sample, stamped with the current time
+    fn from(host_data: WindowsHostRawData) -> Self {
+        let mut capture = Self::new(&host_data.hostname, CaptureOs::Windows);
+        capture.ips = host_data.ips;
+        capture
+            .outputs
+            .insert(WINDOWS_NETSTAT.to_string(), host_data.network_output);
+        capture
+            .outputs
+            .insert(WINDOWS_TASKLIST.to_string(), host_data.tasklist_output);
+        if let Some(route_output) = host_data.route_output {
+            capture
+                .outputs
+                .insert(WINDOWS_ROUTE.to_string(), route_output);
+        }
+        capture
+    }
+}
+
+impl From<HostData> for HostCapture {
+    fn from(host_data: HostData) -> Self {
+        match host_data {
+            HostData::LinuxHostData(h) => h.into(),
+            HostData::WindowsHostData(h) => h.into(),
+        }
+    }
+}
+
+/// Whether the file is a bundle, from its name
+pub fn is_bundle_file(path: &Path) -> bool {
+    bundle_hostname(path).is_some()
+}
+
+/// Name of the host of a bundle file, its file name without the bundle extension
+pub fn bundle_hostname(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_string_lossy();
+    let hostname = file_name.strip_suffix(&format!(".{BUNDLE_EXTENSION}"))?;
+    (!hostname.is_empty()).then(|| hostname.to_string())
+}
+
+/// Path of the bundle of the host `hostname` in `output_dir`
+pub fn bundle_path(output_dir: &Path, hostname: &str) -> PathBuf {
+    output_dir.join(format!("{hostname}.{BUNDLE_EXTENSION}"))
+}
+
+/// Read a bundle file. The bundles of a newer version are read as well as possible, their unknown
+/// fields being ignored.
+pub fn read_bundle(path: &Path) -> anyhow::Result<HostCapture> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("unable to read file {path:?}"))?;
+    let capture: HostCapture = serde_json::from_str(&contents)
+        .with_context(|| format!("unable to parse bundle {path:?}"))?;
+    if capture.version > BUNDLE_VERSION {
+        log::warn!(
+            "bundle {path:?} has version {}, newer than {BUNDLE_VERSION}: some of its data may be left out",
+            capture.version
+        );
+    }
+    Ok(capture)
+}
+
+/// Write the bundle of a host in `output_dir`, named `<hostname>.smap.json`. When the host already
+/// has a bundle, the samples are added to it, so that several samples can be aggregated.
+///
+/// Returns the path of the bundle.
+pub fn write_bundle(output_dir: &Path, capture: &HostCapture) -> anyhow::Result<PathBuf> {
+    let path = bundle_path(output_dir, &capture.hostname);
+    let capture = match path.exists() {
+        true => {
+            let mut existing = read_bundle(&path)?;
+            existing.append(capture.clone());
+            existing
+        }
+        false => capture.clone(),
+    };
+    let json = serde_json::to_string_pretty(&capture)?;
+    std::fs::write(&path, json).with_context(|| format!("unable to write file {path:?}"))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::directory_scanner;
+
+    const SS_OUTPUT: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:50000    users:((\"sshd\",pid=200,fd=4))";
+
+    const SS_OUTPUT_2: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.3:50001    users:((\"sshd\",pid=201,fd=4))";
+
+    fn linux_host_data(ss_output: &str) -> LinuxHostRawData {
+        LinuxHostRawData::new(
+            "machine1".to_string(),
+            NetworkOutput::Ss(ss_output.to_string()),
+            vec!["10.0.0.1".parse().unwrap()],
+            Some("default via 10.0.0.254 dev eth0\n".to_string()),
+        )
+    }
+
+    /// A bundle written over several samples must be read back with all its samples, and parse
+    /// into the same host as the raw data
+    #[test]
+    fn test_bundle_round_trip() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let capture = HostCapture::from(linux_host_data(SS_OUTPUT));
+        let path = write_bundle(output_dir.path(), &capture).unwrap();
+        assert_eq!(path, output_dir.path().join("machine1.smap.json"));
+        assert_eq!(read_bundle(&path).unwrap(), capture);
+
+        write_bundle(
+            output_dir.path(),
+            &HostCapture::from(linux_host_data(SS_OUTPUT_2)),
+        )
+        .unwrap();
+        let bundle = read_bundle(&path).unwrap();
+        assert_eq!(bundle.version, BUNDLE_VERSION);
+        assert_eq!(bundle.os, CaptureOs::Linux);
+        assert_eq!(bundle.timestamps.len(), 2);
+        assert_eq!(bundle.outputs[SS], format!("{SS_OUTPUT}\n{SS_OUTPUT_2}"));
+        assert_eq!(
+            bundle.outputs[LINUX_ROUTE],
+            "default via 10.0.0.254 dev eth0\n"
+        );
+
+        let (host, diagnostics) = bundle.parse_host().unwrap();
+        assert_eq!(host.name(), "machine1");
+        assert_eq!(host.connections().len(), 2);
+        assert_eq!(host.listening_sockets().len(), 1);
+        assert_eq!(host.routes().len(), 1);
+        assert_eq!(diagnostics.coverage().parsed_lines(), 3);
+    }
+
+    /// The fields added by newer versions must be ignored, and the fields added by this version
+    /// must have defaults
+    #[test]
+    fn test_bundle_forward_compatibility() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("machine2.smap.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "version": 7,
+                "hostname": "machine2",
+                "os": "windows",
+                "agent": {"version": "9.0.0"},
+                "outputs": {
+                    "windows_netstat": "  TCP    0.0.0.0:135    0.0.0.0:0    LISTENING    1044",
+                    "windows_tasklist": "\"Image Name\",\"PID\",\"Session Name\",\"Session#\",\"Mem Usage\"\r\n\"svchost.exe\",\"1044\",\"Services\",\"0\",\"12,345 K\"",
+                    "windows_firewall": "unknown kind"
+                }
+            }"#,
+        )
+        .unwrap();
+        let bundle = read_bundle(&path).unwrap();
+        assert_eq!(bundle.version, 7);
+        assert!(bundle.timestamps.is_empty());
+        assert!(bundle.ips.is_empty());
+
+        let (host, _diagnostics) = bundle.parse_host().unwrap();
+        assert_eq!(host.name(), "machine2");
+        assert!(host
+            .listening_sockets()
+            .iter()
+            .any(|s| s.port() == 135 && s.process().name() == "svchost.exe"));
+    }
+
+    /// Bundles and legacy capture files must be read from the same directory, the files of a host
+    /// found in both being merged
+    #[test]
+    fn test_scan_dir_mixed_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        write_bundle(dir.path(), &HostCapture::from(linux_host_data(SS_OUTPUT))).unwrap();
+        linux_host_data(SS_OUTPUT_2)
+            .write_capture_files(dir.path())
+            .unwrap();
+        let mut other = linux_host_data(SS_OUTPUT);
+        other.hostname = "machine3".to_string();
+        other.write_capture_files(dir.path()).unwrap();
+        let mut pre_parsed = Host::new("machine4");
+        pre_parsed.add_ip("10.0.0.4".parse().unwrap());
+        write_bundle(
+            dir.path(),
+            &HostCapture::from_host(pre_parsed, CaptureOs::Linux),
+        )
+        .unwrap();
+
+        let scanned_hosts = directory_scanner::scan_dir(dir.path()).unwrap();
+        let mut hosts = directory_scanner::build_hosts(&scanned_hosts).unwrap();
+        hosts.sort_by(|a, b| a.name().cmp(b.name()));
+        let names: Vec<&str> = hosts.iter().map(|h| h.name()).collect();
+        assert_eq!(names, ["machine1", "machine3", "machine4"]);
+        assert_eq!(hosts[0].connections().len(), 2);
+        assert_eq!(hosts[1].connections().len(), 1);
+        assert!(hosts[2].ips().contains(&"10.0.0.4".parse().unwrap()));
+    }
+}
@@ -1,18 +1,19 @@
 //! This module handles the retrievl=al of information from a specifically crafted CSV files.
 
-use crate::host;
+use crate::{host, parsers::diagnostics::ParseDiagnostics};
 use anyhow::{bail, Context};
 use csv;
 use log;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, path::Path};
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 enum ConState {
     Established,
     Listening,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 struct Record {
     protocol: host::SocketType,
@@ -69,8 +70,20 @@ impl host::Host {
         network_csv_file_path: std::path::PathBuf,
         ip_csv_file_path: std::path::PathBuf,
     ) -> anyhow::Result<Self> {
+        Self::from_csv_files_with_diagnostics(hostname, network_csv_file_path, ip_csv_file_path)
+            .map(|(host, _diagnostics)| host)
+    }
+
+    /// Same as [`Self::from_csv_files`], along with the records of the network file which could
+    /// not be used
+    pub fn from_csv_files_with_diagnostics(
+        hostname: &str,
+        network_csv_file_path: std::path::PathBuf,
+        ip_csv_file_path: std::path::PathBuf,
+    ) -> anyhow::Result<(Self, ParseDiagnostics)> {
         log::debug!("Parsing CSV file for host {}", hostname);
         let mut host = host::Host::new(hostname);
+        let mut diagnostics = ParseDiagnostics::new(hostname);
 
         // Parse IP file
         let mut ip_csv_reader = csv::ReaderBuilder::new()
@@ -92,11 +105,25 @@ impl host::Host {
         let mut network_csv_reader = csv::ReaderBuilder::new()
             .from_path(network_csv_file_path)
             .with_context(|| "unable to read network file")?;
-        for network_record in network_csv_reader.deserialize() {
-            let record: Record = match network_record {
+        let headers = network_csv_reader
+            .headers()
+            .with_context(|| "unable to read network file header")?
+            .clone();
+        for network_record in network_csv_reader.records() {
+            diagnostics.count_lines(1);
+            let parsed_record = network_record.and_then(|record| {
+                let line = record.position().map(|p| p.line() as usize);
+                Ok((record.deserialize::<Record>(Some(&headers))?, line))
+            });
+            let (record, line) = match parsed_record {
                 Ok(n) => n,
                 Err(e) => {
-                    log::warn!("unable to parse CSV network record: {}", e);
+                    // The error may quote the fields, which are left out of the diagnostic
+                    log::debug!("unable to parse CSV network record: {}", e);
+                    diagnostics.error(
+                        e.position().map(|p| p.line() as usize),
+                        "unable to parse the network record",
+                    );
                     continue;
                 }
             };
@@ -109,8 +136,9 @@ impl host::Host {
                             record.protocol().clone(),
                             record.process(hostname).clone(),
                         ));
+                        diagnostics.parsed_line();
                     } else {
-                        log::warn!("missing foreign socket for connection");
+                        diagnostics.error(line, "missing foreign socket for connection");
                         continue;
                     }
                 }
@@ -129,10 +157,97 @@ impl host::Host {
                         hostname.to_string(),
                         ipv6_only,
                     ));
+                    diagnostics.parsed_line();
                 }
             }
         }
 
-        Ok(host)
+        Ok((host, diagnostics))
+    }
+
+    /// Write the host as the two CSV files read by [`Host::from_csv_files`], named after the host
+    /// in `output_dir`. The loopback and IPv4-mapped addresses, which every host has, are left out.
+    ///
+    /// [`Host::from_csv_files`]: host::Host::from_csv_files
+    pub fn write_csv_files(&self, output_dir: &Path) -> anyhow::Result<()> {
+        // IP file
+        let ip_file_path = output_dir.join(format!("{}_ip.csv", self.name()));
+        let mut ip_csv_writer = csv::Writer::from_path(&ip_file_path)
+            .with_context(|| format!("unable to create file {ip_file_path:?}"))?;
+        ip_csv_writer.write_record(["IP"])?;
+        for ip in self.ips() {
+            let ipv4_mapped = match ip {
+                IpAddr::V4(_) => false,
+                IpAddr::V6(ip) => ip.to_ipv4_mapped().is_some(),
+            };
+            if !ip.is_loopback() && !ipv4_mapped {
+                ip_csv_writer.write_record([ip.to_string()])?;
+            }
+        }
+        ip_csv_writer.flush()?;
+
+        // Network file
+        let network_file_path = output_dir.join(format!("{}_network.csv", self.name()));
+        let mut network_csv_writer = csv::Writer::from_path(&network_file_path)
+            .with_context(|| format!("unable to create file {network_file_path:?}"))?;
+        for listening_socket in self.listening_sockets() {
+            network_csv_writer.serialize(Record {
+                protocol: listening_socket.socket_type().clone(),
+                local_socket: *listening_socket.socket(),
+                foreign_socket: None,
+                state: ConState::Listening,
+                pid: *listening_socket.process().pid(),
+                process_name: listening_socket.process().name().to_string(),
+            })?;
+        }
+        for connection in self.connections() {
+            network_csv_writer.serialize(Record {
+                protocol: connection.socket_type().clone(),
+                local_socket: *connection.local_socket(),
+                foreign_socket: Some(*connection.peer_socket()),
+                state: ConState::Established,
+                pid: *connection.process().pid(),
+                process_name: connection.process().name().to_string(),
+            })?;
+        }
+        network_csv_writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::{Connection, Host, ListeningSocket, Process, SocketType};
+
+    /// A host written to CSV files must be read back with the same sockets and IPs
+    #[test]
+    fn test_write_csv_files() {
+        let mut host = Host::new("machine1");
+        host.add_ip("10.0.0.1".parse().unwrap());
+        host.add_listening_socket(ListeningSocket::new(
+            "0.0.0.0:22".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("sshd", 100, "machine1".to_string()),
+            "machine1".to_string(),
+            None,
+        ));
+        host.add_established_connection(Connection::new(
+            "10.0.0.1:22".parse().unwrap(),
+            "10.0.0.2:50000".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("sshd", 200, "machine1".to_string()),
+        ));
+
+        let output_dir = tempfile::tempdir().unwrap();
+        host.write_csv_files(output_dir.path()).unwrap();
+        let read_host = Host::from_csv_files(
+            "machine1",
+            output_dir.path().join("machine1_network.csv"),
+            output_dir.path().join("machine1_ip.csv"),
+        )
+        .unwrap();
+
+        assert_eq!(read_host, host);
     }
 }
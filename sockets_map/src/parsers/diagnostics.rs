@@ -0,0 +1,284 @@
+//! Problems met while parsing the capture files of a host, such as lines without a process or
+//! truncated lines, which do not prevent the host from being built
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticSeverity {
+    /// The line may be normal, such as a socket without a process when the capture was not run as
+    /// root
+    Warning,
+    /// The line could not be parsed, and its socket is missing from the host
+    Error,
+}
+
+impl std::fmt::Display for DiagnosticSeverity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticSeverity::Warning => write!(f, "warning"),
+            DiagnosticSeverity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Problem met on the lines of a capture file. The messages never quote the lines, so that they
+/// can be kept in anonymized reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseDiagnostic {
+    host: String,
+    severity: DiagnosticSeverity,
+    /// First line with the problem, starting at 1, if it concerns a line
+    line: Option<usize>,
+    message: String,
+    /// Number of lines with the same problem
+    occurrences: usize,
+}
+
+impl std::fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.host)?;
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+        if self.occurrences > 1 {
+            write!(f, " ({} lines)", self.occurrences)?;
+        }
+        Ok(())
+    }
+}
+
+impl ParseDiagnostic {
+    /// Get a reference to the diagnostic's host name.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Get the diagnostic's severity.
+    pub fn severity(&self) -> DiagnosticSeverity {
+        self.severity
+    }
+
+    /// Get the diagnostic's first line.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// Get a reference to the diagnostic's message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Get the number of lines with the same problem.
+    pub fn occurrences(&self) -> usize {
+        self.occurrences
+    }
+
+    /// The same diagnostic for the host renamed `host`
+    pub(crate) fn anonymized(&self, host: String) -> Self {
+        Self {
+            host,
+            ..self.clone()
+        }
+    }
+}
+
+/// How much of the network file of a host was used, to tell truncated captures or files of the
+/// wrong host apart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseCoverage {
+    /// Lines of the network file, or records of the CSV files
+    lines: usize,
+    /// Lines which made a socket
+    parsed_lines: usize,
+    /// Lines which could not be parsed
+    error_lines: usize,
+    /// Lines left out since their process is unknown
+    unnamed_processes: usize,
+}
+
+impl ParseCoverage {
+    /// Get the number of lines of the network file.
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Get the number of lines which made a socket.
+    pub fn parsed_lines(&self) -> usize {
+        self.parsed_lines
+    }
+
+    /// Lines which did not make a socket: the headers, the sockets in other states, and the
+    /// lines with a problem
+    pub fn skipped_lines(&self) -> usize {
+        self.lines.saturating_sub(self.parsed_lines)
+    }
+
+    /// Get the number of lines which could not be parsed.
+    pub fn error_lines(&self) -> usize {
+        self.error_lines
+    }
+
+    /// Get the number of lines left out since their process is unknown.
+    pub fn unnamed_processes(&self) -> usize {
+        self.unnamed_processes
+    }
+
+    /// Add the counts of `other`, such as the files of the same host in another directory
+    pub fn add(&mut self, other: &ParseCoverage) {
+        self.lines += other.lines;
+        self.parsed_lines += other.parsed_lines;
+        self.error_lines += other.error_lines;
+        self.unnamed_processes += other.unnamed_processes;
+    }
+}
+
+/// Diagnostics of the capture files of a host, filled by the parsers. The lines with the same
+/// problem are counted in a single diagnostic, and only its first line is logged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseDiagnostics {
+    host: String,
+    diagnostics: Vec<ParseDiagnostic>,
+    coverage: ParseCoverage,
+}
+
+impl ParseDiagnostics {
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            diagnostics: Vec::new(),
+            coverage: ParseCoverage::default(),
+        }
+    }
+
+    /// Record the number of lines of the network file
+    pub fn count_lines(&mut self, lines: usize) {
+        self.coverage.lines += lines;
+    }
+
+    /// Record a line which made a socket
+    pub fn parsed_line(&mut self) {
+        self.coverage.parsed_lines += 1;
+    }
+
+    /// Record a line left out since its process is unknown
+    pub fn missing_process(&mut self, line: Option<usize>, message: &str) {
+        self.coverage.unnamed_processes += 1;
+        self.warning(line, message);
+    }
+
+    /// Record a problem which may be normal
+    pub fn warning(&mut self, line: Option<usize>, message: &str) {
+        self.push(DiagnosticSeverity::Warning, line, message);
+    }
+
+    /// Record a line which could not be parsed
+    pub fn error(&mut self, line: Option<usize>, message: &str) {
+        self.push(DiagnosticSeverity::Error, line, message);
+    }
+
+    fn push(&mut self, severity: DiagnosticSeverity, line: Option<usize>, message: &str) {
+        if severity == DiagnosticSeverity::Error {
+            self.coverage.error_lines += 1;
+        }
+        if let Some(diagnostic) = self
+            .diagnostics
+            .iter_mut()
+            .find(|d| d.severity == severity && d.message == message)
+        {
+            diagnostic.occurrences += 1;
+            return;
+        }
+        let diagnostic = ParseDiagnostic {
+            host: self.host.clone(),
+            severity,
+            line,
+            message: message.to_string(),
+            occurrences: 1,
+        };
+        // The command line users still get the diagnostics in their logs
+        match severity {
+            DiagnosticSeverity::Warning => log::warn!("{diagnostic}"),
+            DiagnosticSeverity::Error => log::error!("{diagnostic}"),
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Get the diagnostics, in the order in which they were met.
+    pub fn diagnostics(&self) -> &[ParseDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// Get the counts of the lines of the network file.
+    pub fn coverage(&self) -> ParseCoverage {
+        self.coverage
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn into_diagnostics(self) -> Vec<ParseDiagnostic> {
+        self.diagnostics
+    }
+
+    /// Add the diagnostics and the counts of `other`, such as the other files of the same host
+    pub fn extend(&mut self, other: ParseDiagnostics) {
+        self.diagnostics.extend(other.diagnostics);
+        self.coverage.add(&other.coverage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines with the same problem must be counted in the diagnostic of the first one
+    #[test]
+    fn test_parse_diagnostics() {
+        let mut diagnostics = ParseDiagnostics::new("machine1");
+        diagnostics.warning(Some(3), "the line does not contain the process");
+        diagnostics.error(Some(4), "the line is incomplete");
+        diagnostics.warning(Some(5), "the line does not contain the process");
+        assert_eq!(diagnostics.diagnostics().len(), 2);
+
+        let diagnostic = &diagnostics.diagnostics()[0];
+        assert_eq!(diagnostic.host(), "machine1");
+        assert_eq!(diagnostic.severity(), DiagnosticSeverity::Warning);
+        assert_eq!(diagnostic.line(), Some(3));
+        assert_eq!(diagnostic.occurrences(), 2);
+        assert_eq!(
+            diagnostic.to_string(),
+            "machine1: line 3: the line does not contain the process (2 lines)"
+        );
+        assert_eq!(
+            diagnostics.diagnostics()[1].to_string(),
+            "machine1: line 4: the line is incomplete"
+        );
+    }
+
+    /// The lines which made no socket must be counted as skipped, whatever the reason
+    #[test]
+    fn test_parse_coverage() {
+        let mut diagnostics = ParseDiagnostics::new("machine1");
+        diagnostics.count_lines(6);
+        diagnostics.parsed_line();
+        diagnostics.parsed_line();
+        diagnostics.missing_process(Some(4), "the line does not contain the process");
+        diagnostics.error(Some(5), "the line is incomplete");
+        diagnostics.error(Some(6), "the line is incomplete");
+        let coverage = diagnostics.coverage();
+        assert_eq!(coverage.parsed_lines(), 2);
+        assert_eq!(coverage.skipped_lines(), 4);
+        assert_eq!(coverage.error_lines(), 2);
+        assert_eq!(coverage.unnamed_processes(), 1);
+        assert_eq!(diagnostics.diagnostics().len(), 2);
+
+        let mut total = ParseCoverage::default();
+        total.add(&coverage);
+        total.add(&coverage);
+        assert_eq!(total.lines(), 12);
+        assert_eq!(total.skipped_lines(), 8);
+    }
+}
@@ -1,27 +1,86 @@
-//! This module scans the samples directory and assumes the type of samples from the files names.
+//! This module scans the samples directory and assumes the type of samples from the files names,
+//! or from their contents when their extension is unknown.
 
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use crate::host::{self, Host};
-use anyhow::bail;
+use anyhow::{bail, Context};
 use log;
+use serde::{Deserialize, Serialize};
 
 use super::{
+    bundle, detect_network_output,
+    diagnostics::{ParseCoverage, ParseDiagnostic, ParseDiagnostics},
+    dns_map,
     linux::file_parser::{LinuxHostFiles, NetworkOutputFile},
-    windows::file_parser::WindowsHostFiles,
+    windows::{
+        self,
+        file_parser::{read_windows_text, WindowsHostFiles},
+    },
+    NetworkCommand,
 };
 
-#[derive(Clone)]
+/// Size of the beginning of the files read to detect their type
+const DETECTED_BYTES: u64 = 4096;
+
+/// What to do with hosts of the same name found in several directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnDuplicate {
+    /// Merge the sockets and IPs of the hosts into one host
+    #[default]
+    Merge,
+    /// Keep the hosts apart, suffixing their names with the name of their directory
+    Suffix,
+    /// Fail, naming the host and its directories
+    Error,
+}
+
+impl std::str::FromStr for OnDuplicate {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(OnDuplicate::Merge),
+            "suffix" => Ok(OnDuplicate::Suffix),
+            "error" => Ok(OnDuplicate::Error),
+            _ => Err("unknown policy, expected merge, suffix or error"),
+        }
+    }
+}
+
+/// How the files of a directory are recognized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// Detect the type of the files whose extension is unknown from their contents
+    pub detect_contents: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            detect_contents: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FileType {
     LinuxIp,
     WindowsIp,
     LinuxNetstat,
     WindowsNetstat,
     WindowsTasklist,
+    LinuxRoute,
+    WindowsRoute,
     LinuxSs,
     Nmap,
     CsvIp,
     CsvNetwork,
+    Bundle,
 }
 
 #[derive(Clone)]
@@ -86,32 +145,79 @@ impl ScannedHost {
 /// - `linux_ip`
 /// - `windows_ip`
 /// - `nmap_<ip>`
-pub fn scan_dir(path: &Path) -> Vec<ScannedHost> {
+/// - `linux_route` and `windows_route`, optional
+/// - `smap.json`, for the bundles holding all the outputs of their host, see [`bundle`]
+///
+/// The type of the other files is detected from their contents with [`detect_file_type`], their
+/// host being named after their stem, such as `web01` for `web01.txt`.
+///
+/// Fails if the directory cannot be read. The entries which cannot be read are skipped and logged.
+pub fn scan_dir(path: &Path) -> anyhow::Result<Vec<ScannedHost>> {
+    let (scanned_hosts, warnings) = scan_dir_with_warnings(path, ScanOptions::default())?;
+    for warning in warnings {
+        log::warn!("{warning}");
+    }
+    Ok(scanned_hosts)
+}
+
+/// Same as [`scan_dir`], returning the reasons the unreadable entries were skipped instead of
+/// logging them. The files which disappear or cannot be read afterwards make their host fail
+/// when the hosts are built.
+pub fn scan_dir_with_warnings(
+    path: &Path,
+    options: ScanOptions,
+) -> anyhow::Result<(Vec<ScannedHost>, Vec<String>)> {
     let mut scanned_hosts = Vec::<ScannedHost>::new();
     let mut scanned_hosts_names = Vec::<String>::new();
+    let mut warnings = Vec::<String>::new();
 
-    for entry in path.read_dir().expect("unable to read directory").flatten() {
-        let entry_path = entry.path();
-        if entry_path.is_dir() {
-            // Skip directories
-            continue;
-        }
-        log::debug!("seeing {}", entry_path.to_string_lossy());
-        let filetype_str = match entry_path.extension() {
-            Some(e) => e.to_string_lossy(),
-            None => {
-                // Skip files without extensions
+    let entries = path
+        .read_dir()
+        .with_context(|| format!("unable to read directory {path:?}"))?;
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warnings.push(format!("skipping an entry of directory {path:?}: {e}"));
                 continue;
             }
         };
+        let entry_path = entry.path();
+        match entry_path.metadata() {
+            Ok(metadata) if metadata.is_dir() => {
+                // Skip directories
+                continue;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                warnings.push(format!("skipping {entry_path:?}: {e}"));
+                continue;
+            }
+        }
+        log::debug!("seeing {}", entry_path.to_string_lossy());
+        // The names of the IP addresses are read apart, see `dns_map::find_dns_map`
+        if dns_map::is_dns_map_file(&entry_path) {
+            continue;
+        }
+        let filetype_str = entry_path
+            .extension()
+            .map(|e| e.to_string_lossy())
+            .unwrap_or_default();
+        // The extension of the bundles is made of two parts
+        let bundle_hostname = bundle::bundle_hostname(&entry_path);
 
+        // Whether the type of the file was detected from its contents
+        let mut detected = false;
         let filetype = match &filetype_str[..] {
+            _ if bundle_hostname.is_some() => FileType::Bundle,
             "ss" => FileType::LinuxSs,
             "linux_netstat" => FileType::LinuxNetstat,
             "windows_netstat" => FileType::WindowsNetstat,
             "windows_ip" => FileType::WindowsIp,
             "linux_ip" => FileType::LinuxIp,
             "windows_tasklist" => FileType::WindowsTasklist,
+            "linux_route" => FileType::LinuxRoute,
+            "windows_route" => FileType::WindowsRoute,
             _ => {
                 // Nmap file are a bit trickier to detect because of the IP at the end
                 if let Some(entry_path_filename) = entry_path.file_name() {
@@ -140,6 +246,13 @@ pub fn scan_dir(path: &Path) -> Vec<ScannedHost> {
                         }
                     {
                         FileType::CsvIp
+                    } else if let Some(filetype) = options
+                        .detect_contents
+                        .then(|| detect_file_type_of(&entry_path))
+                        .flatten()
+                    {
+                        detected = true;
+                        filetype
                     } else {
                         // Skip if extension is unknown
                         log::debug!("skipping file {:?}", entry_path.file_name());
@@ -151,6 +264,14 @@ pub fn scan_dir(path: &Path) -> Vec<ScannedHost> {
             }
         };
         let hostname = match filetype {
+            _ if detected => match entry_path.file_stem() {
+                Some(h) => h.to_string_lossy().to_string(),
+                None => continue,
+            },
+            FileType::Bundle => match bundle_hostname {
+                Some(h) => h,
+                None => continue,
+            },
             FileType::Nmap => {
                 if let Some(entry_path_filename) = entry_path.file_name() {
                     match entry_path_filename.to_string_lossy().split('.').next() {
@@ -211,18 +332,154 @@ pub fn scan_dir(path: &Path) -> Vec<ScannedHost> {
         };
     }
 
-    scanned_hosts
+    Ok((scanned_hosts, warnings))
+}
+
+/// Detect the type of a capture file from its contents, for the files whose extension is unknown.
+/// The route files and the CSV files are only recognized by their names.
+pub fn detect_file_type(contents: &str) -> Option<FileType> {
+    if contents.contains("Nmap scan report") {
+        return Some(FileType::Nmap);
+    }
+    if let Some(command) = detect_network_output(contents) {
+        return Some(match command {
+            NetworkCommand::LinuxSs => FileType::LinuxSs,
+            NetworkCommand::LinuxNetstat => FileType::LinuxNetstat,
+            NetworkCommand::WindowsNetstat => FileType::WindowsNetstat,
+        });
+    }
+    for line in contents.lines() {
+        let trimmed_line = line.trim_start();
+        if trimmed_line.starts_with("inet ") || trimmed_line.starts_with("inet6 ") {
+            return Some(FileType::LinuxIp);
+        }
+        // Get-NetIpAddress output
+        if line.starts_with("IPAddress") {
+            return Some(FileType::WindowsIp);
+        }
+        // tasklist /FO CSV header, whose other columns are translated
+        if line.starts_with('"') && line.split(',').nth(1) == Some("\"PID\"") {
+            return Some(FileType::WindowsTasklist);
+        }
+    }
+    None
+}
+
+/// Detect the type of the file at `path` from its first bytes, `None` if it cannot be read
+fn detect_file_type_of(path: &Path) -> Option<FileType> {
+    let mut contents = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|file| file.take(DETECTED_BYTES).read_to_end(&mut contents))
+        .ok()?;
+    let file_type = detect_file_type(&String::from_utf8_lossy(&contents));
+    log::debug!("detected file type {file_type:?} for {path:?}");
+    file_type
+}
+
+/// Whether the Windows netstat file at `path` was captured with `netstat -anob`
+fn is_netstat_b_file(path: &Path) -> anyhow::Result<bool> {
+    Ok(windows::is_netstat_b_output(&read_windows_text(path)?))
+}
+
+/// Host which could not be built from its files, and was left out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostFailure {
+    name: String,
+    reason: String,
+}
+
+impl HostFailure {
+    fn new(name: &str, reason: impl std::fmt::Display) -> Self {
+        log::warn!("unable to make host {name}: {reason}");
+        Self {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Get a reference to the failed host's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get a reference to the reason of the failure.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// The same failure for the host renamed `name`, with a generic reason since the original one
+    /// may quote the files and the real name of the host
+    pub(crate) fn anonymized(&self, name: String) -> Self {
+        Self {
+            name,
+            reason: "unable to parse its capture files".to_string(),
+        }
+    }
+}
+
+/// Hosts built from scanned directories, with the hosts left out and the problems met while
+/// parsing the others
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuiltHosts {
+    pub hosts: Vec<Host>,
+    /// Hosts whose files could not be parsed
+    pub failures: Vec<HostFailure>,
+    /// Diagnostics of the lines of the hosts files, by host name. The hosts without any are left
+    /// out.
+    pub diagnostics: BTreeMap<String, Vec<ParseDiagnostic>>,
+    /// Counts of the lines of the network files, for every parsed host
+    pub coverage: BTreeMap<String, ParseCoverage>,
+}
+
+impl BuiltHosts {
+    /// Add the hosts of `other`, merging the ones of the same name
+    fn merge(&mut self, other: BuiltHosts) {
+        for host in other.hosts {
+            match self.hosts.iter_mut().find(|h| h.name() == host.name()) {
+                Some(merged_host) => merged_host.merge(&host),
+                None => self.hosts.push(host),
+            }
+        }
+        self.failures.extend(other.failures);
+        for (name, diagnostics) in other.diagnostics {
+            self.diagnostics
+                .entry(name)
+                .or_default()
+                .extend(diagnostics);
+        }
+        for (name, coverage) in other.coverage {
+            self.coverage.entry(name).or_default().add(&coverage);
+        }
+    }
 }
 
 /// Build the hosts vector
 pub fn build_hosts(scanned_hosts: &[ScannedHost]) -> anyhow::Result<Vec<host::Host>> {
-    let mut hosts = Vec::<host::Host>::new();
+    Ok(build_hosts_with_failures(scanned_hosts)?.0)
+}
+
+/// Host parsed from its files, along with the problems met on their lines
+type ParsedHost = anyhow::Result<(Host, ParseDiagnostics)>;
+
+/// Capture files making one host, checked before being parsed
+enum HostFiles {
+    Linux(LinuxHostFiles),
+    Windows(WindowsHostFiles),
+    Nmap(String, PathBuf),
+    Csv(String, PathBuf, PathBuf),
+    /// Bundle of the host, along with its legacy files if the directory has both
+    Bundle(String, PathBuf, Option<Box<HostFiles>>),
+}
 
-    for scanned_host in scanned_hosts {
+impl HostFiles {
+    /// Find the files making `scanned_host`, failing if one is missing or mismatched
+    fn find(scanned_host: &ScannedHost) -> anyhow::Result<Self> {
         // Check that host has one ip file and one network file
         let mut ip_file: Option<&File> = None;
         let mut network_file: Option<&File> = None;
         let mut windows_tasklist_file: Option<&File> = None;
+        let mut route_file: Option<&File> = None;
+        let mut bundle_file: Option<&File> = None;
 
         for file in scanned_host.files() {
             log::debug!("checking {}", file.path().to_string_lossy());
@@ -233,13 +490,37 @@ pub fn build_hosts(scanned_hosts: &[ScannedHost]) -> anyhow::Result<Vec<host::Ho
                 FileType::WindowsNetstat => network_file = Some(file),
                 FileType::LinuxSs => network_file = Some(file),
                 FileType::WindowsTasklist => windows_tasklist_file = Some(file),
+                FileType::LinuxRoute | FileType::WindowsRoute => route_file = Some(file),
                 FileType::Nmap => {
                     ip_file = Some(file);
                     network_file = Some(file)
                 }
                 FileType::CsvIp => ip_file = Some(file),
                 FileType::CsvNetwork => network_file = Some(file),
+                FileType::Bundle => bundle_file = Some(file),
+            };
+        }
+
+        // The legacy files of the host, if any, are merged into its bundle
+        if let Some(bundle_file) = bundle_file {
+            let legacy_files: Vec<File> = scanned_host
+                .files()
+                .iter()
+                .filter(|f| f.file_type() != &FileType::Bundle)
+                .cloned()
+                .collect();
+            let legacy = match legacy_files.is_empty() {
+                true => None,
+                false => Some(Box::new(Self::find(&ScannedHost {
+                    name: scanned_host.name.clone(),
+                    files: legacy_files,
+                })?)),
             };
+            return Ok(Self::Bundle(
+                scanned_host.name().into(),
+                bundle_file.path().to_path_buf(),
+                legacy,
+            ));
         }
 
         let ip_file = match ip_file {
@@ -261,90 +542,607 @@ pub fn build_hosts(scanned_hosts: &[ScannedHost]) -> anyhow::Result<Vec<host::Ho
             }
         };
 
-        if let FileType::WindowsNetstat = network_file.file_type() {
-            if windows_tasklist_file.is_none() {
-                bail!(format!(
-                    "host {} is missing the Windows tasklist file",
-                    scanned_host.name()
-                ));
+        // The routing table must come from the same OS as the other files
+        let route_file = match (ip_file.file_type(), route_file) {
+            (FileType::LinuxIp, Some(f)) if matches!(f.file_type(), FileType::WindowsRoute) => {
+                bail!("wrong association: Linux ip file with Windows route file");
+            }
+            (FileType::WindowsIp, Some(f)) if matches!(f.file_type(), FileType::LinuxRoute) => {
+                bail!("wrong association: Windows ip file with Linux route file");
             }
+            (_, route_file) => route_file.map(|f| f.path().to_path_buf()),
         };
 
-        // Build the host
-        let network_file = network_file;
-        let ip_file = ip_file;
-
-        match ip_file.file_type() {
-            FileType::LinuxIp => {
-                match network_file.file_type() {
-                    FileType::LinuxNetstat => {
-                        let linux_host_files = LinuxHostFiles::new(
-                            scanned_host.name().into(),
-                            NetworkOutputFile::Netstat(network_file.path().into()),
-                            ip_file.path().into(),
-                        );
-                        let host: anyhow::Result<Host> = linux_host_files.into();
-                        match host {
-                            Ok(h) => hosts.push(h),
-                            Err(e) => {
-                                log::warn!("unable to make host {}: {}", scanned_host.name(), e)
-                            }
-                        };
-                    }
-                    FileType::LinuxSs => {
-                        let linux_host_files = LinuxHostFiles::new(
-                            scanned_host.name().into(),
-                            NetworkOutputFile::Ss(network_file.path().into()),
-                            ip_file.path().into(),
-                        );
-                        let host: anyhow::Result<Host> = linux_host_files.into();
-                        match host {
-                            Ok(h) => hosts.push(h),
-                            Err(e) => {
-                                log::warn!("unable to make host {}: {}", scanned_host.name(), e)
-                            }
-                        };
-                    }
-                    FileType::WindowsNetstat => {
-                        bail!("wrong association: Linux ip file with Windows netstat file"
-                            .to_string());
-                    }
-                    _ => continue, // unreachable statement
-                }
+        match (ip_file.file_type(), network_file.file_type()) {
+            (FileType::LinuxIp, FileType::LinuxNetstat) => Ok(Self::Linux(LinuxHostFiles::new(
+                scanned_host.name().into(),
+                NetworkOutputFile::Netstat(network_file.path().into()),
+                ip_file.path().into(),
+                route_file,
+            ))),
+            (FileType::LinuxIp, FileType::LinuxSs) => Ok(Self::Linux(LinuxHostFiles::new(
+                scanned_host.name().into(),
+                NetworkOutputFile::Ss(network_file.path().into()),
+                ip_file.path().into(),
+                route_file,
+            ))),
+            (FileType::LinuxIp, FileType::WindowsNetstat) => {
+                bail!("wrong association: Linux ip file with Windows netstat file".to_string());
             }
-            FileType::WindowsIp => {
-                let windows_tasklist_file = windows_tasklist_file.unwrap();
-                let windows_host_files = WindowsHostFiles::new(
+            (FileType::WindowsIp, _) => {
+                let windows_tasklist_file = match windows_tasklist_file {
+                    Some(n) => Some(n.path().to_path_buf()),
+                    // The output of `netstat -anob` names the processes without the tasklist
+                    None if is_netstat_b_file(network_file.path())? => None,
+                    None => bail!(format!(
+                        "host {} is missing the Windows tasklist file",
+                        scanned_host.name()
+                    )),
+                };
+                Ok(Self::Windows(WindowsHostFiles::new(
                     scanned_host.name().into(),
                     network_file.path().into(),
                     ip_file.path().into(),
-                    windows_tasklist_file.path().into(),
-                );
-                let host: anyhow::Result<Host> = windows_host_files.into();
-                match host {
-                    Ok(h) => hosts.push(h),
-                    Err(e) => bail!(e),
-                };
+                    windows_tasklist_file,
+                    route_file,
+                )))
             }
-            FileType::Nmap => {
-                if let Ok(host) = host::Host::from_nmap_output_file(
-                    scanned_host.name(),
-                    ip_file.path().to_path_buf(),
-                ) {
-                    hosts.push(host);
+            (FileType::Nmap, _) => Ok(Self::Nmap(
+                scanned_host.name().into(),
+                ip_file.path().to_path_buf(),
+            )),
+            (FileType::CsvIp, _) => Ok(Self::Csv(
+                scanned_host.name().into(),
+                network_file.path().to_path_buf(),
+                ip_file.path().to_path_buf(),
+            )),
+            _ => bail!(
+                "host {} has mismatched ip and network files",
+                scanned_host.name()
+            ),
+        }
+    }
+
+    /// Parse the files into the host, along with the problems met on their lines
+    fn parse(self) -> ParsedHost {
+        match self {
+            HostFiles::Linux(linux_host_files) => linux_host_files.parse_host(),
+            HostFiles::Windows(windows_host_files) => windows_host_files.parse_host(),
+            HostFiles::Nmap(name, path) => {
+                Host::from_nmap_output_file_with_diagnostics(&name, path)
+            }
+            HostFiles::Csv(name, network_path, ip_path) => {
+                Host::from_csv_files_with_diagnostics(&name, network_path, ip_path)
+            }
+            HostFiles::Bundle(name, path, legacy) => {
+                let mut capture = bundle::read_bundle(&path)?;
+                capture.hostname = name;
+                let (mut host, mut diagnostics) = capture.parse_host()?;
+                if let Some(legacy) = legacy {
+                    let (legacy_host, legacy_diagnostics) = legacy.parse()?;
+                    host.merge(&legacy_host);
+                    diagnostics.extend(legacy_diagnostics);
+                }
+                Ok((host, diagnostics))
+            }
+        }
+    }
+}
+
+/// Parse the hosts files one after the other
+fn parse_hosts_sequential(host_files: Vec<HostFiles>) -> Vec<ParsedHost> {
+    host_files.into_iter().map(HostFiles::parse).collect()
+}
+
+/// Parse the hosts files on as many threads as there are cores, keeping their order
+fn parse_hosts_parallel(host_files: Vec<HostFiles>) -> Vec<ParsedHost> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    parse_hosts_on_threads(host_files, threads)
+}
+
+/// Parse the hosts files on at most `threads` threads, keeping their order
+fn parse_hosts_on_threads(host_files: Vec<HostFiles>, threads: usize) -> Vec<ParsedHost> {
+    let threads = threads.min(host_files.len());
+    if threads <= 1 {
+        return parse_hosts_sequential(host_files);
+    }
+
+    // Split the files into one chunk per thread, then join the threads in order
+    let chunk_size = host_files.len().div_ceil(threads);
+    let mut chunks = Vec::<Vec<HostFiles>>::new();
+    let mut host_files = host_files.into_iter().peekable();
+    while host_files.peek().is_some() {
+        chunks.push(host_files.by_ref().take(chunk_size).collect());
+    }
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(|| parse_hosts_sequential(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("host parsing thread panicked"))
+            .collect()
+    })
+}
+
+/// Build the hosts vector, along with the hosts whose files could not be parsed. Errors are kept
+/// for the files which do not make a host at all, such as a missing or mismatched file.
+///
+/// The files are parsed in parallel, the hosts keeping the order of `scanned_hosts`.
+pub fn build_hosts_with_failures(
+    scanned_hosts: &[ScannedHost],
+) -> anyhow::Result<(Vec<host::Host>, Vec<HostFailure>)> {
+    let built_hosts = build_hosts_with_diagnostics(scanned_hosts)?;
+    Ok((built_hosts.hosts, built_hosts.failures))
+}
+
+/// Same as [`build_hosts_with_failures`], along with the diagnostics of the hosts
+pub fn build_hosts_with_diagnostics(scanned_hosts: &[ScannedHost]) -> anyhow::Result<BuiltHosts> {
+    build_hosts_with(scanned_hosts, parse_hosts_parallel)
+}
+
+/// Check the files of every host, then parse them with `parse_hosts`
+fn build_hosts_with(
+    scanned_hosts: &[ScannedHost],
+    parse_hosts: fn(Vec<HostFiles>) -> Vec<ParsedHost>,
+) -> anyhow::Result<BuiltHosts> {
+    let host_files = scanned_hosts
+        .iter()
+        .map(HostFiles::find)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // The failures are logged here rather than by the threads, so that they come in order
+    let mut built_hosts = BuiltHosts::default();
+    for (scanned_host, host) in scanned_hosts.iter().zip(parse_hosts(host_files)) {
+        match host {
+            Ok((h, diagnostics)) => {
+                built_hosts
+                    .coverage
+                    .entry(h.name().to_string())
+                    .or_default()
+                    .add(&diagnostics.coverage());
+                if !diagnostics.is_empty() {
+                    built_hosts
+                        .diagnostics
+                        .entry(h.name().to_string())
+                        .or_default()
+                        .extend(diagnostics.into_diagnostics());
                 }
+                built_hosts.hosts.push(h);
             }
-            FileType::CsvIp => {
-                if let Ok(host) = host::Host::from_csv_files(
-                    scanned_host.name(),
-                    network_file.path().to_path_buf(),
-                    ip_file.path().to_path_buf(),
-                ) {
-                    hosts.push(host);
+            Err(e) => built_hosts
+                .failures
+                .push(HostFailure::new(scanned_host.name(), e)),
+        }
+    }
+    Ok(built_hosts)
+}
+
+/// Scan several directories and build their hosts, handling the hosts found in more than one
+/// directory according to `on_duplicate`. The hosts which could not be parsed are returned apart.
+pub fn build_hosts_from_dirs(
+    paths: &[PathBuf],
+    on_duplicate: OnDuplicate,
+    options: ScanOptions,
+) -> anyhow::Result<BuiltHosts> {
+    let mut scanned_hosts = Vec::<(ScannedHost, &Path)>::new();
+    for path in paths {
+        let (scanned_dir, warnings) = scan_dir_with_warnings(path, options)?;
+        for warning in warnings {
+            log::warn!("{warning}");
+        }
+        scanned_hosts.extend(scanned_dir.into_iter().map(|h| (h, path.as_path())));
+    }
+
+    // Directories of each host name
+    let mut dirs = HashMap::<String, Vec<&Path>>::new();
+    for (scanned_host, path) in &scanned_hosts {
+        dirs.entry(scanned_host.name.clone())
+            .or_default()
+            .push(path);
+    }
+    let is_duplicate = |name: &str| dirs.get(name).map(|d| d.len() > 1).unwrap_or(false);
+
+    match on_duplicate {
+        OnDuplicate::Merge => (),
+        OnDuplicate::Suffix => {
+            for (scanned_host, path) in &mut scanned_hosts {
+                if is_duplicate(&scanned_host.name) {
+                    scanned_host.name = format!("{}_{}", scanned_host.name, dir_name(path));
                 }
             }
-            _ => continue, // unreachable statement
+            let mut names: Vec<&str> = scanned_hosts.iter().map(|(h, _)| h.name()).collect();
+            names.sort_unstable();
+            if let Some(name) = names.windows(2).find(|w| w[0] == w[1]).map(|w| w[0]) {
+                bail!("host {name} is still duplicated once suffixed with its directory name");
+            }
+        }
+        OnDuplicate::Error => {
+            let mut duplicates: Vec<_> = dirs.iter().filter(|(_, d)| d.len() > 1).collect();
+            duplicates.sort();
+            if let Some((name, dirs)) = duplicates.first() {
+                bail!("host {name} is found in several directories: {dirs:?}");
+            }
+        }
+    }
+
+    let scanned_hosts: Vec<ScannedHost> = scanned_hosts.into_iter().map(|(h, _)| h).collect();
+    let mut built_hosts = BuiltHosts::default();
+    built_hosts.merge(build_hosts_with_diagnostics(&scanned_hosts)?);
+    Ok(built_hosts)
+}
+
+/// Name of a directory, to tell apart the hosts found in several directories
+fn dir_name(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::HostBuilder;
+
+    /// Write the capture files of `name` in `dir`, with a socket on `port`
+    fn write_host(dir: &Path, name: &str, port: u16) {
+        HostBuilder::new(name)
+            .ip("10.0.0.1")
+            .listen_tcp(port, "server", 100)
+            .connect_tcp("10.0.0.1:50000", "10.0.0.2:22", "ssh", 200)
+            .build()
+            .write_csv_files(dir)
+            .unwrap();
+    }
+
+    /// Write the ss and ip captures of `hosts_count` Linux hosts in `dir`, each with a listening
+    /// socket and `connections_count` established connections
+    fn write_ss_fixture(dir: &Path, hosts_count: usize, connections_count: usize) {
+        for i in 0..hosts_count {
+            let ip = format!("10.0.{}.{}", i / 250, i % 250 + 1);
+            let mut ss_output = String::from(
+                "Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process\n",
+            );
+            ss_output.push_str(
+                "tcp   LISTEN 0      128    0.0.0.0:22 0.0.0.0:* users:((\"sshd\",pid=100,fd=3))\n",
+            );
+            for j in 0..connections_count {
+                ss_output.push_str(&format!(
+                    "tcp   ESTAB  0      0      {ip}:{} 10.1.{}.{}:443 users:((\"curl\",pid={},fd=4))\n",
+                    30000 + j % 30000,
+                    j / 250 % 250,
+                    j % 250 + 1,
+                    1000 + j % 50,
+                ));
+            }
+            std::fs::write(dir.join(format!("host{i}.ss")), ss_output).unwrap();
+            std::fs::write(
+                dir.join(format!("host{i}.linux_ip")),
+                format!("    inet {ip}/24\n"),
+            )
+            .unwrap();
+        }
+    }
+
+    /// Two directories sharing the `gateway` host
+    fn make_dirs() -> (tempfile::TempDir, Vec<PathBuf>) {
+        let root = tempfile::tempdir().unwrap();
+        let dirs = vec![root.path().join("dmz"), root.path().join("internal")];
+        for dir in &dirs {
+            std::fs::create_dir(dir).unwrap();
+        }
+        write_host(&dirs[0], "gateway", 443);
+        write_host(&dirs[0], "web", 80);
+        write_host(&dirs[1], "gateway", 3128);
+        write_host(&dirs[1], "db", 5432);
+        (root, dirs)
+    }
+
+    fn names(hosts: &[Host]) -> Vec<&str> {
+        let mut names: Vec<&str> = hosts.iter().map(|h| h.name()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The sockets of the duplicated host must be merged
+    #[test]
+    fn test_build_hosts_from_dirs_merge() {
+        let (_root, dirs) = make_dirs();
+        let hosts = build_hosts_from_dirs(&dirs, OnDuplicate::Merge, ScanOptions::default())
+            .unwrap()
+            .hosts;
+        assert_eq!(names(&hosts), ["db", "gateway", "web"]);
+
+        let gateway = hosts.iter().find(|h| h.name() == "gateway").unwrap();
+        let mut ports: Vec<u16> = gateway
+            .listening_sockets()
+            .iter()
+            .map(|s| s.port())
+            .collect();
+        ports.sort_unstable();
+        assert_eq!(ports, [443, 3128]);
+        assert_eq!(gateway.connections().len(), 1);
+    }
+
+    /// The duplicated host must be renamed after its directories, the other hosts must not
+    #[test]
+    fn test_build_hosts_from_dirs_suffix() {
+        let (_root, dirs) = make_dirs();
+        let hosts = build_hosts_from_dirs(&dirs, OnDuplicate::Suffix, ScanOptions::default())
+            .unwrap()
+            .hosts;
+        assert_eq!(
+            names(&hosts),
+            ["db", "gateway_dmz", "gateway_internal", "web"]
+        );
+        let gateway = hosts.iter().find(|h| h.name() == "gateway_dmz").unwrap();
+        assert_eq!(gateway.listening_sockets()[0].port(), 443);
+        assert_eq!(
+            gateway.listening_sockets()[0].process().node_id(),
+            "gateway_dmz_server"
+        );
+    }
+
+    /// A host whose files cannot be parsed must be left out and reported, not fail the others
+    #[test]
+    fn test_build_hosts_with_failures() {
+        let (_root, dirs) = make_dirs();
+        std::fs::write(dirs[0].join("broken_ip.csv"), "IP\nnot an IP\n").unwrap();
+        std::fs::write(dirs[0].join("broken_network.csv"), "").unwrap();
+
+        let (hosts, failures) = build_hosts_with_failures(&scan_dir(&dirs[0]).unwrap()).unwrap();
+        assert_eq!(names(&hosts), ["gateway", "web"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].name(), "broken");
+        assert!(failures[0].reason().contains("IP"));
+    }
+
+    /// Parsing the hosts in parallel must give the same hosts and failures, in the same order, as
+    /// parsing them one after the other
+    #[test]
+    fn test_build_hosts_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        write_ss_fixture(dir.path(), 20, 50);
+        std::fs::write(dir.path().join("broken_ip.csv"), "IP\nnot an IP\n").unwrap();
+        std::fs::write(dir.path().join("broken_network.csv"), "").unwrap();
+        let scanned_hosts = scan_dir(dir.path()).unwrap();
+
+        let BuiltHosts {
+            hosts, failures, ..
+        } = build_hosts_with(&scanned_hosts, |files| parse_hosts_on_threads(files, 4)).unwrap();
+        let BuiltHosts {
+            hosts: sequential_hosts,
+            failures: sequential_failures,
+            ..
+        } = build_hosts_with(&scanned_hosts, parse_hosts_sequential).unwrap();
+        assert_eq!(hosts.len(), 20);
+        assert_eq!(hosts, sequential_hosts);
+        assert_eq!(failures, sequential_failures);
+        let scanned_names: Vec<&str> = scanned_hosts
+            .iter()
+            .map(|h| h.name())
+            .filter(|name| *name != "broken")
+            .collect();
+        let built_names: Vec<&str> = hosts.iter().map(|h| h.name()).collect();
+        assert_eq!(built_names, scanned_names);
+        assert_eq!(failures[0].name(), "broken");
+    }
+
+    /// Compare the parsing times of a large capture directory, run with
+    /// `cargo test --release -- --ignored bench_build_hosts --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_build_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        write_ss_fixture(dir.path(), 60, 20000);
+        let scanned_hosts = scan_dir(dir.path()).unwrap();
+
+        let start = std::time::Instant::now();
+        build_hosts_with(&scanned_hosts, parse_hosts_sequential).unwrap();
+        println!("sequential: {:?}", start.elapsed());
+        let start = std::time::Instant::now();
+        build_hosts_with(&scanned_hosts, parse_hosts_parallel).unwrap();
+        println!("parallel: {:?}", start.elapsed());
+    }
+
+    /// The duplicated host must be reported with its directories
+    #[test]
+    fn test_build_hosts_from_dirs_error() {
+        let (_root, dirs) = make_dirs();
+        let err =
+            build_hosts_from_dirs(&dirs, OnDuplicate::Error, ScanOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("host gateway"));
+        assert!(err.to_string().contains("internal"));
+
+        // A single directory has no duplicates
+        let hosts = build_hosts_from_dirs(&dirs[..1], OnDuplicate::Error, ScanOptions::default())
+            .unwrap()
+            .hosts;
+        assert_eq!(names(&hosts), ["gateway", "web"]);
+    }
+
+    /// A directory which cannot be read must give an error, instead of a panic
+    #[test]
+    fn test_scan_dir_unreadable() {
+        let (root, mut dirs) = make_dirs();
+        let missing = root.path().join("missing");
+        assert!(scan_dir(&missing).is_err());
+
+        dirs.push(missing);
+        let err =
+            build_hosts_from_dirs(&dirs, OnDuplicate::Merge, ScanOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("missing"));
+    }
+
+    /// Entries which cannot be read must be skipped with a warning, and files which cannot be
+    /// read must only make their host fail
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_dir_unreadable_entries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_root, dirs) = make_dirs();
+        let dangling_link = dirs[0].join("vanished.ss");
+        std::os::unix::fs::symlink(dirs[0].join("missing.ss"), &dangling_link).unwrap();
+        let (scanned_hosts, warnings) =
+            scan_dir_with_warnings(&dirs[0], ScanOptions::default()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("vanished.ss"));
+        assert!(scanned_hosts.iter().all(|h| h.name() != "vanished"));
+
+        let unreadable_file = dirs[0].join("web_network.csv");
+        std::fs::set_permissions(&unreadable_file, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::read(&unreadable_file).is_ok() {
+            // Running as root, which can read any file
+            return;
+        }
+        let (hosts, failures) = build_hosts_with_failures(&scanned_hosts).unwrap();
+        assert_eq!(names(&hosts), ["gateway"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].name(), "web");
+    }
+
+    /// Each kind of capture must be recognized from its contents, and the other files must not
+    #[test]
+    fn test_detect_file_type() {
+        let cases = [
+            (
+                "Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process\n",
+                Some(FileType::LinuxSs),
+            ),
+            (
+                "tcp   ESTAB  0      0      10.0.0.1:22 10.0.0.2:50000 users:((\"sshd\",pid=1,fd=4))\n",
+                Some(FileType::LinuxSs),
+            ),
+            (
+                "Active Internet connections (servers and established)\nProto Recv-Q Send-Q Local Address Foreign Address State PID/Program name\n",
+                Some(FileType::LinuxNetstat),
+            ),
+            (
+                "\r\nActive Connections\r\n\r\n  Proto  Local Address          Foreign Address        State           PID\r\n",
+                Some(FileType::WindowsNetstat),
+            ),
+            (
+                "  TCP    0.0.0.0:135            0.0.0.0:0              LISTENING       1044\r\n",
+                Some(FileType::WindowsNetstat),
+            ),
+            (
+                "1: lo: <LOOPBACK,UP,LOWER_UP> mtu 65536\n    inet 127.0.0.1/8 scope host lo\n",
+                Some(FileType::LinuxIp),
+            ),
+            (
+                "2: eth0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500\n    inet6 fd00::1/64 scope global\n",
+                Some(FileType::LinuxIp),
+            ),
+            (
+                "\r\nIPAddress         : 10.0.0.1\r\nInterfaceIndex    : 4\r\n",
+                Some(FileType::WindowsIp),
+            ),
+            (
+                "\"Image Name\",\"PID\",\"Session Name\",\"Session#\",\"Mem Usage\"\r\n",
+                Some(FileType::WindowsTasklist),
+            ),
+            (
+                "\"Nom de l'image\",\"PID\",\"Nom de la session\"\r\n",
+                Some(FileType::WindowsTasklist),
+            ),
+            (
+                "Starting Nmap 7.93\nNmap scan report for 10.0.0.3\n22/tcp open  ssh\n",
+                Some(FileType::Nmap),
+            ),
+            ("", None),
+            ("default via 10.0.0.254 dev eth0\n", None),
+            ("Source host,Dest host,Source process name\n", None),
+            ("# Notes about the capture\n", None),
+        ];
+        for (contents, file_type) in cases {
+            assert_eq!(detect_file_type(contents), file_type, "{contents:?}");
         }
     }
-    Ok(hosts)
+
+    /// The files with unknown extensions must make hosts named after their stem, unless the
+    /// detection is disabled
+    #[test]
+    fn test_scan_dir_detected_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_ss_fixture(dir.path(), 1, 1);
+        std::fs::rename(dir.path().join("host0.ss"), dir.path().join("web01.txt")).unwrap();
+        std::fs::rename(
+            dir.path().join("host0.linux_ip"),
+            dir.path().join("web01.ip"),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("printer"),
+            "Nmap scan report for printer (10.0.0.3)\n9100/tcp open  jetdirect\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "nothing to see\n").unwrap();
+
+        let mut hosts = build_hosts(&scan_dir(dir.path()).unwrap()).unwrap();
+        hosts.sort_by(|a, b| a.name().cmp(b.name()));
+        assert_eq!(names(&hosts), ["printer", "web01"]);
+        assert!(hosts[0].ips().contains(&"10.0.0.3".parse().unwrap()));
+        assert_eq!(hosts[0].listening_sockets()[0].port(), 9100);
+        assert!(hosts[1].ips().contains(&"10.0.0.1".parse().unwrap()));
+        assert_eq!(hosts[1].listening_sockets()[0].port(), 22);
+
+        let options = ScanOptions {
+            detect_contents: false,
+        };
+        let (scanned_hosts, _) = scan_dir_with_warnings(dir.path(), options).unwrap();
+        assert!(scanned_hosts.is_empty());
+    }
+
+    /// The tasklist file is only optional when the netstat file comes from `netstat -anob`
+    #[test]
+    fn test_windows_netstat_b_without_tasklist() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("machine1.windows_ip"),
+            "IPAddress         : 10.0.0.1\r\n",
+        )
+        .unwrap();
+        let netstat_output = "\
+  Proto  Local Address          Foreign Address        State           PID\r
+  TCP    0.0.0.0:3389           0.0.0.0:0              LISTENING       1388\r
+";
+        std::fs::write(dir.path().join("machine1.windows_netstat"), netstat_output).unwrap();
+        let err = build_hosts(&scan_dir(dir.path()).unwrap()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("missing the Windows tasklist file"));
+
+        let netstat_output = format!("{netstat_output}  TermService\r\n [svchost.exe]\r\n");
+        std::fs::write(dir.path().join("machine1.windows_netstat"), netstat_output).unwrap();
+        let hosts = build_hosts(&scan_dir(dir.path()).unwrap()).unwrap();
+        let listening_sockets = hosts[0].listening_sockets();
+        assert_eq!(listening_sockets.len(), 1);
+        assert_eq!(listening_sockets[0].process().name(), "svchost.exe");
+    }
+
+    /// The route files are optional, but must come from the same OS as the other files
+    #[test]
+    fn test_route_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_ss_fixture(dir.path(), 2, 1);
+        std::fs::write(
+            dir.path().join("host0.linux_route"),
+            "default via 10.0.0.254 dev eth0\n10.0.0.0/24 dev eth0\n",
+        )
+        .unwrap();
+        let mut hosts = build_hosts(&scan_dir(dir.path()).unwrap()).unwrap();
+        hosts.sort_by(|a, b| a.name().cmp(b.name()));
+        assert_eq!(hosts[0].routes().len(), 2);
+        assert!(hosts[1].routes().is_empty());
+
+        std::fs::write(dir.path().join("host1.windows_route"), "").unwrap();
+        let err = build_hosts(&scan_dir(dir.path()).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("Windows route file"));
+    }
 }
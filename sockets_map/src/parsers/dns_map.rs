@@ -0,0 +1,156 @@
+//! This module reads the names of the IP addresses from a file of the capture directories, so that
+//! the peers can be labeled without DNS lookups, such as on air-gapped networks
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::host::canonical_ip;
+
+/// Names of the files of a capture directory read as a DNS map: `dns.csv` with `ip,name` rows, or
+/// `hosts` in the `/etc/hosts` format
+pub const DNS_MAP_FILE_NAMES: [&str; 2] = ["dns.csv", "hosts"];
+
+/// Name of each IP address, the IPv4-mapped IPv6 addresses being stored as IPv4 addresses
+pub type DnsMap = HashMap<IpAddr, String>;
+
+/// Whether the file is a DNS map rather than a capture file
+pub fn is_dns_map_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| DNS_MAP_FILE_NAMES.contains(&name))
+}
+
+/// Parse a DNS map, either with `ip,name` rows or with the `/etc/hosts` lines of an IP followed by
+/// its names, of which the first one is kept. The comments after `#` are ignored, as well as a
+/// header on the first row. The first name of an IP given twice is kept.
+pub fn parse_dns_map(contents: &str) -> anyhow::Result<DnsMap> {
+    let mut dns_map = DnsMap::new();
+    let mut first_row = true;
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields: Vec<&str> = match line.contains(',') {
+            true => line.split(',').map(str::trim).collect(),
+            false => line.split_whitespace().collect(),
+        };
+        fields.retain(|field| !field.is_empty());
+        let is_first_row = std::mem::replace(&mut first_row, false);
+        let (ip, name) = match fields[..] {
+            [ip, name, ..] => (ip, name),
+            _ => bail!("line {}: expected an IP address and a name", index + 1),
+        };
+        // The scope of the link-local addresses, such as `fe80::1%eth0`, is left out
+        let ip = ip.split('%').next().unwrap_or_default();
+        let ip: IpAddr = match ip.parse() {
+            Ok(ip) => ip,
+            // Header of the CSV files, such as `ip,name`
+            Err(_) if is_first_row => continue,
+            Err(_) => bail!("line {}: invalid IP address {ip}", index + 1),
+        };
+        dns_map
+            .entry(canonical_ip(ip))
+            .or_insert_with(|| name.to_string());
+    }
+    Ok(dns_map)
+}
+
+/// Read the DNS map file at `path`, see [`parse_dns_map`]
+pub fn read_dns_map(path: &Path) -> anyhow::Result<DnsMap> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("unable to read file {path:?}"))?;
+    parse_dns_map(&contents).with_context(|| format!("invalid DNS map {path:?}"))
+}
+
+/// Read the DNS map files found in the capture directories, see [`DNS_MAP_FILE_NAMES`]. The names
+/// of the first directories and files take precedence.
+pub fn find_dns_map(files_directories: &[PathBuf]) -> anyhow::Result<DnsMap> {
+    let mut dns_map = DnsMap::new();
+    for directory in files_directories {
+        for file_name in DNS_MAP_FILE_NAMES {
+            let path = directory.join(file_name);
+            if !path.is_file() {
+                continue;
+            }
+            log::debug!("reading DNS map {path:?}");
+            for (ip, name) in read_dns_map(&path)? {
+                dns_map.entry(ip).or_insert(name);
+            }
+        }
+    }
+    Ok(dns_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name<'a>(dns_map: &'a DnsMap, ip: &str) -> Option<&'a str> {
+        dns_map.get(&ip.parse().unwrap()).map(String::as_str)
+    }
+
+    /// The CSV files may have a header, and the mapped IPv6 addresses are stored as IPv4 ones
+    #[test]
+    fn test_parse_csv_dns_map() {
+        let dns_map = parse_dns_map(
+            "ip,name\n\
+            52.31.4.9,api.stripe.com\n\
+            ::ffff:10.0.0.2, db.internal\n\
+            2001:db8::1,mirror.example.org # IPv6 mirror\n\
+            52.31.4.9,other.example.org\n",
+        )
+        .unwrap();
+        assert_eq!(dns_map.len(), 3);
+        assert_eq!(name(&dns_map, "52.31.4.9"), Some("api.stripe.com"));
+        assert_eq!(name(&dns_map, "10.0.0.2"), Some("db.internal"));
+        assert_eq!(name(&dns_map, "2001:db8::1"), Some("mirror.example.org"));
+
+        let err = parse_dns_map("52.31.4.9,api.stripe.com\n52.31.4,other.example.org\n");
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "line 2: invalid IP address 52.31.4"
+        );
+    }
+
+    /// The first name of the `/etc/hosts` lines is kept, and the comments are ignored
+    #[test]
+    fn test_parse_hosts_dns_map() {
+        let dns_map = parse_dns_map(
+            "# Static names\n\
+            127.0.0.1\tlocalhost\n\
+            \n\
+            10.0.0.5   web01.internal web01   # front\n\
+            fe80::1%eth0 router\n",
+        )
+        .unwrap();
+        assert_eq!(name(&dns_map, "127.0.0.1"), Some("localhost"));
+        assert_eq!(name(&dns_map, "10.0.0.5"), Some("web01.internal"));
+        assert_eq!(name(&dns_map, "fe80::1"), Some("router"));
+        assert!(parse_dns_map("10.0.0.5\n").is_err());
+    }
+
+    /// The maps of the directories are merged, and the other files are not maps
+    #[test]
+    fn test_find_dns_map() {
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        std::fs::write(dirs[0].path().join("dns.csv"), "10.0.0.5,web01\n").unwrap();
+        std::fs::write(
+            dirs[1].path().join("hosts"),
+            "10.0.0.5 web01.dmz\n10.0.0.6 web02.dmz\n",
+        )
+        .unwrap();
+        let paths: Vec<PathBuf> = dirs.iter().map(|dir| dir.path().to_path_buf()).collect();
+        let dns_map = find_dns_map(&paths).unwrap();
+        assert_eq!(name(&dns_map, "10.0.0.5"), Some("web01"));
+        assert_eq!(name(&dns_map, "10.0.0.6"), Some("web02.dmz"));
+
+        assert!(is_dns_map_file(&dirs[1].path().join("hosts")));
+        assert!(!is_dns_map_file(&dirs[1].path().join("web01.hosts")));
+    }
+}
@@ -0,0 +1,245 @@
+//! This module saves the hosts built from the capture directories, so that the next runs on the
+//! same files load them instead of parsing the files again
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+
+use super::directory_scanner::{BuiltHosts, OnDuplicate, ScanOptions};
+
+/// Version of the cache files, to be increased when the hosts change of format
+const CACHE_VERSION: u32 = 1;
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Beginning of the cache files, read before the hosts to tell a stale cache without decoding them
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheHeader {
+    version: u32,
+    key: String,
+}
+
+/// Key of the files, which changes as soon as one of them is added, removed or modified.
+/// `context` stands for the options the hosts are built with.
+pub fn files_key(files: &[PathBuf], context: &str) -> String {
+    let mut files: Vec<&PathBuf> = files.iter().collect();
+    files.sort();
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(format!("{CACHE_VERSION}\n{context}\n"));
+    for file in files {
+        // The files which cannot be read make their hosts fail, and are keyed as such
+        let (size, modified) = match file.metadata() {
+            Ok(metadata) => (
+                metadata.len(),
+                metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|modified| modified.as_nanos())
+                    .unwrap_or_default(),
+            ),
+            Err(_) => (0, 0),
+        };
+        hasher.update(format!("{}\n{size}\n{modified}\n", file.to_string_lossy()));
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Key of the files of the capture directories, along with the options of
+/// [`super::directory_scanner::build_hosts_from_dirs`]
+pub fn directories_key(
+    paths: &[PathBuf],
+    on_duplicate: OnDuplicate,
+    options: ScanOptions,
+) -> anyhow::Result<String> {
+    let mut files = Vec::new();
+    for path in paths {
+        let entries = path
+            .read_dir()
+            .with_context(|| format!("unable to read directory {path:?}"))?;
+        files.extend(
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file()),
+        );
+    }
+    Ok(files_key(
+        &files,
+        &format!("{paths:?} {on_duplicate:?} {options:?}"),
+    ))
+}
+
+/// Cache file of the hosts of `directories` in `cache_dir`. The file is named after the
+/// directories, so that their next captures replace it.
+pub fn cache_path(cache_dir: &Path, directories: &[PathBuf]) -> PathBuf {
+    let mut hasher = sha1::Sha1::new();
+    for directory in directories {
+        let directory = directory
+            .canonicalize()
+            .unwrap_or_else(|_| directory.to_path_buf());
+        hasher.update(format!("{}\n", directory.to_string_lossy()));
+    }
+    cache_dir.join(format!("{}.hosts", hex::encode(hasher.finalize())))
+}
+
+/// Save the hosts built from the files of `key` to the cache file at `path`
+pub fn save(built_hosts: &BuiltHosts, key: &str, path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("unable to create directory {parent:?}"))?;
+    }
+    // The hosts are written to a temporary file first, so that an interrupted run does not leave a
+    // truncated cache
+    let temp_path = path.with_extension("tmp");
+    let file =
+        File::create(&temp_path).with_context(|| format!("unable to create {temp_path:?}"))?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(file), COMPRESSION_LEVEL)?;
+    let header = CacheHeader {
+        version: CACHE_VERSION,
+        key: key.to_string(),
+    };
+    bincode::serialize_into(&mut encoder, &header)?;
+    bincode::serialize_into(&mut encoder, built_hosts)?;
+    encoder.finish()?.flush()?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("unable to write cache file {path:?}"))?;
+    Ok(())
+}
+
+/// Load the hosts of the cache file at `path` if they were built from the files of `key`, or
+/// `None` if the files changed since
+pub fn load(path: &Path, key: &str) -> anyhow::Result<Option<BuiltHosts>> {
+    let file = File::open(path).with_context(|| format!("unable to open {path:?}"))?;
+    let mut decoder = zstd::Decoder::new(BufReader::new(file))?;
+    let header: CacheHeader = bincode::deserialize_from(&mut decoder)
+        .with_context(|| format!("invalid cache file {path:?}"))?;
+    if header.version != CACHE_VERSION || header.key != key {
+        return Ok(None);
+    }
+    let built_hosts = bincode::deserialize_from(&mut decoder)
+        .with_context(|| format!("invalid cache file {path:?}"))?;
+    Ok(Some(built_hosts))
+}
+
+/// Load the hosts of `key` from the cache file at `path`, or build them with `build` and save
+/// them. The cache files which cannot be read or written are only logged.
+pub fn load_or_build(
+    path: &Path,
+    key: &str,
+    build: impl FnOnce() -> anyhow::Result<BuiltHosts>,
+) -> anyhow::Result<BuiltHosts> {
+    if path.exists() {
+        match load(path, key) {
+            Ok(Some(built_hosts)) => {
+                log::info!(
+                    "loaded {} hosts from cache {path:?}",
+                    built_hosts.hosts.len()
+                );
+                return Ok(built_hosts);
+            }
+            Ok(None) => log::info!("the capture files changed since cache {path:?}"),
+            Err(e) => log::warn!("ignoring the cache: {e:#}"),
+        }
+    }
+    let built_hosts = build()?;
+    if let Err(e) = save(&built_hosts, key, path) {
+        log::warn!("unable to save the hosts to the cache: {e:#}");
+    }
+    Ok(built_hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parsers::directory_scanner::build_hosts_from_dirs, test_utils::HostBuilder};
+    use std::cell::Cell;
+
+    fn write_host(directory: &Path, name: &str, port: u16) {
+        HostBuilder::new(name)
+            .listen_tcp(port, "sshd", 100)
+            .build()
+            .write_csv_files(directory)
+            .unwrap();
+    }
+
+    /// Build the hosts of the directory through the cache, counting the builds
+    fn build_cached(cache_dir: &Path, directory: &Path, builds: &Cell<usize>) -> BuiltHosts {
+        let paths = [directory.to_path_buf()];
+        let key = directories_key(&paths, OnDuplicate::Merge, ScanOptions::default()).unwrap();
+        load_or_build(&cache_path(cache_dir, &paths), &key, || {
+            builds.set(builds.get() + 1);
+            build_hosts_from_dirs(&paths, OnDuplicate::Merge, ScanOptions::default())
+        })
+        .unwrap()
+    }
+
+    /// The second run must load the hosts of the first one, along with their counts
+    #[test]
+    fn test_cache_hit() {
+        let (directory, cache_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write_host(directory.path(), "server", 22);
+        let builds = Cell::new(0);
+
+        let built_hosts = build_cached(cache_dir.path(), directory.path(), &builds);
+        let cached_hosts = build_cached(cache_dir.path(), directory.path(), &builds);
+        assert_eq!(builds.get(), 1);
+        assert_eq!(cached_hosts.hosts, built_hosts.hosts);
+        assert_eq!(cached_hosts.coverage, built_hosts.coverage);
+        assert_eq!(cached_hosts.hosts[0].listening_sockets().len(), 1);
+    }
+
+    /// Modifying or adding a capture file must rebuild the hosts
+    #[test]
+    fn test_stale_cache() {
+        let (directory, cache_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write_host(directory.path(), "server", 22);
+        let builds = Cell::new(0);
+        build_cached(cache_dir.path(), directory.path(), &builds);
+
+        write_host(directory.path(), "server", 2222);
+        let built_hosts = build_cached(cache_dir.path(), directory.path(), &builds);
+        assert_eq!(builds.get(), 2);
+        assert_eq!(
+            built_hosts.hosts[0].listening_sockets()[0].port(),
+            2222,
+            "the hosts of the previous files were loaded"
+        );
+
+        write_host(directory.path(), "client", 80);
+        let built_hosts = build_cached(cache_dir.path(), directory.path(), &builds);
+        assert_eq!(builds.get(), 3);
+        assert_eq!(built_hosts.hosts.len(), 2);
+        build_cached(cache_dir.path(), directory.path(), &builds);
+        assert_eq!(builds.get(), 3);
+    }
+
+    /// A corrupted cache file must be rebuilt instead of failing the run
+    #[test]
+    fn test_corrupted_cache() {
+        let (directory, cache_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write_host(directory.path(), "server", 22);
+        let builds = Cell::new(0);
+        build_cached(cache_dir.path(), directory.path(), &builds);
+
+        let paths = [directory.path().to_path_buf()];
+        let path = cache_path(cache_dir.path(), &paths);
+        let key = directories_key(&paths, OnDuplicate::Merge, ScanOptions::default()).unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &contents[..contents.len() - 8]).unwrap();
+        assert!(load(&path, &key).is_err());
+        let built_hosts = build_cached(cache_dir.path(), directory.path(), &builds);
+        assert_eq!(builds.get(), 2);
+        assert_eq!(built_hosts.hosts.len(), 1);
+
+        // The cache was saved again
+        build_cached(cache_dir.path(), directory.path(), &builds);
+        assert_eq!(builds.get(), 2);
+    }
+}
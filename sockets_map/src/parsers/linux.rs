@@ -3,13 +3,26 @@
 
 pub mod agent_parser;
 pub mod file_parser;
+mod terminal;
 
-use crate::host::{self, Host};
+use crate::host::{self, Host, Route};
 use anyhow::anyhow;
 use log;
 use regex;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::{
+    borrow::Cow,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use super::{diagnostics::ParseDiagnostics, parse_network};
+
+/// Diagnostic of the lines whose process is missing
+const MISSING_PROCESS: &str = "the line does not contain the process name, which can be normal for some lines, but can also be because the command was not run as root";
+/// Diagnostic of the lines missing some columns
+const INCOMPLETE_LINE: &str = "the line is incomplete, the capture may be truncated";
+/// Diagnostic of the lines whose sockets are not valid
+const UNPARSABLE_SOCKET: &str = "unable to parse the sockets of the line";
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum NetworkOutput {
@@ -19,31 +32,41 @@ pub enum NetworkOutput {
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct LinuxHostRawData {
-    hostname: String,
-    network_output: NetworkOutput,
-    ips: Vec<IpAddr>,
+    pub(super) hostname: String,
+    pub(super) network_output: NetworkOutput,
+    pub(super) ips: Vec<IpAddr>,
+    /// Output of the `ip route` and `ip -6 route` commands, if they could be run
+    #[serde(default)]
+    pub(super) route_output: Option<String>,
 }
 
 impl LinuxHostRawData {
-    pub fn new(hostname: String, network_output: NetworkOutput, ips: Vec<IpAddr>) -> Self {
+    pub fn new(
+        hostname: String,
+        network_output: NetworkOutput,
+        ips: Vec<IpAddr>,
+        route_output: Option<String>,
+    ) -> Self {
         Self {
             hostname,
             network_output,
             ips,
+            route_output,
         }
     }
 }
 
-pub fn parse_netstat_contents(
-    lines: std::str::Lines,
+pub(crate) fn parse_netstat_contents(
+    lines: &[(usize, Cow<str>)],
     host: &mut Host,
-    mut warned_about_malformed_lines: bool,
+    diagnostics: &mut ParseDiagnostics,
 ) -> Option<anyhow::Result<Host>> {
-    for line in lines {
+    for (line_number, line) in lines {
         // Skip lines that do not concern LISTENING OR ESTABLISHED connections
         if !(line.contains("ESTABLISHED") || line.contains("LISTEN")) {
             continue;
         }
+        let line_number = Some(*line_number);
         log::debug!("netstat line: {}", line);
 
         // Split line into columns
@@ -63,16 +86,19 @@ pub fn parse_netstat_contents(
         let Some(peer_socket_str) = split_line.get(4) else { continue };
         log::debug!("peer_socket: {}", peer_socket_str);
         // Get state
-        let Some(state) = split_line.get(5) else { continue };
+        let state = match split_line.get(5) {
+            Some(s) => s,
+            None => {
+                diagnostics.error(line_number, INCOMPLETE_LINE);
+                continue;
+            }
+        };
         log::debug!("state: {}", state);
         // Get process name
         let process_info = match split_line.get(6) {
             Some(p) => p,
             None => {
-                if !warned_about_malformed_lines {
-                    warned_about_malformed_lines = true;
-                    log::warn!("Some lines of the netstat output do not contain the process name. This can be normal for some lines, but it can also be because the command was not ran as root. If you're sure you did, you can ignore this warning.");
-                }
+                diagnostics.missing_process(line_number, MISSING_PROCESS);
                 continue;
             }
         };
@@ -157,6 +183,7 @@ pub fn parse_netstat_contents(
                 let connection =
                     host::Connection::new(local_socket, peer_socket, socket_type, process);
                 host.add_established_connection(connection);
+                diagnostics.parsed_line();
             }
             "LISTEN" => {
                 // Create listening connection
@@ -171,6 +198,7 @@ pub fn parse_netstat_contents(
                     },
                 );
                 host.add_listening_socket(listening_socket);
+                diagnostics.parsed_line();
             }
             _ => {
                 continue;
@@ -180,14 +208,16 @@ pub fn parse_netstat_contents(
     None
 }
 
-pub fn parse_ss_contents(
-    lines: std::str::Lines,
+pub(crate) fn parse_ss_contents(
+    lines: &[(usize, Cow<str>)],
     host: &mut Host,
-    warned_about_malformed_lines: &mut bool,
+    diagnostics: &mut ParseDiagnostics,
 ) {
-    for line in lines {
+    for (line_number, line) in lines {
         // Cleanup line by removing extraneous whitespaces
         let split_line = clean_and_split_line(line);
+        let process_info = ss_process_info(line);
+        let line_number = Some(*line_number);
 
         // Parse TCP and UDP socktets
         if line.starts_with("tcp") | line.starts_with("udp") {
@@ -205,12 +235,15 @@ pub fn parse_ss_contents(
                 // Parse this line as a listening socket
                 let listening_socket = parse_listening_socket_ss_line(
                     &split_line,
+                    process_info,
                     host.name(),
                     socket_type.clone(),
-                    warned_about_malformed_lines,
+                    line_number,
+                    diagnostics,
                 );
                 if let Some(l) = listening_socket {
                     host.add_listening_socket(l);
+                    diagnostics.parsed_line();
                 }
             }
 
@@ -220,12 +253,15 @@ pub fn parse_ss_contents(
                 // Create the Connection struct and add it to the Host
                 let established_connection = parse_established_connection_ss_line(
                     &split_line,
+                    process_info,
                     host.name(),
                     socket_type.clone(),
-                    warned_about_malformed_lines,
+                    line_number,
+                    diagnostics,
                 );
                 if let Some(c) = established_connection {
                     host.add_established_connection(c);
+                    diagnostics.parsed_line();
                 }
             }
 
@@ -234,12 +270,15 @@ pub fn parse_ss_contents(
                 // Parse this line as an established connection socket
                 let listening_socket = parse_listening_socket_ss_line(
                     &split_line,
+                    process_info,
                     host.name(),
                     socket_type.clone(),
-                    warned_about_malformed_lines,
+                    line_number,
+                    diagnostics,
                 );
                 if let Some(l) = listening_socket {
                     host.add_listening_socket(l);
+                    diagnostics.parsed_line();
                 }
             }
 
@@ -248,12 +287,15 @@ pub fn parse_ss_contents(
                 // Parse this line as an established connection socket
                 let established_connection = parse_established_connection_ss_line(
                     &split_line,
+                    process_info,
                     host.name(),
                     socket_type.clone(),
-                    warned_about_malformed_lines,
+                    line_number,
+                    diagnostics,
                 );
                 if let Some(c) = established_connection {
                     host.add_established_connection(c);
+                    diagnostics.parsed_line();
                 }
             }
         }
@@ -272,15 +314,36 @@ fn clean_and_split_line(line: &str) -> Vec<String> {
     split_line
 }
 
+/// Field of a ss line listing its processes, such as `users:(("sshd",pid=100,fd=3))`. It is
+/// searched for since it follows the timer and the memory fields with the `-O` (oneline) flag of
+/// some versions of ss, and process names may contain spaces.
+fn ss_process_info(line: &str) -> Option<&str> {
+    let start = line.find("users:(")?;
+    let process_info = &line[start..];
+    // The last line of a capture may be truncated
+    Some(match process_info.find("))") {
+        Some(end) => &process_info[..end + 2],
+        None => process_info,
+    })
+}
+
 /// Parse a listening socket ss line
 fn parse_listening_socket_ss_line(
     split_line: &[String],
+    process_info: Option<&str>,
     hostname: &str,
     socket_type: host::SocketType,
-    warned_about_malformed_lines: &mut bool,
+    line_number: Option<usize>,
+    diagnostics: &mut ParseDiagnostics,
 ) -> Option<host::ListeningSocket> {
     // Get sockets
-    let Some(local_socket_str) = split_line.get(4) else { return None };
+    let local_socket_str = match split_line.get(4) {
+        Some(l) => l,
+        None => {
+            diagnostics.error(line_number, INCOMPLETE_LINE);
+            return None;
+        }
+    };
     log::debug!("local_socket_str: {}", local_socket_str);
 
     // Clean loopback sockets from the "%iface" subststring, like in "127.0.0.53%lo:53"
@@ -288,33 +351,20 @@ fn parse_listening_socket_ss_line(
     let local_socket_str = re.replace(local_socket_str, ":");
 
     // Process
-    let process_info = match split_line.get(6) {
+    let process_info = match process_info {
         Some(p) => p,
         None => {
-            if !*warned_about_malformed_lines {
-                *warned_about_malformed_lines = true;
-                log::warn!("Some lines of the ss output do not contain the process name. This can be normal for some lines, but it can also be because the command was not ran as root. If you're sure you did, you can ignore this warning.");
-            }
+            diagnostics.missing_process(line_number, MISSING_PROCESS);
             return None;
         }
     };
-    let Some(process_name) = process_info.split('"').nth(1) else { return None };
-    let pid: u32 = match match match process_info.split(',').nth(1) {
-        Some(s) => s,
-        None => return None,
-    }
-    .split('=')
-    .nth(1)
-    {
-        Some(s) => s,
-        None => return None,
-    }
-    .parse()
-    {
-        Ok(p) => p,
-        Err(_) => return None,
+    let process = match parse_ss_process(process_info, hostname) {
+        Some(p) => p,
+        None => {
+            diagnostics.error(line_number, INCOMPLETE_LINE);
+            return None;
+        }
     };
-    let process = host::Process::new(process_name, pid, hostname.to_string());
 
     // IPv6
     let ipv6 = local_socket_str.starts_with('[') || local_socket_str.starts_with('*');
@@ -332,7 +382,10 @@ fn parse_listening_socket_ss_line(
         false => local_socket_str.parse(),
     } {
         Ok(l) => l,
-        Err(_) => return None,
+        Err(_) => {
+            diagnostics.error(line_number, UNPARSABLE_SOCKET);
+            return None;
+        }
     };
 
     Some(host::ListeningSocket::new(
@@ -347,60 +400,67 @@ fn parse_listening_socket_ss_line(
 /// Parse an established connection ss line
 fn parse_established_connection_ss_line(
     split_line: &[String],
+    process_info: Option<&str>,
     hostname: &str,
     socket_type: host::SocketType,
-    warned_about_malformed_lines: &mut bool,
+    line_number: Option<usize>,
+    diagnostics: &mut ParseDiagnostics,
 ) -> Option<host::Connection> {
     // Get sockets
-    let Some(local_socket_str) = split_line.get(4) else { return None };
-    let Some(peer_socket_str) = split_line.get(5) else { return None };
+    let (local_socket_str, peer_socket_str) = match (split_line.get(4), split_line.get(5)) {
+        (Some(l), Some(p)) => (l, p),
+        _ => {
+            diagnostics.error(line_number, INCOMPLETE_LINE);
+            return None;
+        }
+    };
 
     // Process
-    let process_info = match split_line.get(6) {
+    let process_info = match process_info {
         Some(p) => p,
         None => {
-            if !*warned_about_malformed_lines {
-                *warned_about_malformed_lines = true;
-                log::warn!("Some lines of the ss output do not contain the process name. This can be normal for some lines, but it can also be because the command was not ran as root. If you're sure you did, you can ignore this warning.");
-            }
+            diagnostics.missing_process(line_number, MISSING_PROCESS);
             return None;
         }
     };
-    let Some(process_name) = process_info.split('"').nth(1) else { return None };
-    let pid: u32 = match match match process_info.split(',').nth(1) {
-        Some(s) => s,
-        None => return None,
-    }
-    .split('=')
-    .nth(1)
-    {
-        Some(s) => s,
-        None => return None,
-    }
-    .parse()
-    {
-        Ok(p) => p,
-        Err(_) => return None,
+    let process = match parse_ss_process(process_info, hostname) {
+        Some(p) => p,
+        None => {
+            diagnostics.error(line_number, INCOMPLETE_LINE);
+            return None;
+        }
     };
-    let process = host::Process::new(process_name, pid, hostname.to_string());
 
     // Create the Connection struct and add it to the Host
-    Some(host::Connection::new(
-        match local_socket_str.parse() {
-            Ok(l) => l,
-            Err(_) => return None,
-        },
-        match peer_socket_str.parse() {
-            Ok(l) => l,
-            Err(_) => return None,
-        },
-        socket_type,
-        process,
-    ))
+    match (local_socket_str.parse(), peer_socket_str.parse()) {
+        (Ok(local_socket), Ok(peer_socket)) => Some(host::Connection::new(
+            local_socket,
+            peer_socket,
+            socket_type,
+            process,
+        )),
+        _ => {
+            diagnostics.error(line_number, UNPARSABLE_SOCKET);
+            None
+        }
+    }
+}
+
+/// Parse the process of a ss line, such as `users:(("sshd",pid=100,fd=3))`
+fn parse_ss_process(process_info: &str, hostname: &str) -> Option<host::Process> {
+    let process_name = process_info.split('"').nth(1)?;
+    let pid: u32 = process_info
+        .split(',')
+        .nth(1)?
+        .split('=')
+        .nth(1)?
+        .parse()
+        .ok()?;
+    Some(host::Process::new(process_name, pid, hostname.to_string()))
 }
 
 /// Parse IPs from `ip a` command output and add to the host
-pub fn parse_ip_command_output(
+pub(crate) fn parse_ip_command_output(
     ip_command_output_file_contents: String,
 ) -> anyhow::Result<Vec<IpAddr>> {
     let lines = ip_command_output_file_contents.lines();
@@ -433,35 +493,332 @@ pub fn parse_ip_command_output(
     Ok(ips)
 }
 
+/// Parse the default gateways and the directly connected networks from the `ip route` and
+/// `ip -6 route` commands output. The other routes are left out.
+pub(crate) fn parse_route_command_output(route_command_output: &str) -> Vec<Route> {
+    let mut routes = Vec::<Route>::new();
+
+    for line in route_command_output.lines() {
+        let split_line = clean_and_split_line(line);
+        let field = |name: &str| {
+            split_line
+                .iter()
+                .position(|s| s == name)
+                .and_then(|i| split_line.get(i + 1))
+        };
+        let gateway: Option<IpAddr> = match field("via") {
+            Some(gateway) => match gateway.parse() {
+                Ok(gateway) => Some(gateway),
+                Err(_) => continue,
+            },
+            None => None,
+        };
+        let interface = field("dev").cloned();
+
+        // Lines starting with a route type, such as "unreachable", are not kept
+        let Some(destination) = split_line.first() else {
+            continue;
+        };
+        let (destination, prefix_len) = match destination.as_str() {
+            "default" => match gateway {
+                Some(IpAddr::V6(_)) => (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+                _ => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            },
+            destination => match parse_network(destination) {
+                Some(network) => network,
+                None => continue,
+            },
+        };
+
+        // Routes to other networks through a gateway
+        if prefix_len != 0 && gateway.is_some() {
+            continue;
+        }
+        log::debug!("route line: {}", line);
+        routes.push(Route::new(destination, prefix_len, gateway, interface));
+    }
+    routes
+}
+
 impl From<LinuxHostRawData> for anyhow::Result<Host> {
     fn from(host_data: LinuxHostRawData) -> Self {
+        host_data.parse_host().map(|(host, _diagnostics)| host)
+    }
+}
+
+impl LinuxHostRawData {
+    /// Parse the raw data into the host, along with the problems met on the lines of its network
+    /// output
+    pub fn parse_host(self) -> anyhow::Result<(Host, ParseDiagnostics)> {
         log::debug!(
             "Parsing network info and ip commands output for host {}",
-            host_data.hostname
+            self.hostname
         );
-        let mut host = Host::new(&host_data.hostname);
+        let mut host = Host::new(&self.hostname);
+        let mut diagnostics = ParseDiagnostics::new(&self.hostname);
 
         // Add IPs
-        for ip in host_data.ips {
+        for ip in self.ips {
             host.add_ip(ip);
         }
 
-        // Parse network command output content
-        let network_output_contents = match &host_data.network_output {
-            NetworkOutput::Ss(data) => data,
-            NetworkOutput::Netstat(data) => data,
-        };
-        let lines = network_output_contents.lines();
-        let mut warned_about_malformed_lines = false;
-        match host_data.network_output {
-            NetworkOutput::Ss(_) => {
-                parse_ss_contents(lines, &mut host, &mut warned_about_malformed_lines);
+        // Add routes
+        if let Some(route_output) = &self.route_output {
+            for route in parse_route_command_output(route_output) {
+                host.add_route(route);
             }
-            NetworkOutput::Netstat(_) => {
-                parse_netstat_contents(lines, &mut host, warned_about_malformed_lines);
+        }
+
+        // Parse network command output content, without the artifacts of the terminal sessions
+        match &self.network_output {
+            NetworkOutput::Ss(data) => {
+                diagnostics.count_lines(data.lines().count());
+                let lines = terminal::capture_lines(data, &mut diagnostics);
+                parse_ss_contents(&lines, &mut host, &mut diagnostics);
+            }
+            NetworkOutput::Netstat(data) => {
+                diagnostics.count_lines(data.lines().count());
+                let lines = terminal::capture_lines(data, &mut diagnostics);
+                parse_netstat_contents(&lines, &mut host, &mut diagnostics);
             }
         }
 
-        Ok(host)
+        Ok((host, diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::diagnostics::DiagnosticSeverity;
+
+    const IP_ROUTE_OUTPUT: &str = "\
+default via 10.0.0.254 dev eth0 proto dhcp src 10.0.0.1 metric 100
+10.0.0.0/24 dev eth0 proto kernel scope link src 10.0.0.1 metric 100
+172.17.0.0/16 dev docker0 proto kernel scope link src 172.17.0.1 linkdown
+192.168.50.0/24 via 10.0.0.253 dev eth0
+unreachable 198.51.100.0/24 proto static
+fd00::/64 dev eth0 proto kernel metric 256 pref medium
+fe80::/64 dev eth0 proto kernel metric 256 pref medium
+default via fe80::1 dev eth0 proto ra metric 1024 expires 1797sec hoplimit 64 pref medium";
+
+    /// Only the default gateways and the directly connected networks must be kept
+    #[test]
+    fn test_parse_route_command_output() {
+        let routes: Vec<String> = parse_route_command_output(IP_ROUTE_OUTPUT)
+            .iter()
+            .map(Route::to_string)
+            .collect();
+        assert_eq!(
+            routes,
+            [
+                "default via 10.0.0.254 dev eth0",
+                "10.0.0.0/24 dev eth0",
+                "172.17.0.0/16 dev docker0",
+                "fd00::/64 dev eth0",
+                "fe80::/64 dev eth0",
+                "default via fe80::1 dev eth0",
+            ]
+        );
+        let routes = parse_route_command_output(IP_ROUTE_OUTPUT);
+        assert_eq!(routes[5].destination(), "::".parse::<IpAddr>().unwrap());
+        assert!(routes[5].is_default());
+    }
+
+    /// Sockets of a host as listed by `ss -apn`
+    const SS_APN_OUTPUT: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+u_str ESTAB  0      0      * 20981            * 20982            users:((\"systemd\",pid=1,fd=20))
+udp   UNCONN 0      0      127.0.0.53%lo:53   0.0.0.0:*         users:((\"systemd-resolve\",pid=612,fd=13))
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=901,fd=3))
+tcp   LISTEN 0      511    [::]:443           [::]:*            users:((\"nginx\",pid=1201,fd=7),(\"nginx\",pid=1200,fd=7))
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:51234    users:((\"sshd\",pid=3301,fd=4))
+tcp   ESTAB  0      0      10.0.0.1:40312     10.0.0.3:5432     users:((\"python3\",pid=4410,fd=9))";
+
+    /// The same sockets as [`SS_APN_OUTPUT`], as listed by `ss -tunapH`, `ss -tunapHO` with the
+    /// timer and memory fields, and `ss -tunape` with the extended fields
+    const SS_VARIANTS_OUTPUTS: [(&str, &str); 3] = [
+        (
+            "-H",
+            "\
+udp   UNCONN 0      0      127.0.0.53%lo:53   0.0.0.0:*         users:((\"systemd-resolve\",pid=612,fd=13))
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=901,fd=3))
+tcp   LISTEN 0      511    [::]:443           [::]:*            users:((\"nginx\",pid=1201,fd=7),(\"nginx\",pid=1200,fd=7))
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:51234    users:((\"sshd\",pid=3301,fd=4))
+tcp   ESTAB  0      0      10.0.0.1:40312     10.0.0.3:5432     users:((\"python3\",pid=4410,fd=9))",
+        ),
+        (
+            "-HO",
+            "\
+udp UNCONN 0 0 127.0.0.53%lo:53 0.0.0.0:* skmem:(r0,rb212992,t0,tb212992,f4096,w0,o0,bl0,d0) users:((\"systemd-resolve\",pid=612,fd=13))
+tcp LISTEN 0 128 0.0.0.0:22 0.0.0.0:* skmem:(r0,rb131072,t0,tb16384,f0,w0,o0,bl0,d0) users:((\"sshd\",pid=901,fd=3)) cubic rto:1000 mss:536 cwnd:10
+tcp LISTEN 0 511 [::]:443 [::]:* skmem:(r0,rb131072,t0,tb16384,f0,w0,o0,bl0,d0) users:((\"nginx\",pid=1201,fd=7),(\"nginx\",pid=1200,fd=7)) cubic rto:1000
+tcp ESTAB 0 0 10.0.0.1:22 10.0.0.2:51234 timer:(keepalive,119min,0) skmem:(r0,rb131072,t0,tb87040,f0,w0,o0,bl0,d0) users:((\"sshd\",pid=3301,fd=4)) ts sack cubic wscale:7,7 rto:204 rtt:0.5/0.25
+tcp ESTAB 0 0 10.0.0.1:40312 10.0.0.3:5432 timer:(keepalive,58min,0) skmem:(r0,rb131072,t0,tb46080,f0,w0,o0,bl0,d0) users:((\"python3\",pid=4410,fd=9)) ts sack cubic wscale:7,7 rto:208",
+        ),
+        (
+            "-e",
+            "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+udp   UNCONN 0      0      127.0.0.53%lo:53   0.0.0.0:*         users:((\"systemd-resolve\",pid=612,fd=13)) uid:101 ino:19432 sk:1 cgroup:/system.slice/systemd-resolved.service <->
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=901,fd=3)) ino:21233 sk:2 cgroup:/system.slice/ssh.service <->
+tcp   LISTEN 0      511    [::]:443           [::]:*            users:((\"nginx\",pid=1201,fd=7),(\"nginx\",pid=1200,fd=7)) ino:22810 sk:3 cgroup:/system.slice/nginx.service v6only:1 <->
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:51234    users:((\"sshd\",pid=3301,fd=4)) timer:(keepalive,119min,0) ino:40112 sk:4 cgroup:/system.slice/ssh.service <->
+tcp   ESTAB  0      0      10.0.0.1:40312     10.0.0.3:5432     users:((\"python3\",pid=4410,fd=9)) uid:1000 ino:41873 sk:5 cgroup:/user.slice <->",
+        ),
+    ];
+
+    /// The outputs of ss without header, on one line per socket or with the extended fields must
+    /// give the same host as the default output
+    #[test]
+    fn test_ss_output_variants() {
+        let parse = |ss_output: &str| {
+            let host_data = LinuxHostRawData::new(
+                "machine1".to_string(),
+                NetworkOutput::Ss(ss_output.to_string()),
+                vec!["10.0.0.1".parse().unwrap()],
+                None,
+            );
+            host_data.parse_host().unwrap()
+        };
+        let (expected, diagnostics) = parse(SS_APN_OUTPUT);
+        assert_eq!(expected.listening_sockets().len(), 3);
+        assert_eq!(expected.connections().len(), 2);
+        assert_eq!(diagnostics.coverage().parsed_lines(), 5);
+        for (flags, ss_output) in SS_VARIANTS_OUTPUTS {
+            let (host, diagnostics) = parse(ss_output);
+            assert_eq!(host, expected, "ss {flags}");
+            assert_eq!(diagnostics.coverage().parsed_lines(), 5, "ss {flags}");
+            assert!(diagnostics.is_empty(), "ss {flags}");
+        }
+    }
+
+    /// The sockets of [`SS_APN_OUTPUT`] as logged by `script` in a colored shell, with the
+    /// prompt, the command, the `grep --color` highlights and the `more` markers
+    const SS_SCRIPT_LOG: &str = "\
+Script started on 2024-03-01 10:00:00+01:00 [COMMAND=\"bash\" TERM=\"xterm-256color\"]\r
+\x1b]0;root@machine1: ~\x07\x1b[01;32mroot@machine1\x1b[00m:\x1b[01;34m~\x1b[00m# ss -apn | grep --color -E 'LISTEN|ESTAB|UNCONN' | more\r
+udp   UNCONN 0      0      127.0.0.53%lo:53   0.0.0.0:*         users:((\"systemd-resolve\",pid=612,fd=13))\r
+tcp   \x1b[01;31m\x1b[KLISTEN\x1b[m\x1b[K 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=901,fd=3))\r
+tcp   \x1b[01;31m\x1b[KLISTEN\x1b[m\x1b[K 0      511    [::]:443           [::]:*            users:((\"nginx\",pid=1201,fd=7),(\"nginx\",pid=1200,fd=7))\r
+\x1b[7m--More--\x1b[27m\r        \rtcp   \x1b[01;31m\x1b[KESTAB\x1b[m\x1b[K  0      0      10.0.0.1:22        10.0.0.2:51234    users:((\"sshd\",pid=3301,fd=4))\r
+tcp   \x1b[01;31m\x1b[KESTAB\x1b[m\x1b[K  0      0      10.0.0.1:40312     10.0.0.3:5432     users:((\"python3\",pid=4410,fd=9))\r
+\x1b]0;root@machine1: ~\x07\x1b[01;32mroot@machine1\x1b[00m:\x1b[01;34m~\x1b[00m# exit\r
+Script done on 2024-03-01 10:00:05+01:00 [COMMAND_EXIT_CODE=\"0\"]";
+
+    /// The netstat sockets of a host as logged by PuTTY, with the prompts and the command line
+    /// which contains `LISTEN` itself
+    const NETSTAT_PUTTY_LOG: &str = "\
+=~=~=~=~=~=~=~=~=~=~=~= PuTTY log 2024.03.01 10:00:00 =~=~=~=~=~=~=~=~=~=~=~=\r
+[admin@machine1 ~]$ sudo netstat -tanp | grep -E 'LISTEN|ESTABLISHED'\r
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN      901/sshd\r
+tcp        0      0 10.0.0.1:22             10.0.0.2:51234          ESTABLISHED 3301/sshd\r
+[admin@machine1 ~]$ ";
+
+    /// The captures of interactive terminal sessions must give the same hosts as the clean ones,
+    /// the artifacts of the sessions being counted in the diagnostics
+    #[test]
+    fn test_terminal_session_captures() {
+        let parse = |network_output: NetworkOutput| {
+            LinuxHostRawData::new(
+                "machine1".to_string(),
+                network_output,
+                vec!["10.0.0.1".parse().unwrap()],
+                None,
+            )
+            .parse_host()
+            .unwrap()
+        };
+        let (expected, _) = parse(NetworkOutput::Ss(SS_APN_OUTPUT.to_string()));
+        let (host, diagnostics) = parse(NetworkOutput::Ss(SS_SCRIPT_LOG.to_string()));
+        assert_eq!(host, expected);
+        assert_eq!(diagnostics.coverage().parsed_lines(), 5);
+        let occurrences: Vec<(DiagnosticSeverity, Option<usize>, usize)> = diagnostics
+            .diagnostics()
+            .iter()
+            .map(|d| (d.severity(), d.line(), d.occurrences()))
+            .collect();
+        assert_eq!(
+            occurrences,
+            [
+                (DiagnosticSeverity::Warning, Some(1), 4),
+                (DiagnosticSeverity::Warning, Some(4), 4),
+            ]
+        );
+
+        let netstat_output = "\
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN      901/sshd
+tcp        0      0 10.0.0.1:22             10.0.0.2:51234          ESTABLISHED 3301/sshd";
+        let (expected, _) = parse(NetworkOutput::Netstat(netstat_output.to_string()));
+        let (host, diagnostics) = parse(NetworkOutput::Netstat(NETSTAT_PUTTY_LOG.to_string()));
+        assert_eq!(host, expected);
+        assert_eq!(diagnostics.coverage().parsed_lines(), 2);
+        assert_eq!(diagnostics.coverage().error_lines(), 0);
+        assert_eq!(diagnostics.diagnostics()[0].occurrences(), 3);
+    }
+
+    /// The lines without process and the truncated last line of a capture must be reported with
+    /// their line numbers, and the other lines still parsed
+    #[test]
+    fn test_truncated_ss_diagnostics() {
+        let ss_output = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))
+tcp   ESTAB  0      0      10.0.0.1:40000     10.0.0.3:443
+tcp   ESTAB  0      0      10.0.0.1:40001     10.0.0.3:443
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:50000    users:((\"sshd\",pid=200,fd=4))
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:50001    users:((\"ss";
+        let host_data = LinuxHostRawData::new(
+            "machine1".to_string(),
+            NetworkOutput::Ss(ss_output.to_string()),
+            vec![],
+            None,
+        );
+        let (host, diagnostics) = host_data.parse_host().unwrap();
+        assert_eq!(host.listening_sockets().len(), 1);
+        assert_eq!(host.connections().len(), 1);
+
+        // The header is skipped along with the lines with a problem
+        let coverage = diagnostics.coverage();
+        assert_eq!(coverage.lines(), 6);
+        assert_eq!(coverage.parsed_lines(), 2);
+        assert_eq!(coverage.skipped_lines(), 4);
+        assert_eq!(coverage.error_lines(), 1);
+        assert_eq!(coverage.unnamed_processes(), 2);
+
+        let diagnostics: Vec<(DiagnosticSeverity, Option<usize>, usize)> = diagnostics
+            .diagnostics()
+            .iter()
+            .map(|d| (d.severity(), d.line(), d.occurrences()))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (DiagnosticSeverity::Warning, Some(3), 2),
+                (DiagnosticSeverity::Error, Some(6), 1),
+            ]
+        );
+    }
+
+    /// The routes must be added to the host, and old captures without them must still be parsed
+    #[test]
+    fn test_host_routes() {
+        let host_data = LinuxHostRawData::new(
+            "machine1".to_string(),
+            NetworkOutput::Ss(String::new()),
+            vec![],
+            Some(IP_ROUTE_OUTPUT.to_string()),
+        );
+        let host: Host = anyhow::Result::<Host>::from(host_data).unwrap();
+        assert_eq!(host.routes().len(), 6);
+
+        let host_data = LinuxHostRawData::new(
+            "machine1".to_string(),
+            NetworkOutput::Ss(String::new()),
+            vec![],
+            None,
+        );
+        let host: Host = anyhow::Result::<Host>::from(host_data).unwrap();
+        assert!(host.routes().is_empty());
     }
 }
@@ -1,8 +1,14 @@
-use std::path::PathBuf;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{anyhow, Context};
 
-use crate::{host::Host, parsers::linux::parse_ip_command_output};
+use crate::{
+    host::Host,
+    parsers::{append_to_file, diagnostics::ParseDiagnostics, linux::parse_ip_command_output},
+};
 
 use super::{LinuxHostRawData, NetworkOutput};
 
@@ -15,6 +21,7 @@ pub struct LinuxHostFiles {
     hostname: String,
     network_output_file: NetworkOutputFile,
     ip_output_file: PathBuf,
+    route_output_file: Option<PathBuf>,
 }
 
 impl LinuxHostFiles {
@@ -22,11 +29,13 @@ impl LinuxHostFiles {
         hostname: String,
         network_output_file: NetworkOutputFile,
         ip_output_file: PathBuf,
+        route_output_file: Option<PathBuf>,
     ) -> Self {
         Self {
             hostname,
             network_output_file,
             ip_output_file,
+            route_output_file,
         }
     }
 }
@@ -58,6 +67,15 @@ impl From<LinuxHostFiles> for anyhow::Result<LinuxHostRawData> {
         let network_info_command_output_file_contents = std::fs::read_to_string(network_file_path)
             .with_context(|| format!("unable to read file {network_file_path:?}",))?;
 
+        // The routing table is optional, since older captures do not have it
+        let route_output = match &linux_host_files.route_output_file {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("unable to read file {path:?}"))?,
+            ),
+            None => None,
+        };
+
         Ok(LinuxHostRawData {
             hostname: linux_host_files.hostname,
             network_output: match &linux_host_files.network_output_file {
@@ -69,13 +87,108 @@ impl From<LinuxHostFiles> for anyhow::Result<LinuxHostRawData> {
                 }
             },
             ips,
+            route_output,
         })
     }
 }
 
 impl From<LinuxHostFiles> for anyhow::Result<Host> {
     fn from(linux_host_files: LinuxHostFiles) -> Self {
-        let host_data: anyhow::Result<LinuxHostRawData> = linux_host_files.into();
-        host_data.and_then(|h| h.into())
+        linux_host_files
+            .parse_host()
+            .map(|(host, _diagnostics)| host)
+    }
+}
+
+impl LinuxHostFiles {
+    /// Read and parse the files into the host, along with the problems met on their lines
+    pub fn parse_host(self) -> anyhow::Result<(Host, ParseDiagnostics)> {
+        let host_data: anyhow::Result<LinuxHostRawData> = self.into();
+        host_data?.parse_host()
+    }
+}
+
+impl LinuxHostRawData {
+    /// Write the raw data as capture files in `output_dir`, named the way the directory scanner expects:
+    /// `<hostname>.ss` or `<hostname>.linux_netstat`, `<hostname>.linux_ip` and, if the routing
+    /// table was captured, `<hostname>.linux_route`.
+    ///
+    /// The network output is appended to the existing capture file, so that several samples can be aggregated.
+    pub fn write_capture_files(&self, output_dir: &Path) -> anyhow::Result<()> {
+        let (extension, network_output) = match &self.network_output {
+            NetworkOutput::Ss(output) => ("ss", output),
+            NetworkOutput::Netstat(output) => ("linux_netstat", output),
+        };
+        append_to_file(
+            &output_dir.join(format!("{}.{extension}", self.hostname)),
+            network_output,
+        )?;
+
+        // Write the IPs the way the `ip address` command does
+        let ip_output: String = self
+            .ips
+            .iter()
+            .map(|ip| match ip {
+                IpAddr::V4(_) => format!("    inet {ip}\n"),
+                IpAddr::V6(_) => format!("    inet6 {ip}\n"),
+            })
+            .collect();
+        let ip_file_path = output_dir.join(format!("{}.linux_ip", self.hostname));
+        std::fs::write(&ip_file_path, ip_output)
+            .with_context(|| format!("unable to write file {ip_file_path:?}"))?;
+
+        if let Some(route_output) = &self.route_output {
+            let route_file_path = output_dir.join(format!("{}.linux_route", self.hostname));
+            std::fs::write(&route_file_path, route_output)
+                .with_context(|| format!("unable to write file {route_file_path:?}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::directory_scanner;
+
+    const SS_OUTPUT: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))
+tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:50000    users:((\"sshd\",pid=200,fd=4))";
+
+    /// Capture files written over several samples must be read back by the directory scanner
+    #[test]
+    fn test_write_capture_files() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let host_data = LinuxHostRawData::new(
+            "machine1".to_string(),
+            NetworkOutput::Ss(SS_OUTPUT.to_string()),
+            vec!["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+            Some("default via 10.0.0.254 dev eth0\n10.0.0.0/24 dev eth0\n".to_string()),
+        );
+        host_data.write_capture_files(output_dir.path()).unwrap();
+        host_data.write_capture_files(output_dir.path()).unwrap();
+
+        let scanned_hosts = directory_scanner::scan_dir(output_dir.path()).unwrap();
+        let hosts = directory_scanner::build_hosts(&scanned_hosts).unwrap();
+        assert_eq!(hosts.len(), 1);
+        let host = &hosts[0];
+        assert_eq!(host.name(), "machine1");
+        assert!(host.ips().contains(&"10.0.0.1".parse().unwrap()));
+        assert!(host.ips().contains(&"fd00::1".parse().unwrap()));
+        assert!(host
+            .listening_sockets()
+            .iter()
+            .any(|s| s.port() == 22 && s.process().name() == "sshd"));
+        assert!(host
+            .connections()
+            .iter()
+            .any(|c| c.peer_socket() == &"10.0.0.2:50000".parse().unwrap()));
+        let routes: Vec<String> = host.routes().iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            routes,
+            ["default via 10.0.0.254 dev eth0", "10.0.0.0/24 dev eth0"]
+        );
     }
 }
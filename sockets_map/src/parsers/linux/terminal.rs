@@ -0,0 +1,164 @@
+//! Cleanup of the captures made over interactive terminal sessions, such as the logs of `script`
+//! or PuTTY, before the ss and netstat parsers see their lines
+
+use std::borrow::Cow;
+
+use regex::Regex;
+
+use crate::parsers::diagnostics::ParseDiagnostics;
+
+/// Diagnostic of the lines whose terminal artifacts were removed
+const CLEANED_LINE: &str =
+    "the line contains the color codes, escape sequences or pager marker of a terminal, which were removed";
+/// Diagnostic of the lines which only come from the terminal session
+const TERMINAL_LINE: &str =
+    "the line is a shell prompt, a command or a pager marker of a terminal session, and was left out";
+
+/// Artifacts of the terminal sessions, compiled once per capture
+struct TerminalFilter {
+    /// ANSI escape sequences: colors and cursor moves, window titles and character sets
+    escape_sequence: Regex,
+    /// Prompt of `more`, inline since it is erased by the next line
+    more_marker: Regex,
+    /// Lines only made of a pager prompt, such as the ones of `less`
+    pager_line: Regex,
+    /// Shell prompts, followed by the command typed if any
+    prompt_line: Regex,
+    /// Commands echoed without prompt, and the headers of the session logs
+    session_line: Regex,
+}
+
+impl TerminalFilter {
+    fn new() -> Self {
+        Self {
+            escape_sequence: Regex::new(
+                r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[()][0-9A-Za-z]|[=>78])",
+            )
+            .unwrap(),
+            more_marker: Regex::new(r"--More--(\(\d+%\))?").unwrap(),
+            pager_line: Regex::new(r"^\s*(:|\(END\)|lines \d+-\d+(/\d+)?( \d+%)?( \(END\))?)\s*$")
+                .unwrap(),
+            prompt_line: Regex::new(r"^(\[[^\]]*\]|[\w.@:~/-]*)\s?[#$%>](\s|$)").unwrap(),
+            session_line: Regex::new(
+                r"^(\s*(sudo\s+)?(ss|netstat)(\s+-\S+)*\s*$|Script (started|done) on |=~=~=)",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// The line without its terminal artifacts, or `None` if it only comes from the session
+    fn clean<'a>(&self, line: &'a str) -> Option<Cow<'a, str>> {
+        let mut line = self.escape_sequence.replace_all(line, "");
+        // A carriage return in the line moves back to its start, so that only the text written
+        // after the last one is left on the screen
+        if let Some(start) = line.trim_end_matches('\r').rfind('\r') {
+            line = Cow::Owned(line[start + 1..].trim_end_matches('\r').to_string());
+        }
+        if self.more_marker.is_match(&line) {
+            line = Cow::Owned(self.more_marker.replace_all(&line, "").into_owned());
+            if line.trim().is_empty() {
+                return None;
+            }
+        }
+        if self.pager_line.is_match(&line)
+            || self.prompt_line.is_match(&line)
+            || self.session_line.is_match(&line)
+        {
+            return None;
+        }
+        Some(line)
+    }
+}
+
+/// Lines of the network output of a capture, with their number in the file starting at 1, once
+/// the artifacts of the terminal sessions are removed. The lines which were cleaned or left out
+/// are counted in the `diagnostics`.
+pub(crate) fn capture_lines<'a>(
+    data: &'a str,
+    diagnostics: &mut ParseDiagnostics,
+) -> Vec<(usize, Cow<'a, str>)> {
+    let filter = TerminalFilter::new();
+    let mut lines = Vec::new();
+    for (index, line) in data.lines().enumerate() {
+        let line_number = index + 1;
+        match filter.clean(line) {
+            Some(cleaned) => {
+                if cleaned != line {
+                    diagnostics.warning(Some(line_number), CLEANED_LINE);
+                }
+                lines.push((line_number, cleaned));
+            }
+            None => diagnostics.warning(Some(line_number), TERMINAL_LINE),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The prompts, the commands, the pager markers and the session headers must be left out, and
+    /// the colors and overwritten text removed from the other lines, which keep their numbers
+    #[test]
+    fn test_capture_lines() {
+        let data = "\
+Script started on 2024-03-01 10:00:00+01:00 [COMMAND=\"bash\" TERM=\"xterm\"]
+\x1b]0;root@web01: ~\x07\x1b[01;32mroot@web01\x1b[00m:\x1b[01;34m~\x1b[00m# ss -apn
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   \x1b[01;31m\x1b[KLISTEN\x1b[m\x1b[K 0 128 0.0.0.0:22 0.0.0.0:* users:((\"sshd\",pid=901,fd=3))\r
+--More--(42%)
+--More--\r        \rtcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:51234
+[admin@db01 ~]$
+sudo netstat -tanp
+(END)
+";
+        let mut diagnostics = ParseDiagnostics::new("web01");
+        let lines: Vec<(usize, String)> = capture_lines(data, &mut diagnostics)
+            .into_iter()
+            .map(|(line_number, line)| (line_number, line.into_owned()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (
+                    3,
+                    "Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process"
+                        .to_string()
+                ),
+                (
+                    4,
+                    "tcp   LISTEN 0 128 0.0.0.0:22 0.0.0.0:* users:((\"sshd\",pid=901,fd=3))"
+                        .to_string()
+                ),
+                (
+                    6,
+                    "tcp   ESTAB  0      0      10.0.0.1:22        10.0.0.2:51234".to_string()
+                ),
+            ]
+        );
+        let occurrences: Vec<(Option<usize>, usize)> = diagnostics
+            .diagnostics()
+            .iter()
+            .map(|d| (d.line(), d.occurrences()))
+            .collect();
+        assert_eq!(occurrences, [(Some(1), 6), (Some(4), 2)]);
+    }
+
+    /// The lines of a clean capture must be kept as they are, without diagnostic
+    #[test]
+    fn test_clean_capture_lines() {
+        let data = "\
+Active Internet connections (servers and established)
+Proto Recv-Q Send-Q Local Address           Foreign Address         State       PID/Program name
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN      901/sshd: /usr/sbin
+u_str ESTAB  0      0      * 20981            * 20982            users:((\"systemd\",pid=1,fd=20))";
+        let mut diagnostics = ParseDiagnostics::new("web01");
+        let lines = capture_lines(data, &mut diagnostics);
+        assert_eq!(lines.len(), 4);
+        assert!(lines
+            .iter()
+            .all(|(_, line)| matches!(line, Cow::Borrowed(_))));
+        assert!(diagnostics.is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+//! This module retrieves sockets and processes information directly from the operating system,
+//! without executing and parsing the output of commands such as ss, netstat or tasklist.
+//!
+//! It uses netlink (sock_diag) and `/proc` on Linux, and the IP Helper API on Windows. The routing
+//! table is read the same way, instead of running `ip route` or `route print`.
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "windows")]
+mod windows;
+
+use crate::host::Host;
+use std::net::IpAddr;
+
+#[cfg(target_os = "linux")]
+use linux::{add_routes, add_sockets};
+#[cfg(target_os = "windows")]
+use windows::{add_routes, add_sockets};
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn add_sockets(_host: &mut Host) -> anyhow::Result<()> {
+    anyhow::bail!("native sockets collection is not supported on this platform")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn add_routes(_host: &mut Host) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Build a host with the sockets currently opened on this machine
+pub fn collect_host(hostname: &str, ips: &[IpAddr]) -> anyhow::Result<Host> {
+    let mut host = Host::new(hostname);
+    ips.iter().for_each(|ip| host.add_ip(*ip));
+    add_sockets(&mut host)?;
+    // The sockets are still worth sending without the routes
+    if let Err(e) = add_routes(&mut host) {
+        log::warn!("unable to read the routing table of {hostname}: {e:#}");
+    }
+
+    Ok(host)
+}
+
+/// Keep the default gateways and the directly connected networks, as the routing commands parsers
+/// do. The loopback, multicast and single host routes are left out.
+#[cfg_attr(not(any(target_os = "linux", target_os = "windows")), allow(dead_code))]
+fn is_kept_route(destination: IpAddr, prefix_len: u8, gateway: Option<IpAddr>) -> bool {
+    let single_host = match destination {
+        IpAddr::V4(_) => prefix_len == 32,
+        IpAddr::V6(_) => prefix_len == 128,
+    };
+    match prefix_len {
+        0 => gateway.is_some(),
+        _ => {
+            gateway.is_none()
+                && !single_host
+                && !destination.is_loopback()
+                && !destination.is_multicast()
+        }
+    }
+}
@@ -0,0 +1,560 @@
+//! Linux sockets collection: the sockets are dumped with netlink sock_diag requests, and their
+//! owning processes are found by matching the socket inodes with the file descriptors in `/proc`.
+//! The routes are read from `/proc/net/route` and `/proc/net/ipv6_route`.
+
+use super::is_kept_route;
+use crate::host::{Connection, Host, ListeningSocket, Process, Route, SocketType};
+use anyhow::{bail, Context};
+use std::{
+    collections::HashMap,
+    io,
+    mem::size_of,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+// From linux/sock_diag.h, linux/inet_diag.h and net/tcp_states.h
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+const INET_DIAG_SKV6ONLY: u16 = 11;
+const TCP_ESTABLISHED: u8 = 1;
+const TCP_CLOSE: u8 = 7;
+const TCP_LISTEN: u8 = 10;
+
+// From linux/route.h
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+
+/// Size of the receive buffer, big enough for several sock_diag messages
+const RECV_BUFFER_SIZE: usize = 32 * 1024;
+
+/// `struct inet_diag_sockid`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct InetDiagSockId {
+    sport: [u8; 2],
+    dport: [u8; 2],
+    src: [u8; 16],
+    dst: [u8; 16],
+    interface: u32,
+    cookie: [u32; 2],
+}
+
+/// `struct inet_diag_req_v2`
+#[repr(C)]
+struct InetDiagReqV2 {
+    family: u8,
+    protocol: u8,
+    ext: u8,
+    pad: u8,
+    states: u32,
+    id: InetDiagSockId,
+}
+
+/// `struct inet_diag_msg`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct InetDiagMsg {
+    family: u8,
+    state: u8,
+    timer: u8,
+    retrans: u8,
+    id: InetDiagSockId,
+    expires: u32,
+    rqueue: u32,
+    wqueue: u32,
+    uid: u32,
+    inode: u32,
+}
+
+/// A netlink sock_diag dump request
+#[repr(C)]
+struct DumpRequest {
+    header: libc::nlmsghdr,
+    request: InetDiagReqV2,
+}
+
+/// A socket, as described by sock_diag
+struct DiagSocket {
+    socket_type: SocketType,
+    state: u8,
+    local_socket: SocketAddr,
+    peer_socket: SocketAddr,
+    inode: u32,
+    ipv6_only: Option<bool>,
+}
+
+/// Add the TCP and UDP sockets of this machine to `host`.
+/// As with ss, the sockets whose owning process cannot be found are skipped.
+pub fn add_sockets(host: &mut Host) -> anyhow::Result<()> {
+    let tcp_states = 1 << TCP_ESTABLISHED | 1 << TCP_LISTEN;
+    let udp_states = 1 << TCP_ESTABLISHED | 1 << TCP_CLOSE;
+    let mut sockets = Vec::new();
+    for family in [libc::AF_INET, libc::AF_INET6] {
+        sockets.extend(dump_sockets(
+            family as u8,
+            libc::IPPROTO_TCP as u8,
+            tcp_states,
+        )?);
+        sockets.extend(dump_sockets(
+            family as u8,
+            libc::IPPROTO_UDP as u8,
+            udp_states,
+        )?);
+    }
+    let owners = socket_owners();
+
+    for socket in sockets {
+        let Some(owner) = owners.get(&socket.inode) else {
+            continue;
+        };
+        let process = Process::new(&owner.name, owner.pid, host.name().to_string())
+            .with_start_time(owner.start_time);
+        match (&socket.socket_type, socket.state) {
+            (SocketType::TCP, TCP_LISTEN) | (SocketType::UDP, TCP_CLOSE) => {
+                let listening_socket = ListeningSocket::new(
+                    socket.local_socket,
+                    socket.socket_type,
+                    process,
+                    host.name().to_string(),
+                    socket.ipv6_only,
+                );
+                host.add_listening_socket(listening_socket);
+            }
+            (_, TCP_ESTABLISHED) => {
+                let connection = Connection::new(
+                    socket.local_socket,
+                    socket.peer_socket,
+                    socket.socket_type,
+                    process,
+                );
+                host.add_established_connection(connection);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Dump the sockets of the given family and protocol which are in one of the `states`
+fn dump_sockets(family: u8, protocol: u8, states: u32) -> anyhow::Result<Vec<DiagSocket>> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_SOCK_DIAG,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("unable to open netlink socket");
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // Send the dump request to the kernel
+    let request = DumpRequest {
+        header: libc::nlmsghdr {
+            nlmsg_len: size_of::<DumpRequest>() as u32,
+            nlmsg_type: SOCK_DIAG_BY_FAMILY,
+            nlmsg_flags: (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16,
+            nlmsg_seq: 1,
+            nlmsg_pid: 0,
+        },
+        request: InetDiagReqV2 {
+            family,
+            protocol,
+            ext: 0,
+            pad: 0,
+            states,
+            id: InetDiagSockId::default(),
+        },
+    };
+    let mut kernel_addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    kernel_addr.nl_family = libc::AF_NETLINK as u16;
+    let sent = unsafe {
+        libc::sendto(
+            fd.as_raw_fd(),
+            &request as *const DumpRequest as *const libc::c_void,
+            size_of::<DumpRequest>(),
+            0,
+            &kernel_addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            size_of::<libc::sockaddr_nl>() as u32,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error()).context("unable to send sock_diag request");
+    }
+
+    // Read the responses until the end of the dump
+    let protocol_socket_type = match protocol as i32 {
+        libc::IPPROTO_TCP => SocketType::TCP,
+        _ => SocketType::UDP,
+    };
+    let mut sockets = Vec::new();
+    let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+    loop {
+        let received = unsafe {
+            libc::recv(
+                fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error()).context("unable to read sock_diag response");
+        }
+        let data = &buffer[..received as usize];
+
+        let mut offset = 0;
+        while offset + size_of::<libc::nlmsghdr>() <= data.len() {
+            let header: libc::nlmsghdr =
+                unsafe { std::ptr::read_unaligned(data[offset..].as_ptr() as *const _) };
+            let message_len = header.nlmsg_len as usize;
+            if message_len < size_of::<libc::nlmsghdr>() || offset + message_len > data.len() {
+                bail!("malformed sock_diag response");
+            }
+            let payload = &data[offset + size_of::<libc::nlmsghdr>()..offset + message_len];
+
+            match header.nlmsg_type as i32 {
+                libc::NLMSG_DONE => return Ok(sockets),
+                libc::NLMSG_ERROR => {
+                    let Some(errno) = payload.get(..4) else {
+                        bail!("malformed sock_diag error");
+                    };
+                    let errno = i32::from_ne_bytes(errno.try_into()?);
+                    return Err(io::Error::from_raw_os_error(-errno))
+                        .context("sock_diag request failed");
+                }
+                _ => {
+                    if let Some(socket) = parse_diag_message(payload, protocol_socket_type.clone())
+                    {
+                        sockets.push(socket);
+                    }
+                }
+            }
+            offset += align(message_len);
+        }
+    }
+}
+
+/// Parse an `inet_diag_msg` and its attributes
+fn parse_diag_message(payload: &[u8], socket_type: SocketType) -> Option<DiagSocket> {
+    if payload.len() < size_of::<InetDiagMsg>() {
+        return None;
+    }
+    let message: InetDiagMsg = unsafe { std::ptr::read_unaligned(payload.as_ptr() as *const _) };
+
+    let (local_ip, peer_ip) = match message.family as i32 {
+        libc::AF_INET => {
+            let to_ip = |bytes: [u8; 16]| {
+                IpAddr::from(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+            };
+            (to_ip(message.id.src), to_ip(message.id.dst))
+        }
+        libc::AF_INET6 => (
+            IpAddr::from(Ipv6Addr::from(message.id.src)),
+            IpAddr::from(Ipv6Addr::from(message.id.dst)),
+        ),
+        _ => return None,
+    };
+
+    // Look for the IPV6_V6ONLY flag in the attributes
+    let mut ipv6_only = None;
+    let mut attributes = &payload[align(size_of::<InetDiagMsg>()).min(payload.len())..];
+    while attributes.len() >= 4 {
+        let len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        let kind = u16::from_ne_bytes([attributes[2], attributes[3]]);
+        if len < 4 || len > attributes.len() {
+            break;
+        }
+        if kind == INET_DIAG_SKV6ONLY && len > 4 {
+            ipv6_only = Some(attributes[4] != 0);
+        }
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+    if local_ip.is_ipv4() {
+        ipv6_only = None;
+    }
+
+    Some(DiagSocket {
+        socket_type,
+        state: message.state,
+        local_socket: SocketAddr::new(local_ip, u16::from_be_bytes(message.id.sport)),
+        peer_socket: SocketAddr::new(peer_ip, u16::from_be_bytes(message.id.dport)),
+        inode: message.inode,
+        ipv6_only,
+    })
+}
+
+/// Round up to the netlink alignment (`NLMSG_ALIGN` and `RTA_ALIGN`)
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Process owning a socket
+struct SocketOwner {
+    pid: u32,
+    name: String,
+    /// In clock ticks since the boot
+    start_time: Option<u64>,
+}
+
+/// Map the socket inodes to their owning process, by reading the file descriptors of every process
+/// in `/proc`. When a socket is shared, the first process found is kept.
+fn socket_owners() -> HashMap<u32, SocketOwner> {
+    let mut owners = HashMap::new();
+    let Ok(proc_entries) = std::fs::read_dir("/proc") else {
+        return owners;
+    };
+    for entry in proc_entries.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        // The processes may exit in the meantime, or belong to another user when not running as root
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let Ok(name) = std::fs::read_to_string(entry.path().join("comm")) else {
+            continue;
+        };
+        let name = name.trim_end().to_string();
+        let start_time = std::fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|stat| parse_start_time(&stat));
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|t| t.strip_prefix("socket:["))
+                .and_then(|t| t.strip_suffix(']'))
+                .and_then(|t| t.parse::<u32>().ok());
+            if let Some(inode) = inode {
+                owners.entry(inode).or_insert_with(|| SocketOwner {
+                    pid,
+                    name: name.clone(),
+                    start_time,
+                });
+            }
+        }
+    }
+    owners
+}
+
+/// Add the default gateways and the directly connected networks of this machine to `host`
+pub fn add_routes(host: &mut Host) -> anyhow::Result<()> {
+    let ipv4_routes = std::fs::read_to_string("/proc/net/route").context("/proc/net/route")?;
+    let routes = parse_ipv4_routes(&ipv4_routes);
+    // The IPv6 file is missing when IPv6 is disabled
+    let ipv6_routes = std::fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+    for route in routes.into_iter().chain(parse_ipv6_routes(&ipv6_routes)) {
+        host.add_route(route);
+    }
+    Ok(())
+}
+
+/// Parse the content of `/proc/net/route`, whose addresses are hexadecimal numbers in the native
+/// byte order, after a header line
+fn parse_ipv4_routes(content: &str) -> Vec<Route> {
+    let parse_ip = |field: &str| {
+        u32::from_str_radix(field, 16)
+            .ok()
+            .map(|ip| IpAddr::from(Ipv4Addr::from(ip.to_ne_bytes())))
+    };
+    let mut routes = Vec::new();
+    for line in content.lines().skip(1) {
+        // Iface, Destination, Gateway, Flags, RefCnt, Use, Metric, Mask, ...
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [interface, destination, gateway, flags, _, _, _, mask, ..] = fields[..] else {
+            continue;
+        };
+        let (Some(destination), Some(gateway), Some(IpAddr::V4(mask)), Ok(flags)) = (
+            parse_ip(destination),
+            parse_ip(gateway),
+            parse_ip(mask),
+            u32::from_str_radix(flags, 16),
+        ) else {
+            continue;
+        };
+        let prefix_len = u32::from(mask).count_ones() as u8;
+        let gateway = (flags & RTF_GATEWAY != 0).then_some(gateway);
+        if flags & RTF_UP != 0 && is_kept_route(destination, prefix_len, gateway) {
+            routes.push(Route::new(
+                destination,
+                prefix_len,
+                gateway,
+                Some(interface.to_string()),
+            ));
+        }
+    }
+    routes
+}
+
+/// Parse the content of `/proc/net/ipv6_route`, whose addresses are hexadecimal numbers in the
+/// network byte order
+fn parse_ipv6_routes(content: &str) -> Vec<Route> {
+    let parse_ip = |field: &str| {
+        u128::from_str_radix(field, 16)
+            .ok()
+            .map(|ip| IpAddr::from(Ipv6Addr::from(ip)))
+    };
+    let mut routes = Vec::new();
+    for line in content.lines() {
+        // Destination, prefix length, source, source prefix length, next hop, metric, reference
+        // count, use count, flags, interface
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [destination, prefix_len, _, _, gateway, _, _, _, flags, interface] = fields[..] else {
+            continue;
+        };
+        let (Some(destination), Ok(prefix_len), Some(gateway), Ok(flags)) = (
+            parse_ip(destination),
+            u8::from_str_radix(prefix_len, 16),
+            parse_ip(gateway),
+            u32::from_str_radix(flags, 16),
+        ) else {
+            continue;
+        };
+        let gateway = (flags & RTF_GATEWAY != 0).then_some(gateway);
+        let route = Route::new(
+            destination,
+            prefix_len,
+            gateway,
+            Some(interface.to_string()),
+        );
+        // The local table is listed too, with the same routes through the loopback interface
+        if flags & RTF_UP != 0
+            && is_kept_route(destination, prefix_len, gateway)
+            && !routes.contains(&route)
+        {
+            routes.push(route);
+        }
+    }
+    routes
+}
+
+/// Read the start time of a process from the content of its `/proc/<pid>/stat` file: the 22nd
+/// field, counted after the name which may contain spaces and parentheses
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let (_pid_and_name, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+
+    /// Open sockets in this process and check that they are found with the right process
+    #[test]
+    fn test_add_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let udp_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut host = Host::new("machine1");
+        add_sockets(&mut host).unwrap();
+
+        let pid = std::process::id();
+        let listening_sockets: Vec<_> = host
+            .listening_sockets()
+            .iter()
+            .filter(|s| *s.process().pid() == pid)
+            .map(|s| (*s.socket(), s.socket_type().clone(), s.ipv6_only().cloned()))
+            .collect();
+        assert!(listening_sockets.contains(&(
+            listener.local_addr().unwrap(),
+            SocketType::TCP,
+            None
+        )));
+        assert!(listening_sockets.contains(&(
+            udp_socket.local_addr().unwrap(),
+            SocketType::UDP,
+            None
+        )));
+
+        assert!(host.connections().iter().any(|c| *c.process().pid() == pid
+            && *c.local_socket() == client.local_addr().unwrap()
+            && *c.peer_socket() == client.peer_addr().unwrap()
+            && *c.socket_type() == SocketType::TCP));
+        assert!(host
+            .listening_sockets()
+            .iter()
+            .filter(|s| *s.process().pid() == pid)
+            .all(|s| s.process().start_time().is_some()));
+    }
+
+    /// The start time must be found after a process name with spaces and parentheses
+    #[test]
+    fn test_parse_start_time() {
+        let stat = "1234 (my (odd) name) S 1 1234 1234 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 \
+                    56789 10000000 500 18446744073709551615";
+        assert_eq!(parse_start_time(stat), Some(56789));
+        assert_eq!(parse_start_time("1234 (name) S 1"), None);
+    }
+
+    /// Only the default gateways and the directly connected networks must be kept
+    #[test]
+    fn test_parse_routes() {
+        // Destination, gateway, flags and mask of each line
+        let line = |destination: [u8; 4], gateway: [u8; 4], flags: &str, mask: [u8; 4]| {
+            let hex = |ip: [u8; 4]| format!("{:08X}", u32::from_ne_bytes(ip));
+            format!(
+                "eth0\t{}\t{}\t{flags}\t0\t0\t100\t{}\t0\t0\t0",
+                hex(destination),
+                hex(gateway),
+                hex(mask)
+            )
+        };
+        let ipv4_routes = [
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT"
+                .to_string(),
+            line([0; 4], [192, 168, 1, 1], "0003", [0; 4]),
+            line([192, 168, 1, 0], [0; 4], "0001", [255, 255, 255, 0]),
+            line([10, 0, 0, 0], [192, 168, 1, 254], "0003", [255, 0, 0, 0]),
+        ]
+        .join("\n");
+        assert_eq!(
+            parse_ipv4_routes(&ipv4_routes),
+            [
+                Route::new(
+                    "0.0.0.0".parse().unwrap(),
+                    0,
+                    Some("192.168.1.1".parse().unwrap()),
+                    Some("eth0".to_string())
+                ),
+                Route::new(
+                    "192.168.1.0".parse().unwrap(),
+                    24,
+                    None,
+                    Some("eth0".to_string())
+                ),
+            ]
+        );
+
+        let ipv6_routes = "\
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+00000000000000000000000000000001 80 00000000000000000000000000000000 00 00000000000000000000000000000000 00000000 00000002 00000000 80200001       lo
+ff000000000000000000000000000000 08 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000003 00000000 00000001     eth0
+";
+        assert_eq!(
+            parse_ipv6_routes(ipv6_routes),
+            [
+                Route::new(
+                    "fe80::".parse().unwrap(),
+                    64,
+                    None,
+                    Some("eth0".to_string())
+                ),
+                Route::new(
+                    "::".parse().unwrap(),
+                    0,
+                    Some("fe80::1".parse().unwrap()),
+                    Some("eth0".to_string())
+                ),
+            ]
+        );
+    }
+}
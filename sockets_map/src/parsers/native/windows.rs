@@ -0,0 +1,258 @@
+//! Windows sockets collection, with `GetExtendedTcpTable` and `GetExtendedUdpTable` for the sockets
+//! and their owning PID, and a ToolHelp snapshot for the processes names. The routes are read with
+//! `GetIpForwardTable2`.
+
+use super::is_kept_route;
+use crate::host::{Connection, Host, ListeningSocket, Process, Route, SocketType};
+use anyhow::bail;
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    mem::size_of,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, ERROR_INSUFFICIENT_BUFFER, INVALID_HANDLE_VALUE, NO_ERROR},
+    NetworkManagement::IpHelper::{
+        FreeMibTable, GetExtendedTcpTable, GetExtendedUdpTable, GetIpForwardTable2,
+        MIB_IPFORWARD_TABLE2, MIB_TCP6ROW_OWNER_PID, MIB_TCPROW_OWNER_PID, MIB_TCP_STATE_ESTAB,
+        MIB_TCP_STATE_LISTEN, MIB_UDP6ROW_OWNER_PID, MIB_UDPROW_OWNER_PID, TCP_TABLE_OWNER_PID_ALL,
+        UDP_TABLE_OWNER_PID,
+    },
+    Networking::WinSock::{AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR_INET},
+    System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    },
+};
+
+/// A socket, as described by the IP Helper tables
+struct TableSocket {
+    socket_type: SocketType,
+    state: Option<i32>,
+    local_socket: SocketAddr,
+    peer_socket: Option<SocketAddr>,
+    pid: u32,
+}
+
+/// Add the TCP and UDP sockets of this machine to `host`.
+/// The UDP sockets are all considered as listening, as in the netstat output.
+pub fn add_sockets(host: &mut Host) -> anyhow::Result<()> {
+    let mut sockets = Vec::new();
+
+    // IPv4 TCP
+    let table = get_table(|table, size| unsafe {
+        GetExtendedTcpTable(table, size, 0, AF_INET as u32, TCP_TABLE_OWNER_PID_ALL, 0)
+    })?;
+    for row in unsafe { table_rows::<MIB_TCPROW_OWNER_PID>(&table) } {
+        sockets.push(TableSocket {
+            socket_type: SocketType::TCP,
+            state: Some(row.dwState as i32),
+            local_socket: ipv4_socket(row.dwLocalAddr, row.dwLocalPort),
+            peer_socket: Some(ipv4_socket(row.dwRemoteAddr, row.dwRemotePort)),
+            pid: row.dwOwningPid,
+        });
+    }
+
+    // IPv6 TCP
+    let table = get_table(|table, size| unsafe {
+        GetExtendedTcpTable(table, size, 0, AF_INET6 as u32, TCP_TABLE_OWNER_PID_ALL, 0)
+    })?;
+    for row in unsafe { table_rows::<MIB_TCP6ROW_OWNER_PID>(&table) } {
+        sockets.push(TableSocket {
+            socket_type: SocketType::TCP,
+            state: Some(row.dwState as i32),
+            local_socket: ipv6_socket(row.ucLocalAddr, row.dwLocalPort),
+            peer_socket: Some(ipv6_socket(row.ucRemoteAddr, row.dwRemotePort)),
+            pid: row.dwOwningPid,
+        });
+    }
+
+    // IPv4 UDP
+    let table = get_table(|table, size| unsafe {
+        GetExtendedUdpTable(table, size, 0, AF_INET as u32, UDP_TABLE_OWNER_PID, 0)
+    })?;
+    for row in unsafe { table_rows::<MIB_UDPROW_OWNER_PID>(&table) } {
+        sockets.push(TableSocket {
+            socket_type: SocketType::UDP,
+            state: None,
+            local_socket: ipv4_socket(row.dwLocalAddr, row.dwLocalPort),
+            peer_socket: None,
+            pid: row.dwOwningPid,
+        });
+    }
+
+    // IPv6 UDP
+    let table = get_table(|table, size| unsafe {
+        GetExtendedUdpTable(table, size, 0, AF_INET6 as u32, UDP_TABLE_OWNER_PID, 0)
+    })?;
+    for row in unsafe { table_rows::<MIB_UDP6ROW_OWNER_PID>(&table) } {
+        sockets.push(TableSocket {
+            socket_type: SocketType::UDP,
+            state: None,
+            local_socket: ipv6_socket(row.ucLocalAddr, row.dwLocalPort),
+            peer_socket: None,
+            pid: row.dwOwningPid,
+        });
+    }
+
+    let process_names = process_names()?;
+    for socket in sockets {
+        let Some(process_name) = process_names.get(&socket.pid) else {
+            log::debug!(
+                "unable to find process name for PID {}, skipping",
+                socket.pid
+            );
+            continue;
+        };
+        let process = Process::new(process_name, socket.pid, host.name().to_string());
+        match (socket.state, socket.peer_socket) {
+            (Some(MIB_TCP_STATE_ESTAB), Some(peer_socket)) => {
+                let connection = Connection::new(
+                    socket.local_socket,
+                    peer_socket,
+                    socket.socket_type,
+                    process,
+                );
+                host.add_established_connection(connection);
+            }
+            (Some(MIB_TCP_STATE_LISTEN), _) | (None, _) => {
+                // IPV6_V6ONLY is not reported, so assume it is set, as for netstat
+                let ipv6_only = socket.local_socket.is_ipv6().then_some(true);
+                let listening_socket = ListeningSocket::new(
+                    socket.local_socket,
+                    socket.socket_type,
+                    process,
+                    host.name().to_string(),
+                    ipv6_only,
+                );
+                host.add_listening_socket(listening_socket);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the default gateways and the directly connected networks of this machine to `host`. The
+/// interfaces are named by their index, as in the IPv6 routes of `route print`.
+pub fn add_routes(host: &mut Host) -> anyhow::Result<()> {
+    let mut table: *mut MIB_IPFORWARD_TABLE2 = std::ptr::null_mut();
+    let result = unsafe { GetIpForwardTable2(AF_UNSPEC, &mut table) };
+    if result != NO_ERROR {
+        bail!("unable to retrieve the routing table: error {result}");
+    }
+    let rows = unsafe {
+        std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize)
+    };
+    for row in rows {
+        let (Some(destination), Some(next_hop)) = (
+            inet_ip(&row.DestinationPrefix.Prefix),
+            inet_ip(&row.NextHop),
+        ) else {
+            continue;
+        };
+        let prefix_len = row.DestinationPrefix.PrefixLength;
+        // The directly connected networks have no next hop
+        let gateway = (!next_hop.is_unspecified()).then_some(next_hop);
+        if is_kept_route(destination, prefix_len, gateway) {
+            host.add_route(Route::new(
+                destination,
+                prefix_len,
+                gateway,
+                Some(row.InterfaceIndex.to_string()),
+            ));
+        }
+    }
+    unsafe { FreeMibTable(table as *const c_void) };
+
+    Ok(())
+}
+
+/// Get the IP address of a `SOCKADDR_INET`, `None` if it is neither IPv4 nor IPv6
+fn inet_ip(address: &SOCKADDR_INET) -> Option<IpAddr> {
+    unsafe {
+        match address.si_family {
+            AF_INET => Some(IpAddr::from(Ipv4Addr::from(
+                address.Ipv4.sin_addr.S_un.S_addr.to_ne_bytes(),
+            ))),
+            AF_INET6 => Some(IpAddr::from(Ipv6Addr::from(address.Ipv6.sin6_addr.u.Byte))),
+            _ => None,
+        }
+    }
+}
+
+/// Call one of the `GetExtended*Table` functions, growing the buffer until the table fits in it.
+/// The buffer is made of `u32` so that the rows are properly aligned.
+fn get_table(get: impl Fn(*mut c_void, *mut u32) -> u32) -> anyhow::Result<Vec<u32>> {
+    let mut buffer: Vec<u32> = vec![0; 1];
+    loop {
+        let mut size = (buffer.len() * size_of::<u32>()) as u32;
+        match get(buffer.as_mut_ptr() as *mut c_void, &mut size) {
+            NO_ERROR => return Ok(buffer),
+            ERROR_INSUFFICIENT_BUFFER => {
+                buffer.resize((size as usize).div_ceil(size_of::<u32>()), 0)
+            }
+            e => bail!("unable to retrieve the sockets table: error {e}"),
+        }
+    }
+}
+
+/// Get the rows of a table filled by `get_table`, which starts with the number of rows
+///
+/// # Safety
+///
+/// `T` must be the row type of the table in `buffer`
+unsafe fn table_rows<T: Copy>(buffer: &[u32]) -> Vec<T> {
+    let count = buffer[0] as usize;
+    let rows = buffer.as_ptr().add(1) as *const T;
+    std::slice::from_raw_parts(rows, count).to_vec()
+}
+
+/// Build an IPv4 socket from an address and a port in network byte order
+fn ipv4_socket(addr: u32, port: u32) -> SocketAddr {
+    SocketAddr::new(
+        IpAddr::from(Ipv4Addr::from(addr.to_ne_bytes())),
+        u16::from_be(port as u16),
+    )
+}
+
+/// Build an IPv6 socket from an address and a port in network byte order
+fn ipv6_socket(addr: [u8; 16], port: u32) -> SocketAddr {
+    SocketAddr::new(
+        IpAddr::from(Ipv6Addr::from(addr)),
+        u16::from_be(port as u16),
+    )
+}
+
+/// Map the PIDs of the running processes to their executable name
+fn process_names() -> anyhow::Result<HashMap<u32, String>> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        bail!(
+            "unable to list the processes: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    let mut names = HashMap::new();
+    let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+    entry.dwSize = size_of::<PROCESSENTRY32W>() as u32;
+    let mut found = unsafe { Process32FirstW(snapshot, &mut entry) };
+    while found != 0 {
+        let name_len = entry
+            .szExeFile
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(entry.szExeFile.len());
+        names.insert(
+            entry.th32ProcessID,
+            String::from_utf16_lossy(&entry.szExeFile[..name_len]),
+        );
+        found = unsafe { Process32NextW(snapshot, &mut entry) };
+    }
+    unsafe { CloseHandle(snapshot) };
+
+    Ok(names)
+}
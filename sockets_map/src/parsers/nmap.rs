@@ -1,7 +1,7 @@
 //! This modules parses the output of the nmap command to retrieve information from remote machines
 //! on which the user could not execute more accurate commands such as ss or netsat.
 
-use crate::host;
+use crate::{host, parsers::diagnostics::ParseDiagnostics};
 use anyhow::{bail, Context};
 use log;
 
@@ -16,8 +16,18 @@ impl host::Host {
         hostname: &str,
         nmap_output_file_path: std::path::PathBuf,
     ) -> anyhow::Result<Self> {
+        Self::from_nmap_output_file_with_diagnostics(hostname, nmap_output_file_path)
+            .map(|(host, _diagnostics)| host)
+    }
+
+    /// Same as [`Self::from_nmap_output_file`], along with the port lines which could not be used
+    pub fn from_nmap_output_file_with_diagnostics(
+        hostname: &str,
+        nmap_output_file_path: std::path::PathBuf,
+    ) -> anyhow::Result<(Self, ParseDiagnostics)> {
         log::debug!("Parsing nmap output file for host {}", hostname);
         let mut host = host::Host::new(hostname);
+        let mut diagnostics = ParseDiagnostics::new(hostname);
 
         let nmap_output_file_contents = match std::fs::read_to_string(&nmap_output_file_path) {
             Ok(f) => f,
@@ -28,16 +38,23 @@ impl host::Host {
             }
         };
 
-        // Parse IP
-        let ip_str = match nmap_output_file_path.file_name() {
-            Some(f) => f
-                .to_string_lossy()
+        // Parse IP, from the file name or else from the scan report, for the files whose type was
+        // detected from their contents
+        let file_name = match nmap_output_file_path.file_name() {
+            Some(f) => f.to_string_lossy().to_string(),
+            None => bail!("unable to get nmap output filename"),
+        };
+        let ip_str = match file_name.contains(".nmap_") {
+            true => file_name
                 .split('.')
                 .skip(1)
                 .collect::<Vec<&str>>()
                 .join(".")
                 .replace("nmap_", ""),
-            None => bail!("unable to get nmap output filename"),
+            false => match scan_report_ip(&nmap_output_file_contents) {
+                Some(ip_str) => ip_str.to_string(),
+                None => bail!("unable to find the scanned IP in {nmap_output_file_path:?}"),
+            },
         };
         let ip: std::net::IpAddr = ip_str
             .parse()
@@ -46,7 +63,8 @@ impl host::Host {
 
         // Parse lines
         let lines = nmap_output_file_contents.lines();
-        for line in lines {
+        diagnostics.count_lines(lines.clone().count());
+        for (index, line) in lines.enumerate() {
             // Skip lines that do not start with a number (port)
             match line.chars().next() {
                 Some(c) => {
@@ -83,7 +101,13 @@ impl host::Host {
                                 let socket_type = match proto {
                                     "tcp" => host::SocketType::TCP,
                                     "udp" => host::SocketType::UDP,
-                                    _ => continue,
+                                    _ => {
+                                        diagnostics.warning(
+                                            Some(index + 1),
+                                            "the protocol of the port is neither TCP nor UDP",
+                                        );
+                                        continue;
+                                    }
                                 };
                                 let process = host::Process::new(
                                     format!("{service}?").as_str(),
@@ -103,15 +127,28 @@ impl host::Host {
                                     },
                                 );
                                 host.add_listening_socket(listening_socket);
+                                diagnostics.parsed_line();
+                                continue;
                             }
                         }
                     }
                 }
-            } else {
-                continue;
             }
+            diagnostics.error(
+                Some(index + 1),
+                "the port line is incomplete, the scan may be truncated",
+            );
         }
 
-        Ok(host)
+        Ok((host, diagnostics))
     }
 }
+
+/// IP of the `Nmap scan report for <ip>` or `Nmap scan report for <name> (<ip>)` line
+fn scan_report_ip(nmap_output: &str) -> Option<&str> {
+    nmap_output
+        .lines()
+        .find_map(|line| line.strip_prefix("Nmap scan report for "))
+        .and_then(|target| target.split_whitespace().last())
+        .map(|ip| ip.trim_start_matches('(').trim_end_matches(')'))
+}
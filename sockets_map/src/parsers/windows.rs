@@ -4,18 +4,24 @@
 pub mod agent_parser;
 pub mod file_parser;
 
-use crate::host::{self, Host, ListeningSocket, Process, SocketType};
+use crate::host::{self, Host, ListeningSocket, Process, Route, SocketType};
+use anyhow::Context;
 use log;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::{diagnostics::ParseDiagnostics, parse_network};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct WindowsHostRawData {
-    hostname: String,
-    network_output: String,
-    tasklist_output: String,
-    ips: Vec<IpAddr>,
+    pub(super) hostname: String,
+    pub(super) network_output: String,
+    pub(super) tasklist_output: String,
+    pub(super) ips: Vec<IpAddr>,
+    /// Output of the `route print` command, if it could be run
+    #[serde(default)]
+    pub(super) route_output: Option<String>,
 }
 
 impl WindowsHostRawData {
@@ -24,16 +30,32 @@ impl WindowsHostRawData {
         network_output: String,
         tasklist_output: String,
         ips: Vec<IpAddr>,
+        route_output: Option<String>,
     ) -> Self {
         Self {
             hostname,
             network_output,
             tasklist_output,
             ips,
+            route_output,
         }
     }
 }
 
+/// Decode the output of a command, encoded with the console code page `code_page` (for instance
+/// 850 for the western european consoles).
+/// Unknown code pages are decoded as UTF-8, with the invalid sequences replaced.
+pub fn decode_command_output(output: &[u8], code_page: u16) -> String {
+    // The DOS code pages of the consoles, which encoding_rs does not handle
+    if let Some(table) = oem_cp::code_table::DECODING_TABLE_CP_MAP.get(&code_page) {
+        return table.decode_string_lossy(output);
+    }
+    match codepage::to_encoding_no_replacement(code_page) {
+        Some(encoding) => encoding.decode_without_bom_handling(output).0.into_owned(),
+        None => String::from_utf8_lossy(output).into_owned(),
+    }
+}
+
 /// Parse tasklist output
 /// Returns a hashmap with (<pid>, "<process name>")
 fn parse_tasklist_command_output(
@@ -78,51 +100,195 @@ fn parse_tasklist_command_output(
     Ok(hashmap)
 }
 
-/// Parse ip command output and add
-fn parse_ip_command_output(
+/// Address of the host, as listed by the `Get-NetIPAddress` command
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+struct NetIpAddress {
+    #[serde(rename = "IPAddress")]
+    ip_address: Option<String>,
+    #[serde(rename = "InterfaceAlias")]
+    interface_alias: Option<String>,
+}
+
+/// `ConvertTo-Json` writes an object instead of an array for a single address
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NetIpAddresses {
+    Many(Vec<NetIpAddress>),
+    One(NetIpAddress),
+}
+
+/// Format of the output of the `Get-NetIPAddress` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IpOutputFormat {
+    /// Printed to the console, as `IPAddress : 10.0.0.1` lines
+    Text,
+    /// Exported with `Export-Csv`, whose first line may be a `#TYPE` comment
+    Csv,
+    /// Exported with `ConvertTo-Json`
+    Json,
+}
+
+/// Tell the format of the output of the `Get-NetIPAddress` command from its first line
+fn ip_output_format(contents: &str) -> IpOutputFormat {
+    let first_line = contents
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    match first_line.chars().next() {
+        Some('[' | '{') => IpOutputFormat::Json,
+        Some('"' | '#') => IpOutputFormat::Csv,
+        _ => IpOutputFormat::Text,
+    }
+}
+
+/// Parse an address of the `Get-NetIPAddress` command, without its zone index such as `%12`
+fn parse_net_ip_address(address: &str) -> Option<IpAddr> {
+    let address = address.trim();
+    address.split('%').next().unwrap_or(address).parse().ok()
+}
+
+/// Parse the addresses and the aliases of their interfaces from the text output of the
+/// `Get-NetIPAddress` command, in which the `InterfaceAlias` line follows the `IPAddress` line
+fn parse_ip_text_output(contents: &str) -> Vec<(IpAddr, Option<String>)> {
+    let mut addresses = Vec::<(IpAddr, Option<String>)>::new();
+    for line in contents.lines() {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        match name.trim() {
+            "IPAddress" => {
+                if let Some(ip_addr) = parse_net_ip_address(value) {
+                    addresses.push((ip_addr, None));
+                }
+            }
+            "InterfaceAlias" => {
+                if let Some((_, interface_alias @ None)) = addresses.last_mut() {
+                    *interface_alias = Some(value.trim().to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+    addresses
+}
+
+/// Parse the output of the `Get-NetIPAddress` command exported with `Export-Csv`, with commas or
+/// with the semicolons of `-UseCulture`
+fn parse_ip_csv_output(contents: &str) -> anyhow::Result<Vec<NetIpAddress>> {
+    let rows: Vec<&str> = contents
+        .lines()
+        .filter(|line| !line.trim_start_matches('\u{feff}').starts_with('#'))
+        .collect();
+    let delimiter = match rows.first().is_some_and(|header| header.contains("\";\"")) {
+        true => b';',
+        false => b',',
+    };
+    let rows = rows.join("\n");
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(rows.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(Into::into)
+}
+
+/// Parse the output of the `Get-NetIPAddress` command exported with `ConvertTo-Json`
+fn parse_ip_json_output(contents: &str) -> anyhow::Result<Vec<NetIpAddress>> {
+    Ok(
+        match serde_json::from_str(contents.trim_start_matches('\u{feff}'))? {
+            NetIpAddresses::Many(addresses) => addresses,
+            NetIpAddresses::One(address) => vec![address],
+        },
+    )
+}
+
+/// Parse the IPs of the host from the output of the `Get-NetIPAddress` command, printed to the
+/// console or exported with `Export-Csv` or `ConvertTo-Json`
+pub(crate) fn parse_ip_command_output(
     ip_command_output_contents: String,
     hostname: &str,
 ) -> anyhow::Result<Vec<IpAddr>> {
-    let lines = ip_command_output_contents.lines();
+    let format = ip_output_format(&ip_command_output_contents);
+    let exported = match format {
+        IpOutputFormat::Text => None,
+        IpOutputFormat::Csv => Some(parse_ip_csv_output(&ip_command_output_contents)),
+        IpOutputFormat::Json => Some(parse_ip_json_output(&ip_command_output_contents)),
+    };
+    let addresses = match exported {
+        None => parse_ip_text_output(&ip_command_output_contents),
+        Some(exported) => exported
+            .with_context(|| {
+                format!("unable to parse the Get-NetIPAddress {format:?} output of host {hostname}")
+            })?
+            .into_iter()
+            .filter_map(|address| {
+                let ip_addr = parse_net_ip_address(address.ip_address.as_deref()?)?;
+                Some((ip_addr, address.interface_alias))
+            })
+            .collect(),
+    };
+
     let mut ips = Vec::<IpAddr>::new();
-    for line in lines {
-        if line.starts_with("IPAddress") {
-            // Clean line
-            let mut trimmed_line = line.replace("  ", " ");
-            while trimmed_line.contains("  ") {
-                trimmed_line = trimmed_line.replace("  ", " ");
-            }
+    for (ip_addr, interface_alias) in addresses {
+        log::debug!(
+            "adding ip {} ({}) to host {}",
+            ip_addr,
+            interface_alias.as_deref().unwrap_or("unknown interface"),
+            hostname
+        );
+        ips.push(ip_addr)
+    }
+    Ok(ips)
+}
 
-            // Trim link-local addresses
-            if trimmed_line.contains('%') {
-                trimmed_line = match trimmed_line.split('%').next() {
-                    Some(t) => t,
-                    None => continue,
-                }
-                .to_string();
-            }
+/// Whether the line is the `[name.exe]` line which `netstat -b` prints under a connection
+fn is_process_name_line(line: &str) -> bool {
+    let line = line.trim();
+    line.len() > 2 && line.starts_with('[') && line.ends_with(']')
+}
+
+/// Whether the netstat output was captured with `netstat -anob`, whose connection lines are
+/// followed by the name of their executable, in which case the tasklist output is not needed
+pub(crate) fn is_netstat_b_output(contents: &str) -> bool {
+    contents.lines().any(is_process_name_line)
+}
 
-            let Ok(ip_addr) = match trimmed_line.split(' ').nth(2) {
-                Some(s) => s,
-                None => continue,
+/// Executable names of a `netstat -b` output, by index of their connection line. The component
+/// lines, such as the service names, and the lines of the sockets without ownership information
+/// come in between and are ignored.
+fn netstat_b_process_names(contents: &str) -> HashMap<usize, String> {
+    let mut process_names = HashMap::new();
+    let mut connection_index = None;
+    for (index, line) in contents.lines().enumerate() {
+        let trimmed_line = line.trim();
+        if trimmed_line.starts_with("TCP") || trimmed_line.starts_with("UDP") {
+            connection_index = Some(index);
+        } else if is_process_name_line(trimmed_line) {
+            // Only the first executable line belongs to the connection
+            if let Some(index) = connection_index.take() {
+                let name = &trimmed_line[1..trimmed_line.len() - 1];
+                process_names.insert(index, name.to_string());
             }
-            .parse() else { continue };
-            log::debug!("adding ip {} to host {}", ip_addr, hostname);
-            ips.push(ip_addr)
         }
     }
-    Ok(ips)
+    process_names
 }
 
 fn parse_netstat_contents(
     netstat_command_output_file_contents: String,
     process_name_pid_hashmap: std::collections::HashMap<u32, String>,
     host: &mut Host,
+    diagnostics: &mut ParseDiagnostics,
 ) {
+    // The names printed by `netstat -b` are preferred to the tasklist output, which may have
+    // been captured a little later
+    let process_names = netstat_b_process_names(&netstat_command_output_file_contents);
     let lines = netstat_command_output_file_contents.lines();
+    diagnostics.count_lines(lines.clone().count());
 
     // Iterate over lines
-    for line in lines {
+    for (index, line) in lines.enumerate() {
+        let line_number = Some(index + 1);
         // Clean line
         let mut trimmed_line = line.replace("  ", " ").trim_start().to_string();
         while trimmed_line.contains("  ") {
@@ -140,21 +306,28 @@ fn parse_netstat_contents(
             log::debug!("peer_socket_str: {}", peer_socket_str);
             let Some(state) = line_split.clone().nth(3) else { continue };
             log::debug!("state: {}", state);
-            let pid: u32 = match match line_split.nth(4) {
-                Some(l) => l,
-                None => continue,
-            }
-            .parse()
-            {
-                Ok(p) => p,
-                Err(_) => continue,
+            let pid: u32 = match line_split.nth(4).map(str::parse) {
+                Some(Ok(p)) => p,
+                _ => {
+                    diagnostics.error(
+                        line_number,
+                        "the line is incomplete, the capture may be truncated",
+                    );
+                    continue;
+                }
             };
 
             // Find process name
-            let process_name = match process_name_pid_hashmap.get(&pid) {
+            let process_name = match process_names
+                .get(&index)
+                .or_else(|| process_name_pid_hashmap.get(&pid))
+            {
                 Some(p) => p,
                 None => {
-                    log::warn!("unable to find process name for PID {}, skipping", pid);
+                    diagnostics.missing_process(
+                        line_number,
+                        "the PID of the line is not in the tasklist output, the process may have exited in between",
+                    );
                     continue;
                 }
             };
@@ -174,6 +347,7 @@ fn parse_netstat_contents(
                     let connection =
                         host::Connection::new(local_socket, peer_socket, SocketType::TCP, process);
                     host.add_established_connection(connection);
+                    diagnostics.parsed_line();
                 }
                 // Parse listening connections
                 "LISTENING" => {
@@ -193,6 +367,7 @@ fn parse_netstat_contents(
                         },
                     );
                     host.add_listening_socket(listening_socket);
+                    diagnostics.parsed_line();
                 }
                 _ => {}
             }
@@ -200,8 +375,111 @@ fn parse_netstat_contents(
     }
 }
 
+/// Parse the default gateways and the directly connected networks from the active routes of the
+/// `route print` command output. The loopback, multicast and single host routes are left out.
+pub(crate) fn parse_route_print_output(route_print_output: &str) -> Vec<Route> {
+    let mut routes = Vec::<Route>::new();
+    let mut in_active_routes = false;
+    // IPv6 route whose gateway is wrapped on the next line, after a long destination
+    let mut wrapped_route: Option<(String, &str)> = None;
+
+    for line in route_print_output.lines() {
+        let line = line.trim();
+        if line.starts_with("Active Routes:") {
+            in_active_routes = true;
+            continue;
+        }
+        if line.starts_with('=') {
+            in_active_routes = false;
+            wrapped_route = None;
+            continue;
+        }
+        if !in_active_routes {
+            continue;
+        }
+
+        let split_line: Vec<&str> = line.split_whitespace().collect();
+        let (destination, prefix_len, gateway, interface) = match split_line[..] {
+            // IPv4: Network Destination, Netmask, Gateway, Interface, Metric
+            [destination, netmask, gateway, interface, _metric] => {
+                let (Ok(destination), Ok(netmask)) =
+                    (destination.parse::<Ipv4Addr>(), netmask.parse::<Ipv4Addr>())
+                else {
+                    continue;
+                };
+                let prefix_len = u32::from(netmask).count_ones() as u8;
+                (
+                    IpAddr::V4(destination),
+                    prefix_len,
+                    gateway,
+                    interface.to_string(),
+                )
+            }
+            // IPv6: If, Metric, Network Destination, Gateway
+            [interface, _metric, destination, gateway] => {
+                let Some((destination, prefix_len)) = parse_network(destination) else {
+                    continue;
+                };
+                (destination, prefix_len, gateway, interface.to_string())
+            }
+            [interface, _metric, destination] => {
+                wrapped_route = Some((interface.to_string(), destination));
+                continue;
+            }
+            [gateway] => {
+                let Some((interface, destination)) = wrapped_route.take() else {
+                    continue;
+                };
+                let Some((destination, prefix_len)) = parse_network(destination) else {
+                    continue;
+                };
+                (destination, prefix_len, gateway, interface)
+            }
+            _ => continue,
+        };
+        let gateway: Option<IpAddr> = match gateway {
+            "On-link" => None,
+            gateway => match gateway.parse() {
+                Ok(gateway) => Some(gateway),
+                Err(_) => continue,
+            },
+        };
+
+        // Keep the default gateways and the directly connected networks
+        let single_host = match destination {
+            IpAddr::V4(_) => prefix_len == 32,
+            IpAddr::V6(_) => prefix_len == 128,
+        };
+        let relevant = match prefix_len {
+            0 => gateway.is_some(),
+            _ => {
+                gateway.is_none()
+                    && !single_host
+                    && !destination.is_loopback()
+                    && !destination.is_multicast()
+            }
+        };
+        if !relevant {
+            continue;
+        }
+        log::debug!("route line: {}", line);
+        let route = Route::new(destination, prefix_len, gateway, Some(interface));
+        if !routes.contains(&route) {
+            routes.push(route);
+        }
+    }
+    routes
+}
+
 impl From<WindowsHostRawData> for anyhow::Result<Host> {
-    /// Parse the output of the netstat and ip command address command to get the host IPs
+    fn from(host_data: WindowsHostRawData) -> Self {
+        host_data.parse_host().map(|(host, _diagnostics)| host)
+    }
+}
+
+impl WindowsHostRawData {
+    /// Parse the output of the netstat and ip command address command to get the host IPs, along
+    /// with the problems met on the lines of the netstat output.
     /// The file contains the concatenation of the outputs of the following commands :
     ///
     /// ```bash
@@ -209,30 +487,331 @@ impl From<WindowsHostRawData> for anyhow::Result<Host> {
     /// netstat -p tcp -ano
     /// tasklist /FO CSV
     /// ```
-    fn from(host_data: WindowsHostRawData) -> Self {
+    ///
+    /// The tasklist output may be empty when netstat was run with `-anob`.
+    pub fn parse_host(self) -> anyhow::Result<(Host, ParseDiagnostics)> {
         log::debug!(
             "Parsing netstat, tasklist and get-netipaddress commands output for host {}",
-            host_data.hostname
+            self.hostname
         );
-        let mut host = Host::new(&host_data.hostname);
+        let mut host = Host::new(&self.hostname);
+        let mut diagnostics = ParseDiagnostics::new(&self.hostname);
 
         // Add IPs
-        host_data.ips.iter().for_each(|ip| host.add_ip(*ip));
+        self.ips.iter().for_each(|ip| host.add_ip(*ip));
+
+        // Add routes
+        if let Some(route_output) = &self.route_output {
+            for route in parse_route_print_output(route_output) {
+                host.add_route(route);
+            }
+        }
 
         // Parse process list
-        let process_name_pid_hashmap =
-            match parse_tasklist_command_output(host_data.tasklist_output) {
-                Ok(h) => h,
-                Err(e) => return Err(e),
-            };
+        let process_name_pid_hashmap = parse_tasklist_command_output(self.tasklist_output)?;
 
         // Parse netstat output contents
         parse_netstat_contents(
-            host_data.network_output,
+            self.network_output,
             process_name_pid_hashmap,
             &mut host,
+            &mut diagnostics,
+        );
+
+        Ok((host, diagnostics))
+    }
+}
+
+/// Parse a netstat output which comes without the tasklist file, such as the one piped to
+/// `sockets_map quick`. The process names are read from the tasklist CSV lines found in the same
+/// contents, if any, and the other processes are named after their PID.
+pub(crate) fn parse_standalone_netstat_output(
+    hostname: &str,
+    contents: &str,
+    ips: Vec<IpAddr>,
+) -> Host {
+    let mut host = Host::new(hostname);
+    ips.into_iter().for_each(|ip| host.add_ip(ip));
+
+    let tasklist_lines: Vec<&str> = contents
+        .lines()
+        .filter(|line| line.trim_start().starts_with('"'))
+        .collect();
+    let mut process_name_pid_hashmap =
+        parse_tasklist_command_output(tasklist_lines.join("\n")).unwrap_or_default();
+    for line in contents.lines() {
+        let line = line.trim_start();
+        if !(line.starts_with("TCP") || line.starts_with("UDP")) {
+            continue;
+        }
+        if let Some(pid) = line.split_whitespace().last().and_then(|p| p.parse().ok()) {
+            process_name_pid_hashmap
+                .entry(pid)
+                .or_insert_with(|| format!("PID {pid}"));
+        }
+    }
+    // The processes missing from the tasklist lines are named after their PID, so there is
+    // nothing to report
+    let mut diagnostics = ParseDiagnostics::new(hostname);
+    parse_netstat_contents(
+        contents.to_string(),
+        process_name_pid_hashmap,
+        &mut host,
+        &mut diagnostics,
+    );
+    host
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// tasklist /FO CSV output of a french console, encoded with the code page 850
+    const TASKLIST_OUTPUT_CP850: &[u8] = b"\
+\"Nom de l'image\",\"PID\",\"Nom de la session\",\"Num\x82ro de session\",\"Utilisation\"\r
+\"svchost.exe\",\"1044\",\"Services\",\"0\",\"12\xff345 Ko\"\r
+\"Num\x82risation.exe\",\"4242\",\"Console\",\"1\",\"8\xff192 Ko\"\r
+\"Contr\x93leur.exe\",\"5150\",\"Console\",\"1\",\"4\xff096 Ko\"\r
+";
+
+    /// netstat -anob output, whose connection lines are followed by the names of the services and
+    /// of the executables
+    const NETSTAT_B_OUTPUT: &str = "\
+\r
+Active Connections\r
+\r
+  Proto  Local Address          Foreign Address        State           PID\r
+  TCP    0.0.0.0:135            0.0.0.0:0              LISTENING       1044\r
+  RpcSs\r
+ [svchost.exe]\r
+  TCP    0.0.0.0:445            0.0.0.0:0              LISTENING       4\r
+ Can not obtain ownership information\r
+  TCP    0.0.0.0:3389           0.0.0.0:0              LISTENING       1388\r
+  TermService\r
+ [svchost.exe]\r
+  TCP    10.0.0.1:49712         10.0.0.2:443           ESTABLISHED     6204\r
+ [firefox.exe]\r
+  TCP    [::]:135               [::]:0                 LISTENING       1044\r
+  RpcSs\r
+ [svchost.exe]\r
+  UDP    0.0.0.0:123            *:*                                    1520\r
+  W32Time\r
+ [svchost.exe]\r
+";
+
+    /// The executables printed by `netstat -b` must name the processes without the tasklist
+    #[test]
+    fn test_parse_netstat_b_output() {
+        assert!(is_netstat_b_output(NETSTAT_B_OUTPUT));
+        assert!(!is_netstat_b_output(
+            "  TCP    [::]:135               [::]:0                 LISTENING       1044\r\n"
+        ));
+
+        let host_data = WindowsHostRawData::new(
+            "machine1".to_string(),
+            NETSTAT_B_OUTPUT.to_string(),
+            String::new(),
+            vec!["10.0.0.1".parse().unwrap()],
+            None,
         );
+        let (host, diagnostics) = host_data.parse_host().unwrap();
+        let listener = |port| {
+            host.listening_sockets()
+                .iter()
+                .find(|s| s.port() == port)
+                .map(|s| s.process().name().to_string())
+        };
+        assert_eq!(listener(135).as_deref(), Some("svchost.exe"));
+        assert_eq!(listener(3389).as_deref(), Some("svchost.exe"));
+        // The line without ownership information has no process
+        assert_eq!(listener(445), None);
+        assert_eq!(diagnostics.diagnostics().len(), 1);
+        assert_eq!(host.listening_sockets().len(), 3);
+
+        let connections = host.connections();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].process().name(), "firefox.exe");
+        assert_eq!(*connections[0].process().pid(), 6204);
+    }
+
+    /// The process names of a french console are decoded from its code page
+    #[test]
+    fn test_decode_cp850_tasklist_output() {
+        let output = decode_command_output(TASKLIST_OUTPUT_CP850, 850);
+        assert!(output.contains("Numéro de session"));
+
+        let processes = parse_tasklist_command_output(output).unwrap();
+        let name = |pid| processes.get(&pid).map(String::as_str);
+        assert_eq!(name(1044), Some("svchost.exe"));
+        assert_eq!(name(4242), Some("Numérisation.exe"));
+        assert_eq!(name(5150), Some("Contrôleur.exe"));
+    }
+
+    /// The ANSI and UTF-8 code pages are decoded as well
+    #[test]
+    fn test_decode_command_output_code_pages() {
+        let name = "Numérisation.exe";
+        assert_eq!(decode_command_output(b"Num\xe9risation.exe", 1252), name);
+        assert_eq!(decode_command_output(name.as_bytes(), 65001), name);
+        let cyrillic = b"\x8f\xe0\xae\xe6\xa5\xe1\xe1";
+        assert_eq!(decode_command_output(cyrillic, 866), "Процесс");
+        // Unknown code page
+        assert_eq!(decode_command_output(b"svchost.exe", 1), "svchost.exe");
+    }
+
+    const ROUTE_PRINT_OUTPUT: &str = "\
+===========================================================================
+Interface List
+ 12...00 15 5d 01 02 03 ......Microsoft Hyper-V Network Adapter
+  1...........................Software Loopback Interface 1
+===========================================================================
+
+IPv4 Route Table
+===========================================================================
+Active Routes:
+Network Destination        Netmask          Gateway       Interface  Metric
+          0.0.0.0          0.0.0.0      10.0.0.254       10.0.0.1     25
+         10.0.0.0    255.255.255.0         On-link        10.0.0.1    281
+         10.0.0.1  255.255.255.255         On-link        10.0.0.1    281
+       10.0.0.255  255.255.255.255         On-link        10.0.0.1    281
+        127.0.0.0        255.0.0.0         On-link         127.0.0.1    331
+   192.168.50.0    255.255.255.0       10.0.0.253       10.0.0.1     26
+        224.0.0.0        240.0.0.0         On-link        10.0.0.1    281
+===========================================================================
+Persistent Routes:
+  Network Address          Netmask  Gateway Address  Metric
+          0.0.0.0          0.0.0.0      10.0.0.254  Default
+===========================================================================
+
+IPv6 Route Table
+===========================================================================
+Active Routes:
+ If Metric Network Destination      Gateway
+ 12    281 ::/0                     fe80::1
+  1    331 ::1/128                  On-link
+ 12    281 fd00::/64                On-link
+ 12    281 fd00:0000:0000:0000:0000:0000:0000:0001/128
+                                    On-link
+ 12    281 fe80::/64                On-link
+  1    331 ff00::/8                 On-link
+===========================================================================
+Persistent Routes:
+  None";
+
+    /// Only the default gateways and the directly connected networks must be kept
+    #[test]
+    fn test_parse_route_print_output() {
+        let routes: Vec<String> = parse_route_print_output(ROUTE_PRINT_OUTPUT)
+            .iter()
+            .map(Route::to_string)
+            .collect();
+        assert_eq!(
+            routes,
+            [
+                "default via 10.0.0.254 dev 10.0.0.1",
+                "10.0.0.0/24 dev 10.0.0.1",
+                "default via fe80::1 dev 12",
+                "fd00::/64 dev 12",
+                "fe80::/64 dev 12",
+            ]
+        );
+    }
+
+    /// The routes must be added to the host
+    #[test]
+    fn test_host_routes() {
+        let host_data = WindowsHostRawData::new(
+            "machine1".to_string(),
+            String::new(),
+            String::new(),
+            vec![],
+            Some(ROUTE_PRINT_OUTPUT.to_string()),
+        );
+        let host: Host = anyhow::Result::<Host>::from(host_data).unwrap();
+        assert_eq!(host.routes().len(), 5);
+        assert!(host.routes()[0].is_default());
+    }
+
+    /// Get-NetIPAddress output printed to the console
+    pub(super) const GET_NET_IP_ADDRESS_OUTPUT: &str = "\
+\r
+IPAddress         : fe80::8d4f:1c2a:3b5e:9f01%12\r
+InterfaceIndex    : 12\r
+InterfaceAlias    : Ethernet\r
+AddressFamily     : IPv6\r
+\r
+IPAddress         : 10.0.0.1\r
+InterfaceIndex    : 12\r
+InterfaceAlias    : Ethernet\r
+AddressFamily     : IPv4\r
+\r
+IPAddress         : 127.0.0.1\r
+InterfaceIndex    : 1\r
+InterfaceAlias    : Loopback Pseudo-Interface 1\r
+AddressFamily     : IPv4\r
+";
+
+    /// `Get-NetIPAddress | Select IPAddress,InterfaceAlias | Export-Csv` output
+    pub(super) const GET_NET_IP_ADDRESS_CSV: &str = "\
+#TYPE Selected.Microsoft.Management.Infrastructure.CimInstance\r
+\"IPAddress\",\"InterfaceAlias\"\r
+\"fe80::8d4f:1c2a:3b5e:9f01%12\",\"Ethernet\"\r
+\"10.0.0.1\",\"Ethernet\"\r
+\"127.0.0.1\",\"Loopback Pseudo-Interface 1\"\r
+";
+
+    /// `Get-NetIPAddress | Select IPAddress,InterfaceAlias | ConvertTo-Json` output
+    pub(super) const GET_NET_IP_ADDRESS_JSON: &str = r#"[
+    {
+        "IPAddress":  "fe80::8d4f:1c2a:3b5e:9f01%12",
+        "InterfaceAlias":  "Ethernet"
+    },
+    {
+        "IPAddress":  "10.0.0.1",
+        "InterfaceAlias":  "Ethernet"
+    },
+    {
+        "IPAddress":  "127.0.0.1",
+        "InterfaceAlias":  "Loopback Pseudo-Interface 1"
+    }
+]
+"#;
+
+    /// The console output and its CSV and JSON exports must give the same IPs, without their zone
+    /// index
+    #[test]
+    fn test_parse_ip_command_output_formats() {
+        let expected: Vec<IpAddr> = ["fe80::8d4f:1c2a:3b5e:9f01", "10.0.0.1", "127.0.0.1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        for (output, format) in [
+            (GET_NET_IP_ADDRESS_OUTPUT, IpOutputFormat::Text),
+            (GET_NET_IP_ADDRESS_CSV, IpOutputFormat::Csv),
+            (GET_NET_IP_ADDRESS_JSON, IpOutputFormat::Json),
+        ] {
+            assert_eq!(ip_output_format(output), format);
+            let ips = parse_ip_command_output(output.to_string(), "machine1").unwrap();
+            assert_eq!(ips, expected, "{format:?}");
+        }
+
+        // The interface aliases of the console output
+        let aliases: Vec<Option<String>> = parse_ip_text_output(GET_NET_IP_ADDRESS_OUTPUT)
+            .into_iter()
+            .map(|(_, alias)| alias)
+            .collect();
+        assert_eq!(aliases[2].as_deref(), Some("Loopback Pseudo-Interface 1"));
+
+        // A single address, and the semicolons of -UseCulture
+        let single =
+            r#"{"IPAddress": "10.0.0.1", "InterfaceAlias": "Ethernet", "PrefixLength": 24}"#;
+        let semicolons = "\"IPAddress\";\"InterfaceAlias\"\r\n\"10.0.0.1\";\"Ethernet\"\r\n";
+        for output in [single, semicolons] {
+            let ips = parse_ip_command_output(output.to_string(), "machine1").unwrap();
+            assert_eq!(ips, ["10.0.0.1".parse::<IpAddr>().unwrap()]);
+        }
 
-        Ok(host)
+        let res = parse_ip_command_output("[{\"IPAddress\": ".to_string(), "machine1");
+        assert!(format!("{:#}", res.unwrap_err()).contains("Get-NetIPAddress Json output"));
     }
 }
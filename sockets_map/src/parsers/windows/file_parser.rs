@@ -1,10 +1,12 @@
-use std::{
-    io::{BufReader, Read, Seek},
-    path::PathBuf,
-};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
 
-use crate::{host::Host, parsers::windows::parse_ip_command_output};
-use utf16_reader;
+use crate::{
+    host::Host,
+    parsers::{append_to_file, diagnostics::ParseDiagnostics, windows::parse_ip_command_output},
+};
 
 use super::WindowsHostRawData;
 
@@ -12,7 +14,9 @@ pub struct WindowsHostFiles {
     hostname: String,
     network_output_file: PathBuf,
     ip_output_file: PathBuf,
-    tasklist_output_file: PathBuf,
+    /// Missing when the netstat output comes from `netstat -anob`, which names the processes
+    tasklist_output_file: Option<PathBuf>,
+    route_output_file: Option<PathBuf>,
 }
 
 impl WindowsHostFiles {
@@ -20,17 +24,58 @@ impl WindowsHostFiles {
         hostname: String,
         network_output_file: PathBuf,
         ip_output_file: PathBuf,
-        tasklist_output_file: PathBuf,
+        tasklist_output_file: Option<PathBuf>,
+        route_output_file: Option<PathBuf>,
     ) -> Self {
         Self {
             hostname,
             network_output_file,
             ip_output_file,
             tasklist_output_file,
+            route_output_file,
         }
     }
 }
 
+/// Read a command output file written on Windows: its encoding is given by its BOM, otherwise it
+/// is guessed between utf16 (as written by PowerShell redirections) and utf8
+pub(crate) fn read_windows_text(path: &Path) -> anyhow::Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("unable to read file {path:?}"))?;
+    Ok(decode_windows_text(&bytes))
+}
+
+/// Decode a text written on Windows, see [`read_windows_text`]
+fn decode_windows_text(bytes: &[u8]) -> String {
+    let (encoding, bom_length) = Encoding::for_bom(bytes)
+        .or_else(|| guess_utf16_encoding(bytes).map(|encoding| (encoding, 0)))
+        .unwrap_or((UTF_8, 0));
+    encoding
+        .decode_without_bom_handling(&bytes[bom_length..])
+        .0
+        .into_owned()
+}
+
+/// Guess whether a text without BOM is utf16 from its NUL bytes, which make half of utf16 encoded
+/// ASCII text and are absent from utf8 text. Their position gives the byte order.
+fn guess_utf16_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let count_nuls = |start| {
+        bytes
+            .iter()
+            .skip(start)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    let (even_nuls, odd_nuls) = (count_nuls(0), count_nuls(1));
+    if bytes.is_empty() || (even_nuls + odd_nuls) * 4 < bytes.len() {
+        return None;
+    }
+    match odd_nuls >= even_nuls {
+        true => Some(UTF_16LE),
+        false => Some(UTF_16BE),
+    }
+}
+
 impl From<WindowsHostFiles> for anyhow::Result<WindowsHostRawData> {
     fn from(windows_host_files: WindowsHostFiles) -> Self {
         log::debug!(
@@ -39,48 +84,26 @@ impl From<WindowsHostFiles> for anyhow::Result<WindowsHostRawData> {
         );
 
         // Parse the output of the Get-NetIpAddress command to get the host IPs
-        let ip_file = std::fs::File::open(&windows_host_files.ip_output_file)?;
-
-        // Try to read as utf8, otherwise as utf16
-        let mut reader = BufReader::new(ip_file);
-        let mut buffer = String::new();
-        let ip_command_output_file_contents = if reader.read_to_string(&mut buffer).is_ok() {
-            buffer
-        } else {
-            reader.rewind()?;
-            utf16_reader::read_to_string(reader)
-        };
-        let ips = match parse_ip_command_output(
+        let ip_command_output_file_contents =
+            read_windows_text(&windows_host_files.ip_output_file)?;
+        let ips = parse_ip_command_output(
             ip_command_output_file_contents,
             &windows_host_files.hostname,
-        ) {
-            Ok(ips) => ips,
-            Err(e) => return Err(e),
-        };
+        )?;
 
         // Read tasklist file
-        let tasklist_file = std::fs::File::open(windows_host_files.tasklist_output_file)?;
-        let mut reader = BufReader::new(&tasklist_file);
-        // Try to read as utf8, otherwise as utf16
-        let mut buffer = String::new();
-        let tasklist_output = if reader.read_to_string(&mut buffer).is_ok() {
-            buffer
-        } else {
-            reader.rewind()?;
-            utf16_reader::read_to_string(reader)
+        let tasklist_output = match &windows_host_files.tasklist_output_file {
+            Some(path) => read_windows_text(path)?,
+            None => String::new(),
         };
 
         // Read netstat file
-        let netstat_file = std::fs::File::open(windows_host_files.network_output_file)?;
-
-        // Try to read netstat command output contents as utf8, otherwise as utf16
-        let mut reader = BufReader::new(netstat_file);
-        let mut buffer = String::new();
-        let network_output = if reader.read_to_string(&mut buffer).is_ok() {
-            buffer
-        } else {
-            reader.rewind()?;
-            utf16_reader::read_to_string(reader)
+        let network_output = read_windows_text(&windows_host_files.network_output_file)?;
+
+        // The routing table is optional, since older captures do not have it
+        let route_output = match &windows_host_files.route_output_file {
+            Some(path) => Some(read_windows_text(path)?),
+            None => None,
         };
 
         Ok(WindowsHostRawData {
@@ -88,13 +111,173 @@ impl From<WindowsHostFiles> for anyhow::Result<WindowsHostRawData> {
             network_output,
             tasklist_output,
             ips,
+            route_output,
         })
     }
 }
 
 impl From<WindowsHostFiles> for anyhow::Result<Host> {
     fn from(windows_host_files: WindowsHostFiles) -> Self {
-        let host_data: anyhow::Result<WindowsHostRawData> = windows_host_files.into();
-        host_data.and_then(|h| h.into())
+        windows_host_files
+            .parse_host()
+            .map(|(host, _diagnostics)| host)
+    }
+}
+
+impl WindowsHostFiles {
+    /// Read and parse the files into the host, along with the problems met on their lines
+    pub fn parse_host(self) -> anyhow::Result<(Host, ParseDiagnostics)> {
+        let host_data: anyhow::Result<WindowsHostRawData> = self.into();
+        host_data?.parse_host()
+    }
+}
+
+impl WindowsHostRawData {
+    /// Write the raw data as capture files in `output_dir`, named the way the directory scanner expects:
+    /// `<hostname>.windows_netstat`, `<hostname>.windows_tasklist`, `<hostname>.windows_ip` and, if
+    /// the routing table was captured, `<hostname>.windows_route`.
+    ///
+    /// The netstat and tasklist outputs are appended to the existing capture files, so that several samples can be aggregated.
+    pub fn write_capture_files(&self, output_dir: &Path) -> anyhow::Result<()> {
+        append_to_file(
+            &output_dir.join(format!("{}.windows_netstat", self.hostname)),
+            &self.network_output,
+        )?;
+        append_to_file(
+            &output_dir.join(format!("{}.windows_tasklist", self.hostname)),
+            &self.tasklist_output,
+        )?;
+
+        // Write the IPs the way the `Get-NetIpAddress` command does
+        let ip_output: String = self
+            .ips
+            .iter()
+            .map(|ip| format!("IPAddress         : {ip}\n"))
+            .collect();
+        let ip_file_path = output_dir.join(format!("{}.windows_ip", self.hostname));
+        std::fs::write(&ip_file_path, ip_output)
+            .with_context(|| format!("unable to write file {ip_file_path:?}"))?;
+
+        if let Some(route_output) = &self.route_output {
+            let route_file_path = output_dir.join(format!("{}.windows_route", self.hostname));
+            std::fs::write(&route_file_path, route_output)
+                .with_context(|| format!("unable to write file {route_file_path:?}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASKLIST_OUTPUT: &str = "\
+\"Image Name\",\"PID\",\"Session Name\",\"Session#\",\"Mem Usage\"\r
+\"svchost.exe\",\"1044\",\"Services\",\"0\",\"12,345 K\"\r
+\"Numérisation.exe\",\"4242\",\"Console\",\"1\",\"8,192 K\"\r
+";
+
+    fn utf16_le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn utf16_be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    /// The files are decoded from their BOM, or from their NUL bytes without BOM
+    #[test]
+    fn test_read_windows_text_encodings() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("utf8", TASKLIST_OUTPUT.as_bytes().to_vec()),
+            (
+                "utf8_bom",
+                [b"\xef\xbb\xbf".to_vec(), TASKLIST_OUTPUT.into()].concat(),
+            ),
+            (
+                "utf16le_bom",
+                [vec![0xff, 0xfe], utf16_le(TASKLIST_OUTPUT)].concat(),
+            ),
+            (
+                "utf16be_bom",
+                [vec![0xfe, 0xff], utf16_be(TASKLIST_OUTPUT)].concat(),
+            ),
+            ("utf16le", utf16_le(TASKLIST_OUTPUT)),
+            ("utf16be", utf16_be(TASKLIST_OUTPUT)),
+        ];
+        for (name, contents) in files {
+            let path = dir.path().join(format!("{name}.windows_tasklist"));
+            std::fs::write(&path, contents).unwrap();
+            assert_eq!(read_windows_text(&path).unwrap(), TASKLIST_OUTPUT, "{name}");
+        }
+
+        let missing_file = dir.path().join("missing.windows_tasklist");
+        assert!(read_windows_text(&missing_file).is_err());
+    }
+
+    /// A host is parsed from utf16 files without BOM
+    #[test]
+    fn test_utf16_files_without_bom() {
+        let dir = tempfile::tempdir().unwrap();
+        let netstat_output = "\
+  Proto  Local Address          Foreign Address        State           PID\r
+  TCP    0.0.0.0:445            0.0.0.0:0              LISTENING       4242\r
+";
+        let files = [
+            ("windows_netstat", netstat_output),
+            ("windows_tasklist", TASKLIST_OUTPUT),
+            ("windows_ip", "IPAddress         : 10.0.0.1\r\n"),
+        ];
+        for (extension, contents) in files {
+            let path = dir.path().join(format!("machine1.{extension}"));
+            std::fs::write(path, utf16_le(contents)).unwrap();
+        }
+
+        let host: anyhow::Result<Host> = WindowsHostFiles::new(
+            "machine1".to_string(),
+            dir.path().join("machine1.windows_netstat"),
+            dir.path().join("machine1.windows_ip"),
+            Some(dir.path().join("machine1.windows_tasklist")),
+            None,
+        )
+        .into();
+        let host = host.unwrap();
+        assert!(host.ips().contains(&"10.0.0.1".parse().unwrap()));
+        assert!(host
+            .listening_sockets()
+            .iter()
+            .any(|s| s.port() == 445 && s.process().name() == "Numérisation.exe"));
+    }
+
+    /// The IPs are read from the CSV and JSON exports of Get-NetIPAddress as well
+    #[test]
+    fn test_exported_ip_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let netstat_path = dir.path().join("machine1.windows_netstat");
+        std::fs::write(&netstat_path, "").unwrap();
+        for (name, contents) in [
+            ("csv", super::super::tests::GET_NET_IP_ADDRESS_CSV),
+            ("json", super::super::tests::GET_NET_IP_ADDRESS_JSON),
+        ] {
+            let ip_path = dir.path().join(format!("{name}.windows_ip"));
+            std::fs::write(&ip_path, [vec![0xff, 0xfe], utf16_le(contents)].concat()).unwrap();
+            let host: anyhow::Result<Host> = WindowsHostFiles::new(
+                "machine1".to_string(),
+                netstat_path.clone(),
+                ip_path,
+                None,
+                None,
+            )
+            .into();
+            let host = host.unwrap();
+            assert!(host.ips().contains(&"10.0.0.1".parse().unwrap()), "{name}");
+            assert!(
+                host.ips()
+                    .contains(&"fe80::8d4f:1c2a:3b5e:9f01".parse().unwrap()),
+                "{name}"
+            );
+        }
     }
 }
@@ -0,0 +1,20 @@
+//! The main types and functions, to go from capture files to a graph with a single import:
+//!
+//! ```
+//! use sockets_map::prelude::*;
+//! ```
+
+pub use crate::{
+    connections_model::build_connections_list,
+    graphs::{
+        create_graph, DrawOptions, GraphGranularity, ListenerRendering, LoopbackRendering,
+        MapGraphOptions,
+    },
+    graphviz::run_graphviz,
+    host::{Connection, Host, ListeningSocket, Process, SocketType},
+    parsers::directory_scanner::{build_hosts, scan_dir},
+    server::{
+        client::{Client, Update},
+        message::Message,
+    },
+};
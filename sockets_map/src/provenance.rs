@@ -0,0 +1,145 @@
+//! Provenance of the exported files: the tool, the time, the captures and the options which
+//! produced them, embedded in the files so that a graph can be traced back to its captures
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+use std::path::{Path, PathBuf};
+
+/// Environment variable fixing the generation time, for reproducible outputs
+const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// What produced an exported file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Name and version of the program which wrote the file
+    pub tool: String,
+    /// Generation time, in RFC 3339 format
+    pub generated_at: String,
+    /// Capture directories with the SHA-1 digest of their files, or names of the agents
+    pub inputs: Vec<String>,
+    /// Options with which the file was generated
+    pub options: Vec<String>,
+}
+
+impl Provenance {
+    /// Provenance of a file generated now by `tool`, such as `sockets_map 1.0.0`, from the
+    /// captures of `input_dirs` and the updates of `agents`. The time is taken from
+    /// `SOURCE_DATE_EPOCH` if it is set.
+    pub fn collect(
+        tool: &str,
+        input_dirs: &[impl AsRef<Path>],
+        agents: &[String],
+        options: &[String],
+    ) -> Self {
+        let inputs = input_dirs
+            .iter()
+            .map(|dir| {
+                let dir = dir.as_ref();
+                match dir_digest(dir) {
+                    Ok(digest) => format!("{} (sha1 {digest})", dir.display()),
+                    Err(e) => format!("{} (unreadable: {e})", dir.display()),
+                }
+            })
+            .chain(agents.iter().map(|agent| format!("agent {agent}")))
+            .collect();
+        Self {
+            tool: tool.to_string(),
+            generated_at: generation_time(),
+            inputs,
+            options: options.to_vec(),
+        }
+    }
+
+    /// Named values of the provenance, named after the standard keywords of the PNG text chunks.
+    /// There is a `Source` per input.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("Software", self.tool.clone()),
+            ("Creation Time", self.generated_at.clone()),
+        ];
+        entries.extend(self.inputs.iter().map(|input| ("Source", input.clone())));
+        if !self.options.is_empty() {
+            entries.push(("Options", self.options.join(" ")));
+        }
+        entries
+    }
+
+    /// Lines of [`Self::entries`] starting with the comment `prefix`, such as `#` for CSV files
+    pub fn comment_block(&self, prefix: &str) -> String {
+        self.entries()
+            .into_iter()
+            .map(|(key, value)| format!("{prefix} {key}: {}\n", value.replace(['\r', '\n'], " ")))
+            .collect()
+    }
+}
+
+/// Current time, or the one of `SOURCE_DATE_EPOCH`
+fn generation_time() -> String {
+    std::env::var(SOURCE_DATE_EPOCH)
+        .ok()
+        .and_then(|epoch| epoch.trim().parse().ok())
+        .and_then(|epoch| Utc.timestamp_opt(epoch, 0).single())
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// SHA-1 digest of the paths, relative to `dir`, and of the contents of the files under `dir`
+fn dir_digest(dir: &Path) -> std::io::Result<String> {
+    let mut files = Vec::new();
+    list_files(dir, &mut files)?;
+    files.sort();
+    let mut hasher = sha1::Sha1::new();
+    for file in files {
+        let relative_path = file.strip_prefix(dir).unwrap_or(&file);
+        hasher.update(relative_path.to_string_lossy().replace('\\', "/"));
+        hasher.update(std::fs::read(&file)?);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Add the files under `dir` to `files`
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The digest must change with the captures, and the entries list every input
+    #[test]
+    fn test_collect() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir(directory.path().join("web01")).unwrap();
+        std::fs::write(directory.path().join("web01").join("web01.ss"), "tcp").unwrap();
+        let provenance = Provenance::collect(
+            "sockets_map 1.0.0",
+            &[directory.path()],
+            &["db01".to_string()],
+            &["--loopback".to_string(), "hide".to_string()],
+        );
+        let digest = dir_digest(directory.path()).unwrap();
+        assert_eq!(
+            provenance.inputs,
+            [
+                format!("{} (sha1 {digest})", directory.path().display()),
+                "agent db01".to_string()
+            ]
+        );
+        std::fs::write(directory.path().join("web01").join("web01.ip"), "").unwrap();
+        assert_ne!(dir_digest(directory.path()).unwrap(), digest);
+
+        let comment_block = provenance.comment_block("#");
+        assert!(comment_block.starts_with("# Software: sockets_map 1.0.0\n# Creation Time: "));
+        assert!(comment_block.ends_with("# Source: agent db01\n# Options: --loopback hide\n"));
+    }
+}
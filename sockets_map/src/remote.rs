@@ -0,0 +1,278 @@
+//! This module collects hosts that do not run an agent, by running the capture commands over SSH.
+//!
+//! The outputs of the commands are parsed the same way as the capture files, so the remote host
+//! only needs the usual tools (`ss` or `netstat` and `ip` on Linux, `netstat`, `tasklist` and
+//! PowerShell on Windows).
+
+use crate::{
+    host::Host,
+    parsers::{
+        linux::{self, LinuxHostRawData, NetworkOutput},
+        windows::{self, WindowsHostRawData},
+        HostData,
+    },
+};
+use anyhow::{bail, Context};
+use std::{future::Future, pin::Pin};
+
+/// Operating system of an SSH target, which selects the commands to run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetOs {
+    #[default]
+    Linux,
+    Windows,
+}
+
+/// A host to collect over SSH
+#[derive(Debug, Clone)]
+pub struct SshTarget {
+    /// Destination as given to ssh, such as `user@192.168.1.10` or `ssh://user@host:2222`
+    pub destination: String,
+    /// Name of the host, instead of the one returned by the `hostname` command
+    pub pretty_name: Option<String>,
+    pub os: TargetOs,
+}
+
+impl SshTarget {
+    pub fn new(destination: &str) -> Self {
+        Self {
+            destination: destination.to_string(),
+            pretty_name: None,
+            os: TargetOs::default(),
+        }
+    }
+}
+
+/// Future returned by [`RemoteExecutor::run`]
+pub type CommandOutput<'a> = Pin<Box<dyn Future<Output = anyhow::Result<String>> + Send + 'a>>;
+
+/// Runs commands on a remote host
+pub trait RemoteExecutor {
+    /// Run `program` with `args`, and return its standard output. A command exiting with a failure
+    /// status is an error.
+    fn run<'a>(&'a self, program: &'a str, args: &'a [&'a str]) -> CommandOutput<'a>;
+}
+
+/// Executor running the commands through the system ssh client, in a multiplexed session
+pub struct SshExecutor {
+    session: openssh::Session,
+}
+
+impl SshExecutor {
+    /// Open a session to `destination`. The host key must already be known, as no prompt can be
+    /// answered.
+    pub async fn connect(destination: &str) -> anyhow::Result<Self> {
+        let session = openssh::Session::connect(destination, openssh::KnownHosts::Strict)
+            .await
+            .with_context(|| format!("unable to connect to {destination}"))?;
+        Ok(Self { session })
+    }
+
+    /// Close the session
+    pub async fn close(self) -> anyhow::Result<()> {
+        self.session.close().await?;
+        Ok(())
+    }
+}
+
+impl RemoteExecutor for SshExecutor {
+    fn run<'a>(&'a self, program: &'a str, args: &'a [&'a str]) -> CommandOutput<'a> {
+        Box::pin(async move {
+            let output = self.session.command(program).args(args).output().await?;
+            if !output.status.success() {
+                bail!(
+                    "`{program}` failed on the remote host ({}): {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        })
+    }
+}
+
+/// Run the capture commands of `target` with `executor`
+pub async fn collect_host_data(
+    executor: &impl RemoteExecutor,
+    target: &SshTarget,
+) -> anyhow::Result<HostData> {
+    let hostname = match &target.pretty_name {
+        Some(pretty_name) => pretty_name.clone(),
+        None => executor.run("hostname", &[]).await?.trim().to_string(),
+    };
+
+    match target.os {
+        TargetOs::Linux => {
+            // First try ss, then netstat
+            let network_output = match executor.run("ss", &["-apn"]).await {
+                Ok(output) => NetworkOutput::Ss(output),
+                Err(e) => {
+                    log::debug!("unable to run ss on {hostname}, trying netstat: {e}");
+                    let listening = executor.run("netstat", &["-Wltpn"]).await?;
+                    let established = executor.run("netstat", &["-Wtpn"]).await?;
+                    NetworkOutput::Netstat(format!("{listening}\n{established}"))
+                }
+            };
+            let ips = linux::parse_ip_command_output(executor.run("ip", &["address"]).await?)?;
+            // The routing table is optional
+            let route_output = match (
+                executor.run("ip", &["route"]).await,
+                executor.run("ip", &["-6", "route"]).await,
+            ) {
+                (Ok(routes), Ok(routes6)) => Some(format!("{routes}\n{routes6}")),
+                (Err(e), _) | (_, Err(e)) => {
+                    log::debug!("unable to retrieve the routing table of {hostname}: {e}");
+                    None
+                }
+            };
+            Ok(HostData::LinuxHostData(LinuxHostRawData::new(
+                hostname,
+                network_output,
+                ips,
+                route_output,
+            )))
+        }
+        TargetOs::Windows => {
+            let netstat = executor.run("netstat", &["-ano"]).await?;
+            let tasklist = executor.run("tasklist", &["/FO", "CSV"]).await?;
+            let ips = windows::parse_ip_command_output(
+                executor
+                    .run("powershell", &["-Command", "Get-NetIpAddress"])
+                    .await?,
+                &hostname,
+            )?;
+            // The routing table is optional
+            let route_output = match executor.run("route", &["print"]).await {
+                Ok(route_output) => Some(route_output),
+                Err(e) => {
+                    log::debug!("unable to retrieve the routing table of {hostname}: {e}");
+                    None
+                }
+            };
+            Ok(HostData::WindowsHostData(WindowsHostRawData::new(
+                hostname,
+                netstat,
+                tasklist,
+                ips,
+                route_output,
+            )))
+        }
+    }
+}
+
+/// Connect to `target` and run its capture commands
+pub async fn collect_host_data_via_ssh(target: &SshTarget) -> anyhow::Result<HostData> {
+    let executor = SshExecutor::connect(&target.destination).await?;
+    let host_data = collect_host_data(&executor, target).await;
+    if let Err(e) = executor.close().await {
+        log::warn!(
+            "unable to close the SSH session to {}: {e}",
+            target.destination
+        );
+    }
+    host_data
+}
+
+/// Build the host `target` from the output of its capture commands, run over SSH
+pub async fn collect_via_ssh(target: SshTarget) -> anyhow::Result<Host> {
+    collect_host_data_via_ssh(&target).await?.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Executor returning canned outputs, the other commands being missing
+    struct MockExecutor {
+        outputs: HashMap<&'static str, &'static str>,
+    }
+
+    impl RemoteExecutor for MockExecutor {
+        fn run<'a>(&'a self, program: &'a str, args: &'a [&'a str]) -> CommandOutput<'a> {
+            let command_line = [&[program], args].concat().join(" ");
+            let output = self
+                .outputs
+                .get(command_line.as_str())
+                .map(|o| o.to_string());
+            Box::pin(async move {
+                output.ok_or_else(|| anyhow::anyhow!("{command_line}: command not found"))
+            })
+        }
+    }
+
+    /// Linux hosts are collected with netstat when ss is missing
+    #[tokio::test]
+    async fn test_collect_linux() {
+        let executor = MockExecutor {
+            outputs: HashMap::from([
+                ("hostname", "machine1\n"),
+                (
+                    "netstat -Wltpn",
+                    "tcp 0 0 0.0.0.0:22 0.0.0.0:* LISTEN 100/sshd",
+                ),
+                (
+                    "netstat -Wtpn",
+                    "tcp 0 0 10.0.0.1:22 10.0.0.2:50000 ESTABLISHED 200/sshd",
+                ),
+                (
+                    "ip address",
+                    "2: eth0\n    inet 10.0.0.1/24 brd 10.0.0.255 scope global eth0",
+                ),
+                (
+                    "ip route",
+                    "default via 10.0.0.254 dev eth0\n10.0.0.0/24 dev eth0 scope link",
+                ),
+                ("ip -6 route", ""),
+            ]),
+        };
+        let host_data = collect_host_data(&executor, &SshTarget::new("root@10.0.0.1"))
+            .await
+            .unwrap();
+        let host: anyhow::Result<Host> = host_data.into();
+        let host = host.unwrap();
+
+        assert_eq!(host.name(), "machine1");
+        assert!(host.ips().contains(&"10.0.0.1".parse().unwrap()));
+        assert_eq!(host.listening_sockets().len(), 1);
+        assert_eq!(host.connections().len(), 1);
+        assert_eq!(host.routes().len(), 2);
+    }
+
+    /// Windows hosts are collected with netstat, tasklist and Get-NetIpAddress
+    #[tokio::test]
+    async fn test_collect_windows() {
+        let executor = MockExecutor {
+            outputs: HashMap::from([
+                (
+                    "netstat -ano",
+                    "  TCP    0.0.0.0:3389    0.0.0.0:0    LISTENING    1000\n  \
+                     TCP    10.0.0.3:3389    10.0.0.2:50001    ESTABLISHED    1000",
+                ),
+                (
+                    "tasklist /FO CSV",
+                    "\"Image Name\",\"PID\"\n\"svchost.exe\",\"1000\"",
+                ),
+                (
+                    "powershell -Command Get-NetIpAddress",
+                    "IPAddress         : 10.0.0.3\nInterfaceIndex    : 4",
+                ),
+            ]),
+        };
+        let target = SshTarget {
+            pretty_name: Some("windows1".to_string()),
+            os: TargetOs::Windows,
+            ..SshTarget::new("admin@10.0.0.3")
+        };
+        let host_data = collect_host_data(&executor, &target).await.unwrap();
+        let host: anyhow::Result<Host> = host_data.into();
+        let host = host.unwrap();
+
+        assert_eq!(host.name(), "windows1");
+        assert!(host.ips().contains(&"10.0.0.3".parse().unwrap()));
+        assert_eq!(host.listening_sockets().len(), 1);
+        assert_eq!(host.connections().len(), 1);
+        // route print is missing
+        assert!(host.routes().is_empty());
+    }
+}
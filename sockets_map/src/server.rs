@@ -1,35 +1,133 @@
-use self::client::Client;
+use self::client::{Client, Update};
+use self::event_log::{EventKind, EventLog};
 use super::host;
-use crate::server::message::Message;
-use anyhow::Result;
+use crate::server::message::{Capability, CapabilitySet, Message};
+use anyhow::{anyhow, Result};
 use log;
 use std::{
     collections::HashMap,
     marker::{Send, Sync},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
     time::Duration,
 };
 use tokio::select;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
 use tokio_util::sync::CancellationToken;
 use tsyncp::{self, broadcast::BincodeSender};
 
 pub const DEFAULT_PORT: u16 = 6840;
+/// Number of updates kept for each client unless set in the [`ListenOptions`], which bounds the
+/// memory of the long sessions of agents pushing updates periodically
+pub const DEFAULT_MAX_UPDATES_PER_CLIENT: usize = 100;
+pub const DEFAULT_MAX_UPDATE_BYTES: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_CLIENTS: usize = 1024;
+/// Number of errors in a row after which the listening loop gives up
+const MAX_CONSECUTIVE_ERRORS: usize = 10;
+/// Interval at which `shutdown` checks whether the clients have disconnected
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Pause of the listening loop while no agent is connected, `recv` returning at once in this case
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 pub mod client;
+pub mod clock;
+pub mod event_log;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod message;
 
-/// The server will listen for clients unil `run` is set to `false`
+/// Options of the agents server
+#[derive(Clone, Debug)]
+pub struct ListenOptions {
+    /// Number of updates kept for each client, the oldest ones being dropped first,
+    /// [`DEFAULT_MAX_UPDATES_PER_CLIENT`] by default
+    pub max_updates_per_client: usize,
+    /// Size of the largest update accepted, once serialized and decompressed
+    pub max_update_bytes: u64,
+    /// Number of clients registered at once, the next registrations being refused
+    pub max_clients: usize,
+    /// Flag the clients which do not connect from one of the IPs they register with, such as
+    /// cloned machines registering with the hostname of another one
+    pub verify_source_ip: bool,
+    /// Addresses the clients may connect from without being flagged, such as NAT gateways
+    pub verify_exempt_ips: Vec<IpAddr>,
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        Self {
+            max_updates_per_client: DEFAULT_MAX_UPDATES_PER_CLIENT,
+            max_update_bytes: DEFAULT_MAX_UPDATE_BYTES,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            verify_source_ip: false,
+            verify_exempt_ips: Vec::new(),
+        }
+    }
+}
+
+impl ListenOptions {
+    /// Check that the update is not larger than `max_update_bytes`, giving the reason otherwise
+    pub fn check_update(&self, update: &Update) -> std::result::Result<(), String> {
+        let size = bincode::serialized_size(update).map_err(|e| e.to_string())?;
+        match size > self.max_update_bytes {
+            true => Err(format!(
+                "update of {size} bytes exceeds the limit of {} bytes",
+                self.max_update_bytes
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Check that a client connecting from `source_ip` registered with this IP, or connects from
+    /// an exempt address. Always true if the IPs are not verified.
+    pub fn check_source_ip(&self, source_ip: IpAddr, ip_addresses: &[IpAddr]) -> bool {
+        if !self.verify_source_ip {
+            return true;
+        }
+        let source_ip = host::canonical_ip(source_ip);
+        self.verify_exempt_ips
+            .iter()
+            .chain(ip_addresses)
+            .any(|ip| host::canonical_ip(*ip) == source_ip)
+    }
+
+    /// Check that a new client can register, with `clients_count` clients already registered
+    pub fn check_registration(&self, clients_count: usize) -> std::result::Result<(), String> {
+        match clients_count >= self.max_clients {
+            true => Err(format!(
+                "registration refused, the server already has {clients_count} clients"
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+/// State of the listening loop of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerState {
+    Running,
+    /// Stopped by cancelling the run token
+    Stopped,
+    /// Stopped unexpectedly, with the reason
+    Failed(String),
+}
+
+/// The server will listen for clients until `run_token` is cancelled. The sender to the clients
+/// is stored in `tx_opt`, from which the server also replies to the updates and registrations it
+/// rejects. The returned channel tells when the listening loop stops, and why, and the returned
+/// log records the registrations, updates, disconnections and errors of the clients.
+#[allow(clippy::too_many_arguments)]
 pub async fn listen<FnSocket, FnClient1, FnClient2, FnClient3>(
     server_addr: String,
     clients: Arc<RwLock<HashMap<String, Client>>>,
+    tx_opt: Arc<RwLock<Option<BincodeSender<Message>>>>,
     run_token: CancellationToken,
+    options: ListenOptions,
     on_connect_callback: FnSocket,
     on_client_registration_callback: FnClient1,
     on_client_update_callback: FnClient2,
     on_client_exit_callback: FnClient3,
-) -> Result<BincodeSender<Message>>
+) -> Result<(watch::Receiver<ServerState>, Arc<RwLock<EventLog>>)>
 where
     FnSocket: Fn(SocketAddr) + Send + Sync + 'static,
     FnClient1: Fn(&Client) + Send + 'static,
@@ -42,73 +140,1105 @@ where
             .set_tcp_reuseaddr(true)
             .await?;
     let (mut rx, tx) = channel.split();
+    *tx_opt.write().await = Some(tx);
+
+    let (state_tx, state_rx) = watch::channel(ServerState::Running);
+    let event_log = Arc::new(RwLock::new(EventLog::default()));
+    let task_event_log = event_log.clone();
+    let task = tokio::spawn(async move {
+        let event_log = task_event_log;
+        // Errors which are not due to a single client, such as failing to accept connections
+        let mut consecutive_errors = 0;
 
-    tokio::spawn(async move {
         // Wait for clients to connect
         loop {
-            // Wait a bit not to consume too much CPU
-            tokio::time::sleep(Duration::from_millis(100)).await;
-
             // Break out of the loop if the token is cancelled, instead of waiting for new connections
-            let res = select! {
+            let (res, accepted) = select! {
                 _ = run_token.cancelled() => {
-                    break;
+                    break ServerState::Stopped;
                 },
-                (res, _) = rx.recv().with_addr().accepting().handle(&on_connect_callback) => {
+                res = rx.recv().with_addr().accepting().handle(&on_connect_callback) => {
                     res
                 },
             };
+            if let Err(e) = accepted {
+                break ServerState::Failed(format!("unable to accept connections: {e}"));
+            }
 
-            // Listen to incoming agents
-            if let Some(res) = res {
-                let (message, client_addr) = match res {
-                    Ok((message, client_addr)) => (message, client_addr),
-                    Err(e) => {
-                        if e.is_connection_error() {
-                            log::error!("{} disconnected", e.peer_addr().unwrap());
-                        } else if e.is_decode_error() {
-                            log::error!("decode error from {:?} ", e.peer_addr());
-                        } else {
-                            log::error!("other error from {:?} ", e.peer_addr());
+            // Listen to incoming agents, waiting for the first ones to connect
+            let Some(res) = res else {
+                tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+                continue;
+            };
+            let (message, client_addr) = match res {
+                Ok((message, client_addr)) => (message, client_addr),
+                Err(e) => {
+                    // Only the registered clients are logged, the others having sent Exit already
+                    let peer_addr = e.peer_addr();
+                    let hostname = match peer_addr {
+                        Some(addr) => clients
+                            .read()
+                            .await
+                            .get(&addr.to_string())
+                            .map(|c| c.hostname.clone()),
+                        None => None,
+                    };
+                    // The disconnected clients are kept for their updates
+                    if let (true, Some(addr)) = (e.is_connection_error(), peer_addr) {
+                        if let Some(client) = clients.write().await.get_mut(&addr.to_string()) {
+                            client.set_disconnected();
                         }
-                        continue;
                     }
-                };
-                log::debug!("received message: {message:#?}");
-
-                let mut clients_mut = clients.write().await;
-                log::debug!("clients: {clients_mut:#?}");
-                match message {
-                    Message::Register(r) => {
-                        let client = Client::new(
-                            r.hostname().to_owned(),
-                            r.pretty_name().map(|r| r.to_string()),
-                            r.ip_addresses().to_vec(),
+                    let mut event_log = event_log.write().await;
+                    if e.is_connection_error() {
+                        log::error!("{:?} disconnected", e.peer_addr());
+                        if hostname.is_some() {
+                            event_log.push(EventKind::Disconnected, peer_addr, hostname.as_deref());
+                        }
+                    } else if e.is_decode_error() {
+                        log::error!("decode error from {:?} ", e.peer_addr());
+                        let error = "unable to decode a message".to_string();
+                        event_log.push(
+                            EventKind::ClientError { error },
+                            peer_addr,
+                            hostname.as_deref(),
+                        );
+                    } else {
+                        log::error!("other error from {:?} ", e.peer_addr());
+                        let error = e.to_string();
+                        event_log.push(
+                            EventKind::ClientError { error },
+                            peer_addr,
+                            hostname.as_deref(),
                         );
-                        on_client_registration_callback(&client);
-                        clients_mut.insert(client_addr.to_string(), client);
+                        consecutive_errors += 1;
+                        if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                            break ServerState::Failed(format!(
+                                "{consecutive_errors} errors in a row, the last one being: {e}"
+                            ));
+                        }
                     }
-                    Message::Update(update) => {
-                        if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
-                            client.add_update(update);
-                            on_client_update_callback(client);
-                        } else {
-                            log::error!("unknown client: {}", client_addr);
+                    continue;
+                }
+            };
+            consecutive_errors = 0;
+            let hostname = clients
+                .read()
+                .await
+                .get(&client_addr.to_string())
+                .map(|c| c.hostname.clone());
+            let log_event = |kind| {
+                let event_log = event_log.clone();
+                let hostname = hostname.clone();
+                async move {
+                    event_log
+                        .write()
+                        .await
+                        .push(kind, Some(client_addr), hostname.as_deref());
+                }
+            };
+
+            // Decompress updates so that they are handled like plain ones
+            let message = match message {
+                Message::UpdateCompressed(data) => match message::decompress_update(&data) {
+                    Ok(update) => Message::Update(update),
+                    Err(e) => {
+                        log::error!("unable to decompress update from {client_addr}: {e}");
+                        let error = format!("unable to decompress update: {e}");
+                        log_event(EventKind::ClientError { error }).await;
+                        continue;
+                    }
+                },
+                message => message,
+            };
+            log::debug!("received message: {message:#?}");
+
+            // Oversized updates are rejected before being stored
+            let rejection = match &message {
+                Message::Update(update) | Message::UpdateDelta(update) => {
+                    options.check_update(update).err()
+                }
+                _ => None,
+            };
+            if let Some(reason) = rejection {
+                log::warn!("rejected update from {client_addr}: {reason}");
+                let error = reason.clone();
+                log_event(EventKind::ClientError { error }).await;
+                reply(&tx_opt, Message::UpdateRejected(reason), client_addr).await;
+                continue;
+            }
+
+            let mut clients_mut = clients.write().await;
+            log::debug!("clients: {clients_mut:#?}");
+            match message {
+                Message::Register(r) => {
+                    // A client registering again keeps its place
+                    if !clients_mut.contains_key(&client_addr.to_string()) {
+                        if let Err(reason) = options.check_registration(clients_mut.len()) {
+                            drop(clients_mut);
+                            log::warn!("refused registration from {client_addr}: {reason}");
+                            let error = reason.clone();
+                            log_event(EventKind::ClientError { error }).await;
+                            reply(&tx_opt, Message::UpdateRejected(reason), client_addr).await;
+                            continue;
                         }
                     }
-                    Message::Exit => {
-                        if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
-                            on_client_exit_callback(client);
-                            clients_mut.remove(&client_addr.to_string());
+                    let verified = options.check_source_ip(client_addr.ip(), r.ip_addresses());
+                    if !verified {
+                        log::warn!(
+                            "client {} connects from {client_addr}, which is not one of its IPs {:?}",
+                            r.hostname(),
+                            r.ip_addresses()
+                        );
+                    }
+                    let client = Client::new(
+                        r.hostname().to_owned(),
+                        r.pretty_name().map(|r| r.to_string()),
+                        r.ip_addresses().to_vec(),
+                    )
+                    .with_max_updates(options.max_updates_per_client)
+                    .with_verified(verified);
+                    on_client_registration_callback(&client);
+                    event_log.write().await.push(
+                        EventKind::Registered,
+                        Some(client_addr),
+                        Some(&client.hostname),
+                    );
+                    clients_mut.insert(client_addr.to_string(), client);
+                }
+                Message::Update(update) => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        let kind = update_received(&update);
+                        client.add_update(update);
+                        on_client_update_callback(client);
+                        log_event(kind).await;
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
+                        let error = "update from an unregistered client".to_string();
+                        log_event(EventKind::ClientError { error }).await;
+                    }
+                }
+                Message::UpdateDelta(delta) => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        let kind = update_received(&delta);
+                        client.add_delta(delta);
+                        on_client_update_callback(client);
+                        log_event(kind).await;
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
+                        let error = "update from an unregistered client".to_string();
+                        log_event(EventKind::ClientError { error }).await;
+                    }
+                }
+                Message::Capabilities(capabilities) => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        log::debug!("capabilities of {}: {capabilities:?}", client.hostname);
+                        client.set_capabilities(capabilities);
+                        drop(clients_mut);
+                        let capabilities = CapabilitySet::current();
+                        reply(&tx_opt, Message::Capabilities(capabilities), client_addr).await;
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
+                    }
+                }
+                Message::AgentStats(agent_stats) => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        log::debug!("recording stats of {}: {agent_stats:?}", client.hostname);
+                        client.set_agent_stats(agent_stats);
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
+                    }
+                }
+                Message::UpdateDetails(details) => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        client.set_pending_details(details);
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
+                    }
+                }
+                Message::ClockOffset(clock_offset_ms) => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        log::debug!("clock offset of {}: {clock_offset_ms} ms", client.hostname);
+                        client.set_clock_offset_ms(clock_offset_ms);
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
+                    }
+                }
+                Message::Exit => {
+                    if let Some(client) = clients_mut.get_mut(&client_addr.to_string()) {
+                        on_client_exit_callback(client);
+                        // The clients told to exit by `shutdown` are kept for their updates
+                        if client.is_exiting() {
+                            client.set_disconnected();
                         } else {
-                            log::error!("unknown client: {}", client_addr);
+                            clients_mut.remove(&client_addr.to_string());
                         }
+                        log_event(EventKind::Disconnected).await;
+                    } else {
+                        log::error!("unknown client: {}", client_addr);
                     }
-                    _ => (),
-                };
-            }
+                }
+                _ => (),
+            };
+        }
+    });
+
+    // Report how the loop ended, including if it panicked
+    tokio::spawn(async move {
+        let state = task
+            .await
+            .unwrap_or_else(|e| ServerState::Failed(format!("the server task failed: {e}")));
+        match &state {
+            ServerState::Failed(reason) => log::error!("server stopped: {reason}"),
+            _ => log::info!("server stopped"),
         }
+        let _ = state_tx.send(state);
     });
 
-    Ok(tx)
+    Ok((state_rx, event_log))
+}
+
+/// Stop the server without losing the updates the clients are still sending. The clients are
+/// told to exit, then the server keeps receiving until all of them have answered it or
+/// disconnected, or `timeout` has elapsed, before the run token is cancelled and the end of the
+/// listening loop awaited.
+pub async fn shutdown(
+    tx_opt: &RwLock<Option<BincodeSender<Message>>>,
+    clients: &RwLock<HashMap<String, Client>>,
+    run_token: &CancellationToken,
+    state_rx: &mut watch::Receiver<ServerState>,
+    timeout: Duration,
+) {
+    for client in clients.write().await.values_mut() {
+        client.set_exiting();
+    }
+    if let Some(tx) = tx_opt.write().await.as_mut() {
+        let (res, _accept_res) = tx.send(Message::Exit).accepting().await;
+        if let Err(e) = res {
+            log::error!("unable to tell the clients to exit: {e}");
+        }
+    }
+
+    // The agents answer Exit after the updates they were sending. A clean close of the connection
+    // is not reported, so the older agents, which do not answer, are waited for until `timeout`.
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let running = *state_rx.borrow() == ServerState::Running;
+        if !running || clients.read().await.values().all(|c| c.is_disconnected()) {
+            break;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            log::warn!("some clients did not disconnect in time, their last updates may be lost");
+            break;
+        }
+        tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+
+    run_token.cancel();
+    let stopped = async {
+        while *state_rx.borrow() == ServerState::Running {
+            if state_rx.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+    if tokio::time::timeout(timeout, stopped).await.is_err() {
+        log::warn!("the listening loop did not stop in time");
+    }
+    tx_opt.write().await.take();
+}
+
+/// Event of a received update, with its size and its number of established connections
+fn update_received(update: &Update) -> EventKind {
+    EventKind::UpdateReceived {
+        bytes: bincode::serialized_size(update).unwrap_or_default(),
+        connections: update.host.connections().len(),
+    }
+}
+
+/// Ask the clients for an update aggregating `samples` snapshots taken `interval` seconds apart.
+/// The agents which do not support it are sent a plain `Message::UpdateRequest`, as they would
+/// fail to decode the sampled one.
+pub async fn request_sampled_update(
+    clients: &RwLock<HashMap<String, Client>>,
+    tx_opt: &RwLock<Option<BincodeSender<Message>>>,
+    samples: u8,
+    interval: f64,
+) -> Result<()> {
+    let mut sampled_addrs = Vec::new();
+    let mut single_addrs = Vec::new();
+    for (client_addr, client) in clients.read().await.iter() {
+        let Ok(client_addr) = client_addr.parse::<SocketAddr>() else {
+            continue;
+        };
+        match samples > 1 && client.supports(Capability::SampledUpdates) {
+            true => sampled_addrs.push(client_addr),
+            false => single_addrs.push(client_addr),
+        }
+    }
+
+    let mut tx_opt = tx_opt.write().await;
+    let tx = tx_opt
+        .as_mut()
+        .ok_or_else(|| anyhow!("the server is not running"))?;
+    if !sampled_addrs.is_empty() {
+        let message = Message::UpdateRequestSampled { samples, interval };
+        send_to(tx, message, &sampled_addrs)
+            .await
+            .map_err(|e| anyhow!("unable to send the sampled update request: {e}"))?;
+    }
+    if !single_addrs.is_empty() {
+        send_to(tx, Message::UpdateRequest, &single_addrs)
+            .await
+            .map_err(|e| anyhow!("unable to send the update request: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Tell the clients to start recording with `interval`. The agents supporting it are sent the
+/// current time of the server, to measure the offset of their clock, and the others a plain
+/// `Message::StartRecording`, as they would fail to decode it.
+pub async fn start_recording(
+    clients: &RwLock<HashMap<String, Client>>,
+    tx_opt: &RwLock<Option<BincodeSender<Message>>>,
+    interval: f64,
+) -> Result<()> {
+    let client_addrs: Vec<String> = clients.read().await.keys().cloned().collect();
+    start_clients_recording(clients, tx_opt, &client_addrs, interval).await
+}
+
+/// Tell the clients at `client_addrs` to start recording with `interval`, as [`start_recording`]
+/// does for every client
+pub async fn start_clients_recording(
+    clients: &RwLock<HashMap<String, Client>>,
+    tx_opt: &RwLock<Option<BincodeSender<Message>>>,
+    client_addrs: &[String],
+    interval: f64,
+) -> Result<()> {
+    let mut clock_addrs = Vec::new();
+    let mut plain_addrs = Vec::new();
+    for (client_addr, client) in clients.read().await.iter() {
+        if !client_addrs.contains(client_addr) {
+            continue;
+        }
+        let Ok(client_addr) = client_addr.parse::<SocketAddr>() else {
+            continue;
+        };
+        match client.supports(Capability::ServerClock) {
+            true => clock_addrs.push(client_addr),
+            false => plain_addrs.push(client_addr),
+        }
+    }
+
+    let mut tx_opt = tx_opt.write().await;
+    let tx = tx_opt
+        .as_mut()
+        .ok_or_else(|| anyhow!("the server is not running"))?;
+    if !clock_addrs.is_empty() {
+        let server_time_ms = clock::timestamp_ms(chrono::Utc::now());
+        let message = Message::StartRecordingAt {
+            interval,
+            server_time_ms,
+        };
+        send_to(tx, message, &clock_addrs)
+            .await
+            .map_err(|e| anyhow!("unable to send the start of the recording: {e}"))?;
+    }
+    if !plain_addrs.is_empty() {
+        send_to(tx, Message::StartRecording(interval), &plain_addrs)
+            .await
+            .map_err(|e| anyhow!("unable to send the start of the recording: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Send a message to the clients at `client_addrs`. The connections of the agents registered
+/// since the last message are accepted first, as the sender would skip them otherwise.
+async fn send_to(
+    tx: &mut BincodeSender<Message>,
+    message: Message,
+    client_addrs: &[SocketAddr],
+) -> Result<()> {
+    let (res, _accept_res) = tx.send(message).to(client_addrs).accepting().await;
+    res.map_err(|e| anyhow!(e.to_string()))
+}
+
+/// Send a message to a single client
+async fn reply(
+    tx_opt: &RwLock<Option<BincodeSender<Message>>>,
+    message: Message,
+    client_addr: SocketAddr,
+) {
+    if let Some(tx) = tx_opt.write().await.as_mut() {
+        if let Err(e) = send_to(tx, message, &[client_addr]).await {
+            log::error!("unable to reply to {client_addr}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::message::{AgentStats, Capability, Register};
+    use crate::test_utils::HostBuilder;
+    use std::time::Duration;
+
+    /// Get a free TCP port on the loopback interface
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Update of a host with `count` IPs
+    fn make_update(count: u32) -> Update {
+        let mut host = HostBuilder::new("machine1");
+        for i in 0..count {
+            host = host.ip(&std::net::Ipv4Addr::from(0x0a00_0000 + i).to_string());
+        }
+        Update::new(host.build())
+    }
+
+    /// Updates larger than the limit must be rejected
+    #[test]
+    fn test_check_update() {
+        let update = make_update(100);
+        let size = bincode::serialized_size(&update).unwrap();
+        let options = ListenOptions {
+            max_update_bytes: size,
+            ..Default::default()
+        };
+        assert!(options.check_update(&update).is_ok());
+        let reason = options.check_update(&make_update(101)).unwrap_err();
+        assert!(reason.contains(&format!("limit of {size} bytes")));
+    }
+
+    /// Registrations beyond the clients limit must be refused
+    #[test]
+    fn test_check_registration() {
+        let options = ListenOptions {
+            max_clients: 2,
+            ..Default::default()
+        };
+        assert!(options.check_registration(1).is_ok());
+        assert!(options.check_registration(2).is_err());
+        assert!(ListenOptions::default()
+            .check_registration(DEFAULT_MAX_CLIENTS - 1)
+            .is_ok());
+    }
+
+    /// The clients must be verified if they connect from one of their IPs or from an exempt one
+    #[test]
+    fn test_check_source_ip() {
+        let ip_addresses = ["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+        let mut options = ListenOptions::default();
+        assert!(options.check_source_ip("10.0.0.2".parse().unwrap(), &ip_addresses));
+
+        options.verify_source_ip = true;
+        assert!(options.check_source_ip("10.0.0.1".parse().unwrap(), &ip_addresses));
+        assert!(options.check_source_ip("::ffff:10.0.0.1".parse().unwrap(), &ip_addresses));
+        assert!(options.check_source_ip("fd00::1".parse().unwrap(), &ip_addresses));
+        assert!(!options.check_source_ip("10.0.0.2".parse().unwrap(), &ip_addresses));
+        assert!(!options.check_source_ip("10.0.0.1".parse().unwrap(), &[]));
+
+        options.verify_exempt_ips = vec!["192.168.1.254".parse().unwrap()];
+        assert!(options.check_source_ip("192.168.1.254".parse().unwrap(), &ip_addresses));
+        assert!(!options.check_source_ip("10.0.0.2".parse().unwrap(), &ip_addresses));
+    }
+
+    /// Cancelling the run token must stop the loop, and be reported on the state channel
+    #[tokio::test]
+    async fn test_listen_state() {
+        let run_token = CancellationToken::new();
+        let tx_opt = Arc::new(RwLock::new(None));
+        let (mut state_rx, _event_log) = listen(
+            format!("127.0.0.1:{}", free_port()),
+            Arc::new(RwLock::new(HashMap::new())),
+            tx_opt.clone(),
+            run_token.clone(),
+            ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*state_rx.borrow(), ServerState::Running);
+
+        run_token.cancel();
+        tokio::time::timeout(Duration::from_secs(5), state_rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(*state_rx.borrow(), ServerState::Stopped);
+    }
+
+    /// Connect a fake agent and register it, then give its receiving and sending halves
+    macro_rules! register_agent {
+        ($server_addr:expr, $hostname:expr, $ip_addresses:expr) => {{
+            let channel: tsyncp::channel::BincodeChannel<Message> =
+                tsyncp::channel::channel_to($server_addr.clone())
+                    .await
+                    .unwrap();
+            let (agent_rx, mut agent_tx) = channel.split();
+            agent_tx
+                .send(Message::Register(Register::new(
+                    $hostname.to_string(),
+                    None,
+                    $ip_addresses,
+                )))
+                .await
+                .unwrap();
+            (agent_rx, agent_tx)
+        }};
+    }
+
+    /// Wait for the next message sent to the agent
+    macro_rules! next_message {
+        ($agent_rx:expr) => {
+            tokio::time::timeout(Duration::from_secs(5), $agent_rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        };
+    }
+
+    /// The oversized updates and the registrations beyond the limit must be answered with the
+    /// reason, and the accepted updates be stored
+    #[tokio::test]
+    async fn test_listen_limits() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let tx_opt = Arc::new(RwLock::new(None));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        let max_update_bytes = bincode::serialized_size(&make_update(10)).unwrap();
+        listen(
+            server_addr.clone(),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+            ListenOptions {
+                max_updates_per_client: 2,
+                max_update_bytes,
+                max_clients: 1,
+                ..Default::default()
+            },
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let (mut agent_rx, mut agent_tx) = register_agent!(server_addr, "machine1", vec![]);
+        for _ in 0..50 {
+            if !clients.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(clients.read().await.len(), 1);
+
+        // Oversized update
+        agent_tx
+            .send(Message::Update(make_update(11)))
+            .await
+            .unwrap();
+        let message = next_message!(agent_rx);
+        assert!(
+            matches!(&message, Message::UpdateRejected(reason) if reason.contains("exceeds")),
+            "{message:?}"
+        );
+
+        // Accepted updates, the oldest being dropped
+        let updates: Vec<Update> = [1, 2, 3].into_iter().map(make_update).collect();
+        for update in &updates {
+            agent_tx
+                .send(Message::Update(update.clone()))
+                .await
+                .unwrap();
+        }
+        let stored_updates = |client: &Client| -> Vec<Update> {
+            client
+                .updates()
+                .iter()
+                .map(|timed_update| timed_update.update.clone())
+                .collect()
+        };
+        for _ in 0..50 {
+            if stored_updates(clients.read().await.values().next().unwrap()) == updates[1..] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(
+            stored_updates(clients.read().await.values().next().unwrap()),
+            &updates[1..]
+        );
+
+        // Second agent, beyond the clients limit
+        let (mut other_rx, _other_tx) = register_agent!(server_addr, "machine2", vec![]);
+        let message = next_message!(other_rx);
+        assert!(
+            matches!(&message, Message::UpdateRejected(reason) if reason.contains("refused")),
+            "{message:?}"
+        );
+        assert_eq!(clients.read().await.len(), 1);
+
+        run_token.cancel();
+    }
+
+    /// The clients registering with the IP they connect from must be verified, and the others
+    /// accepted but flagged
+    #[tokio::test]
+    async fn test_listen_verify_source_ip() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        listen(
+            server_addr.clone(),
+            clients.clone(),
+            Arc::new(RwLock::new(None)),
+            run_token.clone(),
+            ListenOptions {
+                verify_source_ip: true,
+                ..Default::default()
+            },
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let _machine1 = register_agent!(
+            server_addr,
+            "machine1",
+            vec!["10.0.0.1".parse().unwrap(), "127.0.0.1".parse().unwrap()]
+        );
+        let _clone = register_agent!(server_addr, "clone", vec!["10.0.0.1".parse().unwrap()]);
+        for _ in 0..50 {
+            if clients.read().await.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let clients = clients.read().await;
+        let verified = |hostname: &str| {
+            clients
+                .values()
+                .find(|c| c.hostname == hostname)
+                .unwrap()
+                .verified()
+        };
+        assert!(verified("machine1"));
+        assert!(!verified("clone"));
+
+        run_token.cancel();
+    }
+
+    /// The capabilities and the recording stats of an agent must be stored, the capabilities being
+    /// answered with the ones of the server, even for the agents connecting after the first answer,
+    /// while the agents which do not send theirs are left without
+    #[tokio::test]
+    async fn test_listen_capabilities() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        listen(
+            server_addr.clone(),
+            clients.clone(),
+            Arc::new(RwLock::new(None)),
+            run_token.clone(),
+            ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let _old_agent = register_agent!(server_addr, "old", vec![]);
+        let (mut agent_rx, mut agent_tx) = register_agent!(server_addr, "new", vec![]);
+        let capabilities = CapabilitySet::default().with(Capability::DeltaUpdates, 1);
+        agent_tx
+            .send(Message::Capabilities(capabilities.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message!(agent_rx),
+            Message::Capabilities(CapabilitySet::current())
+        );
+        let (mut late_rx, mut late_tx) = register_agent!(server_addr, "late", vec![]);
+        late_tx
+            .send(Message::Capabilities(CapabilitySet::current()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message!(late_rx),
+            Message::Capabilities(CapabilitySet::current())
+        );
+        let agent_stats = AgentStats {
+            cpu_ms_per_sample: 12.5,
+            buffered_bytes: 4096,
+            skipped: 1,
+        };
+        agent_tx
+            .send(Message::AgentStats(agent_stats))
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            let clients = clients.read().await;
+            if clients.len() == 3 && clients.values().any(|c| c.agent_stats().is_some()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let clients = clients.read().await;
+        let client = |hostname: &str| clients.values().find(|c| c.hostname == hostname).unwrap();
+        assert_eq!(client("new").capabilities(), Some(&capabilities));
+        assert!(client("new").supports(Capability::DeltaUpdates));
+        assert!(!client("new").supports(Capability::CompressedUpdates));
+        assert_eq!(client("old").capabilities(), None);
+        assert!(!client("old").supports(Capability::DeltaUpdates));
+        assert_eq!(client("new").agent_stats(), Some(agent_stats));
+        assert_eq!(client("old").agent_stats(), None);
+
+        run_token.cancel();
+    }
+
+    /// The agents supporting the sampled updates must be asked for one and the others for a plain
+    /// update, the answers being stored as any other update
+    #[tokio::test]
+    async fn test_request_sampled_update() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let tx_opt = Arc::new(RwLock::new(None));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        listen(
+            server_addr.clone(),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+            ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let (mut old_rx, _old_tx) = register_agent!(server_addr, "old", vec![]);
+        let (mut new_rx, mut new_tx) = register_agent!(server_addr, "new", vec![]);
+        new_tx
+            .send(Message::Capabilities(CapabilitySet::current()))
+            .await
+            .unwrap();
+        next_message!(new_rx);
+        for _ in 0..50 {
+            if clients.read().await.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        request_sampled_update(&clients, &tx_opt, 5, 1.0)
+            .await
+            .unwrap();
+        assert_eq!(
+            next_message!(new_rx),
+            Message::UpdateRequestSampled {
+                samples: 5,
+                interval: 1.0
+            }
+        );
+        assert_eq!(next_message!(old_rx), Message::UpdateRequest);
+
+        let update = make_update(3);
+        new_tx.send(Message::Update(update.clone())).await.unwrap();
+        for _ in 0..50 {
+            if clients
+                .read()
+                .await
+                .values()
+                .any(|c| !c.updates().is_empty())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let stored = clients
+            .read()
+            .await
+            .values()
+            .find(|c| c.hostname == "new")
+            .map(|c| {
+                c.updates()
+                    .iter()
+                    .map(|u| u.update.clone())
+                    .collect::<Vec<_>>()
+            });
+        assert_eq!(stored, Some(vec![update]));
+
+        // A single sample is a plain update request
+        request_sampled_update(&clients, &tx_opt, 1, 1.0)
+            .await
+            .unwrap();
+        assert_eq!(next_message!(new_rx), Message::UpdateRequest);
+        assert_eq!(next_message!(old_rx), Message::UpdateRequest);
+
+        run_token.cancel();
+    }
+
+    /// The agents supporting the server clock must get the time of the server with the start of
+    /// the recording and the others a plain start, the offsets sent back being stored
+    #[tokio::test]
+    async fn test_start_recording() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let tx_opt = Arc::new(RwLock::new(None));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        listen(
+            server_addr.clone(),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+            ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let (mut old_rx, _old_tx) = register_agent!(server_addr, "old", vec![]);
+        let (mut new_rx, mut new_tx) = register_agent!(server_addr, "new", vec![]);
+        new_tx
+            .send(Message::Capabilities(CapabilitySet::current()))
+            .await
+            .unwrap();
+        next_message!(new_rx);
+        for _ in 0..50 {
+            if clients.read().await.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let before = clock::timestamp_ms(chrono::Utc::now());
+        start_recording(&clients, &tx_opt, 2.5).await.unwrap();
+        match next_message!(new_rx) {
+            Message::StartRecordingAt {
+                interval,
+                server_time_ms,
+            } => {
+                assert_eq!(interval, 2.5);
+                assert!(server_time_ms >= before);
+            }
+            m => panic!("unexpected message: {m:?}"),
+        }
+        assert_eq!(next_message!(old_rx), Message::StartRecording(2.5));
+
+        new_tx.send(Message::ClockOffset(-90_000)).await.unwrap();
+        for _ in 0..50 {
+            if clients
+                .read()
+                .await
+                .values()
+                .any(|c| c.clock_offset_ms().is_some())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let clients = clients.read().await;
+        let client = |hostname: &str| clients.values().find(|c| c.hostname == hostname).unwrap();
+        assert_eq!(client("new").clock_offset_ms(), Some(-90_000));
+        assert_eq!(client("old").clock_offset_ms(), None);
+
+        run_token.cancel();
+    }
+
+    /// Only the given clients must be told to start recording, the others keeping their recording
+    #[tokio::test]
+    async fn test_start_clients_recording() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let tx_opt = Arc::new(RwLock::new(None));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        listen(
+            server_addr.clone(),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+            ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let (mut first_rx, _first_tx) = register_agent!(server_addr, "first", vec![]);
+        let (mut second_rx, _second_tx) = register_agent!(server_addr, "second", vec![]);
+        for _ in 0..50 {
+            if clients.read().await.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let (first_addr, second_addr) = {
+            let clients = clients.read().await;
+            let client_addr = |hostname: &str| {
+                let (client_addr, _) = clients
+                    .iter()
+                    .find(|(_, c)| c.hostname == hostname)
+                    .unwrap();
+                client_addr.clone()
+            };
+            (client_addr("first"), client_addr("second"))
+        };
+
+        start_clients_recording(&clients, &tx_opt, &[second_addr], 1.0)
+            .await
+            .unwrap();
+        assert_eq!(next_message!(second_rx), Message::StartRecording(1.0));
+        start_clients_recording(&clients, &tx_opt, &[first_addr], 2.0)
+            .await
+            .unwrap();
+        assert_eq!(next_message!(first_rx), Message::StartRecording(2.0));
+
+        run_token.cancel();
+    }
+
+    /// The registration, updates and exit of an agent must be logged in order, as well as the
+    /// updates which are rejected
+    #[tokio::test]
+    async fn test_listen_event_log() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        let (_state_rx, event_log) = listen(
+            server_addr.clone(),
+            clients.clone(),
+            Arc::new(RwLock::new(None)),
+            run_token.clone(),
+            ListenOptions {
+                max_update_bytes: bincode::serialized_size(&make_update(10)).unwrap(),
+                ..Default::default()
+            },
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let (mut agent_rx, mut agent_tx) = register_agent!(server_addr, "machine1", vec![]);
+        let update = make_update(2);
+        agent_tx
+            .send(Message::Update(update.clone()))
+            .await
+            .unwrap();
+        agent_tx
+            .send(Message::Update(make_update(11)))
+            .await
+            .unwrap();
+        next_message!(agent_rx);
+        agent_tx.send(Message::Exit).await.unwrap();
+        for _ in 0..50 {
+            if event_log.read().await.events().len() == 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let event_log = event_log.read().await;
+        let kinds: Vec<&EventKind> = event_log.events().map(|e| &e.kind).collect();
+        assert_eq!(kinds.len(), 4, "{kinds:?}");
+        assert_eq!(kinds[0], &EventKind::Registered);
+        assert_eq!(
+            kinds[1],
+            &EventKind::UpdateReceived {
+                bytes: bincode::serialized_size(&update).unwrap(),
+                connections: update.host.connections().len(),
+            }
+        );
+        assert!(matches!(kinds[2], EventKind::ClientError { error } if error.contains("exceeds")));
+        assert_eq!(kinds[3], &EventKind::Disconnected);
+        assert!(event_log
+            .events()
+            .all(|e| e.hostname.as_deref() == Some("machine1") && e.client_addr.is_some()));
+
+        run_token.cancel();
+    }
+
+    /// An update still being sent when the server shuts down must be recorded, the agent only
+    /// disconnecting once it is sent
+    #[tokio::test]
+    async fn test_shutdown_drains_updates() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let tx_opt = Arc::new(RwLock::new(None));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        let (mut state_rx, _event_log) = listen(
+            server_addr.clone(),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+            ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+
+        let (mut agent_rx, mut agent_tx) = register_agent!(server_addr, "machine1", vec![]);
+        for _ in 0..50 {
+            if !clients.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // The agent answers Exit like the real one does
+        let update = make_update(10_000);
+        let agent_update = update.clone();
+        let agent = tokio::spawn(async move {
+            agent_tx.send(Message::Update(agent_update)).await.unwrap();
+            assert!(matches!(next_message!(agent_rx), Message::Exit));
+            agent_tx.send(Message::Exit).await.unwrap();
+        });
+        shutdown(
+            &tx_opt,
+            &clients,
+            &run_token,
+            &mut state_rx,
+            Duration::from_secs(10),
+        )
+        .await;
+        agent.await.unwrap();
+
+        assert_eq!(*state_rx.borrow(), ServerState::Stopped);
+        assert!(tx_opt.read().await.is_none());
+        let clients = clients.read().await;
+        let client = clients.values().next().unwrap();
+        assert!(client.is_disconnected());
+        assert_eq!(client.updates().len(), 1);
+        assert_eq!(client.updates()[0].update, update);
+    }
 }
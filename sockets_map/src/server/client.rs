@@ -1,29 +1,21 @@
-use std::net::IpAddr;
+use std::{net::IpAddr, ops::RangeBounds};
+
+use chrono::{DateTime, Utc};
 
 use crate::{
-    host::Host,
-    parsers::{linux::LinuxHostRawData, windows::WindowsHostRawData},
+    host::{Host, HostDetails},
+    server::message::{AgentStats, Capability, CapabilitySet},
 };
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-pub enum HostData {
-    LinuxHostData(LinuxHostRawData),
-    WindowsHostData(WindowsHostRawData),
-}
-
-impl From<HostData> for anyhow::Result<Host> {
-    fn from(host_data: HostData) -> Self {
-        match host_data {
-            HostData::LinuxHostData(h) => h.into(),
-            HostData::WindowsHostData(h) => h.into(),
-        }
-    }
-}
+#[deprecated(since = "1.0.0", note = "moved to `sockets_map::parsers::HostData`")]
+pub type HostData = crate::parsers::HostData;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Update {
+    /// Sent in the layout of the first versions, its details being sent apart
+    #[serde(with = "crate::host::wire")]
     pub host: Host,
 }
 
@@ -33,6 +25,41 @@ impl Update {
     }
 }
 
+/// Update stored by the server, along with the time at which it was received
+#[derive(PartialEq, Debug, Clone)]
+pub struct TimedUpdate {
+    pub update: Update,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Updates of a client drawn in the graphs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateSelection {
+    /// The last update received
+    #[default]
+    Latest,
+    /// The oldest update kept, the previous ones being dropped beyond the updates limit
+    First,
+    /// All the updates kept, merged into a single host
+    MergeAll,
+}
+
+/// Aggregate several updates of the same host into one.
+/// This is the equivalent of joining the output of the commands.
+pub fn generate_aggregate_update(updates: &[Update]) -> anyhow::Result<Update> {
+    if let Some(first_update) = updates.first() {
+        let mut aggregated_host = first_update.host.clone();
+        for update in updates {
+            aggregated_host.merge(&update.host);
+        }
+
+        return Ok(Update::new(aggregated_host));
+    }
+
+    Err(anyhow::Error::msg("no updates were made"))
+}
+
 /// This structure stores the information that is passed from the clients to the server.
 /// It is to be transformed into a Host structure in order to use the connection model on it.
 #[derive(Debug)]
@@ -42,8 +69,29 @@ pub struct Client {
     /// List of local IPs on the client
     pub ips: Vec<IpAddr>,
 
-    /// Number of updates given by the client
-    updates: Vec<Update>,
+    /// Updates given by the client, in the order they were received
+    updates: Vec<TimedUpdate>,
+    /// Time at which the last update was received
+    last_update_time: Option<DateTime<Utc>>,
+    /// Whether deltas were merged into the last update since the last full update
+    merged_deltas: bool,
+    /// Number of updates to keep, the oldest ones being dropped first
+    max_updates: usize,
+    /// Whether the client connected from one of the IPs it registered with, if this is checked
+    verified: bool,
+    /// Capabilities sent by the client, `None` for the agents of the previous versions
+    capabilities: Option<CapabilitySet>,
+    /// Last resources usage sent by the client while recording
+    agent_stats: Option<AgentStats>,
+    /// Offset of the clock of the client to the one of the server, in milliseconds, measured at the
+    /// start of the last recording
+    clock_offset_ms: Option<i64>,
+    /// Whether the connection to the client was lost, the client being kept for its updates
+    disconnected: bool,
+    /// Whether the server told the client to exit, its answer only disconnecting it
+    exiting: bool,
+    /// Details of the next update, sent just before it
+    pending_details: Option<HostDetails>,
 }
 
 impl Client {
@@ -51,16 +99,382 @@ impl Client {
         Self {
             ips,
             updates: vec![],
+            last_update_time: None,
+            merged_deltas: false,
+            max_updates: usize::MAX,
+            verified: true,
+            capabilities: None,
+            agent_stats: None,
+            clock_offset_ms: None,
+            disconnected: false,
+            exiting: false,
+            pending_details: None,
             hostname,
             pretty_name,
         }
     }
 
+    /// Keep only the last `max_updates` updates of this client
+    pub fn with_max_updates(mut self, max_updates: usize) -> Self {
+        self.max_updates = max_updates.max(1);
+        self
+    }
+
+    /// Flag the client as connecting from an IP it did not register with
+    pub fn with_verified(mut self, verified: bool) -> Self {
+        self.verified = verified;
+        self
+    }
+
     pub fn add_update(&mut self, update: Update) {
-        self.updates.push(update);
+        self.add_update_at(update, Utc::now());
     }
 
-    pub fn updates(&self) -> &[Update] {
+    /// Store an update received at `received_at`, which must not be older than the stored ones
+    pub fn add_update_at(&mut self, mut update: Update, received_at: DateTime<Utc>) {
+        if let Some(details) = self.pending_details.take() {
+            update.host.apply_details(details);
+        }
+        // A full update following deltas is sent at the end of a recording, and must match them
+        if self.merged_deltas {
+            if let Some(last_update) = self.updates.last() {
+                if !same_sockets(&last_update.update.host, &update.host) {
+                    log::warn!(
+                        "final update from {} does not match the received deltas",
+                        self.hostname
+                    );
+                }
+            }
+            self.merged_deltas = false;
+        }
+        self.updates.push(TimedUpdate {
+            update,
+            received_at,
+        });
+        if self.updates.len() > self.max_updates {
+            let excess = self.updates.len() - self.max_updates;
+            self.updates.drain(..excess);
+        }
+        self.last_update_time = Some(received_at);
+    }
+
+    /// Merge a delta update into the last update, or store it as is if there is none
+    pub fn add_delta(&mut self, mut delta: Update) {
+        if let Some(details) = self.pending_details.take() {
+            delta.host.apply_details(details);
+        }
+        let now = Utc::now();
+        match self.updates.last_mut() {
+            Some(last_update) => {
+                last_update.update.host.merge(&delta.host);
+                self.merged_deltas = true;
+            }
+            None => self.updates.push(TimedUpdate {
+                update: delta,
+                received_at: now,
+            }),
+        }
+        self.last_update_time = Some(now);
+    }
+
+    /// Get the updates kept, the oldest first.
+    pub fn updates(&self) -> &[TimedUpdate] {
         self.updates.as_ref()
     }
+
+    /// Get the last update received, if any.
+    pub fn latest(&self) -> Option<&TimedUpdate> {
+        self.updates.last()
+    }
+
+    /// Get the update at `index`, the oldest kept one being at 0.
+    pub fn at(&self, index: usize) -> Option<&TimedUpdate> {
+        self.updates.get(index)
+    }
+
+    /// Updates received within `range`, the oldest first
+    pub fn between(
+        &self,
+        range: impl RangeBounds<DateTime<Utc>>,
+    ) -> impl Iterator<Item = &TimedUpdate> {
+        self.updates
+            .iter()
+            .filter(move |timed_update| range.contains(&timed_update.received_at))
+    }
+
+    /// Host of the updates picked by `selection`, if the client sent any
+    pub fn selected_host(&self, selection: UpdateSelection) -> Option<Host> {
+        match selection {
+            UpdateSelection::Latest => self.latest().map(|latest| latest.update.host.clone()),
+            UpdateSelection::First => self.at(0).map(|first| first.update.host.clone()),
+            UpdateSelection::MergeAll => {
+                let (first, others) = self.updates.split_first()?;
+                let mut host = first.update.host.clone();
+                for timed_update in others {
+                    host.merge(&timed_update.update.host);
+                }
+                Some(host)
+            }
+        }
+    }
+
+    /// Time at which the last update was received, if any
+    pub fn last_update_time(&self) -> Option<DateTime<Utc>> {
+        self.last_update_time
+    }
+
+    /// Whether the client connected from one of the IPs it registered with. Clients are verified
+    /// unless the server checks their IPs.
+    pub fn verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Get a reference to the client's capabilities, if it sent them
+    pub fn capabilities(&self) -> Option<&CapabilitySet> {
+        self.capabilities.as_ref()
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: CapabilitySet) {
+        self.capabilities = Some(capabilities);
+    }
+
+    /// Whether the client supports the capability, which the agents of the previous versions never
+    /// do
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports(capability))
+    }
+
+    /// Whether deltas were merged into the last update, and no full update followed them yet
+    pub fn merged_deltas(&self) -> bool {
+        self.merged_deltas
+    }
+
+    /// Get the last resources usage sent by the client while recording, if any
+    pub fn agent_stats(&self) -> Option<AgentStats> {
+        self.agent_stats
+    }
+
+    pub fn set_agent_stats(&mut self, agent_stats: AgentStats) {
+        self.agent_stats = Some(agent_stats);
+    }
+
+    /// Get the offset of the client's clock to the server's, in milliseconds, if it sent it
+    pub fn clock_offset_ms(&self) -> Option<i64> {
+        self.clock_offset_ms
+    }
+
+    pub fn set_clock_offset_ms(&mut self, clock_offset_ms: i64) {
+        self.clock_offset_ms = Some(clock_offset_ms);
+    }
+
+    /// Keep the details sent by the client for its next update or delta
+    pub fn set_pending_details(&mut self, details: HostDetails) {
+        self.pending_details = Some(details);
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    pub fn set_disconnected(&mut self) {
+        self.disconnected = true;
+    }
+
+    pub fn is_exiting(&self) -> bool {
+        self.exiting
+    }
+
+    pub fn set_exiting(&mut self) {
+        self.exiting = true;
+    }
+}
+
+/// Check that two hosts have the same connections and listening sockets, regardless of their order
+fn same_sockets(a: &Host, b: &Host) -> bool {
+    fn sorted<T: Ord + Clone>(v: &[T]) -> Vec<T> {
+        let mut v = v.to_vec();
+        v.sort();
+        v.dedup();
+        v
+    }
+    sorted(a.connections()) == sorted(b.connections())
+        && sorted(a.listening_sockets()) == sorted(b.listening_sockets())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::SocketType;
+    use crate::test_utils::HostBuilder;
+
+    fn make_update(connections: &[(&str, &str)], listening: &[&str]) -> Update {
+        let mut host = HostBuilder::new("machine1");
+        for (local, peer) in connections {
+            host = host.connect_tcp(local, peer, "curl", 100);
+        }
+        for socket in listening {
+            host = host.listen(socket, SocketType::TCP, "nginx", 101, None);
+        }
+        Update::new(host.build())
+    }
+
+    /// Aggregating updates must keep every connection and listening socket once
+    #[test]
+    fn test_aggregate_update() {
+        let updates = vec![
+            make_update(&[("10.0.0.1:5000", "10.0.0.2:443")], &["0.0.0.0:80"]),
+            make_update(
+                &[
+                    ("10.0.0.1:5000", "10.0.0.2:443"),
+                    ("10.0.0.1:5001", "10.0.0.3:443"),
+                ],
+                &["0.0.0.0:80", "0.0.0.0:8080"],
+            ),
+        ];
+        let aggregate = generate_aggregate_update(&updates).unwrap();
+        assert_eq!(aggregate.host.connections().len(), 2);
+        assert_eq!(aggregate.host.listening_sockets().len(), 2);
+
+        assert!(generate_aggregate_update(&[]).is_err());
+    }
+
+    /// A PID reused by another process between two updates must give two processes, while the
+    /// processes whose start time is missing in one update are matched by their PID
+    #[test]
+    fn test_aggregate_update_reused_pid() {
+        let update = |name: &str, start_time: Option<u64>| {
+            let host = HostBuilder::new("machine1")
+                .start_time(start_time)
+                .listen_tcp(80, name, 100)
+                .build();
+            Update::new(host)
+        };
+        let processes = |updates: &[Update]| {
+            let aggregate = generate_aggregate_update(updates).unwrap();
+            aggregate
+                .host
+                .listening_sockets()
+                .iter()
+                .map(|s| (s.process().name().to_string(), s.process().start_time()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            processes(&[update("nginx", Some(10)), update("apache2", Some(20))]),
+            [
+                ("apache2".to_string(), Some(20)),
+                ("nginx".to_string(), Some(10))
+            ]
+        );
+        assert_eq!(
+            processes(&[update("nginx", Some(10)), update("nginx", Some(20))]),
+            [
+                ("nginx".to_string(), Some(10)),
+                ("nginx".to_string(), Some(20))
+            ]
+        );
+        assert_eq!(
+            processes(&[update("nginx", None), update("nginx", Some(10))]),
+            [("nginx".to_string(), Some(10))]
+        );
+    }
+
+    /// Deltas merged by the client must amount to the aggregate of the snapshots
+    #[test]
+    fn test_deltas_match_aggregate() {
+        let snapshots = vec![
+            make_update(&[("10.0.0.1:5000", "10.0.0.2:443")], &["0.0.0.0:80"]),
+            make_update(&[("10.0.0.1:5001", "10.0.0.3:443")], &["0.0.0.0:80"]),
+            make_update(
+                &[("10.0.0.1:5002", "10.0.0.4:443")],
+                &["0.0.0.0:80", "0.0.0.0:8080"],
+            ),
+        ];
+
+        // Compute and send deltas the way the agent does
+        let mut client = Client::new("machine1".to_string(), None, vec![]);
+        let mut sent_host = snapshots[0].host.clone();
+        client.add_update(snapshots[0].clone());
+        for snapshot in &snapshots[1..] {
+            let delta = snapshot.host.delta(&sent_host);
+            assert_eq!(delta.connections().len(), 1);
+            sent_host.merge(&snapshot.host);
+            client.add_delta(Update::new(delta));
+        }
+
+        let aggregate = generate_aggregate_update(&snapshots).unwrap();
+        assert_eq!(client.updates().len(), 1);
+        assert!(same_sockets(
+            &client.updates()[0].update.host,
+            &aggregate.host
+        ));
+        assert!(same_sockets(&sent_host, &aggregate.host));
+    }
+
+    /// Only the last updates must be kept once the cap is reached
+    #[test]
+    fn test_max_updates() {
+        let mut client = Client::new("machine1".to_string(), None, vec![]).with_max_updates(2);
+        let updates = vec![
+            make_update(&[("10.0.0.1:5000", "10.0.0.2:443")], &[]),
+            make_update(&[("10.0.0.1:5001", "10.0.0.2:443")], &[]),
+            make_update(&[("10.0.0.1:5002", "10.0.0.2:443")], &[]),
+        ];
+        for update in &updates {
+            client.add_update(update.clone());
+        }
+        let kept: Vec<&Update> = client.updates().iter().map(|u| &u.update).collect();
+        assert_eq!(kept, [&updates[1], &updates[2]]);
+
+        // Deltas are merged into the last update and do not count
+        client.add_delta(make_update(&[("10.0.0.1:5003", "10.0.0.2:443")], &[]));
+        assert_eq!(client.updates().len(), 2);
+        assert_eq!(client.updates()[1].update.host.connections().len(), 2);
+    }
+
+    /// The updates must be picked by index, by reception time, and merged for the graphs
+    #[test]
+    fn test_update_selection() {
+        let mut client = Client::new("machine1".to_string(), None, vec![]);
+        assert_eq!(client.selected_host(UpdateSelection::MergeAll), None);
+        let start = Utc::now();
+        let seconds = |s| start + chrono::Duration::seconds(s);
+        let updates = [
+            make_update(&[("10.0.0.1:5000", "10.0.0.2:443")], &["0.0.0.0:80"]),
+            make_update(&[("10.0.0.1:5001", "10.0.0.3:443")], &["0.0.0.0:80"]),
+            make_update(&[("10.0.0.1:5002", "10.0.0.4:22")], &["0.0.0.0:8080"]),
+        ];
+        for (index, update) in updates.iter().enumerate() {
+            client.add_update_at(update.clone(), seconds(10 * index as i64));
+        }
+
+        assert_eq!(client.latest().unwrap().update, updates[2]);
+        assert_eq!(client.at(1).unwrap().received_at, seconds(10));
+        assert!(client.at(3).is_none());
+        let received: Vec<&Update> = client
+            .between(seconds(5)..=seconds(20))
+            .map(|u| &u.update)
+            .collect();
+        assert_eq!(received, [&updates[1], &updates[2]]);
+        assert_eq!(client.between(..seconds(10)).count(), 1);
+        assert_eq!(client.last_update_time(), Some(seconds(20)));
+
+        assert_eq!(
+            client.selected_host(UpdateSelection::Latest),
+            Some(updates[2].host.clone())
+        );
+        assert_eq!(
+            client.selected_host(UpdateSelection::First),
+            Some(updates[0].host.clone())
+        );
+        let merged = client.selected_host(UpdateSelection::MergeAll).unwrap();
+        assert_eq!(merged.connections().len(), 3);
+        assert_eq!(merged.listening_sockets().len(), 2);
+        assert!(same_sockets(
+            &merged,
+            &generate_aggregate_update(&updates).unwrap().host
+        ));
+    }
 }
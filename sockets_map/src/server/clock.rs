@@ -0,0 +1,75 @@
+//! Offset between the clock of an agent and the one of the server, measured when a recording
+//! starts, so that the times of the agents can be correlated with the logs of the server
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+/// Offsets below this number of milliseconds cannot be told apart from the time the start of the
+/// recording took to reach the agent, and are taken as synchronized clocks
+pub const CLOCK_OFFSET_TOLERANCE_MS: i64 = 500;
+
+/// Time to send in a message, in milliseconds since the UNIX epoch
+pub fn timestamp_ms(time: DateTime<Utc>) -> i64 {
+    time.timestamp_millis()
+}
+
+/// Time sent by the server, if it is valid
+pub fn from_timestamp_ms(timestamp_ms: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(timestamp_ms).single()
+}
+
+/// Offset to add to the times of the agent to get the ones of the server, in milliseconds, from the
+/// time of the server when it sent a message and the time of the agent when it received it.
+///
+/// The message took some time to arrive, so the offset is short by this latency, which is usually
+/// a few milliseconds on a LAN. The offsets within [`CLOCK_OFFSET_TOLERANCE_MS`] are rounded to 0,
+/// so that synchronized clocks are not shifted by the latency.
+pub fn clock_offset_ms(server_time: DateTime<Utc>, received_at: DateTime<Utc>) -> i64 {
+    let offset_ms = (server_time - received_at).num_milliseconds();
+    match offset_ms.abs() < CLOCK_OFFSET_TOLERANCE_MS {
+        true => 0,
+        false => offset_ms,
+    }
+}
+
+/// Time of the server matching the time `local` of an agent whose clock has `offset_ms`
+pub fn to_server_time(local: DateTime<Utc>, offset_ms: i64) -> DateTime<Utc> {
+    local + Duration::milliseconds(offset_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offset must bring the times of the agent to the ones of the server, the small offsets
+    /// being taken for the latency
+    #[test]
+    fn test_clock_offset() {
+        let server_time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            from_timestamp_ms(timestamp_ms(server_time)),
+            Some(server_time)
+        );
+        assert_eq!(from_timestamp_ms(i64::MAX), None);
+
+        // The agent is 90 seconds late, and got the message 20 ms after it was sent
+        let received_at = server_time - Duration::seconds(90) + Duration::milliseconds(20);
+        let offset_ms = clock_offset_ms(server_time, received_at);
+        assert_eq!(offset_ms, 89_980);
+        let snapshot = received_at + Duration::seconds(30);
+        assert_eq!(
+            to_server_time(snapshot, offset_ms),
+            server_time + Duration::seconds(30)
+        );
+
+        // The agent is ahead
+        let received_at = server_time + Duration::seconds(5);
+        assert_eq!(clock_offset_ms(server_time, received_at), -5_000);
+
+        // Within the tolerance, in both directions
+        let received_at = server_time + Duration::milliseconds(CLOCK_OFFSET_TOLERANCE_MS - 1);
+        assert_eq!(clock_offset_ms(server_time, received_at), 0);
+        let received_at = server_time - Duration::milliseconds(120);
+        assert_eq!(clock_offset_ms(server_time, received_at), 0);
+        assert_eq!(to_server_time(received_at, 0), received_at);
+    }
+}
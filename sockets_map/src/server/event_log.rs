@@ -0,0 +1,216 @@
+//! Chronological log of the server session: registrations, updates, recordings and errors
+
+use std::{collections::VecDeque, net::SocketAddr, path::Path};
+
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
+
+/// Number of events kept by default, the oldest ones being dropped first
+pub const DEFAULT_EVENT_LOG_CAPACITY: usize = 10_000;
+
+/// What happened
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    Registered,
+    /// Full update or delta, with its serialized size and its number of established connections
+    UpdateReceived {
+        bytes: u64,
+        connections: usize,
+    },
+    /// Recording started by the server, with the interval between the updates in seconds
+    RecordingStarted {
+        interval: f64,
+    },
+    RecordingStopped,
+    /// Message of a client which was refused or could not be read
+    ClientError {
+        error: String,
+    },
+    Disconnected,
+}
+
+impl EventKind {
+    /// Name of the event, as in the JSON dump
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Registered => "registered",
+            EventKind::UpdateReceived { .. } => "update_received",
+            EventKind::RecordingStarted { .. } => "recording_started",
+            EventKind::RecordingStopped => "recording_stopped",
+            EventKind::ClientError { .. } => "client_error",
+            EventKind::Disconnected => "disconnected",
+        }
+    }
+
+    /// Details of the event, empty if it has none
+    pub fn details(&self) -> String {
+        match self {
+            EventKind::UpdateReceived { bytes, connections } => {
+                format!("{bytes} bytes, {connections} connections")
+            }
+            EventKind::RecordingStarted { interval } => format!("interval of {interval}s"),
+            EventKind::ClientError { error } => error.clone(),
+            EventKind::Registered | EventKind::RecordingStopped | EventKind::Disconnected => {
+                String::new()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    #[serde(serialize_with = "serialize_time")]
+    pub time: DateTime<Utc>,
+    /// Address of the client, `None` for the events of the whole server
+    pub client_addr: Option<SocketAddr>,
+    /// Hostname of the client, once it is registered
+    pub hostname: Option<String>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl Event {
+    /// Time of the event, in RFC 3339 format
+    pub fn time_string(&self) -> String {
+        self.time.to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+}
+
+fn serialize_time<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// Events of the session in chronological order, up to a capacity
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    capacity: usize,
+    /// Number of events dropped to stay within the capacity
+    dropped: usize,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_LOG_CAPACITY)
+    }
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Add an event which happens now
+    pub fn push(
+        &mut self,
+        kind: EventKind,
+        client_addr: Option<SocketAddr>,
+        hostname: Option<&str>,
+    ) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(Event {
+            time: Utc::now(),
+            client_addr,
+            hostname: hostname.map(str::to_string),
+            kind,
+        });
+    }
+
+    /// Add an event of the whole server, such as the start of a recording
+    pub fn push_server_event(&mut self, kind: EventKind) {
+        self.push(kind, None, None);
+    }
+
+    /// Get the events, the oldest first
+    pub fn events(&self) -> impl DoubleEndedIterator<Item = &Event> + ExactSizeIterator {
+        self.events.iter()
+    }
+
+    /// Get the number of events dropped to stay within the capacity.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Dump the events as a JSON array
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(&self.events)?)
+    }
+
+    /// Dump the events as CSV, with the time, client address, hostname, event and details columns
+    pub fn to_csv(&self) -> anyhow::Result<String> {
+        let mut wtr = csv::Writer::from_writer(Vec::new());
+        wtr.write_record(["time", "client_addr", "hostname", "event", "details"])?;
+        for event in &self.events {
+            wtr.write_record([
+                event.time_string(),
+                event
+                    .client_addr
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_default(),
+                event.hostname.clone().unwrap_or_default(),
+                event.kind.name().to_string(),
+                event.kind.details(),
+            ])?;
+        }
+        let bytes = wtr.into_inner().context("unable to flush CSV")?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    /// Write the events to `path`, as CSV if its extension is `csv` and as JSON otherwise
+    pub fn write_to(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => self.to_csv()?,
+            _ => self.to_json()?,
+        };
+        std::fs::write(path, contents)
+            .with_context(|| format!("unable to write the event log to {path:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The oldest events must be dropped beyond the capacity, and the dumps list the others
+    #[test]
+    fn test_event_log() {
+        let mut event_log = EventLog::new(2);
+        let client_addr = Some("10.0.0.1:50000".parse().unwrap());
+        event_log.push(EventKind::Registered, client_addr, Some("machine1"));
+        event_log.push_server_event(EventKind::RecordingStarted { interval: 2.0 });
+        event_log.push(
+            EventKind::UpdateReceived {
+                bytes: 1024,
+                connections: 3,
+            },
+            client_addr,
+            Some("machine1"),
+        );
+        assert_eq!(event_log.dropped(), 1);
+        let kinds: Vec<&str> = event_log.events().map(|e| e.kind.name()).collect();
+        assert_eq!(kinds, ["recording_started", "update_received"]);
+
+        let json: serde_json::Value = serde_json::from_str(&event_log.to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["type"], "recording_started");
+        assert_eq!(json[0]["interval"], 2.0);
+        assert_eq!(json[0]["client_addr"], serde_json::Value::Null);
+        assert_eq!(json[1]["hostname"], "machine1");
+        assert_eq!(json[1]["bytes"], 1024);
+
+        let csv = event_log.to_csv().unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "time,client_addr,hostname,event,details");
+        assert!(lines[1].ends_with(",,,recording_started,interval of 2s"));
+        assert!(lines[2]
+            .ends_with(",10.0.0.1:50000,machine1,update_received,\"1024 bytes, 3 connections\""));
+    }
+}
@@ -0,0 +1,229 @@
+//! Read-only HTTP status endpoint, started alongside [`super::listen`].
+//!
+//! Routes:
+//! - `GET /clients`: JSON list of the registered clients
+//! - `GET /healthz`: liveness probe
+//! - `POST /update-request`: broadcast an update request to all clients
+
+use super::{client::Client, message::Message};
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr, net::SocketAddr, sync::Arc};
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tsyncp::broadcast::BincodeSender;
+
+pub const DEFAULT_HTTP_PORT: u16 = 6841;
+
+/// Status of a client, as served on `GET /clients`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ClientStatus {
+    pub hostname: String,
+    pub pretty_name: Option<String>,
+    pub ips: Vec<IpAddr>,
+    pub update_count: usize,
+    pub last_update_time: Option<DateTime<Utc>>,
+    /// Offset of the clock of the client to the one of the server, in milliseconds
+    pub clock_offset_ms: Option<i64>,
+}
+
+impl From<&Client> for ClientStatus {
+    fn from(client: &Client) -> Self {
+        Self {
+            hostname: client.hostname.clone(),
+            pretty_name: client.pretty_name.clone(),
+            ips: client.ips.clone(),
+            update_count: client.updates().len(),
+            last_update_time: client.last_update_time(),
+            clock_offset_ms: client.clock_offset_ms(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct HttpState {
+    clients: Arc<RwLock<HashMap<String, Client>>>,
+    tx_opt: Arc<RwLock<Option<BincodeSender<Message>>>>,
+}
+
+/// Start the HTTP status server on `http_addr`, until `run_token` is cancelled.
+///
+/// The listening socket is bound before returning, so that binding errors are reported to the caller.
+/// Returns the address the server is actually bound to.
+pub async fn serve(
+    http_addr: String,
+    clients: Arc<RwLock<HashMap<String, Client>>>,
+    tx_opt: Arc<RwLock<Option<BincodeSender<Message>>>>,
+    run_token: CancellationToken,
+) -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind(http_addr)?;
+    listener.set_nonblocking(true)?;
+    let local_addr = listener.local_addr()?;
+
+    let app = Router::new()
+        .route("/clients", get(get_clients))
+        .route("/healthz", get(get_healthz))
+        .route("/update-request", post(post_update_request))
+        .with_state(HttpState { clients, tx_opt });
+    let server = axum::Server::from_tcp(listener)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move { run_token.cancelled().await });
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("HTTP status server error: {e}");
+        }
+    });
+    log::info!("HTTP status server listening on {local_addr}");
+
+    Ok(local_addr)
+}
+
+async fn get_clients(State(state): State<HttpState>) -> Json<Vec<ClientStatus>> {
+    let clients = state.clients.read().await;
+    let mut statuses: Vec<ClientStatus> = clients.values().map(ClientStatus::from).collect();
+    statuses.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+    Json(statuses)
+}
+
+async fn get_healthz() -> &'static str {
+    "ok"
+}
+
+async fn post_update_request(State(state): State<HttpState>) -> StatusCode {
+    match state.tx_opt.write().await.as_mut() {
+        Some(tx) => {
+            let (res, _accept_res) = tx.send(Message::UpdateRequest).accepting().await;
+            match res {
+                Ok(_) => StatusCode::ACCEPTED,
+                Err(e) => {
+                    log::error!("unable to send update request: {e}");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            }
+        }
+        None => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{self, message::Register};
+    use hyper::{body, Body, Method, Request};
+    use std::time::Duration;
+
+    /// Get a free TCP port on the loopback interface
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Run an agents server and its HTTP status endpoint on loopback, register a fake agent
+    /// and query every route with a real HTTP client.
+    #[tokio::test]
+    async fn test_http_status_endpoint() {
+        let clients = Arc::new(RwLock::new(HashMap::new()));
+        let run_token = CancellationToken::new();
+        let server_addr = format!("127.0.0.1:{}", free_port());
+        let tx_opt = Arc::new(RwLock::new(None));
+        server::listen(
+            server_addr.clone(),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+            server::ListenOptions::default(),
+            |_| (),
+            |_| (),
+            |_| (),
+            |_| (),
+        )
+        .await
+        .unwrap();
+        let http_addr = serve(
+            "127.0.0.1:0".to_string(),
+            clients.clone(),
+            tx_opt,
+            run_token.clone(),
+        )
+        .await
+        .unwrap();
+
+        // Register a fake agent
+        let channel: tsyncp::channel::BincodeChannel<Message> =
+            tsyncp::channel::channel_to(server_addr).await.unwrap();
+        let (mut agent_rx, mut agent_tx) = channel.split();
+        agent_tx
+            .send(Message::Register(Register::new(
+                "machine1".to_string(),
+                Some("web server".to_string()),
+                vec!["10.0.0.1".parse().unwrap()],
+            )))
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            if !clients.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let http_client = hyper::Client::new();
+
+        // Health check
+        let res = http_client
+            .get(format!("http://{http_addr}/healthz").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body::to_bytes(res.into_body()).await.unwrap(), "ok");
+
+        // Clients list
+        let res = http_client
+            .get(format!("http://{http_addr}/clients").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let statuses: Vec<ClientStatus> =
+            serde_json::from_slice(&body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(
+            statuses,
+            vec![ClientStatus {
+                hostname: "machine1".to_string(),
+                pretty_name: Some("web server".to_string()),
+                ips: vec!["10.0.0.1".parse().unwrap()],
+                update_count: 0,
+                last_update_time: None,
+                clock_offset_ms: None,
+            }]
+        );
+
+        // Update request must reach the agent
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{http_addr}/update-request"))
+            .body(Body::empty())
+            .unwrap();
+        let res = http_client.request(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let message = tokio::time::timeout(Duration::from_secs(5), agent_rx.recv())
+            .await
+            .unwrap();
+        assert!(
+            matches!(message, Some(Ok(Message::UpdateRequest))),
+            "agent did not receive the update request: {message:?}"
+        );
+
+        run_token.cancel();
+    }
+}
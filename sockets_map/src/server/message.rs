@@ -1,6 +1,11 @@
 use super::{client::Update, host};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::{collections::BTreeMap, net::IpAddr};
+
+/// Updates whose serialized size exceeds this number of bytes are sent compressed
+pub(crate) const COMPRESSION_THRESHOLD: u64 = 256 * 1024;
+const COMPRESSION_LEVEL: i32 = 3;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Message {
@@ -10,6 +15,174 @@ pub enum Message {
     StartRecording(f64),
     StopRecording,
     Exit,
+    /// zstd-compressed bincode serialization of an `Update`
+    UpdateCompressed(Vec<u8>),
+    /// New connections and listening sockets since the last update, sent while recording
+    UpdateDelta(Update),
+    /// Sent back to an agent whose update or registration was rejected, with the reason
+    UpdateRejected(String),
+    /// Optional features of the peer, sent by the agents right after their registration. The
+    /// server answers with its own ones, so that older agents never receive this message.
+    Capabilities(CapabilitySet),
+    /// Update request for an update aggregating `samples` snapshots taken `interval` seconds
+    /// apart, to catch the short-lived connections. Only sent to the agents supporting
+    /// [`Capability::SampledUpdates`].
+    UpdateRequestSampled {
+        samples: u8,
+        interval: f64,
+    },
+    /// Cost of the recording on the agent, sent periodically while recording. Only sent to the
+    /// servers supporting [`Capability::AgentStats`].
+    AgentStats(AgentStats),
+    /// Same as `StartRecording`, with the UTC time of the server when it was sent, in milliseconds
+    /// since the UNIX epoch, from which the agents measure the offset of their clock. Only sent to
+    /// the agents supporting [`Capability::ServerClock`].
+    StartRecordingAt {
+        interval: f64,
+        server_time_ms: i64,
+    },
+    /// Offset of the clock of the agent to the one of the server, in milliseconds, measured at the
+    /// start of the recording. Only sent to the servers supporting [`Capability::ServerClock`].
+    ClockOffset(i64),
+    /// Details of the host of the next `Update`, `UpdateCompressed` or `UpdateDelta`, which its
+    /// layout leaves out. Only sent to the servers supporting [`Capability::UpdateDetails`].
+    UpdateDetails(host::HostDetails),
+}
+
+impl Message {
+    /// Wrap an update into a message, compressing it if it is larger than [`COMPRESSION_THRESHOLD`]
+    pub fn from_update(update: Update) -> Result<Self> {
+        if bincode::serialized_size(&update)? > COMPRESSION_THRESHOLD {
+            let serialized = bincode::serialize(&update)?;
+            Ok(Message::UpdateCompressed(zstd::encode_all(
+                serialized.as_slice(),
+                COMPRESSION_LEVEL,
+            )?))
+        } else {
+            Ok(Message::Update(update))
+        }
+    }
+
+    /// Messages sending the update, in full or as a `delta`: its details first, unless it has
+    /// none, then the update itself
+    pub fn update_messages(update: Update, delta: bool) -> Result<Vec<Self>> {
+        let mut messages: Vec<Self> = update
+            .host
+            .details()
+            .map(Message::UpdateDetails)
+            .into_iter()
+            .collect();
+        messages.push(match delta {
+            true => Message::UpdateDelta(update),
+            false => Self::from_update(update)?,
+        });
+        Ok(messages)
+    }
+}
+
+/// Decode the content of a `Message::UpdateCompressed`
+pub(crate) fn decompress_update(data: &[u8]) -> Result<Update> {
+    let serialized = zstd::decode_all(data)?;
+    Ok(bincode::deserialize(&serialized)?)
+}
+
+/// Optional features of the protocol, which the agents and the server may not all support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// `Message::UpdateCompressed`
+    CompressedUpdates,
+    /// `Message::UpdateDelta`
+    DeltaUpdates,
+    /// `Message::UpdateRejected`
+    UpdateRejected,
+    /// `Message::UpdateRequestSampled`
+    SampledUpdates,
+    /// `Message::AgentStats`
+    AgentStats,
+    /// `Message::StartRecordingAt` and `Message::ClockOffset`
+    ServerClock,
+    /// `Message::UpdateDetails`
+    UpdateDetails,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 7] = [
+        Capability::CompressedUpdates,
+        Capability::DeltaUpdates,
+        Capability::UpdateRejected,
+        Capability::SampledUpdates,
+        Capability::AgentStats,
+        Capability::ServerClock,
+        Capability::UpdateDetails,
+    ];
+
+    /// Name of the capability in the exchanged sets
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::CompressedUpdates => "compressed_updates",
+            Capability::DeltaUpdates => "delta_updates",
+            Capability::UpdateRejected => "update_rejected",
+            Capability::SampledUpdates => "sampled_updates",
+            Capability::AgentStats => "agent_stats",
+            Capability::ServerClock => "server_clock",
+            Capability::UpdateDetails => "update_details",
+        }
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Capabilities of a peer, with the version of each. They are stored by name, so that a peer
+/// ignores the capabilities it does not know instead of failing to decode the message.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CapabilitySet {
+    versions: BTreeMap<String, u32>,
+}
+
+impl CapabilitySet {
+    /// Capabilities of this version of sockets_map
+    pub fn current() -> Self {
+        Capability::ALL
+            .iter()
+            .fold(Self::default(), |set, capability| set.with(*capability, 1))
+    }
+
+    /// Add a capability, at `version`
+    pub fn with(mut self, capability: Capability, version: u32) -> Self {
+        self.versions.insert(capability.name().to_string(), version);
+        self
+    }
+
+    /// Version of the capability, if the peer supports it
+    pub fn version(&self, capability: Capability) -> Option<u32> {
+        self.versions.get(capability.name()).copied()
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.version(capability).is_some()
+    }
+
+    /// Names of the capabilities, including the ones unknown to this version
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.versions.keys().map(String::as_str)
+    }
+}
+
+/// Resources used by the recording of an agent since its previous stats
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct AgentStats {
+    /// Time spent collecting a snapshot of the sockets, on average, in milliseconds
+    pub cpu_ms_per_sample: f64,
+    /// Size of the aggregate of the updates kept to compute the deltas, in bytes
+    pub buffered_bytes: u64,
+    /// Number of snapshots skipped since the start of the recording, because the previous one was
+    /// still being collected
+    pub skipped: u64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -74,3 +247,289 @@ impl Socket {
         self.ipv6_only
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{Host, Process, Route, SocketType};
+    use crate::test_utils::HostBuilder;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    /// Create a host with `count` distinct connections
+    fn make_busy_host(count: u32) -> Host {
+        let mut host = HostBuilder::new("busy").ip("10.0.0.1");
+        for i in 0..count {
+            let local = format!("10.0.0.1:{}", 10000 + i % 50000);
+            let peer = format!("{}:443", Ipv4Addr::from(0x0a01_0000 + i));
+            host = host.connect_tcp(&local, &peer, "worker", 1000 + i % 16);
+        }
+        host.build()
+    }
+
+    /// Large updates must be compressed and decode back to the same update
+    #[test]
+    fn test_large_update_is_compressed() {
+        let update = Update::new(make_busy_host(50_000));
+        let message = Message::from_update(update.clone()).unwrap();
+        let data = match message {
+            Message::UpdateCompressed(data) => data,
+            m => panic!(
+                "update was not compressed: {:?}",
+                std::mem::discriminant(&m)
+            ),
+        };
+        assert!((data.len() as u64) < bincode::serialized_size(&update).unwrap());
+        assert_eq!(decompress_update(&data).unwrap(), update);
+    }
+
+    /// Messages of the previous versions, without `Message::Capabilities`
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    enum OldMessage {
+        Register(OldRegister),
+        Update(OldUpdate),
+        UpdateRequest,
+        StartRecording(f64),
+        StopRecording,
+        Exit,
+        UpdateCompressed(Vec<u8>),
+        UpdateDelta(OldUpdate),
+        UpdateRejected(String),
+    }
+
+    // Layouts of the first versions, frozen so that any change of the current ones is caught
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct OldRegister {
+        hostname: String,
+        pretty_name: Option<String>,
+        ip_addresses: Vec<IpAddr>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct OldUpdate {
+        host: OldHost,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct OldHost {
+        name: String,
+        cluster_id: String,
+        listening_sockets: Vec<OldListeningSocket>,
+        connections: Vec<OldConnection>,
+        ips: Vec<IpAddr>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct OldListeningSocket {
+        socket: SocketAddr,
+        socket_type: OldSocketType,
+        process: OldProcess,
+        node_name: String,
+        node_id: String,
+        ipv6_only: Option<bool>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct OldConnection {
+        socket_type: OldSocketType,
+        local_socket: SocketAddr,
+        peer_socket: SocketAddr,
+        process: OldProcess,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct OldProcess {
+        name: String,
+        pid: u32,
+        node_id: String,
+    }
+
+    #[allow(clippy::upper_case_acronyms, dead_code)]
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    enum OldSocketType {
+        TCP,
+        UDP,
+        UNIX,
+    }
+
+    impl From<&Process> for OldProcess {
+        fn from(process: &Process) -> Self {
+            Self {
+                name: process.name().to_string(),
+                pid: *process.pid(),
+                node_id: process.node_id().to_string(),
+            }
+        }
+    }
+
+    /// Create a host with a listening socket and a connection, along with the data added since the
+    /// first versions if `detailed`: a start time and a route
+    fn make_host(detailed: bool) -> Host {
+        let mut host = HostBuilder::new("web-01")
+            .ip("10.0.0.1")
+            .start_time(detailed.then_some(4200))
+            .listen("[::]:443", SocketType::TCP, "nginx", 80, Some(false))
+            .connect_tcp("10.0.0.1:443", "10.0.0.2:51234", "nginx", 80)
+            .build();
+        if detailed {
+            host.add_route(Route::new(
+                "10.0.0.0".parse().unwrap(),
+                24,
+                None,
+                Some("eth0".to_string()),
+            ));
+        }
+        host
+    }
+
+    /// The same host, as the agents of the first versions send it
+    fn old_update(host: &Host) -> OldUpdate {
+        let listening_sockets = host
+            .listening_sockets()
+            .iter()
+            .map(|s| OldListeningSocket {
+                socket: *s.socket(),
+                socket_type: OldSocketType::TCP,
+                process: s.process().into(),
+                node_name: s.node_name().to_string(),
+                node_id: s.node_id().to_string(),
+                ipv6_only: s.ipv6_only().cloned(),
+            })
+            .collect();
+        let connections = host
+            .connections()
+            .iter()
+            .map(|c| OldConnection {
+                socket_type: OldSocketType::TCP,
+                local_socket: *c.local_socket(),
+                peer_socket: *c.peer_socket(),
+                process: c.process().into(),
+            })
+            .collect();
+        OldUpdate {
+            host: OldHost {
+                name: host.name().to_string(),
+                cluster_id: host.cluster_id().to_string(),
+                listening_sockets,
+                connections,
+                ips: host.ips().to_vec(),
+            },
+        }
+    }
+
+    /// The messages of older peers must be decoded as before, and the ones they know must still be
+    /// decoded by them
+    #[test]
+    fn test_old_messages_compatibility() {
+        let ips: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap()];
+        let old_register = OldRegister {
+            hostname: "machine1".into(),
+            pretty_name: None,
+            ip_addresses: ips.clone(),
+        };
+        let register = Register::new("machine1".into(), None, ips);
+        let host = make_host(true);
+        let plain_host = make_host(false);
+        let details = host.details().unwrap();
+        let pairs = [
+            (
+                OldMessage::Register(old_register),
+                Message::Register(register),
+            ),
+            (
+                OldMessage::Update(old_update(&host)),
+                Message::Update(Update::new(plain_host.clone())),
+            ),
+            (
+                OldMessage::UpdateDelta(old_update(&host)),
+                Message::UpdateDelta(Update::new(plain_host.clone())),
+            ),
+            (OldMessage::UpdateRequest, Message::UpdateRequest),
+            (
+                OldMessage::StartRecording(2.5),
+                Message::StartRecording(2.5),
+            ),
+            (OldMessage::StopRecording, Message::StopRecording),
+            (OldMessage::Exit, Message::Exit),
+            (
+                OldMessage::UpdateRejected("too large".into()),
+                Message::UpdateRejected("too large".into()),
+            ),
+        ];
+        for (old, new) in pairs {
+            let old_bytes = bincode::serialize(&old).unwrap();
+            assert_eq!(bincode::deserialize::<Message>(&old_bytes).unwrap(), new);
+            let new_bytes = bincode::serialize(&new).unwrap();
+            assert_eq!(bincode::deserialize::<OldMessage>(&new_bytes).unwrap(), old);
+        }
+
+        // The details are left out of the updates sent to the older servers, which still decode
+        // them, and are sent apart to the other ones
+        let messages = Message::update_messages(Update::new(host.clone()), false).unwrap();
+        assert_eq!(messages[0], Message::UpdateDetails(details.clone()));
+        let update_bytes = bincode::serialize(&messages[1]).unwrap();
+        assert_eq!(
+            bincode::deserialize::<OldMessage>(&update_bytes).unwrap(),
+            OldMessage::Update(old_update(&host))
+        );
+        let mut received = match bincode::deserialize::<Message>(&update_bytes).unwrap() {
+            Message::Update(update) => update.host,
+            m => panic!("unexpected message: {m:?}"),
+        };
+        received.apply_details(details);
+        assert_eq!(received, host);
+
+        // Compressed updates of the older agents are decoded as well
+        let old_bytes = bincode::serialize(&old_update(&host)).unwrap();
+        let compressed = zstd::encode_all(old_bytes.as_slice(), COMPRESSION_LEVEL).unwrap();
+        assert_eq!(
+            decompress_update(&compressed).unwrap(),
+            Update::new(plain_host)
+        );
+
+        // Older peers cannot decode the capabilities, so they are only sent back to the agents
+        // which sent theirs
+        let capabilities = bincode::serialize(&Message::Capabilities(CapabilitySet::current()));
+        assert!(bincode::deserialize::<OldMessage>(&capabilities.unwrap()).is_err());
+        let sampled = bincode::serialize(&Message::UpdateRequestSampled {
+            samples: 5,
+            interval: 1.0,
+        });
+        assert!(bincode::deserialize::<OldMessage>(&sampled.unwrap()).is_err());
+        let stats = bincode::serialize(&Message::AgentStats(AgentStats::default()));
+        assert!(bincode::deserialize::<OldMessage>(&stats.unwrap()).is_err());
+        let start = bincode::serialize(&Message::StartRecordingAt {
+            interval: 2.5,
+            server_time_ms: 1_700_000_000_000,
+        });
+        assert!(bincode::deserialize::<OldMessage>(&start.unwrap()).is_err());
+    }
+
+    /// The capabilities unknown to a peer must be kept by name and ignored
+    #[test]
+    fn test_unknown_capabilities() {
+        let mut future = CapabilitySet::current().with(Capability::DeltaUpdates, 2);
+        future.versions.insert("future_feature".into(), 1);
+        let bytes = bincode::serialize(&Message::Capabilities(future)).unwrap();
+        let set = match bincode::deserialize::<Message>(&bytes).unwrap() {
+            Message::Capabilities(set) => set,
+            m => panic!("unexpected message: {m:?}"),
+        };
+        assert!(set.supports(Capability::CompressedUpdates));
+        assert_eq!(set.version(Capability::DeltaUpdates), Some(2));
+        assert!(set.names().any(|name| name == "future_feature"));
+
+        let empty = CapabilitySet::default();
+        assert!(Capability::ALL.iter().all(|c| !empty.supports(*c)));
+    }
+
+    /// Small updates are sent as is, for compatibility
+    #[test]
+    fn test_small_update_is_not_compressed() {
+        let update = Update::new(make_busy_host(10));
+        assert_eq!(
+            Message::from_update(update.clone()).unwrap(),
+            Message::Update(update)
+        );
+    }
+}
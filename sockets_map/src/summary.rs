@@ -0,0 +1,188 @@
+//! This module prints the connections as a table, for a quick look in a terminal.
+
+use crate::connections_model::Connection;
+use comfy_table::{presets, Attribute, Cell, Color, Table};
+use std::collections::BTreeMap;
+
+/// Aggregation of the connections in the summary table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Count the connections between each pair of hosts
+    Host,
+    /// Count the connections between each pair of processes
+    Process,
+    /// Count the connections to each destination port
+    Port,
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(GroupBy::Host),
+            "process" => Ok(GroupBy::Process),
+            "port" => Ok(GroupBy::Port),
+            _ => Err("unknown grouping, expected host, process or port"),
+        }
+    }
+}
+
+/// Cell of the summary table, colored by kind of content
+enum SummaryCell {
+    Host(String),
+    Process(String),
+    Port(u16),
+    Protocol(String),
+}
+
+impl SummaryCell {
+    fn into_cell(self) -> Cell {
+        match self {
+            SummaryCell::Host(name) => Cell::new(name).fg(Color::Cyan),
+            SummaryCell::Process(name) => Cell::new(name).fg(Color::Green),
+            SummaryCell::Port(port) => Cell::new(port).fg(Color::Yellow),
+            SummaryCell::Protocol(protocol) => Cell::new(protocol),
+        }
+    }
+
+    /// Key to sort the rows, with the ports sorted numerically
+    fn sort_key(&self) -> String {
+        match self {
+            SummaryCell::Host(s) | SummaryCell::Process(s) | SummaryCell::Protocol(s) => s.clone(),
+            SummaryCell::Port(port) => format!("{port:05}"),
+        }
+    }
+}
+
+/// Format the connections as an aligned table, one row per distinct connection or per group.
+/// The rows are sorted, and the table is colored when `color` is set.
+pub fn format_summary(
+    connections: &[Connection],
+    group_by: Option<GroupBy>,
+    color: bool,
+) -> String {
+    let header: &[&str] = match group_by {
+        None => &[
+            "Source host",
+            "Source process",
+            "Dest host",
+            "Dest process",
+            "Dest port",
+            "Protocol",
+            "Count",
+        ],
+        Some(GroupBy::Host) => &["Source host", "Dest host", "Count"],
+        Some(GroupBy::Process) => &[
+            "Source host",
+            "Source process",
+            "Dest host",
+            "Dest process",
+            "Count",
+        ],
+        Some(GroupBy::Port) => &["Dest port", "Protocol", "Count"],
+    };
+
+    // Count the connections of each row
+    let mut counts = BTreeMap::<Vec<String>, (usize, Vec<SummaryCell>)>::new();
+    for connection in connections {
+        let source_host = connection.connected_host().name();
+        let source_process = connection.connected_connection().process().name();
+        let dest_host = connection.listening_host().name();
+        let dest_process = connection.listening_connection().process().name();
+        let dest_port = connection.listening_connection().port();
+        let protocol = connection.listening_connection().socket_type().to_string();
+        let cells = match group_by {
+            None => vec![
+                SummaryCell::Host(source_host.to_string()),
+                SummaryCell::Process(source_process.to_string()),
+                SummaryCell::Host(dest_host.to_string()),
+                SummaryCell::Process(dest_process.to_string()),
+                SummaryCell::Port(dest_port),
+                SummaryCell::Protocol(protocol),
+            ],
+            Some(GroupBy::Host) => vec![
+                SummaryCell::Host(source_host.to_string()),
+                SummaryCell::Host(dest_host.to_string()),
+            ],
+            Some(GroupBy::Process) => vec![
+                SummaryCell::Host(source_host.to_string()),
+                SummaryCell::Process(source_process.to_string()),
+                SummaryCell::Host(dest_host.to_string()),
+                SummaryCell::Process(dest_process.to_string()),
+            ],
+            Some(GroupBy::Port) => vec![
+                SummaryCell::Port(dest_port),
+                SummaryCell::Protocol(protocol),
+            ],
+        };
+        let key = cells.iter().map(SummaryCell::sort_key).collect();
+        counts.entry(key).or_insert((0, cells)).0 += 1;
+    }
+
+    let mut table = Table::new();
+    if color {
+        table.load_preset(presets::UTF8_FULL).enforce_styling();
+    } else {
+        table.force_no_tty();
+    }
+    table.set_header(
+        header
+            .iter()
+            .map(|title| Cell::new(title).add_attribute(Attribute::Bold)),
+    );
+    for (count, cells) in counts.into_values() {
+        let count = Cell::new(count).add_attribute(Attribute::Bold);
+        table.add_row(cells.into_iter().map(SummaryCell::into_cell).chain([count]));
+    }
+
+    table.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections_model::{build_connections_list, tests::make_fake_connections};
+
+    /// One row per distinct connection
+    #[test]
+    fn test_format_summary() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+
+        assert_eq!(
+            format_summary(&connections, None, false),
+            "\
++-------------+-----------------+-----------+------------------+-----------+----------+-------+
+| Source host | Source process  | Dest host | Dest process     | Dest port | Protocol | Count |
++=============================================================================================+
+| machine2    | firefox         | machine1  | nginx            | 443       | TCP      | 1     |
+|-------------+-----------------+-----------+------------------+-----------+----------+-------|
+| machine2    | ssh             | machine1  | sshd             | 22        | TCP      | 1     |
+|-------------+-----------------+-----------+------------------+-----------+----------+-------|
+| machine3    | some_udp_client | machine2  | some_udp_service | 50001     | UDP      | 1     |
++-------------+-----------------+-----------+------------------+-----------+----------+-------+"
+        );
+    }
+
+    /// Connections counted by destination port, sorted numerically
+    #[test]
+    fn test_format_summary_group_by_port() {
+        let hosts = make_fake_connections();
+        let connections = build_connections_list(&hosts, false);
+
+        assert_eq!(
+            format_summary(&connections, Some(GroupBy::Port), false),
+            "\
++-----------+----------+-------+
+| Dest port | Protocol | Count |
++==============================+
+| 22        | TCP      | 1     |
+|-----------+----------+-------|
+| 443       | TCP      | 1     |
+|-----------+----------+-------|
+| 50001     | UDP      | 1     |
++-----------+----------+-------+"
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! This module builds hosts for the tests, here and in the crates using the library with the
+//! `test-fixtures` feature: a [`HostBuilder`] to write a host in a few lines, and canned
+//! scenarios covering the usual kinds of connections.
+//!
+//! ```
+//! use sockets_map::{connections_model::build_connections_list, test_utils::HostBuilder};
+//!
+//! let hosts = [
+//!     HostBuilder::new("web01")
+//!         .ip("10.0.0.1")
+//!         .listen_tcp(443, "nginx", 102)
+//!         .build(),
+//!     HostBuilder::new("laptop")
+//!         .ip("10.0.0.2")
+//!         .connect_tcp("10.0.0.2:50000", "10.0.0.1:443", "firefox", 201)
+//!         .build(),
+//! ];
+//! assert_eq!(build_connections_list(&hosts, false).len(), 1);
+//! ```
+
+use std::net::Ipv4Addr;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::host::{Connection, Host, ListeningSocket, Process, SocketType};
+
+/// Builder of a [`Host`], whose addresses are parsed from strings. It panics on invalid addresses,
+/// as fixtures are expected to be valid.
+#[derive(Debug, Clone)]
+pub struct HostBuilder {
+    host: Host,
+    /// Start time of the processes of the next sockets
+    start_time: Option<u64>,
+}
+
+impl HostBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            host: Host::new(name),
+            start_time: None,
+        }
+    }
+
+    /// Give `start_time` to the processes of the sockets added next, `None` for the agents which
+    /// could not collect it
+    pub fn start_time(mut self, start_time: Option<u64>) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    /// Add an IP address, such as `10.0.0.1` or `fd00::1`
+    pub fn ip(mut self, ip: &str) -> Self {
+        self.host.add_ip(ip.parse().expect("invalid IP address"));
+        self
+    }
+
+    /// Add a TCP socket listening on `port` of every IPv4 address
+    pub fn listen_tcp(self, port: u16, process: &str, pid: u32) -> Self {
+        self.listen(
+            &format!("0.0.0.0:{port}"),
+            SocketType::TCP,
+            process,
+            pid,
+            None,
+        )
+    }
+
+    /// Add a UDP socket listening on `port` of every IPv4 address
+    pub fn listen_udp(self, port: u16, process: &str, pid: u32) -> Self {
+        self.listen(
+            &format!("0.0.0.0:{port}"),
+            SocketType::UDP,
+            process,
+            pid,
+            None,
+        )
+    }
+
+    /// Add a listening socket bound to `socket`, such as `127.0.0.1:6379` or `[::]:22`.
+    /// `ipv6_only` is the `IPV6_V6ONLY` flag of the IPv6 sockets, if known.
+    pub fn listen(
+        mut self,
+        socket: &str,
+        socket_type: SocketType,
+        process: &str,
+        pid: u32,
+        ipv6_only: Option<bool>,
+    ) -> Self {
+        let name = self.host.name().to_string();
+        self.host.add_listening_socket(ListeningSocket::new(
+            socket.parse().expect("invalid socket address"),
+            socket_type,
+            Process::new(process, pid, name.clone()).with_start_time(self.start_time),
+            name,
+            ipv6_only,
+        ));
+        self
+    }
+
+    /// Add a TCP connection from the `local` socket to the `peer` one
+    pub fn connect_tcp(self, local: &str, peer: &str, process: &str, pid: u32) -> Self {
+        self.connect(local, peer, SocketType::TCP, process, pid)
+    }
+
+    /// Add a UDP socket bound to the `peer` one
+    pub fn connect_udp(self, local: &str, peer: &str, process: &str, pid: u32) -> Self {
+        self.connect(local, peer, SocketType::UDP, process, pid)
+    }
+
+    /// Add a connection from the `local` socket to the `peer` one
+    pub fn connect(
+        mut self,
+        local: &str,
+        peer: &str,
+        socket_type: SocketType,
+        process: &str,
+        pid: u32,
+    ) -> Self {
+        let name = self.host.name().to_string();
+        self.host.add_established_connection(Connection::new(
+            local.parse().expect("invalid socket address"),
+            peer.parse().expect("invalid socket address"),
+            socket_type,
+            Process::new(process, pid, name).with_start_time(self.start_time),
+        ));
+        self
+    }
+
+    pub fn build(self) -> Host {
+        self.host
+    }
+}
+
+/// SSH client `client` (10.0.0.2) connected to the server `server` (10.0.0.1)
+pub fn client_server() -> Vec<Host> {
+    vec![
+        HostBuilder::new("server")
+            .ip("10.0.0.1")
+            .listen_tcp(22, "sshd", 100)
+            .build(),
+        HostBuilder::new("client")
+            .ip("10.0.0.2")
+            .connect_tcp("10.0.0.2:50000", "10.0.0.1:22", "ssh", 200)
+            .build(),
+    ]
+}
+
+/// Syslog client `client` (10.0.0.2) sending to the UDP server `collector` (10.0.0.3)
+pub fn udp_pair() -> Vec<Host> {
+    vec![
+        HostBuilder::new("collector")
+            .ip("10.0.0.3")
+            .listen_udp(514, "rsyslogd", 300)
+            .build(),
+        HostBuilder::new("client")
+            .ip("10.0.0.2")
+            .connect_udp("10.0.0.2:50514", "10.0.0.3:514", "logger", 200)
+            .build(),
+    ]
+}
+
+/// NTP servers `ntp1` (10.0.0.4) and `ntp2` (10.0.0.5) peering with each other from their UDP
+/// port 123, which is matched from both ends, see
+/// [`crate::connections_model::collapse_bidirectional`]
+pub fn udp_symmetric() -> Vec<Host> {
+    vec![
+        HostBuilder::new("ntp1")
+            .ip("10.0.0.4")
+            .listen_udp(123, "ntpd", 400)
+            .connect_udp("10.0.0.4:123", "10.0.0.5:123", "ntpd", 400)
+            .build(),
+        HostBuilder::new("ntp2")
+            .ip("10.0.0.5")
+            .listen_udp(123, "ntpd", 500)
+            .connect_udp("10.0.0.5:123", "10.0.0.4:123", "ntpd", 500)
+            .build(),
+    ]
+}
+
+/// SSH client `client` (10.0.0.2) connected to the IPv4-mapped address of an IPv6 server
+/// accepting both versions, on `server` (10.0.0.1)
+pub fn v6_mapped() -> Vec<Host> {
+    vec![
+        HostBuilder::new("server")
+            .ip("10.0.0.1")
+            .listen("[::]:22", SocketType::TCP, "sshd", 100, Some(false))
+            .build(),
+        HostBuilder::new("client")
+            .ip("10.0.0.2")
+            .connect_tcp(
+                "[::ffff:10.0.0.2]:50000",
+                "[::ffff:10.0.0.1]:22",
+                "ssh",
+                200,
+            )
+            .build(),
+    ]
+}
+
+/// Application server `app` (10.0.0.2) connected to the SSH server of `db` (10.0.0.3), and to its
+/// PostgreSQL port whose listening socket is missing from the capture of `db`, which only has the
+/// accepted end of the connection. `app` also connects to a host which was not captured. See
+/// [`crate::connections_model::find_unmatched_known_peers`].
+pub fn missing_listener() -> Vec<Host> {
+    vec![
+        HostBuilder::new("db")
+            .ip("10.0.0.3")
+            .listen_tcp(22, "sshd", 300)
+            .connect_tcp("10.0.0.3:5432", "10.0.0.2:40000", "postgres", 301)
+            .build(),
+        HostBuilder::new("app")
+            .ip("10.0.0.2")
+            .connect_tcp("10.0.0.2:40001", "10.0.0.3:22", "ssh", 200)
+            .connect_tcp("10.0.0.2:40000", "10.0.0.3:5432", "java", 201)
+            .connect_tcp("10.0.0.2:40002", "192.0.2.1:443", "java", 201)
+            .build(),
+    ]
+}
+
+/// Number of loopback connections of [`loopback_heavy`]
+pub const LOOPBACK_HEAVY_CONNECTIONS: usize = 12;
+
+/// Single host `app01` (10.0.0.10) whose web workers reach its local cache and database over
+/// the loopback addresses, with [`LOOPBACK_HEAVY_CONNECTIONS`] connections in all
+pub fn loopback_heavy() -> Vec<Host> {
+    let mut builder = HostBuilder::new("app01")
+        .ip("10.0.0.10")
+        .listen_tcp(443, "nginx", 100)
+        .listen("127.0.0.1:6379", SocketType::TCP, "redis-server", 101, None)
+        .listen("[::1]:5432", SocketType::TCP, "postgres", 102, Some(true))
+        .listen("127.0.0.1:8000", SocketType::TCP, "gunicorn", 103, None);
+    for worker in 0..4 {
+        let pid = 200 + worker;
+        let port = 50000 + 10 * worker as u16;
+        builder = builder
+            .connect_tcp(
+                &format!("127.0.0.1:{port}"),
+                "127.0.0.1:6379",
+                "gunicorn",
+                pid,
+            )
+            .connect_tcp(
+                &format!("[::1]:{}", port + 1),
+                "[::1]:5432",
+                "gunicorn",
+                pid,
+            )
+            .connect_tcp(
+                &format!("127.0.0.1:{}", port + 2),
+                "127.0.0.1:8000",
+                "nginx",
+                100,
+            );
+    }
+    vec![builder.build()]
+}
+
+/// Services of the hosts of [`random_mesh`], with the name of their clients
+const MESH_SERVICES: [(&str, u16, &str); 4] = [
+    ("sshd", 22, "ssh"),
+    ("nginx", 443, "curl"),
+    ("postgres", 5432, "psql"),
+    ("redis-server", 6379, "redis-cli"),
+];
+
+/// `host_count` hosts named `host-001` and so on, each one running 1 or 2 services and
+/// connecting to the services of 1 to 3 other hosts. The same `seed` always gives the same hosts.
+pub fn random_mesh(host_count: usize, seed: u64) -> Vec<Host> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let ip =
+        |index: usize| Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 1, 0, 0)) + index as u32 + 1);
+    let services: Vec<Vec<&(&str, u16, &str)>> = (0..host_count)
+        .map(|_| {
+            let count = rng.gen_range(1..=2);
+            MESH_SERVICES.choose_multiple(&mut rng, count).collect()
+        })
+        .collect();
+
+    (0..host_count)
+        .map(|index| {
+            let mut builder =
+                HostBuilder::new(&format!("host-{:03}", index + 1)).ip(&ip(index).to_string());
+            for (pid, (name, port, _)) in services[index].iter().enumerate() {
+                builder = builder.listen_tcp(*port, name, 100 + pid as u32);
+            }
+            let peers: Vec<usize> = (0..host_count).filter(|peer| *peer != index).collect();
+            let count = rng.gen_range(1..=3).min(peers.len());
+            for (number, peer) in peers.choose_multiple(&mut rng, count).enumerate() {
+                let (_, port, client) = services[*peer]
+                    .choose(&mut rng)
+                    .expect("the hosts run at least a service");
+                builder = builder.connect_tcp(
+                    &format!("{}:{}", ip(index), 40000 + number),
+                    &format!("{}:{port}", ip(*peer)),
+                    client,
+                    1000 + number as u32,
+                );
+            }
+            builder.build()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections_model::build_connections_list;
+
+    /// The builder must give the same host as the one built by hand
+    #[test]
+    fn test_host_builder() {
+        let mut expected = Host::new("web01");
+        expected.add_ip("10.0.0.1".parse().unwrap());
+        expected.add_listening_socket(ListeningSocket::new(
+            "0.0.0.0:443".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("nginx", 102, "web01".to_string()),
+            "web01".to_string(),
+            None,
+        ));
+        expected.add_established_connection(Connection::new(
+            "10.0.0.1:50000".parse().unwrap(),
+            "10.0.0.2:5432".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("app", 103, "web01".to_string()),
+        ));
+
+        let host = HostBuilder::new("web01")
+            .ip("10.0.0.1")
+            .listen_tcp(443, "nginx", 102)
+            .connect_tcp("10.0.0.1:50000", "10.0.0.2:5432", "app", 103)
+            .build();
+        assert_eq!(host, expected);
+    }
+
+    /// Every connection of the scenarios must be matched with its listening socket
+    #[test]
+    fn test_scenarios() {
+        for (hosts, expected) in [
+            (client_server(), 1),
+            (udp_pair(), 1),
+            (v6_mapped(), 1),
+            (missing_listener(), 1),
+            (loopback_heavy(), LOOPBACK_HEAVY_CONNECTIONS),
+        ] {
+            let connections = build_connections_list(&hosts, false);
+            assert_eq!(connections.len(), expected, "{connections:#?}");
+        }
+        assert!(build_connections_list(&loopback_heavy(), true).is_empty());
+    }
+
+    /// The mesh must only depend on its seed, and its connections must all be matched
+    #[test]
+    fn test_random_mesh() {
+        let hosts = random_mesh(100, 42);
+        assert_eq!(hosts.len(), 100);
+        assert_eq!(hosts, random_mesh(100, 42));
+        assert_ne!(hosts, random_mesh(100, 43));
+
+        let established: usize = hosts.iter().map(|host| host.connections().len()).sum();
+        assert!(established >= 100);
+        assert_eq!(build_connections_list(&hosts, false).len(), established);
+    }
+}
@@ -0,0 +1,209 @@
+//! This module cuts a recording into time slices, to follow the connections over time.
+
+use crate::connections_model::ConnectionRecord;
+use chrono::{DateTime, Utc};
+use std::time::{Duration, SystemTime};
+
+/// The connections seen during a time window of a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSlice {
+    start: SystemTime,
+    end: SystemTime,
+    records: Vec<ConnectionRecord>,
+}
+
+impl TimeSlice {
+    /// Get the start of the slice.
+    pub fn start(&self) -> SystemTime {
+        self.start
+    }
+
+    /// Get the end of the slice, excluded from it.
+    pub fn end(&self) -> SystemTime {
+        self.end
+    }
+
+    /// Get a reference to the connections seen during the slice.
+    pub fn records(&self) -> &[ConnectionRecord] {
+        &self.records
+    }
+
+    /// Name of the file of the slice, such as `20240105T101500Z-20240105T101600Z.png`
+    pub fn file_name(&self, extension: &str) -> String {
+        format!(
+            "{}-{}.{extension}",
+            format_time(self.start),
+            format_time(self.end)
+        )
+    }
+}
+
+/// Compact UTC time, usable in file names
+pub(crate) fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// Time span during which a record was seen, if it has any time
+fn seen_span(record: &ConnectionRecord) -> Option<(SystemTime, SystemTime)> {
+    match (record.first_seen(), record.last_seen()) {
+        (Some(first), Some(last)) => Some((first.min(last), first.max(last))),
+        (Some(time), None) | (None, Some(time)) => Some((time, time)),
+        (None, None) => None,
+    }
+}
+
+/// Cut the recording into consecutive slices of `window`, from the first time a connection was
+/// seen to the last one. A connection seen over several slices appears in each of them, and the
+/// slices without connections are kept so that the timeline has no gaps.
+///
+/// The records without times are left out. A zero `window` gives a single slice.
+pub fn slice_connections(records: &[ConnectionRecord], window: Duration) -> Vec<TimeSlice> {
+    let spans: Vec<(&ConnectionRecord, (SystemTime, SystemTime))> = records
+        .iter()
+        .filter_map(|record| seen_span(record).map(|span| (record, span)))
+        .collect();
+    let (Some(start), Some(end)) = (
+        spans.iter().map(|(_, (first, _))| *first).min(),
+        spans.iter().map(|(_, (_, last))| *last).max(),
+    ) else {
+        return Vec::new();
+    };
+    let window = match window.is_zero() {
+        true => end.duration_since(start).unwrap_or_default() + Duration::from_secs(1),
+        false => window,
+    };
+
+    let mut slices = Vec::new();
+    let mut slice_start = start;
+    while slice_start <= end {
+        let slice_end = slice_start + window;
+        let records = spans
+            .iter()
+            .filter(|(_, (first, last))| *first < slice_end && *last >= slice_start)
+            .map(|(record, _)| (*record).clone())
+            .collect();
+        slices.push(TimeSlice {
+            start: slice_start,
+            end: slice_end,
+            records,
+        });
+        slice_start = slice_end;
+    }
+    slices
+}
+
+/// Merge the records of successive captures, each made at the given time, into one record per
+/// connection, with the number of captures it was seen in and its first and last times
+pub fn merge_captures(captures: &[(SystemTime, Vec<ConnectionRecord>)]) -> Vec<ConnectionRecord> {
+    let mut merged: Vec<(ConnectionRecord, usize, SystemTime, SystemTime)> = Vec::new();
+    for (time, records) in captures {
+        for record in records {
+            match merged
+                .iter_mut()
+                .find(|(r, ..)| r.is_same_connection(record))
+            {
+                Some((_, count, first, last)) => {
+                    *count += 1;
+                    *first = (*first).min(*time);
+                    *last = (*last).max(*time);
+                }
+                None => merged.push((record.clone(), 1, *time, *time)),
+            }
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(record, count, first, last)| {
+            record
+                .with_count(count)
+                .with_first_seen(first)
+                .with_last_seen(last)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections_model::{
+        build_connection_records, tests::make_fake_connections, ConnectionsOptions,
+    };
+
+    /// Time `secs` seconds after a fixed start
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    /// Records of the fake hosts, each seen during the given span
+    fn make_records(spans: &[(u64, u64)]) -> Vec<ConnectionRecord> {
+        let records = build_connection_records(&make_fake_connections(), &Default::default());
+        assert!(records.len() >= spans.len());
+        records
+            .into_iter()
+            .zip(spans)
+            .map(|(record, (first, last))| {
+                record.with_first_seen(at(*first)).with_last_seen(at(*last))
+            })
+            .collect()
+    }
+
+    /// The slices must cover the recording, each with the connections seen during it
+    #[test]
+    fn test_slice_connections() {
+        let records = make_records(&[(0, 10), (70, 70), (30, 150)]);
+        let slices = slice_connections(&records, Duration::from_secs(60));
+        assert_eq!(slices.len(), 3);
+        assert_eq!(slices[0].start(), at(0));
+        assert_eq!(slices[2].end(), at(180));
+
+        let members = |slice: &TimeSlice| {
+            records
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| slice.records().contains(r))
+                .map(|(i, _)| i)
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(members(&slices[0]), [0, 2]);
+        assert_eq!(members(&slices[1]), [1, 2]);
+        assert_eq!(members(&slices[2]), [2]);
+        assert_eq!(
+            slices[1].file_name("png"),
+            "20231114T221420Z-20231114T221520Z.png"
+        );
+    }
+
+    /// The records without times are left out, and a zero window gives a single slice
+    #[test]
+    fn test_slice_connections_edge_cases() {
+        let records = build_connection_records(&make_fake_connections(), &Default::default());
+        assert!(slice_connections(&records, Duration::from_secs(60)).is_empty());
+
+        let records = make_records(&[(0, 10), (500, 500)]);
+        let slices = slice_connections(&records, Duration::ZERO);
+        assert_eq!(slices.len(), 1);
+        assert_eq!(slices[0].records().len(), 2);
+    }
+
+    /// The captures must be merged into a record per connection, spanning its captures
+    #[test]
+    fn test_merge_captures() {
+        let hosts = make_fake_connections();
+        let options = ConnectionsOptions::default();
+        let records = build_connection_records(&hosts, &options);
+        let captures = vec![
+            (at(0), records[..1].to_vec()),
+            (at(60), records.clone()),
+            (at(120), records[..1].to_vec()),
+        ];
+        let merged = merge_captures(&captures);
+        assert_eq!(merged.len(), records.len());
+        assert_eq!(merged[0].count(), Some(3));
+        assert_eq!(merged[0].first_seen(), Some(at(0)));
+        assert_eq!(merged[0].last_seen(), Some(at(120)));
+        assert_eq!(merged[1].count(), Some(1));
+        assert_eq!(merged[1].first_seen(), Some(at(60)));
+    }
+}
@@ -0,0 +1,96 @@
+//! Compare the native sockets collection with the parsing of the ss output on this machine.
+
+#![cfg(all(target_os = "linux", feature = "native"))]
+
+use sockets_map::{
+    host::{Host, SocketType},
+    parsers::{
+        linux::{LinuxHostRawData, NetworkOutput},
+        native,
+    },
+};
+use std::{
+    collections::BTreeSet,
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::Command,
+};
+
+/// Listening sockets of a host, without their process since shared sockets may be attributed to
+/// any of their processes
+fn listening_sockets(host: &Host) -> BTreeSet<(SocketAddr, SocketType)> {
+    host.listening_sockets()
+        .iter()
+        .map(|s| (*s.socket(), s.socket_type().clone()))
+        .collect()
+}
+
+/// Connections of a host, without their process
+fn connections(host: &Host) -> BTreeSet<(SocketAddr, SocketAddr, SocketType)> {
+    host.connections()
+        .iter()
+        .map(|c| (*c.local_socket(), *c.peer_socket(), c.socket_type().clone()))
+        .collect()
+}
+
+/// Both collections must find the sockets opened by this test, and the listening sockets found by
+/// ss must be found natively too. Sockets may be opened or closed between the collections, so the
+/// native collection is made before and after running ss.
+#[test]
+fn test_native_matches_ss() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    let native_before = native::collect_host("machine1", &[]).unwrap();
+    let ss_output = match Command::new("ss").arg("-apn").output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(e) => {
+            eprintln!("unable to run ss, skipping: {e}");
+            return;
+        }
+    };
+    let native_after = native::collect_host("machine1", &[]).unwrap();
+    let ss_host: anyhow::Result<Host> = LinuxHostRawData::new(
+        "machine1".to_string(),
+        NetworkOutput::Ss(ss_output),
+        vec![],
+        None,
+    )
+    .into();
+    let ss_host = ss_host.unwrap();
+
+    // Sockets opened by this test
+    let own_listener = (listener.local_addr().unwrap(), SocketType::TCP);
+    let own_connections = [
+        (
+            client.local_addr().unwrap(),
+            client.peer_addr().unwrap(),
+            SocketType::TCP,
+        ),
+        (
+            server.local_addr().unwrap(),
+            server.peer_addr().unwrap(),
+            SocketType::TCP,
+        ),
+    ];
+    for host in [&native_before, &native_after, &ss_host] {
+        assert!(listening_sockets(host).contains(&own_listener));
+        for connection in &own_connections {
+            assert!(connections(host).contains(connection));
+        }
+    }
+
+    // Listening sockets found by ss
+    let native_listening_sockets: BTreeSet<_> = listening_sockets(&native_before)
+        .union(&listening_sockets(&native_after))
+        .cloned()
+        .collect();
+    let missing: Vec<_> = listening_sockets(&ss_host)
+        .difference(&native_listening_sockets)
+        .cloned()
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "listening sockets found by ss but not natively: {missing:?}"
+    );
+}
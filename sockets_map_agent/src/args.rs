@@ -1,11 +1,22 @@
-use clap::Parser;
-use std::net::SocketAddr;
+use clap::{Parser, Subcommand};
+use sockets_map::collect::{CaptureFormat, IpFilter};
+use std::{
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::PathBuf,
+    time::Duration,
+};
 
 #[derive(Parser)]
-#[clap(version = clap::crate_version!(), author = "Aurelien Dubois <aurelien.dubois@amossys.fr>", about = "A tool to connect to a Socket Map server in order to map the network interactions between processes in a group of machines, from information that can be gathered using native tools on the targets.")]
+#[clap(version = clap::crate_version!(), author = "Aurelien Dubois <aurelien.dubois@amossys.fr>", about = "A tool to connect to a Socket Map server in order to map the network interactions between processes in a group of machines, from information that can be gathered using native tools on the targets.", args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    #[clap(help = "address:port of the sockets map server")]
-    pub address: SocketAddr,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(
+        help = "address:port of the sockets map server",
+        required_unless_present = "offline"
+    )]
+    pub address: Option<SocketAddr>,
     #[clap(help = "name to display in the graph for this host")]
     pub pretty_name: Option<String>,
     #[clap(
@@ -15,4 +26,219 @@ pub struct Args {
         action
     )]
     pub no_root: bool,
+    #[clap(
+        help = "maximum delay, in seconds, between two attempts to reconnect to the server",
+        long = "reconnect-max-delay",
+        default_value_t = 60.0
+    )]
+    pub reconnect_max_delay: f64,
+    #[clap(
+        help = "also push an update to the server at this interval (e.g. \"30s\" or \"5m\"), except while recording",
+        long = "push-interval",
+        value_parser = parse_interval,
+        conflicts_with = "offline"
+    )]
+    pub push_interval: Option<Duration>,
+    #[clap(
+        help = "do not send the sockets of the processes whose name starts with this (can be repeated)",
+        long = "exclude-process",
+        conflicts_with = "offline"
+    )]
+    pub exclude_process: Vec<String>,
+    #[clap(
+        help = "do not send the sockets using this port or port range, such as \"10050-10051\" (can be repeated)",
+        long = "exclude-port",
+        value_parser = parse_port_range,
+        conflicts_with = "offline"
+    )]
+    pub exclude_port: Vec<RangeInclusive<u16>>,
+    #[clap(
+        help = "do not send the IP addresses of the interfaces whose name matches this pattern, with * and ? wildcards, such as \"docker*\" (can be repeated)",
+        long = "exclude-interface"
+    )]
+    pub exclude_interface: Vec<String>,
+    #[clap(
+        help = "also send this IP address as one of the host, such as the public address of a NAT gateway (can be repeated)",
+        long = "extra-ip"
+    )]
+    pub extra_ip: Vec<IpAddr>,
+    #[clap(
+        help = "also send the link-local addresses and the ones of the default Docker bridge (172.17.0.0/16), which are left out by default",
+        long = "keep-virtual-ips"
+    )]
+    pub keep_virtual_ips: bool,
+    #[clap(
+        help = "serve a local control endpoint (Unix socket, or named pipe on Windows) at this path, for the status and stop subcommands",
+        long = "control-socket",
+        conflicts_with = "offline"
+    )]
+    pub control_socket: Option<PathBuf>,
+    #[clap(
+        help = "write the captures into this directory, as a <host>.smap.json bundle, instead of connecting to a server",
+        long = "offline"
+    )]
+    pub offline: Option<PathBuf>,
+    #[clap(
+        help = "interval, in seconds, between two captures in offline mode",
+        long = "offline-interval",
+        default_value_t = 1.0,
+        requires = "offline"
+    )]
+    pub offline_interval: f64,
+    #[clap(
+        help = "number of captures to make in offline mode, appended to the same files",
+        long = "count",
+        default_value_t = 1,
+        requires = "offline"
+    )]
+    pub count: u32,
+    #[clap(
+        help = "in offline mode, write one file per command output, such as <host>.ss and <host>.linux_ip, instead of a bundle",
+        long = "legacy-files",
+        requires = "offline"
+    )]
+    pub legacy_files: bool,
+    #[clap(
+        help = "run as a service, such as a systemd unit: log without colors nor timestamps, and give up after --register-attempts failed attempts to register",
+        long = "service"
+    )]
+    pub service: bool,
+    #[clap(
+        help = "also write the logs to this file, rotated once it reaches --log-file-max-size",
+        long = "log-file"
+    )]
+    pub log_file: Option<PathBuf>,
+    #[clap(
+        help = "size, in bytes, from which the log file is rotated, the last 3 rotated files being kept",
+        long = "log-file-max-size",
+        default_value_t = crate::logging::DEFAULT_LOG_FILE_MAX_SIZE,
+        requires = "log_file"
+    )]
+    pub log_file_max_size: u64,
+    #[clap(
+        help = "exit with an error after this number of attempts in a row to connect and register to the server, so that the service manager restarts the agent (10 by default with --service, unlimited otherwise)",
+        long = "register-attempts",
+        conflicts_with = "offline"
+    )]
+    pub register_attempts: Option<u32>,
+    #[clap(
+        help = "while recording, send the updates recorded so far in full once they take more than this number of megabytes, instead of keeping them to compute the deltas",
+        long = "max-buffer-mb",
+        conflicts_with = "offline"
+    )]
+    pub max_buffer_mb: Option<f64>,
+    #[cfg(target_os = "linux")]
+    #[clap(
+        help = "print a systemd unit running the agent as a service with the other options, instead of running it",
+        long = "print-systemd-unit"
+    )]
+    pub print_systemd_unit: bool,
+    // Set in the arguments of the Windows service, which is started by the service manager
+    #[cfg(windows)]
+    #[clap(long = "windows-service", hide = true)]
+    pub windows_service: bool,
+}
+
+impl Args {
+    /// Size of the recorded updates from which they are sent in full, in bytes
+    pub fn max_buffer_bytes(&self) -> Option<u64> {
+        self.max_buffer_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64)
+    }
+
+    /// Selection of the local IP addresses sent to the server
+    pub fn ip_filter(&self) -> IpFilter {
+        IpFilter {
+            exclude_interfaces: self.exclude_interface.clone(),
+            extra_ips: self.extra_ip.clone(),
+            keep_virtual_ips: self.keep_virtual_ips,
+        }
+    }
+
+    /// Files written in offline mode
+    pub fn capture_format(&self) -> CaptureFormat {
+        match self.legacy_files {
+            true => CaptureFormat::LegacyFiles,
+            false => CaptureFormat::Bundle,
+        }
+    }
+
+    /// Number of failed attempts to register in a row after which the agent gives up, if any
+    pub fn register_attempts(&self) -> Option<u32> {
+        self.register_attempts
+            .or(self.service.then_some(DEFAULT_SERVICE_REGISTER_ATTEMPTS))
+    }
+}
+
+/// Failed attempts to register in a row after which an agent running as a service gives up
+const DEFAULT_SERVICE_REGISTER_ATTEMPTS: u32 = 10;
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[clap(about = "Print the status of a running agent, as JSON")]
+    Status {
+        #[clap(
+            help = "control endpoint of the running agent",
+            long = "control-socket"
+        )]
+        control_socket: PathBuf,
+    },
+    #[clap(about = "Ask a running agent to disconnect from the server and exit")]
+    Stop {
+        #[clap(
+            help = "control endpoint of the running agent",
+            long = "control-socket"
+        )]
+        control_socket: PathBuf,
+    },
+    #[cfg(windows)]
+    #[clap(about = "Install the agent as a Windows service started with the system")]
+    InstallService {
+        #[clap(help = "address:port of the sockets map server", long = "server")]
+        server: SocketAddr,
+        #[clap(
+            help = "name to display in the graph for this host",
+            long = "pretty-name"
+        )]
+        pretty_name: Option<String>,
+        #[clap(
+            help = "log file of the service (%PROGRAMDATA%\\sockets_map_agent\\agent.log by default)",
+            long = "log-file"
+        )]
+        log_file: Option<PathBuf>,
+    },
+    #[cfg(windows)]
+    #[clap(about = "Stop and remove the Windows service of the agent")]
+    UninstallService,
+    #[cfg(windows)]
+    #[clap(about = "Start the Windows service of the agent")]
+    StartService,
+    #[cfg(windows)]
+    #[clap(about = "Stop the Windows service of the agent")]
+    StopService,
+}
+
+/// Parse a non-zero duration such as "30s" or "5m"
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(s) {
+        Ok(d) if d.is_zero() => Err("the interval must not be zero".to_string()),
+        Ok(d) => Ok(d),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse a port such as "22", or a port range such as "8000-8100"
+fn parse_port_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let start: u16 = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid port: {e}"))?;
+    let end: u16 = end
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid port: {e}"))?;
+    if start > end {
+        return Err(format!("invalid port range: {start} is greater than {end}"));
+    }
+    Ok(start..=end)
 }
@@ -0,0 +1,256 @@
+//! Local control endpoint of a running agent, on a Unix domain socket (Linux) or a named pipe
+//! (Windows).
+//!
+//! Each connection sends one command line and receives one JSON line in return:
+//! - `status`: the agent [`Status`]
+//! - `update-now`: send an update to the server right away
+//! - `shutdown`: disconnect from the server and exit
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sockets_map::server::clock;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::mpsc,
+};
+
+/// Status of the agent, as served on the control endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub pid: u32,
+    pub server_addr: Option<SocketAddr>,
+    pub connected: bool,
+    /// Interval of the running recording, if any
+    pub recording_interval: Option<f64>,
+    pub updates_sent: u64,
+    /// Time of the last update, in the time of the server once the clock offset is known
+    pub last_update_time: Option<DateTime<Utc>>,
+    /// Offset of our clock to the one of the server, measured at the start of the recording, in
+    /// milliseconds
+    pub clock_offset_ms: Option<i64>,
+}
+
+impl Status {
+    pub fn new(server_addr: Option<SocketAddr>) -> Self {
+        Self {
+            pid: std::process::id(),
+            server_addr,
+            ..Default::default()
+        }
+    }
+
+    /// Record that an update was sent to the server
+    pub fn update_sent(&mut self) {
+        self.updates_sent += 1;
+        self.last_update_time = Some(clock::to_server_time(
+            Utc::now(),
+            self.clock_offset_ms.unwrap_or_default(),
+        ));
+    }
+}
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+/// Commands forwarded to the agent main loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    UpdateNow,
+    Shutdown,
+}
+
+/// Response to a command other than `status`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Removes the socket file of the control endpoint when dropped, as the agent exits
+#[must_use = "the control socket is removed as soon as the guard is dropped"]
+pub struct ControlGuard {
+    /// Socket file, none for the named pipes which go away with the agent
+    path: Option<PathBuf>,
+}
+
+impl Drop for ControlGuard {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if let Err(e) = std::fs::remove_file(path) {
+                log::warn!("unable to remove control socket {path:?}: {e}");
+            }
+        }
+    }
+}
+
+/// Start serving the control endpoint at `path`, until the returned guard is dropped
+pub async fn serve(
+    path: &Path,
+    status: SharedStatus,
+    command_tx: mpsc::UnboundedSender<Command>,
+) -> anyhow::Result<ControlGuard> {
+    #[cfg(unix)]
+    let guard = {
+        // A socket file left by a previous agent can be replaced, but not one that is still in use
+        if path.exists() {
+            if tokio::net::UnixStream::connect(path).await.is_ok() {
+                bail!("control socket {path:?} is used by another agent");
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)?;
+        let guard = ControlGuard {
+            path: Some(path.to_owned()),
+        };
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(handle_connection(
+                            stream,
+                            status.clone(),
+                            command_tx.clone(),
+                        ));
+                    }
+                    Err(e) => log::error!("control socket error: {e}"),
+                }
+            }
+        });
+        guard
+    };
+
+    #[cfg(windows)]
+    let guard = {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let path = path.to_owned();
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&path)?;
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.connect().await {
+                    log::error!("control pipe error: {e}");
+                    continue;
+                }
+                // Create the next instance before handling this one, so that clients can always connect
+                let connected = server;
+                server = match ServerOptions::new().create(&path) {
+                    Ok(server) => server,
+                    Err(e) => {
+                        log::error!("unable to create control pipe: {e}");
+                        return;
+                    }
+                };
+                tokio::spawn(handle_connection(
+                    connected,
+                    status.clone(),
+                    command_tx.clone(),
+                ));
+            }
+        });
+        ControlGuard { path: None }
+    };
+
+    log::info!("control endpoint listening on {path:?}");
+    Ok(guard)
+}
+
+/// Read one command and write its response
+async fn handle_connection<S>(
+    stream: S,
+    status: SharedStatus,
+    command_tx: mpsc::UnboundedSender<Command>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    if let Err(e) = stream.read_line(&mut line).await {
+        log::error!("unable to read control command: {e}");
+        return;
+    }
+
+    let status = status.lock().unwrap().clone();
+    let command = match line.trim() {
+        "status" => None,
+        "update-now" => Some(Command::UpdateNow),
+        "shutdown" => Some(Command::Shutdown),
+        other => {
+            log::warn!("unknown control command: {other:?}");
+            let response = Response {
+                ok: false,
+                error: Some(format!("unknown command {other:?}")),
+            };
+            let _ = write_json(&mut stream, &response).await;
+            return;
+        }
+    };
+    let res = match command {
+        None => write_json(&mut stream, &status).await,
+        Some(Command::UpdateNow) if !status.connected => {
+            let response = Response {
+                ok: false,
+                error: Some("not connected to the server".to_string()),
+            };
+            write_json(&mut stream, &response).await
+        }
+        Some(command) => {
+            log::info!("control command: {command:?}");
+            // Respond before forwarding the command, since the agent may exit right away
+            let ok = !command_tx.is_closed();
+            let response = Response {
+                ok,
+                error: (!ok).then(|| "the agent is exiting".to_string()),
+            };
+            let res = write_json(&mut stream, &response).await;
+            let _ = command_tx.send(command);
+            res
+        }
+    };
+    if let Err(e) = res {
+        log::error!("unable to write control response: {e}");
+    }
+}
+
+async fn write_json<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    value: &T,
+) -> anyhow::Result<()> {
+    let mut json = serde_json::to_string(value)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Send a command to the agent listening at `path`, and return its JSON response
+pub async fn send_command(path: &Path, command: &str) -> anyhow::Result<String> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(path).await;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(path);
+    let stream = stream.map_err(|e| anyhow!("unable to connect to the agent at {path:?}: {e}"))?;
+
+    let mut stream = BufReader::new(stream);
+    stream
+        .get_mut()
+        .write_all(format!("{command}\n").as_bytes())
+        .await?;
+    let mut response = String::new();
+    stream.read_line(&mut response).await?;
+    if response.is_empty() {
+        bail!("no response from the agent");
+    }
+    Ok(response.trim_end().to_string())
+}
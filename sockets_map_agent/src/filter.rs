@@ -0,0 +1,25 @@
+//! Filters applied at the source, so that the excluded sockets are never sent to the server
+
+use sockets_map::host::Host;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    /// Prefixes of the names of the processes to exclude
+    pub processes: Vec<String>,
+    /// Ports to exclude, on either side of a connection
+    pub ports: Vec<RangeInclusive<u16>>,
+}
+
+impl Filters {
+    /// Remove the excluded processes and ports from `host`
+    pub fn apply(&self, host: &mut Host) {
+        if !self.processes.is_empty() {
+            let patterns: Vec<&str> = self.processes.iter().map(String::as_str).collect();
+            host.exclude_processes(&patterns);
+        }
+        if !self.ports.is_empty() {
+            host.exclude_ports(&self.ports);
+        }
+    }
+}
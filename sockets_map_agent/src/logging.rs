@@ -0,0 +1,159 @@
+//! Logger of the agent: colored on a terminal, or plain for the service managers such as systemd
+//! which add their own timestamps, and optionally written to a log file rotated by size
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use simplelog::{
+    ColorChoice, CombinedLogger, ConfigBuilder, LevelFilter, SharedLogger, TermLogger,
+    TerminalMode, WriteLogger,
+};
+
+/// Size from which the log file is rotated, in bytes
+pub const DEFAULT_LOG_FILE_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rotated log files kept, besides the current one
+const ROTATED_LOG_FILES: usize = 3;
+
+/// Initialize the logger, without colors nor timestamps on the terminal in service mode
+pub fn init(service: bool, log_file: Option<&Path>, log_file_max_size: u64) -> anyhow::Result<()> {
+    let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
+    let terminal_config = match service {
+        true => ConfigBuilder::new()
+            .set_time_level(LevelFilter::Off)
+            .build(),
+        false => simplelog::Config::default(),
+    };
+    loggers.push(TermLogger::new(
+        LevelFilter::Info,
+        terminal_config,
+        TerminalMode::Mixed,
+        match service {
+            true => ColorChoice::Never,
+            false => ColorChoice::Auto,
+        },
+    ));
+    if let Some(log_file) = log_file {
+        let file = RotatingFile::open(log_file, log_file_max_size, ROTATED_LOG_FILES)
+            .with_context(|| format!("unable to open log file {log_file:?}"))?;
+        loggers.push(WriteLogger::new(
+            LevelFilter::Info,
+            simplelog::Config::default(),
+            file,
+        ));
+    }
+    CombinedLogger::init(loggers).context("unable to init logger")
+}
+
+/// Log file which is renamed to `<path>.1` once it reaches its maximum size, the previous ones
+/// being shifted up to `<path>.<kept>`
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    kept: usize,
+    file: File,
+    size: u64,
+    /// Whether the last write ended a line, since the files are only rotated between lines
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, kept: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            kept,
+            file,
+            size,
+            at_line_start: true,
+        })
+    }
+
+    /// Path of the rotated file with this index
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.kept > 0 {
+            for index in (1..self.kept).rev() {
+                let rotated_path = self.rotated_path(index);
+                if rotated_path.exists() {
+                    std::fs::rename(rotated_path, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The file must be rotated between lines once full, keeping the last rotated files only
+    #[test]
+    fn test_rotating_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("agent.log");
+        let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+        for line in [
+            "first line\n",
+            "second ",
+            "line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "fourth line\n");
+        assert_eq!(read(directory.path().join("agent.log.1")), "third line\n");
+        assert_eq!(
+            read(directory.path().join("agent.log.2")),
+            "first line\nsecond line\n"
+        );
+        assert!(!directory.path().join("agent.log.3").exists());
+
+        // The size of an existing file counts
+        let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+        file.write_all(b"fifth line\n").unwrap();
+        assert_eq!(read(path), "fifth line\n");
+        assert_eq!(read(directory.path().join("agent.log.1")), "fourth line\n");
+    }
+}
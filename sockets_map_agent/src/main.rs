@@ -1,65 +1,285 @@
 use anyhow::{bail, Context};
 use clap::Parser;
-use local_ip_address::list_afinet_netifas;
+use rand::{rngs::StdRng, SeedableRng};
 use std::{
+    ffi::OsString,
+    fmt::Display,
+    future::IntoFuture,
     net::{IpAddr, SocketAddr},
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::Duration,
 };
-use tokio::sync::RwLock;
+use tokio::{select, sync::mpsc};
 
 mod args;
-
-use sockets_map::server::{
-    client::Update,
-    message::{self, Message},
+mod control;
+mod filter;
+mod logging;
+mod push;
+mod reconnect;
+mod record;
+mod recorder;
+mod service;
+
+use sockets_map::{
+    collect,
+    server::{
+        client::{self, Update},
+        clock,
+        message::{self, Capability, CapabilitySet, Message},
+    },
 };
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    // Initialize logger
-    simplelog::TermLogger::init(
-        simplelog::LevelFilter::Info,
-        simplelog::Config::default(),
-        simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )
-    .expect("unable to init termlogger");
-
     // Arguments
     let args = args::Args::parse();
 
-    // Check admin
-    if !args.no_root && !collect::ensure_privileged() {
-        #[cfg(target_os = "linux")]
-        bail!("Must run as root");
-        #[cfg(target_os = "windows")]
-        bail!("Must run as administrator");
+    // Initialize logger
+    logging::init(
+        args.service,
+        args.log_file.as_deref(),
+        args.log_file_max_size,
+    )?;
+
+    // Talk to a running agent through its control endpoint, or manage its service
+    if let Some(command) = &args.command {
+        return run_command(command).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    if args.print_systemd_unit {
+        let agent_args: Vec<OsString> = std::env::args_os().skip(1).collect();
+        print!(
+            "{}",
+            service::systemd_unit(&std::env::current_exe()?, &agent_args)
+        );
+        return Ok(());
     }
 
-    // Set the locale
-    std::env::set_var("LC_ALL", "C");
+    // The service manager runs the agent on a thread of its own
+    #[cfg(windows)]
+    if args.windows_service {
+        return tokio::task::spawn_blocking(service::run_as_service).await?;
+    }
+
+    // Signals stop the agent just like the stop command, while they still stop the offline
+    // captures right away
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    if args.offline.is_none() {
+        tokio::spawn(forward_termination_signals(command_tx.clone()));
+    }
+
+    run_agent(&args, command_tx, command_rx).await
+}
+
+/// Run the agent until it is stopped by a command of `command_rx`, or gives up on the server
+async fn run_agent(
+    args: &args::Args,
+    command_tx: mpsc::UnboundedSender<control::Command>,
+    mut command_rx: mpsc::UnboundedReceiver<control::Command>,
+) -> anyhow::Result<()> {
+    // Check admin
+    collect::check_privileges(args.no_root)?;
 
     // Get local IP addresses
-    let local_ips: Vec<IpAddr> = list_afinet_netifas()
-        .with_context(|| "unable to retrieve list of local IP addresses: {}")?
-        .iter()
-        .map(|(_ifname, addr)| *addr)
-        .collect();
-
-    // Start client loop
-    if let Err(e) = register_and_start_client(args.address, args.pretty_name, local_ips).await {
-        log::error!("{e}");
+    let local_ips = collect::local_ip_addresses(&args.ip_filter())?;
+
+    // Write the captures locally instead of connecting to a server
+    if let Some(output_dir) = &args.offline {
+        return collect::write_captures(
+            output_dir,
+            args.offline_interval,
+            args.count,
+            &collect::host_name(args.pretty_name.as_deref())?,
+            &local_ips,
+            args.capture_format(),
+        )
+        .await;
     }
+    let Some(server_addr) = args.address else {
+        bail!("missing server address");
+    };
+
+    // Local control endpoint
+    let status: control::SharedStatus =
+        Arc::new(Mutex::new(control::Status::new(Some(server_addr))));
+    let _control_guard = match &args.control_socket {
+        Some(control_socket) => {
+            Some(control::serve(control_socket, status.clone(), command_tx).await?)
+        }
+        None => None,
+    };
+
+    // Start client loop, and reconnect whenever the connection to the server is lost
+    let mut backoff = reconnect::Backoff::new(
+        reconnect::INITIAL_DELAY,
+        Duration::from_secs_f64(args.reconnect_max_delay),
+    );
+    let filters = filter::Filters {
+        processes: args.exclude_process.clone(),
+        ports: args.exclude_port.clone(),
+    };
+    let mut recorder = recorder::Recorder::default();
+    let mut failed_attempts = 0;
+    loop {
+        let disconnection = register_and_start_client(
+            server_addr,
+            args.pretty_name.clone(),
+            local_ips.clone(),
+            args.push_interval,
+            &filters,
+            record::RecordingLimits {
+                max_buffer_bytes: args.max_buffer_bytes(),
+                stats_interval: record::STATS_INTERVAL,
+            },
+            &mut recorder,
+            &mut backoff,
+            &status,
+            &mut command_rx,
+        )
+        .await;
+        match disconnection {
+            Ok(Disconnection::Shutdown) => break,
+            Ok(Disconnection::Lost) => log::warn!("connection to the server lost"),
+            Err(e) => log::error!("{e}"),
+        }
 
+        // The recording will be resumed once reconnected
+        recorder.suspend();
+        let registered = {
+            let mut status = status.lock().unwrap();
+            status.recording_interval = None;
+            status.clock_offset_ms = None;
+            std::mem::take(&mut status.connected)
+        };
+
+        // Let the service manager restart the agent once the server seems gone for good
+        failed_attempts = match registered {
+            true => 0,
+            false => failed_attempts + 1,
+        };
+        if let Some(register_attempts) = args.register_attempts() {
+            if failed_attempts >= register_attempts {
+                bail!("unable to register to the server after {failed_attempts} attempts");
+            }
+        }
+
+        let delay = backoff.next_delay();
+        log::info!("reconnecting in {}s", delay.as_secs_f64());
+        select! {
+            _ = tokio::time::sleep(delay) => (),
+            Some(control::Command::Shutdown) = command_rx.recv() => break,
+        }
+    }
+
+    log::info!("exiting");
+    log::logger().flush();
     Ok(())
 }
 
+/// Wait for ctrl-c, or for SIGTERM on Unix, as sent by systemd to stop a unit
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = sigterm.recv() => (),
+            },
+            Err(e) => {
+                log::error!("unable to listen for SIGTERM: {e}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Send a shutdown command when the agent is asked to stop by a signal, so that it is handled
+/// wherever the agent is, connected or waiting to reconnect
+async fn forward_termination_signals(command_tx: mpsc::UnboundedSender<control::Command>) {
+    termination_signal().await;
+    log::info!("stop signal received");
+    let _ = command_tx.send(control::Command::Shutdown);
+}
+
+/// Why the connection to the server ended
+#[derive(Debug, PartialEq, Eq)]
+enum Disconnection {
+    /// The connection was lost, the agent reconnects
+    Lost,
+    /// The agent was asked to exit
+    Shutdown,
+}
+
+/// Tell the server that the agent exits through `send`, before stopping it. Failing to tell it
+/// does not prevent the agent from exiting.
+async fn shutdown<F, Fut, E>(send: F) -> Disconnection
+where
+    F: FnOnce(Message) -> Fut,
+    Fut: IntoFuture<Output = Result<(), E>>,
+    E: Display,
+{
+    if let Err(e) = send(Message::Exit).await {
+        log::warn!("unable to tell the server that the agent exits: {e}");
+    }
+    Disconnection::Shutdown
+}
+
+/// Send a command to a running agent and print its response, or manage the service of the agent
+async fn run_command(command: &args::Command) -> anyhow::Result<()> {
+    match command {
+        args::Command::Status { control_socket } => {
+            println!("{}", control::send_command(control_socket, "status").await?);
+        }
+        args::Command::Stop { control_socket } => {
+            let response = control::send_command(control_socket, "shutdown").await?;
+            let response: control::Response = serde_json::from_str(&response)?;
+            if !response.ok {
+                bail!(
+                    "unable to stop the agent: {}",
+                    response.error.unwrap_or_default()
+                );
+            }
+            log::info!("agent stopped");
+        }
+        #[cfg(windows)]
+        args::Command::InstallService {
+            server,
+            pretty_name,
+            log_file,
+        } => service::install(
+            *server,
+            pretty_name.as_deref(),
+            &log_file.clone().unwrap_or_else(service::default_log_file),
+        )?,
+        #[cfg(windows)]
+        args::Command::UninstallService => service::uninstall()?,
+        #[cfg(windows)]
+        args::Command::StartService => service::start()?,
+        #[cfg(windows)]
+        args::Command::StopService => service::stop()?,
+    }
+    Ok(())
+}
+
+/// Connect and register to the server, then handle its messages until the connection is lost
+#[allow(clippy::too_many_arguments)]
 async fn register_and_start_client(
     server_addr: SocketAddr,
     pretty_name: Option<String>,
     ip_addresses: Vec<IpAddr>,
-) -> anyhow::Result<()> {
+    push_interval: Option<Duration>,
+    filters: &filter::Filters,
+    recording_limits: record::RecordingLimits,
+    recorder: &mut recorder::Recorder,
+    backoff: &mut reconnect::Backoff,
+    status: &control::SharedStatus,
+    command_rx: &mut mpsc::UnboundedReceiver<control::Command>,
+) -> anyhow::Result<Disconnection> {
     // Get hostname
     let hostname = hostname::get()?;
 
@@ -67,259 +287,347 @@ async fn register_and_start_client(
         tsyncp::channel::channel_to(server_addr)
             .set_tcp_nodelay(true)
             .await?;
-    let (mut rx, tx) = channel.split();
-    let tx = Arc::new(RwLock::new(tx));
+    let (mut rx, mut tx) = channel.split();
     let register_message = message::Register::new(
         hostname.to_string_lossy().to_string(),
         pretty_name.clone(),
         ip_addresses.clone(),
     );
 
-    // Create interrupt handler
-    let ctrl_c_tx = tx.clone();
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.unwrap();
-        ctrl_c_tx.write().await.send(Message::Exit).await.unwrap();
-        std::process::exit(0);
-    });
-
     // Send registration message
     log::info!("sending registration message");
     let message = Message::Register(register_message);
-    tx.write()
-        .await
-        .send(message)
+    tx.send(message)
         .await
         .with_context(|| "unable to send registration message")?;
+    // Older servers fail to decode the capabilities, and go on without them
+    tx.send(Message::Capabilities(message::CapabilitySet::current()))
+        .await
+        .with_context(|| "unable to send capabilities message")?;
+    backoff.reset();
+    status.lock().unwrap().connected = true;
+
+    // Messages from the recorder, to be sent to the server
+    let (recorder_tx, mut recorder_rx) = mpsc::unbounded_channel();
+    let collector = Arc::new(record::HostCollector {
+        pretty_name: pretty_name.clone(),
+        hostname: hostname.clone(),
+        ip_addresses: ip_addresses.clone(),
+        filters: filters.clone(),
+    });
+    // Older servers never send theirs
+    let mut server_capabilities = CapabilitySet::default();
+
+    // Resume the recording that was interrupted by a lost connection
+    if let Some(interval) = recorder.interval() {
+        log::info!("resuming recorder with interval of {interval}s");
+        let (recorder_tx, collector) = (recorder_tx.clone(), collector.clone());
+        recorder
+            .start(interval, |stop_token| {
+                record::record(
+                    interval,
+                    stop_token,
+                    recorder_tx,
+                    collector,
+                    recording_limits,
+                )
+            })
+            .await;
+        status.lock().unwrap().recording_interval = Some(interval);
+    }
 
-    // Recorder variables used in tokio jobs
-    let recording = Arc::new(RwLock::new(false));
-    let host_updates: Arc<RwLock<Vec<Update>>> = Arc::new(RwLock::new(Vec::new()));
+    // Unsolicited updates, which are paused while recording
+    let mut push_schedule =
+        push_interval.map(|interval| push::PushSchedule::new(interval, StdRng::from_entropy()));
+    let push_sleep = tokio::time::sleep(
+        push_schedule
+            .as_mut()
+            .map(|schedule| schedule.next_delay())
+            .unwrap_or_default(),
+    );
+    tokio::pin!(push_sleep);
 
     // Listen for instructions
-    while let Some(Ok(msg)) = rx.recv().await {
+    loop {
+        let msg = select! {
+            msg = rx.recv() => msg,
+            _ = &mut push_sleep, if push_schedule.is_some() && !recorder.is_running() => {
+                if let Some(schedule) = push_schedule.as_mut() {
+                    push_sleep.as_mut().reset(tokio::time::Instant::now() + schedule.next_delay());
+                }
+                // Handled just like a request from the server
+                log::info!("pushing scheduled update");
+                Some(Ok(Message::UpdateRequest))
+            }
+            Some(message) = recorder_rx.recv() => {
+                // Older servers cannot decode the stats
+                if let Message::AgentStats(stats) = &message {
+                    log::debug!("recording stats: {stats:?}");
+                    if server_capabilities.supports(Capability::AgentStats) {
+                        if let Err(e) = tx.send(message).await {
+                            log::error!("failure while sending recording stats: {e}");
+                        }
+                    }
+                    continue;
+                }
+                // Nor the details of the updates
+                if let Message::UpdateDetails(_) = &message {
+                    if server_capabilities.supports(Capability::UpdateDetails) {
+                        if let Err(e) = tx.send(message).await {
+                            log::error!("failure while sending update details: {e}");
+                        }
+                    }
+                    continue;
+                }
+                match tx.send(message).await {
+                    Ok(_) => status.lock().unwrap().update_sent(),
+                    Err(e) => log::error!("failure while sending update: {e}"),
+                }
+                continue;
+            }
+            Some(command) = command_rx.recv() => match command {
+                // Handled just like a request from the server
+                control::Command::UpdateNow => Some(Ok(Message::UpdateRequest)),
+                control::Command::Shutdown => {
+                    return Ok(shutdown(|message| tx.send(message)).await);
+                }
+            },
+        };
+        let Some(Ok(msg)) = msg else {
+            break;
+        };
+
+        // The time of the server is only used to measure the offset of our clock, the recording
+        // then starts as for the older servers
+        let msg = match msg {
+            Message::StartRecordingAt {
+                interval,
+                server_time_ms,
+            } => {
+                let received_at = chrono::Utc::now();
+                match clock::from_timestamp_ms(server_time_ms) {
+                    Some(server_time) => {
+                        let offset_ms = clock::clock_offset_ms(server_time, received_at);
+                        log::info!("clock offset to the server: {offset_ms} ms");
+                        status.lock().unwrap().clock_offset_ms = Some(offset_ms);
+                        // Only the servers supporting it send their time
+                        if let Err(e) = tx.send(Message::ClockOffset(offset_ms)).await {
+                            log::error!("failure while sending the clock offset: {e}");
+                        }
+                    }
+                    None => log::warn!("invalid server time {server_time_ms}"),
+                }
+                Message::StartRecording(interval)
+            }
+            msg => msg,
+        };
+
         match msg {
             Message::UpdateRequest => {
                 log::info!("sending update");
-                let update =
-                    collect::generate_one_time_update(&pretty_name, &hostname, &ip_addresses)?;
-                let message = Message::Update(update);
-                if let Err(e) = tx.write().await.send(message).await {
-                    log::error!("failure while sending update: {e}");
+                let messages =
+                    generate_one_time_update(&pretty_name, &hostname, &ip_addresses, filters)
+                        .and_then(|update| Message::update_messages(update, false));
+                match messages {
+                    Ok(messages) => {
+                        for message in messages {
+                            let is_details = matches!(message, Message::UpdateDetails(_));
+                            // Older servers cannot decode the details of the update
+                            if is_details
+                                && !server_capabilities.supports(Capability::UpdateDetails)
+                            {
+                                continue;
+                            }
+                            match tx.send(message).await {
+                                Ok(_) if is_details => (),
+                                Ok(_) => status.lock().unwrap().update_sent(),
+                                Err(e) => log::error!("failure while sending update: {e}"),
+                            }
+                        }
+                    }
+                    Err(e) => log::error!("unable to generate update: {e}"),
                 }
             }
-            Message::StartRecording(interval) => {
-                log::info!("starting recorder with interval of {interval}s");
-                *recording.write().await = true;
-                host_updates.write().await.clear();
-                let host_updates = host_updates.clone();
-                let recording = recording.clone();
-                let tx = tx.clone();
-                let hostname = hostname.clone();
-                let ip_addresses = ip_addresses.clone();
-                let pretty_name = pretty_name.clone();
+            Message::UpdateRequestSampled { samples, interval } => {
+                log::info!("sending update of {samples} samples taken every {interval}s");
+                // Sampled in the background, so that the other messages are still handled
+                let (recorder_tx, pretty_name, hostname, ip_addresses, filters) = (
+                    recorder_tx.clone(),
+                    pretty_name.clone(),
+                    hostname.clone(),
+                    ip_addresses.clone(),
+                    filters.clone(),
+                );
                 tokio::spawn(async move {
-                    // While recording, make updates and wait for the right interval in between
-                    while *recording.read().await {
-                        if let Ok(update) = collect::generate_one_time_update(
-                            &pretty_name,
-                            &hostname,
-                            &ip_addresses,
-                        ) {
-                            host_updates.write().await.push(update);
-                        }
-                        log::info!("captured socket info, waiting for next update");
-                        tokio::time::sleep(Duration::from_secs_f64(interval)).await;
-                    }
-
-                    // When stopped, send aggregate update
-                    match generate_aggregate_update(&host_updates.read().await) {
-                        Ok(update) => {
-                            let message = Message::Update(update);
-                            let mut tx = tx.write().await;
-                            if let Err(e) = tx.send(message.clone()).await {
-                                log::error!("failure while sending update: {e}");
+                    let messages = sample_update(
+                        samples,
+                        interval,
+                        &pretty_name,
+                        &hostname,
+                        &ip_addresses,
+                        &filters,
+                    )
+                    .await
+                    .and_then(|update| Message::update_messages(update, false));
+                    match messages {
+                        Ok(messages) => {
+                            for message in messages {
+                                let _ = recorder_tx.send(message);
                             }
                         }
-                        // TODO: relay agent errors to the server by making `Update` an enum
-                        Err(e) => log::error!("unable to create host object from capture: {e}"),
+                        Err(e) => log::error!("unable to generate sampled update: {e}"),
                     }
-
-                    // Clear updates
-                    host_updates.write().await.clear();
                 });
             }
+            Message::StartRecording(interval) => {
+                log::info!("starting recorder with interval of {interval}s");
+                let (recorder_tx, collector) = (recorder_tx.clone(), collector.clone());
+                recorder
+                    .start(interval, |stop_token| {
+                        record::record(
+                            interval,
+                            stop_token,
+                            recorder_tx,
+                            collector,
+                            recording_limits,
+                        )
+                    })
+                    .await;
+                status.lock().unwrap().recording_interval = Some(interval);
+            }
             Message::StopRecording => {
-                log::info!("stopping recorder and sending aggregate update");
-                *recording.write().await = false;
+                log::info!("stopping recorder and sending final update");
+                recorder.stop().await;
+                status.lock().unwrap().recording_interval = None;
+                // The final update was just sent
+                if let Some(schedule) = push_schedule.as_mut() {
+                    push_sleep
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + schedule.next_delay());
+                }
             }
             Message::Exit => {
-                log::info!("exiting");
-                std::process::exit(0);
+                log::info!("the server asked the agent to exit");
+                // The answer tells the server that the updates sent before have all arrived
+                return Ok(shutdown(|message| tx.send(message)).await);
+            }
+            Message::UpdateRejected(reason) => {
+                log::warn!("the server rejected our message: {reason}");
+            }
+            Message::Capabilities(capabilities) => {
+                let names: Vec<&str> = capabilities.names().collect();
+                log::info!("server capabilities: {}", names.join(", "));
+                server_capabilities = capabilities;
             }
             _ => (),
         }
     }
 
-    Ok(())
+    Ok(Disconnection::Lost)
 }
 
-#[cfg(target_os = "linux")]
-mod collect {
-    use sockets_map::{
-        host::Host, parsers::linux::LinuxHostRawData, server::client::HostData::LinuxHostData,
-        server::client::Update,
-    };
-    use std::{net::IpAddr, process::Command};
-
-    pub type NetworkOutput = sockets_map::parsers::linux::NetworkOutput;
-
-    /// pub Generate an update
-    pub fn generate_one_time_update(
-        pretty_name: &Option<String>,
-        hostname: &std::ffi::OsString,
-        ip_addresses: &[IpAddr],
-    ) -> Result<Update, anyhow::Error> {
-        let linux_host_data = get_host_data(
-            pretty_name,
-            hostname.to_string_lossy().to_string(),
-            ip_addresses,
-        )?;
-        let host: anyhow::Result<Host> = LinuxHostData(linux_host_data).into();
-        let update = Update::new(host?);
-        Ok(update)
-    }
-
-    /// Retrieve sockets information from the host
-    pub fn get_host_data(
-        pretty_name: &Option<String>,
-        hostname: String,
-        ip_addresses: &[IpAddr],
-    ) -> Result<LinuxHostRawData, anyhow::Error> {
-        let host_data = LinuxHostRawData::new(
-            pretty_name.clone().unwrap_or(hostname),
-            get_host_sockets_info()?,
-            ip_addresses.to_vec(),
-        );
-        Ok(host_data)
-    }
+/// Collect the sockets of the host, without the excluded ones
+fn generate_one_time_update(
+    pretty_name: &Option<String>,
+    hostname: &OsString,
+    ip_addresses: &[IpAddr],
+    filters: &filter::Filters,
+) -> anyhow::Result<Update> {
+    let name = pretty_name
+        .clone()
+        .unwrap_or_else(|| hostname.to_string_lossy().to_string());
+    let mut host = collect::collect_host(&name, ip_addresses)?;
+    filters.apply(&mut host);
+    Ok(Update::new(host))
+}
 
-    /// Retrieve network sockets information from the host
-    /// First try ss, then netstat
-    pub fn get_host_sockets_info() -> anyhow::Result<NetworkOutput> {
-        if let Ok(output) = exec_ss() {
-            Ok(NetworkOutput::Ss(output))
-        } else {
-            Ok(NetworkOutput::Netstat(exec_netstat()?))
+/// Take `samples` snapshots `interval` seconds apart, aggregated into a single update just like the
+/// server aggregates the updates of a recording
+async fn sample_update(
+    samples: u8,
+    interval: f64,
+    pretty_name: &Option<String>,
+    hostname: &OsString,
+    ip_addresses: &[IpAddr],
+    filters: &filter::Filters,
+) -> anyhow::Result<Update> {
+    let interval = Duration::try_from_secs_f64(interval).unwrap_or_default();
+    let mut updates = Vec::new();
+    for sample in 0..samples.max(1) {
+        if sample > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        match generate_one_time_update(pretty_name, hostname, ip_addresses, filters) {
+            Ok(update) => updates.push(update),
+            Err(e) => log::error!("unable to create host object from capture: {e}"),
         }
     }
-
-    fn exec_ss() -> anyhow::Result<String> {
-        let output = Command::new("ss").arg("-apn").output()?;
-        let output_str = std::str::from_utf8(&output.stdout)?;
-
-        Ok(output_str.to_string())
-    }
-
-    fn exec_netstat() -> anyhow::Result<String> {
-        // netstat -Wltpn; netstat -Wtpn
-        let output1 = Command::new("netstat").arg("-Wltpn").output()?;
-        let output2 = Command::new("netstat").arg("-Wtpn").output()?;
-        let output_str1 = std::str::from_utf8(&output1.stdout)?;
-        let output_str2 = std::str::from_utf8(&output2.stdout)?;
-
-        Ok(format!("{output_str1}\n{output_str2}"))
-    }
-
-    pub fn ensure_privileged() -> bool {
-        is_sudo::RunningAs::Root == is_sudo::check()
-    }
+    client::generate_aggregate_update(&updates)
 }
 
-#[cfg(target_os = "windows")]
-mod collect {
-    use sockets_map::{
-        host::Host,
-        parsers::windows::WindowsHostRawData,
-        server::client::{HostData::WindowsHostData, Update},
-    };
-    use std::{net::IpAddr, process::Command};
-
-    /// Generate an update
-    pub fn generate_one_time_update(
-        pretty_name: &Option<String>,
-        hostname: &std::ffi::OsString,
-        ip_addresses: &[IpAddr],
-    ) -> Result<Update, anyhow::Error> {
-        let linux_host_data = get_host_data(
-            pretty_name,
-            hostname.to_string_lossy().to_string(),
-            ip_addresses,
-        )?;
-        let host: anyhow::Result<Host> = WindowsHostData(linux_host_data).into();
-        let update = Update::new(host?);
-        Ok(update)
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    /// Retrieve sockets information from the host
-    pub fn get_host_data(
-        pretty_name: &Option<String>,
-        hostname: String,
-        ip_addresses: &[IpAddr],
-    ) -> Result<WindowsHostRawData, anyhow::Error> {
-        let host_data = WindowsHostRawData::new(
-            pretty_name.clone().unwrap_or(hostname),
-            get_host_sockets_info()?,
-            exec_tasklist()?,
-            ip_addresses.to_vec(),
-        );
-        Ok(host_data)
+    /// Sender standing for the channel to the server
+    #[derive(Default)]
+    struct MockSender {
+        sent: Vec<Message>,
+        fail: bool,
     }
 
-    /// Retrieve network sockets information from the host
-    pub fn get_host_sockets_info() -> anyhow::Result<String> {
-        let output = Command::new("netstat").arg("-ano").output()?;
-        let output_str = std::str::from_utf8(&output.stdout)?;
-
-        Ok(output_str.to_string())
+    impl MockSender {
+        async fn send(&mut self, message: Message) -> Result<(), String> {
+            if self.fail {
+                return Err("connection reset".to_string());
+            }
+            self.sent.push(message);
+            Ok(())
+        }
     }
 
-    fn exec_tasklist() -> anyhow::Result<String> {
-        let output = Command::new("tasklist").arg("/FO").arg("CSV").output()?;
-        let output_str = std::str::from_utf8(&output.stdout)?;
-
-        Ok(output_str.to_string())
+    /// The server must be told that the agent exits, which stops even if it cannot be told
+    #[tokio::test]
+    async fn test_shutdown() {
+        let mut sender = MockSender::default();
+        let disconnection = shutdown(|message| sender.send(message)).await;
+        assert_eq!(disconnection, Disconnection::Shutdown);
+        assert_eq!(sender.sent, vec![Message::Exit]);
+
+        let mut sender = MockSender {
+            fail: true,
+            ..Default::default()
+        };
+        let disconnection = shutdown(|message| sender.send(message)).await;
+        assert_eq!(disconnection, Disconnection::Shutdown);
+        assert!(sender.sent.is_empty());
     }
 
-    // TODO: need to fix an build issue with the [is_sudo](https://github.com/spa5k/is_sudo) crate
-    pub fn ensure_privileged() -> bool {
-        true
+    /// Address on which no server listens
+    fn closed_address() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
     }
-}
 
-/// This is the equivalent of joining the output of the commands
-pub fn generate_aggregate_update(updates: &[Update]) -> Result<Update, anyhow::Error> {
-    if let Some(first_update) = updates.first() {
-        let mut aggregated_host = first_update.host.clone();
-        for host_update in updates.iter().map(|u| u.host.clone()) {
-            // Aggregate connections
-            aggregated_host
-                .connections_mut()
-                .extend(host_update.connections().to_vec());
-            aggregated_host.connections_mut().sort();
-            aggregated_host.connections_mut().dedup();
-
-            // Aggregate listening sockets
-            aggregated_host
-                .listening_sockets_mut()
-                .extend(host_update.listening_sockets().to_vec());
-            aggregated_host.listening_sockets_mut().sort();
-            aggregated_host.listening_sockets_mut().dedup();
-        }
-
-        let update = Update::new(aggregated_host);
-        return Ok(update);
+    /// The agent must give up on an unreachable server after the given number of attempts, and
+    /// exit successfully when asked to while waiting to reconnect
+    #[tokio::test]
+    async fn test_run_agent() {
+        let address = closed_address();
+        let args = args::Args::parse_from([
+            "sockets_map_agent",
+            &address,
+            "--no-root",
+            "--register-attempts",
+            "2",
+        ]);
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let result = run_agent(&args, command_tx, command_rx).await;
+        assert!(result.unwrap_err().to_string().contains("after 2 attempts"));
+
+        let args = args::Args::parse_from(["sockets_map_agent", &address, "--no-root"]);
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        command_tx.send(control::Command::Shutdown).unwrap();
+        run_agent(&args, command_tx, command_rx).await.unwrap();
     }
-
-    Err(anyhow::Error::msg("no updates were made"))
 }
@@ -0,0 +1,54 @@
+//! Schedule of the updates pushed to the server without being requested
+
+use rand::{rngs::StdRng, Rng};
+use std::time::Duration;
+
+/// Maximum jitter, as a fraction of the push interval
+pub const JITTER_RATIO: f64 = 0.1;
+
+/// Pushes happen at a fixed interval, randomly shifted by up to `JITTER_RATIO` of it so that
+/// agents started at the same time do not all send their updates at once
+#[derive(Debug)]
+pub struct PushSchedule {
+    interval: Duration,
+    rng: StdRng,
+}
+
+impl PushSchedule {
+    pub fn new(interval: Duration, rng: StdRng) -> Self {
+        Self { interval, rng }
+    }
+
+    /// Get the delay to wait before the next push
+    pub fn next_delay(&mut self) -> Duration {
+        let jitter = self.rng.gen_range(-JITTER_RATIO..=JITTER_RATIO);
+        self.interval.mul_f64(1.0 + jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    /// Delays stay within the jitter bounds around the interval, and are spread out
+    #[test]
+    fn test_push_schedule_jitter() {
+        let interval = Duration::from_secs(60);
+        let mut schedule = PushSchedule::new(interval, StdRng::seed_from_u64(0));
+        let delays: Vec<Duration> = (0..1000).map(|_| schedule.next_delay()).collect();
+
+        let min = interval.mul_f64(1.0 - JITTER_RATIO);
+        let max = interval.mul_f64(1.0 + JITTER_RATIO);
+        assert!(delays.iter().all(|d| (min..=max).contains(d)));
+
+        // Not all agents push at the same time, but on average at the requested interval
+        let (early, late) = (
+            interval.mul_f64(1.0 - JITTER_RATIO / 2.0),
+            interval.mul_f64(1.0 + JITTER_RATIO / 2.0),
+        );
+        assert!(delays.iter().any(|d| *d < early) && delays.iter().any(|d| *d > late));
+        let mean = delays.iter().sum::<Duration>() / delays.len() as u32;
+        assert!(mean > interval.mul_f64(0.99) && mean < interval.mul_f64(1.01));
+    }
+}
@@ -0,0 +1,56 @@
+//! Delays between the attempts to reconnect to the server
+
+use std::time::Duration;
+
+/// Delay before the first reconnection attempt
+pub const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// Exponential backoff: the delay doubles after each failed attempt, up to a maximum
+#[derive(Debug)]
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    next_delay: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay,
+            next_delay: initial_delay.min(max_delay),
+        }
+    }
+
+    /// Get the delay to wait before the next attempt, and double the following one
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next_delay;
+        self.next_delay = (self.next_delay * 2).min(self.max_delay);
+        delay
+    }
+
+    /// Start over from the initial delay, after a successful connection
+    pub fn reset(&mut self) {
+        self.next_delay = self.initial_delay.min(self.max_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delays double until they reach the maximum, and start over after a reset
+    #[test]
+    fn test_backoff_schedule() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+
+        // The maximum delay also caps the initial one
+        let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+    }
+}
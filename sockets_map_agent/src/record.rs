@@ -0,0 +1,411 @@
+//! Recording loop of the agent, which measures its own cost so that a short interval on a busy host
+//! does not go unnoticed
+
+use sockets_map::{
+    host::Host,
+    server::{
+        client::Update,
+        message::{AgentStats, Message},
+    },
+};
+use std::{
+    ffi::OsString,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    sync::mpsc,
+    task::{JoinError, JoinHandle},
+    time::{self, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::filter;
+
+/// Interval between two stats of the recording sent to the server
+pub const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Snapshot of the sockets of the host, taken on a blocking thread
+pub trait Collector: Send + Sync + 'static {
+    fn collect(&self) -> anyhow::Result<Update>;
+}
+
+/// Collects the sockets of this host, without the excluded ones
+pub struct HostCollector {
+    pub pretty_name: Option<String>,
+    pub hostname: OsString,
+    pub ip_addresses: Vec<IpAddr>,
+    pub filters: filter::Filters,
+}
+
+impl Collector for HostCollector {
+    fn collect(&self) -> anyhow::Result<Update> {
+        crate::generate_one_time_update(
+            &self.pretty_name,
+            &self.hostname,
+            &self.ip_addresses,
+            &self.filters,
+        )
+    }
+}
+
+/// Limits of a recording
+#[derive(Debug, Clone, Copy)]
+pub struct RecordingLimits {
+    /// Size of the aggregate of the sent updates from which it is sent in full and dropped, in
+    /// bytes
+    pub max_buffer_bytes: Option<u64>,
+    /// Interval between two stats sent to the server
+    pub stats_interval: Duration,
+}
+
+/// Everything sent to the server so far, against which the deltas are computed
+#[derive(Debug, Default)]
+struct SentHost {
+    host: Option<Host>,
+    /// Whether an update was sent since the start of the recording
+    sent: bool,
+}
+
+impl SentHost {
+    /// Messages sending `update` with its details: in full the first time, then as a delta. Once
+    /// the aggregate exceeds `max_buffer_bytes`, it is sent in full and dropped, so that the next
+    /// update starts a new one.
+    fn push(&mut self, update: Update, max_buffer_bytes: Option<u64>) -> Vec<Message> {
+        self.sent = true;
+        let Some(sent_host) = self.host.as_mut() else {
+            self.host = Some(update.host.clone());
+            return serialized(Message::update_messages(update, false));
+        };
+        let delta = update.host.delta(sent_host);
+        sent_host.merge(&update.host);
+        let mut messages = serialized(Message::update_messages(Update::new(delta), true));
+        if max_buffer_bytes.is_some_and(|max_bytes| self.bytes() > max_bytes) {
+            log::info!("the recorded updates exceed the buffer size, sending them");
+            messages.extend(self.take_snapshot().into_iter().flatten());
+        }
+        messages
+    }
+
+    /// Size of the aggregate, in bytes
+    fn bytes(&self) -> u64 {
+        self.host
+            .as_ref()
+            .and_then(|host| bincode::serialized_size(host).ok())
+            .unwrap_or_default()
+    }
+
+    /// Full update of the aggregate, which is dropped
+    fn take_snapshot(&mut self) -> Option<Vec<Message>> {
+        let host = self.host.take()?;
+        Some(serialized(Message::update_messages(
+            Update::new(host),
+            false,
+        )))
+    }
+}
+
+fn serialized(messages: anyhow::Result<Vec<Message>>) -> Vec<Message> {
+    messages
+        .map_err(|e| log::error!("unable to serialize update: {e}"))
+        .unwrap_or_default()
+}
+
+/// Cost of the snapshots since the last stats sent
+#[derive(Debug, Default)]
+struct RecordingStats {
+    collect_time: Duration,
+    samples: u32,
+    /// Since the start of the recording
+    skipped: u64,
+}
+
+impl RecordingStats {
+    fn add_sample(&mut self, collect_time: Duration) {
+        self.collect_time += collect_time;
+        self.samples += 1;
+    }
+
+    /// Stats of the samples since the last call
+    fn take(&mut self, buffered_bytes: u64) -> AgentStats {
+        let cpu_ms_per_sample = match self.samples {
+            0 => 0.0,
+            samples => self.collect_time.as_secs_f64() * 1000.0 / f64::from(samples),
+        };
+        self.collect_time = Duration::ZERO;
+        self.samples = 0;
+        AgentStats {
+            cpu_ms_per_sample,
+            buffered_bytes,
+            skipped: self.skipped,
+        }
+    }
+}
+
+/// State of a recording loop, which sends its messages to the server through `recorder_tx`
+struct Recording {
+    recorder_tx: mpsc::UnboundedSender<Message>,
+    max_buffer_bytes: Option<u64>,
+    sent_host: SentHost,
+    stats: RecordingStats,
+}
+
+impl Recording {
+    fn send(&self, message: Message) {
+        let _ = self.recorder_tx.send(message);
+    }
+
+    /// Send the update of a finished capture
+    fn collected(&mut self, collected: Result<(anyhow::Result<Update>, Duration), JoinError>) {
+        match collected {
+            Ok((Ok(update), collect_time)) => {
+                self.stats.add_sample(collect_time);
+                for message in self.sent_host.push(update, self.max_buffer_bytes) {
+                    self.send(message);
+                }
+                log::info!("captured socket info, waiting for next update");
+            }
+            Ok((Err(e), _)) => log::error!("unable to create host object from capture: {e}"),
+            Err(e) => log::error!("capture failure: {e}"),
+        }
+    }
+
+    fn send_stats(&mut self) {
+        let stats = self.stats.take(self.sent_host.bytes());
+        self.send(Message::AgentStats(stats));
+    }
+
+    /// Send a full snapshot, so that the server can check its aggregate
+    fn finish(mut self) {
+        match self.sent_host.take_snapshot() {
+            Some(messages) => messages.into_iter().for_each(|message| self.send(message)),
+            // TODO: relay agent errors to the server by making `Update` an enum
+            None if !self.sent_host.sent => log::error!("no updates were made"),
+            // The aggregate was just sent
+            None => (),
+        }
+    }
+}
+
+/// Collect a snapshot on a blocking thread, measuring its duration
+fn spawn_collection<C: Collector>(
+    collector: &Arc<C>,
+) -> JoinHandle<(anyhow::Result<Update>, Duration)> {
+    let collector = collector.clone();
+    tokio::task::spawn_blocking(move || {
+        let start = Instant::now();
+        let update = collector.collect();
+        (update, start.elapsed())
+    })
+}
+
+/// Recording loop: capture the sockets every `interval` seconds until `stop_token` is cancelled.
+/// The first capture is sent in full, then only the deltas, and a full snapshot at the end. The
+/// captures due while the previous one is still being collected are skipped.
+pub async fn record<C: Collector>(
+    interval: f64,
+    stop_token: CancellationToken,
+    recorder_tx: mpsc::UnboundedSender<Message>,
+    collector: Arc<C>,
+    limits: RecordingLimits,
+) {
+    let mut recording = Recording {
+        recorder_tx,
+        max_buffer_bytes: limits.max_buffer_bytes,
+        sent_host: SentHost::default(),
+        stats: RecordingStats::default(),
+    };
+    // A zero period would make the ticker panic
+    let mut ticker =
+        time::interval(Duration::from_secs_f64(interval).max(Duration::from_millis(1)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut stats_ticker = time::interval_at(
+        time::Instant::now() + limits.stats_interval,
+        limits.stats_interval,
+    );
+    let mut collection: Option<JoinHandle<_>> = None;
+    loop {
+        select! {
+            _ = stop_token.cancelled() => break,
+            collected = async { collection.as_mut().unwrap().await }, if collection.is_some() => {
+                collection = None;
+                recording.collected(collected);
+            }
+            _ = ticker.tick() => match collection {
+                Some(_) => {
+                    recording.stats.skipped += 1;
+                    log::warn!("skipping a capture, the previous one is still running");
+                }
+                None => collection = Some(spawn_collection(&collector)),
+            },
+            _ = stats_ticker.tick() => recording.send_stats(),
+        }
+    }
+
+    // The capture in progress cannot be cancelled, and is sent with the others
+    if let Some(collection) = collection {
+        recording.collected(collection.await);
+    }
+    recording.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockets_map::host::{ListeningSocket, Process, SocketType};
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    /// Collector taking `duration`, which finds a new listening socket at each snapshot
+    struct FakeCollector {
+        duration: Duration,
+        snapshots: AtomicU16,
+    }
+
+    impl FakeCollector {
+        fn new(duration: Duration) -> Arc<Self> {
+            Arc::new(Self {
+                duration,
+                snapshots: AtomicU16::new(0),
+            })
+        }
+    }
+
+    impl Collector for FakeCollector {
+        fn collect(&self) -> anyhow::Result<Update> {
+            std::thread::sleep(self.duration);
+            let port = 1000 + self.snapshots.fetch_add(1, Ordering::SeqCst);
+            Ok(make_update(port))
+        }
+    }
+
+    fn make_update(port: u16) -> Update {
+        let mut host = Host::new("machine1");
+        host.add_listening_socket(ListeningSocket::new(
+            format!("0.0.0.0:{port}").parse().unwrap(),
+            SocketType::TCP,
+            Process::new("nginx", 101, "machine1".to_string()),
+            "machine1".to_string(),
+            None,
+        ));
+        Update::new(host)
+    }
+
+    /// Run a recording for `duration`, returning the messages sent to the server
+    async fn run_recording(
+        interval: f64,
+        duration: Duration,
+        collector: Arc<FakeCollector>,
+        limits: RecordingLimits,
+    ) -> Vec<Message> {
+        let (recorder_tx, mut recorder_rx) = mpsc::unbounded_channel();
+        let stop_token = CancellationToken::new();
+        let recording = tokio::spawn(record(
+            interval,
+            stop_token.clone(),
+            recorder_tx,
+            collector,
+            limits,
+        ));
+        time::sleep(duration).await;
+        stop_token.cancel();
+        recording.await.unwrap();
+        let mut messages = Vec::new();
+        while let Ok(message) = recorder_rx.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// The first update must be sent in full, then the deltas, and the aggregate exceeding the
+    /// buffer size must be sent in full and dropped
+    #[test]
+    fn test_sent_host_push() {
+        let mut sent_host = SentHost::default();
+        assert!(matches!(
+            sent_host.push(make_update(1000), None)[..],
+            [Message::Update(_)]
+        ));
+        assert!(matches!(
+            sent_host.push(make_update(1001), None)[..],
+            [Message::UpdateDelta(_)]
+        ));
+        assert!(sent_host.bytes() > 0);
+
+        let messages = sent_host.push(make_update(1002), Some(1));
+        let [Message::UpdateDelta(_), Message::Update(snapshot)] = &messages[..] else {
+            panic!("expected a delta and a snapshot, got {messages:?}");
+        };
+        assert_eq!(snapshot.host.listening_sockets().len(), 3);
+        assert_eq!(sent_host.bytes(), 0);
+        assert!(sent_host.take_snapshot().is_none());
+
+        // A new aggregate is started
+        assert!(matches!(
+            sent_host.push(make_update(1003), Some(1))[..],
+            [Message::Update(_)]
+        ));
+    }
+
+    /// The captures due while a slow collection is running must be skipped and counted in the
+    /// stats
+    #[tokio::test]
+    async fn test_record_skips_slow_collections() {
+        let collector = FakeCollector::new(Duration::from_millis(60));
+        let limits = RecordingLimits {
+            max_buffer_bytes: None,
+            stats_interval: Duration::from_millis(200),
+        };
+        let messages =
+            run_recording(0.01, Duration::from_millis(300), collector.clone(), limits).await;
+
+        let stats: Vec<&AgentStats> = messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::AgentStats(stats) => Some(stats),
+                _ => None,
+            })
+            .collect();
+        let [stats] = stats[..] else {
+            panic!("expected a single stats message, got {stats:?}");
+        };
+        assert!(stats.skipped > 0, "{stats:?}");
+        assert!(stats.cpu_ms_per_sample >= 60.0, "{stats:?}");
+        assert!(stats.buffered_bytes > 0, "{stats:?}");
+
+        // Every collection is sent, and the recording ends with a full snapshot
+        let updates = messages
+            .iter()
+            .filter(|message| !matches!(message, Message::AgentStats(_)))
+            .count();
+        assert_eq!(
+            updates,
+            usize::from(collector.snapshots.load(Ordering::SeqCst)) + 1
+        );
+        assert!(matches!(messages.last(), Some(Message::Update(_))));
+    }
+
+    /// With a buffer size exceeded at each update, every delta must be followed by a full
+    /// snapshot, and no other one is sent at the end
+    #[tokio::test]
+    async fn test_record_flushes_buffer() {
+        let collector = FakeCollector::new(Duration::ZERO);
+        let limits = RecordingLimits {
+            max_buffer_bytes: Some(1),
+            stats_interval: STATS_INTERVAL,
+        };
+        let messages = run_recording(0.01, Duration::from_millis(100), collector, limits).await;
+
+        assert!(messages.len() >= 3, "{messages:?}");
+        assert!(matches!(messages.last(), Some(Message::Update(_))));
+        for (message, next) in messages.iter().zip(messages.iter().skip(1)) {
+            if matches!(message, Message::UpdateDelta(_)) {
+                assert!(matches!(next, Message::Update(_)), "{messages:?}");
+            }
+        }
+        assert!(messages
+            .iter()
+            .any(|message| matches!(message, Message::UpdateDelta(_))));
+    }
+}
@@ -0,0 +1,117 @@
+//! Handle on the recording loop, so that there is never more than one running
+
+use std::future::Future;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Default)]
+pub struct Recorder {
+    /// Interval of the current recording, kept while the recording is suspended
+    interval: Option<f64>,
+    /// Cancelled to ask the recording loop to stop gracefully
+    stop_token: CancellationToken,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Start recording with the `record` loop, which must return once its token is cancelled.
+    /// A recording that is already running is stopped first.
+    pub async fn start<F, Fut>(&mut self, interval: f64, record: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if self.is_running() {
+            log::info!("restarting recorder");
+        }
+        self.stop().await;
+        self.stop_token = CancellationToken::new();
+        self.interval = Some(interval);
+        self.handle = Some(tokio::spawn(record(self.stop_token.clone())));
+    }
+
+    /// Stop the recording loop and wait for it to end
+    pub async fn stop(&mut self) {
+        self.interval = None;
+        self.stop_token.cancel();
+        if let Some(handle) = self.handle.take() {
+            if let Err(e) = handle.await {
+                log::error!("recorder failure: {e}");
+            }
+        }
+    }
+
+    /// Abort the recording loop, but remember its interval so that it can be resumed
+    pub fn suspend(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+
+    /// Interval of the current or suspended recording
+    pub fn interval(&self) -> Option<f64> {
+        self.interval
+    }
+
+    /// Whether a recording loop is running
+    pub fn is_running(&self) -> bool {
+        matches!(&self.handle, Some(handle) if !handle.is_finished())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Fake recording loop that counts the running loops and the stopped ones
+    async fn fake_record(
+        stop_token: CancellationToken,
+        running: Arc<AtomicUsize>,
+        stopped: Arc<AtomicUsize>,
+    ) {
+        running.fetch_add(1, Ordering::SeqCst);
+        stop_token.cancelled().await;
+        running.fetch_sub(1, Ordering::SeqCst);
+        stopped.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// A second `StartRecording` restarts the recorder with the new interval instead of adding a loop
+    #[tokio::test]
+    async fn test_double_start_recording() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut recorder = Recorder::default();
+
+        for interval in [1.0, 2.0] {
+            let (running, stopped) = (running.clone(), stopped.clone());
+            recorder
+                .start(interval, |token| fake_record(token, running, stopped))
+                .await;
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(running.load(Ordering::SeqCst), 1);
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+        assert_eq!(recorder.interval(), Some(2.0));
+
+        recorder.stop().await;
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+        assert_eq!(recorder.interval(), None);
+        assert!(!recorder.is_running());
+    }
+
+    /// A suspended recording keeps its interval
+    #[tokio::test]
+    async fn test_suspend_keeps_interval() {
+        let mut recorder = Recorder::default();
+        recorder
+            .start(0.5, |token| async move { token.cancelled().await })
+            .await;
+        recorder.suspend();
+        assert!(!recorder.is_running());
+        assert_eq!(recorder.interval(), Some(0.5));
+    }
+}
@@ -0,0 +1,310 @@
+//! Integration with the service managers: a systemd unit running the agent on Linux, and the
+//! installation and entry point of the Windows service
+
+#[cfg(target_os = "linux")]
+use std::{ffi::OsString, path::Path};
+
+/// Options of the agent which are not passed to the service it runs as
+#[cfg(target_os = "linux")]
+const UNIT_SKIPPED_ARGS: [&str; 2] = ["--print-systemd-unit", "--service"];
+
+/// systemd unit running `executable` as a service with the options `agent_args`, given without
+/// the executable itself
+#[cfg(target_os = "linux")]
+pub fn systemd_unit(executable: &Path, agent_args: &[OsString]) -> String {
+    let command = std::iter::once(executable.as_os_str())
+        .chain(std::iter::once("--service".as_ref()))
+        .chain(
+            agent_args
+                .iter()
+                .map(OsString::as_os_str)
+                .filter(|arg| !UNIT_SKIPPED_ARGS.iter().any(|skipped| arg == skipped)),
+        )
+        .map(|arg| quote_unit_arg(&arg.to_string_lossy()))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "[Unit]
+Description=Sockets Map agent
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+ExecStart={command}
+Restart=on-failure
+RestartSec=10
+
+[Install]
+WantedBy=multi-user.target
+"
+    )
+}
+
+/// Escape an argument of `ExecStart`, quoting it if it contains spaces
+#[cfg(target_os = "linux")]
+fn quote_unit_arg(arg: &str) -> String {
+    // The specifiers and the environment variables are expanded by systemd
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg
+    }
+}
+
+#[cfg(windows)]
+pub use windows::*;
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        ffi::{OsStr, OsString},
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use anyhow::Context;
+    use clap::Parser;
+    use tokio::sync::mpsc;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept,
+            ServiceErrorControl, ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod,
+            ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    use crate::{args, control};
+
+    /// Name of the service, as used by `sc.exe`
+    pub const SERVICE_NAME: &str = "sockets_map_agent";
+    const SERVICE_DISPLAY_NAME: &str = "Sockets Map agent";
+    const SERVICE_DESCRIPTION: &str =
+        "Sends the sockets of this host to a Socket Map server, to map the network interactions between its processes and the ones of other hosts";
+    /// Delay before the service manager restarts the agent once it gave up on the server
+    const RESTART_DELAY: Duration = Duration::from_secs(60);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Hand the process over to the service manager, which runs the agent on another thread until
+    /// the service is stopped
+    pub fn run_as_service() -> anyhow::Result<()> {
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .context("unable to start the service dispatcher")
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            log::error!("{e:#}");
+        }
+        log::logger().flush();
+    }
+
+    /// Run the agent with the arguments of the service, until it is stopped by the service
+    /// manager or gives up on the server
+    fn run_service() -> anyhow::Result<()> {
+        // The service is started with the arguments written at its installation
+        let args = args::Args::parse();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+
+        // A stop is handled like the stop command, which tells the server that the agent exits
+        let stop_tx = command_tx.clone();
+        let status_handle =
+            service_control_handler::register(SERVICE_NAME, move |event| match event {
+                ServiceControl::Stop => {
+                    log::info!("stop requested by the service manager");
+                    let _ = stop_tx.send(control::Command::Shutdown);
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            })
+            .context("unable to register the service control handler")?;
+        set_status(
+            &status_handle,
+            ServiceState::Running,
+            ServiceControlAccept::STOP,
+            ServiceExitCode::NO_ERROR,
+        )?;
+
+        let result = tokio::runtime::Runtime::new()
+            .context("unable to start the runtime")
+            .and_then(|runtime| runtime.block_on(crate::run_agent(&args, command_tx, command_rx)));
+
+        // An error makes the service manager restart the agent
+        let exit_code = match &result {
+            Ok(()) => ServiceExitCode::NO_ERROR,
+            Err(_) => ServiceExitCode::ServiceSpecific(1),
+        };
+        set_status(
+            &status_handle,
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+        )?;
+        result
+    }
+
+    fn set_status(
+        status_handle: &ServiceStatusHandle,
+        current_state: ServiceState,
+        controls_accepted: ServiceControlAccept,
+        exit_code: ServiceExitCode,
+    ) -> anyhow::Result<()> {
+        status_handle
+            .set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state,
+                controls_accepted,
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+            .with_context(|| format!("unable to set the service status to {current_state:?}"))
+    }
+
+    /// Log file of the service when none is given, in `%PROGRAMDATA%\sockets_map_agent`
+    pub fn default_log_file() -> PathBuf {
+        let program_data =
+            std::env::var_os("PROGRAMDATA").unwrap_or_else(|| OsString::from(r"C:\ProgramData"));
+        Path::new(&program_data)
+            .join(SERVICE_NAME)
+            .join("agent.log")
+    }
+
+    /// Install the agent as a service started with the system, which connects to `server_addr`
+    /// and writes its logs to `log_file`
+    pub fn install(
+        server_addr: SocketAddr,
+        pretty_name: Option<&str>,
+        log_file: &Path,
+    ) -> anyhow::Result<()> {
+        if let Some(parent) = log_file.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("unable to create directory {parent:?}"))?;
+        }
+        let mut launch_arguments = vec![
+            OsString::from("--windows-service"),
+            OsString::from("--service"),
+            OsString::from(server_addr.to_string()),
+        ];
+        launch_arguments.extend(pretty_name.map(OsString::from));
+        launch_arguments.extend([OsString::from("--log-file"), log_file.into()]);
+
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .context("unable to connect to the service manager")?;
+        let service = manager
+            .create_service(
+                &ServiceInfo {
+                    name: SERVICE_NAME.into(),
+                    display_name: SERVICE_DISPLAY_NAME.into(),
+                    service_type: ServiceType::OWN_PROCESS,
+                    start_type: ServiceStartType::AutoStart,
+                    error_control: ServiceErrorControl::Normal,
+                    executable_path: std::env::current_exe()?,
+                    launch_arguments,
+                    dependencies: vec![],
+                    account_name: None,
+                    account_password: None,
+                },
+                ServiceAccess::CHANGE_CONFIG,
+            )
+            .context("unable to create the service")?;
+        service.set_description(SERVICE_DESCRIPTION)?;
+
+        // Like Restart=on-failure with systemd, when the agent gives up on the server
+        service.update_failure_actions(ServiceFailureActions {
+            reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+            reboot_msg: None,
+            command: None,
+            actions: Some(vec![
+                ServiceAction {
+                    action_type: ServiceActionType::Restart,
+                    delay: RESTART_DELAY,
+                };
+                3
+            ]),
+        })?;
+        service.set_failure_actions_on_non_crash_failures(true)?;
+        log::info!("service {SERVICE_NAME} installed, logging to {log_file:?}");
+        Ok(())
+    }
+
+    /// Stop the service if it is running, and remove it
+    pub fn uninstall() -> anyhow::Result<()> {
+        let service = open_service(
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop().context("unable to stop the service")?;
+        }
+        service.delete().context("unable to delete the service")?;
+        log::info!("service {SERVICE_NAME} uninstalled");
+        Ok(())
+    }
+
+    pub fn start() -> anyhow::Result<()> {
+        open_service(ServiceAccess::START)?
+            .start::<&OsStr>(&[])
+            .context("unable to start the service")?;
+        log::info!("service {SERVICE_NAME} started");
+        Ok(())
+    }
+
+    pub fn stop() -> anyhow::Result<()> {
+        open_service(ServiceAccess::STOP)?
+            .stop()
+            .context("unable to stop the service")?;
+        log::info!("service {SERVICE_NAME} stopped");
+        Ok(())
+    }
+
+    fn open_service(access: ServiceAccess) -> anyhow::Result<windows_service::service::Service> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .context("unable to connect to the service manager")?;
+        manager
+            .open_service(SERVICE_NAME, access)
+            .with_context(|| format!("unable to open service {SERVICE_NAME}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    use super::*;
+
+    /// The unit must run the agent as a service with the given options, quoted for systemd
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_systemd_unit() {
+        let args: Vec<OsString> = [
+            "10.0.0.10:6840",
+            "web server",
+            "--print-systemd-unit",
+            "--service",
+            "--exclude-process",
+            "zabbix_agentd",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        let unit = systemd_unit(Path::new("/usr/local/bin/sockets_map_agent"), &args);
+        assert!(unit.contains(
+            "\nExecStart=/usr/local/bin/sockets_map_agent --service 10.0.0.10:6840 \"web server\" --exclude-process zabbix_agentd\n"
+        ), "{unit}");
+        assert!(unit.contains("\nRestart=on-failure\n"));
+        assert!(unit.contains("\nWantedBy=multi-user.target\n"));
+
+        assert_eq!(quote_unit_arg("50%"), "50%%");
+        assert_eq!(quote_unit_arg("a \"b\""), "\"a \\\"b\\\"\"");
+    }
+}
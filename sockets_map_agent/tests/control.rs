@@ -0,0 +1,139 @@
+//! Run the agent against a local server, and drive it through its control socket.
+
+#![cfg(unix)]
+
+use sockets_map::server::{self, client::Client};
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+    sync::RwLock,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Get a free TCP port on the loopback interface
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Run the agent binary with `args`, and return its standard output
+fn run_agent(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_sockets_map_agent"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "agent {args:?} failed: {output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Send a raw command line to the control socket
+async fn send_raw_command(control_socket: &Path, command: &str) -> serde_json::Value {
+    let mut stream = BufReader::new(UnixStream::connect(control_socket).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(format!("{command}\n").as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_line(&mut response).await.unwrap();
+    serde_json::from_str(&response).unwrap()
+}
+
+/// Wait until `condition` is true on the clients of the server
+async fn wait_for_clients(
+    clients: &RwLock<HashMap<String, Client>>,
+    condition: impl Fn(&HashMap<String, Client>) -> bool,
+) {
+    for _ in 0..100 {
+        if condition(&*clients.read().await) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("timeout while waiting for the clients");
+}
+
+/// Query the status, request an update and stop the agent through its control socket
+#[tokio::test]
+async fn test_control_socket() {
+    let clients = Arc::new(RwLock::new(HashMap::new()));
+    let run_token = CancellationToken::new();
+    let server_addr = format!("127.0.0.1:{}", free_port());
+    server::listen(
+        server_addr.clone(),
+        clients.clone(),
+        Arc::new(RwLock::new(None)),
+        run_token.clone(),
+        server::ListenOptions::default(),
+        |_| (),
+        |_| (),
+        |_| (),
+        |_| (),
+    )
+    .await
+    .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let control_socket = dir.path().join("agent.sock");
+    let control_socket_str = control_socket.to_str().unwrap();
+    let mut agent = Command::new(env!("CARGO_BIN_EXE_sockets_map_agent"))
+        .args([
+            "--no-root",
+            "--control-socket",
+            control_socket_str,
+            &server_addr,
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_for_clients(&clients, |clients| !clients.is_empty()).await;
+
+    // Status from the subcommand
+    let status: serde_json::Value = serde_json::from_str(&run_agent(&[
+        "status",
+        "--control-socket",
+        control_socket_str,
+    ]))
+    .unwrap();
+    assert_eq!(status["pid"], agent.id());
+    assert_eq!(status["server_addr"], server_addr);
+    assert_eq!(status["connected"], true);
+    assert_eq!(status["updates_sent"], 0);
+
+    // Update request, which must reach the server
+    let response = send_raw_command(&control_socket, "update-now").await;
+    assert_eq!(response["ok"], true);
+    wait_for_clients(&clients, |clients| {
+        clients.values().any(|c| !c.updates().is_empty())
+    })
+    .await;
+    let status = send_raw_command(&control_socket, "status").await;
+    assert_eq!(status["updates_sent"], 1);
+    assert!(status["last_update_time"].is_string());
+
+    let response = send_raw_command(&control_socket, "bogus").await;
+    assert_eq!(response["ok"], false);
+
+    // Stop: the agent must disconnect and exit
+    run_agent(&["stop", "--control-socket", control_socket_str]);
+    wait_for_clients(&clients, |clients| clients.is_empty()).await;
+    let exit_status = tokio::task::spawn_blocking(move || agent.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(exit_status.success());
+    assert!(!control_socket.exists());
+
+    run_token.cancel();
+}
@@ -1,7 +1,17 @@
 //! This module manages the CLI arguments API
 
-use clap::Parser;
-use sockets_map::graphviz::LayoutEngine;
+use crate::filter::ProcessPattern;
+use clap::{Args, Parser};
+use sockets_map::{
+    connections_model::ConnectionsOptions,
+    csv::{CsvColumn, CsvOptions},
+    graphs::{GraphGranularity, ListenerRendering, LoopbackRendering},
+    graphviz::{LayoutEngine, OverviewOptions},
+    parsers::directory_scanner::{OnDuplicate, ScanOptions},
+    provenance::Provenance,
+    summary::GroupBy,
+};
+use std::ops::RangeInclusive;
 
 #[derive(Parser)]
 #[clap(version = clap::crate_version!(), author = "Aurelien Dubois <aurelien.dubois@amossys.fr>", about = "A tool to map the network interactions between processes in a group of machines, from information that can be gathered using native tools on the targets.")]
@@ -31,15 +41,40 @@ pub enum SubCommand {
     Graph(Graph),
     #[clap(about = "Output a CSV with all compiled information about the targets")]
     Csv(Csv),
+    #[clap(about = "Print a table of the connections between the targets")]
+    Summary(Summary),
     #[clap(
         about = "Show cheatsheets to gather information about targets to use with this program"
     )]
     Cheatsheet(Cheatsheet),
+    #[clap(about = "Generate one graph per time window of a series of captures")]
+    Timeline(Timeline),
+    #[clap(about = "Run the agents server without the GUI")]
+    Serve(Serve),
+    #[clap(
+        about = "Graph or export the sockets of a single host, from the output of ss or netstat read on stdin"
+    )]
+    Quick(Quick),
+    #[cfg(feature = "ssh")]
+    #[clap(about = "Run the capture commands on a host over SSH, and write the capture files")]
+    CollectSsh(CollectSsh),
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    #[clap(about = "Capture the sockets of this machine, and write the capture files")]
+    Capture(Capture),
 }
 
 #[derive(Parser)]
 pub struct Graph {
-    #[clap(long = "no-loopback", help = "Do not display loopback connections")]
+    #[clap(
+        long = "loopback",
+        help = "How to draw the connections between the processes of a same host: show them, hide them, or collapse them into a single node per host (show, hide or collapse)"
+    )]
+    loopback: Option<LoopbackRendering>,
+    #[clap(
+        long = "no-loopback",
+        conflicts_with = "loopback",
+        help = "Deprecated, same as --loopback hide"
+    )]
     no_loopback: bool,
     #[clap(
         long = "vertical",
@@ -53,25 +88,151 @@ pub struct Graph {
     transparent_bg: bool,
     #[clap(long = "hide-legend", help = "Hide the legend")]
     hide_legend: bool,
+    #[clap(
+        long = "legend-caption",
+        help = "Line shown in the legend, such as the date of the capture",
+        conflicts_with = "hide-legend"
+    )]
+    legend_caption: Option<String>,
+    #[clap(
+        long = "summarize-listeners",
+        help = "Draw a single node per host listing its listening ports, instead of a node per listening process"
+    )]
+    summarize_listeners: bool,
+    #[clap(
+        long = "host-level",
+        help = "Draw a node per host and a single edge per pair of hosts, labeled with the number of connections and their destination ports, instead of the processes"
+    )]
+    host_level: bool,
     #[clap(long = "dump", help = "Dump dot code to file")]
     dump: Option<std::path::PathBuf>,
-    #[clap(help = "Graph output file (extension will be passed to Graphviz")]
+    #[clap(
+        help = "Graph output file (extension will be passed to Graphviz, except for .dot and .gv files which get the DOT code, or - for stdout)"
+    )]
     output_file: std::path::PathBuf,
-    #[clap(help = "Directory containing the files for the hosts to include in the analysis")]
-    files_directory: std::path::PathBuf,
+    #[clap(
+        required = true,
+        min_values = 1,
+        help = "Directories containing the files for the hosts to include in the analysis"
+    )]
+    files_directories: Vec<std::path::PathBuf>,
+    #[clap(
+        long = "on-duplicate",
+        default_value = "merge",
+        help = "What to do with the hosts found in several directories: merge them, suffix their names with the directory name, or fail (merge, suffix or error)"
+    )]
+    on_duplicate: OnDuplicate,
+    #[clap(
+        long = "no-content-detection",
+        help = "Skip the files whose extension is unknown, instead of detecting their type from their contents"
+    )]
+    no_content_detection: bool,
+    #[clap(
+        long = "cache",
+        help = "Directory where the parsed hosts are saved, and loaded on the next runs instead of parsing the capture files again as long as they do not change"
+    )]
+    cache: Option<std::path::PathBuf>,
     #[clap(
         long = "dpi",
         help = "DPI value for the graph (DPI other than 96 may give strange results for SVG output)"
     )]
     dpi: Option<f64>,
     #[clap(
+        long = "layout-engine",
         help = "Layout engine to use (dot, neato, fdp, sfdp, circo, twopi, osage or patchwork)"
     )]
     layout_engine: Option<LayoutEngine>,
+    #[clap(
+        long = "overview",
+        help = "Also write a downscaled overview of the graph to <name>_overview.<ext>, given as <corner>[:<scale>] such as bottom-right:0.15. SVG graphs also get it as an inset in this corner"
+    )]
+    overview: Option<OverviewOptions>,
+    #[clap(
+        long = "layout-hints",
+        help = "TOML file giving the rank group (dot) or the pinned position (neato, fdp) of hosts, such as [hosts.web01] rank_group = \"dmz\" or pos = \"0,300\""
+    )]
+    layout_hints: Option<std::path::PathBuf>,
+    #[clap(
+        long = "rank-group",
+        parse(try_from_str = parse_rank_group),
+        help = "Draw these hosts on the same rank with dot, given as <group>=<host>,<host> such as dmz=web01,web02 (can be repeated)"
+    )]
+    rank_groups: Vec<(String, Vec<String>)>,
+    #[clap(
+        long = "focus",
+        help = "Only draw this host, the hosts it is connected to and their connections (can be repeated)"
+    )]
+    focus: Vec<String>,
+    #[clap(
+        long = "focus-depth",
+        default_value = "1",
+        requires = "focus",
+        help = "Number of hops from the --focus hosts within which the hosts are drawn"
+    )]
+    focus_depth: usize,
+    #[clap(
+        long = "proxy",
+        help = "Name of a proxy process such as stunnel, whose clients are also drawn connected to the processes it connects to (can be repeated)"
+    )]
+    proxies: Vec<String>,
+    #[clap(
+        long = "collapse-docker-proxy",
+        help = "Connect the clients of docker-proxy to the processes of the containers it forwards them to, instead of drawing both connections"
+    )]
+    collapse_docker_proxy: bool,
+    #[clap(
+        long = "infer-clients",
+        help = "Infer the clients of the connections captured only on the listening side, so that they are drawn even if the clients were not captured. The clients which were not captured are named after their IP address"
+    )]
+    infer_clients: bool,
+    #[clap(
+        long = "keep-both-directions",
+        help = "Keep both connections of the flows seen from both ends, such as UDP peers listening on the same port, instead of collapsing them into a single bidirectional connection"
+    )]
+    keep_both_directions: bool,
+    #[clap(
+        long = "show-unmatched",
+        help = "Also draw the connections to captured hosts on which no matching listener was found, as dashed edges to a '?' node in the peer host"
+    )]
+    show_unmatched: bool,
+    #[clap(
+        long = "watch",
+        help = "Regenerate the graph each time a capture file is added or modified, until Ctrl-C"
+    )]
+    watch: bool,
+    #[clap(
+        long = "report",
+        help = "Write a JSON report of the run to this file (hosts parsed and failed, connections, output files)"
+    )]
+    report: Option<std::path::PathBuf>,
+    #[clap(
+        long = "strict",
+        help = "Fail if the files of a host cannot be parsed, instead of leaving it out, or if the rendering options give a cropped or slow graph"
+    )]
+    strict: bool,
+    #[clap(
+        long = "stats",
+        help = "Print the number of lines of the network files parsed and skipped for each host, and the sockets found"
+    )]
+    stats: bool,
+    #[clap(
+        long = "no-provenance",
+        help = "Do not embed the version, the time, the digests of the directories and the options of the run in the output files"
+    )]
+    no_provenance: bool,
+    #[clap(flatten)]
+    filters: Filters,
+    #[clap(flatten)]
+    anonymization: Anonymization,
 }
 
 impl Graph {
-    /// Get a reference to the graph's no loopback.
+    /// Get the graph's loopback rendering, `--no-loopback` standing for `--loopback hide`
+    pub fn loopback(&self) -> LoopbackRendering {
+        loopback_rendering(self.loopback, self.no_loopback)
+    }
+
+    /// Get the graph's deprecated no loopback.
     pub fn no_loopback(&self) -> bool {
         self.no_loopback
     }
@@ -91,9 +252,39 @@ impl Graph {
         &self.output_file
     }
 
-    /// Get a reference to the graph's files directory.
-    pub fn files_directory(&self) -> &std::path::PathBuf {
-        &self.files_directory
+    /// Get a reference to the graph's files directories, without the deprecated positional layout
+    /// engine.
+    pub fn files_directories(&self) -> &[std::path::PathBuf] {
+        match self.positional_layout_engine() {
+            Some(_) => &self.files_directories[..self.files_directories.len() - 1],
+            None => &self.files_directories,
+        }
+    }
+
+    /// Get the layout engine given after the directories, as the previous versions took it. The
+    /// last argument is taken as such if it is a layout engine name and not a directory.
+    pub fn positional_layout_engine(&self) -> Option<LayoutEngine> {
+        match self.files_directories.as_slice() {
+            [_, .., last] if !last.is_dir() => last.to_str()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Get the graph's policy for the hosts found in several directories.
+    pub fn on_duplicate(&self) -> OnDuplicate {
+        self.on_duplicate
+    }
+
+    /// Get the graph's directory scan options.
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            detect_contents: !self.no_content_detection,
+        }
+    }
+
+    /// Get the graph's directory of the parsed hosts cache.
+    pub fn cache(&self) -> Option<&std::path::Path> {
+        self.cache.as_deref()
     }
 
     /// Get a reference to the graph's transparent background setting.
@@ -106,29 +297,274 @@ impl Graph {
         self.hide_legend
     }
 
+    /// Get a reference to the graph's legend caption.
+    pub fn legend_caption(&self) -> Option<&str> {
+        self.legend_caption.as_deref()
+    }
+
+    /// Get the graph's listener rendering, a summary per host with `--summarize-listeners`
+    pub fn listener_rendering(&self) -> ListenerRendering {
+        match self.summarize_listeners {
+            true => ListenerRendering::Summary,
+            false => ListenerRendering::PerProcess,
+        }
+    }
+
+    /// Get the graph's granularity, only the hosts with `--host-level`
+    pub fn granularity(&self) -> GraphGranularity {
+        match self.host_level {
+            true => GraphGranularity::Host,
+            false => GraphGranularity::Process,
+        }
+    }
+
     // / Get a reference to the graph's dpi setting.
     pub fn dpi(&self) -> Option<f64> {
         self.dpi
     }
 
-    // / Get a reference to the graph's layout engine setting.
-    pub fn layout_engine(&self) -> Option<&LayoutEngine> {
-        self.layout_engine.as_ref()
+    /// Get the graph's layout engine setting, `--layout-engine` taking precedence over the
+    /// deprecated positional one.
+    pub fn layout_engine(&self) -> Option<LayoutEngine> {
+        self.layout_engine
+            .clone()
+            .or_else(|| self.positional_layout_engine())
+    }
+
+    /// Get a reference to the graph's overview options.
+    pub fn overview(&self) -> Option<&OverviewOptions> {
+        self.overview.as_ref()
+    }
+
+    /// Get a reference to the graph's layout hints file.
+    pub fn layout_hints(&self) -> Option<&std::path::Path> {
+        self.layout_hints.as_deref()
+    }
+
+    /// Get a reference to the graph's rank groups, with their hosts.
+    pub fn rank_groups(&self) -> &[(String, Vec<String>)] {
+        &self.rank_groups
+    }
+
+    /// Get a reference to the graph's focus hosts.
+    pub fn focus(&self) -> &[String] {
+        &self.focus
+    }
+
+    /// Get the graph's focus depth.
+    pub fn focus_depth(&self) -> usize {
+        self.focus_depth
+    }
+
+    /// Get a reference to the graph's proxy processes.
+    pub fn proxies(&self) -> &[String] {
+        &self.proxies
+    }
+
+    /// Get whether the graph draws the connections without a matching listener.
+    pub fn show_unmatched(&self) -> bool {
+        self.show_unmatched
+    }
+
+    /// Get the graph's connections options.
+    pub fn connections_options(&self) -> ConnectionsOptions {
+        ConnectionsOptions {
+            no_loopback: self.loopback() == LoopbackRendering::Hide,
+            collapse_docker_proxy: self.collapse_docker_proxy,
+            infer_clients_from_server_side: self.infer_clients,
+            keep_both_directions: self.keep_both_directions,
+        }
+    }
+
+    /// Get the graph's watch setting.
+    pub fn watch(&self) -> bool {
+        self.watch
+    }
+
+    /// Get a reference to the graph's filters.
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    /// Get a reference to the graph's anonymization settings.
+    pub fn anonymization(&self) -> &Anonymization {
+        &self.anonymization
+    }
+
+    /// Get a reference to the graph's report file.
+    pub fn report(&self) -> Option<&std::path::Path> {
+        self.report.as_deref()
+    }
+
+    /// Get the graph's strict setting.
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Get the graph's stats setting.
+    pub fn stats(&self) -> bool {
+        self.stats
+    }
+
+    /// Get the provenance to embed in the graph, unless `--no-provenance` is given
+    pub fn provenance(&self) -> Option<Provenance> {
+        provenance(
+            !self.no_provenance,
+            &self.anonymization,
+            &self.files_directories,
+        )
     }
 }
 
 #[derive(Parser)]
 pub struct Csv {
+    #[clap(long = "no-loopback", help = "Do not export loopback connections")]
+    no_loopback: bool,
+    #[clap(
+        long = "delimiter",
+        default_value = ",",
+        parse(try_from_str = parse_delimiter),
+        help = "Field delimiter of the CSV file, such as ';' for some spreadsheet locales"
+    )]
+    delimiter: u8,
+    #[clap(
+        long = "columns",
+        use_value_delimiter = true,
+        help = "Comma-separated columns to export, in this order (src_host, dst_host, src_process, dst_process, src_pid, dst_pid, src_socket, dst_socket, protocol, src_ip, src_port, dst_ip, dst_port, dst_bind_address, ipv6_only, count, first_seen, last_seen, dst_name, direction, server_side, row_type). The first nine are exported by default"
+    )]
+    columns: Vec<CsvColumn>,
+    #[clap(long = "no-headers", help = "Do not write the headers of the columns")]
+    no_headers: bool,
+    #[clap(
+        long = "routes",
+        help = "Also write the captured routes of the hosts to this CSV file (host, destination, gateway, interface)"
+    )]
+    routes: Option<std::path::PathBuf>,
+    #[clap(
+        long = "collapse-docker-proxy",
+        help = "Connect the clients of docker-proxy to the processes of the containers it forwards them to, instead of exporting both connections"
+    )]
+    collapse_docker_proxy: bool,
+    #[clap(
+        long = "infer-clients",
+        help = "Infer the clients of the connections captured only on the listening side, so that they are exported even if the clients were not captured. The clients which were not captured are named after their IP address"
+    )]
+    infer_clients: bool,
+    #[clap(
+        long = "keep-both-directions",
+        help = "Keep both connections of the flows seen from both ends, such as UDP peers listening on the same port, instead of collapsing them into a single bidirectional connection"
+    )]
+    keep_both_directions: bool,
+    #[clap(
+        long = "unmatched",
+        help = "Also export the connections to captured hosts on which no matching listener was found, with unmatched_known_peer in the row_type column, which is added if missing"
+    )]
+    unmatched: bool,
     #[clap(help = "CSV output file")]
     output_file: std::path::PathBuf,
-    #[clap(help = "Directory containing the files for the hosts to include in the analysis")]
-    files_directory: std::path::PathBuf,
+    #[clap(
+        required = true,
+        min_values = 1,
+        help = "Directories containing the files for the hosts to include in the analysis"
+    )]
+    files_directories: Vec<std::path::PathBuf>,
+    #[clap(
+        long = "on-duplicate",
+        default_value = "merge",
+        help = "What to do with the hosts found in several directories: merge them, suffix their names with the directory name, or fail (merge, suffix or error)"
+    )]
+    on_duplicate: OnDuplicate,
+    #[clap(
+        long = "no-content-detection",
+        help = "Skip the files whose extension is unknown, instead of detecting their type from their contents"
+    )]
+    no_content_detection: bool,
+    #[clap(
+        long = "cache",
+        help = "Directory where the parsed hosts are saved, and loaded on the next runs instead of parsing the capture files again as long as they do not change"
+    )]
+    cache: Option<std::path::PathBuf>,
+    #[clap(
+        long = "report",
+        help = "Write a JSON report of the run to this file (hosts parsed and failed, connections, output files)"
+    )]
+    report: Option<std::path::PathBuf>,
+    #[clap(
+        long = "strict",
+        help = "Fail if the files of a host cannot be parsed, instead of leaving it out"
+    )]
+    strict: bool,
+    #[clap(
+        long = "stats",
+        help = "Print the number of lines of the network files parsed and skipped for each host, and the sockets found"
+    )]
+    stats: bool,
+    #[clap(
+        long = "provenance",
+        help = "Write the version, the time, the digests of the directories and the options of the run as comments after the rows"
+    )]
+    provenance: bool,
+    #[clap(flatten)]
+    filters: Filters,
+    #[clap(flatten)]
+    anonymization: Anonymization,
 }
 
 impl Csv {
-    /// Get a reference to the csv's files directory.
-    pub fn files_directory(&self) -> &std::path::PathBuf {
-        &self.files_directory
+    /// Get the csv's connections options.
+    pub fn connections_options(&self) -> ConnectionsOptions {
+        ConnectionsOptions {
+            no_loopback: self.no_loopback,
+            collapse_docker_proxy: self.collapse_docker_proxy,
+            infer_clients_from_server_side: self.infer_clients,
+            keep_both_directions: self.keep_both_directions,
+        }
+    }
+
+    /// Get the csv's layout, with the default columns if none are given, and the row type first
+    /// when the unmatched connections are exported
+    pub fn csv_options(&self) -> CsvOptions {
+        let default = CsvOptions::default();
+        let mut columns = match self.columns.is_empty() {
+            true => default.columns.clone(),
+            false => self.columns.clone(),
+        };
+        if self.unmatched && !columns.contains(&CsvColumn::RowType) {
+            columns.insert(0, CsvColumn::RowType);
+        }
+        CsvOptions {
+            columns,
+            delimiter: self.delimiter,
+            headers: !self.no_headers,
+            provenance: provenance(
+                self.provenance,
+                &self.anonymization,
+                &self.files_directories,
+            ),
+            ..default
+        }
+    }
+
+    /// Get a reference to the csv's files directories.
+    pub fn files_directories(&self) -> &[std::path::PathBuf] {
+        &self.files_directories
+    }
+
+    /// Get the csv's policy for the hosts found in several directories.
+    pub fn on_duplicate(&self) -> OnDuplicate {
+        self.on_duplicate
+    }
+
+    /// Get the csv's directory scan options.
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            detect_contents: !self.no_content_detection,
+        }
+    }
+
+    /// Get the csv's directory of the parsed hosts cache.
+    pub fn cache(&self) -> Option<&std::path::Path> {
+        self.cache.as_deref()
     }
 
     /// Get a reference to the csv's output file.
@@ -136,6 +572,194 @@ impl Csv {
     pub fn output_file(&self) -> &std::path::PathBuf {
         &self.output_file
     }
+
+    /// Get whether the csv exports the connections without a matching listener.
+    pub fn unmatched(&self) -> bool {
+        self.unmatched
+    }
+
+    /// Get a reference to the csv's routes output file.
+    pub fn routes(&self) -> Option<&std::path::Path> {
+        self.routes.as_deref()
+    }
+
+    /// Get a reference to the csv's filters.
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    /// Get a reference to the csv's anonymization settings.
+    pub fn anonymization(&self) -> &Anonymization {
+        &self.anonymization
+    }
+
+    /// Get a reference to the csv's report file.
+    pub fn report(&self) -> Option<&std::path::Path> {
+        self.report.as_deref()
+    }
+
+    /// Get the csv's strict setting.
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Get the csv's stats setting.
+    pub fn stats(&self) -> bool {
+        self.stats
+    }
+}
+
+/// Processes and ports filters, applied to the hosts before building the connections
+#[derive(Args)]
+pub struct Filters {
+    #[clap(
+        long = "exclude-process",
+        help = "Exclude the processes whose name starts with this prefix (can be repeated)"
+    )]
+    exclude_process: Vec<String>,
+    #[clap(
+        long = "only-process",
+        help = "Only keep the processes whose name starts with this prefix or regex (can be repeated)"
+    )]
+    only_process: Vec<ProcessPattern>,
+    #[clap(
+        long = "exclude-port",
+        parse(try_from_str = parse_port_range),
+        help = "Exclude the sockets using this port or port range, such as 8000-8100 (can be repeated)"
+    )]
+    exclude_port: Vec<RangeInclusive<u16>>,
+    #[clap(
+        long = "only-port",
+        parse(try_from_str = parse_port_range),
+        help = "Only keep the sockets using this port or port range (can be repeated)"
+    )]
+    only_port: Vec<RangeInclusive<u16>>,
+}
+
+impl Filters {
+    /// Get a reference to the filters's excluded processes prefixes.
+    pub fn exclude_process(&self) -> &[String] {
+        &self.exclude_process
+    }
+
+    /// Get a reference to the filters's kept processes patterns.
+    pub fn only_process(&self) -> &[ProcessPattern] {
+        &self.only_process
+    }
+
+    /// Get a reference to the filters's excluded port ranges.
+    pub fn exclude_port(&self) -> &[RangeInclusive<u16>] {
+        &self.exclude_port
+    }
+
+    /// Get a reference to the filters's kept port ranges.
+    pub fn only_port(&self) -> &[RangeInclusive<u16>] {
+        &self.only_port
+    }
+}
+
+/// Anonymization of the hosts, applied once they are filtered
+#[derive(Args)]
+pub struct Anonymization {
+    #[clap(
+        long = "anonymize",
+        help = "Replace the host names, IP addresses and process names with generic ones (host-01, 10.0.0.1, proc-01...), to share the outputs"
+    )]
+    anonymize: bool,
+    #[clap(
+        long = "mapping-out",
+        requires = "anonymize",
+        help = "Write the mapping from the generic names and addresses to the real ones to this JSON file"
+    )]
+    mapping_out: Option<std::path::PathBuf>,
+}
+
+impl Anonymization {
+    /// Get the anonymization's anonymize setting.
+    pub fn anonymize(&self) -> bool {
+        self.anonymize
+    }
+
+    /// Get a reference to the anonymization's mapping output file.
+    pub fn mapping_out(&self) -> Option<&std::path::Path> {
+        self.mapping_out.as_deref()
+    }
+}
+
+/// Parse a CSV delimiter, which must be a single ASCII character
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => Err(format!(
+            "invalid delimiter {s:?}: expected a single ASCII character"
+        )),
+    }
+}
+
+/// Parse a port, or a range of ports such as `8000-8100`
+fn parse_port_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let start: u16 = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid port: {e}"))?;
+    let end: u16 = end
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid port: {e}"))?;
+    if start > end {
+        return Err(format!("invalid port range: {start} is greater than {end}"));
+    }
+    Ok(start..=end)
+}
+
+/// Parse a rank group and its hosts, such as `dmz=web01,web02`
+fn parse_rank_group(s: &str) -> Result<(String, Vec<String>), String> {
+    let (rank_group, hosts) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid rank group {s:?}: expected <group>=<host>,<host>"))?;
+    let hosts: Vec<String> = hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(String::from)
+        .collect();
+    if rank_group.trim().is_empty() || hosts.is_empty() {
+        return Err(format!(
+            "invalid rank group {s:?}: expected <group>=<host>,<host>"
+        ));
+    }
+    Ok((rank_group.trim().to_string(), hosts))
+}
+
+#[derive(Parser)]
+pub struct Summary {
+    #[clap(long = "no-loopback", help = "Do not display loopback connections")]
+    no_loopback: bool,
+    #[clap(
+        long = "group-by",
+        help = "Count the connections by host, process or port instead of listing them"
+    )]
+    group_by: Option<GroupBy>,
+    #[clap(help = "Directory containing the files for the hosts to include in the analysis")]
+    files_directory: std::path::PathBuf,
+}
+
+impl Summary {
+    /// Get a reference to the summary's no loopback.
+    pub fn no_loopback(&self) -> bool {
+        self.no_loopback
+    }
+
+    /// Get the summary's grouping.
+    pub fn group_by(&self) -> Option<GroupBy> {
+        self.group_by
+    }
+
+    /// Get a reference to the summary's files directory.
+    pub fn files_directory(&self) -> &std::path::PathBuf {
+        &self.files_directory
+    }
 }
 
 #[derive(Parser)]
@@ -166,3 +790,525 @@ pub enum CheatsheetSubcommand {
     #[clap(about = "Show how to make captures for all types of hosts, in a pager")]
     All,
 }
+
+#[derive(Parser)]
+pub struct Timeline {
+    #[clap(
+        long = "loopback",
+        help = "How to draw the connections between the processes of a same host (show, hide or collapse)"
+    )]
+    loopback: Option<LoopbackRendering>,
+    #[clap(
+        long = "no-loopback",
+        conflicts_with = "loopback",
+        help = "Deprecated, same as --loopback hide"
+    )]
+    no_loopback: bool,
+    #[clap(
+        long = "window",
+        default_value = "60s",
+        parse(try_from_str = parse_window),
+        help = "Duration of each graph, in seconds or with a s, m or h suffix"
+    )]
+    window: std::time::Duration,
+    #[clap(
+        long = "format",
+        default_value = "png",
+        help = "Extension of the graphs, passed to Graphviz (dot writes the DOT code without Graphviz)"
+    )]
+    format: String,
+    #[clap(
+        long = "collapse-docker-proxy",
+        help = "Connect the clients of docker-proxy to the processes of the containers it forwards them to, instead of drawing both connections"
+    )]
+    collapse_docker_proxy: bool,
+    #[clap(
+        long = "infer-clients",
+        help = "Infer the clients of the connections captured only on the listening side, so that they are drawn even if the clients were not captured. The clients which were not captured are named after their IP address"
+    )]
+    infer_clients: bool,
+    #[clap(
+        long = "keep-both-directions",
+        help = "Keep both connections of the flows seen from both ends, such as UDP peers listening on the same port, instead of collapsing them into a single bidirectional connection"
+    )]
+    keep_both_directions: bool,
+    #[clap(help = "Directory to write the graphs and their index.csv to")]
+    output_dir: std::path::PathBuf,
+    #[clap(
+        help = "Directory containing one subdirectory of capture files per capture, made at the time the newest file was written"
+    )]
+    files_directory: std::path::PathBuf,
+}
+
+impl Timeline {
+    /// Get the timeline's loopback rendering, `--no-loopback` standing for `--loopback hide`
+    pub fn loopback(&self) -> LoopbackRendering {
+        loopback_rendering(self.loopback, self.no_loopback)
+    }
+
+    /// Get the timeline's deprecated no loopback.
+    pub fn no_loopback(&self) -> bool {
+        self.no_loopback
+    }
+
+    /// Get the timeline's connections options.
+    pub fn connections_options(&self) -> ConnectionsOptions {
+        ConnectionsOptions {
+            no_loopback: self.loopback() == LoopbackRendering::Hide,
+            collapse_docker_proxy: self.collapse_docker_proxy,
+            infer_clients_from_server_side: self.infer_clients,
+            keep_both_directions: self.keep_both_directions,
+        }
+    }
+
+    /// Get the timeline's window.
+    pub fn window(&self) -> std::time::Duration {
+        self.window
+    }
+
+    /// Get a reference to the timeline's graphs format.
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    /// Get a reference to the timeline's output directory.
+    pub fn output_dir(&self) -> &std::path::PathBuf {
+        &self.output_dir
+    }
+
+    /// Get a reference to the timeline's files directory.
+    pub fn files_directory(&self) -> &std::path::PathBuf {
+        &self.files_directory
+    }
+}
+
+#[derive(Parser)]
+pub struct Quick {
+    #[clap(long = "host", help = "Name of the host")]
+    host: String,
+    #[clap(
+        long = "ip",
+        alias = "ips",
+        multiple_occurrences = true,
+        help = "IP addresses of the host, separated with spaces or commas (can be repeated)"
+    )]
+    ips: Vec<String>,
+    #[clap(
+        long = "ip-file",
+        help = "File listing the IP addresses of the host, separated with spaces, commas or new lines"
+    )]
+    ip_file: Option<std::path::PathBuf>,
+    #[clap(
+        long = "format",
+        help = "Output format: csv, dot, or an extension passed to Graphviz such as png (by default, the extension of --out, or dot)"
+    )]
+    format: Option<String>,
+    #[clap(
+        long = "out",
+        default_value = "-",
+        help = "Output file, or - for stdout (only with the csv and dot formats)"
+    )]
+    out: std::path::PathBuf,
+    #[clap(
+        long = "summarize-listeners",
+        help = "Draw a single node listing the listening ports, instead of a node per listening process"
+    )]
+    summarize_listeners: bool,
+}
+
+impl Quick {
+    /// Get a reference to the quick's host name.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Get a reference to the quick's IP addresses lists.
+    pub fn ips(&self) -> &[String] {
+        &self.ips
+    }
+
+    /// Get a reference to the quick's IP addresses file.
+    pub fn ip_file(&self) -> Option<&std::path::Path> {
+        self.ip_file.as_deref()
+    }
+
+    /// Get the quick's output format, from `--format` or from the extension of the output file
+    pub fn format(&self) -> String {
+        match &self.format {
+            Some(format) => format.to_lowercase(),
+            None => self
+                .out
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| "dot".to_string()),
+        }
+    }
+
+    /// Get a reference to the quick's output file, `None` for stdout.
+    pub fn out(&self) -> Option<&std::path::Path> {
+        (self.out.as_os_str() != "-").then_some(self.out.as_path())
+    }
+
+    /// Get the quick's listener rendering, a summary with `--summarize-listeners`
+    pub fn listener_rendering(&self) -> ListenerRendering {
+        match self.summarize_listeners {
+            true => ListenerRendering::Summary,
+            false => ListenerRendering::PerProcess,
+        }
+    }
+}
+
+/// Provenance of the files generated from `files_directories` with the arguments of this run, if
+/// `embed`, and `None` for anonymized files, since it names the real directories
+fn provenance(
+    embed: bool,
+    anonymization: &Anonymization,
+    files_directories: &[std::path::PathBuf],
+) -> Option<Provenance> {
+    match embed && !anonymization.anonymize() {
+        false => None,
+        true => Some(Provenance::collect(
+            concat!("sockets_map ", clap::crate_version!()),
+            files_directories,
+            &[],
+            &std::env::args().skip(1).collect::<Vec<_>>(),
+        )),
+    }
+}
+
+/// Loopback rendering of the `--loopback` option, or of the deprecated `--no-loopback` flag
+fn loopback_rendering(loopback: Option<LoopbackRendering>, no_loopback: bool) -> LoopbackRendering {
+    match no_loopback {
+        true => LoopbackRendering::Hide,
+        false => loopback.unwrap_or_default(),
+    }
+}
+
+/// Parse a duration such as `90`, `90s`, `5m` or `1h`
+fn parse_window(s: &str) -> Result<std::time::Duration, String> {
+    let (number, unit) = match s.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &s[number.len()..]),
+        None => (s, "s"),
+    };
+    let seconds = parse_seconds(number)?
+        * match unit {
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => 1.0,
+        };
+    Ok(std::time::Duration::from_secs_f64(seconds))
+}
+
+#[derive(Parser)]
+pub struct Serve {
+    #[clap(
+        long = "address",
+        default_value = "0.0.0.0",
+        help = "The address the server will listen on"
+    )]
+    address: String,
+    #[clap(
+        long = "port",
+        default_value_t = sockets_map::server::DEFAULT_PORT,
+        help = "The TCP port the server will listen on"
+    )]
+    port: u16,
+    #[clap(
+        long = "http-port",
+        help = "Also serve a read-only HTTP status endpoint on this port"
+    )]
+    http_port: Option<u16>,
+    #[clap(
+        long = "max-updates",
+        default_value_t = sockets_map::server::DEFAULT_MAX_UPDATES_PER_CLIENT,
+        help = "Number of updates kept for each client, the oldest ones being dropped first"
+    )]
+    max_updates: usize,
+    #[clap(
+        long = "max-update-bytes",
+        default_value_t = sockets_map::server::DEFAULT_MAX_UPDATE_BYTES,
+        help = "Size in bytes of the largest update accepted from a client, the larger ones being rejected"
+    )]
+    max_update_bytes: u64,
+    #[clap(
+        long = "max-clients",
+        default_value_t = sockets_map::server::DEFAULT_MAX_CLIENTS,
+        help = "Number of clients registered at once, the next registrations being refused"
+    )]
+    max_clients: usize,
+    #[clap(
+        long = "verify-source-ip",
+        help = "Flag the clients which do not connect from one of the IPs they register with"
+    )]
+    verify_source_ip: bool,
+    #[clap(
+        long = "verify-exempt",
+        requires = "verify-source-ip",
+        help = "Address the clients may connect from without being flagged, such as a NAT gateway (can be repeated)"
+    )]
+    verify_exempt: Vec<std::net::IpAddr>,
+    #[clap(
+        long = "update-on-connect",
+        help = "Request an update from each client when it registers"
+    )]
+    update_on_connect: bool,
+    #[clap(
+        long = "record",
+        parse(try_from_str = parse_seconds),
+        help = "Make the clients record their sockets every <RECORD> seconds, from the first registration until Ctrl-C or the end of --duration"
+    )]
+    record: Option<f64>,
+    #[clap(
+        long = "duration",
+        requires = "record",
+        parse(try_from_str = parse_seconds),
+        help = "Stop the recording and the server after this number of seconds"
+    )]
+    duration: Option<f64>,
+    #[clap(
+        long = "output-dir",
+        help = "Periodically write the hosts of the clients as CSV capture files in this directory, and at exit"
+    )]
+    output_dir: Option<std::path::PathBuf>,
+    #[clap(
+        long = "dump-interval",
+        default_value_t = 10.0,
+        parse(try_from_str = parse_seconds),
+        help = "Number of seconds between two writes of the capture files in --output-dir"
+    )]
+    dump_interval: f64,
+    #[clap(
+        long = "graph",
+        help = "Generate a graph of the clients at exit (extension will be passed to Graphviz)"
+    )]
+    graph: Option<std::path::PathBuf>,
+    #[clap(
+        long = "event-log",
+        help = "Write the events of the session (registrations, updates, errors...) to this file at exit, as CSV if its extension is csv and as JSON otherwise"
+    )]
+    event_log: Option<std::path::PathBuf>,
+}
+
+impl Serve {
+    /// Get a reference to the serve's listen address.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get the serve's listen port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Get the serve's HTTP status endpoint port.
+    pub fn http_port(&self) -> Option<u16> {
+        self.http_port
+    }
+
+    /// Get the serve's number of updates kept for each client.
+    pub fn max_updates(&self) -> usize {
+        self.max_updates
+    }
+
+    /// Get the serve's size of the largest update accepted.
+    pub fn max_update_bytes(&self) -> u64 {
+        self.max_update_bytes
+    }
+
+    /// Get the serve's number of clients registered at once.
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+
+    /// Get the serve's source IP verification setting.
+    pub fn verify_source_ip(&self) -> bool {
+        self.verify_source_ip
+    }
+
+    /// Get a reference to the serve's addresses exempt from the source IP verification.
+    pub fn verify_exempt(&self) -> &[std::net::IpAddr] {
+        &self.verify_exempt
+    }
+
+    /// Get the serve's update on connect setting.
+    pub fn update_on_connect(&self) -> bool {
+        self.update_on_connect
+    }
+
+    /// Get the serve's recording interval.
+    pub fn record(&self) -> Option<f64> {
+        self.record
+    }
+
+    /// Get the serve's recording duration.
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    /// Get a reference to the serve's output directory.
+    pub fn output_dir(&self) -> Option<&std::path::PathBuf> {
+        self.output_dir.as_ref()
+    }
+
+    /// Get the serve's interval between two writes of the capture files.
+    pub fn dump_interval(&self) -> f64 {
+        self.dump_interval
+    }
+
+    /// Get a reference to the serve's graph output file.
+    pub fn graph(&self) -> Option<&std::path::PathBuf> {
+        self.graph.as_ref()
+    }
+
+    /// Get a reference to the serve's event log file.
+    pub fn event_log(&self) -> Option<&std::path::PathBuf> {
+        self.event_log.as_ref()
+    }
+}
+
+/// Parse a strictly positive number of seconds
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(seconds),
+        Ok(_) => Err("must be a positive number of seconds".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(feature = "ssh")]
+#[derive(Parser)]
+pub struct CollectSsh {
+    #[clap(
+        long = "host",
+        help = "SSH destination, such as user@192.168.1.10 (the host key must already be known)"
+    )]
+    host: String,
+    #[clap(long = "out", help = "Directory to write the capture files to")]
+    out: std::path::PathBuf,
+    #[clap(long = "windows", help = "The host runs Windows instead of Linux")]
+    windows: bool,
+    #[clap(
+        long = "pretty-name",
+        help = "Name of the host in the capture files, instead of its hostname"
+    )]
+    pretty_name: Option<String>,
+}
+
+#[cfg(feature = "ssh")]
+impl CollectSsh {
+    /// Get a reference to the collect ssh's host.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Get a reference to the collect ssh's output directory.
+    pub fn out(&self) -> &std::path::PathBuf {
+        &self.out
+    }
+
+    /// Get the collect ssh's windows setting.
+    pub fn windows(&self) -> bool {
+        self.windows
+    }
+
+    /// Get a reference to the collect ssh's pretty name.
+    pub fn pretty_name(&self) -> Option<&String> {
+        self.pretty_name.as_ref()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[derive(Parser)]
+pub struct Capture {
+    #[clap(help = "Directory to write the capture files to")]
+    output_dir: std::path::PathBuf,
+    #[clap(
+        long = "pretty-name",
+        help = "Name of the host in the capture files, instead of its hostname"
+    )]
+    pretty_name: Option<String>,
+    #[clap(
+        long = "count",
+        default_value = "1",
+        help = "Number of captures to make, appended to the same files"
+    )]
+    count: u32,
+    #[clap(
+        long = "interval",
+        default_value = "1",
+        parse(try_from_str = parse_seconds),
+        help = "Interval, in seconds, between two captures"
+    )]
+    interval: f64,
+    #[clap(
+        short = 'n',
+        long = "no-root",
+        help = "Run without root privileges (not all processes will be shown!)"
+    )]
+    no_root: bool,
+    #[clap(
+        long = "exclude-interface",
+        help = "Leave out the IP addresses of the interfaces whose name matches this pattern, with * and ? wildcards, such as \"docker*\" (can be repeated)"
+    )]
+    exclude_interface: Vec<String>,
+    #[clap(
+        long = "extra-ip",
+        help = "Add this IP address to the ones of the host, such as the public address of a NAT gateway (can be repeated)"
+    )]
+    extra_ip: Vec<std::net::IpAddr>,
+    #[clap(
+        long = "keep-virtual-ips",
+        help = "Keep the link-local addresses and the ones of the default Docker bridge (172.17.0.0/16), which are left out by default"
+    )]
+    keep_virtual_ips: bool,
+    #[clap(
+        long = "legacy-files",
+        help = "Write one file per command output, such as <host>.ss and <host>.linux_ip, instead of a single <host>.smap.json bundle"
+    )]
+    legacy_files: bool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+impl Capture {
+    /// Get a reference to the capture's output directory.
+    pub fn output_dir(&self) -> &std::path::PathBuf {
+        &self.output_dir
+    }
+
+    /// Get a reference to the capture's pretty name.
+    pub fn pretty_name(&self) -> Option<&str> {
+        self.pretty_name.as_deref()
+    }
+
+    /// Get the capture's count.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Get the capture's interval, in seconds.
+    pub fn interval(&self) -> f64 {
+        self.interval
+    }
+
+    /// Get the capture's no root setting.
+    pub fn no_root(&self) -> bool {
+        self.no_root
+    }
+
+    /// Get the capture's selection of the local IP addresses.
+    pub fn ip_filter(&self) -> sockets_map::collect::IpFilter {
+        sockets_map::collect::IpFilter {
+            exclude_interfaces: self.exclude_interface.clone(),
+            extra_ips: self.extra_ip.clone(),
+            keep_virtual_ips: self.keep_virtual_ips,
+        }
+    }
+
+    /// Get the capture's format, a bundle unless the legacy files are asked for.
+    pub fn format(&self) -> sockets_map::collect::CaptureFormat {
+        match self.legacy_files {
+            true => sockets_map::collect::CaptureFormat::LegacyFiles,
+            false => sockets_map::collect::CaptureFormat::Bundle,
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! This module collects a host without an agent over SSH, and writes its capture files
+
+use crate::cli_args;
+use sockets_map::remote::{self, SshTarget, TargetOs};
+
+/// Run the capture commands on the host, and append their output to the capture files
+pub async fn run(collect_ssh_args: &cli_args::CollectSsh) -> anyhow::Result<()> {
+    let target = SshTarget {
+        pretty_name: collect_ssh_args.pretty_name().cloned(),
+        os: match collect_ssh_args.windows() {
+            true => TargetOs::Windows,
+            false => TargetOs::Linux,
+        },
+        ..SshTarget::new(collect_ssh_args.host())
+    };
+
+    log::info!("collecting {} over SSH", target.destination);
+    let host_data = remote::collect_host_data_via_ssh(&target).await?;
+    std::fs::create_dir_all(collect_ssh_args.out())?;
+    host_data.write_capture_files(collect_ssh_args.out())?;
+    log::info!("wrote capture files to {:?}", collect_ssh_args.out());
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! This module applies the processes and ports filters of the command line to the hosts
+
+use crate::cli_args::Filters;
+use anyhow::bail;
+use regex::Regex;
+use sockets_map::host::Host;
+
+/// Process name prefix, or regex matched at the start of the process name
+#[derive(Debug, Clone)]
+pub struct ProcessPattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl ProcessPattern {
+    /// Whether the process name matches the pattern
+    pub fn is_match(&self, process_name: &str) -> bool {
+        self.regex.is_match(process_name)
+    }
+
+    /// Get a reference to the pattern as given on the command line.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl std::str::FromStr for ProcessPattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            pattern: s.to_string(),
+            regex: Regex::new(&format!("^(?:{s})"))?,
+        })
+    }
+}
+
+/// Apply the filters to the hosts. It is an error for a process to be both kept and excluded, since
+/// the result would depend on the order of the filters.
+pub fn apply(filters: &Filters, hosts: &mut [Host]) -> anyhow::Result<()> {
+    for host in hosts.iter() {
+        let processes = host
+            .listening_sockets()
+            .iter()
+            .map(|s| s.process())
+            .chain(host.connections().iter().map(|c| c.process()));
+        for process in processes {
+            let only = filters
+                .only_process()
+                .iter()
+                .find(|p| p.is_match(process.name()));
+            let exclude = filters
+                .exclude_process()
+                .iter()
+                .find(|prefix| process.name().starts_with(prefix.as_str()));
+            if let (Some(only), Some(exclude)) = (only, exclude) {
+                bail!(
+                    "process {:?} of host {:?} matches both --only-process {:?} and --exclude-process {:?}",
+                    process.name(),
+                    host.name(),
+                    only.pattern(),
+                    exclude
+                );
+            }
+        }
+    }
+
+    let exclude_process: Vec<&str> = filters
+        .exclude_process()
+        .iter()
+        .map(String::as_str)
+        .collect();
+    for host in hosts.iter_mut() {
+        if !exclude_process.is_empty() {
+            host.exclude_processes(&exclude_process);
+        }
+        if !filters.only_process().is_empty() {
+            host.retain_processes(|process| {
+                filters
+                    .only_process()
+                    .iter()
+                    .any(|p| p.is_match(process.name()))
+            });
+        }
+        if !filters.exclude_port().is_empty() {
+            host.exclude_ports(filters.exclude_port());
+        }
+        if !filters.only_port().is_empty() {
+            host.only_ports(filters.only_port());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli_args;
+    use clap::Parser;
+    use sockets_map::{connections_model, parsers::directory_scanner, test_utils::HostBuilder};
+
+    /// Write a server running nginx and sshd, and a client connecting to both with curl and ssh
+    fn write_hosts(files_directory: &std::path::Path) {
+        HostBuilder::new("server")
+            .ip("10.0.0.1")
+            .listen_tcp(22, "sshd", 100)
+            .listen_tcp(443, "nginx", 101)
+            .build()
+            .write_csv_files(files_directory)
+            .unwrap();
+        HostBuilder::new("client")
+            .ip("10.0.0.2")
+            .connect_tcp("10.0.0.2:50022", "10.0.0.1:22", "ssh", 200)
+            .connect_tcp("10.0.0.2:50443", "10.0.0.1:443", "curl", 201)
+            .build()
+            .write_csv_files(files_directory)
+            .unwrap();
+    }
+
+    /// Parse the filters of a graph command line, and return the remaining connections as
+    /// (client process, server process) pairs
+    fn filtered_connections(filter_args: &[&str]) -> anyhow::Result<Vec<(String, String)>> {
+        let files_directory = tempfile::tempdir().unwrap();
+        write_hosts(files_directory.path());
+        let graph_args = cli_args::Graph::try_parse_from(
+            [
+                "graph",
+                "graph.svg",
+                files_directory.path().to_str().unwrap(),
+            ]
+            .iter()
+            .chain(filter_args),
+        )?;
+
+        let scanned_hosts = directory_scanner::scan_dir(files_directory.path())?;
+        let mut hosts = directory_scanner::build_hosts(&scanned_hosts)?;
+        apply(graph_args.filters(), &mut hosts)?;
+        let mut connections: Vec<_> = connections_model::build_connections_list(&hosts, false)
+            .iter()
+            .map(|c| {
+                (
+                    c.connected_connection().process().name().to_string(),
+                    c.listening_connection().process().name().to_string(),
+                )
+            })
+            .collect();
+        connections.sort();
+        Ok(connections)
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    /// Each filter must remove the connections of the matching processes or ports
+    #[test]
+    fn test_apply() {
+        let all = pairs(&[("curl", "nginx"), ("ssh", "sshd")]);
+        assert_eq!(filtered_connections(&[]).unwrap(), all);
+        assert_eq!(
+            filtered_connections(&["--exclude-process", "ssh"]).unwrap(),
+            pairs(&[("curl", "nginx")])
+        );
+        assert_eq!(
+            filtered_connections(&["--only-process", "ngi", "--only-process", "cu.l"]).unwrap(),
+            pairs(&[("curl", "nginx")])
+        );
+        assert_eq!(
+            filtered_connections(&["--exclude-port", "400-500"]).unwrap(),
+            pairs(&[("ssh", "sshd")])
+        );
+        assert_eq!(
+            filtered_connections(&["--only-port", "22", "--only-port", "443"]).unwrap(),
+            all
+        );
+        assert_eq!(
+            filtered_connections(&["--only-port", "8000-8100"]).unwrap(),
+            pairs(&[])
+        );
+    }
+
+    /// A process both kept and excluded must be reported, and invalid ports must be rejected
+    #[test]
+    fn test_apply_errors() {
+        let err = filtered_connections(&["--only-process", "ssh", "--exclude-process", "sshd"])
+            .unwrap_err();
+        assert!(err.to_string().contains("\"sshd\""));
+        assert!(filtered_connections(&["--only-port", "443-22"]).is_err());
+        assert!(filtered_connections(&["--only-process", "("]).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! This module generates the graph of the captures, once or each time the captures change
+
+use crate::{
+    cli_args, hosts,
+    report::{self, Report},
+};
+use anyhow::{anyhow, bail};
+use notify::{EventKind, RecursiveMode, Watcher};
+use sockets_map::{connections_model, graphs, graphviz};
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::timeout};
+
+/// Time without changes to wait for before regenerating the graph, since a capture is usually
+/// made of several files written in a row
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+
+/// Generate the graph from the captures of the files directories, and write the report if asked
+pub fn generate(graph_args: &cli_args::Graph) -> anyhow::Result<()> {
+    report::run(graph_args.report(), |report| {
+        write_graph(graph_args, report)?;
+        if graph_args.stats() {
+            report.print_stats();
+        }
+        Ok(())
+    })
+}
+
+/// Generate the graph, recording the run in `report`
+fn write_graph(graph_args: &cli_args::Graph, report: &mut Report) -> anyhow::Result<()> {
+    // Build the Hosts structures
+    let mut hosts = hosts::build(
+        graph_args.files_directories(),
+        graph_args.on_duplicate(),
+        graph_args.scan_options(),
+        graph_args.cache(),
+        graph_args.filters(),
+        graph_args.anonymization(),
+        graph_args.strict(),
+        report,
+    )?;
+
+    // Generate connections
+    let loopback = graph_args.loopback();
+    let connections_options = graph_args.connections_options();
+    if connections_options.infer_clients_from_server_side {
+        connections_model::infer_clients_from_server_side(&mut hosts);
+    }
+    let connections = connections_model::build_connections_list_with_options(
+        &hosts,
+        &connections_options,
+        &connections_model::ProxyAwareMatcher::new(graph_args.proxies()),
+    );
+    // Found before the focus leaves out the connections which matched them
+    let unmatched_known_peers = match graph_args.show_unmatched() {
+        true => connections_model::find_unmatched_known_peers(&hosts, &connections),
+        false => Vec::new(),
+    };
+    let connections = match graph_args.focus() {
+        [] => connections,
+        focus_hosts => {
+            if let Some(unknown) = focus_hosts
+                .iter()
+                .find(|focus_host| !hosts.iter().any(|host| host.name() == focus_host.as_str()))
+            {
+                bail!("unknown host {unknown} given with --focus");
+            }
+            connections_model::filter_neighborhood(
+                connections,
+                focus_hosts,
+                graph_args.focus_depth(),
+            )
+        }
+    };
+    report.set_connections(connections.len());
+
+    // The connections without a matching listener are kept between the hosts drawn
+    let drawn_hosts: HashSet<&str> = connections
+        .iter()
+        .flat_map(|c| [c.connected_host().name(), c.listening_host().name()])
+        .chain(graph_args.focus().iter().map(String::as_str))
+        .collect();
+    let unmatched_known_peers = unmatched_known_peers
+        .into_iter()
+        .filter(|unmatched| {
+            graph_args.focus().is_empty()
+                || (drawn_hosts.contains(unmatched.connected_host().name())
+                    && drawn_hosts.contains(unmatched.peer_host().name()))
+        })
+        .collect();
+    let connections_report = connections_model::ConnectionsReport {
+        connections,
+        unmatched_known_peers,
+    };
+
+    let output_file_path = graph_args.output_file();
+    let extension = output_file_path
+        .extension()
+        .map(|e| e.to_string_lossy().to_string());
+    let is_stdout = output_file_path.as_os_str() == "-";
+
+    // Check the rendering options before a long render
+    let mut render_options = graphs::RenderOptions {
+        format: extension.clone().unwrap_or_default(),
+        dpi: graph_args.dpi().unwrap_or(96.0),
+        layout_engine: graph_args.layout_engine(),
+        transparent_background: graph_args.transparent_bg(),
+        nodes: graphs::nodes_count(&connections_report.connections, graph_args.granularity()),
+    };
+    let option_warnings = graphs::validate_options(&render_options);
+    for warning in &option_warnings {
+        if graph_args.strict() && !warning.is_corrected() {
+            bail!("{warning}");
+        }
+        log::warn!("{warning}");
+    }
+    render_options.correct(&option_warnings);
+
+    // Generate the Dot graph
+    let dot_code = graphs::create_report_graph(
+        &connections_report,
+        &graphs::DrawOptions {
+            graph: graphs::MapGraphOptions {
+                granularity: graph_args.granularity(),
+                loopback_rendering: loopback,
+                listener_rendering: graph_args.listener_rendering(),
+                show_unmatched: graph_args.show_unmatched(),
+                ..Default::default()
+            },
+            transparent_background: render_options.transparent_background,
+            hide_legend: graph_args.hide_legend(),
+            legend_caption: graph_args.legend_caption().map(str::to_string),
+            dpi: graph_args.dpi().unwrap_or(96.0),
+            layout_engine: graph_args.layout_engine(),
+            layout_hints: Some(layout_hints(graph_args)?),
+        },
+    )
+    .map_err(|e| anyhow!("unable to generate graph: {e}"))?;
+    let provenance = graph_args.provenance();
+    if is_stdout || matches!(extension.as_deref(), Some("dot" | "gv")) {
+        // The DOT code is the output, so there is no need for Graphviz
+        if graph_args.vertical() {
+            log::warn!("--vertical is passed to Graphviz, and is ignored for DOT output");
+        }
+        if graph_args.overview().is_some() {
+            log::warn!("--overview is ignored for DOT output");
+        }
+        if let Some(dump) = graph_args.dump() {
+            std::fs::write(dump, &dot_code)
+                .map_err(|e| anyhow!("unable to dump dot code to file {dump:?}: {e}"))?;
+            report.add_output_file(dump);
+        }
+        let dot_code = match &provenance {
+            Some(provenance) => format!("{dot_code}\n{}", provenance.comment_block("//")),
+            None => dot_code,
+        };
+        match is_stdout {
+            true => std::io::stdout()
+                .write_all(dot_code.as_bytes())
+                .map_err(|e| anyhow!("unable to write dot code to stdout: {e}"))?,
+            false => {
+                std::fs::write(output_file_path, dot_code).map_err(|e| {
+                    anyhow!("unable to write dot code to {output_file_path:?}: {e}")
+                })?;
+                report.add_output_file(output_file_path);
+            }
+        }
+        return Ok(());
+    }
+    let extension = extension
+        .ok_or_else(|| anyhow!("the output file needs an extension to pass to Graphviz"))?;
+
+    // Run Graphviz command to generate the graph
+    // The DOT code is rendered again for the overview
+    let overview_dot_code = graph_args.overview().map(|_| dot_code.clone());
+    graphviz::run_graphviz(
+        dot_code,
+        output_file_path,
+        extension.clone(),
+        graph_args.dump(),
+        graph_args.vertical(),
+        graph_args.layout_engine().as_ref(),
+    )
+    .map_err(|e| anyhow!("Error in graph generation: {e}"))?;
+    if let Some(provenance) = &provenance {
+        graphviz::embed_provenance(output_file_path, &extension, provenance)?;
+    }
+    if let Some(dump) = graph_args.dump() {
+        report.add_output_file(dump);
+    }
+    report.add_output_file(output_file_path);
+
+    if let (Some(overview), Some(dot_code)) = (graph_args.overview(), overview_dot_code) {
+        write_overview(graph_args, overview, &dot_code, extension, report)?;
+    }
+    Ok(())
+}
+
+/// Layout hints of the `--layout-hints` file, with the `--rank-group` hosts put in their groups
+fn layout_hints(graph_args: &cli_args::Graph) -> anyhow::Result<graphs::LayoutHints> {
+    let mut layout_hints = match graph_args.layout_hints() {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("unable to read the layout hints {path:?}: {e}"))?;
+            toml::from_str(&content).map_err(|e| anyhow!("invalid layout hints {path:?}: {e}"))?
+        }
+        None => graphs::LayoutHints::default(),
+    };
+    for (rank_group, hosts) in graph_args.rank_groups() {
+        layout_hints.set_rank_group(rank_group, hosts);
+    }
+    layout_hints.validate()?;
+    Ok(layout_hints)
+}
+
+/// Write the overview of the graph, drawn as an inset of SVG graphs and rendered again at a lower
+/// DPI for the other formats
+fn write_overview(
+    graph_args: &cli_args::Graph,
+    overview: &graphviz::OverviewOptions,
+    dot_code: &str,
+    extension: String,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let output_file_path = graph_args.output_file();
+    let overview_file_path = graphviz::overview_path(output_file_path);
+    match extension.as_str() {
+        "svg" => graphviz::write_svg_overview(output_file_path, &overview_file_path, overview),
+        _ => graphviz::run_graphviz(
+            graphviz::overview_dot_code(
+                dot_code,
+                graph_args.dpi().unwrap_or(96.0) * overview.scale,
+            ),
+            &overview_file_path,
+            extension,
+            None,
+            graph_args.vertical(),
+            graph_args.layout_engine().as_ref(),
+        ),
+    }
+    .map_err(|e| anyhow!("unable to write the overview: {e}"))?;
+    report.add_output_file(&overview_file_path);
+    Ok(())
+}
+
+/// Regenerate the graph after a change, and print the outcome. Errors do not stop the watch, since
+/// they are often due to a capture being written.
+fn regenerate(graph_args: &cli_args::Graph) -> anyhow::Result<()> {
+    let time = chrono::Local::now().format("%H:%M:%S");
+    let res = generate(graph_args);
+    match &res {
+        Ok(_) => println!("[{time}] graph written to {:?}", graph_args.output_file()),
+        Err(e) => println!("[{time}] unable to generate the graph: {e}"),
+    }
+    res
+}
+
+/// Generate the graph, then regenerate it each time a capture file is added or modified in the
+/// files directories, until Ctrl-C is pressed
+pub async fn watch(graph_args: &cli_args::Graph) -> anyhow::Result<()> {
+    let _ = regenerate(graph_args);
+
+    // The output files may be in the watched directory, and must not trigger a regeneration
+    let output_files: Vec<_> = [Some(graph_args.output_file()), graph_args.dump()]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| absolute_path(path))
+        .collect();
+    let (change_tx, mut change_rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| !output_files.iter().any(|output| path == output))
+                {
+                    let _ = change_tx.send(());
+                }
+            }
+            Err(e) => log::error!("unable to watch the files directory: {e}"),
+        })?;
+    for files_directory in graph_args.files_directories() {
+        watcher.watch(files_directory, RecursiveMode::NonRecursive)?;
+        log::info!("watching {files_directory:?}");
+    }
+
+    loop {
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res?;
+                break;
+            }
+            Some(()) = change_rx.recv() => {
+                // Wait for the changes to settle
+                while let Ok(Some(())) = timeout(DEBOUNCE_DELAY, change_rx.recv()).await {}
+                let _ = regenerate(graph_args);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Absolute path of a file which may not exist yet, as reported by the watcher
+fn absolute_path(path: &Path) -> Option<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(path.file_name()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use sockets_map::{graphviz::LayoutEngine, test_utils::HostBuilder};
+
+    /// Write the capture files of a host with the IP 10.0.0.`number`, which connects to the SSH
+    /// server of the first host
+    fn write_host(files_directory: &std::path::Path, number: u8) {
+        let host = HostBuilder::new(&format!("machine{number}")).ip(&format!("10.0.0.{number}"));
+        let host = match number {
+            1 => host.listen_tcp(22, "sshd", 100),
+            _ => host.connect_tcp(&format!("10.0.0.{number}:50000"), "10.0.0.1:22", "ssh", 200),
+        };
+        host.build().write_csv_files(files_directory).unwrap();
+    }
+
+    /// Each regeneration must take the current captures into account, and failures must not
+    /// prevent the next regenerations. The DOT dump is checked, since Graphviz may be missing.
+    #[test]
+    fn test_regenerate() {
+        let files_directory = tempfile::tempdir().unwrap();
+        let output_directory = tempfile::tempdir().unwrap();
+        let dump = output_directory.path().join("graph.dot");
+        let graph_args = cli_args::Graph::parse_from([
+            "graph".as_ref(),
+            "--dump".as_ref(),
+            dump.as_os_str(),
+            output_directory.path().join("graph.svg").as_os_str(),
+            files_directory.path().as_os_str(),
+        ]);
+
+        write_host(files_directory.path(), 1);
+        write_host(files_directory.path(), 2);
+        let _ = regenerate(&graph_args);
+        let dot = std::fs::read_to_string(&dump).unwrap();
+        assert!(dot.contains("cluster_machine2"));
+        assert!(!dot.contains("cluster_machine3"));
+
+        // Capture being written
+        std::fs::write(files_directory.path().join("machine3_ip.csv"), "IP\n").unwrap();
+        assert!(regenerate(&graph_args).is_err());
+
+        write_host(files_directory.path(), 3);
+        let _ = regenerate(&graph_args);
+        let dot = std::fs::read_to_string(&dump).unwrap();
+        assert!(dot.contains("cluster_machine2"));
+        assert!(dot.contains("cluster_machine3"));
+    }
+
+    /// The layout engine after the directories, as the previous versions took it, must still be
+    /// accepted, unless a directory has its name
+    #[test]
+    fn test_positional_layout_engine() {
+        let files_directory = tempfile::tempdir().unwrap();
+        let graph_args = cli_args::Graph::parse_from([
+            "graph".as_ref(),
+            "graph.svg".as_ref(),
+            files_directory.path().as_os_str(),
+            "neato".as_ref(),
+        ]);
+        assert_eq!(graph_args.files_directories(), [files_directory.path()]);
+        assert_eq!(
+            graph_args.positional_layout_engine(),
+            Some(LayoutEngine::Neato)
+        );
+        assert_eq!(graph_args.layout_engine(), Some(LayoutEngine::Neato));
+
+        let graph_args = cli_args::Graph::parse_from([
+            "graph".as_ref(),
+            "--layout-engine".as_ref(),
+            "circo".as_ref(),
+            "graph.svg".as_ref(),
+            files_directory.path().as_os_str(),
+            "neato".as_ref(),
+        ]);
+        assert_eq!(graph_args.layout_engine(), Some(LayoutEngine::Circo));
+
+        let neato_directory = files_directory.path().join("neato");
+        std::fs::create_dir(&neato_directory).unwrap();
+        let graph_args = cli_args::Graph::parse_from([
+            "graph".as_ref(),
+            "graph.svg".as_ref(),
+            files_directory.path().as_os_str(),
+            neato_directory.as_os_str(),
+        ]);
+        assert_eq!(graph_args.files_directories().len(), 2);
+        assert_eq!(graph_args.layout_engine(), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! This module builds the hosts from the capture directories given on the command line
+
+use crate::{
+    cli_args::{Anonymization, Filters},
+    filter,
+    report::Report,
+    stats,
+};
+use anyhow::bail;
+use sockets_map::{
+    anonymize::Anonymizer,
+    host::Host,
+    parsers::{
+        directory_scanner::{self, BuiltHosts, OnDuplicate, ScanOptions},
+        hosts_cache,
+    },
+};
+use std::path::{Path, PathBuf};
+
+/// Build the hosts of the capture directories, apply the filters and anonymize them if asked. The
+/// hosts which cannot be parsed are left out, or make the build fail when `strict` is set. With a
+/// `cache` directory, the hosts are loaded from it when the capture files did not change.
+#[allow(clippy::too_many_arguments)]
+pub fn build(
+    files_directories: &[PathBuf],
+    on_duplicate: OnDuplicate,
+    scan_options: ScanOptions,
+    cache: Option<&Path>,
+    filters: &Filters,
+    anonymization: &Anonymization,
+    strict: bool,
+    report: &mut Report,
+) -> anyhow::Result<Vec<Host>> {
+    let BuiltHosts {
+        mut hosts,
+        mut failures,
+        mut diagnostics,
+        coverage,
+    } = match cache {
+        Some(cache) => {
+            let key = hosts_cache::directories_key(files_directories, on_duplicate, scan_options)?;
+            hosts_cache::load_or_build(
+                &hosts_cache::cache_path(cache, files_directories),
+                &key,
+                || {
+                    directory_scanner::build_hosts_from_dirs(
+                        files_directories,
+                        on_duplicate,
+                        scan_options,
+                    )
+                },
+            )?
+        }
+        None => {
+            directory_scanner::build_hosts_from_dirs(files_directories, on_duplicate, scan_options)?
+        }
+    };
+
+    // The filters match the real process names
+    filter::apply(filters, &mut hosts)?;
+    diagnostics.retain(|name, _| hosts.iter().any(|host| host.name() == name));
+    let mut host_stats = stats::host_stats(&hosts, &coverage);
+    if anonymization.anonymize() {
+        let mut anonymizer = Anonymizer::new();
+        hosts = anonymizer.anonymize_hosts(&hosts);
+        failures = anonymizer.anonymize_failures(&failures);
+        diagnostics = anonymizer.anonymize_diagnostics(&diagnostics);
+        host_stats = host_stats
+            .into_iter()
+            .map(|(name, stats)| (anonymizer.host_name(&name), stats))
+            .collect();
+        if let Some(mapping_out) = anonymization.mapping_out() {
+            anonymizer.write_mapping(mapping_out)?;
+            report.add_output_file(mapping_out);
+        }
+    }
+
+    report.set_hosts(&hosts, &failures);
+    report.set_diagnostics(diagnostics);
+    report.set_stats(host_stats);
+    if strict && !failures.is_empty() {
+        let failures: Vec<String> = failures
+            .iter()
+            .map(|f| format!("{} ({})", f.name(), f.reason()))
+            .collect();
+        bail!("unable to parse hosts: {}", failures.join(", "));
+    }
+
+    Ok(hosts)
+}
@@ -1,10 +1,20 @@
-use std::path::Path;
+use std::io::IsTerminal;
 
 use clap::Parser;
 
 mod cli_args;
+#[cfg(feature = "ssh")]
+mod collect_ssh;
+mod filter;
+mod graph;
 mod help;
-use sockets_map::{connections_model, csv, graphs, graphviz, parsers};
+mod hosts;
+mod quick;
+mod report;
+mod serve;
+mod stats;
+mod timeline;
+use sockets_map::{connections_model, csv, parsers, summary};
 
 #[tokio::main]
 async fn main() {
@@ -31,74 +41,114 @@ async fn main() {
     // Process subcommand
     match args.subcmd() {
         cli_args::SubCommand::Graph(graph_args) => {
-            // Build the Hosts structures
-            let scan_dir = graph_args.files_directory();
-            let scanned_hosts = parsers::directory_scanner::scan_dir(scan_dir);
-            let hosts =
-                parsers::directory_scanner::build_hosts(&scanned_hosts).unwrap_or_else(|e| {
-                    log::error!("{}", e);
-                    std::process::exit(1)
-                });
-
-            // Generate connections
-            let connections =
-                connections_model::build_connections_list(&hosts, graph_args.no_loopback());
+            if graph_args.no_loopback() {
+                log::warn!("--no-loopback is deprecated, use --loopback hide instead");
+            }
+            if let Some(layout_engine) = graph_args.positional_layout_engine() {
+                log::warn!(
+                    "the layout engine after the directories is deprecated, use --layout-engine {layout_engine} instead"
+                );
+            }
+            let res = match graph_args.watch() {
+                true => graph::watch(graph_args).await,
+                false => graph::generate(graph_args),
+            };
+            if let Err(e) = res {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        cli_args::SubCommand::Csv(csv_args) => {
+            let res = report::run(csv_args.report(), |report| {
+                // Build the Hosts structures
+                let mut hosts = hosts::build(
+                    csv_args.files_directories(),
+                    csv_args.on_duplicate(),
+                    csv_args.scan_options(),
+                    csv_args.cache(),
+                    csv_args.filters(),
+                    csv_args.anonymization(),
+                    csv_args.strict(),
+                    report,
+                )?;
 
-            // Parse output file extension
-            let output_file_path = graph_args.output_file();
-            let extension = output_file_path
-                .extension()
-                .expect("the output file needs an extension to pass to Graphviz");
+                // Generate connections
+                let connections_options = csv_args.connections_options();
+                if connections_options.infer_clients_from_server_side {
+                    connections_model::infer_clients_from_server_side(&mut hosts);
+                }
+                let connections = connections_model::build_connections_list_with_options(
+                    &hosts,
+                    &connections_options,
+                    &connections_model::DefaultMatcher,
+                );
+                report.set_connections(connections.len());
 
-            // Generate the Dot graph
-            let graph = match graphs::create_graph(
-                &connections,
-                graph_args.transparent_bg(),
-                graph_args.hide_legend(),
-                graph_args.dpi().unwrap_or(96.0),
-                graph_args.layout_engine(),
-            ) {
-                Ok(g) => g,
-                Err(e) => {
-                    log::error!("unable to generate graph: {}", e);
-                    std::process::exit(1);
+                // The real names would give the anonymized peers away
+                let mut csv_options = csv_args.csv_options();
+                if !csv_args.anonymization().anonymize() {
+                    csv_options.peer_names =
+                        parsers::dns_map::find_dns_map(csv_args.files_directories())?;
                 }
-            };
+                match csv_args.unmatched() {
+                    true => {
+                        let connections_report = connections_model::ConnectionsReport {
+                            unmatched_known_peers: connections_model::find_unmatched_known_peers(
+                                &hosts,
+                                &connections,
+                            ),
+                            connections,
+                        };
+                        csv::write_connections_to_csv(
+                            &connections_report.records(),
+                            csv_args.output_file(),
+                            &csv_options,
+                        )?;
+                    }
+                    false => csv::write_connections_to_csv(
+                        &connections,
+                        csv_args.output_file(),
+                        &csv_options,
+                    )?,
+                }
+                report.add_output_file(csv_args.output_file());
 
-            // Run Graphviz command to generate the graph
-            match graphviz::run_graphviz(
-                graph.to_string(),
-                Path::new(output_file_path),
-                extension.to_string_lossy().to_string(),
-                graph_args.dump(),
-                graph_args.vertical(),
-                graph_args.layout_engine(),
-            ) {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!("Error in graph generation: {}", e);
+                if let Some(routes_file) = csv_args.routes() {
+                    csv::write_routes_to_csv(&hosts, routes_file, &csv_options)?;
+                    report.add_output_file(routes_file);
                 }
-            };
+                if csv_args.stats() {
+                    report.print_stats();
+                }
+                Ok(())
+            });
+            if let Err(e) = res {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
         }
-        cli_args::SubCommand::Csv(csv_args) => {
+        cli_args::SubCommand::Summary(summary_args) => {
             // Build the Hosts structures
-            let scan_dir = csv_args.files_directory();
-            let scanned_hosts = parsers::directory_scanner::scan_dir(scan_dir);
-            let hosts =
-                parsers::directory_scanner::build_hosts(&scanned_hosts).unwrap_or_else(|e| {
+            let scan_dir = summary_args.files_directory();
+            let hosts = parsers::directory_scanner::scan_dir(scan_dir)
+                .and_then(|scanned_hosts| parsers::directory_scanner::build_hosts(&scanned_hosts))
+                .unwrap_or_else(|e| {
                     log::error!("{}", e);
                     std::process::exit(1)
                 });
 
             // Generate connections
-            let connections = connections_model::build_connections_list(&hosts, false);
+            let connections =
+                connections_model::build_connections_list(&hosts, summary_args.no_loopback());
 
-            match csv::write_connections_to_csv(&connections, csv_args.output_file()) {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!("{}", e);
-                }
-            };
+            println!(
+                "{}",
+                summary::format_summary(
+                    &connections,
+                    summary_args.group_by(),
+                    std::io::stdout().is_terminal()
+                )
+            );
         }
         cli_args::SubCommand::Cheatsheet(help_args) => {
             match help_args.smbcmd() {
@@ -122,5 +172,58 @@ async fn main() {
                 }
             };
         }
+        cli_args::SubCommand::Timeline(timeline_args) => {
+            if timeline_args.no_loopback() {
+                log::warn!("--no-loopback is deprecated, use --loopback hide instead");
+            }
+            if let Err(e) = timeline::run(timeline_args) {
+                log::error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+        cli_args::SubCommand::Serve(serve_args) => {
+            if let Err(e) = serve::run(serve_args).await {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        cli_args::SubCommand::Quick(quick_args) => {
+            if let Err(e) = quick::run(quick_args) {
+                log::error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "ssh")]
+        cli_args::SubCommand::CollectSsh(collect_ssh_args) => {
+            if let Err(e) = collect_ssh::run(collect_ssh_args).await {
+                log::error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(any(target_os = "linux", target_os = "windows"))]
+        cli_args::SubCommand::Capture(capture_args) => {
+            if let Err(e) = capture(capture_args).await {
+                log::error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
     };
 }
+
+/// Capture the sockets of this machine, the same way the agent does in offline mode
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+async fn capture(capture_args: &cli_args::Capture) -> anyhow::Result<()> {
+    use sockets_map::collect;
+
+    collect::check_privileges(capture_args.no_root())?;
+
+    collect::write_captures(
+        capture_args.output_dir(),
+        capture_args.interval(),
+        capture_args.count(),
+        &collect::host_name(capture_args.pretty_name())?,
+        &collect::local_ip_addresses(&capture_args.ip_filter())?,
+        capture_args.format(),
+    )
+    .await
+}
@@ -0,0 +1,90 @@
+//! This module graphs or exports a single host, from the output of a network command read on stdin
+
+use crate::cli_args;
+use anyhow::{anyhow, bail, Context};
+use sockets_map::{
+    connections_model::{self, ToConnectionRecord},
+    csv::{self, CsvOptions},
+    graphs, graphviz, parsers,
+};
+use std::{
+    io::{IsTerminal, Read, Write},
+    net::IpAddr,
+    path::Path,
+};
+
+/// Build the host from stdin, then write its graph or its connections
+pub fn run(quick_args: &cli_args::Quick) -> anyhow::Result<()> {
+    let mut ips = Vec::new();
+    for list in quick_args.ips() {
+        ips.extend(parse_ip_list(list)?);
+    }
+    if let Some(ip_file) = quick_args.ip_file() {
+        let list = std::fs::read_to_string(ip_file)
+            .with_context(|| format!("unable to read IP file {ip_file:?}"))?;
+        ips.extend(parse_ip_list(&list).with_context(|| format!("invalid IP file {ip_file:?}"))?);
+    }
+
+    let mut stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        bail!("pipe the output of ss or netstat to stdin, such as `ss -apn | sockets_map quick`");
+    }
+    let mut contents = Vec::new();
+    stdin
+        .read_to_end(&mut contents)
+        .context("unable to read stdin")?;
+    let host =
+        parsers::detect_and_parse(&String::from_utf8_lossy(&contents), quick_args.host(), ips)?;
+    let hosts = vec![host];
+    let connections = connections_model::build_connections_list(&hosts, false);
+
+    let format = quick_args.format();
+    if format == "csv" {
+        let records: Vec<_> = connections
+            .iter()
+            .map(ToConnectionRecord::to_connection_record)
+            .collect();
+        let csv = csv::connection_records_to_string(&records, &CsvOptions::default())?;
+        return write_output(quick_args.out(), &csv);
+    }
+    let dot_code = graphs::create_graph(
+        &connections,
+        &graphs::DrawOptions {
+            graph: graphs::MapGraphOptions {
+                listener_rendering: quick_args.listener_rendering(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .map_err(|e| anyhow!("unable to generate graph: {e}"))?;
+    match (format.as_str(), quick_args.out()) {
+        ("dot" | "gv", out) => write_output(out, &dot_code),
+        (_, None) => bail!("the {format} format needs an output file, given with --out"),
+        (_, Some(out)) => graphviz::run_graphviz(dot_code, out, format, None, false, None)
+            .map_err(|e| anyhow!("Error in graph generation: {e}")),
+    }
+}
+
+/// Write `contents` to the output file, or to stdout
+fn write_output(out: Option<&Path>, contents: &str) -> anyhow::Result<()> {
+    match out {
+        Some(out) => {
+            std::fs::write(out, contents).with_context(|| format!("unable to write to {out:?}"))
+        }
+        None => std::io::stdout()
+            .write_all(contents.as_bytes())
+            .context("unable to write to stdout"),
+    }
+}
+
+/// Parse IP addresses separated with spaces, commas or new lines, as printed by `hostname -I`
+fn parse_ip_list(list: &str) -> anyhow::Result<Vec<IpAddr>> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| {
+            ip.parse()
+                .with_context(|| format!("invalid IP address {ip}"))
+        })
+        .collect()
+}
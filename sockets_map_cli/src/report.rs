@@ -0,0 +1,177 @@
+//! This module writes a JSON report of a run, for the scripts wrapping the CLI
+
+use crate::stats::{self, HostStats};
+use serde::Serialize;
+use sockets_map::{
+    host::{Host, Route},
+    parsers::{diagnostics::ParseDiagnostic, directory_scanner::HostFailure},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Outcome of a `graph` or `csv` run
+#[derive(Serialize)]
+pub struct Report {
+    /// Whether the run succeeded, in which case the output files were all written
+    success: bool,
+    /// Fatal error of the run
+    error: Option<String>,
+    /// Names of the hosts built from the capture files
+    hosts_parsed: Vec<String>,
+    /// Hosts left out, since their capture files could not be parsed
+    hosts_failed: Vec<HostFailure>,
+    /// Problems met on the lines of the capture files of the parsed hosts, by host name
+    diagnostics: BTreeMap<String, Vec<ParseDiagnostic>>,
+    /// Lines of the network files used and skipped, and sockets kept, by host name
+    stats: BTreeMap<String, HostStats>,
+    /// Captured routes of the hosts, by host name
+    routes: BTreeMap<String, Vec<Route>>,
+    /// Number of connections found between the processes
+    connections: usize,
+    /// Files written by the run
+    output_files: Vec<PathBuf>,
+    /// Duration of the run, in seconds
+    duration_secs: f64,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self {
+            success: false,
+            error: None,
+            hosts_parsed: Vec::new(),
+            hosts_failed: Vec::new(),
+            diagnostics: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            routes: BTreeMap::new(),
+            connections: 0,
+            output_files: Vec::new(),
+            duration_secs: 0.0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Record the hosts built from the capture files, with their routes, and those which failed
+    pub fn set_hosts(&mut self, hosts: &[Host], failures: &[HostFailure]) {
+        self.hosts_parsed = hosts.iter().map(|h| h.name().to_string()).collect();
+        self.hosts_failed = failures.to_vec();
+        self.routes = hosts
+            .iter()
+            .filter(|h| !h.routes().is_empty())
+            .map(|h| (h.name().to_string(), h.routes().to_vec()))
+            .collect();
+    }
+
+    /// Record the diagnostics of the parsed hosts
+    pub fn set_diagnostics(&mut self, diagnostics: BTreeMap<String, Vec<ParseDiagnostic>>) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// Record the stats of the parsed hosts
+    pub fn set_stats(&mut self, stats: BTreeMap<String, HostStats>) {
+        self.stats = stats;
+    }
+
+    /// Print the table of the stats on stderr, so that it does not mix with a graph written to
+    /// stdout
+    pub fn print_stats(&self) {
+        eprint!("{}", stats::format_stats(&self.stats));
+    }
+
+    /// Record the number of connections found
+    pub fn set_connections(&mut self, connections: usize) {
+        self.connections = connections;
+    }
+
+    /// Record a written output file
+    pub fn add_output_file(&mut self, path: &Path) {
+        self.output_files.push(path.to_path_buf());
+    }
+}
+
+/// Run `f`, then write its report to `report_path` if set. The error of `f` is returned as is, and
+/// takes precedence over the error of writing the report.
+pub fn run(
+    report_path: Option<&Path>,
+    f: impl FnOnce(&mut Report) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut report = Report::new();
+    let res = f(&mut report);
+
+    if let Some(report_path) = report_path {
+        report.success = res.is_ok();
+        report.error = res.as_ref().err().map(|e| format!("{e:#}"));
+        report.duration_secs = report.start.elapsed().as_secs_f64();
+        let write_res = serde_json::to_string_pretty(&report)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(report_path, json)?));
+        if let Err(e) = write_res {
+            let e = anyhow::anyhow!("unable to write the report to {report_path:?}: {e}");
+            return res.and(Err(e));
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    fn read_report(path: &Path) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// The report must be written for failed runs too, with the error
+    #[test]
+    fn test_run() {
+        let directory = tempfile::tempdir().unwrap();
+        let report_path = directory.path().join("report.json");
+
+        let mut host = Host::new("machine1");
+        host.add_route(Route::new(
+            "0.0.0.0".parse().unwrap(),
+            0,
+            Some("10.0.0.254".parse().unwrap()),
+            Some("eth0".to_string()),
+        ));
+        let res = run(Some(&report_path), |report| {
+            report.set_hosts(&[host, Host::new("machine2")], &[]);
+            report.set_connections(3);
+            report.add_output_file(Path::new("graph.svg"));
+            Ok(())
+        });
+        assert!(res.is_ok());
+        let report = read_report(&report_path);
+        assert_eq!(report["success"], true);
+        assert_eq!(report["error"], serde_json::Value::Null);
+        assert_eq!(
+            report["hosts_parsed"],
+            serde_json::json!(["machine1", "machine2"])
+        );
+        assert_eq!(
+            report["routes"],
+            serde_json::json!({"machine1": [{
+                "destination": "0.0.0.0",
+                "prefix_len": 0,
+                "gateway": "10.0.0.254",
+                "interface": "eth0",
+            }]})
+        );
+        assert_eq!(report["connections"], 3);
+        assert_eq!(report["output_files"], serde_json::json!(["graph.svg"]));
+        assert!(report["duration_secs"].is_f64());
+
+        let res = run(Some(&report_path), |_| bail!("Graphviz failed"));
+        assert_eq!(res.unwrap_err().to_string(), "Graphviz failed");
+        let report = read_report(&report_path);
+        assert_eq!(report["success"], false);
+        assert_eq!(report["error"], "Graphviz failed");
+    }
+}
@@ -0,0 +1,263 @@
+//! This module runs the agents server without the GUI
+
+use crate::cli_args;
+use anyhow::anyhow;
+use sockets_map::{
+    connections_model, graphs, graphviz,
+    host::Host,
+    server::{
+        self,
+        client::{Client, UpdateSelection},
+        event_log::EventKind,
+        http,
+        message::Message,
+        ServerState,
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, RwLock};
+use tokio_util::sync::CancellationToken;
+use tsyncp::broadcast::BincodeSender;
+
+/// Time to wait for the final updates of the clients at the end of a recording
+const FINAL_UPDATES_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for the clients to disconnect when stopping the server
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run the server until Ctrl-C is pressed or the recording ends, then disconnect the clients
+pub async fn run(serve_args: &cli_args::Serve) -> anyhow::Result<()> {
+    let clients: Arc<RwLock<HashMap<String, Client>>> = Arc::new(RwLock::new(HashMap::new()));
+    let run_token = CancellationToken::new();
+
+    // Registrations are handled below, since messages cannot be sent from the callbacks
+    let (registration_tx, mut registration_rx) = mpsc::unbounded_channel();
+
+    log::info!("starting server");
+    let tx_opt = Arc::new(RwLock::new(None));
+    let (mut state_rx, event_log) = server::listen(
+        format!("{}:{}", serve_args.address(), serve_args.port()),
+        clients.clone(),
+        tx_opt.clone(),
+        run_token.clone(),
+        server::ListenOptions {
+            max_updates_per_client: serve_args.max_updates(),
+            max_update_bytes: serve_args.max_update_bytes(),
+            max_clients: serve_args.max_clients(),
+            verify_source_ip: serve_args.verify_source_ip(),
+            verify_exempt_ips: serve_args.verify_exempt().to_vec(),
+        },
+        |socket_addr| {
+            log::info!("connection from peer {socket_addr:?}");
+        },
+        move |client: &Client| {
+            log::info!(
+                "client registration for {:?}",
+                client.pretty_name.as_ref().unwrap_or(&client.hostname)
+            );
+            let _ = registration_tx.send(());
+        },
+        |client: &Client| {
+            log::info!(
+                "client update ({:?})",
+                client.pretty_name.as_ref().unwrap_or(&client.hostname)
+            );
+        },
+        |client: &Client| {
+            log::info!(
+                "client exit ({:?})",
+                client.pretty_name.as_ref().unwrap_or(&client.hostname)
+            );
+        },
+    )
+    .await?;
+
+    if let Some(http_port) = serve_args.http_port() {
+        http::serve(
+            format!("{}:{}", serve_args.address(), http_port),
+            clients.clone(),
+            tx_opt.clone(),
+            run_token.clone(),
+        )
+        .await?;
+    }
+
+    // The recording starts with the first registration, and ends with Ctrl-C or after its duration
+    let mut recording = false;
+    // Clients told to record, the others being the ones which just registered
+    let mut recording_clients = HashSet::new();
+    let recording_end = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(recording_end);
+    let mut dump_interval =
+        tokio::time::interval(Duration::from_secs_f64(serve_args.dump_interval()));
+
+    let mut failure = None;
+    loop {
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res?;
+                break;
+            }
+            Ok(()) = state_rx.changed() => {
+                let state = state_rx.borrow().clone();
+                if let ServerState::Failed(reason) = state {
+                    failure = Some(reason);
+                    break;
+                }
+            }
+            Some(()) = registration_rx.recv() => {
+                if let Some(interval) = serve_args.record() {
+                    if !recording {
+                        log::info!("starting recording with interval of {interval}s");
+                        recording = true;
+                        event_log
+                            .write()
+                            .await
+                            .push_server_event(EventKind::RecordingStarted { interval });
+                        if let Some(duration) = serve_args.duration() {
+                            let duration = Duration::from_secs_f64(duration);
+                            recording_end.as_mut().reset(tokio::time::Instant::now() + duration);
+                        }
+                    }
+                    let new_clients: Vec<String> = clients
+                        .read()
+                        .await
+                        .keys()
+                        .filter(|client_addr| !recording_clients.contains(*client_addr))
+                        .cloned()
+                        .collect();
+                    // The clients which could not be told are retried at the next registration
+                    let res =
+                        server::start_clients_recording(&clients, &tx_opt, &new_clients, interval)
+                            .await;
+                    match res {
+                        Ok(()) => recording_clients.extend(new_clients),
+                        Err(e) => log::error!("{e}"),
+                    }
+                } else if serve_args.update_on_connect() {
+                    send(&tx_opt, Message::UpdateRequest).await;
+                }
+            }
+            _ = &mut recording_end, if recording && serve_args.duration().is_some() => {
+                log::info!("end of the recording");
+                break;
+            }
+            _ = dump_interval.tick(), if serve_args.output_dir().is_some() => {
+                if let Some(output_dir) = serve_args.output_dir() {
+                    let hosts = client_hosts(&*clients.read().await, recording);
+                    if let Err(e) = write_capture_files(&hosts, output_dir) {
+                        log::error!("{e}");
+                    }
+                }
+            }
+        }
+    }
+    log::info!("stopping server");
+
+    if recording {
+        send(&tx_opt, Message::StopRecording).await;
+        event_log
+            .write()
+            .await
+            .push_server_event(EventKind::RecordingStopped);
+        wait_for_final_updates(&clients).await;
+    }
+
+    // Disconnect clients, keeping the updates they were still sending
+    server::shutdown(
+        &tx_opt,
+        &clients,
+        &run_token,
+        &mut state_rx,
+        SHUTDOWN_TIMEOUT,
+    )
+    .await;
+
+    // Write the outputs
+    let hosts = client_hosts(&*clients.read().await, recording);
+    if let Some(output_dir) = serve_args.output_dir() {
+        write_capture_files(&hosts, output_dir)?;
+    }
+    if let Some(graph_file) = serve_args.graph() {
+        write_graph(&hosts, graph_file)?;
+    }
+
+    if let Some(event_log_file) = serve_args.event_log() {
+        event_log.read().await.write_to(event_log_file)?;
+        log::info!("wrote event log to {event_log_file:?}");
+    }
+
+    match failure {
+        Some(reason) => Err(anyhow!("the server stopped unexpectedly: {reason}")),
+        None => Ok(()),
+    }
+}
+
+/// Send a message to all the clients
+async fn send(tx_opt: &RwLock<Option<BincodeSender<Message>>>, message: Message) {
+    if let Some(tx) = tx_opt.write().await.as_mut() {
+        let (_res, _accept_res) = tx.send(message).accepting().await;
+    }
+}
+
+/// Wait until each client has sent a full update since now, with a timeout
+async fn wait_for_final_updates(clients: &RwLock<HashMap<String, Client>>) {
+    let stop_time = chrono::Utc::now();
+    let start = Instant::now();
+    while start.elapsed() < FINAL_UPDATES_TIMEOUT {
+        if clients.read().await.values().all(|c| {
+            matches!(c.last_update_time(), Some(t) if t >= stop_time) && !c.merged_deltas()
+        }) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    log::warn!("did not get the final update from all clients");
+}
+
+/// Hosts of the clients which sent an update: all their updates aggregated after a recording,
+/// their last update otherwise
+fn client_hosts(clients: &HashMap<String, Client>, aggregate: bool) -> Vec<Host> {
+    clients
+        .values()
+        .filter_map(|client| {
+            client.selected_host(match aggregate {
+                true => UpdateSelection::MergeAll,
+                false => UpdateSelection::Latest,
+            })
+        })
+        .collect()
+}
+
+/// Write the CSV capture files of the hosts, which can be read back by the other subcommands
+fn write_capture_files(hosts: &[Host], output_dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+    for host in hosts {
+        host.write_csv_files(output_dir)?;
+    }
+    log::info!("wrote {} hosts to {output_dir:?}", hosts.len());
+    Ok(())
+}
+
+/// Generate the graph of the hosts with the default options
+fn write_graph(hosts: &[Host], output_file: &Path) -> anyhow::Result<()> {
+    let extension = output_file
+        .extension()
+        .ok_or_else(|| anyhow!("the graph file needs an extension to pass to Graphviz"))?;
+    let connections = connections_model::build_connections_list(hosts, false);
+    let dot_code = graphs::create_graph(&connections, &graphs::DrawOptions::default())?;
+    graphviz::run_graphviz(
+        dot_code,
+        output_file,
+        extension.to_string_lossy().to_string(),
+        None,
+        false,
+        None,
+    )?;
+    log::info!("wrote graph to {output_file:?}");
+    Ok(())
+}
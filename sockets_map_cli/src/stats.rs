@@ -0,0 +1,127 @@
+//! This module counts how much of the capture files was used, printed with `--stats` to detect
+//! truncated captures or files of the wrong host
+
+use serde::Serialize;
+use sockets_map::{host::Host, parsers::diagnostics::ParseCoverage};
+use std::collections::BTreeMap;
+
+/// Lines of the network file of a host, along with the sockets kept once filtered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct HostStats {
+    #[serde(flatten)]
+    coverage: ParseCoverage,
+    listening_sockets: usize,
+    connections: usize,
+}
+
+impl HostStats {
+    fn add(&mut self, other: &HostStats) {
+        self.coverage.add(&other.coverage);
+        self.listening_sockets += other.listening_sockets;
+        self.connections += other.connections;
+    }
+}
+
+/// Stats of the `hosts`, by host name, from the counts of their lines
+pub fn host_stats(
+    hosts: &[Host],
+    coverage: &BTreeMap<String, ParseCoverage>,
+) -> BTreeMap<String, HostStats> {
+    hosts
+        .iter()
+        .map(|host| {
+            let stats = HostStats {
+                coverage: coverage.get(host.name()).copied().unwrap_or_default(),
+                listening_sockets: host.listening_sockets().len(),
+                connections: host.connections().len(),
+            };
+            (host.name().to_string(), stats)
+        })
+        .collect()
+}
+
+/// Table of the stats, one host per row followed by the totals
+pub fn format_stats(stats: &BTreeMap<String, HostStats>) -> String {
+    const HEADER: [&str; 8] = [
+        "HOST",
+        "LINES",
+        "PARSED",
+        "SKIPPED",
+        "ERRORS",
+        "UNNAMED",
+        "LISTENING",
+        "CONNECTIONS",
+    ];
+    let row = |name: &str, stats: &HostStats| {
+        let coverage = &stats.coverage;
+        [
+            name.to_string(),
+            coverage.lines().to_string(),
+            coverage.parsed_lines().to_string(),
+            coverage.skipped_lines().to_string(),
+            coverage.error_lines().to_string(),
+            coverage.unnamed_processes().to_string(),
+            stats.listening_sockets.to_string(),
+            stats.connections.to_string(),
+        ]
+    };
+    let mut total = HostStats::default();
+    let mut rows = vec![HEADER.map(str::to_string)];
+    for (name, host_stats) in stats {
+        total.add(host_stats);
+        rows.push(row(name, host_stats));
+    }
+    rows.push(row("TOTAL", &total));
+
+    let mut widths = [0; HEADER.len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in rows {
+        // The host names are aligned to the left, the counts to the right
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(index, (cell, width))| match index {
+                0 => format!("{cell:<width$}"),
+                _ => format!("{cell:>width$}"),
+            })
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockets_map::parsers::diagnostics::ParseDiagnostics;
+
+    /// Every host must have a row, even without any line, and the totals must add them up
+    #[test]
+    fn test_format_stats() {
+        let mut diagnostics = ParseDiagnostics::new("web01");
+        diagnostics.count_lines(12);
+        for _ in 0..9 {
+            diagnostics.parsed_line();
+        }
+        diagnostics.error(Some(12), "the line is incomplete");
+        let coverage = BTreeMap::from([("web01".to_string(), diagnostics.coverage())]);
+        let hosts = [Host::new("web01"), Host::new("db")];
+
+        assert_eq!(
+            format_stats(&host_stats(&hosts, &coverage)),
+            "\
+HOST   LINES  PARSED  SKIPPED  ERRORS  UNNAMED  LISTENING  CONNECTIONS
+db         0       0        0       0        0          0            0
+web01     12       9        3       1        0          0            0
+TOTAL     12       9        3       1        0          0            0
+"
+        );
+    }
+}
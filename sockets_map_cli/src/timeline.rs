@@ -0,0 +1,170 @@
+//! This module generates a graph per time window of a series of captures
+
+use crate::cli_args;
+use anyhow::{anyhow, bail, Context};
+use sockets_map::{
+    connections_model::{self, ConnectionRecord, ConnectionsOptions},
+    graphs, graphviz,
+    host::Host,
+    parsers::directory_scanner,
+    timeline::{self, TimeSlice},
+};
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Name of the file listing the graphs of the time slices
+const INDEX_FILE_NAME: &str = "index.csv";
+
+/// Slice the captures of the files directory, then write the graph of each slice and the index
+pub fn run(timeline_args: &cli_args::Timeline) -> anyhow::Result<()> {
+    let captures = read_captures(timeline_args.files_directory())?;
+    let options = timeline_args.connections_options();
+    let captured_records: Vec<(SystemTime, Vec<ConnectionRecord>)> = captures
+        .iter()
+        .map(|(time, hosts)| {
+            (
+                *time,
+                connections_model::build_connection_records(hosts, &options),
+            )
+        })
+        .collect();
+    let records = timeline::merge_captures(&captured_records);
+    let slices = timeline::slice_connections(&records, timeline_args.window());
+
+    // The graphs are drawn from the hosts of all the captures, keeping the connections of a slice
+    let mut hosts = Vec::<Host>::new();
+    for host in captures.into_iter().flat_map(|(_, hosts)| hosts) {
+        match hosts.iter_mut().find(|h| h.name() == host.name()) {
+            Some(merged_host) => merged_host.merge(&host),
+            None => hosts.push(host),
+        }
+    }
+    // The same clients as in the records of the captures
+    if options.infer_clients_from_server_side {
+        connections_model::infer_clients_from_server_side(&mut hosts);
+    }
+
+    let output_dir = timeline_args.output_dir();
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("unable to create directory {output_dir:?}"))?;
+    let mut index = String::from("Start,End,Connections,File\n");
+    for slice in &slices {
+        let file_name = slice.file_name(timeline_args.format());
+        write_slice_graph(
+            slice,
+            &hosts,
+            &options,
+            timeline_args.loopback(),
+            &output_dir.join(&file_name),
+            timeline_args.format(),
+        )?;
+        index.push_str(&format!(
+            "{},{},{},{file_name}\n",
+            chrono::DateTime::<chrono::Utc>::from(slice.start()).to_rfc3339(),
+            chrono::DateTime::<chrono::Utc>::from(slice.end()).to_rfc3339(),
+            slice.records().len(),
+        ));
+    }
+    let index_path = output_dir.join(INDEX_FILE_NAME);
+    std::fs::write(&index_path, index)
+        .with_context(|| format!("unable to write index file {index_path:?}"))?;
+    log::info!("wrote {} graphs to {output_dir:?}", slices.len());
+    Ok(())
+}
+
+/// Read the hosts of each capture subdirectory, along with the time of its newest file, oldest
+/// capture first
+fn read_captures(files_directory: &Path) -> anyhow::Result<Vec<(SystemTime, Vec<Host>)>> {
+    let mut captures = Vec::new();
+    let entries = files_directory
+        .read_dir()
+        .with_context(|| format!("unable to read directory {files_directory:?}"))?;
+    let mut capture_dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    capture_dirs.sort();
+
+    for capture_dir in capture_dirs {
+        let Some(time) = newest_file_time(&capture_dir)? else {
+            log::debug!("skipping empty directory {capture_dir:?}");
+            continue;
+        };
+        let scanned_hosts = directory_scanner::scan_dir(&capture_dir)?;
+        let (hosts, _failures) = directory_scanner::build_hosts_with_failures(&scanned_hosts)
+            .with_context(|| format!("unable to read the captures of {capture_dir:?}"))?;
+        captures.push((time, hosts));
+    }
+    if captures.is_empty() {
+        bail!("no capture subdirectory found in {files_directory:?}");
+    }
+    captures.sort_by_key(|(time, _)| *time);
+    Ok(captures)
+}
+
+/// Modification time of the newest file of the directory, if it has any file
+fn newest_file_time(dir: &Path) -> anyhow::Result<Option<SystemTime>> {
+    let mut newest = None;
+    for entry in dir
+        .read_dir()
+        .with_context(|| format!("unable to read directory {dir:?}"))?
+        .flatten()
+    {
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            newest = newest.max(Some(metadata.modified()?));
+        }
+    }
+    Ok(newest)
+}
+
+/// Write the graph of the connections of the slice, built with the same `options` as its records
+fn write_slice_graph(
+    slice: &TimeSlice,
+    hosts: &[Host],
+    options: &ConnectionsOptions,
+    loopback: graphs::LoopbackRendering,
+    output_file_path: &Path,
+    format: &str,
+) -> anyhow::Result<()> {
+    let mut connections = connections_model::build_connections_list_with_options(
+        hosts,
+        options,
+        &connections_model::DefaultMatcher,
+    );
+    connections.retain(|connection| {
+        let record = ConnectionRecord::from(connection);
+        slice
+            .records()
+            .iter()
+            .any(|r| r.is_same_connection(&record))
+    });
+    let dot_code = graphs::create_graph(
+        &connections,
+        &graphs::DrawOptions {
+            graph: graphs::MapGraphOptions {
+                loopback_rendering: loopback,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .map_err(|e| anyhow!("unable to generate graph: {e}"))?;
+
+    match format {
+        "dot" | "gv" => std::fs::write(output_file_path, dot_code)
+            .with_context(|| format!("unable to write dot code to {output_file_path:?}")),
+        _ => graphviz::run_graphviz(
+            dot_code,
+            output_file_path,
+            format.to_string(),
+            None,
+            false,
+            None,
+        )
+        .map_err(|e| anyhow!("Error in graph generation: {e}")),
+    }
+}
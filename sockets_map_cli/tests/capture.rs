@@ -0,0 +1,40 @@
+//! Capture the sockets of this machine, and read back the capture files.
+
+#![cfg(target_os = "linux")]
+
+use sockets_map::parsers::directory_scanner;
+use std::{net::TcpListener, process::Command};
+
+/// The capture files must make a host with the listening socket opened by the test
+#[test]
+fn test_capture() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let output_dir = tempfile::tempdir().unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args([
+            "capture",
+            "--no-root",
+            "--pretty-name",
+            "this_machine",
+            "--count",
+            "2",
+            "--interval",
+            "0.1",
+        ])
+        .arg(output_dir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let scanned_hosts = directory_scanner::scan_dir(output_dir.path()).unwrap();
+    let hosts = directory_scanner::build_hosts(&scanned_hosts).unwrap();
+    assert_eq!(hosts.len(), 1);
+    let host = &hosts[0];
+    assert_eq!(host.name(), "this_machine");
+    assert!(host.ips().contains(&"127.0.0.1".parse().unwrap()));
+    assert!(host.listening_sockets().iter().any(
+        |s| s.port() == port && s.ip_addr() == "127.0.0.1".parse::<std::net::IpAddr>().unwrap()
+    ));
+}
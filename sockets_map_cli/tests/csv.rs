@@ -0,0 +1,550 @@
+//! Run the csv subcommand on capture files, and read back its output.
+
+use sockets_map::test_utils::HostBuilder;
+use std::{io::Write, path::Path, process::Command};
+
+/// Write the capture files of a database server, queried by a local process and by a client
+fn write_hosts(files_directory: &Path) {
+    HostBuilder::new("server")
+        .ip("10.0.0.1")
+        .listen_tcp(5432, "postgres", 100)
+        .connect_tcp("127.0.0.1:40000", "127.0.0.1:5432", "backup", 101)
+        .build()
+        .write_csv_files(files_directory)
+        .unwrap();
+    HostBuilder::new("client")
+        .ip("10.0.0.2")
+        .connect_tcp("10.0.0.2:50000", "10.0.0.1:5432", "psql", 200)
+        .build()
+        .write_csv_files(files_directory)
+        .unwrap();
+}
+
+/// Run the csv subcommand with `args`, and return the source processes of the exported rows
+fn export(args: &[&str], delimiter: u8) -> Vec<String> {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .arg("csv")
+        .args(args)
+        .arg(&output_file)
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .from_path(&output_file)
+        .unwrap();
+    assert_eq!(reader.headers().unwrap().len(), 9);
+    let mut processes: Vec<String> = reader
+        .records()
+        .map(|record| record.unwrap()[2].to_string())
+        .collect();
+    processes.sort();
+    processes
+}
+
+/// Loopback connections are exported unless `--no-loopback` is set
+#[test]
+fn test_csv_no_loopback() {
+    assert_eq!(export(&[], b','), ["backup", "psql"]);
+    assert_eq!(export(&["--no-loopback"], b','), ["psql"]);
+}
+
+/// The fields are separated with the `--delimiter` character
+#[test]
+fn test_csv_delimiter() {
+    assert_eq!(export(&["--delimiter", ";"], b';'), ["backup", "psql"]);
+
+    let output_directory = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["csv", "--delimiter", ";;"])
+        .arg(output_directory.path().join("connections.csv"))
+        .arg(output_directory.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+/// A host which cannot be parsed is left out and listed in the report, or fails the run with
+/// `--strict`. The records which cannot be used are listed as diagnostics of their host.
+#[test]
+fn test_csv_report() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let mut client_network = std::fs::OpenOptions::new()
+        .append(true)
+        .open(files_directory.path().join("client_network.csv"))
+        .unwrap();
+    client_network
+        .write_all(b"tcp,10.0.0.2:50001,,Established,201,psql\n")
+        .unwrap();
+    std::fs::write(
+        files_directory.path().join("broken_ip.csv"),
+        "IP\nnot an IP\n",
+    )
+    .unwrap();
+    std::fs::write(files_directory.path().join("broken_network.csv"), "").unwrap();
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let report_file = output_directory.path().join("report.json");
+    let read_report = || -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(&report_file).unwrap()).unwrap()
+    };
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .arg("csv")
+        .arg("--report")
+        .arg(&report_file)
+        .arg(&output_file)
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let report = read_report();
+    assert_eq!(report["success"], true);
+    let mut hosts_parsed: Vec<&str> = report["hosts_parsed"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| h.as_str().unwrap())
+        .collect();
+    hosts_parsed.sort_unstable();
+    assert_eq!(hosts_parsed, ["client", "server"]);
+    assert_eq!(report["hosts_failed"][0]["name"], "broken");
+    assert_eq!(report["diagnostics"]["client"][0]["severity"], "error");
+    assert_eq!(report["diagnostics"]["client"][0]["line"], 3);
+    assert_eq!(
+        report["diagnostics"]["client"][0]["message"],
+        "missing foreign socket for connection"
+    );
+    assert!(report["diagnostics"].get("server").is_none());
+    assert_eq!(report["connections"], 2);
+    assert_eq!(
+        report["output_files"][0].as_str().unwrap(),
+        output_file.to_str().unwrap()
+    );
+
+    std::fs::remove_file(&output_file).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["csv", "--strict", "--report"])
+        .arg(&report_file)
+        .arg(&output_file)
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+    assert!(!output_file.exists());
+    let report = read_report();
+    assert_eq!(report["success"], false);
+    assert!(report["error"].as_str().unwrap().contains("broken"));
+    assert_eq!(report["output_files"], serde_json::json!([]));
+}
+
+/// `--columns` exports the given columns in their order, and `--no-headers` leaves out the
+/// headers
+#[test]
+fn test_csv_columns() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+            .args(["csv"])
+            .args(args)
+            .arg(&output_file)
+            .arg(files_directory.path())
+            .status()
+            .unwrap()
+            .success()
+    };
+
+    assert!(run(&[
+        "--no-loopback",
+        "--columns",
+        "src_process,dst_ip,dst_port,dst_bind_address"
+    ]));
+    assert_eq!(
+        std::fs::read_to_string(&output_file).unwrap(),
+        "Source process,Dest IP,Dest port,Dest bind address\npsql,10.0.0.1,5432,0.0.0.0\n"
+    );
+
+    assert!(run(&[
+        "--no-loopback",
+        "--no-headers",
+        "--columns",
+        "dst_host"
+    ]));
+    assert_eq!(std::fs::read_to_string(&output_file).unwrap(), "server\n");
+
+    assert!(!run(&["--columns", "src_host,state"]));
+}
+
+/// The `dst_name` column names the peers after the DNS map of the capture directory, which is not
+/// taken for a host
+#[test]
+fn test_csv_dns_map() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    std::fs::write(
+        files_directory.path().join("dns.csv"),
+        "ip,name\n10.0.0.1,db.internal\n",
+    )
+    .unwrap();
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["csv"])
+        .args(["--columns", "src_process,dst_ip,dst_name"])
+        .arg(&output_file)
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut rows: Vec<String> = std::fs::read_to_string(&output_file)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    rows.sort();
+    assert_eq!(
+        rows,
+        [
+            "Source process,Dest IP,Dest name",
+            "backup,127.0.0.1,",
+            "psql,10.0.0.1,db.internal",
+        ]
+    );
+}
+
+/// The routes of the hosts are written to the `--routes` file
+#[test]
+fn test_csv_routes() {
+    let files_directory = tempfile::tempdir().unwrap();
+    std::fs::write(
+        files_directory.path().join("router.ss"),
+        "tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))\n",
+    )
+    .unwrap();
+    std::fs::write(
+        files_directory.path().join("router.linux_ip"),
+        "    inet 10.0.0.1/24\n",
+    )
+    .unwrap();
+    std::fs::write(
+        files_directory.path().join("router.linux_route"),
+        "default via 10.0.0.254 dev eth0\n10.0.0.0/24 dev eth0 proto kernel scope link\n",
+    )
+    .unwrap();
+    let output_directory = tempfile::tempdir().unwrap();
+    let routes_file = output_directory.path().join("routes.csv");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["csv", "--routes"])
+        .arg(&routes_file)
+        .arg(output_directory.path().join("connections.csv"))
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&routes_file).unwrap(),
+        "Host,Destination,Gateway,Interface\n\
+         router,0.0.0.0/0,10.0.0.254,eth0\n\
+         router,10.0.0.0/24,,eth0\n"
+    );
+}
+
+/// `--stats` prints the lines parsed and skipped for each host, the truncated and unnamed lines
+/// being counted apart
+#[test]
+fn test_csv_stats() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    std::fs::write(
+        files_directory.path().join("web.ss"),
+        "Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process\n\
+        tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))\n\
+        tcp   ESTAB  0      0      10.0.0.3:40000     10.0.0.1:5432\n\
+        tcp   ESTAB  0      0      10.0.0.3:22        10.0.0.2:50000    users:((\"sshd\",pid=200,fd=4))\n\
+        tcp   ESTAB  0      0      10.0.0.3:22        10.0.0.2:50001    users:((\"ss",
+    )
+    .unwrap();
+    std::fs::write(
+        files_directory.path().join("web.linux_ip"),
+        "    inet 10.0.0.3/24\n",
+    )
+    .unwrap();
+    let output_directory = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["csv", "--stats"])
+        .arg(output_directory.path().join("connections.csv"))
+        .arg(files_directory.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let rows: Vec<Vec<&str>> = stderr
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .skip_while(|row| row.first() != Some(&"HOST"))
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            vec![
+                "HOST",
+                "LINES",
+                "PARSED",
+                "SKIPPED",
+                "ERRORS",
+                "UNNAMED",
+                "LISTENING",
+                "CONNECTIONS"
+            ],
+            vec!["client", "1", "1", "0", "0", "0", "0", "1"],
+            vec!["server", "2", "2", "0", "0", "0", "1", "1"],
+            vec!["web", "5", "2", "3", "1", "1", "1", "1"],
+            vec!["TOTAL", "8", "5", "3", "1", "1", "2", "3"],
+        ]
+    );
+}
+
+/// `--anonymize` replaces the names in the exported rows and the report, and `--mapping-out`
+/// writes the real ones
+#[test]
+fn test_csv_anonymize() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let mapping_file = output_directory.path().join("mapping.json");
+    let report_file = output_directory.path().join("report.json");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["csv", "--no-loopback", "--anonymize", "--mapping-out"])
+        .arg(&mapping_file)
+        .arg("--report")
+        .arg(&report_file)
+        .args([
+            "--columns",
+            "src_host,src_process,dst_host,dst_process,dst_port",
+        ])
+        .arg(&output_file)
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&output_file).unwrap(),
+        "Source host,Source process,Dest host,Dest process,Dest port\nhost-01,proc-03,host-02,proc-02,5432\n"
+    );
+
+    let mapping: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&mapping_file).unwrap()).unwrap();
+    assert_eq!(
+        mapping["hosts"],
+        serde_json::json!({"host-01": "client", "host-02": "server"})
+    );
+    assert_eq!(mapping["processes"]["proc-02"], "postgres");
+
+    let report = std::fs::read_to_string(&report_file).unwrap();
+    assert!(report.contains("host-01"));
+    assert!(!report.contains("client"));
+
+    // The mapping is only written for anonymized outputs
+    let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .arg("csv")
+        .arg("--mapping-out")
+        .arg(&mapping_file)
+        .arg(&output_file)
+        .arg(files_directory.path())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+/// `--cache` saves the hosts on the first run, loads them on the next ones while the capture files
+/// do not change, and parses the files again when they do or when the cache is corrupted
+#[test]
+fn test_csv_cache() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let cache_directory = tempfile::tempdir().unwrap();
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let export = || {
+        let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+            .args(["csv", "--no-loopback", "--cache"])
+            .arg(cache_directory.path())
+            .arg(&output_file)
+            .arg(files_directory.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::read_to_string(&output_file).unwrap()
+    };
+    let cache_file = || {
+        let entries: Vec<_> = std::fs::read_dir(cache_directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        entries[0].clone()
+    };
+
+    let connections = export();
+    let cache_file = cache_file();
+    let modified = cache_file.metadata().unwrap().modified().unwrap();
+    assert_eq!(export(), connections);
+    assert_eq!(cache_file.metadata().unwrap().modified().unwrap(), modified);
+
+    // A new host in the capture directory
+    HostBuilder::new("other_client")
+        .ip("10.0.0.3")
+        .connect_tcp("10.0.0.3:50000", "10.0.0.1:5432", "pg_dump", 300)
+        .build()
+        .write_csv_files(files_directory.path())
+        .unwrap();
+    let connections = export();
+    assert!(connections.contains("pg_dump"));
+
+    std::fs::write(&cache_file, "not a cache").unwrap();
+    assert_eq!(export(), connections);
+}
+
+/// With `--collapse-docker-proxy`, the client of a published port is exported connected to the
+/// process of the container instead of docker-proxy
+#[test]
+fn test_csv_collapse_docker_proxy() {
+    let files_directory = tempfile::tempdir().unwrap();
+    HostBuilder::new("docker01")
+        .ip("10.0.0.5")
+        .ip("172.17.0.1")
+        .listen_tcp(8080, "docker-proxy", 2301)
+        .connect_tcp("172.17.0.1:46790", "172.17.0.2:80", "docker-proxy", 2301)
+        .build()
+        .write_csv_files(files_directory.path())
+        .unwrap();
+    HostBuilder::new("webapp")
+        .ip("172.17.0.2")
+        .listen_tcp(80, "nginx", 1)
+        .build()
+        .write_csv_files(files_directory.path())
+        .unwrap();
+    HostBuilder::new("laptop")
+        .ip("10.0.0.2")
+        .connect_tcp("10.0.0.2:51000", "10.0.0.5:8080", "firefox", 4000)
+        .build()
+        .write_csv_files(files_directory.path())
+        .unwrap();
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let export = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+            .args([
+                "csv",
+                "--no-headers",
+                "--columns",
+                "src_process,dst_process",
+            ])
+            .args(args)
+            .arg(&output_file)
+            .arg(files_directory.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut rows: Vec<String> = std::fs::read_to_string(&output_file)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        rows.sort();
+        rows
+    };
+
+    assert_eq!(export(&[]), ["docker-proxy,nginx", "firefox,docker-proxy"]);
+    assert_eq!(export(&["--collapse-docker-proxy"]), ["firefox,nginx"]);
+}
+
+/// The UDP peers seen from both ends are exported as a single row going both ways, unless
+/// `--keep-both-directions` is set
+#[test]
+fn test_csv_bidirectional() {
+    let files_directory = tempfile::tempdir().unwrap();
+    for host in sockets_map::test_utils::udp_symmetric() {
+        host.write_csv_files(files_directory.path()).unwrap();
+    }
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let export = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+            .args([
+                "csv",
+                "--no-headers",
+                "--columns",
+                "src_host,dst_host,direction",
+            ])
+            .args(args)
+            .arg(&output_file)
+            .arg(files_directory.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::read_to_string(&output_file).unwrap()
+    };
+
+    assert_eq!(export(&[]), "ntp1,ntp2,both\n");
+    let rows = export(&["--keep-both-directions"]);
+    assert!(rows.contains("ntp1,ntp2,out\n"));
+    assert!(rows.contains("ntp2,ntp1,out\n"));
+}
+
+/// The provenance is written as comments after the rows, naming the version, the capture directory
+/// and the options, with `--provenance` only
+#[test]
+fn test_csv_provenance() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("connections.csv");
+    let export = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .args(["csv", "--no-loopback", "--columns", "src_process"])
+            .args(args)
+            .arg(&output_file)
+            .arg(files_directory.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::read_to_string(&output_file).unwrap()
+    };
+
+    assert_eq!(export(&[]), "Source process\npsql\n");
+
+    let output = export(&["--provenance"]);
+    let (rows, comments) = output.split_at(output.find('#').unwrap());
+    assert_eq!(rows, "Source process\npsql\n");
+    let comments: Vec<&str> = comments.lines().collect();
+    assert_eq!(comments.len(), 4);
+    assert_eq!(
+        comments[0],
+        concat!("# Software: sockets_map ", env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(comments[1], "# Creation Time: 2023-11-14T22:13:20Z");
+    assert!(comments[2].starts_with(&format!(
+        "# Source: {} (sha1 ",
+        files_directory.path().display()
+    )));
+    assert!(comments[3].starts_with("# Options: csv --no-loopback --columns src_process "));
+}
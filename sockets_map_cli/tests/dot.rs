@@ -0,0 +1,366 @@
+//! Run the graph subcommand with a DOT output, which must not need Graphviz.
+
+#![cfg(unix)]
+
+use sockets_map::{connections_model, graphs, parsers::directory_scanner, test_utils::HostBuilder};
+use std::{os::unix::fs::PermissionsExt, path::Path, process::Command};
+
+/// Write the capture files of a SSH server and of its client
+fn write_hosts(files_directory: &Path) {
+    HostBuilder::new("server")
+        .ip("10.0.0.1")
+        .listen_tcp(22, "sshd", 100)
+        .connect_tcp("10.0.0.1:40000", "10.0.0.1:22", "backup", 101)
+        .build()
+        .write_csv_files(files_directory)
+        .unwrap();
+    HostBuilder::new("client")
+        .ip("10.0.0.2")
+        .connect_tcp("10.0.0.2:50000", "10.0.0.1:22", "ssh", 200)
+        .build()
+        .write_csv_files(files_directory)
+        .unwrap();
+}
+
+/// Remove the edge colors from DOT code, since they are picked at random
+fn without_colors(dot_code: &str) -> String {
+    regex::Regex::new(r#"color="[0-9.,+]+""#)
+        .unwrap()
+        .replace_all(dot_code, "color=\"\"")
+        .to_string()
+}
+
+/// Remove the provenance comments which end the DOT code
+fn without_provenance(dot_code: &str) -> String {
+    dot_code
+        .split_once("\n// ")
+        .map_or(dot_code, |(dot_code, _)| dot_code)
+        .to_string()
+}
+
+/// DOT code of the captures, with the default graph options
+fn expected_dot(files_directory: &Path) -> String {
+    let scanned_hosts = directory_scanner::scan_dir(files_directory).unwrap();
+    let hosts = directory_scanner::build_hosts(&scanned_hosts).unwrap();
+    let connections = connections_model::build_connections_list(&hosts, false);
+    let dot_code = graphs::create_graph(&connections, &graphs::DrawOptions::default()).unwrap();
+    assert!(dot_code.contains("client_ssh->"));
+    dot_code
+}
+
+/// Run the graph subcommand with a fake `dot` command in the PATH, which leaves a marker file if
+/// it is ever spawned
+fn run_graph(output: &Path, files_directory: &Path, bin_directory: &Path) -> std::process::Output {
+    run_graph_with_args(&[], output, files_directory, bin_directory)
+}
+
+/// Same as [`run_graph`], with extra options
+fn run_graph_with_args(
+    args: &[&str],
+    output: &Path,
+    files_directory: &Path,
+    bin_directory: &Path,
+) -> std::process::Output {
+    let fake_dot = bin_directory.join("dot");
+    std::fs::write(
+        &fake_dot,
+        format!("#!/bin/sh\n: > {:?}\n", bin_directory.join("spawned")),
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_dot, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .env("PATH", bin_directory)
+        .arg("graph")
+        .args(args)
+        .arg(output)
+        .arg(files_directory)
+        .output()
+        .unwrap()
+}
+
+/// `.dot` and `.gv` outputs get the DOT code, without running Graphviz
+#[test]
+fn test_dot_file() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let output_directory = tempfile::tempdir().unwrap();
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    for file_name in ["graph.dot", "graph.gv"] {
+        let output_file = output_directory.path().join(file_name);
+        let output = run_graph(&output_file, files_directory.path(), bin_directory.path());
+        assert!(output.status.success());
+        let dot_code = std::fs::read_to_string(&output_file).unwrap();
+        assert!(dot_code.contains("\n// Software: sockets_map "));
+        assert_eq!(
+            without_colors(&without_provenance(&dot_code)),
+            without_colors(&expected_dot(files_directory.path()))
+        );
+    }
+    assert!(!bin_directory.path().join("spawned").exists());
+}
+
+/// `-` writes the DOT code to stdout, without running Graphviz, and `--no-provenance` leaves the
+/// provenance comments out
+#[test]
+fn test_dot_stdout() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    let output = run_graph_with_args(
+        &["--no-provenance"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        without_colors(&String::from_utf8(output.stdout).unwrap()),
+        without_colors(&expected_dot(files_directory.path()))
+    );
+    assert!(!bin_directory.path().join("spawned").exists());
+
+    // Other outputs still go through Graphviz
+    let output_directory = tempfile::tempdir().unwrap();
+    let output_file = output_directory.path().join("graph.svg");
+    run_graph(&output_file, files_directory.path(), bin_directory.path());
+    assert!(bin_directory.path().join("spawned").exists());
+}
+
+/// `--loopback collapse` replaces the loopback connections with a single node, and the deprecated
+/// `--no-loopback` still hides them
+#[test]
+fn test_dot_loopback() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+    let run = |args: &[&str]| {
+        let output = run_graph_with_args(
+            args,
+            Path::new("-"),
+            files_directory.path(),
+            bin_directory.path(),
+        );
+        (
+            output.status.success(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+
+    let (success, dot_code) = run(&[]);
+    assert!(success);
+    assert!(dot_code.contains("backup"));
+
+    let (success, dot_code) = run(&["--loopback", "collapse"]);
+    assert!(success);
+    assert!(!dot_code.contains("backup"));
+    assert!(dot_code.contains("1 local connection among 2 processes"));
+    assert!(dot_code.contains("client_ssh->"));
+
+    for args in [&["--loopback", "hide"][..], &["--no-loopback"]] {
+        let (success, dot_code) = run(args);
+        assert!(success);
+        assert!(!dot_code.contains("backup") && !dot_code.contains("local connection"));
+    }
+
+    let (success, _) = run(&["--loopback", "hide", "--no-loopback"]);
+    assert!(!success);
+}
+
+/// `--summarize-listeners` draws the listening ports of the server as a single node, which the
+/// connection of the client targets
+#[test]
+fn test_dot_summarize_listeners() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    let output = run_graph_with_args(
+        &["--summarize-listeners"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    let dot_code = String::from_utf8(output.stdout).unwrap();
+    assert!(dot_code.contains("\"tcp: 22\""));
+    assert!(dot_code.contains("client_ssh->cluster_server_listeners"));
+    assert!(dot_code.contains("label=\"tcp:22\""));
+    assert!(dot_code.contains("Listening ports"));
+    assert!(!dot_code.contains("Listening process"));
+}
+
+/// `--host-level` draws the hosts only, the connection of the client being labeled with its port
+#[test]
+fn test_dot_host_level() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    let output = run_graph_with_args(
+        &["--host-level"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    let dot_code = String::from_utf8(output.stdout).unwrap();
+    assert!(dot_code.contains("cluster_client->cluster_server"));
+    assert!(dot_code.contains("label=\"1 connection: tcp:22\""));
+    assert!(!dot_code.contains("client_ssh"));
+    assert!(!dot_code.contains("Listening process"));
+}
+
+/// `--legend-caption` adds a line to the legend, which cannot be hidden at the same time
+#[test]
+fn test_dot_legend_caption() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    let output = run_graph_with_args(
+        &["--legend-caption", "Capture of the 17th"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    let dot_code = String::from_utf8(output.stdout).unwrap();
+    assert!(dot_code.contains("label=\"Capture of the 17th\""));
+
+    let output = run_graph_with_args(
+        &["--legend-caption", "Capture of the 17th", "--hide-legend"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(!output.status.success());
+}
+
+/// `--strict` refuses the rendering options giving a cropped graph before running Graphviz, while
+/// the transparent background of JPEG graphs is only corrected
+#[test]
+fn test_dot_strict_options() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let output_directory = tempfile::tempdir().unwrap();
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    let output = run_graph_with_args(
+        &["--strict", "--dpi", "300"],
+        &output_directory.path().join("graph.svg"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("cropped"));
+    assert!(!bin_directory.path().join("spawned").exists());
+
+    let dump = output_directory.path().join("graph.dot");
+    let output = run_graph_with_args(
+        &[
+            "--strict",
+            "--transparent-bg",
+            "--dump",
+            dump.to_str().unwrap(),
+        ],
+        &output_directory.path().join("graph.jpeg"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    assert!(bin_directory.path().join("spawned").exists());
+    let dot_code = std::fs::read_to_string(dump).unwrap();
+    assert!(dot_code.contains("bgcolor=white"));
+    assert!(!dot_code.contains("transparent"));
+}
+
+/// `--focus` leaves out the connections which do not involve the focus hosts, and refuses the
+/// unknown hosts
+#[test]
+fn test_dot_focus() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+
+    let output = run_graph_with_args(
+        &["--focus", "client"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    let dot_code = String::from_utf8(output.stdout).unwrap();
+    assert!(dot_code.contains("client_ssh->"));
+    assert!(!dot_code.contains("backup"));
+
+    let output = run_graph_with_args(
+        &["--focus", "client", "--focus-depth", "2"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("backup"));
+
+    let output = run_graph_with_args(
+        &["--focus", "router"],
+        Path::new("-"),
+        files_directory.path(),
+        bin_directory.path(),
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("router"));
+}
+
+/// `--rank-group` and the `--layout-hints` file put the hosts on the same rank with dot, and the
+/// positions of the file are pinned with neato
+#[test]
+fn test_dot_layout_hints() {
+    let files_directory = tempfile::tempdir().unwrap();
+    write_hosts(files_directory.path());
+    let bin_directory = tempfile::tempdir().unwrap();
+    let hints_directory = tempfile::tempdir().unwrap();
+    let layout_hints = hints_directory.path().join("layout.toml");
+    std::fs::write(
+        &layout_hints,
+        "[hosts.server]\npos = \"0,300\"\n[hosts.client]\nrank_group = \"office\"\n",
+    )
+    .unwrap();
+    let layout_hints = layout_hints.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let output = run_graph_with_args(
+            args,
+            Path::new("-"),
+            files_directory.path(),
+            bin_directory.path(),
+        );
+        (
+            output.status.success(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+
+    let (success, dot_code) = run(&["--layout-hints", layout_hints, "--rank-group", "dmz=server"]);
+    assert!(success);
+    assert!(dot_code.contains(
+        "subgraph {graph [rank=same;];cluster_server;};subgraph {graph [rank=same;];cluster_client;};"
+    ));
+    assert!(!dot_code.contains("pos="));
+
+    let (success, dot_code) = run(&["--layout-hints", layout_hints, "--layout-engine", "neato"]);
+    assert!(success);
+    assert!(dot_code.contains("cluster_server[pos=\"0,300!\";];"));
+    assert!(!dot_code.contains("rank=same"));
+
+    // Invalid hints
+    let (success, _) = run(&["--rank-group", "dmz"]);
+    assert!(!success);
+    std::fs::write(layout_hints, "[hosts.server]\npos = \"0\"\n").unwrap();
+    let (success, _) = run(&["--layout-hints", layout_hints]);
+    assert!(!success);
+}
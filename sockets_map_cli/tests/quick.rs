@@ -0,0 +1,88 @@
+//! Run the quick subcommand on a network command output piped to stdin.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const SS_OUTPUT: &str = "\
+Netid State  Recv-Q Send-Q Local Address:Port Peer Address:Port Process
+tcp   LISTEN 0      128    0.0.0.0:22         0.0.0.0:*         users:((\"sshd\",pid=100,fd=3))
+tcp   ESTAB  0      0      127.0.0.1:40000    127.0.0.1:22      users:((\"backup\",pid=101,fd=4))
+tcp   ESTAB  0      0      127.0.0.1:22       127.0.0.1:40000   users:((\"sshd\",pid=102,fd=4))
+";
+
+/// Run the quick subcommand with `args`, piping `input` to it
+fn quick(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .arg("quick")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// The loopback connections of the host must be exported as CSV, or drawn as DOT code
+#[test]
+fn test_quick_outputs() {
+    let output = quick(
+        &[
+            "--host",
+            "server",
+            "--ips",
+            "10.0.0.1 fd00::1 ",
+            "--format",
+            "csv",
+        ],
+        SS_OUTPUT,
+    );
+    assert!(output.status.success());
+    let mut reader = csv::Reader::from_reader(output.stdout.as_slice());
+    let processes: Vec<String> = reader
+        .records()
+        .map(|record| record.unwrap()[2].to_string())
+        .collect();
+    assert_eq!(processes, ["backup"]);
+
+    let output_directory = tempfile::tempdir().unwrap();
+    let dot_file = output_directory.path().join("graph.dot");
+    let output = quick(
+        &[
+            "--host",
+            "server",
+            "--ip",
+            "10.0.0.1",
+            "--out",
+            dot_file.to_str().unwrap(),
+        ],
+        SS_OUTPUT,
+    );
+    assert!(output.status.success());
+    let dot_code = std::fs::read_to_string(dot_file).unwrap();
+    assert!(dot_code.contains("server"));
+    assert!(dot_code.contains("backup"));
+}
+
+/// Unknown outputs, invalid addresses and Graphviz formats on stdout must be refused
+#[test]
+fn test_quick_errors() {
+    let output = quick(&["--host", "server"], "inet 10.0.0.1/24\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unable to recognize"));
+
+    let output = quick(&["--host", "server", "--ip", "10.0.0"], SS_OUTPUT);
+    assert!(!output.status.success());
+
+    let output = quick(&["--host", "server", "--format", "png"], SS_OUTPUT);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--out"));
+}
@@ -0,0 +1,142 @@
+//! Run the headless server, and talk to it as an agent would.
+
+#![cfg(unix)]
+
+use sockets_map::{
+    host::{Connection, Host, ListeningSocket, Process, SocketType},
+    parsers::directory_scanner,
+    server::{
+        client::Update,
+        message::{Message, Register},
+    },
+};
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// Get a free TCP port on the loopback interface
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Host sent by the fake agent
+fn make_host() -> Host {
+    let mut host = Host::new("machine1");
+    host.add_ip("10.0.0.1".parse().unwrap());
+    host.add_listening_socket(ListeningSocket::new(
+        "0.0.0.0:22".parse().unwrap(),
+        SocketType::TCP,
+        Process::new("sshd", 100, "machine1".to_string()),
+        "machine1".to_string(),
+        None,
+    ));
+    host.add_established_connection(Connection::new(
+        "10.0.0.1:50000".parse().unwrap(),
+        "10.0.0.2:443".parse().unwrap(),
+        SocketType::TCP,
+        Process::new("curl", 200, "machine1".to_string()),
+    ));
+    host
+}
+
+/// Wait until `path` exists
+async fn wait_for_file(path: &Path) {
+    for _ in 0..100 {
+        if path.exists() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("timeout while waiting for {path:?}");
+}
+
+/// The server must request an update on registration, write it to the output directory, and
+/// disconnect the agent on Ctrl-C
+#[tokio::test]
+async fn test_serve() {
+    let port = free_port();
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args([
+            "serve",
+            "--address",
+            "127.0.0.1",
+            "--port",
+            &port.to_string(),
+        ])
+        .args([
+            "--update-on-connect",
+            "--dump-interval",
+            "0.2",
+            "--output-dir",
+        ])
+        .arg(output_dir.path())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Connect as an agent, once the server is up
+    let mut channel = None;
+    for _ in 0..100 {
+        match tsyncp::channel::channel_to(format!("127.0.0.1:{port}")).await {
+            Ok(c) => {
+                channel = Some(c);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    let channel: tsyncp::channel::BincodeChannel<Message> =
+        channel.expect("unable to connect to the server");
+    let (mut rx, mut tx) = channel.split();
+    tx.send(Message::Register(Register::new(
+        "machine1".to_string(),
+        None,
+        vec!["10.0.0.1".parse().unwrap()],
+    )))
+    .await
+    .unwrap();
+
+    // Update requested on registration
+    let message = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+        .await
+        .unwrap();
+    assert!(matches!(message, Some(Ok(Message::UpdateRequest))));
+    tx.send(Message::Update(Update::new(make_host())))
+        .await
+        .unwrap();
+
+    // Periodic capture files
+    let network_file = output_dir.path().join("machine1_network.csv");
+    wait_for_file(&network_file).await;
+
+    // Ctrl-C
+    let status = Command::new("kill")
+        .args(["-INT", &server.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let message = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+        .await
+        .unwrap();
+    assert!(matches!(message, Some(Ok(Message::Exit))));
+    // Answered like the real agent does, for the server not to wait for the end of its timeout
+    tx.send(Message::Exit).await.unwrap();
+    let exit_status = tokio::task::spawn_blocking(move || server.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(exit_status.success());
+
+    // The capture files must give back the host
+    let scanned_hosts = directory_scanner::scan_dir(output_dir.path()).unwrap();
+    let hosts = directory_scanner::build_hosts(&scanned_hosts).unwrap();
+    assert_eq!(hosts, vec![make_host()]);
+}
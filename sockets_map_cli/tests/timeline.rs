@@ -0,0 +1,118 @@
+//! Run the timeline subcommand on two captures, with DOT outputs which do not need Graphviz.
+
+use sockets_map::host::{Connection, Host, ListeningSocket, Process, SocketType};
+use std::{
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+/// Write the capture files of a server and of its client in `capture_dir`, dated `time`. The
+/// client connects to SSH, and also to HTTP if `with_http`.
+fn write_capture(capture_dir: &Path, time: SystemTime, with_http: bool) {
+    std::fs::create_dir(capture_dir).unwrap();
+    let mut server = Host::new("server");
+    server.add_ip("10.0.0.1".parse().unwrap());
+    for (port, process) in [(22, "sshd"), (80, "nginx")] {
+        server.add_listening_socket(ListeningSocket::new(
+            format!("0.0.0.0:{port}").parse().unwrap(),
+            SocketType::TCP,
+            Process::new(process, 100 + port as u32, "server".to_string()),
+            "server".to_string(),
+            None,
+        ));
+    }
+    server.write_csv_files(capture_dir).unwrap();
+
+    let mut client = Host::new("client");
+    client.add_ip("10.0.0.2".parse().unwrap());
+    client.add_established_connection(Connection::new(
+        "10.0.0.2:50000".parse().unwrap(),
+        "10.0.0.1:22".parse().unwrap(),
+        SocketType::TCP,
+        Process::new("ssh", 200, "client".to_string()),
+    ));
+    if with_http {
+        client.add_established_connection(Connection::new(
+            "10.0.0.2:50001".parse().unwrap(),
+            "10.0.0.1:80".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("curl", 201, "client".to_string()),
+        ));
+    }
+    client.write_csv_files(capture_dir).unwrap();
+
+    for entry in capture_dir.read_dir().unwrap() {
+        std::fs::File::options()
+            .write(true)
+            .open(entry.unwrap().path())
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+}
+
+/// A connection seen in both captures must be in both slices, and one seen in the last capture
+/// only in the last slice
+#[test]
+fn test_timeline() {
+    let files_directory = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    write_capture(&files_directory.path().join("capture1"), start, false);
+    write_capture(
+        &files_directory.path().join("capture2"),
+        start + Duration::from_secs(90),
+        true,
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .args(["timeline", "--window", "1m", "--format", "dot"])
+        .arg(output_dir.path())
+        .arg(files_directory.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let index = std::fs::read_to_string(output_dir.path().join("index.csv")).unwrap();
+    let lines: Vec<&str> = index.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "Start,End,Connections,File",
+            "2023-11-14T22:13:20+00:00,2023-11-14T22:14:20+00:00,1,20231114T221320Z-20231114T221420Z.dot",
+            "2023-11-14T22:14:20+00:00,2023-11-14T22:15:20+00:00,2,20231114T221420Z-20231114T221520Z.dot",
+        ]
+    );
+
+    let first = std::fs::read_to_string(
+        output_dir
+            .path()
+            .join("20231114T221320Z-20231114T221420Z.dot"),
+    )
+    .unwrap();
+    assert!(first.contains("client_ssh->"));
+    assert!(!first.contains("client_curl->"));
+    let second = std::fs::read_to_string(
+        output_dir
+            .path()
+            .join("20231114T221420Z-20231114T221520Z.dot"),
+    )
+    .unwrap();
+    assert!(second.contains("client_ssh->"));
+    assert!(second.contains("client_curl->"));
+}
+
+/// A files directory without capture subdirectories must be an error
+#[test]
+fn test_timeline_without_captures() {
+    let files_directory = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sockets_map"))
+        .arg("timeline")
+        .arg(output_dir.path())
+        .arg(files_directory.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
@@ -1,16 +1,26 @@
 mod app_msgs;
+mod appearance;
+mod auto_refresh;
+mod connections_table;
+mod export;
 mod files;
+mod generation;
 mod graph_options;
 mod help;
+mod home;
+mod notifications;
+mod preferences;
+mod preview_files;
 mod server;
+mod session;
+mod shortcuts;
 
-use anyhow::bail;
 use gtk::{
     glib::clone,
-    prelude::FileExt,
+    prelude::{FileChooserExtManual, FileExt},
     traits::{
-        BoxExt, ButtonExt, FileChooserExt, GtkWindowExt, NativeDialogExt, ToggleButtonExt,
-        WidgetExt,
+        BoxExt, ButtonExt, DialogExt, EditableExt, FileChooserExt, GtkWindowExt, NativeDialogExt,
+        PopoverExt, ToggleButtonExt, WidgetExt,
     },
     FileChooser, FileFilter,
 };
@@ -19,43 +29,98 @@ use relm4::{
     MessageBroker, RelmContainerExt,
 };
 use sockets_map::{
-    host::Host,
-    parsers::directory_scanner::ScannedHost,
-    server::{client::Client, message::Message},
+    connections_model::ConnectionRecord,
+    host::{DisplayOverride, Host},
+    provenance::Provenance,
+    server::{
+        client::Client,
+        event_log::{EventKind, EventLog},
+        message::Message,
+        ServerState as ListenState,
+    },
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, watch, RwLock};
 use tokio_util::sync::CancellationToken;
 use tsyncp::{self, broadcast::BincodeSender};
 
 use self::{
-    app_msgs::{AppCmdOutput, GraphMsg, ServerMsg},
-    files::{FilesOptions, FilesPageWidgets},
+    app_msgs::{AppCmdOutput, GraphMsg, ServerMsg, ServerOption, SessionMsg},
+    appearance::Appearance,
+    files::{FilesOptions, FilesPageWidgets, InputFolder},
+    generation::GenerationStatus,
     graph_options::{GraphOptions, GraphPageWidgets, DEFAULT_DPI},
     help::HelpWindow,
+    notifications::{Notification, Notifications, Severity},
+    preferences::Preferences,
+    preview_files::PreviewFiles,
     server::{
         client::{ClientInfo, ClientLabelMsg},
-        ServerPageWidgets,
+        recorder, ServerPageWidgets,
     },
+    session::{GraphSettings, Session, WindowLayout},
+    shortcuts::ShortcutAction,
 };
 
 static HELP_WINDOW_BROKER: MessageBroker<help::HelpWindow> = MessageBroker::new();
+/// Time to wait for the clients to disconnect when stopping the server
+const SERVER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tracker::track]
 pub struct AppModel {
+    /// Temporary files of the graph preview, in the format in which it is rendered
     #[tracker::do_not_track]
-    /// A temporary file that will receive the generated graph
-    image_graph_tempfile: tempfile::NamedTempFile,
-    /// The error message to be shown in the info bar
-    error_message: Option<String>,
+    preview_files: PreviewFiles,
+    /// DOT code of the generated graph, to show it and to export it in another format than the
+    /// preview
+    graph_dot_code: String,
+    /// Whether the DOT code is shown instead of the graph image
+    show_dot_code: bool,
+    /// Whether the home page is shown instead of the generated graph
+    show_home: bool,
+    /// The message shown in the info bar
+    notification: Option<Notification>,
+    /// Last messages, listed in the messages popover
+    notifications: Notifications,
     /// Whether the graph is being generated or not
     generating_graph: bool,
+    /// Outcome of the last generation, shown in the window subtitle
+    generation_status: Option<GenerationStatus>,
+    /// Cancels the graph generation in progress
+    #[tracker::do_not_track]
+    generation_cancel_token: CancellationToken,
     pub graph_image_path: Option<PathBuf>,
+    /// Whether the input folders changed since the graph shown was generated, which cannot be
+    /// exported until it is generated again
+    graph_outdated: bool,
+    /// Zoom factor of the graph preview, or `None` to fit it in the view
+    graph_zoom: Option<f64>,
+    /// Connections of the last generated graph
+    connection_records: Vec<ConnectionRecord>,
+    /// Hosts of the last generated graph, to export it anonymized
+    #[tracker::do_not_track]
+    graph_hosts: Vec<Host>,
+    /// Number of hosts and processes highlighted in the last generated graph
+    highlight_matches: Option<usize>,
+    /// Server listen settings, as edited in the server page
+    #[tracker::do_not_track]
+    server_option: ServerOption,
+    /// Appearance of the application, as set in the preferences window
+    #[tracker::do_not_track]
+    appearance: Appearance,
+    /// Whether the provenance is embedded in the exported files, as set in the preferences window
+    #[tracker::do_not_track]
+    embed_provenance: bool,
+    /// Session opened from a file, to be shown in the widgets
+    #[tracker::no_eq]
+    session_to_apply: Option<Session>,
+    /// Recently opened or saved session files, most recent first
+    recent_sessions: Vec<PathBuf>,
     /// Input files parameters
     #[tracker::do_not_track]
     files_options: FilesOptions,
@@ -68,26 +133,72 @@ pub struct AppModel {
     /// Client labels to show the list
     #[tracker::do_not_track]
     clients: FactoryVecDeque<server::client::ClientLabel>,
+    /// Names and colors of the hosts edited in the client details, by hostname
+    #[tracker::do_not_track]
+    display_overrides: BTreeMap<String, DisplayOverride>,
     #[tracker::do_not_track]
     /// Recording indicator used by the recorder timer
     recording_since: Option<std::time::Instant>,
+    /// Interval of the running recording, in seconds
+    #[tracker::do_not_track]
+    recording_interval: f64,
+    /// Updates received from the clients since the recording started
+    #[tracker::do_not_track]
+    recording_updates: usize,
+    /// Whether the graph is regenerated periodically while recording
+    #[tracker::do_not_track]
+    auto_refresh: bool,
+    /// Interval between two refreshes, in seconds
+    #[tracker::do_not_track]
+    auto_refresh_interval: f64,
+    /// Stops the periodic refreshes
+    #[tracker::do_not_track]
+    auto_refresh_token: Option<CancellationToken>,
+    /// Dropped once the refresh in progress is done, to let the next one start
+    #[tracker::do_not_track]
+    auto_refresh_done: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Debug)]
 pub enum AppMsg {
-    Error(Option<String>),
+    /// Show a message in the info bar, and keep it in the messages history
+    Notify(Severity, String),
+    /// Hide the info bar
+    DismissNotification,
     ServerMsg(ServerMsg),
     GraphMsg(GraphMsg),
+    SessionMsg(SessionMsg),
+    /// Set the appearance of the application, from the preferences window
+    SetAppearance(Appearance),
+    /// Embed the provenance in the exported files or not, from the preferences window
+    SetEmbedProvenance(bool),
+    /// The window is closed, with its size
+    Quit {
+        width: i32,
+        height: i32,
+    },
 }
 
 #[allow(unused)]
 pub struct AppWidgets {
+    title: adw::WindowTitle,
     info_bar_msg: gtk::Label,
     info_bar: gtk::InfoBar,
+    notifications_box: gtk::Box,
+    highlight_matches_label: gtk::Label,
     files_page_widgets: FilesPageWidgets,
     graph_page_widgets: GraphPageWidgets,
-    export_graph_button: gtk::Button,
+    export_graph_button: adw::SplitButton,
     open_graph_button: gtk::Button,
+    zoom_fit_button: gtk::Button,
+    zoom_original_button: gtk::Button,
+    view_dot_button: gtk::ToggleButton,
+    home_button: gtk::ToggleButton,
+    session_popover: gtk::Popover,
+    recent_sessions_box: gtk::Box,
+    /// Resized to the layout of the opened sessions, with the sidebar
+    window: adw::Window,
+    flap: adw::Flap,
     server_page_widgets: ServerPageWidgets,
     #[allow(unused)]
     help_window: Controller<HelpWindow>,
@@ -102,19 +213,11 @@ impl Component for AppModel {
     type Widgets = AppWidgets;
 
     fn init_root() -> Self::Root {
-        #[cfg(target_os = "windows")]
-        set_dark_theme();
-
         let window = adw::Window::builder()
             .default_width(1000)
             .default_height(600)
             .title("Socket Map")
             .build();
-        window.connect_close_request(move |w| {
-            w.close();
-            std::process::exit(0);
-        });
-
         window
     }
 
@@ -186,9 +289,24 @@ impl Component for AppModel {
         let (server_page_widgets, clients) =
             server::init_sidebar_server_widgets(&sidebar_stack, sender.clone());
 
+        // Preferences of the last run, the widgets send them to the model
+        let preferences = Preferences::load();
+        appearance::apply(preferences.appearance);
+        app_window.set_default_size(preferences.window_width, preferences.window_height);
+        graph_options::apply_graph_settings(&graph_page_widgets.options, &preferences.graph);
+        server::apply_server_option(&server_page_widgets, &preferences.server);
+        app_window.connect_close_request(clone!(@strong sender => move |window| {
+            let (width, height) = window.default_size();
+            sender.input(AppMsg::Quit { width, height });
+            gtk::Inhibit(true)
+        }));
+
         // Sidebar files widgets
         let files_page_widgets =
             files::init_sidebar_files_widgets(&sidebar_stack, sender.clone(), app_window);
+        let mut recent_folders = preferences.recent_folders;
+        recent_folders.prune();
+        files::fill_recent_folders_box(&files_page_widgets, &recent_folders, &sender);
 
         // File chooser
         let file_chooser = gtk::FileChooserNative::new(
@@ -204,13 +322,18 @@ impl Component for AppModel {
         filter.add_mime_type("image/png");
         filter.add_mime_type("image/jpeg");
         filter.add_mime_type("text/csv");
+        filter.add_mime_type("application/json");
+        filter.add_pattern("*.dot");
+        filter.add_pattern("*.gv");
         file_chooser.set_filter(&filter);
+        file_chooser.add_choice("anonymize", "Anonymize hosts, IPs and processes", &[]);
         file_chooser.connect_response(
             clone!(@strong sender  => move |file_chooser, response_type| {
                 if response_type == gtk::ResponseType::Accept {
                     let chooser: FileChooser = file_chooser.to_owned().into();
-                    if let Some(file) = chooser.file().and_then(|d| d.path()) {
-                        sender.input(AppMsg::GraphMsg(GraphMsg::ExportGraph(file)));
+                    if let Some(path) = chooser.file().and_then(|d| d.path()) {
+                        let anonymize = chooser.choice("anonymize").as_deref() == Some("true");
+                        sender.input(AppMsg::GraphMsg(GraphMsg::ExportGraph { path, anonymize }));
                     }
                 }
 
@@ -218,6 +341,144 @@ impl Component for AppModel {
             }),
         );
 
+        // Export all chooser, the chosen file name being the one of every exported file
+        let export_all_chooser = gtk::FileChooserNative::new(
+            Some("Export all"),
+            Some(app_window),
+            gtk::FileChooserAction::Save,
+            Some("Export"),
+            Some("Cancel"),
+        );
+        export_all_chooser.set_current_name("graph");
+        export_all_chooser.connect_response(
+            clone!(@strong sender => move |file_chooser, response_type| {
+                if response_type == gtk::ResponseType::Accept {
+                    let chooser: FileChooser = file_chooser.to_owned().into();
+                    if let Some(path) = chooser.file().and_then(|f| f.path()) {
+                        sender.input(AppMsg::GraphMsg(GraphMsg::ExportAll {
+                            path,
+                            overwrite: false,
+                        }));
+                    }
+                }
+                file_chooser.hide();
+            }),
+        );
+
+        // Session menu
+        let session_file_filter = FileFilter::new();
+        session_file_filter.add_pattern("*.json");
+        let open_session_chooser = gtk::FileChooserNative::new(
+            Some("Open session"),
+            Some(app_window),
+            gtk::FileChooserAction::Open,
+            Some("Open"),
+            Some("Cancel"),
+        );
+        open_session_chooser.set_filter(&session_file_filter);
+        let save_session_chooser = gtk::FileChooserNative::new(
+            Some("Save session"),
+            Some(app_window),
+            gtk::FileChooserAction::Save,
+            Some("Save"),
+            Some("Cancel"),
+        );
+        save_session_chooser.set_filter(&session_file_filter);
+        let window = app_window.clone();
+        let graph_paned = graph_page_widgets.graph_paned.clone();
+        for chooser in [&open_session_chooser, &save_session_chooser] {
+            chooser.connect_response(
+                clone!(@strong sender, @strong window, @strong flap, @strong graph_paned => move |file_chooser, response_type| {
+                    if response_type == gtk::ResponseType::Accept {
+                        let chooser: FileChooser = file_chooser.to_owned().into();
+                        if let Some(path) = chooser.file().and_then(|f| f.path()) {
+                            let msg = match chooser.action() {
+                                gtk::FileChooserAction::Save => SessionMsg::Save(
+                                    path,
+                                    window_layout(&window, &flap, &graph_paned),
+                                ),
+                                _ => SessionMsg::Open(path),
+                            };
+                            sender.input(AppMsg::SessionMsg(msg));
+                        }
+                    }
+                    file_chooser.hide();
+                }),
+            );
+        }
+        let session_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(5)
+            .build();
+        let session_popover = gtk::Popover::builder().child(&session_box).build();
+        let open_session_button = gtk::Button::builder()
+            .label("Open session…")
+            .has_frame(false)
+            .build();
+        open_session_button.connect_clicked(
+            clone!(@strong open_session_chooser, @strong session_popover => move |_| {
+                session_popover.popdown();
+                open_session_chooser.show();
+            }),
+        );
+        let save_session_button = gtk::Button::builder()
+            .label("Save session…")
+            .has_frame(false)
+            .build();
+        save_session_button.connect_clicked(
+            clone!(@strong save_session_chooser, @strong session_popover => move |_| {
+                session_popover.popdown();
+                save_session_chooser.show();
+            }),
+        );
+        let recent_sessions_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .build();
+        session_box.append(&open_session_button);
+        session_box.append(&save_session_button);
+        session_box.append(&gtk::Separator::new(gtk::Orientation::Horizontal));
+        session_box.append(
+            &gtk::Label::builder()
+                .label("<b>Recent sessions</b>")
+                .use_markup(true)
+                .build(),
+        );
+        session_box.append(&recent_sessions_box);
+        let recent_sessions = session::load_recent_sessions();
+        fill_recent_sessions_box(
+            &recent_sessions_box,
+            &recent_sessions,
+            &session_popover,
+            &sender,
+        );
+        let session_button = gtk::MenuButton::builder()
+            .icon_name("document-open-recent-symbolic")
+            .tooltip_text("Sessions")
+            .popover(&session_popover)
+            .build();
+        header_bar.pack_start(&session_button);
+
+        // Search entry, highlighting the matching hosts and processes when Enter is pressed
+        let highlight_entry = gtk::SearchEntry::builder()
+            .placeholder_text("Highlight hosts and processes")
+            .tooltip_text("Names to highlight in the graph, with * and ? wildcards")
+            .build();
+        highlight_entry.connect_activate(clone!(@strong sender => move |entry| {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetHighlightQuery(entry.text().to_string())));
+        }));
+        highlight_entry.connect_search_changed(clone!(@strong sender => move |entry| {
+            // Clearing the entry restores the normal rendering right away
+            if entry.text().is_empty() {
+                sender.input(AppMsg::GraphMsg(GraphMsg::SetHighlightQuery(String::new())));
+            }
+        }));
+        let highlight_matches_label = gtk::Label::builder()
+            .visible(false)
+            .css_classes(vec!["dim-label".to_string()])
+            .build();
+        header_bar.pack_start(&highlight_entry);
+        header_bar.pack_start(&highlight_matches_label);
+
         // Help button and window
         let help_window = help::HelpWindow::builder()
             .transient_for(app_window)
@@ -226,20 +487,73 @@ impl Component for AppModel {
         let help_window_sender = help_window.sender();
         let help_button = gtk::Button::builder()
             .icon_name("dialog-question-symbolic")
+            .tooltip_text(&shortcuts::tooltip("Help", ShortcutAction::ShowHelp))
             .build();
         help_button.connect_clicked(clone!(@strong help_window_sender => move |_| {
             help_window_sender.emit(help::HelpWindowMsg::Show)
         }));
         header_bar.pack_end(&help_button);
 
-        // Export button
-        let export_graph_button = gtk::Button::builder()
+        // Preferences button and window
+        let preferences_window = appearance::init_preferences_window(
+            app_window,
+            preferences.appearance,
+            preferences.embed_provenance,
+            sender.clone(),
+        );
+        let preferences_button = gtk::Button::builder()
+            .icon_name("preferences-system-symbolic")
+            .tooltip_text("Preferences")
+            .build();
+        preferences_button.connect_clicked(move |_| preferences_window.present());
+        header_bar.pack_end(&preferences_button);
+
+        // Home page and button
+        home::init_home_page(
+            &graph_page_widgets.home_page,
+            &flap,
+            &sidebar_stack,
+            &files_page_widgets,
+            &server_page_widgets,
+            help_window_sender,
+        );
+        let home_button = gtk::ToggleButton::builder()
             .sensitive(false)
-            .has_frame(true)
+            .icon_name("go-home-symbolic")
+            .tooltip_text("Show the first steps")
+            .build();
+        home_button.connect_toggled(clone!(@strong sender => move |button| {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetShowHome(button.is_active())));
+        }));
+        header_bar.pack_end(&home_button);
+
+        // Export button, with the export of every file at once in its menu
+        let export_menu_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .build();
+        let export_menu = gtk::Popover::builder().child(&export_menu_box).build();
+        let export_all_button = gtk::Button::builder()
+            .label("Export all…")
+            .tooltip_text("Export the image, the DOT code and the connections as CSV and JSON, named after the chosen file")
+            .has_frame(false)
+            .build();
+        export_all_button.connect_clicked(
+            clone!(@strong export_all_chooser, @strong export_menu => move |_| {
+                export_menu.popdown();
+                export_all_chooser.show();
+            }),
+        );
+        export_menu_box.append(&export_all_button);
+        let export_graph_button = adw::SplitButton::builder()
+            .sensitive(false)
+            .popover(&export_menu)
             .build();
         let export_graph_button_content = adw::ButtonContent::builder()
             .icon_name("document-save-symbolic")
-            .tooltip_text("Export graph to file")
+            .tooltip_text(&shortcuts::tooltip(
+                "Export graph to file",
+                ShortcutAction::ExportGraph,
+            ))
             .label("Export")
             .use_underline(true)
             .build();
@@ -249,6 +563,18 @@ impl Component for AppModel {
         }));
         header_bar.pack_end(&export_graph_button);
 
+        // Keyboard shortcuts, doing the same as the buttons
+        shortcuts::install_shortcuts(
+            app_window,
+            &sender,
+            &file_chooser,
+            &export_graph_button,
+            &files_page_widgets,
+            &graph_page_widgets,
+            &server_page_widgets,
+            help_window_sender,
+        );
+
         // Open in external viewer button
         let open_graph_button = gtk::Button::builder()
             .sensitive(false)
@@ -266,6 +592,37 @@ impl Component for AppModel {
         }));
         header_bar.pack_end(&open_graph_button);
 
+        // Zoom buttons
+        let zoom_fit_button = gtk::Button::builder()
+            .sensitive(false)
+            .icon_name("zoom-fit-best-symbolic")
+            .tooltip_text("Fit graph to view")
+            .build();
+        zoom_fit_button.connect_clicked(clone!(@strong sender => move |_| {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetZoom(None)));
+        }));
+        let zoom_original_button = gtk::Button::builder()
+            .sensitive(false)
+            .icon_name("zoom-original-symbolic")
+            .tooltip_text("Show graph at 100% (Ctrl + scroll to zoom)")
+            .build();
+        zoom_original_button.connect_clicked(clone!(@strong sender => move |_| {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetZoom(Some(1.0))));
+        }));
+        header_bar.pack_end(&zoom_original_button);
+        header_bar.pack_end(&zoom_fit_button);
+
+        // DOT code toggle
+        let view_dot_button = gtk::ToggleButton::builder()
+            .sensitive(false)
+            .icon_name("text-x-generic-symbolic")
+            .tooltip_text("View DOT code")
+            .build();
+        view_dot_button.connect_toggled(clone!(@strong sender => move |button| {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetShowDotCode(button.is_active())));
+        }));
+        header_bar.pack_end(&view_dot_button);
+
         // Info bar
         let info_bar = gtk::InfoBar::builder()
             .revealed(false)
@@ -278,10 +635,33 @@ impl Component for AppModel {
             .build();
         info_bar.add_child(&info_bar_msg);
         info_bar.connect_response(clone!(@strong sender => move |info_bar, _response_type| {
-            sender.input(AppMsg::Error(None));
+            sender.input(AppMsg::DismissNotification);
             info_bar.set_revealed(false);
         }));
 
+        // Messages history
+        let notifications_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(5)
+            .build();
+        fill_notifications_box(&notifications_box, &Notifications::default());
+        let notifications_scrolled_window = gtk::ScrolledWindow::builder()
+            .child(&notifications_box)
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .propagate_natural_height(true)
+            .max_content_height(400)
+            .build();
+        let notifications_button = gtk::MenuButton::builder()
+            .icon_name("dialog-information-symbolic")
+            .tooltip_text("Messages")
+            .popover(
+                &gtk::Popover::builder()
+                    .child(&notifications_scrolled_window)
+                    .build(),
+            )
+            .build();
+        header_bar.pack_end(&notifications_button);
+
         // Add widgets
         outer_box.append(&header_bar);
         outer_box.append(&info_bar);
@@ -290,30 +670,69 @@ impl Component for AppModel {
 
         ComponentParts {
             model: AppModel {
-                image_graph_tempfile: generate_png_temp_file_path(),
-                error_message: None,
+                preview_files: PreviewFiles::new(graph_options::preview_format()),
+                graph_dot_code: String::new(),
+                show_dot_code: false,
+                show_home: false,
+                notification: None,
+                notifications: Notifications::default(),
                 generating_graph: false,
+                generation_status: None,
+                generation_cancel_token: CancellationToken::new(),
                 server_state: ServerState {
                     run_token: CancellationToken::new(),
                     clients: Arc::new(RwLock::new(HashMap::new())),
                     is_enabled: false,
                     tx: Arc::new(RwLock::new(None)),
+                    event_log: Arc::new(RwLock::new(EventLog::default())),
+                    state_rx: None,
                 },
                 graph_options,
                 graph_image_path: None,
+                graph_outdated: false,
+                graph_zoom: None,
+                connection_records: Vec::new(),
+                graph_hosts: Vec::new(),
+                highlight_matches: None,
+                server_option: ServerOption::default(),
+                appearance: preferences.appearance,
+                embed_provenance: preferences.embed_provenance,
+                session_to_apply: None,
+                recent_sessions,
                 tracker: 0,
-                files_options: FilesOptions::default(),
+                files_options: FilesOptions {
+                    recent_folders,
+                    ..Default::default()
+                },
                 clients,
+                display_overrides: preferences.graph.display_overrides.clone(),
                 recording_since: None,
+                recording_interval: 0.0,
+                recording_updates: 0,
+                auto_refresh: false,
+                auto_refresh_interval: auto_refresh::DEFAULT_AUTO_REFRESH_INTERVAL,
+                auto_refresh_token: None,
+                auto_refresh_done: None,
             },
             widgets: AppWidgets {
+                title,
                 info_bar_msg,
                 info_bar,
+                notifications_box,
+                highlight_matches_label,
                 files_page_widgets,
                 graph_page_widgets,
                 export_graph_button,
                 server_page_widgets,
                 open_graph_button,
+                zoom_fit_button,
+                zoom_original_button,
+                view_dot_button,
+                home_button,
+                session_popover,
+                recent_sessions_box,
+                window,
+                flap,
                 help_window,
             },
         }
@@ -323,15 +742,38 @@ impl Component for AppModel {
         &mut self,
         message: Self::Input,
         sender: relm4::ComponentSender<Self>,
-        _root: &Self::Root,
+        root: &Self::Root,
     ) {
         self.reset();
         self.graph_options.reset();
         self.files_options.reset();
         match message {
-            AppMsg::GraphMsg(msg) => self.handle_graph_message(msg, &sender),
-            AppMsg::Error(error_msg) => self.set_error_message(error_msg),
+            AppMsg::GraphMsg(msg) => self.handle_graph_message(msg, &sender, root),
+            AppMsg::Notify(severity, message) => self.notify(severity, message),
+            AppMsg::DismissNotification => self.set_notification(None),
             AppMsg::ServerMsg(msg) => self.handle_server_message(msg, &sender),
+            AppMsg::SessionMsg(msg) => self.handle_session_message(msg, &sender),
+            AppMsg::SetAppearance(appearance) => {
+                self.appearance = appearance;
+                appearance::apply(appearance);
+            }
+            AppMsg::SetEmbedProvenance(embed_provenance) => {
+                self.embed_provenance = embed_provenance
+            }
+            AppMsg::Quit { width, height } => {
+                Preferences {
+                    window_width: width,
+                    window_height: height,
+                    appearance: self.appearance,
+                    embed_provenance: self.embed_provenance,
+                    recent_folders: self.files_options.recent_folders.clone(),
+                    graph: self.graph_settings(),
+                    server: self.server_option.clone(),
+                }
+                .save();
+                self.preview_files.clear();
+                std::process::exit(0);
+            }
         }
 
         // Regenerate graph if options are changed
@@ -353,36 +795,99 @@ impl Component for AppModel {
         self.graph_options.reset();
         self.files_options.reset();
         match message {
-            AppCmdOutput::GeneratedGraph(image_path) => {
-                if let Some(image_path) = &image_path {
+            AppCmdOutput::GeneratedGraph(generated) => {
+                if let Some((image_graph_tempfile, generated)) = generated {
+                    let image_path = image_graph_tempfile.path().to_path_buf();
                     log::info!("generated graph at {image_path:?}");
-                    sender.input(AppMsg::Error(None));
+                    self.preview_files.replace(image_graph_tempfile);
+                    self.set_graph_outdated(false);
+                    self.set_graph_dot_code(generated.dot_code);
+                    self.set_show_home(false);
+                    self.set_generation_status(Some(GenerationStatus::Generated {
+                        hosts_count: generated.hosts.len(),
+                        connections_count: generated.connection_records.len(),
+                        time: chrono::Local::now(),
+                    }));
+                    self.set_highlight_matches(generated.highlight_matches);
+                    self.graph_hosts = generated.hosts;
+                    self.files_options.set_diagnostics(generated.diagnostics);
+                    self.set_notification(None);
+                    for warning in generated.warnings {
+                        self.notify(Severity::Warning, warning);
+                    }
+                    sender.input(AppMsg::GraphMsg(GraphMsg::SetConnectionRecords(
+                        generated.connection_records,
+                    )));
+                    sender.input(AppMsg::GraphMsg(GraphMsg::SetImagePath(Some(image_path))));
                 } else {
                     log::info!("did not generate graph");
+                    self.set_generation_status(Some(GenerationStatus::Failed));
                 }
                 sender.input(AppMsg::GraphMsg(GraphMsg::Generating(false)));
-                sender.input(AppMsg::GraphMsg(GraphMsg::SetImagePath(image_path)));
+            }
+            AppCmdOutput::GenerationProgress(phase) => widgets
+                .graph_page_widgets
+                .generate_progress_label
+                .set_label(&phase),
+            AppCmdOutput::GenerationCancelled => {
+                // Otherwise, it was replaced by a newer generation which is still running
+                if self.generation_cancel_token.is_cancelled() {
+                    log::info!("graph generation cancelled");
+                    sender.input(AppMsg::GraphMsg(GraphMsg::Generating(false)));
+                }
+            }
+            AppCmdOutput::ClientDetails { index, details } => server::show_client_details(
+                &widgets.server_page_widgets,
+                index,
+                &details,
+                self.display_overrides.get(&details.hostname),
+            ),
+            AppCmdOutput::AutoRefreshStopped(refreshes) => {
+                log::info!("stopped refreshing the graph, after {refreshes} refreshes")
+            }
+            AppCmdOutput::ServerFailed(reason) => {
+                sender.input(AppMsg::ServerMsg(ServerMsg::StopRecorder));
+                self.server_state.is_enabled = false;
+                self.server_state.run_token.cancel();
+                self.clients.guard().clear();
+                self.notify(
+                    Severity::Error,
+                    format!("the server stopped unexpectedly: {reason}"),
+                );
+                server::show_deploy_commands(&widgets.server_page_widgets, &[]);
+                server::reset_server_button(&widgets.server_page_widgets);
+            }
+            AppCmdOutput::ServerStartFailed(message) => {
+                self.server_state.is_enabled = false;
+                self.server_state.run_token.cancel();
+                self.notify(Severity::Error, message);
+                server::reset_server_button(&widgets.server_page_widgets);
             }
             AppCmdOutput::SetServerIsEnabled(server_is_enabled) => {
                 self.server_state.is_enabled = server_is_enabled;
                 if !server_is_enabled {
                     self.clients.guard().clear();
+                    server::show_deploy_commands(&widgets.server_page_widgets, &[]);
                 }
             }
-            AppCmdOutput::Error(error_msg) => self.set_error_message(error_msg),
+            AppCmdOutput::EventLog(lines) => {
+                server::show_event_log(&widgets.server_page_widgets, &lines)
+            }
+            AppCmdOutput::DeployCommands(commands) => {
+                server::show_deploy_commands(&widgets.server_page_widgets, &commands)
+            }
+            AppCmdOutput::Notify(severity, message) => self.notify(severity, message),
+            AppCmdOutput::Done => (),
             AppCmdOutput::RecorderTimerTick => {
                 if let Some(recording_since) = self.recording_since {
                     // Update label
-                    let now = std::time::Instant::now();
-                    let interval = now - recording_since;
-                    let interval = Duration::new(interval.as_secs(), 0);
-                    widgets
-                        .server_page_widgets
-                        .recorder_timer
-                        .set_label(&format!(
-                        "<span size=\"small\" foreground=\"grey\"><i>(recording for {})</i></span>",
-                        humantime::format_duration(interval)
-                    ));
+                    widgets.server_page_widgets.recorder_timer.set_label(
+                        &recorder::format_recorder_timer(
+                            recording_since.elapsed(),
+                            self.recording_interval,
+                            self.recording_updates,
+                        ),
+                    );
 
                     // Send next tick
                     sender.oneshot_command(async move {
@@ -404,101 +909,207 @@ impl Component for AppModel {
                 }
             }
         }
+        self.update_view(widgets, sender);
     }
 
-    fn update_view(&self, widgets: &mut Self::Widgets, _sender: relm4::ComponentSender<Self>) {
+    fn update_view(&self, widgets: &mut Self::Widgets, sender: relm4::ComponentSender<Self>) {
         // Main window view
-        if self.changed(Self::error_message()) {
-            if let Some(error_msg) = self.get_error_message() {
-                widgets.info_bar_msg.set_label(error_msg);
+        if self.changed(Self::notification()) {
+            if let Some(notification) = self.get_notification() {
+                widgets.info_bar_msg.set_label(&notification.message);
+                widgets
+                    .info_bar
+                    .set_message_type(match notification.severity {
+                        Severity::Info => gtk::MessageType::Info,
+                        Severity::Warning => gtk::MessageType::Warning,
+                        Severity::Error => gtk::MessageType::Error,
+                    });
                 widgets.info_bar.set_revealed(true);
             } else {
                 widgets.info_bar_msg.set_label("");
                 widgets.info_bar.set_revealed(false);
             }
         }
+        if self.changed(Self::notifications()) {
+            fill_notifications_box(&widgets.notifications_box, self.get_notifications());
+        }
+
+        if self.changed(Self::generation_status()) || self.changed(Self::graph_outdated()) {
+            let mut subtitle = self
+                .get_generation_status()
+                .as_ref()
+                .map(GenerationStatus::to_string)
+                .unwrap_or_default();
+            if *self.get_graph_outdated() {
+                subtitle.push_str(" · outdated");
+            }
+            widgets.title.set_subtitle(&subtitle);
+        }
 
         // Graph page view
         if self.changed(Self::generating_graph()) {
+            let generating = *self.get_generating_graph();
             widgets
                 .graph_page_widgets
                 .generate_button_spinner
-                .set_spinning(*self.get_generating_graph());
+                .set_spinning(generating);
             widgets
                 .graph_page_widgets
                 .generate_button_spinner
-                .set_visible(*self.get_generating_graph());
+                .set_visible(generating);
+            widgets
+                .graph_page_widgets
+                .generate_button
+                .set_sensitive(!generating);
+            widgets
+                .graph_page_widgets
+                .cancel_generation_button
+                .set_visible(generating);
+            widgets
+                .graph_page_widgets
+                .generate_progress_label
+                .set_label("");
+            widgets
+                .graph_page_widgets
+                .generate_progress_label
+                .set_visible(generating);
         }
         if self.changed(Self::graph_image_path()) {
             if let Some(graph_image_path) = self.get_graph_image_path() {
-                widgets
-                    .graph_page_widgets
-                    .graph_image
-                    .set_filename(Some(&graph_image_path));
-                widgets
-                    .graph_page_widgets
-                    .image_view_stack
-                    .set_visible_child(&widgets.graph_page_widgets.graph_image);
-                widgets.export_graph_button.set_sensitive(true);
+                graph_options::load_graph_image(&mut widgets.graph_page_widgets, graph_image_path);
                 widgets.open_graph_button.set_sensitive(true);
+                widgets.zoom_fit_button.set_sensitive(true);
+                widgets.zoom_original_button.set_sensitive(true);
+                widgets.view_dot_button.set_sensitive(true);
+                widgets.home_button.set_sensitive(true);
             }
         }
+        if self.changed(Self::graph_image_path()) || self.changed(Self::graph_outdated()) {
+            let outdated = *self.get_graph_outdated();
+            graph_options::mark_graph_outdated(&widgets.graph_page_widgets, outdated);
+            widgets
+                .export_graph_button
+                .set_sensitive(self.get_graph_image_path().is_some() && !outdated);
+        }
+        if self.changed(Self::graph_dot_code()) {
+            graph_options::set_dot_code(&widgets.graph_page_widgets, self.get_graph_dot_code());
+        }
+        if self.changed(Self::graph_image_path())
+            || self.changed(Self::show_dot_code())
+            || self.changed(Self::show_home())
+        {
+            let graph_page_widgets = &widgets.graph_page_widgets;
+            let has_graph = self.get_graph_image_path().is_some();
+            match (
+                has_graph && !*self.get_show_home(),
+                *self.get_show_dot_code(),
+            ) {
+                (false, _) => graph_page_widgets
+                    .image_view_stack
+                    .set_visible_child(&graph_page_widgets.home_page),
+                (true, true) => graph_page_widgets
+                    .image_view_stack
+                    .set_visible_child(&graph_page_widgets.dot_box),
+                (true, false) => graph_page_widgets
+                    .image_view_stack
+                    .set_visible_child(&graph_page_widgets.graph_scrolled_window),
+            }
+            widgets
+                .view_dot_button
+                .set_active(*self.get_show_dot_code());
+            widgets.home_button.set_active(*self.get_show_home());
+        }
+        // The zoom is kept when the graph is regenerated
+        if self.changed(Self::graph_image_path()) || self.changed(Self::graph_zoom()) {
+            graph_options::apply_zoom(&widgets.graph_page_widgets, *self.get_graph_zoom());
+        }
 
-        // Files page view
-        if self.files_options.changed(FilesOptions::input_directory()) {
-            widgets.files_page_widgets.folder_label.set_markup(
-                &self
-                    .files_options
-                    .input_directory
-                    .clone()
-                    .and_then(|dir| dir.file_name().map(|s| s.to_string_lossy().to_string()))
-                    .map(|dir_name| format!("Selected folder: <b>{dir_name}</b>"))
-                    .unwrap_or_else(|| "Select folder to add hosts".to_string()),
+        if self.changed(Self::highlight_matches()) {
+            match self.get_highlight_matches() {
+                Some(matches) => {
+                    widgets.highlight_matches_label.set_label(&format!(
+                        "{matches} {}",
+                        match matches {
+                            1 => "match",
+                            _ => "matches",
+                        }
+                    ));
+                    widgets.highlight_matches_label.set_visible(true);
+                }
+                None => widgets.highlight_matches_label.set_visible(false),
+            }
+        }
+
+        if self.changed(Self::connection_records()) {
+            connections_table::set_records(
+                &widgets.graph_page_widgets.connections_table,
+                self.get_connection_records(),
             );
-            widgets
-                .files_page_widgets
-                .delete_button
-                .set_visible(self.files_options.input_directory.is_some());
-        }
-        if self.files_options.changed(FilesOptions::scanned_hosts()) {
-            if let Some(hosts) = self.files_options.get_scanned_hosts() {
-                let mut text = String::from("<b>Parsed hosts:</b>\n\n");
-                for host in hosts {
-                    text.push_str(&format!("- {}\n", host.name()));
-                }
-                widgets.files_page_widgets.hosts_text.set_markup(&text);
-                widgets.files_page_widgets.separator.set_visible(true);
-            } else {
-                widgets.files_page_widgets.hosts_text.set_text("");
-                widgets.files_page_widgets.separator.set_visible(false);
+        }
+
+        // Sessions
+        if self.changed(Self::session_to_apply()) {
+            if let Some(session) = self.get_session_to_apply() {
+                graph_options::apply_graph_settings(
+                    &widgets.graph_page_widgets.options,
+                    &session.graph,
+                );
+                server::apply_server_option(&widgets.server_page_widgets, &session.server);
+                if let Some(layout) = &session.window {
+                    apply_window_layout(widgets, layout);
+                }
             }
         }
-    }
-}
+        if self.changed(Self::recent_sessions()) {
+            fill_recent_sessions_box(
+                &widgets.recent_sessions_box,
+                self.get_recent_sessions(),
+                &widgets.session_popover,
+                &sender,
+            );
+        }
 
-#[cfg(target_os = "windows")]
-fn set_dark_theme() {
-    let display = gtk::gdk::Display::default().expect("unable to get default display");
-    let style_manager = adw::StyleManager::for_display(&display);
-    style_manager.set_color_scheme(adw::ColorScheme::ForceDark);
+        // Files page view
+        if self.files_options.changed(FilesOptions::input_folders())
+            || self.files_options.changed(FilesOptions::diagnostics())
+        {
+            files::fill_input_folders_box(
+                &widgets.files_page_widgets,
+                self.files_options.get_input_folders(),
+                self.files_options.get_diagnostics(),
+                &sender,
+            );
+            home::update_home_page(
+                &widgets.graph_page_widgets.home_page,
+                files::host_names(self.files_options.get_input_folders()).len(),
+            );
+        }
+        if self.files_options.changed(FilesOptions::recent_folders()) {
+            files::fill_recent_folders_box(
+                &widgets.files_page_widgets,
+                self.files_options.get_recent_folders(),
+                &sender,
+            );
+        }
+    }
 }
 
 impl AppModel {
-    fn regenerate_temp_png_file_path(&mut self) {
-        let named_temp_file = generate_png_temp_file_path();
-        self.image_graph_tempfile = named_temp_file;
-    }
-
     fn handle_server_message(&mut self, msg: ServerMsg, sender: &relm4::ComponentSender<AppModel>) {
         match msg {
-            ServerMsg::SetServerIsEnabled(enabled) => {
-                self.server_state.is_enabled = enabled;
-                if !enabled {
-                    self.clients.guard().clear();
-                }
-            }
+            ServerMsg::SetServerOption(server_option) => self.server_option = server_option,
             ServerMsg::ClientConnect(client) => {
-                self.clients.guard().push_back(client);
+                let display_name = self
+                    .display_overrides
+                    .get(&client.hostname)
+                    .and_then(|display_override| display_override.name.clone());
+                let mut clients = self.clients.guard();
+                clients.push_back(client);
+                clients.send(
+                    clients.len() - 1,
+                    ClientLabelMsg::SetDisplayName(display_name),
+                );
+                sender.input(AppMsg::ServerMsg(ServerMsg::RefreshEventLog));
             }
             ServerMsg::ClientDisconnect(client) => {
                 let client_index = self
@@ -510,6 +1121,7 @@ impl AppModel {
                 if let Some(index) = client_index {
                     self.clients.guard().remove(index);
                 }
+                sender.input(AppMsg::ServerMsg(ServerMsg::RefreshEventLog));
             }
             ServerMsg::ClientUpdate(client) => {
                 let client_index = self
@@ -521,18 +1133,32 @@ impl AppModel {
                 if let Some(index) = client_index {
                     self.clients.guard().send(index, ClientLabelMsg::GotUpdate);
                 }
+                if self.recording_since.is_some() {
+                    self.recording_updates += 1;
+                }
+                sender.input(AppMsg::ServerMsg(ServerMsg::RefreshEventLog));
             }
             ServerMsg::StartRecorder(interval) => {
+                let clients = self.server_state.clients.clone();
                 let tx_opt = self.server_state.tx.clone();
+                let event_log = self.server_state.event_log.clone();
                 self.clients
                     .guard()
                     .broadcast(ClientLabelMsg::Recording(true));
                 self.recording_since = Some(std::time::Instant::now());
+                self.recording_interval = interval;
+                self.recording_updates = 0;
+                self.update_auto_refresh(sender);
                 sender.oneshot_command(async move {
-                    if let Some(tx) = tx_opt.write().await.as_mut() {
-                        let (_res, _accept_res) =
-                            tx.send(Message::StartRecording(interval)).accepting().await;
+                    if let Err(e) =
+                        sockets_map::server::start_recording(&clients, &tx_opt, interval).await
+                    {
+                        log::error!("{e}");
                     }
+                    event_log
+                        .write()
+                        .await
+                        .push_server_event(EventKind::RecordingStarted { interval });
 
                     // Start the timer
                     AppCmdOutput::RecorderTimerTick
@@ -540,15 +1166,25 @@ impl AppModel {
             }
             ServerMsg::StopRecorder => {
                 let tx_opt = self.server_state.tx.clone();
+                let event_log = self.server_state.event_log.clone();
+                let was_recording = self.recording_since.is_some();
                 self.clients
                     .guard()
                     .broadcast(ClientLabelMsg::Recording(false));
                 self.recording_since = None;
+                self.update_auto_refresh(sender);
                 sender.oneshot_command(async move {
                     if let Some(tx) = tx_opt.write().await.as_mut() {
                         let (_res, _accept_res) = tx.send(Message::StopRecording).accepting().await;
                     }
-                    AppCmdOutput::Error(None)
+                    // The recorder is also stopped along with the server, even if it was not running
+                    if was_recording {
+                        event_log
+                            .write()
+                            .await
+                            .push_server_event(EventKind::RecordingStopped);
+                    }
+                    AppCmdOutput::Done
                 })
             }
             ServerMsg::EnableServer(server_options) => {
@@ -556,8 +1192,11 @@ impl AppModel {
                     let clients = self.server_state.clients.clone();
                     self.server_state.run_token = CancellationToken::new();
                     let token = self.server_state.run_token.clone();
+                    let http_clients = clients.clone();
+                    let http_token = token.clone();
                     let tx_opt = self.server_state.tx.clone();
-                    sender.input(AppMsg::ServerMsg(ServerMsg::SetServerIsEnabled(true)));
+                    // Set right away, so that a failure to listen always disables it afterwards
+                    self.server_state.is_enabled = true;
                     sender.oneshot_command(clone!(@strong sender => async move {
                         log::info!("starting server");
                         match sockets_map::server::listen(
@@ -566,7 +1205,9 @@ impl AppModel {
                                 server_options.listen_addr, server_options.listen_port
                             ),
                             clients,
+                            tx_opt.clone(),
                             token,
+                            server_options.listen_options(),
                             |socket_addr| {
                                 log::info!("connection from peer {socket_addr:?}");
                             },
@@ -578,11 +1219,7 @@ impl AppModel {
                                         &client.pretty_name.as_ref().unwrap_or(&client.hostname)
                                     );
                                     sender.input(AppMsg::ServerMsg(ServerMsg::ClientConnect(
-                                        ClientInfo {
-                                            hostname: client.hostname.clone(),
-                                            pretty_name: client.pretty_name.clone(),
-                                            ips: client.ips.clone()
-                                    })));
+                                        ClientInfo::from(client))));
                                 }
                             },
                             {
@@ -593,11 +1230,7 @@ impl AppModel {
                                         &client.pretty_name.as_ref().unwrap_or(&client.hostname)
                                     );
                                     sender.input(AppMsg::ServerMsg(ServerMsg::ClientUpdate(
-                                        ClientInfo {
-                                            hostname: client.hostname.clone(),
-                                            pretty_name: client.pretty_name.clone(),
-                                            ips: client.ips.clone()
-                                        }
+                                        ClientInfo::from(client)
                                     )))
                                 }
                             },
@@ -609,20 +1242,52 @@ impl AppModel {
                                         &client.pretty_name.as_ref().unwrap_or(&client.hostname)
                                     );
                                     sender.input(AppMsg::ServerMsg(ServerMsg::ClientDisconnect(
-                                        ClientInfo {
-                                            hostname: client.hostname.clone(),
-                                            pretty_name: client.pretty_name.clone(),
-                                            ips: client.ips.clone()
-                                    })));
+                                        ClientInfo::from(client))));
                                 }
                             },
                         )
                         .await {
-                            Ok(tx) => {
-                                *tx_opt.write().await = Some(tx);
-                                AppCmdOutput::Error(None)
+                            Ok((mut state_rx, event_log)) => {
+                                sender.command_sender().emit(AppCmdOutput::DeployCommands(
+                                    server::deploy::listen_deploy_commands(&server_options),
+                                ));
+                                sender.input(AppMsg::ServerMsg(ServerMsg::ServerStarted {
+                                    state_rx: state_rx.clone(),
+                                    event_log,
+                                }));
+                                // Report the failures of the server once it is started
+                                sender.oneshot_command(clone!(@strong tx_opt => async move {
+                                    while state_rx.changed().await.is_ok() {
+                                        let state = state_rx.borrow().clone();
+                                        if let ListenState::Failed(reason) = state {
+                                            tx_opt.write().await.take();
+                                            return AppCmdOutput::ServerFailed(reason);
+                                        }
+                                    }
+                                    AppCmdOutput::Done
+                                }));
+                                if server_options.http_port.is_empty() {
+                                    return AppCmdOutput::Done;
+                                }
+                                match sockets_map::server::http::serve(
+                                    format!(
+                                        "{}:{}",
+                                        server_options.listen_addr, server_options.http_port
+                                    ),
+                                    http_clients,
+                                    tx_opt,
+                                    http_token,
+                                )
+                                .await {
+                                    Ok(_) => AppCmdOutput::Done,
+                                    Err(e) => AppCmdOutput::Notify(Severity::Error, format!(
+                                        "unable to start the HTTP status endpoint: {e}"
+                                    )),
+                                }
                             },
-                            Err(e) => AppCmdOutput::Error(Some(e.to_string())),
+                            Err(e) => AppCmdOutput::ServerStartFailed(
+                                server::startup::listen_error_message(&e, &server_options)
+                            ),
                         }
                     }));
                 } else if self.server_state.is_enabled {
@@ -630,66 +1295,411 @@ impl AppModel {
                     // If recorder was running, stop it
                     sender.input(AppMsg::ServerMsg(ServerMsg::StopRecorder));
 
-                    // Stop the server
+                    // Stop the server, keeping the updates the clients were still sending
                     let token = self.server_state.run_token.clone();
                     let tx_opt = self.server_state.tx.clone();
+                    let clients = self.server_state.clients.clone();
+                    let state_rx = self.server_state.state_rx.take();
                     sender.oneshot_command(async move {
-                        // Disconnect clients
-                        // Taking the `tx_opt` value here drops it at then end and closes the listening socket
-                        if let Some(mut tx) = tx_opt.write().await.take() {
-                            let (_res, _accept_res) = tx.send(Message::Exit).accepting().await;
+                        match state_rx {
+                            Some(mut state_rx) => {
+                                sockets_map::server::shutdown(
+                                    &tx_opt,
+                                    &clients,
+                                    &token,
+                                    &mut state_rx,
+                                    SERVER_SHUTDOWN_TIMEOUT,
+                                )
+                                .await
+                            }
+                            None => {
+                                tx_opt.write().await.take();
+                                token.cancel();
+                            }
                         }
-
-                        // Shutdown server
-                        token.cancel();
                         AppCmdOutput::SetServerIsEnabled(false)
                     });
                     self.server_state.is_enabled = false;
                 }
             }
+            ServerMsg::ServerStarted {
+                state_rx,
+                event_log,
+            } => {
+                self.server_state.state_rx = Some(state_rx);
+                self.server_state.event_log = event_log;
+                sender.input(AppMsg::ServerMsg(ServerMsg::RefreshEventLog));
+            }
+            ServerMsg::RefreshEventLog => {
+                let event_log = self.server_state.event_log.clone();
+                sender.oneshot_command(async move {
+                    AppCmdOutput::EventLog(server::event_log::event_lines(&*event_log.read().await))
+                })
+            }
+            ServerMsg::ShowClientDetails(index) => {
+                let Some(hostname) = self
+                    .clients
+                    .guard()
+                    .get(index)
+                    .map(|client| client.info.hostname.clone())
+                else {
+                    return;
+                };
+                let clients = self.server_state.clients.clone();
+                sender.oneshot_command(async move {
+                    match clients
+                        .read()
+                        .await
+                        .values()
+                        .find(|client| client.hostname == hostname)
+                    {
+                        Some(client) => AppCmdOutput::ClientDetails {
+                            index,
+                            details: client.into(),
+                        },
+                        None => AppCmdOutput::Notify(
+                            Severity::Error,
+                            format!("Unknown client {hostname}"),
+                        ),
+                    }
+                })
+            }
+            ServerMsg::SetDisplayOverride {
+                hostname,
+                display_override,
+            } => {
+                match display_override.is_empty() {
+                    true => self.display_overrides.remove(&hostname),
+                    false => self.display_overrides.insert(hostname, display_override),
+                };
+                self.show_display_names();
+                if self.get_graph_image_path().is_some() {
+                    sender.input(AppMsg::GraphMsg(GraphMsg::Generating(true)));
+                }
+            }
             ServerMsg::SendUpdateRequest => {
                 let tx_opt = self.server_state.tx.clone();
                 sender.oneshot_command(async move {
                     if let Some(tx) = tx_opt.write().await.as_mut() {
                         let (_res, _accept_res) = tx.send(Message::UpdateRequest).accepting().await;
                     }
-                    AppCmdOutput::Error(None)
+                    AppCmdOutput::Done
+                })
+            }
+            ServerMsg::SendSampledUpdateRequest { samples, interval } => {
+                let clients = self.server_state.clients.clone();
+                let tx_opt = self.server_state.tx.clone();
+                sender.oneshot_command(async move {
+                    match sockets_map::server::request_sampled_update(
+                        &clients, &tx_opt, samples, interval,
+                    )
+                    .await
+                    {
+                        Ok(()) => AppCmdOutput::Done,
+                        Err(e) => AppCmdOutput::Notify(Severity::Error, e.to_string()),
+                    }
                 })
             }
         }
     }
 
-    fn handle_graph_message(&mut self, msg: GraphMsg, sender: &relm4::ComponentSender<AppModel>) {
+    fn handle_session_message(
+        &mut self,
+        msg: SessionMsg,
+        sender: &relm4::ComponentSender<AppModel>,
+    ) {
+        let path = match msg {
+            SessionMsg::Save(path, window) => {
+                let path = match path.extension() {
+                    Some(_) => path,
+                    None => path.with_extension("json"),
+                };
+                let session = Session {
+                    input_directories: self
+                        .files_options
+                        .input_folders
+                        .iter()
+                        .map(|folder| folder.path.clone())
+                        .collect(),
+                    graph: self.graph_settings(),
+                    server: self.server_option.clone(),
+                    window: Some(window),
+                    ..Default::default()
+                };
+                if let Err(e) = session.save(&path) {
+                    self.notify(Severity::Error, e.to_string());
+                    return;
+                }
+                path
+            }
+            SessionMsg::Open(path) => match Session::load(&path) {
+                Ok(session) => {
+                    sender.input(AppMsg::GraphMsg(GraphMsg::SetInputDirs(
+                        session.input_directories.clone(),
+                    )));
+                    self.display_overrides = session.graph.display_overrides.clone();
+                    self.show_display_names();
+                    self.set_session_to_apply(Some(session));
+                    path
+                }
+                Err(e) => {
+                    self.notify(Severity::Error, format!("{e:#}"));
+                    return;
+                }
+            },
+        };
+        let mut recent_sessions = self.recent_sessions.clone();
+        session::add_recent_session(&mut recent_sessions, &path);
+        session::save_recent_sessions(&recent_sessions);
+        self.set_recent_sessions(recent_sessions);
+    }
+
+    /// Graph settings of the preferences and sessions, with the edited names and colors of the hosts
+    fn graph_settings(&self) -> GraphSettings {
+        GraphSettings {
+            display_overrides: self.display_overrides.clone(),
+            ..(&self.graph_options).into()
+        }
+    }
+
+    /// Provenance to embed in the exported files: the input folders, the connected agents and the
+    /// graph settings, or `None` if it is disabled in the preferences
+    fn export_provenance(&mut self) -> Option<Provenance> {
+        if !self.embed_provenance {
+            return None;
+        }
+        let graph_settings = serde_json::to_string(&self.graph_settings()).unwrap_or_default();
+        let input_folders: Vec<&Path> = self
+            .files_options
+            .input_folders
+            .iter()
+            .map(|folder| folder.path.as_path())
+            .collect();
+        let clients = self.clients.guard();
+        let agents: Vec<String> = (0..clients.len())
+            .filter_map(|index| clients.get(index))
+            .map(|client| client.info.hostname.clone())
+            .collect();
+        Some(Provenance::collect(
+            concat!("sockets_map_gui ", env!("CARGO_PKG_VERSION")),
+            &input_folders,
+            &agents,
+            &[graph_settings],
+        ))
+    }
+
+    /// Show the edited names in the clients list
+    fn show_display_names(&mut self) {
+        let mut clients = self.clients.guard();
+        for index in 0..clients.len() {
+            let display_name = clients.get(index).and_then(|client| {
+                self.display_overrides
+                    .get(&client.info.hostname)
+                    .and_then(|display_override| display_override.name.clone())
+            });
+            clients.send(index, ClientLabelMsg::SetDisplayName(display_name));
+        }
+    }
+
+    /// Show a message in the info bar, and keep it in the messages history
+    fn notify(&mut self, severity: Severity, message: String) {
+        let notification = Notification {
+            severity,
+            message,
+            time: chrono::Local::now(),
+        };
+        self.get_mut_notifications().push(notification.clone());
+        self.set_notification(Some(notification));
+    }
+
+    /// Scan an input folder and add it, or scan it again if it was already added. Returns whether
+    /// it could be scanned.
+    fn add_input_dir(&mut self, dir: PathBuf) -> bool {
+        if !dir.is_dir() {
+            self.notify(Severity::Error, format!("Folder {dir:?} does not exist"));
+            return false;
+        }
+        let scanned_hosts = match sockets_map::parsers::directory_scanner::scan_dir_with_warnings(
+            &dir,
+            sockets_map::parsers::directory_scanner::ScanOptions::default(),
+        ) {
+            Ok((scanned_hosts, warnings)) => {
+                for warning in warnings {
+                    self.notify(Severity::Warning, warning);
+                }
+                scanned_hosts
+            }
+            Err(e) => {
+                self.notify(Severity::Error, format!("{e:#}"));
+                return false;
+            }
+        };
+        let input_folder = InputFolder {
+            scanned_hosts,
+            path: dir.clone(),
+        };
+        let input_folders = self.files_options.get_mut_input_folders();
+        match input_folders.iter_mut().find(|folder| folder.path == dir) {
+            Some(folder) => *folder = input_folder,
+            None => input_folders.push(input_folder),
+        }
+        self.files_options.get_mut_recent_folders().add(&dir);
+        true
+    }
+
+    /// Mark the graph shown as outdated once the input folders change, until it is generated again
+    fn outdate_graph(&mut self) {
+        if self.get_graph_image_path().is_some() && !*self.get_graph_outdated() {
+            self.set_graph_outdated(true);
+            self.notify(
+                Severity::Info,
+                "The input folders changed: generate the graph again to update it".into(),
+            );
+        }
+    }
+
+    /// Path of the graph preview to export, or `None` after notifying that it must be generated
+    /// again since the input folders changed
+    fn exportable_preview(&mut self) -> Option<PathBuf> {
+        let preview_path = match self.graph_outdated {
+            true => None,
+            false => self.preview_files.current_path().map(PathBuf::from),
+        };
+        if preview_path.is_none() {
+            self.notify(
+                Severity::Error,
+                "The input folders changed since the graph was generated: generate it again to export it"
+                    .into(),
+            );
+        }
+        preview_path
+    }
+
+    /// Start or restart the periodic refreshes of the graph while recording, or stop them
+    fn update_auto_refresh(&mut self, sender: &relm4::ComponentSender<AppModel>) {
+        if let Some(token) = self.auto_refresh_token.take() {
+            token.cancel();
+        }
+        if !self.auto_refresh || self.recording_since.is_none() {
+            return;
+        }
+
+        let token = CancellationToken::new();
+        self.auto_refresh_token = Some(token.clone());
+        let interval = Duration::from_secs_f64(self.auto_refresh_interval);
+        let input_sender = sender.input_sender().clone();
+        sender.oneshot_command(async move {
+            let refreshes = auto_refresh::schedule_refreshes(interval, &token, || {
+                let (done_tx, done_rx) = oneshot::channel();
+                input_sender.emit(AppMsg::GraphMsg(GraphMsg::AutoRefresh(done_tx)));
+                async move {
+                    let _ = done_rx.await;
+                }
+            })
+            .await;
+            AppCmdOutput::AutoRefreshStopped(refreshes)
+        });
+    }
+
+    fn handle_graph_message(
+        &mut self,
+        msg: GraphMsg,
+        sender: &relm4::ComponentSender<AppModel>,
+        root: &adw::Window,
+    ) {
         match msg {
             GraphMsg::GenerateGraph(graph_options) => {
-                self.regenerate_temp_png_file_path();
+                // A generation still running is outdated
+                self.generation_cancel_token.cancel();
+                self.generation_cancel_token = CancellationToken::new();
+                let cancel_token = self.generation_cancel_token.clone();
 
-                let scanned_hosts = self.files_options.scanned_hosts.clone();
-                let image_graph_tempfile_path = self.image_graph_tempfile.path().to_path_buf();
+                // The preview is replaced only once the new graph is rendered
+                let image_graph_tempfile = match self.preview_files.create() {
+                    Ok(image_graph_tempfile) => image_graph_tempfile,
+                    Err(e) => {
+                        self.notify(
+                            Severity::Error,
+                            format!("Unable to create the file of the graph preview: {e}"),
+                        );
+                        self.set_generation_status(Some(GenerationStatus::Failed));
+                        sender.input(AppMsg::GraphMsg(GraphMsg::Generating(false)));
+                        return;
+                    }
+                };
+                let mut graph_settings: GraphSettings = (&graph_options).into();
+                graph_settings.file_extension = self.preview_files.format().to_string();
+                graph_settings.display_overrides = self.display_overrides.clone();
+                let input_folders = self.files_options.scanned_hosts();
+                let hosts_cache_dir = preferences::cache_dir();
                 let input_sender = sender.input_sender().clone();
+                let command_sender = sender.command_sender().clone();
                 let clients = self.server_state.clients.clone();
                 let tx_opt = self.server_state.tx.clone();
+                let update_timeout = self.server_option.update_timeout();
+                let update_selection = self.server_option.update_selection;
                 sender.oneshot_command(async move {
-                    match generate_graph(
-                        scanned_hosts,
+                    match generation::generate_graph(
+                        input_folders,
+                        hosts_cache_dir.as_deref(),
                         clients,
                         tx_opt,
-                        &graph_options,
-                        &image_graph_tempfile_path,
-                        None,
+                        update_timeout,
+                        update_selection,
+                        &graph_settings,
+                        image_graph_tempfile.path(),
+                        &cancel_token,
+                        |phase: generation::Phase| {
+                            command_sender.emit(AppCmdOutput::GenerationProgress(phase.to_string()))
+                        },
                     )
                     .await
                     {
-                        Ok(_) => AppCmdOutput::GeneratedGraph(Some(image_graph_tempfile_path)),
+                        Ok(generated) => {
+                            AppCmdOutput::GeneratedGraph(Some((image_graph_tempfile, generated)))
+                        }
+                        Err(e) if e.is::<generation::Cancelled>() => {
+                            AppCmdOutput::GenerationCancelled
+                        }
                         Err(e) => {
-                            input_sender.emit(AppMsg::Error(Some(e.to_string())));
+                            input_sender.emit(AppMsg::Notify(Severity::Error, e.to_string()));
                             AppCmdOutput::GeneratedGraph(None)
                         }
                     }
                 });
             }
+            GraphMsg::CancelGeneration => self.generation_cancel_token.cancel(),
+            GraphMsg::AutoRefresh(done) => {
+                // Skipped if a graph is already being generated
+                if !*self.get_generating_graph() {
+                    self.auto_refresh_done = Some(done);
+                    sender.input(AppMsg::GraphMsg(GraphMsg::Generating(true)));
+                }
+            }
+            GraphMsg::SetAutoRefresh(auto_refresh) => {
+                self.auto_refresh = auto_refresh;
+                self.update_auto_refresh(sender);
+            }
+            GraphMsg::TrySetAutoRefreshInterval(interval_str) => {
+                match interval_str.parse::<f64>() {
+                    Ok(interval) if interval >= 1.0 => self.auto_refresh_interval = interval,
+                    _ => {
+                        if !interval_str.is_empty() {
+                            sender.input(AppMsg::Notify(
+                                Severity::Error,
+                                "Invalid auto-refresh interval (must be >= 1s)".into(),
+                            ));
+                        }
+                        self.auto_refresh_interval = auto_refresh::DEFAULT_AUTO_REFRESH_INTERVAL;
+                    }
+                }
+                self.update_auto_refresh(sender);
+            }
             GraphMsg::Generating(generating) => {
                 self.set_generating_graph(generating);
+                if !generating {
+                    self.auto_refresh_done = None;
+                }
                 if generating {
                     // Generate the graph
                     sender.input(AppMsg::GraphMsg(GraphMsg::GenerateGraph(
@@ -698,44 +1708,176 @@ impl AppModel {
                 }
             }
 
-            GraphMsg::SetHideLoopbackConnections(value) => {
-                self.graph_options.set_hide_loopback_connections(value)
+            GraphMsg::SetLoopbackRendering(value) => {
+                self.graph_options.set_loopback_rendering(value)
             }
+            GraphMsg::SetSourcePolicy(value) => self.graph_options.set_source_policy(value),
             GraphMsg::SetVerticalGraph(value) => self.graph_options.set_vertical_graph(value),
             GraphMsg::SetTransparentBackground(value) => {
                 self.graph_options.set_transparent_background(value)
             }
             GraphMsg::SetHideLegend(value) => self.graph_options.set_hide_legend(value),
+            GraphMsg::SetListenerRendering(value) => {
+                self.graph_options.set_listener_rendering(value)
+            }
+            GraphMsg::SetGranularity(value) => self.graph_options.set_granularity(value),
             GraphMsg::SetHideAgents(value) => self.graph_options.set_hide_agents(value),
+            GraphMsg::SetExcludedProcesses(prefixes) => {
+                self.graph_options.set_excluded_processes(prefixes)
+            }
+            GraphMsg::SetExcludedHosts(names) => self.graph_options.set_excluded_hosts(names),
+            GraphMsg::SetHighlightQuery(query) => {
+                let query = Some(query.trim().to_string()).filter(|query| !query.is_empty());
+                if query != self.graph_options.highlight_query {
+                    self.graph_options.set_highlight_query(query);
+                    // Only a graph which is already shown is generated again
+                    if self.get_graph_image_path().is_some() {
+                        sender.input(AppMsg::GraphMsg(GraphMsg::Generating(true)));
+                    }
+                }
+            }
             GraphMsg::SetImagePath(image_path) => self.set_graph_image_path(image_path),
-            GraphMsg::SetInputDir(dir) => {
-                self.files_options.set_input_directory(dir.clone());
-                if let Some(dir) = dir {
-                    self.files_options.set_scanned_hosts(Some(
-                        sockets_map::parsers::directory_scanner::scan_dir(&dir),
-                    ));
-                } else {
-                    self.files_options.set_scanned_hosts(None);
+            GraphMsg::SetShowDotCode(show_dot_code) => {
+                self.set_show_dot_code(show_dot_code);
+                if show_dot_code {
+                    self.set_show_home(false);
+                }
+            }
+            GraphMsg::SetShowHome(show_home) => {
+                self.set_show_home(show_home);
+                if show_home {
+                    self.set_show_dot_code(false);
+                }
+            }
+            GraphMsg::AddInputDir(dir) => {
+                if self.add_input_dir(dir) {
+                    self.outdate_graph();
                 }
             }
+            GraphMsg::RemoveInputDir(index) => {
+                if index < self.files_options.input_folders.len() {
+                    self.files_options.get_mut_input_folders().remove(index);
+                    self.outdate_graph();
+                }
+            }
+            GraphMsg::SetInputDirs(dirs) => {
+                self.files_options.get_mut_input_folders().clear();
+                for dir in dirs {
+                    self.add_input_dir(dir);
+                }
+                self.outdate_graph();
+            }
+            GraphMsg::ClearRecentFolders => self.files_options.get_mut_recent_folders().clear(),
             GraphMsg::SetFileExtension(file_extension) => {
                 self.graph_options.set_file_extension(file_extension);
             }
-            GraphMsg::ExportGraph(path) => {
-                if let Err(msg) = std::fs::copy(
-                    self.image_graph_tempfile.path(),
-                    path.with_extension(self.graph_options.file_extension.clone()),
+            GraphMsg::ExportGraph {
+                path,
+                anonymize: true,
+            } => {
+                if self.exportable_preview().is_none() {
+                    return;
+                }
+                match export::export_anonymized(
+                    &path,
+                    &self.graph_hosts,
+                    &(&self.graph_options).into(),
                 ) {
-                    self.set_error_message(Some(msg.to_string()));
+                    Ok(mapping_path) => self.notify(
+                        Severity::Info,
+                        format!("Real names of the anonymized graph written to {mapping_path:?}"),
+                    ),
+                    Err(e) => self.notify(Severity::Error, e.to_string()),
+                }
+            }
+            GraphMsg::ExportGraph { path, .. } => {
+                let Some(preview_path) = self.exportable_preview() else {
+                    return;
+                };
+                let provenance = self.export_provenance();
+                let graph_options = &self.graph_options;
+                if let Err(e) = export::export(
+                    &path,
+                    &preview_path,
+                    self.preview_files.format(),
+                    &graph_options.file_extension,
+                    &self.graph_dot_code,
+                    |output_path| {
+                        sockets_map::graphviz::run_graphviz(
+                            self.graph_dot_code.clone(),
+                            output_path,
+                            graph_options.file_extension.clone(),
+                            None,
+                            graph_options.vertical_graph,
+                            Some(&graph_options.layout_engine),
+                        )
+                    },
+                    &self.connection_records,
+                    provenance.as_ref(),
+                ) {
+                    self.notify(Severity::Error, e.to_string());
                 };
             }
+            GraphMsg::ExportAll { path, overwrite } => {
+                let Some(preview_path) = self.exportable_preview() else {
+                    return;
+                };
+                let image_extension = self.graph_options.file_extension.clone();
+                let existing_paths: Vec<PathBuf> =
+                    export::export_all_paths(&path, &image_extension)
+                        .into_iter()
+                        .filter(|path| path.exists())
+                        .collect();
+                if !overwrite && !existing_paths.is_empty() {
+                    confirm_overwrite(root, &existing_paths, path, sender);
+                } else {
+                    let graph = export::ExportedGraph {
+                        dot_code: self.graph_dot_code.clone(),
+                        connection_records: self.connection_records.clone(),
+                        preview_path,
+                        preview_extension: self.preview_files.format().to_string(),
+                        provenance: self.export_provenance(),
+                    };
+                    let graph_settings: GraphSettings = (&self.graph_options).into();
+                    let input_sender = sender.input_sender().clone();
+                    sender.oneshot_command(async move {
+                        let dot_code = graph.dot_code.clone();
+                        let render = |output_path: PathBuf| async move {
+                            sockets_map::graphviz::run_graphviz_cancellable(
+                                dot_code,
+                                &output_path,
+                                graph_settings.file_extension,
+                                None,
+                                graph_settings.vertical_graph,
+                                Some(&graph_settings.layout_engine),
+                                &CancellationToken::new(),
+                            )
+                            .await
+                        };
+                        for (path, res) in
+                            export::export_all(&path, &image_extension, &graph, render).await
+                        {
+                            input_sender.emit(match res {
+                                Ok(()) => {
+                                    AppMsg::Notify(Severity::Info, format!("Exported {path:?}"))
+                                }
+                                Err(e) => AppMsg::Notify(
+                                    Severity::Error,
+                                    format!("Unable to export {path:?}: {e:#}"),
+                                ),
+                            });
+                        }
+                        AppCmdOutput::Done
+                    });
+                }
+            }
             GraphMsg::TrySetOutputDPI(dpi_str) => match dpi_str.parse::<f64>() {
                 Ok(dpi) => {
                     self.graph_options.dpi = dpi;
                 }
                 Err(e) => {
                     if !dpi_str.is_empty() {
-                        sender.input(AppMsg::Error(Some(e.to_string())));
+                        sender.input(AppMsg::Notify(Severity::Error, e.to_string()));
                     }
                     self.graph_options.dpi = DEFAULT_DPI;
                 }
@@ -750,117 +1892,121 @@ impl AppModel {
                     }
                 }
             }
+            GraphMsg::SetZoom(zoom) => self.set_graph_zoom(zoom),
+            GraphMsg::SetConnectionRecords(records) => self.set_connection_records(records),
         }
     }
 }
 
-fn generate_png_temp_file_path() -> tempfile::NamedTempFile {
-    let named_temp_file = tempfile::Builder::new()
-        .suffix(".png")
-        .tempfile()
-        .expect("unable to create temporary file");
-    named_temp_file
+/// List the notifications in the messages popover
+fn fill_notifications_box(notifications_box: &gtk::Box, notifications: &Notifications) {
+    while let Some(child) = notifications_box.first_child() {
+        notifications_box.remove(&child);
+    }
+    if notifications.latest().is_none() {
+        notifications_box.append(&gtk::Label::new(Some("No messages")));
+    }
+    for notification in notifications.iter() {
+        notifications_box.append(
+            &gtk::Label::builder()
+                .label(notification.to_string())
+                .halign(gtk::Align::Start)
+                .wrap(true)
+                .max_width_chars(60)
+                .selectable(true)
+                .build(),
+        );
+    }
 }
 
-async fn generate_graph(
-    scanned_hosts: Option<Vec<ScannedHost>>,
-    clients: Arc<RwLock<HashMap<String, Client>>>,
-    tx_opt: Arc<RwLock<Option<BincodeSender<Message>>>>,
-    graph_options: &GraphOptions,
-    output_file: &Path,
-    dump_dot_code: Option<&PathBuf>,
-) -> anyhow::Result<()> {
-    // If the server is running and does not have got any update yet, send a request to clients
-    if let Some(tx) = tx_opt.write().await.as_mut() {
-        if !clients
-            .read()
-            .await
-            .iter()
-            .any(|(_name, client)| !client.updates().is_empty())
-        {
-            log::info!("sending update request to clients");
-            let (_res, _accept_res) = tx.send(Message::UpdateRequest).accepting().await;
-            log::debug!("peers when sending: {:?}", tx.peer_addrs());
-
-            // Wait for all clients to send their update, with a timeout
-            let mut interval = tokio::time::interval(Duration::from_millis(100));
-            let mut number_of_remaining_intervals = 20;
-            let mut still_missing_all_updates = true;
-            while number_of_remaining_intervals > 0 {
-                number_of_remaining_intervals -= 1;
-                interval.tick().await;
-                if clients
-                    .read()
-                    .await
-                    .iter()
-                    .any(|(_name, client)| client.updates().is_empty())
-                {
-                    continue;
-                } else {
-                    number_of_remaining_intervals = 0;
-                    still_missing_all_updates = false;
-                }
-            }
-
-            if still_missing_all_updates {
-                log::warn!("did not get an update from all clients"); // TODO: show in GUI
-            }
-        }
+/// Fill the recent sessions menu with a button per session file
+fn fill_recent_sessions_box(
+    recent_sessions_box: &gtk::Box,
+    recent_sessions: &[PathBuf],
+    session_popover: &gtk::Popover,
+    sender: &relm4::ComponentSender<AppModel>,
+) {
+    while let Some(child) = recent_sessions_box.first_child() {
+        recent_sessions_box.remove(&child);
+    }
+    if recent_sessions.is_empty() {
+        recent_sessions_box.append(&gtk::Label::new(Some("None")));
+    }
+    for path in recent_sessions {
+        let button = gtk::Button::builder()
+            .label(
+                path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            )
+            .tooltip_text(path.to_string_lossy().as_ref())
+            .has_frame(false)
+            .build();
+        button.connect_clicked(
+            clone!(@strong sender, @strong path, @strong session_popover => move |_| {
+                session_popover.popdown();
+                sender.input(AppMsg::SessionMsg(SessionMsg::Open(path.clone())));
+            }),
+        );
+        recent_sessions_box.append(&button);
     }
+}
 
-    let clients = clients.read().await;
+/// Layout of the window, saved in the sessions
+fn window_layout(window: &adw::Window, flap: &adw::Flap, graph_paned: &gtk::Paned) -> WindowLayout {
+    let (width, height) = window.default_size();
+    WindowLayout {
+        width,
+        height,
+        sidebar_revealed: flap.reveals_flap(),
+        preview_height: graph_paned.position(),
+    }
+}
 
-    // Scanned hosts
-    let mut hosts = scanned_hosts
-        .and_then(|scanned_hosts| {
-            sockets_map::parsers::directory_scanner::build_hosts(&scanned_hosts).ok()
-        })
-        .unwrap_or_default();
+/// Resize the window and its panes to the layout of an opened session
+fn apply_window_layout(widgets: &AppWidgets, layout: &WindowLayout) {
+    widgets.window.set_default_size(layout.width, layout.height);
+    widgets.flap.set_reveal_flap(layout.sidebar_revealed);
+    widgets
+        .graph_page_widgets
+        .graph_paned
+        .set_position(layout.preview_height);
+}
 
-    // Client hosts
-    let client_hosts: Vec<Host> = clients
+/// Ask before replacing the `existing_paths` of an export of every file to `path`, which is done
+/// again once confirmed
+fn confirm_overwrite(
+    root: &adw::Window,
+    existing_paths: &[PathBuf],
+    path: PathBuf,
+    sender: &relm4::ComponentSender<AppModel>,
+) {
+    let file_names: Vec<String> = existing_paths
         .iter()
-        .filter_map(|(_name, client)| client.updates().last().map(|update| update.host.clone()))
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().to_string())
         .collect();
-    hosts.extend(client_hosts);
-    if hosts.is_empty() {
-        bail!("No hosts to generate graph from");
-    }
-
-    // Exclude some processes from the analysis
-    if *graph_options.get_hide_agents() {
-        let excluded_processes = ["sockets_map"];
-        for host in hosts.iter_mut() {
-            host.exclude_processes(&excluded_processes);
+    let dialog = gtk::MessageDialog::builder()
+        .transient_for(root)
+        .modal(true)
+        .message_type(gtk::MessageType::Question)
+        .text("Replace the existing files?")
+        .secondary_text(format!("{} will be replaced.", file_names.join(", ")))
+        .build();
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel),
+        ("Replace", gtk::ResponseType::Accept),
+    ]);
+    dialog.connect_response(clone!(@strong sender => move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            sender.input(AppMsg::GraphMsg(GraphMsg::ExportAll {
+                path: path.clone(),
+                overwrite: true,
+            }));
         }
-    }
-
-    // Generate connections
-    let connections = sockets_map::connections_model::build_connections_list(
-        &hosts,
-        graph_options.hide_loopback_connections,
-    );
-
-    // Generate the Dot graph
-    let graph = sockets_map::graphs::create_graph(
-        &connections,
-        graph_options.transparent_background,
-        graph_options.hide_legend,
-        graph_options.dpi,
-        Some(&graph_options.layout_engine),
-    )?;
-
-    // Run Graphviz command to generate the graph
-    sockets_map::graphviz::run_graphviz(
-        graph.to_string(),
-        output_file,
-        graph_options.file_extension.clone(),
-        dump_dot_code,
-        graph_options.vertical_graph,
-        Some(&graph_options.layout_engine),
-    )?;
-
-    Ok(())
+        dialog.close();
+    }));
+    dialog.show();
 }
 
 pub struct ServerState {
@@ -871,4 +2017,8 @@ pub struct ServerState {
     pub is_enabled: bool,
     /// Channel sender
     pub tx: Arc<RwLock<Option<BincodeSender<Message>>>>,
+    /// Events of the last server started
+    pub event_log: Arc<RwLock<EventLog>>,
+    /// State of the listening loop of the running server
+    state_rx: Option<watch::Receiver<ListenState>>,
 }
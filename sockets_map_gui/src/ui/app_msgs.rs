@@ -1,12 +1,116 @@
-use sockets_map::graphviz::LayoutEngine;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use sockets_map::{
+    connections_model::ConnectionRecord,
+    graphs::{GraphGranularity, ListenerRendering, LoopbackRendering},
+    graphviz::LayoutEngine,
+    host::{DisplayOverride, SourcePolicy},
+    server::{client::UpdateSelection, event_log::EventLog, ListenOptions, ServerState},
+};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{oneshot, watch, RwLock};
 
-use super::{graph_options::GraphOptions, server::client::ClientInfo};
+use super::{
+    generation::{GeneratedGraph, DEFAULT_UPDATE_TIMEOUT},
+    graph_options::GraphOptions,
+    notifications::Severity,
+    server::{
+        client::ClientInfo, client_details::ClientDetails, deploy::DeployCommand,
+        recorder::DEFAULT_RECORDER_INTERVAL,
+    },
+    session::WindowLayout,
+};
 
-#[derive(Debug)]
+/// Number of updates kept for each client by the server of the GUI. It is above the default of
+/// the headless server, since all the updates kept may be merged into the graph.
+pub(crate) const GUI_MAX_UPDATES_PER_CLIENT: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerOption {
     pub listen_addr: String,
     pub listen_port: String,
+    /// Port of the HTTP status endpoint, disabled if empty
+    pub http_port: String,
+    /// Number of updates kept for each client
+    pub max_updates_per_client: String,
+    /// Size of the largest update accepted, in MiB
+    pub max_update_size: String,
+    /// Number of clients registered at once
+    pub max_clients: String,
+    /// Flag the clients which do not connect from one of their IPs
+    pub verify_source_ip: bool,
+    /// Comma-separated source IPs always accepted, such as the ones of NAT gateways
+    pub verify_exempt_ips: String,
+    /// Seconds to wait for the clients updates before generating the graph
+    pub update_timeout: String,
+    /// Updates of each client drawn in the graph
+    pub update_selection: UpdateSelection,
+    /// Seconds between two snapshots of the agents while recording
+    pub recorder_interval: String,
+}
+
+impl Default for ServerOption {
+    fn default() -> Self {
+        let listen_options = ListenOptions::default();
+        Self {
+            listen_addr: "0.0.0.0".into(),
+            listen_port: "6840".into(),
+            http_port: String::new(),
+            max_updates_per_client: GUI_MAX_UPDATES_PER_CLIENT.to_string(),
+            max_update_size: (listen_options.max_update_bytes / MIB).to_string(),
+            max_clients: listen_options.max_clients.to_string(),
+            verify_source_ip: listen_options.verify_source_ip,
+            verify_exempt_ips: String::new(),
+            update_timeout: DEFAULT_UPDATE_TIMEOUT.as_secs().to_string(),
+            update_selection: UpdateSelection::Latest,
+            recorder_interval: DEFAULT_RECORDER_INTERVAL.into(),
+        }
+    }
+}
+
+/// Number of bytes in a MiB, the unit of the update size limit
+const MIB: u64 = 1024 * 1024;
+
+impl ServerOption {
+    /// Time to wait for the clients updates before generating the graph, the default one if the
+    /// entry is invalid
+    pub fn update_timeout(&self) -> Duration {
+        self.update_timeout
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .unwrap_or(DEFAULT_UPDATE_TIMEOUT)
+    }
+
+    /// Limits of the server, the invalid ones being left to their default values
+    pub fn listen_options(&self) -> ListenOptions {
+        let default = ListenOptions::default();
+        ListenOptions {
+            max_updates_per_client: self
+                .max_updates_per_client
+                .trim()
+                .parse()
+                .unwrap_or(GUI_MAX_UPDATES_PER_CLIENT),
+            max_update_bytes: self
+                .max_update_size
+                .trim()
+                .parse::<u64>()
+                .map(|size| size.saturating_mul(MIB))
+                .unwrap_or(default.max_update_bytes),
+            max_clients: self
+                .max_clients
+                .trim()
+                .parse()
+                .unwrap_or(default.max_clients),
+            verify_source_ip: self.verify_source_ip,
+            verify_exempt_ips: self
+                .verify_exempt_ips
+                .split(',')
+                .filter_map(|ip| ip.trim().parse().ok())
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -14,41 +118,154 @@ pub enum ServerMsg {
     EnableServer(Option<ServerOption>),
     /// One time update request
     SendUpdateRequest,
+    /// One time update request, aggregating several snapshots taken `interval` seconds apart by
+    /// the agents which support it
+    SendSampledUpdateRequest {
+        samples: u8,
+        interval: f64,
+    },
     /// Start the recorder by sending a recorder request to agents
     StartRecorder(f64),
     /// Stop the recorder and collect data
     StopRecorder,
-    SetServerIsEnabled(bool),
+    /// Sent when the listen settings are edited
+    SetServerOption(ServerOption),
     ClientConnect(ClientInfo),
     ClientDisconnect(ClientInfo),
     ClientUpdate(ClientInfo),
+    /// Sent when the row of a client is clicked, with its index in the list
+    ShowClientDetails(usize),
+    /// Sent when the name or the color of a host is edited in the client details, an empty
+    /// override removing the previous one
+    SetDisplayOverride {
+        hostname: String,
+        display_override: DisplayOverride,
+    },
+    /// Sent once the server is started, with the state of its listening loop and its log of events
+    ServerStarted {
+        state_rx: watch::Receiver<ServerState>,
+        event_log: Arc<RwLock<EventLog>>,
+    },
+    /// Read the event log again, to show its new events
+    RefreshEventLog,
 }
 
 #[derive(Debug)]
 pub enum GraphMsg {
     GenerateGraph(GraphOptions),
     Generating(bool),
+    /// Stop the graph generation in progress, keeping the previous graph
+    CancelGeneration,
+    /// Periodic refresh while recording, `done` is dropped once the graph is generated
+    AutoRefresh(oneshot::Sender<()>),
+    SetAutoRefresh(bool),
+    TrySetAutoRefreshInterval(String),
     /// If `Some`, server is enabled with the options,
     /// otherwise it is disabled.
-    SetHideLoopbackConnections(bool),
+    SetLoopbackRendering(LoopbackRendering),
+    /// How the hosts found both in the input folders and in the agents updates are drawn
+    SetSourcePolicy(SourcePolicy),
     SetVerticalGraph(bool),
     SetTransparentBackground(bool),
     SetHideLegend(bool),
+    SetListenerRendering(ListenerRendering),
+    /// Whether the processes or only the hosts are drawn
+    SetGranularity(GraphGranularity),
     SetHideAgents(bool),
+    /// Prefixes of the names of the processes to hide
+    SetExcludedProcesses(Vec<String>),
+    /// Names of the hosts to hide
+    SetExcludedHosts(Vec<String>),
     SetImagePath(Option<PathBuf>),
+    /// Show the DOT code of the graph instead of its image
+    SetShowDotCode(bool),
+    /// Show the home page instead of the generated graph
+    SetShowHome(bool),
     SetFileExtension(String),
     TrySetOutputDPI(String),
     SetLayoutEngine(LayoutEngine),
-    /// Sent by the files stack page
-    SetInputDir(Option<PathBuf>),
-    ExportGraph(PathBuf),
+    /// Query of the search entry, the graph is generated again with its matches highlighted
+    SetHighlightQuery(String),
+    /// Sent by the files stack page, to add a folder, or scan it again if it was already added
+    AddInputDir(PathBuf),
+    /// Index of the input folder to remove
+    RemoveInputDir(usize),
+    /// Replace the input folders, when a session is opened
+    SetInputDirs(Vec<PathBuf>),
+    ClearRecentFolders,
+    /// Export the graph to a file, with generic names instead of the real ones if `anonymize` is set
+    ExportGraph {
+        path: PathBuf,
+        anonymize: bool,
+    },
+    /// Export the image, the DOT code and the connections as CSV and JSON next to `path`, named
+    /// after it. Existing files are only replaced if `overwrite` is set, otherwise it is asked.
+    ExportAll {
+        path: PathBuf,
+        overwrite: bool,
+    },
     OpenInViewer,
+    /// Zoom factor of the graph preview, or `None` to fit it in the view
+    SetZoom(Option<f64>),
+    /// Connections of the generated graph, shown in the table
+    SetConnectionRecords(Vec<ConnectionRecord>),
+}
+
+#[derive(Debug)]
+pub enum SessionMsg {
+    /// Save the session to the file, with the current layout of the window
+    Save(PathBuf, WindowLayout),
+    Open(PathBuf),
 }
 
 #[derive(Debug)]
 pub enum AppCmdOutput {
-    GeneratedGraph(Option<PathBuf>),
+    /// The rendered preview and the generated graph, or `None` if the generation failed
+    GeneratedGraph(Option<(tempfile::NamedTempFile, GeneratedGraph)>),
+    /// Current phase of the graph generation
+    GenerationProgress(String),
+    GenerationCancelled,
+    /// The periodic refreshes stopped, after this number of refreshes
+    AutoRefreshStopped(usize),
+    /// Details of the client at `index` in the clients list
+    ClientDetails {
+        index: usize,
+        details: ClientDetails,
+    },
     SetServerIsEnabled(bool),
-    Error(Option<String>),
+    /// The server stopped by itself, for this reason
+    ServerFailed(String),
+    /// The server could not listen, with the message to show
+    ServerStartFailed(String),
+    /// Rows of the event log list
+    EventLog(Vec<String>),
+    /// Commands to run the agents against the started server
+    DeployCommands(Vec<DeployCommand>),
+    Notify(Severity, String),
+    /// Sent by the commands which have nothing to report
+    Done,
     RecorderTimerTick,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The server of the GUI must keep its own number of updates, whether the entry is left as is
+    /// or is invalid
+    #[test]
+    fn test_listen_options_max_updates() {
+        let mut server_option = ServerOption::default();
+        assert_eq!(
+            server_option.listen_options().max_updates_per_client,
+            GUI_MAX_UPDATES_PER_CLIENT
+        );
+        server_option.max_updates_per_client = "many".into();
+        assert_eq!(
+            server_option.listen_options().max_updates_per_client,
+            GUI_MAX_UPDATES_PER_CLIENT
+        );
+        server_option.max_updates_per_client = "20".into();
+        assert_eq!(server_option.listen_options().max_updates_per_client, 20);
+    }
+}
@@ -0,0 +1,125 @@
+//! Appearance of the application (system, light or dark), set in the preferences window
+
+use gtk::glib::clone;
+use relm4::adw::{
+    self,
+    prelude::{
+        ActionRowExt, ComboRowExt, PreferencesGroupExt, PreferencesPageExt, PreferencesWindowExt,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use super::AppMsg;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Appearance {
+    /// Follow the light or dark style of the system
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+/// Appearances, in the order of the dropdown
+const APPEARANCES: [Appearance; 3] = [Appearance::System, Appearance::Light, Appearance::Dark];
+
+impl Appearance {
+    fn label(&self) -> &'static str {
+        match self {
+            Appearance::System => "Follow system",
+            Appearance::Light => "Light",
+            Appearance::Dark => "Dark",
+        }
+    }
+}
+
+/// Color scheme of libadwaita giving this appearance
+pub(crate) fn color_scheme(appearance: Appearance) -> adw::ColorScheme {
+    match appearance {
+        Appearance::System => adw::ColorScheme::Default,
+        Appearance::Light => adw::ColorScheme::ForceLight,
+        Appearance::Dark => adw::ColorScheme::ForceDark,
+    }
+}
+
+/// Apply the appearance to the whole application
+pub(crate) fn apply(appearance: Appearance) {
+    let display = gtk::gdk::Display::default().expect("unable to get default display");
+    adw::StyleManager::for_display(&display).set_color_scheme(color_scheme(appearance));
+}
+
+/// Create the preferences window, hidden until it is presented
+pub(crate) fn init_preferences_window(
+    app_window: &adw::Window,
+    appearance: Appearance,
+    embed_provenance: bool,
+    sender: relm4::ComponentSender<super::AppModel>,
+) -> adw::PreferencesWindow {
+    let labels: Vec<&str> = APPEARANCES.iter().map(Appearance::label).collect();
+    let appearance_row = adw::ComboRow::builder()
+        .title("Appearance")
+        .subtitle("Style of the application")
+        .model(&gtk::StringList::new(&labels))
+        .build();
+    if let Some(index) = APPEARANCES.iter().position(|a| *a == appearance) {
+        appearance_row.set_selected(index as u32);
+    }
+    appearance_row.connect_selected_notify(clone!(@strong sender => move |row| {
+        if let Some(appearance) = APPEARANCES.get(row.selected() as usize) {
+            sender.input(AppMsg::SetAppearance(*appearance));
+        }
+    }));
+
+    let provenance_switch = gtk::Switch::builder()
+        .active(embed_provenance)
+        .valign(gtk::Align::Center)
+        .build();
+    provenance_switch.connect_active_notify(clone!(@strong sender => move |switch| {
+        sender.input(AppMsg::SetEmbedProvenance(switch.is_active()));
+    }));
+    let provenance_row = adw::ActionRow::builder()
+        .title("Embed provenance")
+        .subtitle(
+            "Write the version, the time, the inputs and the graph settings in the exported files",
+        )
+        .activatable_widget(&provenance_switch)
+        .build();
+    provenance_row.add_suffix(&provenance_switch);
+
+    let style_group = adw::PreferencesGroup::builder().title("Style").build();
+    style_group.add(&appearance_row);
+    let export_group = adw::PreferencesGroup::builder().title("Export").build();
+    export_group.add(&provenance_row);
+    let page = adw::PreferencesPage::builder()
+        .title("General")
+        .icon_name("preferences-system-symbolic")
+        .build();
+    page.add(&style_group);
+    page.add(&export_group);
+    let window = adw::PreferencesWindow::builder()
+        .transient_for(app_window)
+        .modal(true)
+        .hide_on_close(true)
+        .search_enabled(false)
+        .build();
+    window.add(&page);
+    window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the system appearance must let the system choose the style
+    #[test]
+    fn test_color_scheme() {
+        assert_eq!(color_scheme(Appearance::System), adw::ColorScheme::Default);
+        assert_eq!(
+            color_scheme(Appearance::Light),
+            adw::ColorScheme::ForceLight
+        );
+        assert_eq!(color_scheme(Appearance::Dark), adw::ColorScheme::ForceDark);
+        assert_eq!(Appearance::default(), Appearance::System);
+    }
+}
@@ -0,0 +1,96 @@
+//! Periodic regeneration of the graph while recording
+
+use std::{future::Future, pin::Pin, time::Duration};
+
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+/// Default interval between two refreshes, in seconds
+pub(crate) const DEFAULT_AUTO_REFRESH_INTERVAL: f64 = 5.0;
+
+/// Start a generation every `interval`, until `cancel_token` is cancelled. The ticks occurring
+/// while the previous generation is still in flight are skipped. Return the number of generations
+/// which were started.
+pub(crate) async fn schedule_refreshes<F, Fut>(
+    interval: Duration,
+    cancel_token: &CancellationToken,
+    mut generate: F,
+) -> usize
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut in_flight: Option<Pin<Box<Fut>>> = None;
+    let mut started = 0;
+    loop {
+        tokio::select! {
+            biased;
+            _ = cancel_token.cancelled() => break,
+            _ = async { in_flight.as_mut().unwrap().await }, if in_flight.is_some() => {
+                in_flight = None;
+            }
+            _ = ticks.tick() => {
+                if in_flight.is_some() {
+                    log::debug!("skipping the graph refresh, a generation is in flight");
+                } else {
+                    in_flight = Some(Box::pin(generate()));
+                    started += 1;
+                }
+            }
+        }
+    }
+    started
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::sync::Notify;
+
+    /// Ticks must be skipped until the generation in flight completes
+    #[tokio::test]
+    async fn test_schedule_refreshes_skips_in_flight() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+        let cancel_token = CancellationToken::new();
+        let scheduler = tokio::spawn({
+            let started = started.clone();
+            let release = release.clone();
+            let cancel_token = cancel_token.clone();
+            async move {
+                schedule_refreshes(Duration::from_millis(10), &cancel_token, move || {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    let release = release.clone();
+                    async move { release.notified().await }
+                })
+                .await
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(55)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        release.notify_one();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+
+        // Cancelling does not wait for the generation in flight
+        cancel_token.cancel();
+        assert_eq!(scheduler.await.unwrap(), 2);
+    }
+
+    /// No generation must be started once cancelled
+    #[tokio::test]
+    async fn test_schedule_refreshes_cancelled() {
+        let cancel_token = CancellationToken::new();
+        cancel_token.cancel();
+        let started =
+            schedule_refreshes(Duration::from_millis(1), &cancel_token, || async {}).await;
+        assert_eq!(started, 0);
+    }
+}
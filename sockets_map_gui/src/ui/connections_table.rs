@@ -0,0 +1,208 @@
+//! Table of the connections of the last generated graph
+
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use gtk::{
+    gdk, gio,
+    glib::{clone, BoxedAnyObject},
+    prelude::{Cast, ListModelExt, StaticType},
+    traits::{BoxExt, ButtonExt, EditableExt, FilterExt, PopoverExt, SelectionModelExt, WidgetExt},
+};
+use sockets_map::{connections_model::ConnectionRecord, csv::CsvOptions};
+
+/// Columns of the table
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    SourceHost,
+    SourceProcess,
+    DestHost,
+    DestProcess,
+    Port,
+    Protocol,
+}
+
+impl Column {
+    const ALL: [Column; 6] = [
+        Column::SourceHost,
+        Column::SourceProcess,
+        Column::DestHost,
+        Column::DestProcess,
+        Column::Port,
+        Column::Protocol,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            Column::SourceHost => "Source host",
+            Column::SourceProcess => "Source process",
+            Column::DestHost => "Dest host",
+            Column::DestProcess => "Dest process",
+            Column::Port => "Port",
+            Column::Protocol => "Protocol",
+        }
+    }
+
+    /// Text of the cell, processes are shown with their PID
+    fn text(&self, record: &ConnectionRecord) -> String {
+        match self {
+            Column::SourceHost => record.source_host().to_string(),
+            Column::SourceProcess => {
+                format!("{} ({})", record.source_process(), record.source_pid())
+            }
+            Column::DestHost => record.dest_host().to_string(),
+            Column::DestProcess => format!("{} ({})", record.dest_process(), record.dest_pid()),
+            Column::Port => record.dest_socket().port().to_string(),
+            Column::Protocol => record.protocol().to_string(),
+        }
+    }
+
+    fn compare(&self, a: &ConnectionRecord, b: &ConnectionRecord) -> Ordering {
+        match self {
+            Column::SourceProcess => {
+                (a.source_process(), a.source_pid()).cmp(&(b.source_process(), b.source_pid()))
+            }
+            Column::DestProcess => {
+                (a.dest_process(), a.dest_pid()).cmp(&(b.dest_process(), b.dest_pid()))
+            }
+            Column::Port => a.dest_socket().port().cmp(&b.dest_socket().port()),
+            Column::Protocol => a.protocol().cmp(b.protocol()),
+            _ => self.text(a).cmp(&self.text(b)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConnectionsTableWidgets {
+    /// Search entry and table, hidden until a graph is generated
+    pub container: gtk::Box,
+    store: gio::ListStore,
+}
+
+/// Generate the table widgets, with a search entry filtering the rows
+pub(crate) fn init_connections_table() -> ConnectionsTableWidgets {
+    let container = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .visible(false)
+        .build();
+    let store = gio::ListStore::new(BoxedAnyObject::static_type());
+
+    // Rows matching the search text in any column
+    let search_entry = gtk::SearchEntry::builder()
+        .placeholder_text("Search connections")
+        .build();
+    let search_text = Rc::new(RefCell::new(String::new()));
+    let filter = gtk::CustomFilter::new(clone!(@strong search_text => move |item| {
+        let search_text = search_text.borrow();
+        let record = item.downcast_ref::<BoxedAnyObject>().unwrap();
+        let record = record.borrow::<ConnectionRecord>();
+        Column::ALL
+            .iter()
+            .any(|column| column.text(&record).to_lowercase().contains(search_text.as_str()))
+    }));
+    search_entry.connect_search_changed(clone!(@strong filter => move |entry| {
+        *search_text.borrow_mut() = entry.text().to_lowercase();
+        filter.changed(gtk::FilterChange::Different);
+    }));
+    let filter_model = gtk::FilterListModel::builder()
+        .model(&store)
+        .filter(&filter)
+        .build();
+
+    // Sorted by clicking on the column headers
+    let column_view = gtk::ColumnView::builder()
+        .show_column_separators(true)
+        .show_row_separators(true)
+        .build();
+    for column in Column::ALL {
+        let factory = gtk::SignalListItemFactory::new();
+        factory.connect_setup(|_, list_item| {
+            list_item.set_child(Some(&gtk::Label::builder().xalign(0.0).build()));
+        });
+        factory.connect_bind(move |_, list_item| {
+            let record = list_item
+                .item()
+                .and_then(|item| item.downcast::<BoxedAnyObject>().ok());
+            let label = list_item
+                .child()
+                .and_then(|child| child.downcast::<gtk::Label>().ok());
+            if let (Some(record), Some(label)) = (record, label) {
+                label.set_label(&column.text(&record.borrow::<ConnectionRecord>()));
+            }
+        });
+        let sorter = gtk::CustomSorter::new(move |a, b| {
+            let a = a.downcast_ref::<BoxedAnyObject>().unwrap();
+            let b = b.downcast_ref::<BoxedAnyObject>().unwrap();
+            column
+                .compare(
+                    &a.borrow::<ConnectionRecord>(),
+                    &b.borrow::<ConnectionRecord>(),
+                )
+                .into()
+        });
+        column_view.append_column(
+            &gtk::ColumnViewColumn::builder()
+                .title(column.title())
+                .factory(&factory)
+                .sorter(&sorter)
+                .resizable(true)
+                .expand(true)
+                .build(),
+        );
+    }
+    let sort_model = gtk::SortListModel::builder().model(&filter_model).build();
+    sort_model.set_sorter(column_view.sorter().as_ref());
+    let selection = gtk::MultiSelection::builder().model(&sort_model).build();
+    column_view.set_model(Some(&selection));
+
+    // Right click menu to copy the selected rows
+    let copy_button = gtk::Button::builder()
+        .label("Copy as CSV")
+        .has_frame(false)
+        .build();
+    let context_menu = gtk::Popover::builder()
+        .child(&copy_button)
+        .has_arrow(false)
+        .build();
+    context_menu.set_parent(&column_view);
+    copy_button.connect_clicked(
+        clone!(@strong selection, @strong column_view, @strong context_menu => move |_| {
+            let records: Vec<ConnectionRecord> = (0..selection.n_items())
+                .filter(|position| selection.is_selected(*position))
+                .filter_map(|position| selection.item(position))
+                .filter_map(|item| item.downcast::<BoxedAnyObject>().ok())
+                .map(|item| item.borrow::<ConnectionRecord>().clone())
+                .collect();
+            match sockets_map::csv::connection_records_to_string(&records, &CsvOptions::default()) {
+                Ok(csv) => column_view.clipboard().set_text(&csv),
+                Err(e) => log::error!("unable to copy the connections: {e}"),
+            }
+            context_menu.popdown();
+        }),
+    );
+    let right_click = gtk::GestureClick::builder()
+        .button(gdk::BUTTON_SECONDARY)
+        .build();
+    right_click.connect_pressed(clone!(@strong context_menu => move |_, _, x, y| {
+        context_menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        context_menu.popup();
+    }));
+    column_view.add_controller(&right_click);
+
+    let scrolled_window = gtk::ScrolledWindow::builder()
+        .vexpand(true)
+        .child(&column_view)
+        .build();
+    container.append(&search_entry);
+    container.append(&scrolled_window);
+
+    ConnectionsTableWidgets { container, store }
+}
+
+/// Replace the rows of the table, and show it
+pub(crate) fn set_records(table: &ConnectionsTableWidgets, records: &[ConnectionRecord]) {
+    table.store.remove_all();
+    for record in records {
+        table.store.append(&BoxedAnyObject::new(record.clone()));
+    }
+    table.container.set_visible(true);
+}
@@ -0,0 +1,543 @@
+//! Export of the generated graph, as an image, as DOT code or as the list of its connections
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use sockets_map::{
+    anonymize::Anonymizer,
+    connections_model::{self, ConnectionRecord},
+    csv::CsvOptions,
+    graphs::{self, LoopbackRendering},
+    graphviz,
+    host::Host,
+    provenance::Provenance,
+};
+
+use super::{graph_options::SUPPORTED_FORMATS, session::GraphSettings};
+
+/// What is written to the file chosen in the export dialog
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    /// Generated image, in the selected output format
+    Image,
+    /// DOT code of the graph, to be edited by hand
+    Dot,
+    /// Connections of the graph
+    Csv,
+    /// Connections of the graph, as JSON
+    Json,
+}
+
+/// Choose what to export from the extension of `path`. Without extension, the image is exported.
+pub(crate) fn export_format(path: &Path) -> anyhow::Result<ExportFormat> {
+    let extension = match path.extension() {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+        None => return Ok(ExportFormat::Image),
+    };
+    match extension.as_str() {
+        "csv" => Ok(ExportFormat::Csv),
+        "json" => Ok(ExportFormat::Json),
+        "dot" | "gv" => Ok(ExportFormat::Dot),
+        "jpg" => Ok(ExportFormat::Image),
+        extension if SUPPORTED_FORMATS.contains(&extension) => Ok(ExportFormat::Image),
+        extension => bail!(
+            "unable to export to a .{extension} file, use an image format, .dot, .gv, .csv or .json"
+        ),
+    }
+}
+
+/// Export the graph to `path`. Images get the `export_extension`, whatever the extension of
+/// `path`: the preview at `image_path` is copied if it has the same format, otherwise the graph is
+/// rendered again by `render`. The `provenance` is embedded in the file, if any.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn export(
+    path: &Path,
+    image_path: &Path,
+    image_extension: &str,
+    export_extension: &str,
+    dot_code: &str,
+    render: impl FnOnce(&Path) -> anyhow::Result<()>,
+    connection_records: &[ConnectionRecord],
+    provenance: Option<&Provenance>,
+) -> anyhow::Result<()> {
+    match export_format(path)? {
+        ExportFormat::Image => {
+            let output_path = path.with_extension(export_extension);
+            if image_extension == export_extension {
+                std::fs::copy(image_path, &output_path)?;
+            } else {
+                render(&output_path)?;
+            }
+            if let Some(provenance) = provenance {
+                graphviz::embed_provenance(&output_path, export_extension, provenance)?;
+            }
+        }
+        ExportFormat::Dot => std::fs::write(path, dot_with_provenance(dot_code, provenance))?,
+        ExportFormat::Csv => std::fs::write(
+            path,
+            sockets_map::csv::connection_records_to_string(
+                connection_records,
+                &csv_options(provenance),
+            )?,
+        )?,
+        ExportFormat::Json => {
+            std::fs::write(path, connections_json(connection_records, provenance)?)?
+        }
+    }
+    Ok(())
+}
+
+/// DOT code ending with the `provenance` as comments, if any
+fn dot_with_provenance(dot_code: &str, provenance: Option<&Provenance>) -> String {
+    match provenance {
+        Some(provenance) => format!("{dot_code}\n{}", provenance.comment_block("//")),
+        None => dot_code.to_string(),
+    }
+}
+
+/// Default CSV layout, with the `provenance` as comments after the rows
+fn csv_options(provenance: Option<&Provenance>) -> CsvOptions {
+    CsvOptions {
+        provenance: provenance.cloned(),
+        ..Default::default()
+    }
+}
+
+/// Connections as JSON, in a `connections` object next to the `provenance` in `_meta` if any
+fn connections_json(
+    connection_records: &[ConnectionRecord],
+    provenance: Option<&Provenance>,
+) -> anyhow::Result<String> {
+    Ok(match provenance {
+        Some(provenance) => serde_json::to_string_pretty(&serde_json::json!({
+            "_meta": provenance,
+            "connections": connection_records,
+        }))?,
+        None => serde_json::to_string_pretty(connection_records)?,
+    })
+}
+
+/// Export the graph of `hosts` anonymized to `path`, drawn with `graph_settings`, and write the
+/// mapping to the real names next to it. Returns the path of the mapping file.
+pub(crate) fn export_anonymized(
+    path: &Path,
+    hosts: &[Host],
+    graph_settings: &GraphSettings,
+) -> anyhow::Result<PathBuf> {
+    let mut anonymizer = Anonymizer::new();
+    let hosts = anonymizer.anonymize_hosts(hosts);
+    let connections = connections_model::build_connections_list(
+        &hosts,
+        graph_settings.loopback_rendering == LoopbackRendering::Hide,
+    );
+    // The highlight query matches the real names, so it is left out
+    let dot_code = graphs::create_graph(
+        &connections,
+        &graphs::DrawOptions {
+            graph: graphs::MapGraphOptions {
+                granularity: graph_settings.granularity,
+                loopback_rendering: graph_settings.loopback_rendering,
+                listener_rendering: graph_settings.listener_rendering,
+                ..Default::default()
+            },
+            transparent_background: graph_settings.transparent_background,
+            hide_legend: graph_settings.hide_legend,
+            dpi: graph_settings.dpi,
+            layout_engine: Some(graph_settings.layout_engine.clone()),
+            ..Default::default()
+        },
+    )?;
+    let connection_records: Vec<ConnectionRecord> =
+        connections.iter().map(ConnectionRecord::from).collect();
+
+    // The preview shows the real names, so the image is always rendered again. The provenance
+    // names the real capture directories and hosts, so it is left out.
+    export(
+        path,
+        Path::new(""),
+        "",
+        &graph_settings.file_extension,
+        &dot_code,
+        |output_path| {
+            sockets_map::graphviz::run_graphviz(
+                dot_code.clone(),
+                output_path,
+                graph_settings.file_extension.clone(),
+                None,
+                graph_settings.vertical_graph,
+                Some(&graph_settings.layout_engine),
+            )
+        },
+        &connection_records,
+        None,
+    )?;
+
+    let mapping_path = path.with_extension("mapping.json");
+    anonymizer.write_mapping(&mapping_path)?;
+    Ok(mapping_path)
+}
+
+/// Last generated graph, written at once by [`export_all`]
+pub(crate) struct ExportedGraph {
+    pub dot_code: String,
+    pub connection_records: Vec<ConnectionRecord>,
+    /// Rendered preview, copied if it has the exported image format
+    pub preview_path: PathBuf,
+    pub preview_extension: String,
+    /// Embedded in every file, if any
+    pub provenance: Option<Provenance>,
+}
+
+/// Files written by [`export_all`] next to `path`, named after it: the image with the
+/// `image_extension`, the connections as CSV, the DOT code and the connections as JSON
+pub(crate) fn export_all_paths(path: &Path, image_extension: &str) -> [PathBuf; 4] {
+    [image_extension, "csv", "dot", "json"].map(|extension| path.with_extension(extension))
+}
+
+/// Export the image, the connections and the DOT code of `graph` to the files of
+/// [`export_all_paths`], overwriting them. The image is rendered by `render` unless the preview
+/// has the same format. A failed file does not prevent the others from being written, and the
+/// outcome of each file is returned.
+pub(crate) async fn export_all<F>(
+    path: &Path,
+    image_extension: &str,
+    graph: &ExportedGraph,
+    render: impl FnOnce(PathBuf) -> F,
+) -> Vec<(PathBuf, anyhow::Result<()>)>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let [image_path, csv_path, dot_path, json_path] = export_all_paths(path, image_extension);
+    let image_res = if graph.preview_extension == image_extension {
+        tokio::fs::copy(&graph.preview_path, &image_path)
+            .await
+            .map(|_| ())
+            .map_err(anyhow::Error::from)
+    } else {
+        render(image_path.clone()).await
+    };
+    let image_res = match (image_res, &graph.provenance) {
+        (Ok(()), Some(provenance)) => {
+            graphviz::embed_provenance(&image_path, image_extension, provenance)
+        }
+        (image_res, _) => image_res,
+    };
+
+    let mut exported = vec![(image_path, image_res)];
+    let contents = [
+        (
+            csv_path,
+            sockets_map::csv::connection_records_to_string(
+                &graph.connection_records,
+                &csv_options(graph.provenance.as_ref()),
+            ),
+        ),
+        (
+            dot_path,
+            Ok(dot_with_provenance(
+                &graph.dot_code,
+                graph.provenance.as_ref(),
+            )),
+        ),
+        (
+            json_path,
+            connections_json(&graph.connection_records, graph.provenance.as_ref()),
+        ),
+    ];
+    for (path, contents) in contents {
+        let res = match contents {
+            Ok(contents) => tokio::fs::write(&path, contents)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        exported.push((path, res));
+    }
+    exported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The format is chosen from the extension, whatever its case
+    #[test]
+    fn test_export_format() {
+        assert_eq!(
+            export_format(Path::new("graph")).unwrap(),
+            ExportFormat::Image
+        );
+        assert_eq!(
+            export_format(Path::new("graph.png")).unwrap(),
+            ExportFormat::Image
+        );
+        assert_eq!(
+            export_format(Path::new("graph.JPG")).unwrap(),
+            ExportFormat::Image
+        );
+        assert_eq!(
+            export_format(Path::new("graph.svg")).unwrap(),
+            ExportFormat::Image
+        );
+        assert_eq!(
+            export_format(Path::new("graph.csv")).unwrap(),
+            ExportFormat::Csv
+        );
+        assert_eq!(
+            export_format(Path::new("graph.CSV")).unwrap(),
+            ExportFormat::Csv
+        );
+        assert_eq!(
+            export_format(Path::new("graph.json")).unwrap(),
+            ExportFormat::Json
+        );
+        assert_eq!(
+            export_format(Path::new("graph.dot")).unwrap(),
+            ExportFormat::Dot
+        );
+        assert_eq!(
+            export_format(Path::new("graph.gv")).unwrap(),
+            ExportFormat::Dot
+        );
+        assert!(export_format(Path::new("graph.txt")).is_err());
+    }
+
+    /// Render the graph as a fake image
+    fn render(output_path: &Path) -> anyhow::Result<()> {
+        std::fs::write(output_path, b"rendered")?;
+        Ok(())
+    }
+
+    /// The CSV file must be written from the records, not copied from the image
+    #[test]
+    fn test_export() {
+        let directory = tempfile::tempdir().unwrap();
+        let image_path = directory.path().join("tmp.png");
+        std::fs::write(&image_path, b"\x89PNG").unwrap();
+
+        let csv_path = directory.path().join("connections.csv");
+        export(&csv_path, &image_path, "png", "png", "", render, &[], None).unwrap();
+        assert!(std::fs::read_to_string(&csv_path)
+            .unwrap()
+            .starts_with("Source host,Dest host"));
+
+        let json_path = directory.path().join("connections.json");
+        export(&json_path, &image_path, "png", "png", "", render, &[], None).unwrap();
+        assert_eq!(std::fs::read_to_string(&json_path).unwrap(), "[]");
+
+        export(
+            &directory.path().join("graph"),
+            &image_path,
+            "png",
+            "png",
+            "",
+            render,
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(directory.path().join("graph.png")).unwrap(),
+            b"\x89PNG"
+        );
+
+        assert!(export(
+            &directory.path().join("graph.txt"),
+            &image_path,
+            "png",
+            "png",
+            "",
+            render,
+            &[],
+            None
+        )
+        .is_err());
+    }
+
+    /// A preview in another format than the exported one must be rendered again
+    #[test]
+    fn test_export_other_format() {
+        let directory = tempfile::tempdir().unwrap();
+        let image_path = directory.path().join("tmp.svg");
+        std::fs::write(&image_path, b"<svg/>").unwrap();
+
+        export(
+            &directory.path().join("graph.png"),
+            &image_path,
+            "svg",
+            "png",
+            "",
+            render,
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(directory.path().join("graph.png")).unwrap(),
+            b"rendered"
+        );
+
+        export(
+            &directory.path().join("graph.svg"),
+            &image_path,
+            "svg",
+            "svg",
+            "",
+            |_: &Path| panic!("the preview must be copied"),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(directory.path().join("graph.svg")).unwrap(),
+            b"<svg/>"
+        );
+    }
+
+    /// The DOT code must be written as is, without rendering it
+    #[test]
+    fn test_export_dot() {
+        let directory = tempfile::tempdir().unwrap();
+        let image_path = directory.path().join("tmp.svg");
+        std::fs::write(&image_path, b"<svg/>").unwrap();
+        let dot_code = "strict digraph G { a -> b; }";
+
+        for file_name in ["graph.dot", "graph.gv"] {
+            let path = directory.path().join(file_name);
+            export(
+                &path,
+                &image_path,
+                "svg",
+                "png",
+                dot_code,
+                |_: &Path| panic!("the DOT code must not be rendered"),
+                &[],
+                None,
+            )
+            .unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), dot_code);
+        }
+    }
+
+    /// Every file must be written next to the chosen path, even when the image cannot be rendered
+    #[tokio::test]
+    async fn test_export_all() {
+        let directory = tempfile::tempdir().unwrap();
+        let preview_path = directory.path().join("tmp.svg");
+        std::fs::write(&preview_path, b"<svg/>").unwrap();
+        let graph = ExportedGraph {
+            dot_code: "strict digraph G { a -> b; }".to_string(),
+            connection_records: Vec::new(),
+            preview_path,
+            preview_extension: "svg".to_string(),
+            provenance: None,
+        };
+        let path = directory.path().join("audit.png");
+        let render_failed = |_| async { anyhow::bail!("dot is not installed") };
+
+        let exported = export_all(&path, "svg", &graph, render_failed).await;
+        let paths: Vec<&PathBuf> = exported.iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            export_all_paths(&path, "svg").iter().collect::<Vec<_>>()
+        );
+        assert!(exported.iter().all(|(_, res)| res.is_ok()));
+        assert_eq!(
+            std::fs::read(directory.path().join("audit.svg")).unwrap(),
+            b"<svg/>"
+        );
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join("audit.dot")).unwrap(),
+            graph.dot_code
+        );
+        assert!(std::fs::read_to_string(directory.path().join("audit.csv"))
+            .unwrap()
+            .starts_with("Source host,Dest host"));
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join("audit.json")).unwrap(),
+            "[]"
+        );
+
+        let exported = export_all(&path, "png", &graph, render_failed).await;
+        assert_eq!(exported[0].0, directory.path().join("audit.png"));
+        assert!(exported[0].1.is_err());
+        assert!(exported[1..].iter().all(|(_, res)| res.is_ok()));
+    }
+
+    /// The provenance must be embedded in every file, the JSON connections moving to an object
+    #[tokio::test]
+    async fn test_export_all_provenance() {
+        let directory = tempfile::tempdir().unwrap();
+        let preview_path = directory.path().join("tmp.svg");
+        std::fs::write(&preview_path, "<svg width=\"8pt\">\n</svg>\n").unwrap();
+        let provenance = Provenance::collect(
+            "sockets_map_gui 1.0.0",
+            &[directory.path()],
+            &["db01".to_string()],
+            &[],
+        );
+        let graph = ExportedGraph {
+            dot_code: "strict digraph G { a -> b; }".to_string(),
+            connection_records: Vec::new(),
+            preview_path,
+            preview_extension: "svg".to_string(),
+            provenance: Some(provenance.clone()),
+        };
+        let path = directory.path().join("audit");
+        let render = |_| async { anyhow::bail!("the preview must be copied") };
+
+        let exported = export_all(&path, "svg", &graph, render).await;
+        assert!(exported.iter().all(|(_, res)| res.is_ok()));
+        let read = |extension| std::fs::read_to_string(path.with_extension(extension)).unwrap();
+        assert!(read("svg").contains("<metadata id=\"provenance\">\nSoftware: sockets_map_gui"));
+        assert!(read("csv").ends_with(&provenance.comment_block("#")));
+        assert!(read("dot").ends_with(&provenance.comment_block("//")));
+        let json: serde_json::Value = serde_json::from_str(&read("json")).unwrap();
+        assert_eq!(json["_meta"]["inputs"][1], "agent db01");
+        assert_eq!(json["connections"], serde_json::json!([]));
+    }
+
+    /// The anonymized exports must not show the real names, which are written to the mapping file
+    #[test]
+    fn test_export_anonymized() {
+        use sockets_map::host::{Connection, ListeningSocket, Process, SocketType};
+
+        let mut server = Host::new("db-prod");
+        server.add_ip("192.168.1.20".parse().unwrap());
+        server.add_listening_socket(ListeningSocket::new(
+            "0.0.0.0:5432".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("postgres", 100, "db-prod".to_string()),
+            "db-prod".to_string(),
+            None,
+        ));
+        let mut client = Host::new("web-prod");
+        client.add_ip("192.168.1.10".parse().unwrap());
+        client.add_established_connection(Connection::new(
+            "192.168.1.10:50000".parse().unwrap(),
+            "192.168.1.20:5432".parse().unwrap(),
+            SocketType::TCP,
+            Process::new("nginx", 200, "web-prod".to_string()),
+        ));
+        let hosts = [server, client];
+        let directory = tempfile::tempdir().unwrap();
+
+        for file_name in ["connections.csv", "graph.dot"] {
+            let path = directory.path().join(file_name);
+            let mapping_path = export_anonymized(&path, &hosts, &GraphSettings::default()).unwrap();
+            let exported = std::fs::read_to_string(&path).unwrap();
+            assert!(exported.contains("host-01"), "{exported}");
+            for real in ["db-prod", "web-prod", "postgres", "nginx", "192.168.1."] {
+                assert!(!exported.contains(real), "{real} in {file_name}");
+            }
+
+            let mapping = std::fs::read_to_string(&mapping_path).unwrap();
+            assert!(mapping.contains("db-prod"));
+        }
+        assert!(directory.path().join("connections.mapping.json").exists());
+        assert!(directory.path().join("graph.mapping.json").exists());
+    }
+}
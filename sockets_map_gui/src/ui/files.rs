@@ -1,41 +1,75 @@
 //! File input page widgets
 
 mod cheatsheet;
+pub(crate) mod recent_folders;
 
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 use gtk::{
     glib::clone,
     prelude::FileExt,
-    traits::{BoxExt, ButtonExt, FileChooserExt, NativeDialogExt},
+    traits::{BoxExt, ButtonExt, FileChooserExt, NativeDialogExt, PopoverExt, WidgetExt},
     FileChooser, FileFilter,
 };
 use relm4::{adw, ComponentController, Controller};
 use relm4::{Component, MessageBroker, RelmWidgetExt};
-use sockets_map::parsers::directory_scanner::ScannedHost;
+use sockets_map::parsers::{
+    diagnostics::{DiagnosticSeverity, ParseDiagnostic},
+    directory_scanner::ScannedHost,
+};
 
+use self::recent_folders::RecentFolders;
 use super::AppModel;
-use super::{app_msgs::GraphMsg, AppMsg};
+use super::{
+    app_msgs::GraphMsg,
+    shortcuts::{self, ShortcutAction},
+    AppMsg,
+};
 
 use relm4::ComponentSender;
 
 static CHEATSHEET_WINDOW_BROKER: MessageBroker<cheatsheet::CheatsheetWindow> = MessageBroker::new();
 
+/// A directory from which the app parses static hosts
+pub(crate) struct InputFolder {
+    pub path: PathBuf,
+    /// The static hosts found in the directory
+    pub scanned_hosts: Vec<ScannedHost>,
+}
+
 #[tracker::track]
 #[derive(Default)]
 pub(crate) struct FilesOptions {
-    /// The directory from which the app will parse static hosts
-    pub input_directory: Option<PathBuf>,
+    /// The input folders, in the order in which they were added
     #[tracker::no_eq]
-    /// The parsed static hosts
-    pub scanned_hosts: Option<Vec<ScannedHost>>,
+    pub input_folders: Vec<InputFolder>,
+    /// Input directories opened lately
+    pub recent_folders: RecentFolders,
+    /// Problems met while parsing the hosts at the last generation, by host name
+    pub diagnostics: BTreeMap<String, Vec<ParseDiagnostic>>,
+}
+
+impl FilesOptions {
+    /// The scanned hosts of each input folder
+    pub fn scanned_hosts(&self) -> Vec<Vec<ScannedHost>> {
+        self.input_folders
+            .iter()
+            .map(|folder| folder.scanned_hosts.clone())
+            .collect()
+    }
 }
 
 pub(crate) struct FilesPageWidgets {
-    pub hosts_text: gtk::Label,
+    /// The page in the sidebar stack
+    pub page: gtk::ScrolledWindow,
+    pub open_button: gtk::Button,
+    /// The parsed hosts, with a badge listing the problems of their files
+    pub hosts_box: gtk::Box,
     pub separator: gtk::Separator,
-    pub folder_label: gtk::Label,
-    pub delete_button: gtk::Button,
+    /// A row per input folder, with its delete button
+    folders_box: gtk::Box,
+    recent_folders_popover: gtk::Popover,
+    recent_folders_box: gtk::Box,
     #[allow(unused)]
     pub cheatsheet_window: Controller<cheatsheet::CheatsheetWindow>,
 }
@@ -76,7 +110,7 @@ pub(crate) fn init_sidebar_files_widgets(
             if response_type == gtk::ResponseType::Accept {
                 let chooser: FileChooser = file_chooser.to_owned().into();
                 if let Some(directory) = chooser.file().and_then(|d| d.path()) {
-                    sender.input(AppMsg::GraphMsg(GraphMsg::SetInputDir(Some(directory))));
+                    sender.input(AppMsg::GraphMsg(GraphMsg::AddInputDir(directory)));
                 }
             }
 
@@ -98,7 +132,12 @@ pub(crate) fn init_sidebar_files_widgets(
         .hexpand(false)
         .halign(gtk::Align::Center)
         .build();
-    let add_files_button = gtk::Button::new();
+    let add_files_button = gtk::Button::builder()
+        .tooltip_text(&shortcuts::tooltip(
+            "Add a capture folder",
+            ShortcutAction::OpenFolder,
+        ))
+        .build();
     let add_files_button_content = adw::ButtonContent::builder()
         .icon_name("document-open-symbolic")
         .label("Open")
@@ -112,6 +151,18 @@ pub(crate) fn init_sidebar_files_widgets(
     );
     buttons_box.append(&add_files_button);
 
+    // Recent folders menu
+    let recent_folders_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .build();
+    let recent_folders_popover = gtk::Popover::builder().child(&recent_folders_box).build();
+    let recent_folders_button = gtk::MenuButton::builder()
+        .icon_name("document-open-recent-symbolic")
+        .tooltip_text("Recent folders")
+        .popover(&recent_folders_popover)
+        .build();
+    buttons_box.append(&recent_folders_button);
+
     // Cheatsheets buttons
     let cheatsheet_button = gtk::Button::new();
     let cheatsheet_button_content = adw::ButtonContent::builder()
@@ -124,39 +175,27 @@ pub(crate) fn init_sidebar_files_widgets(
     }));
     buttons_box.append(&cheatsheet_button);
 
-    // Selected folder display
-    let selected_folder_box = gtk::Box::builder()
-        .orientation(gtk::Orientation::Horizontal)
-        .spacing(10)
+    // Added folders display
+    let folders_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(5)
         .halign(gtk::Align::Center)
         .build();
-    let delete_button = gtk::Button::builder()
-        .icon_name("delete")
-        .css_classes(vec!["destructive-action".to_string()])
-        .visible(false)
-        .build();
-    delete_button.connect_clicked(clone!(@strong sender => move |_| {
-        sender.input(AppMsg::GraphMsg(GraphMsg::SetInputDir(None)))
-    }));
-    let folder_label = gtk::Label::builder()
-        .label("Select folder to add hosts")
-        .max_width_chars(20)
-        .wrap(true)
-        .build();
-    selected_folder_box.append(&delete_button);
-    selected_folder_box.append(&folder_label);
 
     // Label to show parses hosts
     let separator = gtk::Separator::builder()
         .orientation(gtk::Orientation::Horizontal)
         .visible(false)
         .build();
-    let hosts_text = gtk::Label::builder().use_markup(true).build();
+    let hosts_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(5)
+        .build();
 
     files_box.append(&buttons_box);
-    files_box.append(&selected_folder_box);
+    files_box.append(&folders_box);
     files_box.append(&separator);
-    files_box.append(&hosts_text);
+    files_box.append(&hosts_box);
 
     // Add to the sidebar view stack
     sidebar_stack.add(&scrollable_file_box);
@@ -167,11 +206,203 @@ pub(crate) fn init_sidebar_files_widgets(
         .page(&scrollable_file_box)
         .set_title(Some("Files"));
 
-    FilesPageWidgets {
-        hosts_text,
+    let widgets = FilesPageWidgets {
+        page: scrollable_file_box,
+        open_button: add_files_button,
+        hosts_box,
         separator,
-        folder_label,
-        delete_button,
+        folders_box,
+        recent_folders_popover,
+        recent_folders_box,
         cheatsheet_window,
+    };
+    fill_input_folders_box(&widgets, &[], &BTreeMap::new(), &sender);
+    widgets
+}
+
+/// Names of the hosts of all the input folders, the ones found in several folders being listed once
+pub(crate) fn host_names(input_folders: &[InputFolder]) -> Vec<&str> {
+    let mut host_names: Vec<&str> = Vec::new();
+    for scanned_host in input_folders
+        .iter()
+        .flat_map(|folder| &folder.scanned_hosts)
+    {
+        if !host_names.contains(&scanned_host.name()) {
+            host_names.push(scanned_host.name());
+        }
     }
+    host_names
+}
+
+/// Show a row per input folder with its number of hosts, and the hosts of all the folders with
+/// their diagnostics
+pub(crate) fn fill_input_folders_box(
+    widgets: &FilesPageWidgets,
+    input_folders: &[InputFolder],
+    diagnostics: &BTreeMap<String, Vec<ParseDiagnostic>>,
+    sender: &ComponentSender<AppModel>,
+) {
+    let folders_box = &widgets.folders_box;
+    while let Some(child) = folders_box.first_child() {
+        folders_box.remove(&child);
+    }
+    if input_folders.is_empty() {
+        folders_box.append(&gtk::Label::new(Some("Select folders to add hosts")));
+    }
+    for (index, folder) in input_folders.iter().enumerate() {
+        let row = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(10)
+            .build();
+        let delete_button = gtk::Button::builder()
+            .icon_name("delete")
+            .css_classes(vec!["destructive-action".to_string()])
+            .tooltip_text("Remove folder")
+            .build();
+        delete_button.connect_clicked(clone!(@strong sender => move |_| {
+            sender.input(AppMsg::GraphMsg(GraphMsg::RemoveInputDir(index)))
+        }));
+        let folder_name = folder
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| folder.path.to_string_lossy().to_string());
+        let hosts_count = folder.scanned_hosts.len();
+        let folder_label = gtk::Label::builder()
+            .label(format!(
+                "<b>{}</b> ({hosts_count} {})",
+                gtk::glib::markup_escape_text(&folder_name),
+                match hosts_count {
+                    1 => "host",
+                    _ => "hosts",
+                }
+            ))
+            .use_markup(true)
+            .tooltip_text(folder.path.to_string_lossy().as_ref())
+            .max_width_chars(20)
+            .wrap(true)
+            .build();
+        row.append(&delete_button);
+        row.append(&folder_label);
+        folders_box.append(&row);
+    }
+
+    // Hosts of all the folders
+    let hosts_box = &widgets.hosts_box;
+    while let Some(child) = hosts_box.first_child() {
+        hosts_box.remove(&child);
+    }
+    let host_names = host_names(input_folders);
+    widgets.separator.set_visible(!host_names.is_empty());
+    if host_names.is_empty() {
+        return;
+    }
+    hosts_box.append(
+        &gtk::Label::builder()
+            .label("<b>Parsed hosts:</b>")
+            .use_markup(true)
+            .build(),
+    );
+    for host_name in host_names {
+        let row = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(5)
+            .build();
+        row.append(
+            &gtk::Label::builder()
+                .label(format!("- {host_name}"))
+                .xalign(0.0)
+                .build(),
+        );
+        if let Some(diagnostics) = diagnostics.get(host_name).filter(|d| !d.is_empty()) {
+            row.append(&diagnostics_button(diagnostics));
+        }
+        hosts_box.append(&row);
+    }
+}
+
+/// Warning badge of a host, with a popover listing the problems met while parsing its files
+fn diagnostics_button(diagnostics: &[ParseDiagnostic]) -> gtk::MenuButton {
+    let diagnostics_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(5)
+        .build();
+    for diagnostic in diagnostics {
+        let mut text = match diagnostic.line() {
+            Some(line) => format!("Line {line}: {}", diagnostic.message()),
+            None => diagnostic.message().to_string(),
+        };
+        if diagnostic.occurrences() > 1 {
+            text.push_str(&format!(" ({} lines)", diagnostic.occurrences()));
+        }
+        let style = match diagnostic.severity() {
+            DiagnosticSeverity::Warning => "warning",
+            DiagnosticSeverity::Error => "error",
+        };
+        diagnostics_box.append(
+            &gtk::Label::builder()
+                .label(text)
+                .css_classes(vec![style.to_string()])
+                .xalign(0.0)
+                .max_width_chars(50)
+                .wrap(true)
+                .build(),
+        );
+    }
+    gtk::MenuButton::builder()
+        .icon_name("dialog-warning-symbolic")
+        .tooltip_text(match diagnostics.len() {
+            1 => "1 parsing problem".to_string(),
+            count => format!("{count} parsing problems"),
+        })
+        .css_classes(vec!["flat".to_string(), "warning".to_string()])
+        .popover(&gtk::Popover::builder().child(&diagnostics_box).build())
+        .build()
+}
+
+/// Fill the recent folders menu with a button per folder, the missing ones being disabled
+pub(crate) fn fill_recent_folders_box(
+    widgets: &FilesPageWidgets,
+    recent_folders: &RecentFolders,
+    sender: &ComponentSender<AppModel>,
+) {
+    let recent_folders_box = &widgets.recent_folders_box;
+    let popover = &widgets.recent_folders_popover;
+    while let Some(child) = recent_folders_box.first_child() {
+        recent_folders_box.remove(&child);
+    }
+    if recent_folders.is_empty() {
+        recent_folders_box.append(&gtk::Label::new(Some("No recent folders")));
+        return;
+    }
+    for folder in recent_folders.iter() {
+        let button = gtk::Button::builder()
+            .label(
+                folder
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| folder.to_string_lossy().to_string()),
+            )
+            .tooltip_text(folder.to_string_lossy().as_ref())
+            .has_frame(false)
+            .sensitive(folder.is_dir())
+            .build();
+        button.connect_clicked(
+            clone!(@strong sender, @strong folder, @strong popover => move |_| {
+                popover.popdown();
+                sender.input(AppMsg::GraphMsg(GraphMsg::AddInputDir(folder.clone())));
+            }),
+        );
+        recent_folders_box.append(&button);
+    }
+    let clear_button = gtk::Button::builder()
+        .label("Clear recent")
+        .has_frame(false)
+        .build();
+    clear_button.connect_clicked(clone!(@strong sender, @strong popover => move |_| {
+        popover.popdown();
+        sender.input(AppMsg::GraphMsg(GraphMsg::ClearRecentFolders));
+    }));
+    recent_folders_box.append(&gtk::Separator::new(gtk::Orientation::Horizontal));
+    recent_folders_box.append(&clear_button);
 }
@@ -0,0 +1,82 @@
+//! Most recently used input folders
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of folders kept in the list
+const MAX_RECENT_FOLDERS: usize = 10;
+
+/// Recently opened input folders, most recent first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct RecentFolders(Vec<PathBuf>);
+
+impl RecentFolders {
+    /// Move `path` to the top of the list, dropping the oldest folder if the list is full
+    pub fn add(&mut self, path: &Path) {
+        self.0.retain(|folder| folder != path);
+        self.0.insert(0, path.to_path_buf());
+        self.0.truncate(MAX_RECENT_FOLDERS);
+    }
+
+    /// Remove the folders which do not exist anymore
+    pub fn prune(&mut self) {
+        self.0.retain(|folder| folder.is_dir());
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PathBuf> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The most recent folder must come first, without duplicates
+    #[test]
+    fn test_add_recent_folder() {
+        let mut recent_folders = RecentFolders::default();
+        for i in 0..=MAX_RECENT_FOLDERS {
+            recent_folders.add(Path::new(&format!("/captures/{i}")));
+        }
+        recent_folders.add(Path::new("/captures/3"));
+        let folders: Vec<&PathBuf> = recent_folders.iter().collect();
+        assert_eq!(folders.len(), MAX_RECENT_FOLDERS);
+        assert_eq!(folders[0], Path::new("/captures/3"));
+        assert_eq!(
+            folders[1],
+            Path::new(&format!("/captures/{MAX_RECENT_FOLDERS}"))
+        );
+        assert!(!folders.contains(&&PathBuf::from("/captures/0")));
+
+        recent_folders.clear();
+        assert!(recent_folders.is_empty());
+    }
+
+    /// Missing folders must be pruned, keeping the order of the others
+    #[test]
+    fn test_prune_recent_folders() {
+        let directory = tempfile::tempdir().unwrap();
+        let first = directory.path().join("first");
+        let second = directory.path().join("second");
+        std::fs::create_dir(&first).unwrap();
+        std::fs::create_dir(&second).unwrap();
+
+        let mut recent_folders = RecentFolders::default();
+        recent_folders.add(&first);
+        recent_folders.add(&directory.path().join("missing"));
+        recent_folders.add(&second);
+        recent_folders.prune();
+        assert_eq!(recent_folders.iter().collect::<Vec<_>>(), [&second, &first]);
+    }
+}
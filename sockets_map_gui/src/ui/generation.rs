@@ -0,0 +1,693 @@
+//! Graph generation, from the scanned hosts and the agents updates, reporting its phases
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local};
+use regex::RegexBuilder;
+use sockets_map::{
+    connections_model::ConnectionRecord,
+    graphs::{nodes_count, validate_options, HighlightRules, LoopbackRendering, RenderOptions},
+    host::{apply_display_overrides, merge_host_sources, Host},
+    parsers::{
+        diagnostics::ParseDiagnostic,
+        directory_scanner::{self, BuiltHosts, ScannedHost},
+        hosts_cache,
+    },
+    server::{
+        client::{Client, UpdateSelection},
+        message::Message,
+    },
+};
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tsyncp::broadcast::BincodeSender;
+
+use super::session::GraphSettings;
+
+/// Phases of the graph generation, shown next to the spinner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    WaitingForClients,
+    BuildingHosts,
+    BuildingConnections,
+    Rendering,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Phase::WaitingForClients => "Waiting for client updates",
+            Phase::BuildingHosts => "Building hosts",
+            Phase::BuildingConnections => "Building connections",
+            Phase::Rendering => "Rendering with Graphviz",
+        })
+    }
+}
+
+/// Error of a generation which was cancelled
+#[derive(Debug)]
+pub(crate) struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("graph generation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Result of a graph generation
+#[derive(Debug)]
+pub(crate) struct GeneratedGraph {
+    /// DOT code of the graph, to render it again in another format
+    pub dot_code: String,
+    pub connection_records: Vec<ConnectionRecord>,
+    /// Problems which did not prevent the generation, to be shown to the user
+    pub warnings: Vec<String>,
+    /// Number of highlighted hosts and processes, if a highlight query was set
+    pub highlight_matches: Option<usize>,
+    /// Hosts the connections were built from, to export the graph anonymized
+    pub hosts: Vec<Host>,
+    /// Problems met while parsing the input folders, by host name
+    pub diagnostics: BTreeMap<String, Vec<ParseDiagnostic>>,
+}
+
+/// Outcome of the last graph generation, shown in the window subtitle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum GenerationStatus {
+    Generated {
+        hosts_count: usize,
+        connections_count: usize,
+        time: DateTime<Local>,
+    },
+    Failed,
+}
+
+impl std::fmt::Display for GenerationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerationStatus::Generated {
+                hosts_count,
+                connections_count,
+                time,
+            } => write!(
+                f,
+                "{hosts_count} {} · {connections_count} {} · generated {}",
+                match hosts_count {
+                    1 => "host",
+                    _ => "hosts",
+                },
+                match connections_count {
+                    1 => "connection",
+                    _ => "connections",
+                },
+                time.format("%H:%M:%S")
+            ),
+            GenerationStatus::Failed => f.write_str("generation failed"),
+        }
+    }
+}
+
+/// Translate the search entry query into highlight rules, ignoring the case. Each word of the
+/// query is a glob pattern (with `*` and `?`) matching the whole name, or a part of the name if
+/// it has no wildcard.
+pub(crate) fn highlight_rules(query: &str) -> anyhow::Result<HighlightRules> {
+    let patterns = query
+        .split_whitespace()
+        .map(|term| {
+            let mut pattern: String = term
+                .chars()
+                .map(|c| match c {
+                    '*' => ".*".to_string(),
+                    '?' => ".".to_string(),
+                    c => regex::escape(&c.to_string()),
+                })
+                .collect();
+            if term.contains(['*', '?']) {
+                pattern = format!("^{pattern}$");
+            }
+            RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("invalid search term {term:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(HighlightRules::new(patterns))
+}
+
+/// Build the hosts of all the input folders, or load them from `cache_dir` if their files did not
+/// change. A host found in several folders is merged into one, along with its diagnostics.
+pub(crate) fn build_input_hosts(
+    input_folders: &[Vec<ScannedHost>],
+    cache_dir: Option<&Path>,
+) -> anyhow::Result<BuiltHosts> {
+    let scanned_hosts = input_folders.concat();
+    let build = || directory_scanner::build_hosts_with_diagnostics(&scanned_hosts);
+    let mut built_hosts = match cache_dir {
+        Some(cache_dir) => {
+            let files: Vec<PathBuf> = scanned_hosts
+                .iter()
+                .flat_map(ScannedHost::files)
+                .map(|file| file.path().to_path_buf())
+                .collect();
+            let mut directories: Vec<PathBuf> = files
+                .iter()
+                .filter_map(|file| file.parent())
+                .map(Path::to_path_buf)
+                .collect();
+            directories.sort();
+            directories.dedup();
+            let names: Vec<&str> = scanned_hosts.iter().map(ScannedHost::name).collect();
+            hosts_cache::load_or_build(
+                &hosts_cache::cache_path(cache_dir, &directories),
+                &hosts_cache::files_key(&files, &names.join(",")),
+                build,
+            )?
+        }
+        None => build()?,
+    };
+    let mut hosts = Vec::<Host>::new();
+    for host in std::mem::take(&mut built_hosts.hosts) {
+        match hosts.iter_mut().find(|h| h.name() == host.name()) {
+            Some(merged_host) => merged_host.merge(&host),
+            None => hosts.push(host),
+        }
+    }
+    built_hosts.hosts = hosts;
+    Ok(built_hosts)
+}
+
+/// Leave out the hosts named `names`, by their host name or the name drawn in the graph
+fn exclude_hosts(hosts: &mut Vec<Host>, names: &[String]) {
+    hosts.retain(|host| {
+        !names
+            .iter()
+            .any(|name| name == host.name() || name == host.display_name())
+    });
+}
+
+/// Time to wait for the clients updates before generating the graph, unless set in the server options
+pub(crate) const DEFAULT_UPDATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Names of the clients which have not sent any update yet, sorted
+fn clients_missing_updates(clients: &HashMap<String, Client>) -> Vec<String> {
+    let mut missing: Vec<String> = clients
+        .values()
+        .filter(|client| client.updates().is_empty())
+        .map(|client| {
+            client
+                .pretty_name
+                .clone()
+                .unwrap_or_else(|| client.hostname.clone())
+        })
+        .collect();
+    missing.sort();
+    missing
+}
+
+/// Wait for every client to send an update, at most `timeout`, and return the names of the clients
+/// still missing one
+async fn wait_for_updates(
+    clients: &RwLock<HashMap<String, Client>>,
+    timeout: Duration,
+    cancel_token: &CancellationToken,
+) -> anyhow::Result<Vec<String>> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = cancel_token.cancelled() => return Err(Cancelled.into()),
+        }
+        let missing = clients_missing_updates(&*clients.read().await);
+        if missing.is_empty() || tokio::time::Instant::now() >= deadline {
+            return Ok(missing);
+        }
+    }
+}
+
+/// Report the next phase, unless the generation is cancelled
+fn enter_phase(
+    phase: Phase,
+    cancel_token: &CancellationToken,
+    progress: &impl Fn(Phase),
+) -> anyhow::Result<()> {
+    if cancel_token.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    progress(phase);
+    Ok(())
+}
+
+/// Generate the graph to `output_file`, in the format of the file extension of `graph_settings`,
+/// and return its DOT code and connections. When the server runs without any update yet, the
+/// clients are asked for one, waiting for them at most `update_timeout`. The cancellation is
+/// checked between the phases and while Graphviz runs, in which case a [`Cancelled`] error is
+/// returned. The hosts of the input folders are cached in `hosts_cache_dir`, if any, and the
+/// updates of each client are picked by `update_selection`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn generate_graph(
+    input_folders: Vec<Vec<ScannedHost>>,
+    hosts_cache_dir: Option<&Path>,
+    clients: Arc<RwLock<HashMap<String, Client>>>,
+    tx_opt: Arc<RwLock<Option<BincodeSender<Message>>>>,
+    update_timeout: Duration,
+    update_selection: UpdateSelection,
+    graph_settings: &GraphSettings,
+    output_file: &Path,
+    cancel_token: &CancellationToken,
+    progress: impl Fn(Phase),
+) -> anyhow::Result<GeneratedGraph> {
+    let mut warnings = Vec::new();
+
+    // If the server is running and does not have got any update yet, send a request to clients
+    if let Some(tx) = tx_opt.write().await.as_mut() {
+        if !clients
+            .read()
+            .await
+            .iter()
+            .any(|(_name, client)| !client.updates().is_empty())
+        {
+            enter_phase(Phase::WaitingForClients, cancel_token, &progress)?;
+            log::info!("sending update request to clients");
+            let (_res, _accept_res) = tx.send(Message::UpdateRequest).accepting().await;
+            log::debug!("peers when sending: {:?}", tx.peer_addrs());
+
+            // The graph is generated from the clients which did answer
+            let missing = wait_for_updates(&clients, update_timeout, cancel_token).await?;
+            if !missing.is_empty() {
+                warnings.push(format!(
+                    "No update from {} after {}s, the graph is generated without {}: {}",
+                    match missing.len() {
+                        1 => "1 client".to_string(),
+                        count => format!("{count} clients"),
+                    },
+                    update_timeout.as_secs_f64(),
+                    match missing.len() {
+                        1 => "it",
+                        _ => "them",
+                    },
+                    missing.join(", ")
+                ));
+            }
+        }
+    }
+
+    enter_phase(Phase::BuildingHosts, cancel_token, &progress)?;
+    let clients = clients.read().await;
+
+    // Scanned hosts
+    let (hosts, diagnostics) = match build_input_hosts(&input_folders, hosts_cache_dir) {
+        Ok(BuiltHosts {
+            hosts,
+            failures,
+            diagnostics,
+            ..
+        }) => {
+            warnings.extend(failures.iter().map(|failure| {
+                format!(
+                    "Unable to parse host {}: {}",
+                    failure.name(),
+                    failure.reason()
+                )
+            }));
+            (hosts, diagnostics)
+        }
+        Err(e) => {
+            warnings.push(format!("Unable to parse the input folders: {e}"));
+            (Vec::new(), BTreeMap::new())
+        }
+    };
+
+    // Client hosts
+    let client_hosts: Vec<Host> = clients
+        .iter()
+        .filter_map(|(_name, client)| client.selected_host(update_selection))
+        .collect();
+    let mut hosts = merge_host_sources(hosts, client_hosts, graph_settings.source_policy);
+    apply_display_overrides(&mut hosts, &graph_settings.display_overrides);
+    exclude_hosts(&mut hosts, &graph_settings.excluded_hosts);
+    if hosts.is_empty() {
+        bail!("No hosts to generate graph from");
+    }
+
+    // Exclude some processes from the analysis
+    let mut excluded_processes: Vec<&str> = graph_settings
+        .excluded_processes
+        .iter()
+        .map(String::as_str)
+        .collect();
+    if graph_settings.hide_agents {
+        excluded_processes.push("sockets_map");
+    }
+    if !excluded_processes.is_empty() {
+        for host in hosts.iter_mut() {
+            host.exclude_processes(&excluded_processes);
+        }
+    }
+
+    // Generate connections
+    enter_phase(Phase::BuildingConnections, cancel_token, &progress)?;
+    let connections = sockets_map::connections_model::build_connections_list(
+        &hosts,
+        graph_settings.loopback_rendering == LoopbackRendering::Hide,
+    );
+
+    // Check the rendering options before a long render
+    let mut render_options = RenderOptions {
+        format: graph_settings.file_extension.clone(),
+        dpi: graph_settings.dpi,
+        layout_engine: Some(graph_settings.layout_engine.clone()),
+        transparent_background: graph_settings.transparent_background,
+        nodes: nodes_count(&connections, graph_settings.granularity),
+    };
+    let option_warnings = validate_options(&render_options);
+    warnings.extend(option_warnings.iter().map(ToString::to_string));
+    render_options.correct(&option_warnings);
+
+    // Generate the Dot graph
+    let highlight = graph_settings
+        .highlight_query
+        .as_deref()
+        .map(highlight_rules)
+        .transpose()?;
+    let dot_code = sockets_map::graphs::create_graph(
+        &connections,
+        &sockets_map::graphs::DrawOptions {
+            graph: sockets_map::graphs::MapGraphOptions {
+                granularity: graph_settings.granularity,
+                loopback_rendering: graph_settings.loopback_rendering,
+                listener_rendering: graph_settings.listener_rendering,
+                highlight: highlight.clone(),
+                ..Default::default()
+            },
+            transparent_background: render_options.transparent_background,
+            hide_legend: graph_settings.hide_legend,
+            dpi: graph_settings.dpi,
+            layout_engine: Some(graph_settings.layout_engine.clone()),
+            ..Default::default()
+        },
+    )?;
+
+    // Run Graphviz command to generate the graph
+    enter_phase(Phase::Rendering, cancel_token, &progress)?;
+    let res = sockets_map::graphviz::run_graphviz_cancellable(
+        dot_code.clone(),
+        output_file,
+        graph_settings.file_extension.clone(),
+        None,
+        graph_settings.vertical_graph,
+        Some(&graph_settings.layout_engine),
+        cancel_token,
+    )
+    .await;
+    if cancel_token.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    res?;
+
+    Ok(GeneratedGraph {
+        dot_code,
+        connection_records: connections.iter().map(ConnectionRecord::from).collect(),
+        warnings,
+        highlight_matches: highlight.map(|rules| rules.count_matches(&connections)),
+        hosts,
+        diagnostics,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockets_map::{
+        host::DisplayOverride, parsers::directory_scanner::scan_dir, server::client::Update,
+        test_utils::HostBuilder,
+    };
+    use std::sync::Mutex;
+
+    /// Write the capture files of a host `name` running a server on `port`
+    fn write_host(directory: &Path, name: &str, port: u16) {
+        HostBuilder::new(name)
+            .ip("10.0.0.1")
+            .listen_tcp(port, "sshd", 100)
+            .build()
+            .write_csv_files(directory)
+            .unwrap();
+    }
+
+    /// Generate the graph from the hosts of `directory`, and return the reported phases
+    async fn generate(
+        directory: &Path,
+        cancel_token: &CancellationToken,
+        cancel_at: Option<Phase>,
+    ) -> (anyhow::Result<GeneratedGraph>, Vec<Phase>) {
+        let phases = Mutex::new(Vec::new());
+        let res = generate_graph(
+            vec![scan_dir(directory).unwrap()],
+            None,
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(None)),
+            Duration::ZERO,
+            UpdateSelection::Latest,
+            &GraphSettings::default(),
+            &directory.join("graph.png"),
+            cancel_token,
+            |phase| {
+                phases.lock().unwrap().push(phase);
+                if Some(phase) == cancel_at {
+                    cancel_token.cancel();
+                }
+            },
+        )
+        .await;
+        (res, phases.into_inner().unwrap())
+    }
+
+    /// A cancelled generation must stop at the next phase, and never run Graphviz
+    #[tokio::test]
+    async fn test_generate_graph_cancelled() {
+        let directory = tempfile::tempdir().unwrap();
+        write_host(directory.path(), "server", 22);
+
+        let (res, phases) = generate(
+            directory.path(),
+            &CancellationToken::new(),
+            Some(Phase::BuildingConnections),
+        )
+        .await;
+        assert!(res.unwrap_err().is::<Cancelled>());
+        assert_eq!(phases, [Phase::BuildingHosts, Phase::BuildingConnections]);
+        assert!(!directory.path().join("graph.png").exists());
+
+        let cancel_token = CancellationToken::new();
+        cancel_token.cancel();
+        let (res, phases) = generate(directory.path(), &cancel_token, None).await;
+        assert!(res.unwrap_err().is::<Cancelled>());
+        assert!(phases.is_empty());
+    }
+
+    /// Without hosts, the generation fails with an error which is not a cancellation
+    #[tokio::test]
+    async fn test_generate_graph_without_hosts() {
+        let directory = tempfile::tempdir().unwrap();
+        let (res, phases) = generate(directory.path(), &CancellationToken::new(), None).await;
+        assert!(!res.unwrap_err().is::<Cancelled>());
+        assert_eq!(phases, [Phase::BuildingHosts]);
+    }
+
+    /// The clients answering before the timeout must not be reported, the others being named
+    /// after their pretty name if they have one
+    #[tokio::test]
+    async fn test_wait_for_updates() {
+        let mut web = Client::new("web".into(), None, vec![]);
+        web.add_update(Update::new(Host::new("web")));
+        let clients = Arc::new(RwLock::new(HashMap::from([
+            ("10.0.0.1:50000".to_string(), web),
+            (
+                "10.0.0.2:50000".to_string(),
+                Client::new("db".into(), None, vec![]),
+            ),
+            (
+                "10.0.0.3:50000".to_string(),
+                Client::new("srv-3".into(), Some("backup".into()), vec![]),
+            ),
+        ])));
+        assert_eq!(
+            clients_missing_updates(&*clients.read().await),
+            ["backup", "db"]
+        );
+
+        // db answers while waiting, backup never does
+        let answering_clients = clients.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            let mut clients = answering_clients.write().await;
+            let db = clients.get_mut("10.0.0.2:50000").unwrap();
+            db.add_update(Update::new(Host::new("db")));
+        });
+        let missing = wait_for_updates(
+            &clients,
+            Duration::from_millis(500),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(missing, ["backup"]);
+
+        // Once all the clients answered, there is nothing to wait for
+        clients
+            .write()
+            .await
+            .get_mut("10.0.0.3:50000")
+            .unwrap()
+            .add_update(Update::new(Host::new("backup")));
+        let missing =
+            wait_for_updates(&clients, Duration::from_secs(60), &CancellationToken::new())
+                .await
+                .unwrap();
+        assert!(missing.is_empty());
+    }
+
+    /// The hosts of all the folders must be built, the ones found in several folders being merged
+    #[test]
+    fn test_build_input_hosts() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        write_host(first.path(), "server", 22);
+        write_host(first.path(), "gateway", 22);
+        write_host(second.path(), "server", 2222);
+
+        let BuiltHosts {
+            hosts,
+            failures,
+            diagnostics,
+            ..
+        } = build_input_hosts(
+            &[
+                scan_dir(first.path()).unwrap(),
+                scan_dir(second.path()).unwrap(),
+            ],
+            None,
+        )
+        .unwrap();
+        assert!(failures.is_empty() && diagnostics.is_empty());
+        let mut names: Vec<&str> = hosts.iter().map(Host::name).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["gateway", "server"]);
+        let server = hosts.iter().find(|h| h.name() == "server").unwrap();
+        assert_eq!(server.listening_sockets().len(), 2);
+
+        let built_hosts = build_input_hosts(&[], None).unwrap();
+        assert!(built_hosts.hosts.is_empty() && built_hosts.failures.is_empty());
+    }
+
+    /// The hosts must be excluded by their host name as well as by their drawn name
+    #[test]
+    fn test_exclude_hosts() {
+        let mut hosts: Vec<Host> = ["web01", "db01", "ip-10-0-3-12"]
+            .into_iter()
+            .map(|name| HostBuilder::new(name).build())
+            .collect();
+        apply_display_overrides(
+            &mut hosts,
+            &BTreeMap::from([(
+                "ip-10-0-3-12".to_string(),
+                DisplayOverride {
+                    name: Some("backup".into()),
+                    color: None,
+                },
+            )]),
+        );
+
+        exclude_hosts(&mut hosts, &["db01".into(), "backup".into()]);
+        let names: Vec<&str> = hosts.iter().map(Host::name).collect();
+        assert_eq!(names, ["web01"]);
+    }
+
+    /// The cached hosts must be loaded until a file of the input folders changes
+    #[test]
+    fn test_build_input_hosts_cached() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        write_host(directory.path(), "server", 22);
+        let build = || {
+            build_input_hosts(
+                &[scan_dir(directory.path()).unwrap()],
+                Some(cache_dir.path()),
+            )
+            .unwrap()
+            .hosts
+        };
+
+        let hosts = build();
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+        assert_eq!(build(), hosts);
+        write_host(directory.path(), "server", 2222);
+        assert_eq!(build()[0].listening_sockets()[0].port(), 2222);
+    }
+
+    /// The subtitle must give the counts and the time of the generation
+    #[test]
+    fn test_generation_status_display() {
+        use chrono::TimeZone;
+
+        let time = Local.with_ymd_and_hms(2023, 5, 1, 14, 32, 5).unwrap();
+        let status = GenerationStatus::Generated {
+            hosts_count: 12,
+            connections_count: 87,
+            time,
+        };
+        assert_eq!(
+            status.to_string(),
+            "12 hosts · 87 connections · generated 14:32:05"
+        );
+        let status = GenerationStatus::Generated {
+            hosts_count: 1,
+            connections_count: 1,
+            time,
+        };
+        assert_eq!(
+            status.to_string(),
+            "1 host · 1 connection · generated 14:32:05"
+        );
+        assert_eq!(GenerationStatus::Failed.to_string(), "generation failed");
+    }
+
+    /// A word without wildcard matches a part of the name, a glob pattern the whole name
+    #[test]
+    fn test_highlight_rules_glob() {
+        let rules = highlight_rules("ssh").unwrap();
+        assert!(rules.matches("sshd"));
+        assert!(rules.matches("openssh"));
+
+        let rules = highlight_rules("ssh*").unwrap();
+        assert!(rules.matches("sshd"));
+        assert!(!rules.matches("openssh"));
+
+        let rules = highlight_rules("web-?? db.*").unwrap();
+        assert!(rules.matches("web-01"));
+        assert!(!rules.matches("web-001"));
+        assert!(rules.matches("db.local"));
+        assert!(!rules.matches("db-local"));
+
+        assert!(highlight_rules("  ").unwrap().is_empty());
+    }
+
+    /// The query must match the names regardless of their case
+    #[test]
+    fn test_highlight_rules_case_insensitive() {
+        let rules = highlight_rules("NGINX").unwrap();
+        assert!(rules.matches("nginx"));
+        assert!(rules.matches("Nginx: worker"));
+
+        let rules = highlight_rules("*Server").unwrap();
+        assert!(rules.matches("WEBSERVER"));
+        assert!(!rules.matches("servers"));
+    }
+}
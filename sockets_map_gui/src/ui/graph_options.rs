@@ -1,52 +1,176 @@
 //! Graph options page widgets
 
-use std::str::FromStr;
+use std::{
+    cell::Cell,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use super::AppModel;
-use super::{app_msgs::GraphMsg, AppMsg};
+use super::{
+    app_msgs::GraphMsg,
+    auto_refresh::DEFAULT_AUTO_REFRESH_INTERVAL,
+    connections_table::{self, ConnectionsTableWidgets},
+    session::GraphSettings,
+    shortcuts::{self, ShortcutAction},
+    AppMsg,
+};
 
 use gtk::{
+    gdk::{self, prelude::PaintableExt},
+    gdk_pixbuf,
     glib::clone,
-    traits::{BoxExt, ButtonExt, CheckButtonExt, EditableExt, WidgetExt},
+    traits::{
+        AdjustmentExt, BoxExt, ButtonExt, CheckButtonExt, EditableExt, EventControllerExt,
+        GestureDragExt, TextBufferExt, TextViewExt, WidgetExt,
+    },
 };
 use relm4::{adw, ComponentSender, RelmWidgetExt};
-use sockets_map::graphviz::LayoutEngine;
+use sockets_map::{
+    graphs::{GraphGranularity, ListenerRendering, LoopbackRendering},
+    graphviz::LayoutEngine,
+    host::SourcePolicy,
+};
 
-const SUPPORTED_FORMATS: [&str; 4] = ["png", "jpeg", "svg", "bmp"];
+pub(crate) const SUPPORTED_FORMATS: [&str; 4] = ["png", "jpeg", "svg", "bmp"];
 pub const DEFAULT_DPI: f64 = 96.0;
+/// Layout engines, in the order of the dropdown
+const LAYOUT_ENGINES: [LayoutEngine; 4] = [
+    LayoutEngine::Dot,
+    LayoutEngine::Neato,
+    LayoutEngine::Fdp,
+    LayoutEngine::Circo,
+];
+/// Loopback renderings, in the order of the dropdown
+const LOOPBACK_RENDERINGS: [LoopbackRendering; 3] = [
+    LoopbackRendering::Show,
+    LoopbackRendering::Hide,
+    LoopbackRendering::Collapse,
+];
+/// Graph granularities, in the order of the dropdown
+const GRANULARITIES: [GraphGranularity; 2] = [GraphGranularity::Process, GraphGranularity::Host];
+/// Policies for the hosts found in the files and in the agents updates, in the order of the dropdown
+const SOURCE_POLICIES: [SourcePolicy; 3] = [
+    SourcePolicy::Merge,
+    SourcePolicy::PreferAgent,
+    SourcePolicy::PreferFiles,
+];
+/// Bounds of the graph preview zoom factor
+const MIN_ZOOM: f64 = 0.1;
+const MAX_ZOOM: f64 = 8.0;
+/// Zoom factor applied by each step of the mouse wheel
+const ZOOM_STEP: f64 = 1.1;
 
 #[derive(Debug)]
 pub(crate) struct GraphPageWidgets {
+    pub generate_button: gtk::Button,
     pub generate_button_spinner: gtk::Spinner,
+    /// Phase of the graph generation, shown while generating
+    pub generate_progress_label: gtk::Label,
+    pub cancel_generation_button: gtk::Button,
     pub image_view_stack: gtk::Stack,
+    /// Shown until a graph is generated, with the first steps
+    pub home_page: adw::StatusPage,
+    /// Scrollable view of the graph image, shown once a graph is generated
+    pub graph_scrolled_window: gtk::ScrolledWindow,
     pub graph_image: gtk::Picture,
+    /// Size of the graph image, regardless of the zoom
+    graph_image_size: Rc<Cell<Option<(i32, i32)>>>,
+    /// Set if the graph image is an SVG, rasterized again at each zoom change
+    svg_image_path: Option<PathBuf>,
+    /// DOT code of the graph, shown instead of the image when toggled
+    pub dot_box: gtk::Box,
+    pub dot_view: gtk::TextView,
+    /// Connections of the graph, below the preview
+    pub connections_table: ConnectionsTableWidgets,
+    /// Splits the preview from the connections table below it
+    pub graph_paned: gtk::Paned,
+    pub options: GraphOptionsWidgets,
+}
+
+/// Widgets of the sidebar which set the graph options
+#[derive(Debug)]
+pub(crate) struct GraphOptionsWidgets {
+    output_format_dropdown: gtk::DropDown,
+    output_dpi: gtk::Entry,
+    layout_engine_dropdown: gtk::DropDown,
+    loopback_dropdown: gtk::DropDown,
+    granularity_dropdown: gtk::DropDown,
+    source_policy_dropdown: gtk::DropDown,
+    vertical_graph_checkbox: gtk::CheckButton,
+    transparent_background_checkbox: gtk::CheckButton,
+    hide_legend_checkbox: gtk::CheckButton,
+    summarize_listeners_checkbox: gtk::CheckButton,
+    hide_agents_checkbox: gtk::CheckButton,
+    excluded_processes_entry: gtk::Entry,
+    excluded_hosts_entry: gtk::Entry,
 }
 
 #[tracker::track]
 #[derive(Debug, Clone)]
 pub struct GraphOptions {
-    pub hide_loopback_connections: bool,
+    pub loopback_rendering: LoopbackRendering,
     pub vertical_graph: bool,
     pub transparent_background: bool,
     pub hide_legend: bool,
+    pub listener_rendering: ListenerRendering,
+    /// Whether the processes or only the hosts are drawn
+    pub granularity: GraphGranularity,
     pub file_extension: String,
     pub dpi: f64,
     pub hide_agents: bool,
+    /// Prefixes of the names of the processes to leave out of the graph
+    pub excluded_processes: Vec<String>,
+    /// Names of the hosts to leave out of the graph
+    pub excluded_hosts: Vec<String>,
     pub layout_engine: LayoutEngine,
+    /// How the hosts found both in the input folders and in the agents updates are drawn
+    pub source_policy: SourcePolicy,
+    /// Hosts and processes to highlight, as typed in the search entry
+    pub highlight_query: Option<String>,
+}
+
+impl From<&GraphOptions> for GraphSettings {
+    fn from(graph_options: &GraphOptions) -> Self {
+        Self {
+            loopback_rendering: graph_options.loopback_rendering,
+            vertical_graph: graph_options.vertical_graph,
+            transparent_background: graph_options.transparent_background,
+            hide_legend: graph_options.hide_legend,
+            listener_rendering: graph_options.listener_rendering,
+            granularity: graph_options.granularity,
+            file_extension: graph_options.file_extension.clone(),
+            dpi: graph_options.dpi,
+            hide_agents: graph_options.hide_agents,
+            excluded_processes: graph_options.excluded_processes.clone(),
+            excluded_hosts: graph_options.excluded_hosts.clone(),
+            layout_engine: graph_options.layout_engine.clone(),
+            source_policy: graph_options.source_policy,
+            highlight_query: graph_options.highlight_query.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 impl GraphOptions {
     pub fn new() -> Self {
         Self {
-            hide_loopback_connections: false,
+            loopback_rendering: LoopbackRendering::Show,
             vertical_graph: false,
             transparent_background: false,
             hide_legend: false,
+            listener_rendering: ListenerRendering::PerProcess,
+            granularity: GraphGranularity::Process,
             file_extension: "png".into(),
             tracker: 0,
             dpi: DEFAULT_DPI,
             hide_agents: true,
+            excluded_processes: Vec::new(),
+            excluded_hosts: Vec::new(),
             layout_engine: LayoutEngine::Dot,
+            source_policy: SourcePolicy::Merge,
+            highlight_query: None,
         }
     }
 }
@@ -73,14 +197,33 @@ pub(crate) fn init_sidebar_graph_page_widgets(
         .build();
     generate_button_box.append(&gtk::Label::new(Some("Generate graph")));
     generate_button_box.append(&generate_button_spinner);
-    let generate_graph_button = gtk::Button::builder()
+    let generate_button = gtk::Button::builder()
         .css_classes(vec!["suggested-action".to_string()])
+        .tooltip_text(&shortcuts::tooltip(
+            "Generate the graph",
+            ShortcutAction::GenerateGraph,
+        ))
         .child(&generate_button_box)
         .build();
-    generate_graph_button.connect_clicked(clone!(@strong sender => move |_| {
+    generate_button.connect_clicked(clone!(@strong sender => move |_| {
         sender.input(AppMsg::GraphMsg(GraphMsg::Generating(true)))
     }));
-    graph_box.append(&generate_graph_button);
+    graph_box.append(&generate_button);
+
+    // Generation progress and cancel button
+    let generate_progress_label = gtk::Label::builder()
+        .css_classes(vec!["dim-label".to_string()])
+        .visible(false)
+        .build();
+    graph_box.append(&generate_progress_label);
+    let cancel_generation_button = gtk::Button::builder()
+        .label("Cancel")
+        .visible(false)
+        .build();
+    cancel_generation_button.connect_clicked(clone!(@strong sender => move |_| {
+        sender.input(AppMsg::GraphMsg(GraphMsg::CancelGeneration))
+    }));
+    graph_box.append(&cancel_generation_button);
 
     // Graph options
     let graph_options_sep = gtk::Separator::new(gtk::Orientation::Horizontal);
@@ -127,35 +270,79 @@ pub(crate) fn init_sidebar_graph_page_widgets(
             .use_markup(true)
             .build(),
     );
-    let layout_engines: [&str; 4] = [
-        (&LayoutEngine::Dot).into(),
-        (&LayoutEngine::Neato).into(),
-        (&LayoutEngine::Fdp).into(),
-        (&LayoutEngine::Circo).into(),
-    ];
-    let layout_engine_dropbox = gtk::DropDown::from_strings(&layout_engines);
-    layout_engine_dropbox.connect_selected_notify(
-        clone!(@strong sender, @strong layout_engines => move |dropdown| {
-            let index = dropdown.selected();
-            if let Some(layout_engine_str) = layout_engines.get(index as usize) {
-                if let Ok(layout_engine) = LayoutEngine::from_str(layout_engine_str) {
-                    sender.input(AppMsg::GraphMsg(GraphMsg::SetLayoutEngine(layout_engine)))
-                }
-            }
-        }),
-    );
-    layout_engine_box.append(&layout_engine_dropbox);
+    let layout_engines: Vec<&str> = LAYOUT_ENGINES.iter().map(<&str>::from).collect();
+    let layout_engine_dropdown = gtk::DropDown::from_strings(&layout_engines);
+    layout_engine_dropdown.connect_selected_notify(clone!(@strong sender => move |dropdown| {
+        if let Some(layout_engine) = LAYOUT_ENGINES.get(dropdown.selected() as usize) {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetLayoutEngine(layout_engine.clone())))
+        }
+    }));
+    layout_engine_box.append(&layout_engine_dropdown);
     graph_box.append(&layout_engine_box);
 
+    // Loopback connections
+    let loopback_box = gtk::Box::new(gtk::Orientation::Horizontal, 13);
+    loopback_box.append(
+        &gtk::Label::builder()
+            .label("<b>Loopback connections</b>")
+            .use_markup(true)
+            .build(),
+    );
+    let loopback_dropdown = gtk::DropDown::from_strings(&["Show", "Hide", "Collapse"]);
+    loopback_dropdown.set_tooltip_text(Some(
+        "Collapse draws a single node per host, telling the number of its loopback connections",
+    ));
+    loopback_dropdown.connect_selected_notify(clone!(@strong sender => move |dropdown| {
+        if let Some(loopback_rendering) = LOOPBACK_RENDERINGS.get(dropdown.selected() as usize) {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetLoopbackRendering(*loopback_rendering)))
+        }
+    }));
+    loopback_box.append(&loopback_dropdown);
+    graph_box.append(&loopback_box);
+
+    // Processes or hosts only
+    let granularity_box = gtk::Box::new(gtk::Orientation::Horizontal, 13);
+    granularity_box.append(
+        &gtk::Label::builder()
+            .label("<b>Nodes</b>")
+            .use_markup(true)
+            .build(),
+    );
+    let granularity_dropdown = gtk::DropDown::from_strings(&["Processes", "Hosts"]);
+    granularity_dropdown.set_tooltip_text(Some(
+        "Hosts draws a single edge per pair of hosts, telling the number of connections and their destination ports",
+    ));
+    granularity_dropdown.connect_selected_notify(clone!(@strong sender => move |dropdown| {
+        if let Some(granularity) = GRANULARITIES.get(dropdown.selected() as usize) {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetGranularity(*granularity)))
+        }
+    }));
+    granularity_box.append(&granularity_dropdown);
+    graph_box.append(&granularity_box);
+
+    // Hosts found in the files and in the agents updates
+    let source_policy_box = gtk::Box::new(gtk::Orientation::Horizontal, 13);
+    source_policy_box.append(
+        &gtk::Label::builder()
+            .label("<b>Hosts in files and agents</b>")
+            .use_markup(true)
+            .build(),
+    );
+    let source_policy_dropdown =
+        gtk::DropDown::from_strings(&["Merge", "Prefer agents", "Prefer files"]);
+    source_policy_dropdown.set_tooltip_text(Some(
+        "How to draw a host found both in the input folders and in the agents updates, matched by its name or by an IP",
+    ));
+    source_policy_dropdown.connect_selected_notify(clone!(@strong sender => move |dropdown| {
+        if let Some(source_policy) = SOURCE_POLICIES.get(dropdown.selected() as usize) {
+            sender.input(AppMsg::GraphMsg(GraphMsg::SetSourcePolicy(*source_policy)))
+        }
+    }));
+    source_policy_box.append(&source_policy_dropdown);
+    graph_box.append(&source_policy_box);
+
     // Checkboxes
     let graph_options = GraphOptions::new();
-    let hide_loopback_checkbox = gtk::CheckButton::with_label("Hide loopback connections");
-    hide_loopback_checkbox.connect_toggled(
-        clone!(@strong sender, @strong graph_options => move |button| {
-            sender.input(AppMsg::GraphMsg(GraphMsg::SetHideLoopbackConnections(button.is_active())));
-        }),
-    );
-    graph_box.append(&hide_loopback_checkbox);
     let vertical_graph_checkbox = gtk::CheckButton::with_label("Vertical graph");
     vertical_graph_checkbox.connect_toggled(clone!(@strong sender => move |button| {
         sender.input(AppMsg::GraphMsg(GraphMsg::SetVerticalGraph(button.is_active())));
@@ -171,6 +358,18 @@ pub(crate) fn init_sidebar_graph_page_widgets(
         sender.input(AppMsg::GraphMsg(GraphMsg::SetHideLegend(button.is_active())));
     }));
     graph_box.append(&hide_legend_checkbox);
+    let summarize_listeners_checkbox = gtk::CheckButton::with_label("Summarize listening ports");
+    summarize_listeners_checkbox.set_tooltip_text(Some(
+        "Draw a single node per host listing its listening ports, instead of a node per listening process",
+    ));
+    summarize_listeners_checkbox.connect_toggled(clone!(@strong sender => move |button| {
+        let listener_rendering = match button.is_active() {
+            true => ListenerRendering::Summary,
+            false => ListenerRendering::PerProcess,
+        };
+        sender.input(AppMsg::GraphMsg(GraphMsg::SetListenerRendering(listener_rendering)));
+    }));
+    graph_box.append(&summarize_listeners_checkbox);
     let hide_agents_checkbox = gtk::CheckButton::with_label("Hide agents");
     hide_agents_checkbox.set_active(true);
     hide_agents_checkbox.connect_toggled(clone!(@strong sender => move |button| {
@@ -178,6 +377,65 @@ pub(crate) fn init_sidebar_graph_page_widgets(
     }));
     graph_box.append(&hide_agents_checkbox);
 
+    // Excluded processes
+    let excluded_processes_label = gtk::Label::builder()
+        .label("<b>Excluded processes</b>")
+        .use_markup(true)
+        .halign(gtk::Align::Start)
+        .build();
+    graph_box.append(&excluded_processes_label);
+    let excluded_processes_entry = gtk::Entry::builder()
+        .placeholder_text("chrome, firefox, teams")
+        .tooltip_text("Comma-separated prefixes of the process names to hide")
+        .build();
+    excluded_processes_entry.connect_changed(clone!(@strong sender => move |entry| {
+        sender.input(AppMsg::GraphMsg(GraphMsg::SetExcludedProcesses(
+            parse_excluded_names(&entry.text()),
+        )));
+    }));
+    graph_box.append(&excluded_processes_entry);
+
+    // Excluded hosts
+    let excluded_hosts_label = gtk::Label::builder()
+        .label("<b>Excluded hosts</b>")
+        .use_markup(true)
+        .halign(gtk::Align::Start)
+        .build();
+    graph_box.append(&excluded_hosts_label);
+    let excluded_hosts_entry = gtk::Entry::builder()
+        .placeholder_text("backup01, monitoring")
+        .tooltip_text("Comma-separated names of the hosts to hide")
+        .build();
+    excluded_hosts_entry.connect_changed(clone!(@strong sender => move |entry| {
+        sender.input(AppMsg::GraphMsg(GraphMsg::SetExcludedHosts(
+            parse_excluded_names(&entry.text()),
+        )));
+    }));
+    graph_box.append(&excluded_hosts_entry);
+
+    // Auto-refresh while recording
+    let auto_refresh_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    let auto_refresh_checkbox = gtk::CheckButton::builder()
+        .label("Auto-refresh during recording")
+        .tooltip_text("Regenerate the graph periodically while the clients are recording")
+        .build();
+    auto_refresh_checkbox.connect_toggled(clone!(@strong sender => move |button| {
+        sender.input(AppMsg::GraphMsg(GraphMsg::SetAutoRefresh(button.is_active())));
+    }));
+    let auto_refresh_interval_entry = gtk::Entry::builder()
+        .placeholder_text(DEFAULT_AUTO_REFRESH_INTERVAL.to_string().as_str())
+        .tooltip_text("The interval, in seconds, between two refreshes")
+        .max_width_chars(6)
+        .build();
+    auto_refresh_interval_entry.connect_changed(clone!(@strong sender => move |entry| {
+        sender.input(AppMsg::GraphMsg(GraphMsg::TrySetAutoRefreshInterval(
+            entry.text().to_string(),
+        )));
+    }));
+    auto_refresh_box.append(&auto_refresh_checkbox);
+    auto_refresh_box.append(&auto_refresh_interval_entry);
+    graph_box.append(&auto_refresh_box);
+
     // Add to the view stack
     sidebar_stack.add(&graph_box);
     sidebar_stack
@@ -190,43 +448,352 @@ pub(crate) fn init_sidebar_graph_page_widgets(
     flap.set_separator(Some(&separator));
 
     // Leaflet content
-    let leaflet_content = gtk::Box::builder()
-        .hexpand(true)
-        .vexpand(true)
-        .valign(gtk::Align::Center)
-        .halign(gtk::Align::Center)
-        .build();
+    let leaflet_content = gtk::Box::builder().hexpand(true).vexpand(true).build();
     let image_view_stack = gtk::Stack::builder()
         .transition_type(gtk::StackTransitionType::Crossfade)
         .hexpand(true)
         .vexpand(true)
         .build();
-    let image_preview_placeholder = adw::StatusPage::builder()
-        .title("Graph preview")
-        .description("Once generated, the graph will appear here")
+    // Home page, filled once the other pages are built
+    let home_page = adw::StatusPage::builder()
         .width_request(300)
         .height_request(400)
+        .valign(gtk::Align::Center)
+        .halign(gtk::Align::Center)
         .icon_name("view-app-grid")
         .build();
-    image_view_stack.add_child(&image_preview_placeholder);
+    image_view_stack.add_child(&home_page);
 
-    // Image
+    // Image, in a scrolled window for the zoomed in graphs
     let graph_image = gtk::Picture::new();
     graph_image.set_hexpand(true);
     graph_image.set_vexpand(true);
     graph_image.set_can_shrink(true);
+    let graph_image_size = Rc::new(Cell::new(None));
+    let graph_scrolled_window = gtk::ScrolledWindow::builder()
+        .hexpand(true)
+        .vexpand(true)
+        .child(&graph_image)
+        .build();
+
+    // Ctrl + wheel to zoom, wheel alone still scrolls
+    let scroll_controller =
+        gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL);
+    scroll_controller.connect_scroll(
+        clone!(@strong sender, @strong graph_image, @strong graph_image_size => move |controller, _dx, dy| {
+            if !controller
+                .current_event_state()
+                .contains(gdk::ModifierType::CONTROL_MASK)
+            {
+                return gtk::Inhibit(false);
+            }
+            if let Some(zoom) = current_zoom(&graph_image, graph_image_size.get()) {
+                let zoom = match dy < 0.0 {
+                    true => zoom * ZOOM_STEP,
+                    false => zoom / ZOOM_STEP,
+                };
+                sender.input(AppMsg::GraphMsg(GraphMsg::SetZoom(Some(
+                    zoom.clamp(MIN_ZOOM, MAX_ZOOM),
+                ))));
+            }
+            gtk::Inhibit(true)
+        }),
+    );
+    graph_scrolled_window.add_controller(&scroll_controller);
+
+    // Drag to pan
+    let drag_start = Rc::new(Cell::new((0.0, 0.0)));
+    let drag_gesture = gtk::GestureDrag::new();
+    drag_gesture.connect_drag_begin(
+        clone!(@strong graph_scrolled_window, @strong drag_start => move |_, _x, _y| {
+            drag_start.set((
+                graph_scrolled_window.hadjustment().value(),
+                graph_scrolled_window.vadjustment().value(),
+            ));
+        }),
+    );
+    drag_gesture.connect_drag_update(
+        clone!(@strong graph_scrolled_window, @strong drag_start => move |_, dx, dy| {
+            let (start_x, start_y) = drag_start.get();
+            graph_scrolled_window.hadjustment().set_value(start_x - dx);
+            graph_scrolled_window.vadjustment().set_value(start_y - dy);
+        }),
+    );
+    graph_scrolled_window.add_controller(&drag_gesture);
+
+    // DOT code, with a button to copy it
+    let dot_view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .hexpand(true)
+        .vexpand(true)
+        .build();
+    dot_view.set_margin_all(5);
+    let dot_copy_button = gtk::Button::builder()
+        .icon_name("edit-copy-symbolic")
+        .label("Copy")
+        .halign(gtk::Align::End)
+        .build();
+    dot_copy_button.set_margin_all(5);
+    dot_copy_button.connect_clicked(clone!(@strong dot_view => move |button| {
+        let buffer = dot_view.buffer();
+        let (start, end) = buffer.bounds();
+        button.clipboard().set_text(&buffer.text(&start, &end, false));
+    }));
+    let dot_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .build();
+    dot_box.append(&dot_copy_button);
+    dot_box.append(
+        &gtk::ScrolledWindow::builder()
+            .hexpand(true)
+            .vexpand(true)
+            .child(&dot_view)
+            .build(),
+    );
 
     // Add to stack
-    image_view_stack.add_child(&graph_image);
-    image_view_stack.set_visible_child(&image_preview_placeholder);
+    image_view_stack.add_child(&graph_scrolled_window);
+    image_view_stack.add_child(&dot_box);
+    image_view_stack.set_visible_child(&home_page);
+
+    // Connections table below the preview
+    let connections_table = connections_table::init_connections_table();
+    let graph_paned = gtk::Paned::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .start_child(&image_view_stack)
+        .end_child(&connections_table.container)
+        .shrink_end_child(false)
+        .hexpand(true)
+        .vexpand(true)
+        .build();
 
-    leaflet_content.append(&image_view_stack);
+    leaflet_content.append(&graph_paned);
     flap.set_content(Some(&leaflet_content));
 
     let graph_page_widgets = GraphPageWidgets {
+        generate_button,
         generate_button_spinner,
+        generate_progress_label,
+        cancel_generation_button,
         image_view_stack,
+        home_page,
+        graph_scrolled_window,
         graph_image,
+        graph_image_size,
+        svg_image_path: None,
+        dot_box,
+        dot_view,
+        connections_table,
+        graph_paned,
+        options: GraphOptionsWidgets {
+            output_format_dropdown,
+            output_dpi,
+            layout_engine_dropdown,
+            loopback_dropdown,
+            granularity_dropdown,
+            source_policy_dropdown,
+            vertical_graph_checkbox,
+            transparent_background_checkbox,
+            hide_legend_checkbox,
+            summarize_listeners_checkbox,
+            hide_agents_checkbox,
+            excluded_processes_entry,
+            excluded_hosts_entry,
+        },
     };
     (graph_options, graph_page_widgets)
 }
+
+/// Show the DOT code of the generated graph
+pub(crate) fn set_dot_code(widgets: &GraphPageWidgets, dot_code: &str) {
+    widgets.dot_view.buffer().set_text(dot_code);
+}
+
+/// Dim the graph preview while it does not match the input folders anymore
+pub(crate) fn mark_graph_outdated(widgets: &GraphPageWidgets, outdated: bool) {
+    match outdated {
+        true => {
+            widgets.graph_image.set_opacity(0.4);
+            widgets.graph_image.set_tooltip_text(Some(
+                "The input folders changed since this graph was generated",
+            ));
+        }
+        false => {
+            widgets.graph_image.set_opacity(1.0);
+            widgets.graph_image.set_tooltip_text(None);
+        }
+    }
+}
+
+/// Show the graph options of a loaded session. The widgets then send the options to the model.
+pub(crate) fn apply_graph_settings(widgets: &GraphOptionsWidgets, settings: &GraphSettings) {
+    if let Some(index) = SUPPORTED_FORMATS
+        .iter()
+        .position(|format| *format == settings.file_extension)
+    {
+        widgets.output_format_dropdown.set_selected(index as u32);
+    }
+    match settings.dpi == DEFAULT_DPI {
+        true => widgets.output_dpi.set_text(""),
+        false => widgets.output_dpi.set_text(&settings.dpi.to_string()),
+    }
+    if let Some(index) = LAYOUT_ENGINES
+        .iter()
+        .position(|layout_engine| *layout_engine == settings.layout_engine)
+    {
+        widgets.layout_engine_dropdown.set_selected(index as u32);
+    }
+    if let Some(index) = LOOPBACK_RENDERINGS
+        .iter()
+        .position(|loopback_rendering| *loopback_rendering == settings.loopback_rendering)
+    {
+        widgets.loopback_dropdown.set_selected(index as u32);
+    }
+    if let Some(index) = GRANULARITIES
+        .iter()
+        .position(|granularity| *granularity == settings.granularity)
+    {
+        widgets.granularity_dropdown.set_selected(index as u32);
+    }
+    if let Some(index) = SOURCE_POLICIES
+        .iter()
+        .position(|source_policy| *source_policy == settings.source_policy)
+    {
+        widgets.source_policy_dropdown.set_selected(index as u32);
+    }
+    widgets
+        .vertical_graph_checkbox
+        .set_active(settings.vertical_graph);
+    widgets
+        .transparent_background_checkbox
+        .set_active(settings.transparent_background);
+    widgets
+        .hide_legend_checkbox
+        .set_active(settings.hide_legend);
+    widgets
+        .summarize_listeners_checkbox
+        .set_active(settings.listener_rendering == ListenerRendering::Summary);
+    widgets
+        .hide_agents_checkbox
+        .set_active(settings.hide_agents);
+    widgets
+        .excluded_processes_entry
+        .set_text(&settings.excluded_processes.join(", "));
+    widgets
+        .excluded_hosts_entry
+        .set_text(&settings.excluded_hosts.join(", "));
+}
+
+/// Split a comma-separated list of host names or process name prefixes, ignoring the empty ones
+fn parse_excluded_names(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|prefix| !prefix.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Format of the graph preview. SVG stays sharp when zoomed, but its loader is missing from some
+/// GTK installations, such as the cross-compiled Windows packages.
+pub(crate) fn preview_format() -> &'static str {
+    match gdk_pixbuf::PixbufLoader::with_type("svg") {
+        Ok(_) => "svg",
+        Err(e) => {
+            log::info!("previewing graphs as PNG, SVG images are not supported: {e}");
+            "png"
+        }
+    }
+}
+
+/// Show the image at `path` in the graph preview. [`apply_zoom`] must be called next.
+pub(crate) fn load_graph_image(widgets: &mut GraphPageWidgets, path: &Path) {
+    let svg_size = match path.extension() == Some(OsStr::new("svg")) {
+        true => gdk_pixbuf::Pixbuf::file_info(path).map(|(_format, width, height)| (width, height)),
+        false => None,
+    };
+    if let Some(size) = svg_size {
+        widgets.svg_image_path = Some(path.to_path_buf());
+        widgets.graph_image_size.set(Some(size));
+    } else {
+        widgets.svg_image_path = None;
+        widgets.graph_image.set_filename(Some(path));
+        widgets.graph_image_size.set(
+            widgets
+                .graph_image
+                .paintable()
+                .map(|paintable| (paintable.intrinsic_width(), paintable.intrinsic_height())),
+        );
+    }
+}
+
+/// Scale the graph preview: fit it in the view if `zoom` is `None`, otherwise show it at `zoom`
+/// times its size, in the scrolled window. This must be called again when the image changes.
+pub(crate) fn apply_zoom(widgets: &GraphPageWidgets, zoom: Option<f64>) {
+    let graph_image = &widgets.graph_image;
+    let size = widgets
+        .graph_image_size
+        .get()
+        .filter(|(width, height)| *width > 0 && *height > 0);
+
+    // SVG images are rasterized at the size they are shown
+    if let (Some(svg_image_path), Some((width, height))) = (&widgets.svg_image_path, size) {
+        let scale = zoom
+            .or_else(|| fit_zoom(&widgets.graph_scrolled_window, (width, height)))
+            .unwrap_or(1.0);
+        match gdk_pixbuf::Pixbuf::from_file_at_scale(
+            svg_image_path,
+            (width as f64 * scale).round() as i32,
+            (height as f64 * scale).round() as i32,
+            true,
+        ) {
+            Ok(pixbuf) => graph_image.set_pixbuf(Some(&pixbuf)),
+            Err(e) => log::error!("unable to render the graph preview: {e}"),
+        }
+    }
+
+    match (zoom, size) {
+        (Some(zoom), Some((width, height))) => {
+            // Centered, so that it is not stretched when smaller than the view
+            graph_image.set_halign(gtk::Align::Center);
+            graph_image.set_valign(gtk::Align::Center);
+            graph_image.set_size_request(
+                (width as f64 * zoom).round() as i32,
+                (height as f64 * zoom).round() as i32,
+            );
+        }
+        _ => {
+            graph_image.set_halign(gtk::Align::Fill);
+            graph_image.set_valign(gtk::Align::Fill);
+            graph_image.set_size_request(-1, -1);
+        }
+    }
+}
+
+/// Scale at which an image of `size` fits in the view, once the view is allocated
+fn fit_zoom(graph_scrolled_window: &gtk::ScrolledWindow, size: (i32, i32)) -> Option<f64> {
+    let (view_width, view_height) = (
+        graph_scrolled_window.width(),
+        graph_scrolled_window.height(),
+    );
+    if view_width <= 0 || view_height <= 0 {
+        return None;
+    }
+    Some(f64::min(
+        view_width as f64 / size.0 as f64,
+        view_height as f64 / size.1 as f64,
+    ))
+}
+
+/// Scale at which the graph preview is shown, whether it fits the view or is zoomed
+fn current_zoom(graph_image: &gtk::Picture, size: Option<(i32, i32)>) -> Option<f64> {
+    let (width, height) = size?;
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+    let zoom = f64::min(
+        graph_image.width() as f64 / width as f64,
+        graph_image.height() as f64 / height as f64,
+    );
+    Some(zoom)
+}
@@ -0,0 +1,103 @@
+//! Home page, shown in place of the graph preview, with the first steps to make a graph
+
+use gtk::{
+    glib::clone,
+    traits::{BoxExt, ButtonExt, WidgetExt},
+};
+use relm4::{adw, Sender};
+
+use super::{files::FilesPageWidgets, help::HelpWindowMsg, server::ServerPageWidgets};
+
+/// Add the first steps buttons to the home page: each one shows its sidebar page and starts its
+/// action
+pub(crate) fn init_home_page(
+    home_page: &adw::StatusPage,
+    flap: &adw::Flap,
+    sidebar_stack: &adw::ViewStack,
+    files_page_widgets: &FilesPageWidgets,
+    server_page_widgets: &ServerPageWidgets,
+    help_window_sender: &Sender<HelpWindowMsg>,
+) {
+    let buttons_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(10)
+        .halign(gtk::Align::Center)
+        .build();
+
+    let open_folder_button = gtk::Button::builder()
+        .child(
+            &adw::ButtonContent::builder()
+                .icon_name("document-open-symbolic")
+                .label("Open capture folder…")
+                .build(),
+        )
+        .css_classes(vec!["suggested-action".to_string(), "pill".to_string()])
+        .build();
+    let files_page = &files_page_widgets.page;
+    let open_button = &files_page_widgets.open_button;
+    open_folder_button.connect_clicked(
+        clone!(@strong flap, @strong sidebar_stack, @strong files_page, @strong open_button => move |_| {
+            flap.set_reveal_flap(true);
+            sidebar_stack.set_visible_child(&files_page);
+            open_button.emit_clicked();
+        }),
+    );
+    buttons_box.append(&open_folder_button);
+
+    let start_server_button = gtk::Button::builder()
+        .child(
+            &adw::ButtonContent::builder()
+                .icon_name("network-workgroup-symbolic")
+                .label("Start agent server…")
+                .build(),
+        )
+        .css_classes(vec!["pill".to_string()])
+        .build();
+    let server_page = &server_page_widgets.page;
+    let server_button = &server_page_widgets.server_button;
+    start_server_button.connect_clicked(
+        clone!(@strong flap, @strong sidebar_stack, @strong server_page, @strong server_button => move |_| {
+            flap.set_reveal_flap(true);
+            sidebar_stack.set_visible_child(&server_page);
+            if !server_button.is_active() {
+                server_button.emit_clicked();
+            }
+        }),
+    );
+    buttons_box.append(&start_server_button);
+
+    let help_button = gtk::LinkButton::builder()
+        .label("How do I collect captures?")
+        .uri("help")
+        .build();
+    help_button.connect_activate_link(clone!(@strong help_window_sender => move |_| {
+        help_window_sender.emit(HelpWindowMsg::Show);
+        gtk::Inhibit(true)
+    }));
+    buttons_box.append(&help_button);
+
+    home_page.set_child(Some(&buttons_box));
+    update_home_page(home_page, 0);
+}
+
+/// Tell the next step, depending on the number of hosts loaded from the capture folders
+pub(crate) fn update_home_page(home_page: &adw::StatusPage, hosts_count: usize) {
+    match hosts_count {
+        0 => {
+            home_page.set_title("Welcome to Sockets Map");
+            home_page.set_description(Some(
+                "Open a folder of capture files, or start the server for the agents to connect",
+            ));
+        }
+        hosts_count => {
+            home_page.set_title(&format!(
+                "{hosts_count} {} loaded",
+                match hosts_count {
+                    1 => "host",
+                    _ => "hosts",
+                }
+            ));
+            home_page.set_description(Some("Click Generate graph to draw their connections"));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Messages shown to the user in the info bar, and kept in the messages history
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+
+/// Number of notifications kept in the history
+const MAX_NOTIFICATIONS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "Info",
+            Severity::Warning => "Warning",
+            Severity::Error => "Error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Notification {
+    pub severity: Severity,
+    pub message: String,
+    pub time: DateTime<Local>,
+}
+
+impl std::fmt::Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}: {}",
+            self.time.format("%H:%M:%S"),
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// History of the last notifications
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Notifications {
+    history: VecDeque<Notification>,
+}
+
+impl Notifications {
+    /// Add a notification, dropping the oldest one if the history is full
+    pub fn push(&mut self, notification: Notification) {
+        match notification.severity {
+            Severity::Info => log::info!("{}", notification.message),
+            Severity::Warning => log::warn!("{}", notification.message),
+            Severity::Error => log::error!("{}", notification.message),
+        }
+        self.history.push_front(notification);
+        self.history.truncate(MAX_NOTIFICATIONS);
+    }
+
+    /// The notifications, most recent first
+    pub fn iter(&self) -> impl Iterator<Item = &Notification> {
+        self.history.iter()
+    }
+
+    pub fn latest(&self) -> Option<&Notification> {
+        self.history.front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn notification(severity: Severity, message: &str, second: u32) -> Notification {
+        Notification {
+            severity,
+            message: message.into(),
+            time: Local.with_ymd_and_hms(2023, 5, 1, 12, 3, second).unwrap(),
+        }
+    }
+
+    /// Only the most recent notifications must be kept, newest first
+    #[test]
+    fn test_notifications_history() {
+        let mut notifications = Notifications::default();
+        assert!(notifications.latest().is_none());
+        for i in 0..MAX_NOTIFICATIONS + 2 {
+            notifications.push(notification(Severity::Info, &i.to_string(), 0));
+        }
+        notifications.push(notification(Severity::Error, "last", 0));
+
+        assert_eq!(notifications.iter().count(), MAX_NOTIFICATIONS);
+        assert_eq!(notifications.latest().unwrap().message, "last");
+        let messages: Vec<&str> = notifications.iter().map(|n| n.message.as_str()).collect();
+        assert_eq!(messages[1], (MAX_NOTIFICATIONS + 1).to_string());
+        assert!(!messages.contains(&"2"));
+    }
+
+    /// Notifications are listed with their time and severity
+    #[test]
+    fn test_notification_display() {
+        assert_eq!(
+            notification(
+                Severity::Warning,
+                "did not get an update from all clients",
+                4
+            )
+            .to_string(),
+            "[12:03:04] Warning: did not get an update from all clients"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! User preferences, loaded at startup and saved on exit
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    app_msgs::ServerOption, appearance::Appearance, files::recent_folders::RecentFolders,
+    session::GraphSettings,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Preferences {
+    // Tables must come after the values in TOML
+    pub window_width: i32,
+    pub window_height: i32,
+    pub appearance: Appearance,
+    /// Whether the provenance of the graph is embedded in the exported files
+    pub embed_provenance: bool,
+    pub recent_folders: RecentFolders,
+    pub graph: GraphSettings,
+    pub server: ServerOption,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            window_width: 1000,
+            window_height: 600,
+            appearance: Appearance::default(),
+            embed_provenance: true,
+            recent_folders: RecentFolders::default(),
+            graph: GraphSettings::default(),
+            server: ServerOption::default(),
+        }
+    }
+}
+
+impl Preferences {
+    /// Load the preferences file. Missing values take their default value, and a missing or
+    /// corrupt file gives the default preferences.
+    pub fn load() -> Self {
+        preferences_file()
+            .map(|file| Self::load_from(&file))
+            .unwrap_or_default()
+    }
+
+    fn load_from(path: &Path) -> Self {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return Self::default(),
+        };
+        let mut preferences: Self = toml::from_str(&content).unwrap_or_else(|e| {
+            log::warn!("ignoring invalid preferences file {path:?}: {e}");
+            Self::default()
+        });
+        preferences.graph.migrate();
+        preferences
+    }
+
+    /// Write the preferences file
+    pub fn save(&self) {
+        if let Some(file) = preferences_file() {
+            if let Err(e) = self.save_to(&file) {
+                log::warn!("unable to save the preferences to {file:?}: {e}");
+            }
+        }
+    }
+
+    fn save_to(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Configuration directory of the application, for the preferences and the recent sessions
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "sockets_map")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// Cache directory of the application, for the hosts parsed from the input folders
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "sockets_map").map(|dirs| dirs.cache_dir().join("hosts"))
+}
+
+fn preferences_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("preferences.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockets_map::graphviz::LayoutEngine;
+
+    /// Saved preferences must be loaded as is, even in a directory which does not exist yet
+    #[test]
+    fn test_preferences_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory
+            .path()
+            .join("sockets_map")
+            .join("preferences.toml");
+        let preferences = Preferences {
+            window_width: 1280,
+            appearance: Appearance::Dark,
+            embed_provenance: false,
+            graph: GraphSettings {
+                dpi: 150.0,
+                layout_engine: LayoutEngine::Fdp,
+                excluded_processes: vec!["chrome".into()],
+                ..Default::default()
+            },
+            server: ServerOption {
+                listen_port: "7000".into(),
+                recorder_interval: "2.5".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        preferences.save_to(&path).unwrap();
+        assert_eq!(Preferences::load_from(&path), preferences);
+    }
+
+    /// Missing values are merged with the defaults, and invalid files are ignored
+    #[test]
+    fn test_preferences_fallback() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("preferences.toml");
+        assert_eq!(Preferences::load_from(&path), Preferences::default());
+
+        std::fs::write(&path, "window_width = 800\n[graph]\nhide_legend = true\n").unwrap();
+        let preferences = Preferences::load_from(&path);
+        assert_eq!(preferences.window_width, 800);
+        assert_eq!(preferences.window_height, 600);
+        assert_eq!(preferences.appearance, Appearance::System);
+        assert!(preferences.embed_provenance);
+        assert!(preferences.graph.hide_legend);
+        assert!(preferences.graph.hide_agents);
+        assert_eq!(preferences.server, ServerOption::default());
+
+        std::fs::write(&path, "window_width = \"wide\"\n[graph").unwrap();
+        assert_eq!(Preferences::load_from(&path), Preferences::default());
+    }
+}
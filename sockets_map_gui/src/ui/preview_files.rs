@@ -0,0 +1,96 @@
+//! Temporary files receiving the graph previews. Each generation renders to a file of its own,
+//! which replaces the file of the preview shown once the graph is generated, so that the
+//! superseded previews do not pile up until the application exits.
+
+use std::path::Path;
+
+use tempfile::NamedTempFile;
+
+/// Files of the graph preview, in the format in which the previews are rendered
+#[derive(Debug)]
+pub(crate) struct PreviewFiles {
+    format: &'static str,
+    /// File of the preview shown, if a graph was generated
+    current: Option<NamedTempFile>,
+}
+
+impl PreviewFiles {
+    pub fn new(format: &'static str) -> Self {
+        Self {
+            format,
+            current: None,
+        }
+    }
+
+    /// New file for a generation, deleted when dropped unless it is shown with [`Self::replace`]
+    pub fn create(&self) -> std::io::Result<NamedTempFile> {
+        tempfile::Builder::new()
+            .suffix(&format!(".{}", self.format))
+            .tempfile()
+    }
+
+    /// Show the preview of `file`, deleting the file of the previous preview
+    pub fn replace(&mut self, file: NamedTempFile) {
+        if let Some(previous) = self.current.replace(file) {
+            close(previous);
+        }
+    }
+
+    /// Delete the file of the preview shown, such as before exiting, since the temporary files are
+    /// not deleted by `std::process::exit`
+    pub fn clear(&mut self) {
+        if let Some(current) = self.current.take() {
+            close(current);
+        }
+    }
+
+    /// Get the path of the preview shown.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(NamedTempFile::path)
+    }
+
+    /// Get the format of the previews.
+    pub fn format(&self) -> &'static str {
+        self.format
+    }
+}
+
+fn close(file: NamedTempFile) {
+    let path = file.path().to_path_buf();
+    if let Err(e) = file.close() {
+        log::warn!("unable to delete the previous graph preview {path:?}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the file of the preview shown must be kept, and deleted once cleared
+    #[test]
+    fn test_preview_files() {
+        let mut preview_files = PreviewFiles::new("svg");
+        assert!(preview_files.current_path().is_none());
+
+        let first = preview_files.create().unwrap();
+        let first_path = first.path().to_path_buf();
+        assert_eq!(first_path.extension().unwrap(), "svg");
+        preview_files.replace(first);
+        assert_eq!(preview_files.current_path(), Some(first_path.as_path()));
+
+        // A generation which fails or is cancelled drops its file
+        let failed_path = preview_files.create().unwrap().path().to_path_buf();
+        assert!(!failed_path.exists());
+
+        let second = preview_files.create().unwrap();
+        let second_path = second.path().to_path_buf();
+        preview_files.replace(second);
+        assert!(!first_path.exists());
+        assert!(second_path.exists());
+        assert_eq!(preview_files.current_path(), Some(second_path.as_path()));
+
+        preview_files.clear();
+        assert!(!second_path.exists());
+        assert!(preview_files.current_path().is_none());
+    }
+}
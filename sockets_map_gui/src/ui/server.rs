@@ -1,23 +1,96 @@
 //! Server page widgets
 
 pub mod client;
+pub mod client_details;
+pub mod deploy;
+pub mod event_log;
+pub mod recorder;
+pub mod startup;
+
+use std::{cell::RefCell, rc::Rc};
 
 use gtk::{
+    gdk,
     glib::clone,
     prelude::ObjectExt,
-    traits::{BoxExt, ButtonExt, EditableExt, ToggleButtonExt, WidgetExt},
+    traits::{
+        BoxExt, ButtonExt, CheckButtonExt, ColorChooserExt, EditableExt, EntryExt, PopoverExt,
+        ToggleButtonExt, WidgetExt,
+    },
 };
 use relm4::{adw, factory::FactoryVecDeque, ComponentSender, RelmWidgetExt};
+use sockets_map::{
+    host::{parse_hex_color, DisplayOverride},
+    server::client::UpdateSelection,
+};
 
-use self::client::ClientLabel;
+use self::{
+    client::ClientLabel,
+    client_details::{edited_display_override, format_client_details, ClientDetails},
+    deploy::DeployCommand,
+    startup::ServerToggle,
+};
 
-use super::{app_msgs::ServerMsg, app_msgs::ServerOption, AppModel, AppMsg};
+use super::{
+    app_msgs::ServerMsg,
+    app_msgs::ServerOption,
+    notifications::Severity,
+    shortcuts::{self, ShortcutAction},
+    AppModel, AppMsg,
+};
+
+/// Number of snapshots of the sampled update requests, to catch the short-lived connections
+const SAMPLED_UPDATE_SAMPLES: u8 = 5;
+/// Interval between the snapshots of the sampled update requests, in seconds
+const SAMPLED_UPDATE_INTERVAL: f64 = 1.0;
+/// Tooltip of the recorder interval entry, replaced by the error while the interval is invalid
+const RECORDER_INTERVAL_TOOLTIP: &str =
+    "The interval, in seconds, between updates in Recorder mode";
+/// Selections of the clients updates, in the order of the dropdown
+const UPDATE_SELECTIONS: [UpdateSelection; 3] = [
+    UpdateSelection::Latest,
+    UpdateSelection::First,
+    UpdateSelection::MergeAll,
+];
 
 #[derive(Debug)]
 pub(crate) struct ServerPageWidgets {
+    /// The page in the sidebar stack
+    pub page: adw::Clamp,
+    pub server_button: gtk::ToggleButton,
+    pub server_address: gtk::Entry,
+    pub server_port: gtk::Entry,
+    pub http_port: gtk::Entry,
+    pub max_updates_per_client: gtk::Entry,
+    pub max_update_size: gtk::Entry,
+    pub max_clients: gtk::Entry,
+    pub verify_source_ip: gtk::CheckButton,
+    pub verify_exempt_ips: gtk::Entry,
+    pub update_timeout: gtk::Entry,
+    pub update_selection: gtk::DropDown,
+    pub recorder_interval: gtk::Entry,
     pub recorder_timer: gtk::Label,
     pub clients_record_button: gtk::ToggleButton,
     pub client_record_button_content: adw::ButtonContent,
+    pub clients_box: gtk::ListBox,
+    pub client_details: ClientDetailsWidgets,
+    /// Events of the session, the newest first
+    pub event_log_box: gtk::ListBox,
+    /// Commands to run the agents against the started server
+    pub deploy_box: gtk::Box,
+}
+
+/// Popover showing the details of the clicked client, and editing its name and color in the graphs
+#[derive(Debug)]
+pub(crate) struct ClientDetailsWidgets {
+    popover: gtk::Popover,
+    details_label: gtk::Label,
+    display_name: gtk::Entry,
+    /// Whether the host cluster is filled with the color of `color_button`
+    fill_color: gtk::CheckButton,
+    color_button: gtk::ColorButton,
+    /// Hostname of the client shown
+    hostname: Rc<RefCell<String>>,
 }
 
 /// Generate the server controls widgets for the sidebar
@@ -34,6 +107,7 @@ pub(crate) fn init_sidebar_server_widgets(
     let clamp = adw::Clamp::builder().maximum_size(300).build();
 
     // Server options
+    let server_option = ServerOption::default();
     let server_address_label = gtk::Label::builder()
         .label("Server listen address")
         .hexpand(true)
@@ -42,7 +116,7 @@ pub(crate) fn init_sidebar_server_widgets(
         .build();
     let server_address = gtk::Entry::builder()
         .tooltip_text("The address the server will listen on")
-        .text("0.0.0.0")
+        .text(server_option.listen_addr.as_str())
         .build();
     let server_port_label = gtk::Label::builder()
         .label("Server listen port")
@@ -52,8 +126,168 @@ pub(crate) fn init_sidebar_server_widgets(
         .build();
     let server_port = gtk::Entry::builder()
         .tooltip_text("The TCP port the server will listen on")
-        .text("6840")
+        .text(server_option.listen_port.as_str())
+        .build();
+    let http_port_label = gtk::Label::builder()
+        .label("HTTP status port")
+        .hexpand(true)
+        .halign(gtk::Align::Start)
+        .justify(gtk::Justification::Left)
+        .build();
+    let http_port = gtk::Entry::builder()
+        .tooltip_text("The TCP port of the read-only HTTP status endpoint (leave empty to disable)")
+        .placeholder_text("Disabled")
+        .text(server_option.http_port.as_str())
+        .build();
+
+    // Limits, hidden by default
+    let advanced_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(5)
+        .build();
+    let limit_entry = |label: &str, tooltip: &str, text: &str| {
+        advanced_box.append(
+            &gtk::Label::builder()
+                .label(label)
+                .halign(gtk::Align::Start)
+                .build(),
+        );
+        let entry = gtk::Entry::builder()
+            .tooltip_text(tooltip)
+            .input_purpose(gtk::InputPurpose::Digits)
+            .text(text)
+            .build();
+        advanced_box.append(&entry);
+        entry
+    };
+    let max_updates_per_client = limit_entry(
+        "Updates kept per client",
+        "Number of updates kept for each client, the oldest ones being dropped first",
+        &server_option.max_updates_per_client,
+    );
+    let max_update_size = limit_entry(
+        "Maximum update size (MiB)",
+        "Updates larger than this are rejected, and the agent is told why",
+        &server_option.max_update_size,
+    );
+    let max_clients = limit_entry(
+        "Maximum clients",
+        "Registrations beyond this number of clients are refused",
+        &server_option.max_clients,
+    );
+    let update_timeout = limit_entry(
+        "Update timeout (s)",
+        "Seconds to wait for the clients updates before generating the graph without the missing ones",
+        &server_option.update_timeout,
+    );
+    advanced_box.append(
+        &gtk::Label::builder()
+            .label("Updates drawn per client")
+            .halign(gtk::Align::Start)
+            .build(),
+    );
+    let update_selection = gtk::DropDown::from_strings(&["Latest", "First", "Merge all"]);
+    update_selection.set_tooltip_text(Some(
+        "Which of the updates kept for each client are drawn: the latest one, the oldest one, or all of them merged",
+    ));
+    advanced_box.append(&update_selection);
+    let verify_source_ip = gtk::CheckButton::builder()
+        .label("Verify the agents source IP")
+        .tooltip_text(
+            "Flag the agents which do not connect from one of the IPs they register with, such as cloned machines",
+        )
+        .active(server_option.verify_source_ip)
+        .build();
+    advanced_box.append(&verify_source_ip);
+    advanced_box.append(
+        &gtk::Label::builder()
+            .label("Exempt source IPs")
+            .halign(gtk::Align::Start)
+            .build(),
+    );
+    let verify_exempt_ips = gtk::Entry::builder()
+        .tooltip_text("Comma-separated source IPs never flagged, such as the ones of NAT gateways")
+        .placeholder_text("None")
+        .text(server_option.verify_exempt_ips.as_str())
         .build();
+    verify_source_ip
+        .bind_property("active", &verify_exempt_ips, "sensitive")
+        .sync_create()
+        .build();
+    advanced_box.append(&verify_exempt_ips);
+    let advanced_expander = gtk::Expander::builder()
+        .label("Advanced settings")
+        .child(&advanced_box)
+        .build();
+
+    // Commands to run the agents, filled once the server is started
+    let deploy_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(5)
+        .build();
+    let deploy_expander = gtk::Expander::builder()
+        .label("Deploy agents")
+        .child(&deploy_box)
+        .build();
+
+    // Recorder interval, checked as it is typed
+    let recorder_interval_entry = gtk::Entry::builder()
+        .text(server_option.recorder_interval.as_str())
+        .tooltip_text(RECORDER_INTERVAL_TOOLTIP)
+        .build();
+    recorder_interval_entry.connect_changed(|entry| {
+        match recorder::parse_recorder_interval(&entry.text()) {
+            Ok(_) => {
+                entry.remove_css_class("error");
+                entry.set_tooltip_text(Some(RECORDER_INTERVAL_TOOLTIP));
+            }
+            Err(e) => {
+                entry.add_css_class("error");
+                entry.set_tooltip_text(Some(e.as_str()));
+            }
+        }
+    });
+
+    let entries = [
+        server_address.clone(),
+        server_port.clone(),
+        http_port.clone(),
+        max_updates_per_client.clone(),
+        max_update_size.clone(),
+        max_clients.clone(),
+        verify_exempt_ips.clone(),
+        update_timeout.clone(),
+        recorder_interval_entry.clone(),
+    ];
+    for entry in &entries {
+        entry.connect_changed(
+            clone!(@strong sender, @strong entries, @strong verify_source_ip, @strong update_selection => move |_| {
+                sender.input(AppMsg::ServerMsg(ServerMsg::SetServerOption(read_server_option(
+                    &entries,
+                    &verify_source_ip,
+                    &update_selection,
+                ))));
+            }),
+        );
+    }
+    verify_source_ip.connect_toggled(
+        clone!(@strong sender, @strong entries, @strong update_selection => move |button| {
+            sender.input(AppMsg::ServerMsg(ServerMsg::SetServerOption(read_server_option(
+                &entries,
+                button,
+                &update_selection,
+            ))));
+        }),
+    );
+    update_selection.connect_selected_notify(
+        clone!(@strong sender, @strong entries, @strong verify_source_ip => move |dropdown| {
+            sender.input(AppMsg::ServerMsg(ServerMsg::SetServerOption(read_server_option(
+                &entries,
+                &verify_source_ip,
+                dropdown,
+            ))));
+        }),
+    );
 
     // Start and stop button
     let server_button_start_content = adw::ButtonContent::builder()
@@ -69,20 +303,26 @@ pub(crate) fn init_sidebar_server_widgets(
         .css_classes(vec!["suggested-action".to_string()])
         .build();
     server_button.connect_clicked(
-        clone!(@strong sender, @strong server_address, @strong server_port => move |button| {
-            if button.is_active() {
-                button.set_child(Some(&server_button_stop_content));
-                button.set_css_classes(&["destructive-action"]);
-                sender.input(AppMsg::ServerMsg(ServerMsg::EnableServer(Some(
-                    ServerOption {
-                        listen_addr: server_address.text().to_string(),
-                        listen_port: server_port.text().to_string(),
-                    },
-                ))));
-            } else {
-                button.set_child(Some(&server_button_start_content));
-                button.set_css_classes(&["suggested-action"]);
-                sender.input(AppMsg::ServerMsg(ServerMsg::EnableServer(None)));
+        clone!(@strong sender, @strong entries, @strong verify_source_ip, @strong update_selection => move |button| {
+            match startup::toggle(
+                button.is_active(),
+                read_server_option(&entries, &verify_source_ip, &update_selection),
+            ) {
+                ServerToggle::Start(server_option) => {
+                    button.set_child(Some(&server_button_stop_content));
+                    button.set_css_classes(&["destructive-action"]);
+                    sender.input(AppMsg::ServerMsg(ServerMsg::EnableServer(Some(server_option))));
+                }
+                ServerToggle::Refuse(message) => {
+                    // The button is still showing the start content
+                    button.set_active(false);
+                    sender.input(AppMsg::Notify(Severity::Error, message));
+                }
+                ServerToggle::Stop => {
+                    button.set_child(Some(&server_button_start_content));
+                    button.set_css_classes(&["suggested-action"]);
+                    sender.input(AppMsg::ServerMsg(ServerMsg::EnableServer(None)));
+                }
             }
         }),
     );
@@ -105,27 +345,49 @@ pub(crate) fn init_sidebar_server_widgets(
         .build();
     clients_list_label_box.append(&clients_list_label);
 
-    // Update button
+    // Update button, with a menu to catch the short-lived connections with several samples
     let client_update_button_content = adw::ButtonContent::builder()
         .icon_name("view-refresh-symbolic")
         .label("Update")
         .build();
-    let clients_update_button = gtk::Button::builder()
-        .tooltip_text("Send a one time update request to clients, to make a graph of their connection at one instant")
+    let update_menu_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .build();
+    let update_menu = gtk::Popover::builder().child(&update_menu_box).build();
+    for (label, samples) in [
+        ("Update (1 sample)".to_string(), 1),
+        (
+            format!(
+                "Update ({SAMPLED_UPDATE_SAMPLES} samples over {}s)",
+                f64::from(SAMPLED_UPDATE_SAMPLES) * SAMPLED_UPDATE_INTERVAL
+            ),
+            SAMPLED_UPDATE_SAMPLES,
+        ),
+    ] {
+        let button = gtk::Button::builder().label(label).has_frame(false).build();
+        button.connect_clicked(clone!(@strong sender, @strong update_menu => move |_| {
+            update_menu.popdown();
+            sender.input(AppMsg::ServerMsg(ServerMsg::SendSampledUpdateRequest {
+                samples,
+                interval: SAMPLED_UPDATE_INTERVAL,
+            }))
+        }));
+        update_menu_box.append(&button);
+    }
+    let clients_update_button = adw::SplitButton::builder()
+        .tooltip_text(&shortcuts::tooltip(
+            "Send a one time update request to clients, to make a graph of their connection at one instant",
+            ShortcutAction::RequestUpdate,
+        ))
         .sensitive(false)
         .child(&client_update_button_content)
+        .popover(&update_menu)
         .halign(gtk::Align::End)
         .build();
     clients_update_button.connect_clicked(clone!(@strong sender => move |_| {
         sender.input(AppMsg::ServerMsg(ServerMsg::SendUpdateRequest))
     }));
 
-    // Recorder internal
-    let recorder_interval_entry = gtk::Entry::builder()
-        .text("1.0")
-        .tooltip_text("The interval, in seconds, between updates in Recorder mode")
-        .build();
-
     // Record button
     let client_record_button_content = adw::ButtonContent::builder()
         .icon_name("media-record-symbolic")
@@ -150,18 +412,18 @@ pub(crate) fn init_sidebar_server_widgets(
                 b.set_child(Some(&client_record_button_content));
                 log::info!("stopping recorders");
                 sender.input(AppMsg::ServerMsg(ServerMsg::StopRecorder))
-            } else if let Ok(i) = recorder_interval_entry.text().parse() {
-                if i < 0.1 {
-                    b.set_active(false);
-                    sender.input(AppMsg::Error(Some("Interval too low (must be >= 0.1s)".into())))
-                } else {
-                    log::info!("starting recorders");
-                    b.set_child(Some(&client_recording_button_content));
-                    sender.input(AppMsg::ServerMsg(ServerMsg::StartRecorder(i)))
-                }
             } else {
-                b.set_active(false);
-                sender.input(AppMsg::Error(Some("Invalid recorder update interval (seconds)".into())))
+                match recorder::parse_recorder_interval(&recorder_interval_entry.text()) {
+                    Ok(i) => {
+                        log::info!("starting recorders");
+                        b.set_child(Some(&client_recording_button_content));
+                        sender.input(AppMsg::ServerMsg(ServerMsg::StartRecorder(i)))
+                    }
+                    Err(e) => {
+                        b.set_active(false);
+                        sender.input(AppMsg::Notify(Severity::Error, e))
+                    }
+                }
             }
     }));
 
@@ -197,7 +459,32 @@ pub(crate) fn init_sidebar_server_widgets(
         .selection_mode(gtk::SelectionMode::None)
         .build();
     clients_box.set_hexpand(true);
-    let clients = FactoryVecDeque::new(clients_box, sender.input_sender());
+    clients_box.connect_row_activated(clone!(@strong sender => move |_, row| {
+        if let Ok(index) = usize::try_from(row.index()) {
+            sender.input(AppMsg::ServerMsg(ServerMsg::ShowClientDetails(index)))
+        }
+    }));
+    let client_details = init_client_details_popover(&clients_box, sender.clone());
+    let clients = FactoryVecDeque::new(clients_box.clone(), sender.input_sender());
+
+    // Event log, refreshed when shown
+    let event_log_box = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    let event_log_scrolled_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(200)
+        .child(&event_log_box)
+        .build();
+    let event_log_expander = gtk::Expander::builder()
+        .label("Event log")
+        .child(&event_log_scrolled_window)
+        .build();
+    event_log_expander.connect_expanded_notify(clone!(@strong sender => move |expander| {
+        if expander.is_expanded() {
+            sender.input(AppMsg::ServerMsg(ServerMsg::RefreshEventLog));
+        }
+    }));
 
     // Add to box
     page_box.append(&server_button);
@@ -205,10 +492,15 @@ pub(crate) fn init_sidebar_server_widgets(
     page_box.append(&server_address);
     page_box.append(&server_port_label);
     page_box.append(&server_port);
+    page_box.append(&http_port_label);
+    page_box.append(&http_port);
+    page_box.append(&advanced_expander);
+    page_box.append(&deploy_expander);
     page_box.append(&separator);
     page_box.append(&clients_list_label_box);
     page_box.append(&clients_label_button_box);
     page_box.append(clients.widget());
+    page_box.append(&event_log_expander);
     clamp.set_child(Some(&page_box));
 
     sidebar_stack.add(&clamp);
@@ -218,9 +510,331 @@ pub(crate) fn init_sidebar_server_widgets(
     sidebar_stack.page(&clamp).set_title(Some("Server"));
 
     let widgets = ServerPageWidgets {
+        page: clamp,
+        server_button,
+        server_address,
+        server_port,
+        http_port,
+        max_updates_per_client,
+        max_update_size,
+        max_clients,
+        verify_source_ip,
+        verify_exempt_ips,
+        update_timeout,
+        update_selection,
+        recorder_interval: recorder_interval_entry,
         recorder_timer,
         clients_record_button,
         client_record_button_content,
+        clients_box,
+        client_details,
+        event_log_box,
+        deploy_box,
     };
+    show_deploy_commands(&widgets, &[]);
     (widgets, clients)
 }
+
+/// Generate the client details popover, attached to the clients list
+fn init_client_details_popover(
+    clients_box: &gtk::ListBox,
+    sender: ComponentSender<AppModel>,
+) -> ClientDetailsWidgets {
+    let details_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(10)
+        .build();
+    let details_label = gtk::Label::builder()
+        .selectable(true)
+        .halign(gtk::Align::Start)
+        .build();
+    // Agents are reached through a broadcast channel, so all of them are asked
+    let request_update_button = gtk::Button::builder()
+        .label("Request update")
+        .tooltip_text("Send an update request to the clients")
+        .build();
+
+    // Name and color of the host in the graphs
+    let display_name = gtk::Entry::builder()
+        .tooltip_text("The name of the host in the graphs")
+        .build();
+    let fill_color = gtk::CheckButton::builder()
+        .label("Fill color")
+        .tooltip_text("Fill the host in the graphs with a color")
+        .build();
+    let color_button = gtk::ColorButton::builder().sensitive(false).build();
+    color_button.set_use_alpha(false);
+    fill_color.connect_toggled(clone!(@strong color_button => move |button| {
+        color_button.set_sensitive(button.is_active());
+    }));
+    let color_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(10)
+        .build();
+    color_box.append(&fill_color);
+    color_box.append(&color_button);
+    let apply_button = gtk::Button::builder()
+        .label("Apply")
+        .tooltip_text("Draw the host with this name and color")
+        .build();
+    let reset_button = gtk::Button::builder()
+        .label("Reset")
+        .tooltip_text("Draw the host with its own name and without color")
+        .build();
+    let display_buttons_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(10)
+        .homogeneous(true)
+        .build();
+    display_buttons_box.append(&reset_button);
+    display_buttons_box.append(&apply_button);
+
+    details_box.append(&details_label);
+    details_box.append(&request_update_button);
+    details_box.append(&gtk::Separator::new(gtk::Orientation::Horizontal));
+    details_box.append(
+        &gtk::Label::builder()
+            .label("Name in the graphs")
+            .halign(gtk::Align::Start)
+            .build(),
+    );
+    details_box.append(&display_name);
+    details_box.append(&color_box);
+    details_box.append(&display_buttons_box);
+
+    let popover = gtk::Popover::builder().child(&details_box).build();
+    popover.set_parent(clients_box);
+    request_update_button.connect_clicked(clone!(@strong popover, @strong sender => move |_| {
+        sender.input(AppMsg::ServerMsg(ServerMsg::SendUpdateRequest));
+        popover.popdown();
+    }));
+
+    let hostname = Rc::new(RefCell::new(String::new()));
+    let send_display_override = clone!(@strong popover, @strong hostname => move |display_override| {
+        sender.input(AppMsg::ServerMsg(ServerMsg::SetDisplayOverride {
+            hostname: hostname.borrow().clone(),
+            display_override,
+        }));
+        popover.popdown();
+    });
+    apply_button.connect_clicked(clone!(
+        @strong display_name,
+        @strong fill_color,
+        @strong color_button,
+        @strong send_display_override => move |_| {
+            let rgba = color_button.rgba();
+            let color = fill_color
+                .is_active()
+                .then(|| [rgba.red(), rgba.green(), rgba.blue()]);
+            send_display_override(edited_display_override(&display_name.text(), color));
+        }
+    ));
+    display_name.connect_activate(clone!(@strong apply_button => move |_| {
+        apply_button.emit_clicked();
+    }));
+    reset_button.connect_clicked(move |_| send_display_override(DisplayOverride::default()));
+
+    ClientDetailsWidgets {
+        popover,
+        details_label,
+        display_name,
+        fill_color,
+        color_button,
+        hostname,
+    }
+}
+
+/// Show the details of the client at `index` in the clients list
+pub(crate) fn show_client_details(
+    widgets: &ServerPageWidgets,
+    index: usize,
+    details: &ClientDetails,
+    display_override: Option<&DisplayOverride>,
+) {
+    let Some(row) = i32::try_from(index)
+        .ok()
+        .and_then(|index| widgets.clients_box.row_at_index(index))
+    else {
+        return;
+    };
+    widgets
+        .client_details
+        .details_label
+        .set_label(&format_client_details(
+            details,
+            std::time::SystemTime::now(),
+        ));
+    show_display_override(&widgets.client_details, details, display_override);
+    widgets
+        .client_details
+        .popover
+        .set_pointing_to(Some(&row.allocation()));
+    widgets.client_details.popover.popup();
+}
+
+/// Fill the name and color entries with the override of the client, if any
+fn show_display_override(
+    client_details: &ClientDetailsWidgets,
+    details: &ClientDetails,
+    display_override: Option<&DisplayOverride>,
+) {
+    *client_details.hostname.borrow_mut() = details.hostname.clone();
+    let display_override = display_override.cloned().unwrap_or_default();
+    client_details
+        .display_name
+        .set_text(display_override.name.as_deref().unwrap_or_default());
+    client_details.display_name.set_placeholder_text(Some(
+        details.pretty_name.as_deref().unwrap_or(&details.hostname),
+    ));
+    let color = display_override.color.as_deref().and_then(parse_hex_color);
+    if let Some([red, green, blue]) = color {
+        client_details.color_button.set_rgba(&gdk::RGBA::new(
+            f32::from(red) / 255.0,
+            f32::from(green) / 255.0,
+            f32::from(blue) / 255.0,
+            1.0,
+        ));
+    }
+    client_details.fill_color.set_active(color.is_some());
+}
+
+/// Replace the rows of the event log list
+pub(crate) fn show_event_log(widgets: &ServerPageWidgets, lines: &[String]) {
+    let event_log_box = &widgets.event_log_box;
+    while let Some(row) = event_log_box.first_child() {
+        event_log_box.remove(&row);
+    }
+    for line in lines {
+        event_log_box.append(
+            &gtk::Label::builder()
+                .label(line)
+                .halign(gtk::Align::Start)
+                .wrap(true)
+                .selectable(true)
+                .build(),
+        );
+    }
+}
+
+/// Replace the commands of the *Deploy agents* expander, each with a button to copy it, or tell to
+/// start the server first when there is none
+pub(crate) fn show_deploy_commands(widgets: &ServerPageWidgets, commands: &[DeployCommand]) {
+    let deploy_box = &widgets.deploy_box;
+    while let Some(child) = deploy_box.first_child() {
+        deploy_box.remove(&child);
+    }
+    if commands.is_empty() {
+        deploy_box.append(
+            &gtk::Label::builder()
+                .label("Start the server to show the commands running the agents")
+                .halign(gtk::Align::Start)
+                .wrap(true)
+                .css_classes(vec!["dim-label".to_string()])
+                .build(),
+        );
+        return;
+    }
+    for deploy_command in commands {
+        deploy_box.append(
+            &gtk::Label::builder()
+                .label(&deploy_command.label)
+                .halign(gtk::Align::Start)
+                .wrap(true)
+                .css_classes(vec!["caption-heading".to_string()])
+                .build(),
+        );
+        let command_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(5)
+            .build();
+        command_box.append(
+            &gtk::Label::builder()
+                .label(&deploy_command.command)
+                .halign(gtk::Align::Start)
+                .hexpand(true)
+                .wrap(true)
+                .wrap_mode(gtk::pango::WrapMode::Char)
+                .selectable(true)
+                .css_classes(vec!["monospace".to_string()])
+                .build(),
+        );
+        let copy_button = gtk::Button::builder()
+            .icon_name("edit-copy-symbolic")
+            .tooltip_text("Copy the command")
+            .valign(gtk::Align::Center)
+            .build();
+        let command = deploy_command.command.clone();
+        copy_button.connect_clicked(move |button| button.clipboard().set_text(&command));
+        command_box.append(&copy_button);
+        deploy_box.append(&command_box);
+    }
+}
+
+/// Put the start button back once the server stopped by itself or could not start. The server
+/// is already marked as disabled, so the button does not ask to stop it again.
+pub(crate) fn reset_server_button(widgets: &ServerPageWidgets) {
+    if widgets.server_button.is_active() {
+        widgets.server_button.emit_clicked();
+    }
+}
+
+/// Show the listen settings of a loaded session
+pub(crate) fn apply_server_option(widgets: &ServerPageWidgets, server_option: &ServerOption) {
+    widgets.server_address.set_text(&server_option.listen_addr);
+    widgets.server_port.set_text(&server_option.listen_port);
+    widgets.http_port.set_text(&server_option.http_port);
+    widgets
+        .max_updates_per_client
+        .set_text(&server_option.max_updates_per_client);
+    widgets
+        .max_update_size
+        .set_text(&server_option.max_update_size);
+    widgets.max_clients.set_text(&server_option.max_clients);
+    widgets
+        .verify_source_ip
+        .set_active(server_option.verify_source_ip);
+    widgets
+        .verify_exempt_ips
+        .set_text(&server_option.verify_exempt_ips);
+    widgets
+        .update_timeout
+        .set_text(&server_option.update_timeout);
+    if let Some(index) = UPDATE_SELECTIONS
+        .iter()
+        .position(|update_selection| *update_selection == server_option.update_selection)
+    {
+        widgets.update_selection.set_selected(index as u32);
+    }
+    widgets
+        .recorder_interval
+        .set_text(&server_option.recorder_interval);
+}
+
+/// Read the server settings from the address, ports, limits, exempt IPs, update timeout and
+/// recorder interval entries, in this order, and from the updates selection dropdown
+fn read_server_option(
+    entries: &[gtk::Entry; 9],
+    verify_source_ip: &gtk::CheckButton,
+    update_selection: &gtk::DropDown,
+) -> ServerOption {
+    let [listen_addr, listen_port, http_port, max_updates_per_client, max_update_size, max_clients, verify_exempt_ips, update_timeout, recorder_interval] =
+        entries
+            .each_ref()
+            .map(|entry| entry.text().trim().to_string());
+    ServerOption {
+        listen_addr,
+        listen_port,
+        http_port,
+        max_updates_per_client,
+        max_update_size,
+        max_clients,
+        verify_source_ip: verify_source_ip.is_active(),
+        verify_exempt_ips,
+        update_timeout,
+        update_selection: UPDATE_SELECTIONS
+            .get(update_selection.selected() as usize)
+            .copied()
+            .unwrap_or_default(),
+        recorder_interval,
+    }
+}
@@ -5,6 +5,7 @@ use relm4::{
     self,
     prelude::{DynamicIndex, FactoryComponent},
 };
+use sockets_map::server::client::Client;
 use std::{net::IpAddr, time::Duration};
 
 use crate::ui::AppMsg;
@@ -14,6 +15,19 @@ pub struct ClientInfo {
     pub hostname: String,
     pub pretty_name: Option<String>,
     pub ips: Vec<IpAddr>,
+    /// Whether the client connected from one of its IPs, see [`Client::verified`]
+    pub verified: bool,
+}
+
+impl From<&Client> for ClientInfo {
+    fn from(client: &Client) -> Self {
+        Self {
+            hostname: client.hostname.clone(),
+            pretty_name: client.pretty_name.clone(),
+            ips: client.ips.clone(),
+            verified: client.verified(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -23,9 +37,15 @@ pub struct ClientLabel {
     pub index: DynamicIndex,
     last_update: Option<std::time::Instant>,
     recording: bool,
+    /// Name of the host edited in the client details, shown instead of its pretty name
+    display_name: Option<String>,
+    /// Whether the "updated X ago" label is being refreshed every second
+    #[do_not_track]
+    ticking: bool,
 }
 
 pub struct ClientLabelWidgets {
+    host_label: gtk::Label,
     last_update_label: gtk::Label,
 }
 
@@ -33,6 +53,7 @@ pub struct ClientLabelWidgets {
 pub enum ClientLabelMsg {
     GotUpdate,
     Recording(bool),
+    SetDisplayName(Option<String>),
 }
 
 #[derive(Debug)]
@@ -61,6 +82,8 @@ impl FactoryComponent for ClientLabel {
             last_update: None,
             tracker: 0,
             recording: false,
+            display_name: None,
+            ticking: false,
         }
     }
 
@@ -81,12 +104,15 @@ impl FactoryComponent for ClientLabel {
         _sender: relm4::FactorySender<Self>,
     ) -> Self::Widgets {
         // Client label
-        let text = if let Some(pretty_name) = &self.info.pretty_name {
-            format!("{pretty_name} ({})", &self.info.hostname)
-        } else {
-            self.info.hostname.clone()
-        };
-        let host_label = gtk::Label::new(Some(&text));
+        let host_label = gtk::Label::new(Some(&self.host_text()));
+        if !self.info.verified {
+            root.append(
+                &gtk::Image::builder()
+                    .icon_name("dialog-warning-symbolic")
+                    .tooltip_text("This client does not connect from one of the IPs it registered with, it may be a cloned machine")
+                    .build(),
+            );
+        }
 
         // Last update label
         let last_update_label = gtk::Label::builder()
@@ -98,7 +124,10 @@ impl FactoryComponent for ClientLabel {
         root.append(&host_label);
         root.append(&last_update_label);
 
-        ClientLabelWidgets { last_update_label }
+        ClientLabelWidgets {
+            host_label,
+            last_update_label,
+        }
     }
 
     fn update(&mut self, message: Self::Input, sender: relm4::FactorySender<Self>) {
@@ -106,20 +135,29 @@ impl FactoryComponent for ClientLabel {
         match message {
             ClientLabelMsg::GotUpdate => {
                 self.set_last_update(Some(std::time::Instant::now()));
-                sender.oneshot_command(async move { ClientLabelCmdOutput::LastUpdateTimerTick });
+                // Updates keep coming while recording, only one timer is needed
+                if !self.ticking {
+                    self.ticking = true;
+                    sender
+                        .oneshot_command(async move { ClientLabelCmdOutput::LastUpdateTimerTick });
+                }
             }
             ClientLabelMsg::Recording(recording) => {
                 self.set_last_update(None);
                 self.set_recording(recording);
                 if !recording {}
             }
+            ClientLabelMsg::SetDisplayName(display_name) => self.set_display_name(display_name),
         }
     }
 
     fn update_view(&self, widgets: &mut Self::Widgets, _sender: relm4::FactorySender<Self>) {
+        if self.changed(Self::display_name()) {
+            widgets.host_label.set_label(&self.host_text());
+        }
         if self.changed(Self::last_update()) {
             widgets.last_update_label.set_label(
-                "<span size=\"small\" foreground=\"grey\"><i>updated just now ago</i></span>",
+                "<span size=\"small\" foreground=\"grey\"><i>updated just now</i></span>",
             );
         }
         if self.changed(Self::recording()) {
@@ -156,8 +194,24 @@ impl FactoryComponent for ClientLabel {
                         tokio::time::sleep(Duration::from_secs(1)).await;
                         ClientLabelCmdOutput::LastUpdateTimerTick
                     })
+                } else {
+                    self.ticking = false;
                 }
             }
         }
     }
 }
+
+impl ClientLabel {
+    /// Text of the host label: its edited or pretty name, followed by its hostname
+    fn host_text(&self) -> String {
+        let name = self
+            .display_name
+            .as_ref()
+            .or(self.info.pretty_name.as_ref());
+        match name {
+            Some(name) => format!("{name} ({})", &self.info.hostname),
+            None => self.info.hostname.clone(),
+        }
+    }
+}
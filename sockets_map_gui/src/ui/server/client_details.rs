@@ -0,0 +1,225 @@
+//! Details of a server client, shown when its row is clicked
+
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use sockets_map::{
+    host::DisplayOverride,
+    server::{client::Client, message::AgentStats},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientDetails {
+    pub hostname: String,
+    pub pretty_name: Option<String>,
+    pub ips: Vec<IpAddr>,
+    pub verified: bool,
+    /// Names of the optional protocol features of the agent, `None` for the agents of the previous
+    /// versions which do not tell them
+    pub capabilities: Option<Vec<String>>,
+    /// Number of updates kept by the server
+    pub updates_count: usize,
+    pub last_update_time: Option<SystemTime>,
+    /// Cost of the recording on the agent, last sent while recording
+    pub agent_stats: Option<AgentStats>,
+    /// Offset of the clock of the agent to the server's, in milliseconds, measured at the start of
+    /// the last recording
+    pub clock_offset_ms: Option<i64>,
+}
+
+impl From<&Client> for ClientDetails {
+    fn from(client: &Client) -> Self {
+        Self {
+            hostname: client.hostname.clone(),
+            pretty_name: client.pretty_name.clone(),
+            ips: client.ips.clone(),
+            verified: client.verified(),
+            capabilities: client
+                .capabilities()
+                .map(|capabilities| capabilities.names().map(str::to_string).collect()),
+            updates_count: client.updates().len(),
+            last_update_time: client.last_update_time().map(SystemTime::from),
+            agent_stats: client.agent_stats(),
+            clock_offset_ms: client.clock_offset_ms(),
+        }
+    }
+}
+
+/// Text of the details popover, one detail per line
+pub(crate) fn format_client_details(details: &ClientDetails, now: SystemTime) -> String {
+    let mut lines = vec![format!("Hostname: {}", details.hostname)];
+    if let Some(pretty_name) = &details.pretty_name {
+        lines.push(format!("Name: {pretty_name}"));
+    }
+    let ips: Vec<String> = details.ips.iter().map(IpAddr::to_string).collect();
+    lines.push(format!(
+        "IPs: {}",
+        match ips.is_empty() {
+            true => "none".to_string(),
+            false => ips.join(", "),
+        }
+    ));
+    if !details.verified {
+        lines.push("Warning: connected from an IP which is not one of its own".to_string());
+    }
+    if let Some(capabilities) = &details.capabilities {
+        lines.push(format!(
+            "Capabilities: {}",
+            match capabilities.is_empty() {
+                true => "none".to_string(),
+                false => capabilities.join(", "),
+            }
+        ));
+    }
+    lines.push(format!("Updates: {}", details.updates_count));
+    lines.push(format!(
+        "Last update: {}",
+        match details.last_update_time {
+            Some(last_update_time) => {
+                let elapsed = now.duration_since(last_update_time).unwrap_or_default();
+                format!(
+                    "{} ({} ago)",
+                    humantime::format_rfc3339_seconds(last_update_time),
+                    humantime::format_duration(Duration::from_secs(elapsed.as_secs()))
+                )
+            }
+            None => "never".to_string(),
+        }
+    ));
+    if let Some(stats) = &details.agent_stats {
+        lines.push(format!(
+            "Recording: {:.1} ms per capture, {} buffered, {} skipped",
+            stats.cpu_ms_per_sample,
+            format_bytes(stats.buffered_bytes),
+            stats.skipped
+        ));
+    }
+    if let Some(clock_offset_ms) = details.clock_offset_ms {
+        lines.push(match clock_offset_ms {
+            0 => "Clock: in sync with the server".to_string(),
+            offset_ms => format!(
+                "Clock: {:.1} s {} the server",
+                offset_ms.unsigned_abs() as f64 / 1000.0,
+                match offset_ms > 0 {
+                    true => "behind",
+                    false => "ahead of",
+                }
+            ),
+        });
+    }
+    lines.join("\n")
+}
+
+/// Size in bytes, with a binary unit
+fn format_bytes(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{bytes} B"),
+                _ => format!("{size:.1} {unit}"),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} GiB")
+}
+
+/// Override of the name and color edited in the details popover, `color` being the red, green and
+/// blue components between 0 and 1 of the color picked, if any
+pub(crate) fn edited_display_override(name: &str, color: Option<[f32; 3]>) -> DisplayOverride {
+    let name = name.trim();
+    DisplayOverride {
+        name: (!name.is_empty()).then(|| name.to_string()),
+        color: color.map(|color| {
+            let [red, green, blue] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            format!("#{red:02x}{green:02x}{blue:02x}")
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Optional details must be omitted or replaced, and the last update shown with its age
+    #[test]
+    fn test_format_client_details() {
+        let mut details = ClientDetails {
+            hostname: "machine1".into(),
+            pretty_name: None,
+            ips: vec![],
+            verified: true,
+            capabilities: None,
+            updates_count: 0,
+            last_update_time: None,
+            agent_stats: None,
+            clock_offset_ms: None,
+        };
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            format_client_details(&details, now),
+            "Hostname: machine1\nIPs: none\nUpdates: 0\nLast update: never"
+        );
+
+        details.pretty_name = Some("Web server".into());
+        details.ips = vec!["10.0.0.1".parse().unwrap(), "fe80::1".parse().unwrap()];
+        details.updates_count = 3;
+        details.last_update_time = Some(now - Duration::from_millis(65_400));
+        assert_eq!(
+            format_client_details(&details, now),
+            "Hostname: machine1\nName: Web server\nIPs: 10.0.0.1, fe80::1\nUpdates: 3\n\
+            Last update: 2023-11-14T22:12:14Z (1m 5s ago)"
+        );
+
+        details.verified = false;
+        details.last_update_time = None;
+        assert_eq!(
+            format_client_details(&details, now),
+            "Hostname: machine1\nName: Web server\nIPs: 10.0.0.1, fe80::1\n\
+            Warning: connected from an IP which is not one of its own\nUpdates: 3\nLast update: never"
+        );
+
+        // Only the agents which sent their capabilities have them listed
+        details.verified = true;
+        details.capabilities = Some(vec!["compressed_updates".into(), "delta_updates".into()]);
+        assert!(format_client_details(&details, now)
+            .contains("\nCapabilities: compressed_updates, delta_updates\nUpdates: 3\n"));
+
+        // The agents which are recording send the cost of the recording
+        details.agent_stats = Some(AgentStats {
+            cpu_ms_per_sample: 12.54,
+            buffered_bytes: 4096,
+            skipped: 1,
+        });
+        assert!(format_client_details(&details, now)
+            .ends_with("\nRecording: 12.5 ms per capture, 4.0 KiB buffered, 1 skipped"));
+
+        // The offset is added to the times of the agent, which is behind when it is positive
+        details.clock_offset_ms = Some(90_300);
+        assert!(format_client_details(&details, now).ends_with("\nClock: 90.3 s behind the server"));
+        details.clock_offset_ms = Some(-1_500);
+        assert!(
+            format_client_details(&details, now).ends_with("\nClock: 1.5 s ahead of the server")
+        );
+        details.clock_offset_ms = Some(0);
+        assert!(format_client_details(&details, now).ends_with("\nClock: in sync with the server"));
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    /// The blank names must be dropped, and the colors written as in the session files
+    #[test]
+    fn test_edited_display_override() {
+        let display_override = edited_display_override(" Web server ", Some([1.0, 0.5, 0.0]));
+        assert_eq!(display_override.name.as_deref(), Some("Web server"));
+        assert_eq!(display_override.color.as_deref(), Some("#ff8000"));
+        assert_eq!(
+            sockets_map::host::parse_hex_color("#ff8000"),
+            Some([255, 128, 0])
+        );
+        assert!(edited_display_override("  ", None).is_empty());
+    }
+}
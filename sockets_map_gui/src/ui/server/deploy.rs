@@ -0,0 +1,166 @@
+//! Commands to run the agents against the started server, shown in the *Deploy agents* expander of
+//! the server page
+
+use std::net::{IpAddr, SocketAddr};
+
+use super::{super::app_msgs::ServerOption, startup::parse_port};
+
+/// Directory the offline agents write their captures into
+const OFFLINE_CAPTURES_DIR: &str = "captures";
+
+/// A command to copy on the machines to map, with what it is for
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DeployCommand {
+    pub label: String,
+    pub command: String,
+}
+
+/// Addresses the agents can reach the server at: the listen address if it is a specific one, or
+/// else the addresses of the local interfaces, leaving out the loopback ones and, when listening
+/// on `0.0.0.0`, the IPv6 ones
+pub(crate) fn server_addresses(
+    listen_addr: &str,
+    port: u16,
+    local_ips: &[IpAddr],
+) -> Vec<SocketAddr> {
+    let only_ipv4 = match listen_addr.trim().parse::<IpAddr>() {
+        Ok(ip) if !ip.is_unspecified() => return vec![SocketAddr::new(ip, port)],
+        Ok(ip) => ip.is_ipv4(),
+        // A host name, the agents only take addresses
+        Err(_) => false,
+    };
+    local_ips
+        .iter()
+        .filter(|ip| !ip.is_loopback() && !(only_ipv4 && ip.is_ipv6()))
+        .map(|ip| SocketAddr::new(*ip, port))
+        .collect()
+}
+
+/// Commands running the agent on Linux and in a Windows PowerShell for each of the server
+/// `addresses`, followed by the offline captures for the machines which cannot reach it
+pub(crate) fn deploy_commands(addresses: &[SocketAddr]) -> Vec<DeployCommand> {
+    let mut commands = Vec::new();
+    for address in addresses {
+        let target = match addresses.len() {
+            1 => String::new(),
+            _ => format!(", to {address}"),
+        };
+        commands.push(DeployCommand {
+            label: format!("Linux{target}"),
+            command: format!("sudo ./sockets_map_agent {address} \"$(hostname)\""),
+        });
+        commands.push(DeployCommand {
+            label: format!("Windows PowerShell, as administrator{target}"),
+            command: format!(".\\sockets_map_agent.exe {address} $env:COMPUTERNAME"),
+        });
+    }
+    commands.push(DeployCommand {
+        label: "Linux, offline capture to open in the Files tab".into(),
+        command: format!("sudo ./sockets_map_agent --offline ./{OFFLINE_CAPTURES_DIR}"),
+    });
+    commands.push(DeployCommand {
+        label: "Windows PowerShell, offline capture to open in the Files tab".into(),
+        command: format!(".\\sockets_map_agent.exe --offline .\\{OFFLINE_CAPTURES_DIR}"),
+    });
+    commands
+}
+
+/// Commands for the server listening with `server_option`, or none if its port is invalid
+pub(crate) fn listen_deploy_commands(server_option: &ServerOption) -> Vec<DeployCommand> {
+    let Ok(port) = parse_port("server port", &server_option.listen_port) else {
+        return Vec::new();
+    };
+    let addresses = server_addresses(&server_option.listen_addr, port, &local_ip_addresses());
+    deploy_commands(&addresses)
+}
+
+/// Addresses of the local interfaces, without the link-local ones which the agents cannot reach
+fn local_ip_addresses() -> Vec<IpAddr> {
+    match local_ip_address::list_afinet_netifas() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .map(|(_, ip)| ip)
+            .filter(|ip| !is_link_local(ip))
+            .collect(),
+        Err(e) => {
+            log::warn!("no local address in the agents commands: {e}");
+            Vec::new()
+        }
+    }
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A specific listen address is the only one given, and a wildcard one is replaced by the
+    /// local addresses of its family, without the loopback ones
+    #[test]
+    fn test_server_addresses() {
+        let local_ips: Vec<IpAddr> = ["127.0.0.1", "10.0.0.5", "::1", "fd00::5"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        assert_eq!(
+            server_addresses("192.168.1.2", 6840, &local_ips),
+            vec!["192.168.1.2:6840".parse().unwrap()]
+        );
+        assert_eq!(
+            server_addresses("0.0.0.0", 7000, &local_ips),
+            vec!["10.0.0.5:7000".parse().unwrap()]
+        );
+        assert_eq!(
+            server_addresses("::", 6840, &local_ips),
+            vec![
+                "10.0.0.5:6840".parse().unwrap(),
+                "[fd00::5]:6840".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            server_addresses("server.lan", 6840, &local_ips),
+            server_addresses("::", 6840, &local_ips)
+        );
+    }
+
+    /// Each address must be substituted in the Linux and Windows commands, the IPv6 ones with
+    /// brackets, and the offline commands must come last
+    #[test]
+    fn test_deploy_commands() {
+        let commands = deploy_commands(&["10.0.0.5:6840".parse().unwrap()]);
+        assert_eq!(commands.len(), 4);
+        assert_eq!(commands[0].label, "Linux");
+        assert_eq!(
+            commands[0].command,
+            "sudo ./sockets_map_agent 10.0.0.5:6840 \"$(hostname)\""
+        );
+        assert_eq!(
+            commands[1].command,
+            ".\\sockets_map_agent.exe 10.0.0.5:6840 $env:COMPUTERNAME"
+        );
+        assert_eq!(
+            commands[2].command,
+            "sudo ./sockets_map_agent --offline ./captures"
+        );
+
+        let commands = deploy_commands(&[
+            "10.0.0.5:6840".parse().unwrap(),
+            "[fd00::5]:6840".parse().unwrap(),
+        ]);
+        assert_eq!(commands.len(), 6);
+        assert_eq!(commands[2].label, "Linux, to [fd00::5]:6840");
+        assert_eq!(
+            commands[2].command,
+            "sudo ./sockets_map_agent [fd00::5]:6840 \"$(hostname)\""
+        );
+
+        // Without any address, only the offline captures are left
+        assert_eq!(deploy_commands(&[]).len(), 2);
+    }
+}
@@ -0,0 +1,63 @@
+//! Lines of the event log list of the server page
+
+use sockets_map::server::event_log::{Event, EventLog};
+
+/// Number of events listed, the older ones being left to the exported log
+pub(crate) const LISTED_EVENTS: usize = 500;
+
+/// Text of the row of an event: its local time, its client and its details
+pub(crate) fn format_event(event: &Event) -> String {
+    let time = event.time.with_timezone(&chrono::Local).format("%H:%M:%S");
+    let client = match (&event.hostname, event.client_addr) {
+        (Some(hostname), Some(addr)) => format!(" {hostname} ({addr})"),
+        (None, Some(addr)) => format!(" {addr}"),
+        (Some(hostname), None) => format!(" {hostname}"),
+        (None, None) => String::new(),
+    };
+    let name = event.kind.name().replace('_', " ");
+    let details = event.kind.details();
+    match details.is_empty() {
+        true => format!("{time}{client}: {name}"),
+        false => format!("{time}{client}: {name}, {details}"),
+    }
+}
+
+/// Rows of the event log list, the newest event first
+pub(crate) fn event_lines(event_log: &EventLog) -> Vec<String> {
+    event_log
+        .events()
+        .rev()
+        .take(LISTED_EVENTS)
+        .map(format_event)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockets_map::server::event_log::EventKind;
+
+    /// The rows must name the client when there is one, and list the newest events first
+    #[test]
+    fn test_event_lines() {
+        let mut event_log = EventLog::default();
+        event_log.push(
+            EventKind::Registered,
+            Some("10.0.0.1:50000".parse().unwrap()),
+            Some("machine1"),
+        );
+        event_log.push_server_event(EventKind::RecordingStarted { interval: 1.5 });
+        let lines = event_lines(&event_log);
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].ends_with(": recording started, interval of 1.5s"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].ends_with(" machine1 (10.0.0.1:50000): registered"),
+            "{}",
+            lines[1]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Interval of the recorder and progress of the recording, shown next to the clients list
+
+use std::time::Duration;
+
+/// Interval shown when none was saved, in seconds
+pub(crate) const DEFAULT_RECORDER_INTERVAL: &str = "1.0";
+/// Shortest interval between two snapshots of the agents, in seconds
+pub(crate) const MIN_RECORDER_INTERVAL: f64 = 0.1;
+
+/// Interval of the recorder entry, in seconds, or the reason why it is invalid
+pub(crate) fn parse_recorder_interval(text: &str) -> Result<f64, String> {
+    match text.trim().parse::<f64>() {
+        Ok(interval) if interval >= MIN_RECORDER_INTERVAL => Ok(interval),
+        Ok(_) => Err(format!(
+            "Interval too low (must be >= {MIN_RECORDER_INTERVAL}s)"
+        )),
+        Err(_) => Err("Invalid recorder update interval (seconds)".into()),
+    }
+}
+
+/// Number of snapshots each agent took after recording for `elapsed`, the first one being taken
+/// right away
+pub(crate) fn expected_samples(elapsed: Duration, interval: f64) -> u64 {
+    (elapsed.as_secs_f64() / interval) as u64 + 1
+}
+
+/// Markup of the label next to the clients list while recording. The agents send their snapshots
+/// in batches, so the samples are estimated from the interval.
+pub(crate) fn format_recorder_timer(elapsed: Duration, interval: f64, updates: usize) -> String {
+    let updates = match updates {
+        1 => "1 update received".to_string(),
+        updates => format!("{updates} updates received"),
+    };
+    format!(
+        "<span size=\"small\" foreground=\"grey\"><i>(recording for {}, ~{} samples collected, {updates})</i></span>",
+        humantime::format_duration(Duration::from_secs(elapsed.as_secs())),
+        expected_samples(elapsed, interval)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Intervals below the minimum and text which is not a number must be rejected
+    #[test]
+    fn test_parse_recorder_interval() {
+        assert_eq!(parse_recorder_interval(DEFAULT_RECORDER_INTERVAL), Ok(1.0));
+        assert_eq!(parse_recorder_interval(" 2.5 "), Ok(2.5));
+        assert_eq!(parse_recorder_interval("0.1"), Ok(0.1));
+        assert_eq!(
+            parse_recorder_interval("0.05"),
+            Err("Interval too low (must be >= 0.1s)".into())
+        );
+        assert_eq!(
+            parse_recorder_interval("-1"),
+            Err("Interval too low (must be >= 0.1s)".into())
+        );
+        assert_eq!(
+            parse_recorder_interval("1s"),
+            Err("Invalid recorder update interval (seconds)".into())
+        );
+        assert!(parse_recorder_interval("").is_err());
+    }
+
+    /// The estimation must count the snapshot taken at the start of the recording
+    #[test]
+    fn test_expected_samples() {
+        assert_eq!(expected_samples(Duration::ZERO, 1.0), 1);
+        assert_eq!(expected_samples(Duration::from_millis(2900), 1.0), 3);
+        assert_eq!(expected_samples(Duration::from_secs(65), 0.5), 131);
+        assert_eq!(
+            format_recorder_timer(Duration::from_millis(65_400), 5.0, 1),
+            "<span size=\"small\" foreground=\"grey\"><i>(recording for 1m 5s, ~14 samples collected, 1 update received)</i></span>"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Start and stop of the server from the button of the server page, with the checks of the
+//! settings and the messages of the failures
+
+use std::io::ErrorKind;
+
+use super::super::app_msgs::ServerOption;
+
+/// What toggling the start and stop button does
+#[derive(Debug, PartialEq)]
+pub(crate) enum ServerToggle {
+    /// Listen with these settings
+    Start(ServerOption),
+    /// The settings are invalid: the button goes back to inactive and the message is shown
+    Refuse(String),
+    Stop,
+}
+
+/// Transition of the server when the button becomes `active` or inactive, with the settings of the
+/// server page
+pub(crate) fn toggle(active: bool, server_option: ServerOption) -> ServerToggle {
+    if !active {
+        return ServerToggle::Stop;
+    }
+    if let Err(message) = parse_port("server port", &server_option.listen_port) {
+        return ServerToggle::Refuse(message);
+    }
+    // The HTTP status endpoint is disabled without a port
+    if !server_option.http_port.is_empty() {
+        if let Err(message) = parse_port("HTTP status port", &server_option.http_port) {
+            return ServerToggle::Refuse(message);
+        }
+    }
+    ServerToggle::Start(server_option)
+}
+
+/// Parse the port of an entry, refusing the port 0 which would pick a random one
+pub(crate) fn parse_port(name: &str, port: &str) -> Result<u16, String> {
+    match port.trim().parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(format!(
+            "Invalid {name} \"{port}\": give a number between 1 and 65535"
+        )),
+    }
+}
+
+/// Message shown when the server cannot listen with its settings
+pub(crate) fn listen_error_message(error: &anyhow::Error, server_option: &ServerOption) -> String {
+    let port = &server_option.listen_port;
+    let address = &server_option.listen_addr;
+    match io_error_kind(error) {
+        Some(ErrorKind::AddrInUse) => {
+            format!("Port {port} is already in use — choose another port")
+        }
+        Some(ErrorKind::AddrNotAvailable) => {
+            format!("{address} is not an address of this machine — choose another address")
+        }
+        Some(ErrorKind::PermissionDenied) => {
+            format!("Not allowed to listen on port {port} — choose a port above 1023")
+        }
+        _ => format!("Unable to start the server on {address}:{port}: {error}"),
+    }
+}
+
+/// Kind of the I/O error causing `error`. The errors of the channel may only keep its message,
+/// from which the address in use is still told apart.
+fn io_error_kind(error: &anyhow::Error) -> Option<ErrorKind> {
+    if let Some(io_error) = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+    {
+        return Some(io_error.kind());
+    }
+    let message = format!("{error:#}").to_lowercase();
+    // The last one is the code of Windows
+    ["address already in use", "address in use", "os error 10048"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+        .then_some(ErrorKind::AddrInUse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only valid ports must start the server, and the inactive button always stops it
+    #[test]
+    fn test_toggle() {
+        let server_option = ServerOption::default();
+        assert_eq!(
+            toggle(true, server_option.clone()),
+            ServerToggle::Start(server_option.clone())
+        );
+        assert_eq!(toggle(false, server_option.clone()), ServerToggle::Stop);
+
+        for (listen_port, http_port) in [("68x0", ""), ("70000", ""), ("0", ""), ("6840", "http")] {
+            let server_option = ServerOption {
+                listen_port: listen_port.into(),
+                http_port: http_port.into(),
+                ..ServerOption::default()
+            };
+            assert!(
+                matches!(toggle(true, server_option.clone()), ServerToggle::Refuse(_)),
+                "{listen_port} {http_port}"
+            );
+            assert_eq!(toggle(false, server_option), ServerToggle::Stop);
+        }
+        assert_eq!(
+            parse_port("server port", "abc"),
+            Err("Invalid server port \"abc\": give a number between 1 and 65535".to_string())
+        );
+    }
+
+    /// The port in use must be named, whether the I/O error is kept or only its message
+    #[test]
+    fn test_listen_error_message() {
+        let server_option = ServerOption::default();
+        let in_use = "Port 6840 is already in use — choose another port";
+        let error = anyhow::Error::new(std::io::Error::from(ErrorKind::AddrInUse))
+            .context("unable to bind");
+        assert_eq!(listen_error_message(&error, &server_option), in_use);
+        let error = anyhow::anyhow!("Encountered IO Error: Address already in use (os error 98)");
+        assert_eq!(listen_error_message(&error, &server_option), in_use);
+
+        let error = anyhow::anyhow!("unexpected");
+        assert_eq!(
+            listen_error_message(&error, &server_option),
+            "Unable to start the server on 0.0.0.0:6840: unexpected"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Sessions, saving the input folder, the graph and server options and the window layout to a
+//! file
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sockets_map::{
+    graphs::{GraphGranularity, ListenerRendering, LoopbackRendering},
+    graphviz::LayoutEngine,
+    host::{DisplayOverride, SourcePolicy},
+};
+
+use super::{app_msgs::ServerOption, graph_options::DEFAULT_DPI, preferences};
+
+/// Number of sessions kept in the recent sessions menu
+const MAX_RECENT_SESSIONS: usize = 5;
+
+/// Graph options, as saved in a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GraphSettings {
+    pub loopback_rendering: LoopbackRendering,
+    /// Loopback setting of the files saved by the previous versions, see [`Self::migrate`]
+    #[serde(skip_serializing)]
+    hide_loopback_connections: bool,
+    pub vertical_graph: bool,
+    pub transparent_background: bool,
+    pub hide_legend: bool,
+    pub listener_rendering: ListenerRendering,
+    /// Whether the processes or only the hosts are drawn
+    pub granularity: GraphGranularity,
+    pub file_extension: String,
+    pub dpi: f64,
+    pub hide_agents: bool,
+    pub excluded_processes: Vec<String>,
+    /// Names of the hosts to leave out of the graph
+    pub excluded_hosts: Vec<String>,
+    pub layout_engine: LayoutEngine,
+    /// How the hosts found both in the input folders and in the agents updates are drawn
+    pub source_policy: SourcePolicy,
+    /// Query of the search entry, not saved since it is typed for the current graph only
+    #[serde(skip)]
+    pub highlight_query: Option<String>,
+    /// Names and colors of the hosts edited from the server page, by host name. This table must
+    /// stay the last field for the TOML preferences.
+    pub display_overrides: BTreeMap<String, DisplayOverride>,
+}
+
+impl Default for GraphSettings {
+    fn default() -> Self {
+        Self {
+            loopback_rendering: LoopbackRendering::Show,
+            hide_loopback_connections: false,
+            vertical_graph: false,
+            transparent_background: false,
+            hide_legend: false,
+            listener_rendering: ListenerRendering::PerProcess,
+            granularity: GraphGranularity::Process,
+            file_extension: "png".into(),
+            dpi: DEFAULT_DPI,
+            hide_agents: true,
+            excluded_processes: Vec::new(),
+            excluded_hosts: Vec::new(),
+            layout_engine: LayoutEngine::Dot,
+            source_policy: SourcePolicy::Merge,
+            highlight_query: None,
+            display_overrides: BTreeMap::new(),
+        }
+    }
+}
+
+impl GraphSettings {
+    /// Replace the loopback setting of the previous versions with the loopback rendering
+    pub fn migrate(&mut self) {
+        if std::mem::take(&mut self.hide_loopback_connections) {
+            self.loopback_rendering = LoopbackRendering::Hide;
+        }
+    }
+}
+
+/// Size of the window and of its panes, as saved in a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct WindowLayout {
+    pub width: i32,
+    pub height: i32,
+    /// Whether the sidebar is shown
+    pub sidebar_revealed: bool,
+    /// Height of the graph preview, above the connections table
+    pub preview_height: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Session {
+    /// The directories from which the static hosts are parsed
+    pub input_directories: Vec<PathBuf>,
+    /// Single input directory of the sessions saved by the previous versions
+    #[serde(skip_serializing)]
+    input_directory: Option<PathBuf>,
+    pub graph: GraphSettings,
+    pub server: ServerOption,
+    /// Layout of the window, left as is when opening the sessions saved without it
+    pub window: Option<WindowLayout>,
+}
+
+impl Session {
+    /// Read a session file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read session file {path:?}"))?;
+        let mut session: Session = serde_json::from_str(&content)
+            .with_context(|| format!("invalid session file {path:?}"))?;
+        if let Some(input_directory) = session.input_directory.take() {
+            session.input_directories.insert(0, input_directory);
+        }
+        session.graph.migrate();
+        Ok(session)
+    }
+
+    /// Write the session file
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("unable to write session file {path:?}"))
+    }
+}
+
+/// File listing the recent sessions, in the configuration directory
+fn recent_sessions_file() -> Option<PathBuf> {
+    preferences::config_dir().map(|dir| dir.join("recent_sessions.json"))
+}
+
+/// The recently opened or saved sessions, most recent first. An unreadable list is ignored.
+pub(crate) fn load_recent_sessions() -> Vec<PathBuf> {
+    recent_sessions_file()
+        .and_then(|file| std::fs::read_to_string(file).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Write the list of the recent sessions
+pub(crate) fn save_recent_sessions(recent_sessions: &[PathBuf]) {
+    let Some(file) = recent_sessions_file() else {
+        return;
+    };
+    let res = file
+        .parent()
+        .map(std::fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| {
+            std::fs::write(
+                &file,
+                serde_json::to_string(recent_sessions).unwrap_or_default(),
+            )
+        });
+    if let Err(e) = res {
+        log::warn!("unable to save the recent sessions to {file:?}: {e}");
+    }
+}
+
+/// Move `path` to the top of the recent sessions
+pub(crate) fn add_recent_session(recent_sessions: &mut Vec<PathBuf>, path: &Path) {
+    recent_sessions.retain(|recent_session| recent_session != path);
+    recent_sessions.insert(0, path.to_path_buf());
+    recent_sessions.truncate(MAX_RECENT_SESSIONS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A saved session must be loaded as is
+    #[test]
+    fn test_session_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("session.json");
+        let session = Session {
+            input_directories: vec![PathBuf::from("/captures"), PathBuf::from("/lab")],
+            graph: GraphSettings {
+                hide_legend: true,
+                file_extension: "svg".into(),
+                dpi: 150.0,
+                excluded_processes: vec!["chrome".into(), "teams".into()],
+                excluded_hosts: vec!["backup01".into()],
+                layout_engine: LayoutEngine::Neato,
+                display_overrides: BTreeMap::from([(
+                    "ip-10-0-3-12".into(),
+                    DisplayOverride {
+                        name: Some("Payment API".into()),
+                        color: Some("#3584e4".into()),
+                    },
+                )]),
+                ..Default::default()
+            },
+            server: ServerOption {
+                listen_addr: "127.0.0.1".into(),
+                listen_port: "7000".into(),
+                http_port: "8080".into(),
+                max_clients: "10".into(),
+                ..Default::default()
+            },
+            window: Some(WindowLayout {
+                width: 1400,
+                height: 900,
+                sidebar_revealed: false,
+                preview_height: 600,
+            }),
+            ..Default::default()
+        };
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), session);
+    }
+
+    /// Missing fields take their default values, so that older session files can be opened
+    #[test]
+    fn test_session_defaults() {
+        let session: Session =
+            serde_json::from_str(r#"{"graph": {"layout_engine": "circo"}}"#).unwrap();
+        assert!(session.input_directories.is_empty());
+        assert_eq!(session.graph.layout_engine, LayoutEngine::Circo);
+        assert!(session.graph.hide_agents);
+        assert_eq!(session.server, ServerOption::default());
+        assert!(session.graph.excluded_hosts.is_empty());
+        assert_eq!(session.window, None);
+    }
+
+    /// The loopback checkbox of the sessions saved by the previous versions must be kept
+    #[test]
+    fn test_session_hide_loopback_connections() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("session.json");
+        std::fs::write(&path, r#"{"graph": {"hide_loopback_connections": true}}"#).unwrap();
+        let session = Session::load(&path).unwrap();
+        assert_eq!(session.graph.loopback_rendering, LoopbackRendering::Hide);
+
+        session.save(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("hide_loopback_connections"));
+        assert!(content.contains(r#""loopback_rendering": "hide""#));
+    }
+
+    /// The input directory of the sessions saved by the previous versions must be kept
+    #[test]
+    fn test_session_single_input_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("session.json");
+        std::fs::write(&path, r#"{"input_directory": "/captures"}"#).unwrap();
+        let session = Session::load(&path).unwrap();
+        assert_eq!(session.input_directories, [PathBuf::from("/captures")]);
+
+        session.save(&path).unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("\"input_directory\""));
+    }
+
+    /// The most recent session must come first, without duplicates
+    #[test]
+    fn test_add_recent_session() {
+        let mut recent_sessions = Vec::new();
+        for i in 0..=MAX_RECENT_SESSIONS {
+            add_recent_session(&mut recent_sessions, Path::new(&format!("{i}.json")));
+        }
+        add_recent_session(&mut recent_sessions, Path::new("3.json"));
+        assert_eq!(recent_sessions.len(), MAX_RECENT_SESSIONS);
+        assert_eq!(recent_sessions[0], Path::new("3.json"));
+        assert_eq!(
+            recent_sessions[1],
+            Path::new(&format!("{MAX_RECENT_SESSIONS}.json"))
+        );
+        assert!(!recent_sessions.contains(&PathBuf::from("0.json")));
+    }
+}
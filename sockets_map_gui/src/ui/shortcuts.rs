@@ -0,0 +1,303 @@
+//! Keyboard shortcuts of the main window, and the shortcuts window listing them
+
+use gtk::{
+    gio::SimpleAction,
+    glib::clone,
+    prelude::{ActionMapExt, GtkApplicationExt, ObjectExt},
+    traits::{ButtonExt, GtkWindowExt, NativeDialogExt},
+};
+use relm4::{adw, ComponentSender, Sender};
+
+use super::{
+    app_msgs::{GraphMsg, ServerMsg},
+    files::FilesPageWidgets,
+    graph_options::GraphPageWidgets,
+    help::HelpWindowMsg,
+    server::ServerPageWidgets,
+    AppModel, AppMsg,
+};
+
+/// What a keyboard shortcut does, the same as the matching button
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShortcutAction {
+    GenerateGraph,
+    ExportGraph,
+    OpenFolder,
+    RequestUpdate,
+    ShowHelp,
+    ShowShortcuts,
+    Quit,
+}
+
+impl ShortcutAction {
+    /// Every action, which must all have a shortcut
+    pub(crate) const ALL: [ShortcutAction; 7] = [
+        ShortcutAction::GenerateGraph,
+        ShortcutAction::ExportGraph,
+        ShortcutAction::OpenFolder,
+        ShortcutAction::RequestUpdate,
+        ShortcutAction::ShowHelp,
+        ShortcutAction::ShowShortcuts,
+        ShortcutAction::Quit,
+    ];
+}
+
+/// Keyboard shortcut, registered as the `app.<name>` action of the application
+pub(crate) struct Shortcut {
+    pub action: ShortcutAction,
+    pub name: &'static str,
+    /// Accelerator, in the syntax of `gtk::accelerator_parse`
+    pub accel: &'static str,
+    /// Group of the shortcut in the shortcuts window
+    pub group: &'static str,
+    pub title: &'static str,
+}
+
+/// Shortcuts of the main window, in the order of the shortcuts window
+pub(crate) const SHORTCUTS: [Shortcut; 7] = [
+    Shortcut {
+        action: ShortcutAction::GenerateGraph,
+        name: "generate-graph",
+        accel: "<Primary>g",
+        group: "Graph",
+        title: "Generate the graph",
+    },
+    Shortcut {
+        action: ShortcutAction::ExportGraph,
+        name: "export-graph",
+        accel: "<Primary>e",
+        group: "Graph",
+        title: "Export the graph",
+    },
+    Shortcut {
+        action: ShortcutAction::OpenFolder,
+        name: "open-folder",
+        accel: "<Primary>o",
+        group: "Graph",
+        title: "Open a capture folder",
+    },
+    Shortcut {
+        action: ShortcutAction::RequestUpdate,
+        name: "request-update",
+        accel: "<Primary>r",
+        group: "Agents",
+        title: "Request an update of the clients",
+    },
+    Shortcut {
+        action: ShortcutAction::ShowHelp,
+        name: "show-help",
+        accel: "F1",
+        group: "General",
+        title: "Show the help",
+    },
+    Shortcut {
+        action: ShortcutAction::ShowShortcuts,
+        name: "show-shortcuts",
+        accel: "<Primary>question",
+        group: "General",
+        title: "Show the keyboard shortcuts",
+    },
+    Shortcut {
+        action: ShortcutAction::Quit,
+        name: "quit",
+        accel: "<Primary>q",
+        group: "General",
+        title: "Quit",
+    },
+];
+
+/// Shortcut of the action
+pub(crate) fn shortcut(action: ShortcutAction) -> &'static Shortcut {
+    SHORTCUTS
+        .iter()
+        .find(|shortcut| shortcut.action == action)
+        .expect("every action has a shortcut")
+}
+
+/// Accelerator as shown to the users, such as `Ctrl+G` for `<Primary>g`
+pub(crate) fn accel_label(accel: &str) -> String {
+    let mut label = String::new();
+    let mut key = accel;
+    while let Some((modifier, rest)) = key.strip_prefix('<').and_then(|k| k.split_once('>')) {
+        label.push_str(match modifier {
+            "Primary" | "Control" | "Ctrl" => "Ctrl+",
+            "Shift" => "Shift+",
+            "Alt" => "Alt+",
+            _ => "",
+        });
+        key = rest;
+    }
+    match key {
+        "question" => label.push('?'),
+        key if key.chars().count() == 1 => label.push_str(&key.to_uppercase()),
+        key => label.push_str(key),
+    }
+    label
+}
+
+/// Tooltip of the button of the action, telling its shortcut
+pub(crate) fn tooltip(text: &str, action: ShortcutAction) -> String {
+    format!("{text} ({})", accel_label(shortcut(action).accel))
+}
+
+/// Escape the text of an XML element
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Definition of the shortcuts window, for `gtk::Builder`, with a group per group of shortcuts
+pub(crate) fn shortcuts_window_ui() -> String {
+    let mut groups = Vec::<(&str, Vec<&Shortcut>)>::new();
+    for shortcut in &SHORTCUTS {
+        match groups
+            .iter_mut()
+            .find(|(group, _)| *group == shortcut.group)
+        {
+            Some((_, shortcuts)) => shortcuts.push(shortcut),
+            None => groups.push((shortcut.group, vec![shortcut])),
+        }
+    }
+    let mut ui = String::from(
+        "<interface>\n  <object class=\"GtkShortcutsWindow\" id=\"shortcuts_window\">\n    <property name=\"modal\">1</property>\n    <child>\n      <object class=\"GtkShortcutsSection\">\n        <property name=\"section-name\">shortcuts</property>\n",
+    );
+    for (group, shortcuts) in groups {
+        ui.push_str(&format!(
+            "        <child>\n          <object class=\"GtkShortcutsGroup\">\n            <property name=\"title\">{}</property>\n",
+            escape_xml(group)
+        ));
+        for shortcut in shortcuts {
+            ui.push_str(&format!(
+                "            <child>\n              <object class=\"GtkShortcutsShortcut\">\n                <property name=\"title\">{}</property>\n                <property name=\"accelerator\">{}</property>\n              </object>\n            </child>\n",
+                escape_xml(shortcut.title),
+                escape_xml(shortcut.accel)
+            ));
+        }
+        ui.push_str("          </object>\n        </child>\n");
+    }
+    ui.push_str("      </object>\n    </child>\n  </object>\n</interface>\n");
+    ui
+}
+
+/// Register the shortcuts as actions of the application. Each action does the same as its button,
+/// and is disabled while its button is.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn install_shortcuts(
+    app_window: &adw::Window,
+    sender: &ComponentSender<AppModel>,
+    export_chooser: &gtk::FileChooserNative,
+    export_graph_button: &adw::SplitButton,
+    files_page_widgets: &FilesPageWidgets,
+    graph_page_widgets: &GraphPageWidgets,
+    server_page_widgets: &ServerPageWidgets,
+    help_window_sender: &Sender<HelpWindowMsg>,
+) {
+    let app = relm4::main_application();
+    let shortcuts_window: gtk::ShortcutsWindow = gtk::Builder::from_string(&shortcuts_window_ui())
+        .object("shortcuts_window")
+        .expect("the shortcuts window is defined");
+    shortcuts_window.set_transient_for(Some(app_window));
+    shortcuts_window.set_hide_on_close(true);
+
+    for shortcut in &SHORTCUTS {
+        let action = SimpleAction::new(shortcut.name, None);
+        match shortcut.action {
+            ShortcutAction::GenerateGraph => {
+                let generate_button = &graph_page_widgets.generate_button;
+                generate_button
+                    .bind_property("sensitive", &action, "enabled")
+                    .sync_create()
+                    .build();
+                action.connect_activate(clone!(@strong sender => move |_, _| {
+                    sender.input(AppMsg::GraphMsg(GraphMsg::Generating(true)))
+                }));
+            }
+            ShortcutAction::ExportGraph => {
+                export_graph_button
+                    .bind_property("sensitive", &action, "enabled")
+                    .sync_create()
+                    .build();
+                action.connect_activate(clone!(@strong export_chooser => move |_, _| {
+                    export_chooser.show()
+                }));
+            }
+            ShortcutAction::OpenFolder => {
+                let open_button = &files_page_widgets.open_button;
+                action.connect_activate(clone!(@strong open_button => move |_, _| {
+                    open_button.emit_clicked()
+                }));
+            }
+            ShortcutAction::RequestUpdate => {
+                server_page_widgets
+                    .server_button
+                    .bind_property("active", &action, "enabled")
+                    .sync_create()
+                    .build();
+                action.connect_activate(clone!(@strong sender => move |_, _| {
+                    sender.input(AppMsg::ServerMsg(ServerMsg::SendUpdateRequest))
+                }));
+            }
+            ShortcutAction::ShowHelp => {
+                action.connect_activate(clone!(@strong help_window_sender => move |_, _| {
+                    help_window_sender.emit(HelpWindowMsg::Show)
+                }));
+            }
+            ShortcutAction::ShowShortcuts => {
+                action.connect_activate(clone!(@strong shortcuts_window => move |_, _| {
+                    shortcuts_window.present()
+                }));
+            }
+            // Closing the window saves the preferences, as with its close button
+            ShortcutAction::Quit => {
+                action.connect_activate(clone!(@strong app_window => move |_, _| {
+                    app_window.close()
+                }));
+            }
+        }
+        app.add_action(&action);
+        app.set_accels_for_action(&format!("app.{}", shortcut.name), &[shortcut.accel]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every action must have exactly one shortcut, and the names and accelerators must not be
+    /// shared
+    #[test]
+    fn test_shortcuts_table() {
+        for action in ShortcutAction::ALL {
+            assert_eq!(
+                SHORTCUTS.iter().filter(|s| s.action == action).count(),
+                1,
+                "{action:?}"
+            );
+        }
+        assert_eq!(SHORTCUTS.len(), ShortcutAction::ALL.len());
+        for (i, shortcut) in SHORTCUTS.iter().enumerate() {
+            for other in &SHORTCUTS[i + 1..] {
+                assert_ne!(shortcut.name, other.name);
+                assert_ne!(shortcut.accel, other.accel);
+            }
+        }
+        let ui = shortcuts_window_ui();
+        assert_eq!(ui.matches("GtkShortcutsShortcut").count(), SHORTCUTS.len());
+        assert_eq!(ui.matches("GtkShortcutsGroup").count(), 3);
+        assert!(ui.contains("<property name=\"accelerator\">&lt;Primary&gt;g</property>"));
+    }
+
+    /// The accelerators must be shown the way GTK shows them
+    #[test]
+    fn test_accel_label() {
+        assert_eq!(accel_label("<Primary>g"), "Ctrl+G");
+        assert_eq!(accel_label("<Primary>question"), "Ctrl+?");
+        assert_eq!(accel_label("<Primary><Shift>e"), "Ctrl+Shift+E");
+        assert_eq!(accel_label("F1"), "F1");
+        assert_eq!(
+            tooltip("Export graph to file", ShortcutAction::ExportGraph),
+            "Export graph to file (Ctrl+E)"
+        );
+    }
+}